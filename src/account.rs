@@ -0,0 +1,174 @@
+use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
+use std::fs::{metadata, read};
+use parking_lot::Mutex;
+use crate::emu::cfg;
+use crate::util::{CString, convert_io_result};
+use crate::result::*;
+
+pub mod result;
+
+// Note: https://switchbrew.org/wiki/Account_services
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[repr(C)]
+pub struct Uid {
+    pub uid: [u64; 2]
+}
+
+impl Uid {
+    pub const fn new(high: u64, low: u64) -> Self {
+        Self { uid: [high, low] }
+    }
+
+    pub const fn is_valid(&self) -> bool {
+        (self.uid[0] != 0) || (self.uid[1] != 0)
+    }
+
+    // Uids are written as 32 hex digits (like the 128-bit value)
+    pub fn parse(uid_str: &str) -> Option<Self> {
+        if (uid_str.len() != 32) || !uid_str.chars().all(|ch| ch.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        let high = u64::from_str_radix(&uid_str[..16], 16).ok()?;
+        let low = u64::from_str_radix(&uid_str[16..], 16).ok()?;
+        Some(Self::new(high, low))
+    }
+}
+
+impl Display for Uid {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{:016X}{:016X}", self.uid[0], self.uid[1])
+    }
+}
+
+impl Debug for Uid {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}", self)
+    }
+}
+
+pub type Nickname = CString<0x20>;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[repr(C)]
+pub struct ProfileBase {
+    pub uid: Uid,
+    pub last_edit_timestamp: u64,
+    pub nickname: Nickname
+}
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct UserData {
+    pub unk: u32,
+    pub icon_id: u32,
+    pub icon_background_color_id: u8,
+    pub reserved_1: [u8; 0x7],
+    pub mii_id: [u8; 0x10],
+    pub reserved_2: [u8; 0x60]
+}
+
+impl Default for UserData {
+    fn default() -> Self {
+        Self {
+            unk: 0,
+            icon_id: 0,
+            icon_background_color_id: 0,
+            reserved_1: [0; 0x7],
+            mii_id: [0; 0x10],
+            reserved_2: [0; 0x60]
+        }
+    }
+}
+
+// The console supports up to 8 users
+pub const USER_COUNT_MAX: usize = 8;
+
+#[derive(Clone, Debug)]
+pub struct UserProfile {
+    pub uid: Uid,
+    pub nickname: String,
+    // JPEG avatar, otherwise the user has no image
+    pub avatar_path: Option<String>
+}
+
+impl UserProfile {
+    pub fn get_profile_base(&self) -> Result<ProfileBase> {
+        Ok(ProfileBase {
+            uid: self.uid,
+            last_edit_timestamp: 0,
+            nickname: Nickname::from_str(&self.nickname)?
+        })
+    }
+
+    pub fn get_image_size(&self) -> Result<usize> {
+        match self.avatar_path.as_ref() {
+            Some(avatar_path) => Ok(convert_io_result(metadata(avatar_path))?.len() as usize),
+            None => Ok(0)
+        }
+    }
+
+    pub fn load_image(&self) -> Result<Vec<u8>> {
+        match self.avatar_path.as_ref() {
+            Some(avatar_path) => {
+                let image = convert_io_result(read(avatar_path))?;
+                // JPEG files start with a SOI marker
+                result_return_unless!(image.starts_with(&[0xFF, 0xD8]), result::ResultInvalidImage);
+                Ok(image)
+            },
+            None => Ok(Vec::new())
+        }
+    }
+}
+
+static mut G_USER_PROFILES: Mutex<Vec<UserProfile>> = parking_lot::const_mutex(Vec::new());
+
+// Users are defined in the config (see cfg::Config::user_profiles), invalid or repeated ones being skipped
+pub fn load_user_profiles() {
+    let mut user_profiles: Vec<UserProfile> = Vec::new();
+    for user_cfg in cfg::get_config().user_profiles.iter() {
+        let uid = match Uid::parse(&user_cfg.uid) {
+            Some(uid) if uid.is_valid() => uid,
+            _ => {
+                log_line!("[account] Skipping user '{}' with invalid uid '{}'", user_cfg.name, user_cfg.uid);
+                continue;
+            }
+        };
+
+        if user_profiles.iter().any(|user| user.uid == uid) {
+            log_line!("[account] Skipping user '{}' with repeated uid {}", user_cfg.name, uid);
+            continue;
+        }
+        if user_profiles.len() == USER_COUNT_MAX {
+            log_line!("[account] Skipping user '{}' since there can be at most {} users", user_cfg.name, USER_COUNT_MAX);
+            continue;
+        }
+
+        user_profiles.push(UserProfile {
+            uid: uid,
+            nickname: user_cfg.name.clone(),
+            avatar_path: user_cfg.avatar_path.clone()
+        });
+    }
+
+    log_line!("[account] Loaded users: {:?}", user_profiles);
+    unsafe {
+        *G_USER_PROFILES.lock() = user_profiles;
+    }
+}
+
+pub fn get_user_profiles() -> Vec<UserProfile> {
+    unsafe {
+        G_USER_PROFILES.lock().clone()
+    }
+}
+
+pub fn find_user_profile(uid: Uid) -> Result<UserProfile> {
+    result_return_unless!(uid.is_valid(), result::ResultInvalidUserId);
+
+    match get_user_profiles().into_iter().find(|user| user.uid == uid) {
+        Some(user) => Ok(user),
+        None => result::ResultUserNotFound::make_err()
+    }
+}
@@ -0,0 +1,7 @@
+pub const RESULT_MODULE: u32 = 123;
+
+result_define_group!(RESULT_MODULE => {
+    InvalidUserId: 22,
+    UserNotFound: 100,
+    InvalidImage: 101
+});
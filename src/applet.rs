@@ -0,0 +1,100 @@
+use std::mem;
+use crate::result::*;
+
+pub mod result;
+
+pub mod error;
+
+pub mod swkbd;
+
+// Note: https://switchbrew.org/wiki/Applet_Manager_services
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum AppletId {
+    OverlayDisplay = 0x02,
+    Auth = 0x0A,
+    Cabinet = 0x0B,
+    Controller = 0x0C,
+    DataErase = 0x0D,
+    Error = 0x0E,
+    NetConnect = 0x0F,
+    PlayerSelect = 0x10,
+    SoftwareKeyboard = 0x11,
+    MiiEdit = 0x12,
+    Web = 0x13,
+    Shop = 0x14,
+    PhotoViewer = 0x15,
+    Set = 0x16,
+    OfflineWeb = 0x17,
+    LoginShare = 0x18,
+    WifiWebAuth = 0x19,
+    MyPage = 0x1A
+}
+
+impl AppletId {
+    pub const fn from(raw: u32) -> Option<Self> {
+        match raw {
+            0x02 => Some(Self::OverlayDisplay),
+            0x0A => Some(Self::Auth),
+            0x0B => Some(Self::Cabinet),
+            0x0C => Some(Self::Controller),
+            0x0D => Some(Self::DataErase),
+            0x0E => Some(Self::Error),
+            0x0F => Some(Self::NetConnect),
+            0x10 => Some(Self::PlayerSelect),
+            0x11 => Some(Self::SoftwareKeyboard),
+            0x12 => Some(Self::MiiEdit),
+            0x13 => Some(Self::Web),
+            0x14 => Some(Self::Shop),
+            0x15 => Some(Self::PhotoViewer),
+            0x16 => Some(Self::Set),
+            0x17 => Some(Self::OfflineWeb),
+            0x18 => Some(Self::LoginShare),
+            0x19 => Some(Self::WifiWebAuth),
+            0x1A => Some(Self::MyPage),
+            _ => None
+        }
+    }
+}
+
+// Every library applet receives these as its first input storage
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[repr(C)]
+pub struct CommonArguments {
+    pub version: u32,
+    pub size: u32,
+    pub library_applet_api_version: u32,
+    pub theme_color: u32,
+    pub play_startup_sound: bool,
+    pub pad: [u8; 0x7],
+    pub system_tick: u64
+}
+
+// Library applets aren't actually launched: host implementations take the input storages pushed by the caller and return the output storages it will pop
+pub type LibraryAppletFn = fn(in_data: &[Vec<u8>]) -> Result<Vec<Vec<u8>>>;
+
+pub fn get_library_applet_fn(applet_id: AppletId) -> Option<LibraryAppletFn> {
+    match applet_id {
+        AppletId::Error => Some(error::run),
+        AppletId::SoftwareKeyboard => Some(swkbd::run),
+        _ => None
+    }
+}
+
+pub fn read_storage_val<T: Copy + Default>(storage: &[u8], offset: usize) -> Option<T> {
+    let end_offset = offset.checked_add(mem::size_of::<T>())?;
+    if end_offset > storage.len() {
+        return None;
+    }
+
+    let mut t = T::default();
+    unsafe {
+        std::ptr::copy_nonoverlapping(storage[offset..].as_ptr(), &mut t as *mut T as *mut u8, mem::size_of::<T>());
+    }
+    Some(t)
+}
+
+pub fn read_common_arguments(in_data: &[Vec<u8>]) -> Option<CommonArguments> {
+    in_data.first().and_then(|storage| read_storage_val(storage, 0))
+}
@@ -0,0 +1,93 @@
+use crate::result::*;
+use super::*;
+
+// Host implementation of the error applet: the error is just logged and the applet is dismissed right away, like if the user closed the dialog
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum ErrorType {
+    Normal = 0,
+    SystemData = 1,
+    Application = 2,
+    Eula = 3,
+    Record = 4,
+    System = 5
+}
+
+impl ErrorType {
+    pub const fn from(raw: u8) -> Option<Self> {
+        match raw {
+            0 => Some(Self::Normal),
+            1 => Some(Self::SystemData),
+            2 => Some(Self::Application),
+            3 => Some(Self::Eula),
+            4 => Some(Self::Record),
+            5 => Some(Self::System),
+            _ => None
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[repr(C)]
+pub struct ErrorCommonHeader {
+    pub error_type: u8,
+    pub jump: bool,
+    pub reserved: [u8; 0x3],
+    pub context_flag: u8,
+    pub result_flag: u8,
+    pub context_flag_2: u8
+}
+
+// Normal errors are shown either from their error code ("2XXX-YYYY") or from their result (when the result flag is unset)
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[repr(C)]
+pub struct ErrorCommonArgument {
+    pub header: ErrorCommonHeader,
+    pub error_code_category: u32,
+    pub error_code_number: u32,
+    pub result: u32
+}
+
+const APPLICATION_ERROR_NUMBER_OFFSET: usize = 0x8;
+const APPLICATION_ERROR_DIALOG_MESSAGE_OFFSET: usize = 0x18;
+const APPLICATION_ERROR_MESSAGE_SIZE: usize = 0x800;
+
+fn read_message(storage: &[u8], offset: usize) -> String {
+    let message = storage.get(offset..storage.len().min(offset + APPLICATION_ERROR_MESSAGE_SIZE)).unwrap_or(&[]);
+    let message_len = message.iter().position(|&ch| ch == 0).unwrap_or(message.len());
+    String::from_utf8_lossy(&message[..message_len]).into_owned()
+}
+
+pub fn run(in_data: &[Vec<u8>]) -> Result<Vec<Vec<u8>>> {
+    let error_arg = match in_data.get(1) {
+        Some(error_arg) => error_arg,
+        None => {
+            log_line!("[applet] Error applet launched without error arguments");
+            return Ok(Vec::new());
+        }
+    };
+
+    let header: ErrorCommonHeader = read_storage_val(error_arg, 0).unwrap_or_default();
+    match ErrorType::from(header.error_type) {
+        Some(ErrorType::Normal) => {
+            let arg: ErrorCommonArgument = read_storage_val(error_arg, 0).unwrap_or_default();
+            if header.result_flag == 0 {
+                let rc = ResultCode::new(arg.result);
+                log_line!("[applet] Error applet: result {} ({:?})", rc, rc);
+            }
+            else {
+                log_line!("[applet] Error applet: error code {:0>4}-{:0>4}", arg.error_code_category, arg.error_code_number);
+            }
+        },
+        Some(ErrorType::Application) => {
+            let error_number: u32 = read_storage_val(error_arg, APPLICATION_ERROR_NUMBER_OFFSET).unwrap_or_default();
+            let dialog_message = read_message(error_arg, APPLICATION_ERROR_DIALOG_MESSAGE_OFFSET);
+            log_line!("[applet] Error applet: application error {} - '{}'", error_number, dialog_message);
+        },
+        error_type => log_line!("[applet] Error applet: {:?} error (type {}, arguments size {:#X})", error_type, header.error_type, error_arg.len())
+    }
+
+    // Note: the error applet has no output
+    Ok(Vec::new())
+}
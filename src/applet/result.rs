@@ -0,0 +1,7 @@
+pub const RESULT_MODULE: u32 = 128;
+
+result_define_group!(RESULT_MODULE => {
+    NoDataInChannel: 2,
+    InvalidStorageOffset: 503,
+    LibraryAppletAlreadyStarted: 506
+});
@@ -0,0 +1,36 @@
+use crate::emu::cfg;
+use crate::result::*;
+use super::*;
+
+// Host implementation of the software keyboard: instead of the user typing anything, the configured text (see cfg::Config::swkbd_response_text) is returned right away
+
+// The output storage is the close reason followed by the (UTF-16, nul-terminated) text
+const OUTPUT_TEXT_SIZE: usize = 0x7D4;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum CloseReason {
+    Ok = 0,
+    Cancel = 1
+}
+
+pub fn make_output(close_reason: CloseReason, text: &str) -> Vec<u8> {
+    let mut output = Vec::with_capacity(mem::size_of::<u32>() + OUTPUT_TEXT_SIZE);
+    output.extend_from_slice(&(close_reason as u32).to_le_bytes());
+
+    // Leave room for the nul terminator
+    let max_text_len = OUTPUT_TEXT_SIZE / mem::size_of::<u16>() - 1;
+    for ch in text.encode_utf16().take(max_text_len) {
+        output.extend_from_slice(&ch.to_le_bytes());
+    }
+    output.resize(mem::size_of::<u32>() + OUTPUT_TEXT_SIZE, 0);
+    output
+}
+
+pub fn run(in_data: &[Vec<u8>]) -> Result<Vec<Vec<u8>>> {
+    let text = cfg::get_config().swkbd_response_text.clone();
+    let api_version = read_common_arguments(in_data).map(|common_args| common_args.library_applet_api_version);
+    log_line!("[applet] Software keyboard (API version {:X?}) -> '{}'", api_version, text);
+
+    Ok(vec![make_output(CloseReason::Ok, &text)])
+}
@@ -0,0 +1,222 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use eframe::{egui, epi};
+use parking_lot::Mutex;
+use pegasus_core::embed::{ContentSource, Emulator, EmulatorBuilder, Process};
+use pegasus_core::util::LogLine;
+
+// GUI frontend (built with the 'gui' feature), driving the emulator through the embedding API like any other embedder would
+
+// Only the most recent lines are kept (and shown)
+const MAX_LOG_LINE_COUNT: usize = 0x2000;
+const DEFAULT_EXEFS_PATH: &str = "nso_test/build/exefs";
+
+struct GuiApp {
+    emulator: Option<Emulator>,
+    startup_error: Option<String>,
+    log_lines: Arc<Mutex<VecDeque<LogLine>>>,
+    // Launched processes are kept since only their handles know their main thread (see Process::get_exit_code)
+    launched_processes: Vec<Process>,
+    selected_process_id: Option<u64>,
+    exefs_path: String,
+    last_error: Option<String>,
+    log_filter: String,
+    log_selected_process_only: bool
+}
+
+impl GuiApp {
+    fn new() -> Self {
+        let log_lines = Arc::new(Mutex::new(VecDeque::new()));
+
+        let callback_log_lines = log_lines.clone();
+        let builder = EmulatorBuilder::new().print_logs(false).on_log(move |line: &LogLine| {
+            let mut log_lines = callback_log_lines.lock();
+            if log_lines.len() >= MAX_LOG_LINE_COUNT {
+                log_lines.pop_front();
+            }
+            log_lines.push_back(line.clone());
+        });
+        let (emulator, startup_error) = match builder.build() {
+            Ok(emulator) => (Some(emulator), None),
+            Err(err) => (None, Some(format!("{}", err)))
+        };
+
+        Self {
+            emulator: emulator,
+            startup_error: startup_error,
+            log_lines: log_lines,
+            launched_processes: Vec::new(),
+            selected_process_id: None,
+            exefs_path: String::from(DEFAULT_EXEFS_PATH),
+            last_error: None,
+            log_filter: String::new(),
+            log_selected_process_only: false
+        }
+    }
+
+    fn launch(&mut self) {
+        if let Some(emulator) = self.emulator.as_ref() {
+            match emulator.launch(ContentSource::HostExeFs(self.exefs_path.clone())) {
+                Ok(process) => {
+                    self.selected_process_id = Some(process.get_id());
+                    self.launched_processes.push(process);
+                    self.last_error = None;
+                },
+                Err(err) => self.last_error = Some(format!("Unable to launch '{}': {}", self.exefs_path, err))
+            };
+        }
+    }
+
+    // Launched processes come with their main thread, the rest are listed as they are
+    fn get_processes(&self) -> Vec<Process> {
+        let mut processes: Vec<Process> = match self.emulator.as_ref() {
+            Some(emulator) => emulator.get_processes(),
+            None => Vec::new()
+        };
+        for process in processes.iter_mut() {
+            let process_id = process.get_id();
+            if let Some(launched_process) = self.launched_processes.iter().find(|launched_process| launched_process.get_id() == process_id) {
+                *process = launched_process.clone();
+            }
+        }
+        processes
+    }
+
+    fn show_process_list(&mut self, ui: &mut egui::Ui, processes: &[Process]) {
+        ui.heading("Processes");
+        ui.separator();
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for process in processes {
+                let process_id = process.get_id();
+                let kind = match process.is_guest() {
+                    true => "guest",
+                    false => "emulated"
+                };
+                let label = format!("{:#X} '{}' ({})", process_id, process.get_name(), kind);
+                let is_selected = self.selected_process_id == Some(process_id);
+                if ui.selectable_label(is_selected, label).clicked() {
+                    self.selected_process_id = Some(process_id);
+                }
+            }
+        });
+    }
+
+    fn show_process_details(&mut self, ui: &mut egui::Ui, process: &Process) {
+        ui.heading(format!("'{}' (program ID {})", process.get_name(), process.get_program_id()));
+
+        let status = match (process.get_exit_code(), process.is_paused()) {
+            (Some(exit_code), _) => format!("Exited with code {:#X}", exit_code),
+            (None, true) => String::from("Paused"),
+            (None, false) => String::from("Running")
+        };
+        ui.label(status);
+
+        ui.horizontal(|ui| {
+            // Emulated system processes run host code, thus they can't be paused like guest ones
+            if process.is_guest() {
+                if ui.button("Pause").clicked() {
+                    if let Err(rc) = process.pause() {
+                        self.last_error = Some(format!("Unable to pause: {0} ({0:?})", rc));
+                    }
+                }
+                if ui.button("Resume").clicked() {
+                    if let Err(rc) = process.resume() {
+                        self.last_error = Some(format!("Unable to resume: {0} ({0:?})", rc));
+                    }
+                }
+            }
+            if ui.button("Terminate").clicked() {
+                process.terminate();
+            }
+        });
+
+        ui.collapsing("Threads", |ui| {
+            for thread in process.get_threads() {
+                let name = match thread.guest_name.as_ref() {
+                    Some(guest_name) => format!("{} ('{}')", thread.host_name, guest_name),
+                    None => thread.host_name.clone()
+                };
+                ui.label(format!("{}: {} [{:?}]", thread.id, name, thread.state));
+            }
+        });
+    }
+
+    fn show_log(&mut self, ui: &mut egui::Ui, selected_process_name: Option<String>) {
+        ui.horizontal(|ui| {
+            ui.label("Filter:");
+            ui.text_edit_singleline(&mut self.log_filter);
+            ui.checkbox(&mut self.log_selected_process_only, "Selected process only");
+            if ui.button("Clear").clicked() {
+                self.log_lines.lock().clear();
+            }
+        });
+        ui.separator();
+
+        let log_lines = self.log_lines.lock();
+        let filtered_lines = log_lines.iter().filter(|line| {
+            let matches_process = match (self.log_selected_process_only, selected_process_name.as_ref()) {
+                (true, Some(process_name)) => line.process_name == *process_name,
+                _ => true
+            };
+            matches_process && (self.log_filter.is_empty() || line.msg.contains(&self.log_filter))
+        });
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for line in filtered_lines {
+                ui.monospace(format!("[{} -> {}] {}", line.process_name, line.thread_name, line.msg));
+            }
+        });
+    }
+}
+
+impl epi::App for GuiApp {
+    fn name(&self) -> &str {
+        "pegasus"
+    }
+
+    fn update(&mut self, ctx: &egui::CtxRef, _frame: &mut epi::Frame<'_>) {
+        if let Some(startup_error) = self.startup_error.as_ref() {
+            egui::CentralPanel::default().show(ctx, |ui| {
+                ui.heading("Unable to start the emulator");
+                ui.label(startup_error);
+            });
+            return;
+        }
+
+        egui::TopBottomPanel::top("launch").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("ExeFS:");
+                ui.text_edit_singleline(&mut self.exefs_path);
+                if ui.button("Launch").clicked() {
+                    self.launch();
+                }
+            });
+            if let Some(last_error) = self.last_error.as_ref() {
+                ui.colored_label(egui::Color32::RED, last_error);
+            }
+        });
+
+        let processes = self.get_processes();
+        let selected_process = self.selected_process_id.and_then(|process_id| processes.iter().find(|process| process.get_id() == process_id).cloned());
+
+        egui::SidePanel::left("processes").show(ctx, |ui| {
+            self.show_process_list(ui, &processes);
+        });
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            if let Some(process) = selected_process.as_ref() {
+                self.show_process_details(ui, process);
+                ui.separator();
+            }
+            self.show_log(ui, selected_process.as_ref().map(|process| process.get_name()));
+        });
+
+        // Logs and process states change on their own
+        ctx.request_repaint();
+    }
+}
+
+fn main() {
+    eframe::run_native(Box::new(GuiApp::new()), eframe::NativeOptions::default());
+}
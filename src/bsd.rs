@@ -0,0 +1,204 @@
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, ToSocketAddrs};
+use crate::emu::cfg;
+
+// Note: https://switchbrew.org/wiki/Sockets_services
+// Guest sockets follow FreeBSD's constants and errno values, which differ from the host ones (thus everything gets translated)
+
+pub const AF_INET: u32 = 2;
+
+pub const SOCK_STREAM: u32 = 1;
+pub const SOCK_DGRAM: u32 = 2;
+
+pub const IPPROTO_IP: u32 = 0;
+pub const IPPROTO_TCP: u32 = 6;
+pub const IPPROTO_UDP: u32 = 17;
+
+pub const SOL_SOCKET: u32 = 0xFFFF;
+pub const SO_ERROR: u32 = 0x1007;
+pub const SO_TYPE: u32 = 0x1008;
+pub const TCP_NODELAY: u32 = 1;
+
+pub const F_GETFL: u32 = 3;
+pub const F_SETFL: u32 = 4;
+pub const O_NONBLOCK: u32 = 4;
+
+pub const MSG_PEEK: u32 = 0x2;
+pub const MSG_DONTWAIT: u32 = 0x80;
+
+pub const SHUT_RD: u32 = 0;
+pub const SHUT_WR: u32 = 1;
+pub const SHUT_RDWR: u32 = 2;
+
+pub const POLLIN: u16 = 0x1;
+pub const POLLOUT: u16 = 0x4;
+pub const POLLERR: u16 = 0x8;
+pub const POLLHUP: u16 = 0x10;
+pub const POLLNVAL: u16 = 0x20;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum Errno {
+    Interrupted = 4,
+    Io = 5,
+    BadFileDescriptor = 9,
+    AccessDenied = 13,
+    InvalidArgument = 22,
+    TooManyFiles = 24,
+    BrokenPipe = 32,
+    WouldBlock = 35,
+    InProgress = 36,
+    Already = 37,
+    NotSocket = 38,
+    ProtocolNotAvailable = 42,
+    ProtocolNotSupported = 43,
+    OperationNotSupported = 45,
+    AddressFamilyNotSupported = 47,
+    AddressInUse = 48,
+    AddressNotAvailable = 49,
+    NetworkDown = 50,
+    ConnectionAborted = 53,
+    ConnectionReset = 54,
+    IsConnected = 56,
+    NotConnected = 57,
+    TimedOut = 60,
+    ConnectionRefused = 61,
+    HostUnreachable = 65
+}
+
+impl Errno {
+    pub fn from_io_error(err: &io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::Interrupted => Self::Interrupted,
+            io::ErrorKind::PermissionDenied => Self::AccessDenied,
+            io::ErrorKind::InvalidInput => Self::InvalidArgument,
+            io::ErrorKind::BrokenPipe => Self::BrokenPipe,
+            io::ErrorKind::WouldBlock => Self::WouldBlock,
+            io::ErrorKind::AddrInUse => Self::AddressInUse,
+            io::ErrorKind::AddrNotAvailable => Self::AddressNotAvailable,
+            io::ErrorKind::ConnectionAborted => Self::ConnectionAborted,
+            io::ErrorKind::ConnectionReset => Self::ConnectionReset,
+            io::ErrorKind::NotConnected => Self::NotConnected,
+            io::ErrorKind::TimedOut => Self::TimedOut,
+            io::ErrorKind::ConnectionRefused => Self::ConnectionRefused,
+            _ => Self::Io
+        }
+    }
+}
+
+pub type BsdResult<T> = std::result::Result<T, Errno>;
+
+pub fn convert_io_bsd_result<T>(r: io::Result<T>) -> BsdResult<T> {
+    r.map_err(|err| Errno::from_io_error(&err))
+}
+
+// Sent by clients on RegisterClient, just informative here since host sockets have their own buffers
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[repr(C)]
+pub struct LibraryConfigData {
+    pub version: u32,
+    pub tcp_tx_buf_size: u32,
+    pub tcp_rx_buf_size: u32,
+    pub tcp_tx_buf_max_size: u32,
+    pub tcp_rx_buf_max_size: u32,
+    pub udp_tx_buf_size: u32,
+    pub udp_rx_buf_size: u32,
+    pub sb_efficiency: u32
+}
+
+// BSD's sockaddr_in, with the port and address in network byte order
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[repr(C)]
+pub struct SockAddrIn {
+    pub len: u8,
+    pub family: u8,
+    pub port: [u8; 2],
+    pub addr: [u8; 4],
+    pub zero: [u8; 8]
+}
+
+impl SockAddrIn {
+    pub fn from_socket_addr(addr: SocketAddrV4) -> Self {
+        Self {
+            len: std::mem::size_of::<Self>() as u8,
+            family: AF_INET as u8,
+            port: addr.port().to_be_bytes(),
+            addr: addr.ip().octets(),
+            zero: [0; 8]
+        }
+    }
+
+    pub fn to_socket_addr(&self) -> SocketAddrV4 {
+        SocketAddrV4::new(Ipv4Addr::from(self.addr), u16::from_be_bytes(self.port))
+    }
+
+    pub fn from_bytes(data: &[u8]) -> BsdResult<Self> {
+        if data.len() < std::mem::size_of::<Self>() {
+            return Err(Errno::InvalidArgument);
+        }
+
+        let addr = unsafe { *(data.as_ptr() as *const Self) };
+        if addr.family != AF_INET as u8 {
+            return Err(Errno::AddressFamilyNotSupported);
+        }
+        Ok(addr)
+    }
+
+    // Returns the actual (untruncated) address size, like BSD does
+    pub fn write_bytes(&self, out_data: &mut [u8]) -> u32 {
+        let data = unsafe { std::slice::from_raw_parts(self as *const Self as *const u8, std::mem::size_of::<Self>()) };
+        let copy_size = data.len().min(out_data.len());
+        out_data[..copy_size].copy_from_slice(&data[..copy_size]);
+        data.len() as u32
+    }
+}
+
+pub fn get_any_address() -> SocketAddrV4 {
+    SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0)
+}
+
+// Guest sockets are IPv4-only (like the console)
+pub fn get_socket_addr_v4(addr: SocketAddr) -> BsdResult<SocketAddrV4> {
+    match addr {
+        SocketAddr::V4(addr) => Ok(addr),
+        SocketAddr::V6(_) => Err(Errno::AddressFamilyNotSupported)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[repr(C)]
+pub struct PollFd {
+    pub fd: i32,
+    pub events: u16,
+    pub revents: u16
+}
+
+pub fn is_network_offline() -> bool {
+    cfg::get_config().network_offline
+}
+
+// Allowed hosts are "<host>" or "<host>:<port>" entries, hostnames being resolved on the host
+// Note: everything is allowed if no allowlist is configured
+pub fn is_address_allowed(addr: SocketAddrV4) -> bool {
+    let allowed_hosts = match cfg::get_config().network_allowed_hosts.as_ref() {
+        Some(allowed_hosts) => allowed_hosts,
+        None => return true
+    };
+
+    allowed_hosts.iter().any(|allowed_host| {
+        let (host, port) = match allowed_host.rsplit_once(':') {
+            Some((host, port)) => match port.parse::<u16>() {
+                Ok(port) => (host, Some(port)),
+                Err(_) => return false
+            },
+            None => (allowed_host.as_str(), None)
+        };
+
+        let port_matches = port.map(|port| port == addr.port()).unwrap_or(true);
+        let host_matches = match (host, 0).to_socket_addrs() {
+            Ok(mut host_addrs) => host_addrs.any(|host_addr| host_addr.ip() == *addr.ip()),
+            Err(_) => false
+        };
+        port_matches && host_matches
+    })
+}
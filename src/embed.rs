@@ -0,0 +1,456 @@
+use backtrace::Backtrace;
+use std::fmt::Write;
+use std::panic;
+use std::process;
+use std::sync::{Arc, Once};
+use std::sync::atomic::{AtomicBool, Ordering};
+use parking_lot::Mutex;
+use crate::emu;
+use crate::emu::cfg::{self, Config};
+use crate::emu::cpu;
+use crate::emu::heap::HeapReport;
+use crate::emu::output::OutputEntry;
+use crate::emu::shutdown::ShutdownSummary;
+use crate::emu::speed::SpeedMode;
+use crate::fs::{self, FileSystem};
+use crate::kern::{self, proc::KProcess, thread::{KThread, ThreadState, try_get_current_thread}};
+use crate::ncm::{self, ProgramId, StorageId};
+use crate::proc::boot2::BootManifest;
+use crate::util::{self, LogCallback, LogLine, Shared, make_log_guard};
+use crate::result::*;
+
+// Embedding API: other Rust programs (GUIs, test harnesses...) drive the emulator through this instead of spawning the CLI binary
+// Note: the emulated system is global state (kernel, services, config...), thus only a single emulator can be created per host process
+
+// Same base address the CLI loads programs at
+const PROGRAM_BASE_ADDRESS: u64 = 0x6900000;
+
+#[derive(Clone, Debug)]
+pub struct CrashReport {
+    // The panic message (along with its location)
+    pub message: String,
+    // Thread/process info and emulator backtrace, as printed by the crash handler
+    pub details: String
+}
+
+// Presented framebuffers, in RGBA8888
+// Note: vi isn't emulated yet, thus nothing presents frames so far (see notify_frame)
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>
+}
+
+pub type CrashCallback = Arc<dyn Fn(&CrashReport) + Send + Sync>;
+pub type FrameCallback = Arc<dyn Fn(&Frame) + Send + Sync>;
+
+static mut G_CRASH_CALLBACK: Mutex<Option<CrashCallback>> = parking_lot::const_mutex(None);
+static mut G_FRAME_CALLBACK: Mutex<Option<FrameCallback>> = parking_lot::const_mutex(None);
+static mut G_EMULATOR_CREATED: AtomicBool = AtomicBool::new(false);
+static G_CRASH_HANDLER_INSTALLED: Once = Once::new();
+
+pub fn notify_frame(frame: &Frame) {
+    let callback = unsafe { G_FRAME_CALLBACK.lock().clone() };
+    if let Some(callback) = callback {
+        (callback)(frame);
+    }
+}
+
+fn make_crash_details(backtrace: &Backtrace) -> String {
+    // Note: writing to a String never fails
+    let mut details = String::new();
+
+    // Show information about the panicking thread/process, if possible
+    if let Some(thread) = try_get_current_thread() {
+        let _ = writeln!(details, " ---- Thread/process info ----");
+        let _ = writeln!(details);
+
+        let mut memory_map: Vec<cpu::MemoryMapEntry> = Vec::new();
+        if let Some(proc) = thread.get().owner_process.as_ref() {
+            let _ = writeln!(details, "* Process name: '{}'", proc.get().npdm.meta.name.get_str().unwrap());
+            let _ = writeln!(details, "* Process ID: {:#X}", proc.get().id);
+            let _ = writeln!(details, "* Program ID: {}", proc.get().npdm.aci0.program_id);
+
+            if let Some(ctx) = proc.get().cpu_ctx.as_ref() {
+                let _ = writeln!(details, "* Modules:");
+                for module in ctx.modules.iter() {
+                    let mod_name = match module.get_name() {
+                        Some(name) => name,
+                        None => String::from("<unk>")
+                    };
+
+                    let _ = writeln!(details, " -- {} (file: {})", mod_name, module.file_name);
+                }
+            }
+
+            memory_map = KProcess::get_memory_map(proc);
+            let _ = writeln!(details, "* Memory map:");
+            for entry in memory_map.iter() {
+                let _ = writeln!(details, " -- {}", entry);
+            }
+        }
+        else {
+            let _ = writeln!(details, "* Not a process...");
+        }
+
+        let _ = writeln!(details, "* Host thread name: '{}'", thread.get().get_host_name());
+        if let Some(guest_name) = thread.get().guest_name.as_ref() {
+            let _ = writeln!(details, "* Guest thread name: '{}'", guest_name);
+        }
+        let _ = writeln!(details, "* Is emulated thread: {}", thread.get().is_emu_thread());
+
+        // If the thread is from an actual external program, print its context
+        match thread.get().get_context_dump() {
+            Ok(Some(dump)) => {
+                let _ = writeln!(details, "* Context:");
+                let _ = write!(details, "{}", dump);
+                if !memory_map.is_empty() {
+                    let _ = writeln!(details, "* PC location: {}", cpu::describe_address(&memory_map, dump.pc));
+                    let _ = writeln!(details, "* LR location: {}", cpu::describe_address(&memory_map, dump.x[30]));
+                }
+            },
+            Ok(None) => {},
+            Err(rc) => {
+                let _ = writeln!(details, "* Unable to dump context: {0} ({0:?})", rc);
+            }
+        };
+
+        let _ = writeln!(details);
+    }
+
+    let _ = writeln!(details, " ---- Emulator backtrace ----");
+    let _ = writeln!(details);
+    let _ = writeln!(details, "{:?}", backtrace);
+    details
+}
+
+// Panics are unrecoverable errors: the crash report is printed (and passed to the crash callback, if any) and the host process exits
+// Note: only installed once, the CLI installing it right away (before any subcommand) and emulators when built
+pub fn install_crash_handler() {
+    G_CRASH_HANDLER_INSTALLED.call_once(|| {
+        let orig_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |panic_info| {
+            // Generate backtrace
+            // TODO: backtrace without panic calls, just everything before the panic?
+            // TODO: actual code backtrace for external programs?
+            let backtrace = Backtrace::new();
+
+            // Whatever was logged before the panic is printed first, then the guard prevents other thread logs to mix with the panic printing
+            util::flush_log();
+            let _guard = make_log_guard();
+
+            // Invoke the default panic handler
+            orig_hook(panic_info);
+
+            println!();
+
+            let report = CrashReport {
+                message: panic_info.to_string(),
+                details: make_crash_details(&backtrace)
+            };
+            print!("{}", report.details);
+
+            // A panic while fuzzing is most likely caused by the request being sent
+            emu::fuzz::log_current_case();
+
+            let callback = unsafe { G_CRASH_CALLBACK.lock().clone() };
+            if let Some(callback) = callback {
+                (callback)(&report);
+            }
+
+            // Exit everything, panic = unrecoverable error
+            println!("Exiting...");
+            process::exit(1);
+        }));
+    });
+}
+
+pub enum ContentSource {
+    // Host directory with the ExeFS contents (main.npdm, main, subsdk*...)
+    HostExeFs(String),
+    // Program content of an installed title
+    Installed(StorageId, ProgramId)
+}
+
+impl ContentSource {
+    fn open_exefs(&self) -> HostResult<Shared<dyn FileSystem>> {
+        match self {
+            Self::HostExeFs(exefs_path) => {
+                let exefs: Shared<dyn FileSystem> = fs::HostFileSystem::new(exefs_path.clone());
+                Ok(exefs)
+            },
+            Self::Installed(storage_id, program_id) => {
+                let mut program_cnt = ncm::lookup_content(*storage_id, *program_id, cntx::nca::ContentType::Program).with_context(|| format!("while looking up the program content of {}", program_id))?;
+                program_cnt.open_partition_filesystem(0).with_context(|| format!("while opening the ExeFS of {}", program_id))
+            }
+        }
+    }
+}
+
+pub struct EmulatorBuilder {
+    config: Option<Config>,
+    config_path: Option<String>,
+    boot_manifest: Option<BootManifest>,
+    speed: Option<(SpeedMode, f64)>,
+    print_logs: bool,
+    log_callback: Option<LogCallback>,
+    crash_callback: Option<CrashCallback>,
+    frame_callback: Option<FrameCallback>
+}
+
+impl EmulatorBuilder {
+    pub fn new() -> Self {
+        Self {
+            config: None,
+            config_path: None,
+            boot_manifest: None,
+            speed: None,
+            print_logs: true,
+            log_callback: None,
+            crash_callback: None,
+            frame_callback: None
+        }
+    }
+
+    // By default the config file in the current directory is loaded (or created)
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    pub fn config_path(mut self, config_path: String) -> Self {
+        self.config_path = Some(config_path);
+        self
+    }
+
+    // Launches the system modules in the manifest (see proc::boot2) instead of just the emulated ones
+    pub fn boot_system(mut self, manifest: BootManifest) -> Self {
+        self.boot_manifest = Some(manifest);
+        self
+    }
+
+    // Overrides the configured emulation speed (see emu::speed)
+    pub fn speed(mut self, mode: SpeedMode, fast_forward_multiplier: f64) -> Self {
+        self.speed = Some((mode, fast_forward_multiplier));
+        self
+    }
+
+    // Whether log lines are still printed to stdout besides being passed to the log callback
+    pub fn print_logs(mut self, print_logs: bool) -> Self {
+        self.print_logs = print_logs;
+        self
+    }
+
+    // Note: the callback runs on the logger thread (see util::log_line_msg), thus it may take its time without stalling emulated threads
+    pub fn on_log<F: Fn(&LogLine) + Send + Sync + 'static>(mut self, f: F) -> Self {
+        self.log_callback = Some(Arc::new(f));
+        self
+    }
+
+    // Called right before the host process exits due to a panic
+    pub fn on_crash<F: Fn(&CrashReport) + Send + Sync + 'static>(mut self, f: F) -> Self {
+        self.crash_callback = Some(Arc::new(f));
+        self
+    }
+
+    pub fn on_frame<F: Fn(&Frame) + Send + Sync + 'static>(mut self, f: F) -> Self {
+        self.frame_callback = Some(Arc::new(f));
+        self
+    }
+
+    pub fn build(self) -> HostResult<Emulator> {
+        let already_created = unsafe { G_EMULATOR_CREATED.swap(true, Ordering::SeqCst) };
+        if already_created {
+            return Err(Error::new(ResultEmulatorAlreadyCreated::make()));
+        }
+
+        install_crash_handler();
+        unsafe {
+            *G_CRASH_CALLBACK.lock() = self.crash_callback;
+            *G_FRAME_CALLBACK.lock() = self.frame_callback;
+        }
+        util::set_log_callback(self.log_callback);
+        util::set_log_stdout_enabled(self.print_logs);
+
+        let config_path = self.config_path.unwrap_or_else(cfg::get_default_config_path);
+        match self.config {
+            Some(config) => cfg::initialize_with_config(config, config_path)?,
+            None => cfg::initialize_from(config_path)?
+        };
+
+        emu::metrics::initialize().context("while initializing metrics")?;
+        emu::watchdog::initialize().context("while initializing the IPC watchdog")?;
+        emu::profiler::initialize();
+        emu::host_profiler::initialize();
+        emu::speed::initialize();
+        if let Some((mode, fast_forward_multiplier)) = self.speed {
+            emu::speed::set_mode(mode, fast_forward_multiplier);
+        }
+        ncm::initialize()?;
+
+        // The emulated firmware version decides which IPC commands are available (see ipc::sf::CommandMetadata)
+        if let Err(rc) = crate::proc::set::sys::get_firmware_version(false) {
+            log_line!("Unable to load the firmware version, all IPC commands will be available: {0} ({0:?})", rc);
+        }
+
+        kern::initialize().context("while initializing the kernel")?;
+
+        match self.boot_manifest.as_ref() {
+            Some(manifest) => crate::proc::boot2::boot_system(manifest).context("while booting the system")?,
+            None => crate::proc::initialize().context("while starting the emulated processes")?
+        };
+
+        Ok(Emulator {})
+    }
+}
+
+pub struct Emulator {}
+
+impl Emulator {
+    // The process starts running right away, and its main thread's exit is tracked (see Process::get_exit_code)
+    pub fn launch(&self, source: ContentSource) -> HostResult<Process> {
+        let exefs = source.open_exefs()?;
+
+        let mut cpu_ctx = cpu::Context::new();
+        let (start_addr, npdm) = cpu_ctx.load_program(exefs, PROGRAM_BASE_ADDRESS).context("while loading the program")?;
+        let process_name = npdm.meta.name.get_string()?;
+        let main_thread_host_name = format!("ext.{}.MainThread", process_name);
+
+        let mut process = KProcess::new(Some(cpu_ctx), npdm).context("while creating the process")?;
+        let (mut main_thread, main_thread_handle) = KProcess::create_main_thread(&mut process, main_thread_host_name, start_addr).context("while creating the main thread")?;
+        log_line!("Running process '{}' at {:#X}...", process_name, start_addr);
+        let main_thread_id = main_thread.get().id;
+        emu::run::watch_thread_exit(main_thread_id);
+        KThread::start_exec(&mut main_thread, 0u64, main_thread_handle).context("while starting the main thread")?;
+
+        Ok(Process {
+            process: process,
+            main_thread_id: Some(main_thread_id)
+        })
+    }
+
+    // Every process, emulated system processes included
+    pub fn get_processes(&self) -> Vec<Process> {
+        kern::proc::get_process_list().into_iter().map(|process| Process {
+            process: process,
+            main_thread_id: None
+        }).collect()
+    }
+
+    // Terminates every guest process, waiting (for a while) for their threads to unwind
+    pub fn shutdown(self) -> ShutdownSummary {
+        emu::shutdown::terminate_guest_processes()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ThreadInfo {
+    pub id: u64,
+    pub host_name: String,
+    pub guest_name: Option<String>,
+    pub state: ThreadState
+}
+
+// Note: meant to be used from threads outside the emulator, thus the process and its threads are accessed waiting for emulator threads to release them (see Shared::get_blocking)
+#[derive(Clone)]
+pub struct Process {
+    process: Shared<KProcess>,
+    // Only known for processes launched through the emulator (see Emulator::launch)
+    main_thread_id: Option<u64>
+}
+
+impl Process {
+    pub fn get_id(&self) -> u64 {
+        self.process.get_blocking().id
+    }
+
+    pub fn get_name(&self) -> String {
+        self.process.get_blocking().npdm.meta.name.get_string().unwrap_or_default()
+    }
+
+    pub fn get_program_id(&self) -> ProgramId {
+        self.process.get_blocking().npdm.aci0.program_id
+    }
+
+    // Emulated system processes run host code instead
+    pub fn is_guest(&self) -> bool {
+        self.process.get_blocking().cpu_ctx.is_some()
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.process.get_blocking().is_paused
+    }
+
+    pub fn get_threads(&self) -> Vec<ThreadInfo> {
+        let threads = self.process.get_blocking().get_threads();
+        threads.iter().map(|thread| {
+            let thread_ref = thread.get_blocking();
+            ThreadInfo {
+                id: thread_ref.id,
+                host_name: String::from(thread_ref.get_host_name()),
+                guest_name: thread_ref.guest_name.clone(),
+                state: thread_ref.state
+            }
+        }).collect()
+    }
+
+    pub fn get_main_thread_id(&self) -> Option<u64> {
+        self.main_thread_id
+    }
+
+    // The value the main thread's entrypoint returned, once it exited
+    pub fn get_exit_code(&self) -> Option<u32> {
+        self.main_thread_id.and_then(emu::run::get_thread_exit_code)
+    }
+
+    #[inline]
+    pub fn has_exited(&self) -> bool {
+        self.get_exit_code().is_some()
+    }
+
+    pub fn pause(&self) -> Result<()> {
+        KProcess::set_activity(&mut self.process.clone(), true)
+    }
+
+    pub fn resume(&self) -> Result<()> {
+        KProcess::set_activity(&mut self.process.clone(), false)
+    }
+
+    // Only requests it, the threads stop on their own shortly after
+    pub fn terminate(&self) {
+        let threads = self.process.get_blocking().get_threads();
+        for mut thread in threads {
+            KThread::request_termination(&mut thread);
+        }
+    }
+
+    // Most recent output of every channel (see emu::output)
+    pub fn get_output(&self) -> Vec<OutputEntry> {
+        emu::output::get_process_output(self.get_id())
+    }
+
+    pub fn get_memory_map(&self) -> Vec<cpu::MemoryMapEntry> {
+        KProcess::get_memory_map(&self.process)
+    }
+
+    // Walks the lmem heaps found in the process's memory (see emu::heap)
+    // Note: as with core dumps, the process should be paused first for consistent results
+    pub fn analyze_heaps(&self) -> Vec<HeapReport> {
+        let memory_map = self.get_memory_map();
+        let process = self.process.get();
+        match process.cpu_ctx.as_ref() {
+            Some(cpu_ctx) => emu::heap::analyze_lmem_heaps(&|address: u64, data: &mut [u8]| cpu_ctx.read_memory(address, data), &memory_map),
+            None => Vec::new()
+        }
+    }
+
+    // Note: the process should be paused first for a consistent dump
+    pub fn dump_core(&self, path: String) -> Result<()> {
+        KProcess::dump_core(&self.process, path)
+    }
+
+    pub fn get_kernel_process(&self) -> Shared<KProcess> {
+        self.process.clone()
+    }
+}
@@ -2,4 +2,40 @@ pub mod cpu;
 
 pub mod kern;
 
-pub mod cfg;
\ No newline at end of file
+pub mod cfg;
+
+pub mod trace;
+
+pub mod metrics;
+
+pub mod symbols;
+
+pub mod disasm;
+
+pub mod heap;
+
+pub mod profiler;
+
+pub mod host_profiler;
+
+pub mod harness;
+
+pub mod coredump;
+
+pub mod output;
+
+pub mod watchdog;
+
+pub mod run;
+
+pub mod shutdown;
+
+pub mod speed;
+
+pub mod fuzz;
+
+// Note: nothing consumes input until hid is emulated, thus recording/replaying it isn't exposed yet
+#[allow(dead_code)]
+mod input;
+
+pub mod service_mock;
@@ -1,7 +1,14 @@
 use cntx::key::Keyset;
 use serde::{Serialize, Deserialize};
-use std::fs::{File, create_dir};
+use std::collections::BTreeMap;
+use std::fs::{File, create_dir, read_to_string};
 use crate::{result::*, util::{convert_io_result, convert_serde_json_result, get_path_relative_to_cwd}};
+use crate::fs::result as fs_result;
+use crate::emu::speed::SpeedMode;
+use crate::ncm::StorageId;
+use crate::ncm::storage::ContentStorageKind;
+use crate::nifm::NetworkStatus;
+use crate::set::{Language, RegionCode};
 
 const CONFIG_FILE: &str = "config.cfg";
 const KEYSET_FILE: &str = "prod.keys";
@@ -9,13 +16,294 @@ const KEYSET_FILE: &str = "prod.keys";
 
 const DEFAULT_NAND_SYSTEM_DIR: &str = "nand_system";
 const DEFAULT_NAND_USER_DIR: &str = "nand_user";
+const DEFAULT_NAND_SAFE_DIR: &str = "nand_safe";
+const DEFAULT_NAND_CALIBRATION_DIR: &str = "nand_calibration";
 const DEFAULT_SD_CARD_DIR: &str = "sd_card";
+const DEFAULT_MODS_DIR: &str = "mods";
+const DEFAULT_SHARED_FONT_DIR: &str = "fonts";
+const DEFAULT_HOST_PROFILER_OUTPUT_FILE: &str = "host_profile.folded";
+// Where tzdata is usually installed on Unix hosts
+const DEFAULT_TIME_ZONE_INFO_DIR: &str = "/usr/share/zoneinfo";
+const DEFAULT_USER_UID: &str = "00000000000000010000000000000001";
+
+const fn default_enforce_service_access_control() -> bool {
+    true
+}
+
+const fn default_fs_cache_filesystem_count() -> usize {
+    0x10
+}
+
+const fn default_fs_cache_block_count() -> usize {
+    0x400
+}
+
+const fn default_fs_async_worker_count() -> usize {
+    4
+}
+
+const fn default_metrics_summary_interval_secs() -> u64 {
+    10
+}
+
+const fn default_profiler_sample_interval() -> u64 {
+    0x10
+}
+
+const fn default_profiler_report_entry_count() -> usize {
+    0x20
+}
+
+const fn default_share_module_segments() -> bool {
+    true
+}
+
+const fn default_spl_use_keyset_keys() -> bool {
+    true
+}
+
+const fn default_speed_mode() -> SpeedMode {
+    SpeedMode::Unlimited
+}
+
+const fn default_speed_fast_forward_multiplier() -> f64 {
+    2.0
+}
+
+// The console's CPU runs at 1020MHz, roughly one instruction per cycle
+const fn default_speed_instructions_per_tick() -> u64 {
+    53
+}
+
+const fn default_speed_frame_rate() -> u32 {
+    60
+}
+
+const fn default_svc_fault_policy() -> SvcFaultPolicy {
+    SvcFaultPolicy::Exception
+}
+
+const fn default_svc_detection_mode() -> SvcDetectionMode {
+    SvcDetectionMode::CodeHook
+}
+
+const fn default_system_language() -> Language {
+    Language::AmericanEnglish
+}
+
+const fn default_system_region() -> RegionCode {
+    RegionCode::Usa
+}
+
+const fn default_nifm_network_status() -> NetworkStatus {
+    NetworkStatus::Connected
+}
+
+fn default_time_zone_location_name() -> String {
+    String::from("UTC")
+}
+
+fn default_time_zone_info_path() -> String {
+    String::from(DEFAULT_TIME_ZONE_INFO_DIR)
+}
+
+fn default_device_nickname() -> String {
+    String::from("pegasus")
+}
+
+fn default_swkbd_response_text() -> String {
+    String::from("pegasus")
+}
+
+fn default_user_profiles() -> Vec<UserProfileConfig> {
+    vec![
+        UserProfileConfig {
+            name: String::from("pegasus"),
+            uid: String::from(DEFAULT_USER_UID),
+            avatar_path: None
+        }
+    ]
+}
+
+fn default_nand_safe_path() -> String {
+    get_path_relative_to_cwd(DEFAULT_NAND_SAFE_DIR)
+}
+
+fn default_nand_calibration_path() -> String {
+    get_path_relative_to_cwd(DEFAULT_NAND_CALIBRATION_DIR)
+}
+
+fn default_mods_path() -> String {
+    get_path_relative_to_cwd(DEFAULT_MODS_DIR)
+}
+
+fn default_shared_font_path() -> String {
+    get_path_relative_to_cwd(DEFAULT_SHARED_FONT_DIR)
+}
+
+fn default_host_profiler_output_path() -> String {
+    get_path_relative_to_cwd(DEFAULT_HOST_PROFILER_OUTPUT_FILE)
+}
+
+// What happens when a guest calls an SVC which is disabled for its process, unimplemented or invalid
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum SvcFaultPolicy {
+    // Bring down the emulator (useful when debugging the emulator itself)
+    Panic,
+    // Return ResultNotImplemented to the guest
+    Result,
+    // Raise an InvalidSystemCall exception to the process, like the console does
+    Exception
+}
+
+// How SVC instructions executed by guests are detected
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum SvcDetectionMode {
+    // A code hook inspects every single executed instruction (slow, but works with any code)
+    CodeHook,
+    // SVCs are handled once they raise their interrupt, code hooks only covering the pages where instructions needing emulation were found when loading (see emu::cpu::find_emulated_insn_ranges)
+    Interrupt
+}
+
+// A user served by the emulated account services (see account::load_user_profiles)
+#[derive(Clone, Serialize, Deserialize)]
+pub struct UserProfileConfig {
+    pub name: String,
+    // As 32 hex digits
+    pub uid: String,
+    // JPEG avatar image
+    #[serde(default)]
+    pub avatar_path: Option<String>
+}
+
+// A content storage backend (see ncm::storage), like a NAND dump for the system storage or loose NCAs for the SD card one
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ContentStorageConfig {
+    pub storage_id: StorageId,
+    pub kind: ContentStorageKind,
+    pub path: String
+}
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Config {
     pub nand_system_path: String,
     pub nand_user_path: String,
-    pub sd_card_path: String
+    pub sd_card_path: String,
+    // Note: defaulted so that config files created before this field existed keep loading
+    #[serde(default = "default_enforce_service_access_control")]
+    pub enforce_service_access_control: bool,
+    // Content overrides (LayeredFS), as <mods_path>/<program-id>/romfs
+    #[serde(default = "default_mods_path")]
+    pub mods_path: String,
+    // How many opened NCA filesystems / decrypted file blocks (see fs::cache) are kept around
+    #[serde(default = "default_fs_cache_filesystem_count")]
+    pub fs_cache_filesystem_count: usize,
+    #[serde(default = "default_fs_cache_block_count")]
+    pub fs_cache_block_count: usize,
+    // Host threads performing asynchronous file reads (see fs::aio)
+    #[serde(default = "default_fs_async_worker_count")]
+    pub fs_async_worker_count: usize,
+    // Other BIS partitions (see fs::bis) in the directory-expanded layout, like the system/user ones above
+    #[serde(default = "default_nand_safe_path")]
+    pub nand_safe_path: String,
+    #[serde(default = "default_nand_calibration_path")]
+    pub nand_calibration_path: String,
+    // Raw NAND dump, whose partitions can be opened as BIS storages
+    #[serde(default)]
+    pub nand_dump_path: Option<String>,
+    // Pin the host threads running on each emulated core to a host CPU (by default, core N to the N-th host CPU, otherwise to the host CPU IDs in the list)
+    #[serde(default)]
+    pub pin_host_threads: bool,
+    #[serde(default)]
+    pub host_cpu_ids: Option<Vec<usize>>,
+    // Emulation metrics (see emu::metrics), periodically logged (0 disables the summary) and optionally served in Prometheus format
+    #[serde(default)]
+    pub metrics_enabled: bool,
+    #[serde(default = "default_metrics_summary_interval_secs")]
+    pub metrics_summary_interval_secs: u64,
+    #[serde(default)]
+    pub metrics_http_port: Option<u16>,
+    // Guest code profiler (see emu::profiler), sampling every N-th executed instruction and reporting the hottest modules/functions at exit
+    #[serde(default)]
+    pub profiler_enabled: bool,
+    #[serde(default = "default_profiler_sample_interval")]
+    pub profiler_sample_interval: u64,
+    #[serde(default = "default_profiler_report_entry_count")]
+    pub profiler_report_entry_count: usize,
+    // Host profiler (see emu::host_profiler), timing the emulator's own hot paths and exporting them as folded stacks at exit
+    #[serde(default)]
+    pub host_profiler_enabled: bool,
+    #[serde(default = "default_host_profiler_output_path")]
+    pub host_profiler_output_path: String,
+    // Whether identical read-only module segments (same build ID) are shared between processes instead of being copied
+    #[serde(default = "default_share_module_segments")]
+    pub share_module_segments: bool,
+    #[serde(default = "default_svc_fault_policy")]
+    pub svc_fault_policy: SvcFaultPolicy,
+    #[serde(default = "default_svc_detection_mode")]
+    pub svc_detection_mode: SvcDetectionMode,
+    // Code ranges ("<start>-<end>", hex) whose executed instructions are logged disassembled (see emu::disasm), meant for small ranges since it's as slow as it gets
+    #[serde(default)]
+    pub disasm_trace_ranges: Vec<String>,
+    // Whether guest writes to executable memory are tracked to retranslate the modified code before it runs (slower, but needed by JITs and other self-modifying code)
+    #[serde(default)]
+    pub detect_self_modifying_code: bool,
+    // Whether the emulated spl derives keys from the keyset master keys/sources (when present) instead of deterministic fake ones
+    #[serde(default = "default_spl_use_keyset_keys")]
+    pub spl_use_keyset_keys: bool,
+    // Values returned by spl's GetConfig, by config item name (like "HardwareType"), overriding the defaults
+    #[serde(default)]
+    pub spl_config_overrides: BTreeMap<String, u64>,
+    // Directory where each process's output channels are written to, as '<program-id>.<channel>.log' files (see emu::output)
+    #[serde(default)]
+    pub process_output_path: Option<String>,
+    // Host directories (with exefs/ and romfs/ subdirectories) served as the program content of a program ID (as hex digits), instead of actual NCAs (see ncm::lookup_content)
+    #[serde(default)]
+    pub virtual_contents: BTreeMap<String, String>,
+    // Content storage backends, scanned in order (storages without any listed backend use the dumps at the NAND/SD card paths above)
+    #[serde(default)]
+    pub content_storages: Vec<ContentStorageConfig>,
+    // Sync IPC requests pending for longer than this get reported, along with their client/server processes, service and command (see emu::watchdog)
+    #[serde(default)]
+    pub ipc_watchdog_timeout_ms: Option<u64>,
+    // Emulation speed (see emu::speed): guest instructions are budgeted per host frame, the console's speed being approximated as a fixed amount of instructions per system tick
+    #[serde(default = "default_speed_mode")]
+    pub speed_mode: SpeedMode,
+    #[serde(default = "default_speed_fast_forward_multiplier")]
+    pub speed_fast_forward_multiplier: f64,
+    #[serde(default = "default_speed_instructions_per_tick")]
+    pub speed_instructions_per_tick: u64,
+    #[serde(default = "default_speed_frame_rate")]
+    pub speed_frame_rate: u32,
+    // System settings served by set/set:sys (see proc::set), queried by applications right away for localization
+    #[serde(default = "default_system_language")]
+    pub system_language: Language,
+    #[serde(default = "default_system_region")]
+    pub system_region: RegionCode,
+    #[serde(default = "default_device_nickname")]
+    pub device_nickname: String,
+    // Users served by acc:u0 (most applications refuse to run without at least one)
+    #[serde(default = "default_user_profiles")]
+    pub user_profiles: Vec<UserProfileConfig>,
+    // Text "typed" in the software keyboard applet, which is dismissed right away (see applet::swkbd)
+    #[serde(default = "default_swkbd_response_text")]
+    pub swkbd_response_text: String,
+    // Fallback TTF fonts (see pl::SharedFontType::get_fallback_file_name) served by pl:u when the system font data archives aren't present
+    #[serde(default = "default_shared_font_path")]
+    pub shared_font_path: String,
+    // Guest sockets (see proc::bsd) are backed by host sockets: offline, nothing reaches the network (as if there was none), otherwise only the allowed hosts (as "<host>" or "<host>:<port>", anything if not set) can be connected/sent to, bound/listened on and accepted from
+    #[serde(default)]
+    pub network_offline: bool,
+    #[serde(default)]
+    pub network_allowed_hosts: Option<Vec<String>>,
+    // Network status reported by nifm (see nifm::NetworkStatus), which titles often check (or wait for) during boot even without using the network
+    #[serde(default = "default_nifm_network_status")]
+    pub nifm_network_status: NetworkStatus,
+    // Device time zone (like "Europe/Madrid") and the host tzdata directory used when the system time zone archive isn't present (see time::load_time_zone_rule)
+    #[serde(default = "default_time_zone_location_name")]
+    pub time_zone_location_name: String,
+    #[serde(default = "default_time_zone_info_path")]
+    pub time_zone_info_path: String
 }
 
 impl Default for Config {
@@ -29,10 +317,66 @@ impl Default for Config {
         let sd_card_path = get_path_relative_to_cwd(DEFAULT_SD_CARD_DIR);
         let _ = create_dir(sd_card_path.clone());
 
+        let nand_safe_path = default_nand_safe_path();
+        let _ = create_dir(nand_safe_path.clone());
+
+        let nand_calibration_path = default_nand_calibration_path();
+        let _ = create_dir(nand_calibration_path.clone());
+
+        let mods_path = default_mods_path();
+        let _ = create_dir(mods_path.clone());
+
+        let shared_font_path = default_shared_font_path();
+        let _ = create_dir(shared_font_path.clone());
+
         Self {
             nand_system_path: nand_system_path,
             nand_user_path: nand_user_path,
             sd_card_path: sd_card_path,
+            enforce_service_access_control: default_enforce_service_access_control(),
+            mods_path: mods_path,
+            fs_cache_filesystem_count: default_fs_cache_filesystem_count(),
+            fs_cache_block_count: default_fs_cache_block_count(),
+            fs_async_worker_count: default_fs_async_worker_count(),
+            nand_safe_path: nand_safe_path,
+            nand_calibration_path: nand_calibration_path,
+            nand_dump_path: None,
+            pin_host_threads: false,
+            host_cpu_ids: None,
+            metrics_enabled: false,
+            metrics_summary_interval_secs: default_metrics_summary_interval_secs(),
+            metrics_http_port: None,
+            profiler_enabled: false,
+            profiler_sample_interval: default_profiler_sample_interval(),
+            profiler_report_entry_count: default_profiler_report_entry_count(),
+            host_profiler_enabled: false,
+            host_profiler_output_path: default_host_profiler_output_path(),
+            share_module_segments: default_share_module_segments(),
+            svc_fault_policy: default_svc_fault_policy(),
+            svc_detection_mode: default_svc_detection_mode(),
+            disasm_trace_ranges: Vec::new(),
+            detect_self_modifying_code: false,
+            spl_use_keyset_keys: default_spl_use_keyset_keys(),
+            spl_config_overrides: BTreeMap::new(),
+            process_output_path: None,
+            virtual_contents: BTreeMap::new(),
+            content_storages: Vec::new(),
+            ipc_watchdog_timeout_ms: None,
+            speed_mode: default_speed_mode(),
+            speed_fast_forward_multiplier: default_speed_fast_forward_multiplier(),
+            speed_instructions_per_tick: default_speed_instructions_per_tick(),
+            speed_frame_rate: default_speed_frame_rate(),
+            system_language: default_system_language(),
+            system_region: default_system_region(),
+            device_nickname: default_device_nickname(),
+            user_profiles: default_user_profiles(),
+            swkbd_response_text: default_swkbd_response_text(),
+            shared_font_path: shared_font_path,
+            network_offline: false,
+            network_allowed_hosts: None,
+            nifm_network_status: default_nifm_network_status(),
+            time_zone_location_name: default_time_zone_location_name(),
+            time_zone_info_path: default_time_zone_info_path()
         }
     }
 }
@@ -64,6 +408,10 @@ fn set_config(cfg: Config, path: String) {
     }
 }
 
+pub fn get_keyset_path() -> String {
+    get_path_relative_to_cwd(KEYSET_FILE)
+}
+
 pub fn get_keyset() -> &'static Keyset {
     unsafe {
         assert!(G_KEYSET.is_some());
@@ -78,9 +426,9 @@ fn set_keyset(keyset: Keyset) {
     }
 }
 
-pub fn load_config(path: String) -> Result<()> {
-    let file = convert_io_result(File::open(path.clone()))?;
-    let cfg: Config = convert_serde_json_result(serde_json::from_reader(file))?;
+pub fn load_config(path: String) -> HostResult<()> {
+    let file = File::open(path.clone())?;
+    let cfg: Config = serde_json::from_reader(file)?;
     set_config(cfg, path);
 
     Ok(())
@@ -91,23 +439,71 @@ pub fn save_config() -> Result<()> {
     convert_serde_json_result(serde_json::to_writer_pretty(file, get_config()))
 }
 
-pub fn initialize() -> Result<()> {
-    // Load config
-    let config_path = get_path_relative_to_cwd(CONFIG_FILE);
-    match load_config(config_path.clone()) {
-        Err(_) => {
-            let default_cfg: Config = Default::default();
-            set_config(default_cfg, config_path);
-            save_config().unwrap();
+// Keyset files are "<key-name> = <hex-value>" lines, checked beforehand since the keyset parser doesn't tell which line is wrong
+fn check_keyset_file(path: &str) -> HostResult<()> {
+    let keyset_data = read_to_string(path)?;
+    for (i, line) in keyset_data.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+
+        let is_valid = match line.split_once('=') {
+            Some((name, value)) => {
+                let value = value.trim();
+                !name.trim().is_empty() && !value.is_empty() && ((value.len() % 2) == 0) && value.chars().all(|ch| ch.is_ascii_hexdigit())
+            },
+            None => false
+        };
+        if !is_valid {
+            return Err(Error::with_message(ResultInvalidKeyset::make(), format!("invalid key entry '{}'", line)).add_context(format!("while parsing {} line {}", KEYSET_FILE, i + 1)));
         }
-        _ => {}
     }
 
-    // Load keyset
-    let keyset_path = get_path_relative_to_cwd(KEYSET_FILE);
-    let keyset_file = convert_io_result(File::open(keyset_path))?;
-    let keyset = convert_io_result(Keyset::from(keyset_file))?;
+    Ok(())
+}
+
+fn load_keyset(path: &str) -> HostResult<()> {
+    check_keyset_file(path)?;
+
+    let keyset_file = File::open(path)?;
+    let keyset = Keyset::from(keyset_file)?;
     set_keyset(keyset);
 
     Ok(())
+}
+
+pub fn initialize() -> HostResult<()> {
+    initialize_from(get_default_config_path())
+}
+
+pub fn initialize_from(config_path: String) -> HostResult<()> {
+    // Load config (a default one is created if there's none yet, but an invalid one is never overwritten)
+    if let Err(err) = load_config(config_path.clone()) {
+        let is_missing = fs_result::ResultPathNotFound::matches(err.get_result());
+        if !is_missing {
+            return Err(err.add_context(format!("while loading config file '{}'", config_path)));
+        }
+
+        let default_cfg: Config = Default::default();
+        set_config(default_cfg, config_path.clone());
+        save_config().with_context(|| format!("while creating the default config file '{}'", config_path))?;
+    }
+
+    initialize_keyset()
+}
+
+// The given config is used as is, only being written to the config path if saved afterwards (see save_config)
+pub fn initialize_with_config(cfg: Config, config_path: String) -> HostResult<()> {
+    set_config(cfg, config_path);
+    initialize_keyset()
+}
+
+fn initialize_keyset() -> HostResult<()> {
+    let keyset_path = get_keyset_path();
+    load_keyset(&keyset_path).with_context(|| format!("while loading keyset '{}'", keyset_path))
+}
+
+pub fn get_default_config_path() -> String {
+    get_path_relative_to_cwd(CONFIG_FILE)
 }
\ No newline at end of file
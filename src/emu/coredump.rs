@@ -0,0 +1,558 @@
+use std::fs;
+use crate::emu::cpu::{self, Architecture, ExecutionContext, MemoryPermission, MemoryRegion, ModuleMemory, Register};
+use crate::emu::cpu::result as cpu_result;
+use crate::emu::cpu::backend::{self, BackendKind, CpuBackend};
+use crate::kern::mem::KMemoryState;
+use crate::kern::proc::KProcess;
+use crate::ncm::ProgramId;
+use crate::result::*;
+use crate::util::{self, Shared, convert_io_result};
+
+// ELF core dumps of emulated processes: every mapped region (modules, stacks, TLRs) is a PT_LOAD segment, thread registers are NT_PRSTATUS notes (like Linux ones, thus usual tools understand them) and pegasus-specific notes keep everything else needed to import them back (see CoreDump::load)
+
+const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const ELF_CLASS_64: u8 = 2;
+const ELF_DATA_LE: u8 = 1;
+const ELF_VERSION_CURRENT: u8 = 1;
+const ELF_TYPE_CORE: u16 = 4;
+const ELF_MACHINE_ARM: u16 = 40;
+const ELF_MACHINE_AARCH64: u16 = 183;
+
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const NOTE_NAME_CORE: &str = "CORE";
+const NOTE_NAME_PEGASUS: &str = "PEGASUS";
+const NT_PRSTATUS: u32 = 1;
+const NT_PEGASUS_PROCESS: u32 = 0x100;
+const NT_PEGASUS_MODULE: u32 = 0x101;
+const NT_PEGASUS_REGION: u32 = 0x102;
+
+// Same order as Linux's user_pt_regs (X0-X30, SP, PC, PSTATE)
+pub const CORE_DUMP_REGISTER_COUNT: usize = 34;
+const CORE_DUMP_REGISTERS: [Register; CORE_DUMP_REGISTER_COUNT] = [
+    Register::X0, Register::X1, Register::X2, Register::X3, Register::X4, Register::X5, Register::X6, Register::X7,
+    Register::X8, Register::X9, Register::X10, Register::X11, Register::X12, Register::X13, Register::X14, Register::X15,
+    Register::X16, Register::X17, Register::X18, Register::X19, Register::X20, Register::X21, Register::X22, Register::X23,
+    Register::X24, Register::X25, Register::X26, Register::X27, Register::X28, Register::X29, Register::X30,
+    Register::SP, Register::PC, Register::NZCV
+];
+const CORE_DUMP_PC_INDEX: usize = 32;
+
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+struct ElfHeader {
+    ident: [u8; 0x10],
+    elf_type: u16,
+    machine: u16,
+    version: u32,
+    entry: u64,
+    ph_offset: u64,
+    sh_offset: u64,
+    flags: u32,
+    eh_size: u16,
+    ph_entry_size: u16,
+    ph_count: u16,
+    sh_entry_size: u16,
+    sh_count: u16,
+    sh_str_index: u16
+}
+const _: () = assert!(std::mem::size_of::<ElfHeader>() == 0x40);
+
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+struct ProgramHeader {
+    segment_type: u32,
+    flags: u32,
+    offset: u64,
+    virtual_address: u64,
+    physical_address: u64,
+    file_size: u64,
+    memory_size: u64,
+    align: u64
+}
+const _: () = assert!(std::mem::size_of::<ProgramHeader>() == 0x38);
+
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+struct NoteHeader {
+    name_size: u32,
+    desc_size: u32,
+    note_type: u32
+}
+
+// Linux's elf_prstatus (AArch64 layout), only the PID and registers are filled
+#[derive(Copy, Clone)]
+#[repr(C)]
+struct PrStatus {
+    signal_info: [u32; 3],
+    current_signal: u16,
+    pad_1: u16,
+    pending_signals: u64,
+    held_signals: u64,
+    pid: u32,
+    parent_pid: u32,
+    group_id: u32,
+    session_id: u32,
+    times: [u64; 8],
+    registers: [u64; CORE_DUMP_REGISTER_COUNT],
+    fp_valid: u32,
+    pad_2: u32
+}
+const _: () = assert!(std::mem::size_of::<PrStatus>() == 392);
+
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+struct CoreProcessInfo {
+    program_id: u64,
+    process_id: u64,
+    is_64bit: u32,
+    pad: u32,
+    name: [u8; 0x10]
+}
+
+// Followed by the module's file name
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+struct CoreModuleInfo {
+    start_address: u64,
+    end_address: u64,
+    file_name_len: u32,
+    pad: u32
+}
+
+// Followed by the region's name, if it has one (see MemoryRegion::name)
+#[derive(Copy, Clone, Default)]
+#[repr(C)]
+struct CoreRegionInfo {
+    address: u64,
+    size: u64,
+    perm: u32,
+    state: u32,
+    // Owner thread of stacks/TLRs
+    thread_id: u64
+}
+
+pub struct CoreDumpThread {
+    pub id: u64,
+    pub registers: [u64; CORE_DUMP_REGISTER_COUNT],
+    pub stack: Option<MemoryRegion>,
+    pub tlr: Option<MemoryRegion>
+}
+
+pub struct CoreDump {
+    pub arch: Architecture,
+    pub program_id: ProgramId,
+    pub process_id: u64,
+    pub process_name: String,
+    pub modules: Vec<ModuleMemory>,
+    pub threads: Vec<CoreDumpThread>
+}
+
+#[inline]
+const fn align_4(value: usize) -> usize {
+    (value + 3) & !3
+}
+
+fn push_val<T: Copy>(buf: &mut Vec<u8>, t: T) {
+    let offset = buf.len();
+    buf.resize(offset + std::mem::size_of::<T>(), 0);
+    util::slice_write_val(buf, Some(offset), t).unwrap();
+}
+
+fn push_note(buf: &mut Vec<u8>, name: &str, note_type: u32, desc: &[u8]) {
+    push_val(buf, NoteHeader {
+        name_size: name.len() as u32 + 1,
+        desc_size: desc.len() as u32,
+        note_type: note_type
+    });
+
+    let name_offset = buf.len();
+    buf.extend_from_slice(name.as_bytes());
+    buf.resize(name_offset + align_4(name.len() + 1), 0);
+
+    let desc_offset = buf.len();
+    buf.extend_from_slice(desc);
+    buf.resize(desc_offset + align_4(desc.len()), 0);
+}
+
+fn make_val_bytes<T: Copy>(t: T) -> Vec<u8> {
+    let mut buf: Vec<u8> = Vec::new();
+    push_val(&mut buf, t);
+    buf
+}
+
+fn copy_region(region: &MemoryRegion) -> MemoryRegion {
+    region.make_alias(region.address)
+}
+
+fn get_segment_flags(perm: MemoryPermission) -> u32 {
+    let mut flags: u32 = 0;
+    if perm.contains(MemoryPermission::READ) {
+        flags |= PF_R;
+    }
+    if perm.contains(MemoryPermission::WRITE) {
+        flags |= PF_W;
+    }
+    if perm.contains(MemoryPermission::EXEC) {
+        flags |= PF_X;
+    }
+    flags
+}
+
+impl CoreDump {
+    // Note: the process should be paused (or dead), otherwise memory/registers might change while they are being copied
+    pub fn from_process(process: &Shared<KProcess>) -> Result<Self> {
+        let (arch, program_id, process_id, process_name, modules, threads) = {
+            let process_ref = process.get();
+            let cpu_ctx = match process_ref.cpu_ctx.as_ref() {
+                Some(cpu_ctx) => cpu_ctx,
+                None => return cpu_result::ResultInvalidCoreDump::make_err()
+            };
+
+            let modules: Vec<ModuleMemory> = cpu_ctx.modules.iter().map(|module| ModuleMemory::new(module.file_name.clone(), module.regions.iter().map(copy_region).collect())).collect();
+            (cpu_ctx.arch, process_ref.npdm.aci0.program_id, process_ref.id, process_ref.npdm.meta.name.get_string()?, modules, process_ref.get_threads())
+        };
+
+        let mut dump_threads: Vec<CoreDumpThread> = Vec::new();
+        for thread in threads.iter() {
+            let thread_ref = thread.get();
+            if let Some(exec_ctx) = thread_ref.cpu_exec_ctx.as_ref() {
+                let ctx_h = exec_ctx.get_handle();
+                let mut registers = [0u64; CORE_DUMP_REGISTER_COUNT];
+                for (i, reg) in CORE_DUMP_REGISTERS.iter().enumerate() {
+                    // Note: registers without an AArch32 equivalent are left as zero for 32-bit processes
+                    registers[i] = ctx_h.read_register(*reg).unwrap_or(0);
+                }
+
+                dump_threads.push(CoreDumpThread {
+                    id: thread_ref.id,
+                    registers: registers,
+                    stack: Some(copy_region(&exec_ctx.stack)),
+                    tlr: Some(copy_region(&exec_ctx.tlr))
+                });
+            }
+        }
+
+        Ok(Self {
+            arch: arch,
+            program_id: program_id,
+            process_id: process_id,
+            process_name: process_name,
+            modules: modules,
+            threads: dump_threads
+        })
+    }
+
+    pub fn save(&self, path: String) -> Result<()> {
+        // Every region along with its owner thread (if any)
+        let mut regions: Vec<(&MemoryRegion, u64)> = self.modules.iter().flat_map(|module| module.regions.iter()).map(|region| (region, 0)).collect();
+        for thread in self.threads.iter() {
+            for region in thread.stack.iter().chain(thread.tlr.iter()) {
+                regions.push((region, thread.id));
+            }
+        }
+
+        let mut notes: Vec<u8> = Vec::new();
+        let mut name = [0u8; 0x10];
+        let name_len = self.process_name.len().min(name.len() - 1);
+        name[..name_len].copy_from_slice(&self.process_name.as_bytes()[..name_len]);
+        push_note(&mut notes, NOTE_NAME_PEGASUS, NT_PEGASUS_PROCESS, &make_val_bytes(CoreProcessInfo {
+            program_id: self.program_id.0,
+            process_id: self.process_id,
+            is_64bit: (self.arch == Architecture::Aarch64) as u32,
+            pad: 0,
+            name: name
+        }));
+        for thread in self.threads.iter() {
+            push_note(&mut notes, NOTE_NAME_CORE, NT_PRSTATUS, &make_val_bytes(PrStatus {
+                signal_info: [0; 3],
+                current_signal: 0,
+                pad_1: 0,
+                pending_signals: 0,
+                held_signals: 0,
+                pid: thread.id as u32,
+                parent_pid: 0,
+                group_id: self.process_id as u32,
+                session_id: 0,
+                times: [0; 8],
+                registers: thread.registers,
+                fp_valid: 0,
+                pad_2: 0
+            }));
+        }
+        for module in self.modules.iter() {
+            let start_address = module.regions.iter().map(|region| region.start()).min().unwrap_or(0);
+            let end_address = module.regions.iter().map(|region| region.end()).max().unwrap_or(0);
+            let mut desc = make_val_bytes(CoreModuleInfo {
+                start_address: start_address,
+                end_address: end_address,
+                file_name_len: module.file_name.len() as u32,
+                pad: 0
+            });
+            desc.extend_from_slice(module.file_name.as_bytes());
+            push_note(&mut notes, NOTE_NAME_PEGASUS, NT_PEGASUS_MODULE, &desc);
+        }
+        for (region, thread_id) in regions.iter() {
+            let mut desc = make_val_bytes(CoreRegionInfo {
+                address: region.address,
+                size: region.len() as u64,
+                perm: region.perm.bits(),
+                state: region.state.get(),
+                thread_id: *thread_id
+            });
+            if let Some(name) = region.name.as_ref() {
+                desc.extend_from_slice(name.as_bytes());
+            }
+            push_note(&mut notes, NOTE_NAME_PEGASUS, NT_PEGASUS_REGION, &desc);
+        }
+
+        let header_count = 1 + regions.len();
+        let notes_offset = std::mem::size_of::<ElfHeader>() + header_count * std::mem::size_of::<ProgramHeader>();
+        let mut data_offset = util::align_up(notes_offset + notes.len(), 0x1000);
+
+        let mut ident = [0u8; 0x10];
+        ident[..4].copy_from_slice(&ELF_MAGIC);
+        ident[4] = ELF_CLASS_64;
+        ident[5] = ELF_DATA_LE;
+        ident[6] = ELF_VERSION_CURRENT;
+
+        let mut core: Vec<u8> = Vec::new();
+        push_val(&mut core, ElfHeader {
+            ident: ident,
+            elf_type: ELF_TYPE_CORE,
+            machine: match self.arch {
+                Architecture::Aarch64 => ELF_MACHINE_AARCH64,
+                Architecture::Aarch32 => ELF_MACHINE_ARM
+            },
+            version: ELF_VERSION_CURRENT as u32,
+            entry: 0,
+            ph_offset: std::mem::size_of::<ElfHeader>() as u64,
+            sh_offset: 0,
+            flags: 0,
+            eh_size: std::mem::size_of::<ElfHeader>() as u16,
+            ph_entry_size: std::mem::size_of::<ProgramHeader>() as u16,
+            ph_count: header_count as u16,
+            sh_entry_size: 0,
+            sh_count: 0,
+            sh_str_index: 0
+        });
+        push_val(&mut core, ProgramHeader {
+            segment_type: PT_NOTE,
+            flags: 0,
+            offset: notes_offset as u64,
+            virtual_address: 0,
+            physical_address: 0,
+            file_size: notes.len() as u64,
+            memory_size: 0,
+            align: 4
+        });
+        for (region, _) in regions.iter() {
+            push_val(&mut core, ProgramHeader {
+                segment_type: PT_LOAD,
+                flags: get_segment_flags(region.perm),
+                offset: data_offset as u64,
+                virtual_address: region.address,
+                physical_address: 0,
+                file_size: region.len() as u64,
+                memory_size: region.len() as u64,
+                align: 0x1000
+            });
+            data_offset += region.len();
+        }
+
+        core.extend_from_slice(&notes);
+        for (region, _) in regions.iter() {
+            core.resize(util::align_up(core.len(), 0x1000), 0);
+            core.extend_from_slice(&region.data);
+        }
+
+        convert_io_result(fs::write(path, core))
+    }
+
+    pub fn load(path: String) -> Result<Self> {
+        let core = convert_io_result(fs::read(path))?;
+
+        let header: ElfHeader = util::slice_read_val(&core, None)?;
+        result_return_unless!(header.ident[..4] == ELF_MAGIC, cpu_result::ResultInvalidCoreDump);
+        result_return_unless!((header.ident[4] == ELF_CLASS_64) && (header.ident[5] == ELF_DATA_LE) && (header.elf_type == ELF_TYPE_CORE), cpu_result::ResultInvalidCoreDump);
+        let arch = match header.machine {
+            ELF_MACHINE_AARCH64 => Architecture::Aarch64,
+            ELF_MACHINE_ARM => Architecture::Aarch32,
+            _ => return cpu_result::ResultInvalidCoreDump::make_err()
+        };
+
+        let mut process_info: Option<CoreProcessInfo> = None;
+        let mut thread_registers: Vec<(u64, [u64; CORE_DUMP_REGISTER_COUNT])> = Vec::new();
+        let mut module_infos: Vec<(CoreModuleInfo, String)> = Vec::new();
+        let mut region_infos: Vec<(CoreRegionInfo, Option<String>)> = Vec::new();
+        let mut region_datas: Vec<(u64, Vec<u8>)> = Vec::new();
+        for i in 0..header.ph_count as usize {
+            let ph_offset = header.ph_offset as usize + i * std::mem::size_of::<ProgramHeader>();
+            let program_header: ProgramHeader = util::slice_read_val(&core, Some(ph_offset))?;
+            let segment_data = util::slice_read_data(&core, Some(program_header.offset as usize), program_header.file_size as usize)?;
+
+            match program_header.segment_type {
+                PT_LOAD => region_datas.push((program_header.virtual_address, segment_data)),
+                PT_NOTE => {
+                    let mut offset: usize = 0;
+                    while offset < segment_data.len() {
+                        let note_header: NoteHeader = util::slice_read_val_advance(&segment_data, &mut offset)?;
+                        let name = util::slice_read_data(&segment_data, Some(offset), note_header.name_size as usize)?;
+                        offset += align_4(note_header.name_size as usize);
+                        let desc = util::slice_read_data(&segment_data, Some(offset), note_header.desc_size as usize)?;
+                        offset += align_4(note_header.desc_size as usize);
+
+                        let name_str = String::from_utf8_lossy(&name[..name.len().saturating_sub(1)]).to_string();
+                        match (name_str.as_str(), note_header.note_type) {
+                            (NOTE_NAME_CORE, NT_PRSTATUS) => {
+                                let pr_status: PrStatus = util::slice_read_val(&desc, None)?;
+                                thread_registers.push((pr_status.pid as u64, pr_status.registers));
+                            },
+                            (NOTE_NAME_PEGASUS, NT_PEGASUS_PROCESS) => process_info = Some(util::slice_read_val(&desc, None)?),
+                            (NOTE_NAME_PEGASUS, NT_PEGASUS_MODULE) => {
+                                let mut desc_offset: usize = 0;
+                                let module_info: CoreModuleInfo = util::slice_read_val_advance(&desc, &mut desc_offset)?;
+                                let file_name = util::slice_read_data(&desc, Some(desc_offset), module_info.file_name_len as usize)?;
+                                module_infos.push((module_info, String::from_utf8_lossy(&file_name).to_string()));
+                            },
+                            (NOTE_NAME_PEGASUS, NT_PEGASUS_REGION) => {
+                                let region_info: CoreRegionInfo = util::slice_read_val(&desc, None)?;
+                                // Note: dumps made before regions had names just lack them
+                                let name = match desc.len() > std::mem::size_of::<CoreRegionInfo>() {
+                                    true => Some(String::from_utf8_lossy(&desc[std::mem::size_of::<CoreRegionInfo>()..]).to_string()),
+                                    false => None
+                                };
+                                region_infos.push((region_info, name));
+                            },
+                            // Note: other notes (from other tools, for instance) are just ignored
+                            _ => {}
+                        };
+                    }
+                },
+                _ => {}
+            };
+        }
+
+        let process_info = match process_info {
+            Some(process_info) => process_info,
+            None => return cpu_result::ResultInvalidCoreDump::make_err()
+        };
+
+        let mut modules: Vec<ModuleMemory> = module_infos.iter().map(|(_, file_name)| ModuleMemory::new(file_name.clone(), Vec::new())).collect();
+        let mut threads: Vec<CoreDumpThread> = thread_registers.iter().map(|(thread_id, registers)| CoreDumpThread {
+            id: *thread_id,
+            registers: *registers,
+            stack: None,
+            tlr: None
+        }).collect();
+        for (address, data) in region_datas.into_iter() {
+            let (region_info, region_name) = match region_infos.iter().find(|(region_info, _)| region_info.address == address) {
+                Some((region_info, region_name)) => (*region_info, region_name.clone()),
+                None => return cpu_result::ResultInvalidCoreDump::make_err()
+            };
+
+            let mut region = MemoryRegion::from(address, data, MemoryPermission::from_bits_truncate(region_info.perm));
+            region.state = KMemoryState::from(region_info.state);
+            region.name = region_name;
+
+            if region_info.thread_id != 0 {
+                if let Some(thread) = threads.iter_mut().find(|thread| thread.id == region_info.thread_id) {
+                    match region.state == KMemoryState::ThreadLocal() {
+                        true => thread.tlr = Some(region),
+                        false => thread.stack = Some(region)
+                    };
+                }
+            }
+            else {
+                match module_infos.iter().position(|(module_info, _)| (module_info.start_address <= address) && (address < module_info.end_address)) {
+                    Some(module_idx) => modules[module_idx].regions.push(region),
+                    None => return cpu_result::ResultInvalidCoreDump::make_err()
+                };
+            }
+        }
+
+        let name_len = process_info.name.iter().position(|&ch| ch == 0).unwrap_or(process_info.name.len());
+        Ok(Self {
+            arch: arch,
+            program_id: ProgramId(process_info.program_id),
+            process_id: process_info.process_id,
+            process_name: String::from_utf8_lossy(&process_info.name[..name_len]).to_string(),
+            modules: modules,
+            threads: threads
+        })
+    }
+
+    pub fn get_memory_map(&self) -> Vec<cpu::MemoryMapEntry> {
+        cpu::make_memory_map(&self.modules, self.threads.iter().flat_map(|thread| thread.stack.iter().chain(thread.tlr.iter())))
+    }
+
+    // Fresh engine with all the dumped memory and the registers of the given thread, meant for post-mortem inspection (not for resuming execution)
+    pub fn create_execution_context(&self, thread_idx: usize) -> Result<ExecutionContext> {
+        let thread = match self.threads.get(thread_idx) {
+            Some(thread) => thread,
+            None => return cpu_result::ResultInvalidCoreDump::make_err()
+        };
+        let (stack, tlr) = match (thread.stack.as_ref(), thread.tlr.as_ref()) {
+            (Some(stack), Some(tlr)) => (copy_region(stack), copy_region(tlr)),
+            _ => return cpu_result::ResultInvalidCoreDump::make_err()
+        };
+
+        // The engine needs an entry address inside module memory, which the PC might not be in after a crash
+        let pc = thread.registers[CORE_DUMP_PC_INDEX];
+        let is_pc_mapped = self.modules.iter().flat_map(|module| module.regions.iter()).any(|region| region.contains(pc));
+        let entry_addr = match is_pc_mapped {
+            true => pc,
+            false => self.modules.iter().flat_map(|module| module.regions.iter()).map(|region| region.start()).next().unwrap_or(pc)
+        };
+
+        let registers: Vec<(Register, u64)> = CORE_DUMP_REGISTERS.iter().zip(thread.registers.iter()).filter(|(reg, _)| cpu::is_register_supported(self.arch, **reg)).map(|(reg, value)| (*reg, *value)).collect();
+        ExecutionContext::new(self.arch, entry_addr, &self.modules, stack, tlr, &registers)
+    }
+
+    // Reads dumped memory (modules or thread stacks/TLRs) without creating any engine
+    pub fn read_memory(&self, address: u64, data: &mut [u8]) -> Result<()> {
+        if let Some(module) = self.modules.iter().find(|module| module.contains(address)) {
+            return module.read_memory(address, data);
+        }
+
+        for region in self.threads.iter().flat_map(|thread| thread.stack.iter().chain(thread.tlr.iter())) {
+            if region.contains(address) && region.contains(address + data.len() as u64 - 1) {
+                let offset = (address - region.start()) as usize;
+                data.copy_from_slice(&region.data[offset..offset + data.len()]);
+                return Ok(());
+            }
+        }
+
+        cpu_result::ResultUnicornReadUnmappedMemory::make_err()
+    }
+
+    // The given thread's stack and TLR, as (address, size) ranges
+    pub fn get_thread_ranges(&self, thread_idx: usize) -> Vec<(u64, usize)> {
+        self.threads.get(thread_idx).map(|thread| thread.stack.iter().chain(thread.tlr.iter()).map(|region| (region.address, region.len())).collect()).unwrap_or_default()
+    }
+
+    // Same as above but on any CPU backend (see emu::cpu::lockstep), with only the given thread's stack and TLR mapped along with the modules
+    pub fn create_backend(&self, thread_idx: usize, kind: BackendKind) -> Result<Box<dyn CpuBackend>> {
+        let thread = match self.threads.get(thread_idx) {
+            Some(thread) => thread,
+            None => return cpu_result::ResultInvalidCoreDump::make_err()
+        };
+
+        let mut cpu_backend = backend::create_backend(kind, self.arch)?;
+        for module in self.modules.iter() {
+            cpu_backend.map_module(module)?;
+        }
+        for region in thread.stack.iter().chain(thread.tlr.iter()) {
+            cpu_backend.map_memory(region.address, &region.data, region.perm)?;
+        }
+        for (reg, value) in CORE_DUMP_REGISTERS.iter().zip(thread.registers.iter()).filter(|(reg, _)| cpu::is_register_supported(self.arch, **reg)) {
+            cpu_backend.write_register(*reg, *value)?;
+        }
+
+        Ok(cpu_backend)
+    }
+}
@@ -1,8 +1,16 @@
-use unicorn::{RegisterARM64, Engine, Handle};
+use unicorn::{RegisterARM, RegisterARM64, EngineBuilder, Handle, MemoryMap};
 use unicorn::unicorn_const::{Arch, Mode, Permission};
+use core::result::Result as CoreResult;
 use std::boxed::Box;
+use std::collections::BTreeSet;
 use std::ffi::c_void;
+use std::fmt;
+use std::marker::PhantomData;
 use std::path::PathBuf;
+use std::ptr;
+use std::sync::{Arc, Weak};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use parking_lot::{Condvar, Mutex, RwLock};
 use crate::fs::{FileSystem, FileOpenMode, ReadOption};
 use crate::fs::result as fs_result;
 use crate::kern::proc::get_current_process;
@@ -10,37 +18,88 @@ use crate::ldr::npdm::NpdmData;
 use crate::os::ThreadLocalRegion;
 use crate::util::{self, Shared, slice_read_data_advance, slice_read_val_advance};
 use crate::result::*;
+use crate::emu::cfg;
 use crate::emu::kern as emu_kern;
-use crate::kern::thread::{get_current_thread, get_scheduler};
+use crate::emu::disasm;
+use crate::emu::host_profiler;
+use crate::emu::metrics;
+use crate::emu::profiler;
+use crate::emu::speed;
+use crate::emu::trace;
+use crate::emu::cpu::backend::{CpuBackend, UnicornBackend};
+use crate::kern::thread::{get_current_thread, get_scheduler, update_current_guest_thread_name};
+use crate::kern;
 use crate::kern::svc;
+use crate::kern::result as kern_result;
+use crate::kern::mem::{PAGE_SIZE, KMemoryState, convert_memory_state, is_page_aligned, check_page_aligned_range};
+use crate::result as lib_result;
 use crate::ldr;
 use crate::ldr::result as ldr_result;
 
 pub mod result;
 
+pub mod backend;
+
+pub mod lockstep;
+
 pub struct MemoryRegion {
     pub address: u64,
-    pub data: Vec<u8>,
-    pub perm: Permission
+    // Note: read-only module segments might be shared with other processes (see Context::load_nso), thus any writes must be done copy-on-write
+    pub data: Arc<Vec<u8>>,
+    pub perm: Permission,
+    pub state: KMemoryState,
+    // Shown in memory maps (module segments, thread stacks/TLRs...), the owner module's file name being used otherwise (see ModuleMemory::get_region_name)
+    pub name: Option<String>
+}
+
+// Module segments are code memory: writable ones (.data/.bss) are CodeData, the rest Code
+fn get_default_memory_state(perm: Permission) -> KMemoryState {
+    match perm.contains(Permission::WRITE) {
+        true => KMemoryState::CodeData(),
+        false => KMemoryState::Code()
+    }
 }
 
 impl MemoryRegion {
-    pub const fn empty() -> Self {
+    pub fn empty() -> Self {
         Self {
             address: 0,
-            data: Vec::new(),
-            perm: Permission::NONE
+            data: Arc::new(Vec::new()),
+            perm: Permission::NONE,
+            state: KMemoryState::Free(),
+            name: None
         }
     }
 
     pub fn from(address: u64, data: Vec<u8>, perm: Permission) -> Self {
+        Self {
+            address: address,
+            data: Arc::new(data),
+            perm: perm,
+            state: get_default_memory_state(perm),
+            name: None
+        }
+    }
+
+    pub fn from_shared(address: u64, data: Arc<Vec<u8>>, perm: Permission) -> Self {
         Self {
             address: address,
             data: data,
-            perm: perm
+            perm: perm,
+            state: get_default_memory_state(perm),
+            name: None
         }
     }
 
+    pub fn is_shared(&self) -> bool {
+        Arc::strong_count(&self.data) > 1
+    }
+
+    // Note: only meant for regions which are never shared (stack, TLR...), otherwise the returned memory might not be the mapped one
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        Arc::make_mut(&mut self.data).as_mut_ptr()
+    }
+
     pub fn start(&self) -> u64 {
         self.address
     }
@@ -56,6 +115,75 @@ impl MemoryRegion {
     pub fn contains(&self, addr: u64) -> bool {
         (self.start() <= addr) && (self.end() > addr)
     }
+
+    #[inline]
+    pub fn check_page_alignment(&self) -> Result<()> {
+        check_page_aligned_range(self.address, self.len())
+    }
+
+    // Another region backed by the same memory, keeping everything but the address
+    pub fn make_alias(&self, address: u64) -> Self {
+        Self {
+            address: address,
+            data: self.data.clone(),
+            perm: self.perm,
+            state: self.state,
+            name: self.name.clone()
+        }
+    }
+}
+
+// A single mapping as shown in memory maps (QueryMemory logs, core-info, crash reports...)
+#[derive(Clone, Debug)]
+pub struct MemoryMapEntry {
+    pub address: u64,
+    pub size: usize,
+    pub perm: Permission,
+    pub state: KMemoryState,
+    pub name: String
+}
+
+impl MemoryMapEntry {
+    pub fn new(region: &MemoryRegion, name: String) -> Self {
+        Self {
+            address: region.start(),
+            size: region.len(),
+            perm: region.perm,
+            state: region.state,
+            name: name
+        }
+    }
+
+    pub fn end(&self) -> u64 {
+        self.address + self.size as u64
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        (self.address <= addr) && (self.end() > addr)
+    }
+}
+
+impl fmt::Display for MemoryMapEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let perm_str: String = [(Permission::READ, 'r'), (Permission::WRITE, 'w'), (Permission::EXEC, 'x')].iter().map(|(perm, ch)| if self.perm.contains(*perm) { *ch } else { '-' }).collect();
+        write!(f, "{:#012X}-{:#012X} {} {:<16} {}", self.address, self.end(), perm_str, format!("{:?}", convert_memory_state(self.state)), self.name)
+    }
+}
+
+// Sorted by address, for the given module and thread (stack/TLR) regions
+pub fn make_memory_map<'a, I: Iterator<Item = &'a MemoryRegion>>(modules: &[ModuleMemory], thread_regions: I) -> Vec<MemoryMapEntry> {
+    let mut memory_map: Vec<MemoryMapEntry> = modules.iter().flat_map(|module| module.regions.iter().map(move |region| MemoryMapEntry::new(region, module.get_region_name(region)))).collect();
+    memory_map.extend(thread_regions.map(|region| MemoryMapEntry::new(region, region.name.clone().unwrap_or_else(|| String::from("<unk>")))));
+    memory_map.sort_by_key(|entry| entry.address);
+    memory_map
+}
+
+// Something like 'main.text+0x1234', for annotating addresses in logs
+pub fn describe_address(memory_map: &[MemoryMapEntry], address: u64) -> String {
+    match memory_map.iter().find(|entry| entry.contains(address)) {
+        Some(entry) => format!("{}+{:#X}", entry.name, address - entry.address),
+        None => String::from("<unmapped>")
+    }
 }
 
 pub struct ModuleMemory {
@@ -88,37 +216,166 @@ impl ModuleMemory {
 
         None
     }
+
+    pub fn get_region_name(&self, region: &MemoryRegion) -> String {
+        region.name.clone().unwrap_or_else(|| self.file_name.clone())
+    }
+
+    pub fn start(&self) -> u64 {
+        self.regions.first().map(|region| region.start()).unwrap_or(0)
+    }
+
+    pub fn end(&self) -> u64 {
+        self.regions.iter().map(|region| region.end()).max().unwrap_or(0)
+    }
+
+    pub fn contains(&self, addr: u64) -> bool {
+        self.regions.iter().any(|region| region.contains(addr))
+    }
+
+    pub fn read_memory(&self, address: u64, data: &mut [u8]) -> Result<()> {
+        // Note: the last accessed address below would underflow otherwise
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        for region in self.regions.iter() {
+            if region.contains(address) && region.contains(address + data.len() as u64 - 1) {
+                let offset = (address - region.start()) as usize;
+                data.copy_from_slice(&region.data[offset..offset + data.len()]);
+                return Ok(());
+            }
+        }
+
+        result::ResultUnicornReadUnmappedMemory::make_err()
+    }
+
+    pub fn read_memory_val<T: Copy>(&self, address: u64) -> Result<T> {
+        let mut data: Vec<u8> = vec![0; std::mem::size_of::<T>()];
+        self.read_memory(address, &mut data)?;
+        util::slice_read_val(&data, None)
+    }
 }
 
 pub type UnicornHook = *mut c_void;
 pub type Register = RegisterARM64;
 pub type MemoryPermission = Permission;
 
-pub struct ContextHandle(pub Handle);
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Architecture {
+    Aarch64,
+    Aarch32
+}
+
+impl Architecture {
+    pub const fn from_npdm(npdm: &NpdmData) -> Self {
+        match npdm.meta.flags.is_64bit() {
+            true => Self::Aarch64,
+            false => Self::Aarch32
+        }
+    }
+}
+
+const AARCH32_R0: i32 = RegisterARM::R0 as i32;
+
+// Registers are always specified with their AArch64 names, 32-bit guests use the equivalent AArch32 registers
+// Note: only R0-R12 have an equivalent (Xn/Wn), 32-bit SVCs taking 64-bit values (split in register pairs) would need their own handlers
+fn get_aarch32_register_id(reg: Register) -> Option<i32> {
+    let reg_id = reg as i32;
+    if (Register::X0 as i32 <= reg_id) && (reg_id <= Register::X12 as i32) {
+        return Some(AARCH32_R0 + (reg_id - Register::X0 as i32));
+    }
+    if (Register::W0 as i32 <= reg_id) && (reg_id <= Register::W12 as i32) {
+        return Some(AARCH32_R0 + (reg_id - Register::W0 as i32));
+    }
+
+    let aarch32_reg = match reg {
+        Register::X29 => RegisterARM::FP,
+        Register::X30 => RegisterARM::LR,
+        Register::SP => RegisterARM::SP,
+        Register::PC => RegisterARM::PC,
+        Register::NZCV => RegisterARM::CPSR,
+        Register::CPACR_EL1 => RegisterARM::C1_C0_2,
+        Register::TPIDR_EL0 => RegisterARM::C13_C0_2,
+        Register::TPIDRRO_EL0 => RegisterARM::C13_C0_3,
+        _ => return None
+    };
+    Some(aarch32_reg as i32)
+}
+
+fn get_arch_register_id(arch: Architecture, reg: Register) -> Result<i32> {
+    match arch {
+        Architecture::Aarch64 => Ok(reg as i32),
+        Architecture::Aarch32 => match get_aarch32_register_id(reg) {
+            Some(reg_id) => Ok(reg_id),
+            None => result::ResultUnsupportedRegister::make_err()
+        }
+    }
+}
+
+pub fn is_register_supported(arch: Architecture, reg: Register) -> bool {
+    get_arch_register_id(arch, reg).is_ok()
+}
+
+// Handle to the backend of an execution context (see backend::CpuBackend::get_handle), for code accessing it while it runs (hooks, SVC handlers, the kernel...)
+pub struct ContextHandle(Box<dyn CpuBackend>);
+
+// Note: handles are just views of backends, which are only run by their own threads
+unsafe impl Send for ContextHandle {}
+
+impl Clone for ContextHandle {
+    fn clone(&self) -> Self {
+        self.0.get_handle()
+    }
+}
 
 impl ContextHandle {
+    pub fn new(backend: Box<dyn CpuBackend>) -> Self {
+        Self(backend)
+    }
+
+    fn from_unicorn(uc_h: Handle) -> Self {
+        Self(Box::new(UnicornBackend::from_handle(uc_h).unwrap()))
+    }
+
+    pub fn get_architecture(&self) -> Result<Architecture> {
+        Ok(self.0.get_architecture())
+    }
+
+    // Values go through the backend as 64-bit ones, thus only types up to 8 bytes (integers, results, handles...) can be read/written
     pub fn read_register<T>(&self, reg: Register) -> Result<T> {
-        result::convert_unicorn_error(self.0.reg_read::<T>(reg as i32))
+        result_return_if!(std::mem::size_of::<T>() > std::mem::size_of::<u64>(), result::ResultUnsupportedRegister);
+        let value = self.0.read_register(reg)?;
+        Ok(unsafe { ptr::read_unaligned(&value as *const u64 as *const T) })
     }
 
     pub fn write_register<T>(&mut self, reg: Register, t: T) -> Result<()> {
-        result::convert_unicorn_error(self.0.reg_write::<T>(reg as i32, t))
+        result_return_if!(std::mem::size_of::<T>() > std::mem::size_of::<u64>(), result::ResultUnsupportedRegister);
+        let mut value: u64 = 0;
+        unsafe {
+            ptr::write_unaligned(&mut value as *mut u64 as *mut T, t);
+        }
+        self.0.write_register(reg, value)
     }
 
     pub fn read_memory(&self, address: u64, data: &mut [u8]) -> Result<()> {
-        result::convert_unicorn_error(self.0.mem_read(address, data))
+        self.0.read_memory(address, data)
     }
 
     pub fn write_memory(&mut self, address: u64, data: &[u8]) -> Result<()> {
-        result::convert_unicorn_error(self.0.mem_write(address, data))
+        self.0.write_memory(address, data)
     }
 
     pub fn read_memory_val<T>(&self, address: u64) -> Result<T> {
-        result::convert_unicorn_error(self.0.mem_read_val(address))
+        let mut t = std::mem::MaybeUninit::<T>::zeroed();
+        let t_data = unsafe { std::slice::from_raw_parts_mut(t.as_mut_ptr() as *mut u8, std::mem::size_of::<T>()) };
+        self.read_memory(address, t_data)?;
+        Ok(unsafe { t.assume_init() })
     }
 
     pub fn write_memory_val<T>(&mut self, address: u64, t: T) -> Result<()> {
-        result::convert_unicorn_error(self.0.mem_write_val(address, t))
+        let t_data = unsafe { std::slice::from_raw_parts(&t as *const T as *const u8, std::mem::size_of::<T>()) };
+        self.write_memory(address, t_data)
     }
 
     pub fn start<T, U>(&mut self, arg_x0: T, arg_x1: U, exec_start_addr: u64, exec_end_addr: u64) -> Result<()> {
@@ -129,13 +386,248 @@ impl ContextHandle {
         let fpv: u64 = 3 << 20;
         self.write_register(Register::CPACR_EL1, fpv)?;
 
-        result::convert_unicorn_error(self.0.emu_start(exec_start_addr, exec_end_addr, 0, 0))
+        self.resume(exec_start_addr, exec_end_addr)
+    }
+
+    // Continues execution at the given address, keeping the current register state
+    pub fn resume(&mut self, exec_start_addr: u64, exec_end_addr: u64) -> Result<()> {
+        self.0.run_at(exec_start_addr, exec_end_addr)
+    }
+
+    pub fn stop(&mut self) -> Result<()> {
+        self.0.stop()
+    }
+
+    // Address to resume execution at after it was stopped (32-bit guests running T32 code need the Thumb bit set)
+    pub fn get_resume_address(&self) -> Result<u64> {
+        let pc: u64 = self.read_register(Register::PC)?;
+        if self.get_architecture()? == Architecture::Aarch32 {
+            const CPSR_THUMB: u32 = 1 << 5;
+            let cpsr: u32 = self.read_register(Register::NZCV)?;
+            if (cpsr & CPSR_THUMB) != 0 {
+                return Ok(pc | 1);
+            }
+        }
+
+        Ok(pc)
+    }
+
+    // Returns how many bytes (up to max_size) starting at the given address are mapped (possibly across several contiguous regions) with at least the given permissions
+    pub fn get_accessible_size(&self, address: u64, max_size: usize, perm: Permission) -> Result<usize> {
+        self.0.get_accessible_size(address, max_size, perm)
+    }
+
+    pub fn check_memory_access(&self, address: u64, size: usize, perm: Permission) -> Result<()> {
+        result_return_if!(address.checked_add(size as u64).is_none(), kern_result::ResultInvalidPointer);
+        result_return_unless!(self.get_accessible_size(address, size, perm)? == size, kern_result::ResultInvalidPointer);
+        Ok(())
+    }
+
+    pub fn map_region(&mut self, region: &MemoryRegion) -> Result<()> {
+        self.0.map_region(region)
+    }
+
+    // Maps either all the regions or none of them, failing with the index of the conflicting region otherwise
+    pub fn map_regions(&mut self, regions: &[MemoryRegion]) -> CoreResult<(), (usize, ResultCode)> {
+        self.0.map_regions(regions)
+    }
+
+    pub fn unmap_memory(&mut self, address: u64, size: usize) -> Result<()> {
+        self.0.unmap_memory(address, size)
+    }
+
+    pub fn protect_memory(&mut self, address: u64, size: usize, perm: Permission) -> Result<()> {
+        self.0.protect_memory(address, size, perm)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum StringDecodeMode {
+    // Invalid strings are an error
+    Strict,
+    // Invalid sequences are replaced with U+FFFD
+    Lossy
+}
+
+// Typed pointer to guest memory: accesses are validated against the mapped regions first, so that malformed guest pointers end up as proper results instead of unicorn faults
+pub struct GuestPtr<T: Copy> {
+    address: u64,
+    _marker: PhantomData<T>
+}
+
+impl<T: Copy> Clone for GuestPtr<T> {
+    fn clone(&self) -> Self {
+        Self::new(self.address)
+    }
+}
+
+impl<T: Copy> Copy for GuestPtr<T> {}
+
+impl<T: Copy> GuestPtr<T> {
+    pub const fn new(address: u64) -> Self {
+        Self {
+            address: address,
+            _marker: PhantomData
+        }
+    }
+
+    #[inline]
+    pub const fn get_address(&self) -> u64 {
+        self.address
+    }
+
+    #[inline]
+    pub const fn is_null(&self) -> bool {
+        self.address == 0
+    }
+
+    pub fn offset(&self, count: usize) -> Result<Self> {
+        match (count as u64).checked_mul(std::mem::size_of::<T>() as u64).and_then(|offset| self.address.checked_add(offset)) {
+            Some(address) => Ok(Self::new(address)),
+            None => kern_result::ResultInvalidPointer::make_err()
+        }
+    }
+
+    fn get_array_size(count: usize) -> Result<usize> {
+        match count.checked_mul(std::mem::size_of::<T>()) {
+            Some(size) => Ok(size),
+            None => kern_result::ResultInvalidSize::make_err()
+        }
+    }
+
+    pub fn read(&self, ctx_h: &ContextHandle) -> Result<T> {
+        ctx_h.check_memory_access(self.address, std::mem::size_of::<T>(), Permission::READ)?;
+        ctx_h.read_memory_val(self.address)
+    }
+
+    pub fn write(&self, ctx_h: &mut ContextHandle, t: T) -> Result<()> {
+        ctx_h.check_memory_access(self.address, std::mem::size_of::<T>(), Permission::WRITE)?;
+        ctx_h.write_memory_val(self.address, t)
+    }
+
+    pub fn read_array(&self, ctx_h: &ContextHandle, count: usize) -> Result<Vec<T>> {
+        let size = Self::get_array_size(count)?;
+        ctx_h.check_memory_access(self.address, size, Permission::READ)?;
+
+        // Read everything at once, unicorn reads are not that cheap
+        let mut data: Vec<u8> = vec![0; size];
+        if size > 0 {
+            ctx_h.read_memory(self.address, &mut data)?;
+        }
+
+        let mut ts: Vec<T> = Vec::with_capacity(count);
+        for i in 0..count {
+            ts.push(util::slice_read_val(&data, Some(i * std::mem::size_of::<T>()))?);
+        }
+        Ok(ts)
+    }
+
+    pub fn write_array(&self, ctx_h: &mut ContextHandle, ts: &[T]) -> Result<()> {
+        ctx_h.check_memory_access(self.address, Self::get_array_size(ts.len())?, Permission::WRITE)?;
+
+        for (i, t) in ts.iter().enumerate() {
+            ctx_h.write_memory_val(self.offset(i)?.address, *t)?;
+        }
+        Ok(())
+    }
+}
+
+impl GuestPtr<u8> {
+    pub fn read_bytes(&self, ctx_h: &ContextHandle, size: usize) -> Result<Vec<u8>> {
+        ctx_h.check_memory_access(self.address, size, Permission::READ)?;
+
+        let mut data: Vec<u8> = vec![0; size];
+        if size > 0 {
+            ctx_h.read_memory(self.address, &mut data)?;
+        }
+        Ok(data)
+    }
+
+    pub fn write_bytes(&self, ctx_h: &mut ContextHandle, data: &[u8]) -> Result<()> {
+        ctx_h.check_memory_access(self.address, data.len(), Permission::WRITE)?;
+
+        if !data.is_empty() {
+            ctx_h.write_memory(self.address, data)?;
+        }
+        Ok(())
+    }
+
+    // Reads a NUL-terminated string, failing if no terminator is found within the first max_len bytes
+    pub fn read_c_str(&self, ctx_h: &ContextHandle, max_len: usize, mode: StringDecodeMode) -> Result<String> {
+        let str_buf = read_c_str_units(*self, ctx_h, max_len)?;
+        match mode {
+            StringDecodeMode::Strict => util::convert_utf8_result(String::from_utf8(str_buf)),
+            StringDecodeMode::Lossy => Ok(String::from_utf8_lossy(&str_buf).into_owned())
+        }
+    }
+
+    pub fn read_str(&self, ctx_h: &ContextHandle, len: usize, mode: StringDecodeMode) -> Result<String> {
+        let str_buf = self.read_bytes(ctx_h, len)?;
+        match mode {
+            StringDecodeMode::Strict => util::convert_utf8_result(String::from_utf8(str_buf)),
+            StringDecodeMode::Lossy => Ok(String::from_utf8_lossy(&str_buf).into_owned())
+        }
+    }
+}
+
+impl GuestPtr<u16> {
+    // Same as above but for UTF-16 strings, where lengths are in code units instead of bytes
+    pub fn read_c_str(&self, ctx_h: &ContextHandle, max_len: usize, mode: StringDecodeMode) -> Result<String> {
+        let str_buf = read_c_str_units(*self, ctx_h, max_len)?;
+        match mode {
+            StringDecodeMode::Strict => String::from_utf16(&str_buf).map_err(|_| lib_result::ResultInvalidUtf8String::make()),
+            StringDecodeMode::Lossy => Ok(String::from_utf16_lossy(&str_buf))
+        }
+    }
+
+    pub fn read_str(&self, ctx_h: &ContextHandle, len: usize, mode: StringDecodeMode) -> Result<String> {
+        let str_buf = self.read_array(ctx_h, len)?;
+        match mode {
+            StringDecodeMode::Strict => String::from_utf16(&str_buf).map_err(|_| lib_result::ResultInvalidUtf8String::make()),
+            StringDecodeMode::Lossy => Ok(String::from_utf16_lossy(&str_buf))
+        }
+    }
+}
+
+// Reads code units until the NUL terminator (not included), reading all the accessible memory at once instead of unit by unit
+fn read_c_str_units<T: Copy + Default + PartialEq>(ptr: GuestPtr<T>, ctx_h: &ContextHandle, max_len: usize) -> Result<Vec<T>> {
+    let unit_size = std::mem::size_of::<T>();
+    let max_size = match max_len.checked_mul(unit_size) {
+        Some(max_size) => max_size,
+        None => return kern_result::ResultInvalidSize::make_err()
+    };
+
+    let accessible_len = ctx_h.get_accessible_size(ptr.get_address(), max_size, Permission::READ)? / unit_size;
+    let units = ptr.read_array(ctx_h, accessible_len)?;
+    match units.iter().position(|unit| *unit == T::default()) {
+        Some(nul_idx) => Ok(units[..nul_idx].to_vec()),
+        None => match accessible_len < max_len {
+            // Memory ended before the terminator, the pointer itself is not valid
+            true => kern_result::ResultInvalidPointer::make_err(),
+            false => kern_result::ResultOutOfRange::make_err()
+        }
     }
 }
 
 pub type HookedInstructionHandlerFn = Box<dyn Fn(ContextHandle) -> Result<()>>;
 
 const SVC_INSN_BASE: u32 = 0xD4000001;
+const A32_SVC_INSN_BASE: u32 = 0xEF000000;
+const T32_SVC_INSN_BASE: u16 = 0xDF00;
+
+// MRS Xt, PMCCNTR_EL0 (Xt in the lowest 5 bits)
+const MRS_PMCCNTR_EL0_INSN_BASE: u32 = 0xD53B9D00;
+const MRS_DST_REGISTER_MASK: u32 = 0x1F;
+
+// The console's CPU clock, which the cycle counter is derived from
+const CPU_CLOCK_FREQUENCY: u64 = 1020000000;
+
+const GENERAL_PURPOSE_REGISTERS: [Register; 31] = [
+    Register::X0, Register::X1, Register::X2, Register::X3, Register::X4, Register::X5, Register::X6, Register::X7,
+    Register::X8, Register::X9, Register::X10, Register::X11, Register::X12, Register::X13, Register::X14, Register::X15,
+    Register::X16, Register::X17, Register::X18, Register::X19, Register::X20, Register::X21, Register::X22, Register::X23,
+    Register::X24, Register::X25, Register::X26, Register::X27, Register::X28, Register::X29, Register::X30
+];
 
 pub fn on_interrupt() {
     let is_schedulable = get_current_thread().get().is_schedulable;
@@ -147,47 +639,199 @@ pub fn on_interrupt() {
     }
 }
 
-fn unicorn_code_hook(uc_h: Handle, address: u64, _size: usize) {
-    let ctx_h = ContextHandle(uc_h);
-    let cur_insn: u32 = ctx_h.read_memory_val(address).unwrap();
+#[inline]
+fn record_executed_instruction(address: u64) {
+    metrics::record_instruction();
+    profiler::record_instruction(address);
+    speed::record_instruction();
+}
 
-    // Check first if the instruction is an actual SVC instruction
-    // This quick calc allows us to avoid iterating the SVC handler table for every single instruction, even though it's still a quite ugly implementation (see below)
-    let maybe_svc_id = ((cur_insn & !SVC_INSN_BASE) >> 5) as u8;
+// This quick calc allows us to avoid iterating the SVC handler table for every single instruction
+#[inline]
+fn get_a64_svc_id(insn: u32) -> Option<u8> {
+    let maybe_svc_id = ((insn & !SVC_INSN_BASE) >> 5) as u8;
     let svc_insn = SVC_INSN_BASE | ((maybe_svc_id as u32) << 5);
-    if svc_insn == cur_insn {
-        if let Some(svc_id) = svc::SvcId::from(maybe_svc_id) {
-            if let Some(svc_handler) = emu_kern::try_find_svc_handler(&svc_id) {
-                let svc_enabled = get_current_process().get().npdm.aci0_kernel_capabilities.enabled_svcs.contains(&svc_id);
-                if !svc_enabled {
-                    // TODO: how is this handled in a real console?
-                    panic!("SVC not enabled for this process: {:?}", svc_id);
-                }
-                
+    match svc_insn == insn {
+        true => Some(maybe_svc_id),
+        false => None
+    }
+}
+
+#[inline]
+fn is_pmccntr_read_insn(insn: u32) -> bool {
+    (insn & !MRS_DST_REGISTER_MASK) == MRS_PMCCNTR_EL0_INSN_BASE
+}
+
+// Note: instructions are read through the raw handle, since this runs for every single instruction and only the hooked ones need an actual ContextHandle
+fn unicorn_code_hook(uc_h: Handle, address: u64, size: usize) {
+    let cur_insn: u32 = uc_h.mem_read_val(address).unwrap();
+    record_executed_instruction(address);
+
+    // Check first if the instruction is an actual SVC instruction
+    if let Some(svc_id) = get_a64_svc_id(cur_insn) {
+        handle_svc_insn(ContextHandle::from_unicorn(uc_h), address, size, svc_id);
+    }
+    else if is_pmccntr_read_insn(cur_insn) {
+        handle_pmccntr_read(ContextHandle::from_unicorn(uc_h), address, (cur_insn & MRS_DST_REGISTER_MASK) as usize);
+    }
+}
+
+// Like the above, minus SVCs (see cfg::SvcDetectionMode::Interrupt)
+fn unicorn_emulated_insn_hook(uc_h: Handle, address: u64, _size: usize) {
+    record_executed_instruction(address);
+
+    let cur_insn: u32 = uc_h.mem_read_val(address).unwrap();
+    if is_pmccntr_read_insn(cur_insn) {
+        handle_pmccntr_read(ContextHandle::from_unicorn(uc_h), address, (cur_insn & MRS_DST_REGISTER_MASK) as usize);
+    }
+}
+
+fn unicorn_emulated_insn_hook_aarch32(_uc_h: Handle, address: u64, _size: usize) {
+    record_executed_instruction(address);
+}
+
+// Cycle counter reads are emulated on top of the system tick, so that they follow the same clock as everything else (unicorn's PMU might not even allow EL0 accesses)
+fn handle_pmccntr_read(mut ctx_h: ContextHandle, address: u64, dst_reg_idx: usize) {
+    let cycle_count = ((kern::get_system_tick() as u128 * CPU_CLOCK_FREQUENCY as u128) / kern::SYSTEM_TICK_FREQUENCY as u128) as u64;
+
+    // Writes to XZR are discarded
+    if let Some(dst_reg) = GENERAL_PURPOSE_REGISTERS.get(dst_reg_idx) {
+        ctx_h.write_register(*dst_reg, cycle_count).unwrap();
+    }
+
+    // Skip the actual instruction: unicorn restarts the execution at the new PC once this hook returns
+    ctx_h.write_register(Register::PC, address + 4).unwrap();
+}
+
+// 32-bit guests might run A32 or T32 code, told apart by the instruction size (T32 SVCs are 16-bit instructions)
+fn unicorn_code_hook_aarch32(uc_h: Handle, address: u64, size: usize) {
+    record_executed_instruction(address);
+
+    if size == 2 {
+        let cur_insn: u16 = uc_h.mem_read_val(address).unwrap();
+        if (cur_insn & 0xFF00) == T32_SVC_INSN_BASE {
+            handle_svc_insn(ContextHandle::from_unicorn(uc_h), address, size, (cur_insn & 0xFF) as u8);
+        }
+    }
+    else {
+        // Note: only unconditional SVCs are considered (no guest code uses conditional ones), and no 32-bit T32 instruction can match this
+        let cur_insn: u32 = uc_h.mem_read_val(address).unwrap();
+        if (cur_insn & 0xFFFFFF00) == A32_SVC_INSN_BASE {
+            handle_svc_insn(ContextHandle::from_unicorn(uc_h), address, size, (cur_insn & 0xFF) as u8);
+        }
+    }
+}
+
+fn handle_svc_insn(ctx_h: ContextHandle, address: u64, insn_size: usize, raw_svc_id: u8) {
+    if let Some(svc_id) = svc::SvcId::from(raw_svc_id) {
+        if let Some(svc_handler) = emu_kern::try_find_svc_handler(&svc_id) {
+            let svc_enabled = get_current_process().get().npdm.aci0_kernel_capabilities.enabled_svcs.contains(&svc_id);
+            if !svc_enabled {
+                handle_svc_fault(ctx_h, address, insn_size, format!("SVC not enabled for this process: {:?}", svc_id));
+                return;
+            }
+
+            // Note: names are set by the guest without notifying the kernel at all, thus this is the best place to notice them
+            update_current_guest_thread_name();
+            
+            let mut svc_ctx_h = ctx_h.clone();
+            {
+                let _span = host_profiler::enter_with(|| format!("svc:{:?}", svc_id));
                 (svc_handler)(ctx_h).unwrap();
             }
-            else {
-                panic!("Unimplemented SVC: {:?}", svc_id);
+            metrics::record_svc(svc_id);
+
+            if trace::is_enabled() {
+                let rc: u32 = svc_ctx_h.read_register(Register::W0).unwrap();
+                trace::record_svc(svc_id, rc);
+            }
+
+            // See KThread::request_termination
+            let is_termination_requested = get_current_thread().get().should_be_terminated;
+            if is_termination_requested {
+                svc_ctx_h.stop().unwrap();
             }
         }
         else {
-            panic!("Invalid SVC Id: {}", maybe_svc_id);
+            handle_svc_fault(ctx_h, address, insn_size, format!("Unimplemented SVC: {:?}", svc_id));
         }
     }
-    
+    else {
+        handle_svc_fault(ctx_h, address, insn_size, format!("Invalid SVC Id: {}", raw_svc_id));
+    }
+}
+
+// Badly behaved guests shouldn't be able to bring down the whole emulator, thus this depends on the configured policy
+fn handle_svc_fault(mut ctx_h: ContextHandle, address: u64, insn_size: usize, fault_desc: String) {
+    match cfg::get_config().svc_fault_policy {
+        cfg::SvcFaultPolicy::Panic => panic!("{}", fault_desc),
+        cfg::SvcFaultPolicy::Result => {
+            log_line!("{}, returning ResultNotImplemented...", fault_desc);
+            ctx_h.write_register(Register::W0, kern_result::ResultNotImplemented::make()).unwrap();
+        },
+        cfg::SvcFaultPolicy::Exception => {
+            log_line!("{}, raising an exception...", fault_desc);
+
+            // Note: as on the console, the reported PC is the one after the SVC instruction, thus returning from the exception doesn't retry the SVC
+            // The exception is dispatched once the execution is stopped (see KThread::exec_thread_fn)
+            let cur_thread = get_current_thread();
+            cur_thread.get().pending_exception = Some((svc::ExceptionType::InvalidSystemCall, address + insn_size as u64, kern_result::ResultNotImplemented::make()));
+            ctx_h.stop().unwrap();
+        }
+    };
 }
 
 fn unicorn_intr_hook(_uc_h: Handle, _intr_no: u32) {
     // This hook is present since unicorn would fail if an interrupt happens and no hook is added.
     // In other CPU emulators, we would be able to get the SVC ID from here, but unicorn itself doesn't provide it.
-    // Therefore, the SVCs are handled above (thanks unicorn for this awful implementation)
+    // Therefore, the SVCs are handled above (thanks unicorn for this awful implementation), unless they're detected through their interrupt (see below)
 
     // log_line!("Interrupt {}!", intr_no);
 
     on_interrupt();
 }
 
+// QEMU's (thus unicorn's) interrupt number for SVC exceptions
+const EXCP_SWI: u32 = 2;
+
+// SVCs are decoded from the instruction which raised the interrupt, since the SVC ID (the exception syndrome) isn't available either
+// Note: the PC is already past the SVC instruction at this point (it's the exception's return address)
+fn unicorn_intr_hook_svc(uc_h: Handle, intr_no: u32) {
+    if intr_no == EXCP_SWI {
+        let ctx_h = ContextHandle::from_unicorn(uc_h);
+        let pc: u64 = ctx_h.read_register(Register::PC).unwrap();
+        match ctx_h.get_architecture().unwrap() {
+            Architecture::Aarch64 => {
+                let svc_insn: u32 = ctx_h.read_memory_val(pc - 4).unwrap();
+                if let Some(svc_id) = get_a64_svc_id(svc_insn) {
+                    handle_svc_insn(ctx_h, pc - 4, 4, svc_id);
+                }
+            },
+            Architecture::Aarch32 => {
+                const CPSR_THUMB: u32 = 1 << 5;
+                let cpsr: u32 = ctx_h.read_register(Register::NZCV).unwrap();
+                if (cpsr & CPSR_THUMB) != 0 {
+                    let svc_insn: u16 = ctx_h.read_memory_val(pc - 2).unwrap();
+                    if (svc_insn & 0xFF00) == T32_SVC_INSN_BASE {
+                        handle_svc_insn(ctx_h, pc - 2, 2, (svc_insn & 0xFF) as u8);
+                    }
+                }
+                else {
+                    let svc_insn: u32 = ctx_h.read_memory_val(pc - 4).unwrap();
+                    if (svc_insn & 0xFFFFFF00) == A32_SVC_INSN_BASE {
+                        handle_svc_insn(ctx_h, pc - 4, 4, (svc_insn & 0xFF) as u8);
+                    }
+                }
+            }
+        };
+    }
+
+    on_interrupt();
+}
+
 fn create_memory_region(segment_file_data: Vec<u8>, address: u64, is_compressed: bool, section_size: usize, perm: Permission) -> Result<MemoryRegion> {
+    result_return_unless!(is_page_aligned(address), kern_result::ResultInvalidAddress);
+
     let mut segment_data = match is_compressed {
         true => lz4_flex::decompress(&segment_file_data, section_size).unwrap(),
         false => segment_file_data
@@ -196,66 +840,343 @@ fn create_memory_region(segment_file_data: Vec<u8>, address: u64, is_compressed:
     // TODO: check hashes if flag enabled?
     
     assert_eq!(segment_data.len(), section_size);
-    segment_data.resize_with(util::align_up(section_size, 0x1000), || 0);
+    segment_data.resize_with(util::align_up(section_size, PAGE_SIZE), || 0);
     log_line!("Creating memory region (size {:#X}, aligned {:#X}) at address {:#X}...", section_size, segment_data.len(), address);
 
     Ok(MemoryRegion::from(address, segment_data, perm))
 }
 
+// Regions of loaded modules are always .text, .rodata, .data and (optionally) .bss, in that order
+fn name_module_regions(file_name: &str, regions: &mut [MemoryRegion]) {
+    for (region, segment_name) in regions.iter_mut().zip([".text", ".rodata", ".data", ".bss"].iter()) {
+        region.name = Some(format!("{}{}", file_name, segment_name));
+    }
+}
+
+// Read-only segments (.text/.rodata) of identical modules (same build ID) loaded by several processes (sdk, for instance) are backed by the same memory
+struct SharedSegment {
+    module_id: [u8; 0x20],
+    memory_offset: u32,
+    data: Weak<Vec<u8>>
+}
+
+static mut G_SHARED_SEGMENTS: Mutex<Vec<SharedSegment>> = parking_lot::const_mutex(Vec::new());
+
+fn create_shared_memory_region<F: FnOnce() -> Result<MemoryRegion>>(module_id: &[u8; 0x20], memory_offset: u32, address: u64, perm: Permission, create_fn: F) -> Result<MemoryRegion> {
+    if !cfg::get_config().share_module_segments {
+        return create_fn();
+    }
+
+    // Note: modules built without a build ID (like most homebrew) have an all-zero one, thus it can't tell whether they're actually identical
+    if *module_id == [0; 0x20] {
+        return create_fn();
+    }
+
+    unsafe {
+        let mut shared_segments = G_SHARED_SEGMENTS.lock();
+        shared_segments.retain(|segment| segment.data.strong_count() > 0);
+
+        let shared_data = shared_segments.iter().filter(|segment| (segment.module_id == *module_id) && (segment.memory_offset == memory_offset)).find_map(|segment| segment.data.upgrade());
+        if let Some(data) = shared_data {
+            log_line!("Sharing memory region (size {:#X}) at address {:#X}...", data.len(), address);
+            return Ok(MemoryRegion::from_shared(address, data, perm));
+        }
+
+        let region = create_fn()?;
+        shared_segments.push(SharedSegment {
+            module_id: *module_id,
+            memory_offset: memory_offset,
+            data: Arc::downgrade(&region.data)
+        });
+        Ok(region)
+    }
+}
+
 #[inline]
 fn map_memory_region(uc_h: &mut Handle, region: &MemoryRegion) -> Result<()> {
-    result::convert_unicorn_error(uc_h.mem_map_ptr(region.address, region.len(), region.perm, region.data.as_ptr() as *mut c_void))
+    region.check_page_alignment()?;
+    uc_h.mem_map_ptr(region.address, region.len(), region.perm, region.data.as_ptr() as *mut c_void).map_err(ResultCode::from)
+}
+
+// Maps either all the regions or none of them, failing with the index of the conflicting region otherwise
+fn map_memory_regions(uc_h: &mut Handle, regions: &[MemoryRegion]) -> CoreResult<(), (usize, ResultCode)> {
+    for (i, region) in regions.iter().enumerate() {
+        region.check_page_alignment().map_err(|rc| (i, rc))?;
+    }
+
+    let maps: Vec<MemoryMap> = regions.iter().map(|region| MemoryMap {
+        address: region.address,
+        size: region.len(),
+        perms: region.perm,
+        ptr: region.data.as_ptr() as *mut c_void
+    }).collect();
+
+    uc_h.mem_map_ptr_all(&maps).map_err(|(region_idx, err)| (region_idx, ResultCode::from(err)))
+}
+
+fn make_engine_builder(arch: Architecture) -> EngineBuilder {
+    match arch {
+        Architecture::Aarch64 => EngineBuilder::new(Arch::ARM64, Mode::ARM),
+        Architecture::Aarch32 => EngineBuilder::new(Arch::ARM, Mode::ARM)
+    }
+}
+
+// Pages of executable regions containing instructions which need to be emulated (cycle counter reads), as inclusive ranges (like unicorn hook ranges)
+// Note: code which is written/generated afterwards (JITs) isn't covered
+pub fn find_emulated_insn_ranges(modules: &[ModuleMemory]) -> Vec<(u64, u64)> {
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    for region in modules.iter().flat_map(|module| module.regions.iter()).filter(|region| region.perm.contains(Permission::EXEC)) {
+        for (page_idx, page) in region.data.chunks(PAGE_SIZE).enumerate() {
+            let has_emulated_insn = page.chunks_exact(4).any(|insn| is_pmccntr_read_insn(u32::from_le_bytes([insn[0], insn[1], insn[2], insn[3]])));
+            if has_emulated_insn {
+                let page_address = region.address + (page_idx * PAGE_SIZE) as u64;
+                let page_end = page_address + PAGE_SIZE as u64 - 1;
+                match ranges.last_mut() {
+                    Some((_, range_end)) if (*range_end + 1) == page_address => *range_end = page_end,
+                    _ => ranges.push((page_address, page_end))
+                };
+            }
+        }
+    }
+
+    ranges
+}
+
+// Hooks every guest engine needs
+fn apply_engine_hook_preset(arch: Architecture, modules: &[ModuleMemory], builder: EngineBuilder) -> EngineBuilder {
+    let builder = match cfg::get_config().svc_detection_mode {
+        cfg::SvcDetectionMode::CodeHook => {
+            let builder = match arch {
+                Architecture::Aarch64 => builder.code_hook(unicorn_code_hook, 1, 0),
+                Architecture::Aarch32 => builder.code_hook(unicorn_code_hook_aarch32, 1, 0)
+            };
+            builder.intr_hook(unicorn_intr_hook, 1, 0)
+        },
+        cfg::SvcDetectionMode::Interrupt => {
+            let code_hook_fn: fn(Handle, u64, usize) = match arch {
+                Architecture::Aarch64 => unicorn_emulated_insn_hook,
+                Architecture::Aarch32 => unicorn_emulated_insn_hook_aarch32
+            };
+
+            // Every instruction is still hooked if something counts them, otherwise only the pages with instructions to emulate are (only A64 ones are emulated)
+            let counts_instructions = metrics::is_enabled() || profiler::is_enabled() || speed::is_enabled();
+            let builder = match (counts_instructions, arch) {
+                (true, _) => builder.code_hook(code_hook_fn, 1, 0),
+                (false, Architecture::Aarch64) => find_emulated_insn_ranges(modules).into_iter().fold(builder, |builder, (begin, end)| builder.code_hook(code_hook_fn, begin, end)),
+                (false, Architecture::Aarch32) => builder
+            };
+            builder.intr_hook(unicorn_intr_hook_svc, 1, 0)
+        }
+    };
+
+    builder.memory_fault_tracking()
+}
+
+// Writable range backed by memory which is (or might later be) executed at other addresses
+struct CodeWriteRange {
+    address: u64,
+    size: usize,
+    exec_addresses: Vec<u64>
+}
+
+// Guest writes to executable memory, tracked to retranslate the modified code before it gets executed (see cfg's detect_self_modifying_code)
+// Note: this is accessed from hooks, which can't lock the process, thus it's kept apart from the process' Context (which just updates the ranges)
+pub struct CodeWriteWatch {
+    ranges: RwLock<Vec<CodeWriteRange>>,
+    // Pages (at their executable addresses) written since the last flush
+    dirty_pages: Mutex<BTreeSet<u64>>,
+    has_dirty_pages: AtomicBool
+}
+
+impl CodeWriteWatch {
+    pub fn new() -> Self {
+        Self {
+            ranges: RwLock::new(Vec::new()),
+            dirty_pages: Mutex::new(BTreeSet::new()),
+            has_dirty_pages: AtomicBool::new(false)
+        }
+    }
+
+    fn on_write(&self, address: u64, size: usize) {
+        let ranges = self.ranges.read();
+        for range in ranges.iter().filter(|range| (address < range.address + range.size as u64) && (address + size as u64 > range.address)) {
+            let offset = address.max(range.address) - range.address;
+            let end_offset = (address + size as u64).min(range.address + range.size as u64) - range.address;
+
+            let mut dirty_pages = self.dirty_pages.lock();
+            for exec_address in range.exec_addresses.iter() {
+                let mut page = (exec_address + offset) & !(PAGE_SIZE as u64 - 1);
+                while page < exec_address + end_offset {
+                    dirty_pages.insert(page);
+                    page += PAGE_SIZE as u64;
+                }
+            }
+            self.has_dirty_pages.store(true, Ordering::Release);
+        }
+    }
+
+    fn is_dirty(&self, address: u64) -> bool {
+        // Quick check, since this is done for every executed instruction
+        if !self.has_dirty_pages.load(Ordering::Acquire) {
+            return false;
+        }
+
+        self.dirty_pages.lock().contains(&(address & !(PAGE_SIZE as u64 - 1)))
+    }
+
+    fn take_dirty_pages(&self) -> Vec<u64> {
+        let mut dirty_pages = self.dirty_pages.lock();
+        self.has_dirty_pages.store(false, Ordering::Release);
+        std::mem::take(&mut *dirty_pages).into_iter().collect()
+    }
+}
+
+// Shared with the thread running the execution context, which parks on it while paused (see KThread::exec_thread_fn)
+pub struct ExecutionPauseState {
+    is_paused: Mutex<bool>,
+    resumed: Condvar
+}
+
+impl ExecutionPauseState {
+    fn new() -> Self {
+        Self {
+            is_paused: Mutex::new(false),
+            resumed: Condvar::new()
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.is_paused.lock()
+    }
+
+    pub fn wait_while_paused(&self) {
+        let mut is_paused = self.is_paused.lock();
+        while *is_paused {
+            self.resumed.wait(&mut is_paused);
+        }
+    }
+}
+
+// Guest execution contexts run on unicorn, which detects SVCs (and instructions needing emulation) through the hooks above
+// Note: code write detection needs hooks on every guest write, thus it can only be enabled when the backend is created
+fn create_guest_backend(arch: Architecture, modules: &[ModuleMemory], translation_stats: Option<Arc<metrics::TranslationStats>>, code_write_watch: Option<Arc<CodeWriteWatch>>) -> Result<Box<dyn CpuBackend>> {
+    let mut builder = make_engine_builder(arch).page_size(PAGE_SIZE).preset(|builder| apply_engine_hook_preset(arch, modules, builder));
+
+    if let Some(stats) = translation_stats {
+        builder = builder.block_hook(move |_, address, _| stats.record_block(address), 1, 0);
+    }
+    for (start, end) in disasm::get_trace_ranges() {
+        builder = builder.code_hook(|uc_h, address, size| disasm::trace_instruction(&ContextHandle::from_unicorn(uc_h), get_current_process().get().id, address, size), start, end - 1);
+    }
+
+    let mut uc = builder.build()?;
+    if arch == Architecture::Aarch32 {
+        // VFP/NEON also need to be enabled in FPEXC, otherwise any FP instruction is undefined
+        const FPEXC_EN: u32 = 1 << 30;
+        uc.reg_write::<u32>(RegisterARM::FPEXC as i32, FPEXC_EN)?;
+    }
+
+    // Guest writes to watched memory mark the code as dirty, and execution is stopped before running dirty code so that it gets retranslated (see KThread::exec_thread_fn)
+    if let Some(watch) = code_write_watch {
+        let write_watch = watch.clone();
+        uc.add_mem_write_hook(move |_, address, size, _| write_watch.on_write(address, size), 1, 0)?;
+        uc.add_code_hook(move |uc_h, address, _| {
+            if watch.is_dirty(address) {
+                get_current_thread().get().pending_code_flush = true;
+                ContextHandle::from_unicorn(uc_h).stop().unwrap();
+            }
+        }, 1, 0)?;
+    }
+
+    Ok(Box::new(UnicornBackend::from_engine(uc, arch)))
+}
+
+// Only present if metrics are enabled
+fn create_translation_stats() -> Option<Arc<metrics::TranslationStats>> {
+    match metrics::is_enabled() {
+        true => Some(Arc::new(metrics::TranslationStats::new())),
+        false => None
+    }
 }
 
+static G_NEXT_EXECUTION_CONTEXT_ID: AtomicU64 = AtomicU64::new(0);
+
 pub struct ExecutionContext {
-    uc: Engine,
+    backend: Box<dyn CpuBackend>,
+    // Tells the context apart from the other ones of its process (see Context::release_execution_context)
+    id: u64,
     pub exec_start_addr: u64,
     pub exec_end_addr: u64,
     pub stack: MemoryRegion,
-    pub tlr: MemoryRegion
+    pub tlr: MemoryRegion,
+    // Only present if metrics were enabled when the context was created
+    pub translation_stats: Option<Arc<metrics::TranslationStats>>,
+    pub pause_state: Arc<ExecutionPauseState>
 }
 
 impl ExecutionContext {
-    pub fn new(entry_addr: u64, modules: &Vec<ModuleMemory>, stack: MemoryRegion, tlr: MemoryRegion) -> Result<Self> {
-        let mut uc = result::convert_unicorn_error(Engine::new(Arch::ARM64, Mode::ARM))?; 
-
-        result::convert_unicorn_error(uc.add_code_hook(unicorn_code_hook, 1, 0))?;
-        result::convert_unicorn_error(uc.add_intr_hook(unicorn_intr_hook, 1, 0))?;
-        // NOTE: great unicorn Rust bindings, can't even add an invalid-mem-read/write/fetch hook ;)
+    pub fn new(arch: Architecture, entry_addr: u64, modules: &Vec<ModuleMemory>, stack: MemoryRegion, tlr: MemoryRegion, initial_registers: &[(Register, u64)]) -> Result<Self> {
+        let translation_stats = create_translation_stats();
+        let backend = create_guest_backend(arch, modules, translation_stats.clone(), None)?;
+        Self::from_backend(backend, entry_addr, modules, stack, tlr, initial_registers, translation_stats)
+    }
 
+    // Any backend works as long as it can map regions in place (see CpuBackend::map_region), since they're shared with the process and its other contexts
+    pub fn from_backend(mut backend: Box<dyn CpuBackend>, entry_addr: u64, modules: &[ModuleMemory], stack: MemoryRegion, tlr: MemoryRegion, initial_registers: &[(Register, u64)], translation_stats: Option<Arc<metrics::TranslationStats>>) -> Result<Self> {
         let mut exec_end_addr = u64::MAX;
-        for module in modules {
-            for region in module.regions.iter() {
-                map_memory_region(&mut uc.handle, region)?;
-                if region.contains(entry_addr) {
-                    exec_end_addr = region.end();
-                }
+        for region in modules.iter().flat_map(|module| module.regions.iter()) {
+            if region.contains(entry_addr) {
+                exec_end_addr = region.end();
             }
         }
         result_return_if!(exec_end_addr == u64::MAX, result::ResultInvalidExecutionAddress);
 
-        map_memory_region(&mut uc.handle, &stack)?;
-        map_memory_region(&mut uc.handle, &tlr)?;
+        for region in modules.iter().flat_map(|module| module.regions.iter()).chain([&stack, &tlr].iter().copied()) {
+            backend.map_region(region)?;
+        }
 
-        let stack_top = stack.end();
-        let tlr_addr = tlr.start();
+        backend.write_register(Register::SP, stack.end())?;
+        backend.write_register(Register::TPIDRRO_EL0, tlr.start())?;
+        for (reg, value) in initial_registers {
+            backend.write_register(*reg, *value)?;
+        }
 
-        let mut exec_ctx = Self {
-            uc: uc,
+        Ok(Self {
+            backend: backend,
+            id: G_NEXT_EXECUTION_CONTEXT_ID.fetch_add(1, Ordering::SeqCst),
             exec_start_addr: entry_addr,
             exec_end_addr: exec_end_addr,
             stack: stack,
-            tlr: tlr
-        };
+            tlr: tlr,
+            translation_stats: translation_stats,
+            pause_state: Arc::new(ExecutionPauseState::new())
+        })
+    }
+
+    pub fn get_handle(&self) -> ContextHandle {
+        self.backend.get_handle()
+    }
 
-        exec_ctx.write_register(Register::SP, stack_top)?;
-        exec_ctx.write_register(Register::TPIDRRO_EL0, tlr_addr)?;
+    // Stops the backend at the next hookable point (the next instruction, or right after the current SVC), after which its thread parks until resumed
+    // Note: the register context is kept by the backend meanwhile, execution resuming right where it stopped
+    pub fn pause(&self) -> Result<()> {
+        *self.pause_state.is_paused.lock() = true;
+        self.backend.stop()
+    }
 
-        Ok(exec_ctx)
+    pub fn resume(&self) {
+        *self.pause_state.is_paused.lock() = false;
+        self.pause_state.resumed.notify_all();
     }
 
-    pub fn get_handle(&self) -> ContextHandle {
-        ContextHandle(self.uc.handle)
+    #[inline]
+    pub fn is_paused(&self) -> bool {
+        self.pause_state.is_paused()
+    }
+
+    // Gathers the details of the last failed execution (the faulting memory access is only known for memory errors)
+    pub fn get_error_context(&self, rc: ResultCode, thread_id: Option<u64>) -> result::ExecutionErrorContext {
+        result::ExecutionErrorContext::new(rc, thread_id, self.backend.read_register(Register::PC).ok(), self.backend.take_last_memory_fault())
     }
 
     pub fn read_register<T>(&mut self, reg: Register) -> Result<T> {
@@ -270,37 +1191,64 @@ impl ExecutionContext {
 }
 
 pub struct Context {
-    pub modules: Vec<ModuleMemory>
+    pub modules: Vec<ModuleMemory>,
+    // Taken from the program's NPDM (see Context::load_program)
+    pub arch: Architecture,
+    // Handles of the currently alive execution contexts (along with their IDs), needed to (un)map modules loaded at runtime (NROs) in all of them
+    exec_handles: Vec<(u64, ContextHandle)>,
+    // Also of the alive execution contexts, to account for code cache flushes (see metrics::TranslationStats)
+    exec_translation_stats: Vec<Arc<metrics::TranslationStats>>,
+    exec_end_address: u64,
+    // Set on every new execution context of this process (like system registers enabling PMU counters)
+    pub initial_registers: Vec<(Register, u64)>,
+    // Only present if self-modifying code detection is enabled
+    code_write_watch: Option<Arc<CodeWriteWatch>>
 }
 
 impl Context {
     pub const fn new() -> Self {
         Self {
-            modules: Vec::new()
+            modules: Vec::new(),
+            arch: Architecture::Aarch64,
+            exec_handles: Vec::new(),
+            exec_translation_stats: Vec::new(),
+            exec_end_address: 0,
+            initial_registers: Vec::new(),
+            code_write_watch: None
         }
     }
 
     pub fn load_nso(&mut self, file_name: String, base_address: u64, nso_data: Vec<u8>) -> Result<u64> {
         let nso_header: ldr::NsoHeader = util::slice_read_val(&nso_data, None)?;
         result_return_unless!(nso_header.magic == ldr::NsoHeader::MAGIC, ldr_result::ResultInvalidNso);
+        result_return_unless!(is_page_aligned(base_address), kern_result::ResultInvalidAddress);
+        for segment in [nso_header.text_segment, nso_header.rodata_segment, nso_header.data_segment].iter() {
+            result_return_unless!(is_page_aligned(segment.memory_offset as u64), ldr_result::ResultInvalidNso);
+        }
 
         let text_address = base_address + nso_header.text_segment.memory_offset as u64;
-        let text_file_offset = nso_header.text_segment.file_offset as usize;
-        let text_file_size = nso_header.text_file_size as usize;
-        let text_data = nso_data[text_file_offset..text_file_offset + text_file_size].to_vec();
-        let text = create_memory_region(text_data, text_address,
-            nso_header.flags.contains(ldr::NsoFlags::TextCompressed()),
-            nso_header.text_segment.section_size as usize,
-            Permission::READ | Permission::EXEC)?;
+        let text = create_shared_memory_region(&nso_header.module_id, nso_header.text_segment.memory_offset, text_address, Permission::READ | Permission::EXEC, || {
+            let text_file_offset = nso_header.text_segment.file_offset as usize;
+            let text_file_size = nso_header.text_file_size as usize;
+            let text_data = nso_data[text_file_offset..text_file_offset + text_file_size].to_vec();
+            create_memory_region(text_data, text_address,
+                nso_header.flags.contains(ldr::NsoFlags::TextCompressed()),
+                nso_header.text_segment.section_size as usize,
+                Permission::READ | Permission::EXEC)
+        })?;
 
         let rodata_address = base_address + nso_header.rodata_segment.memory_offset as u64;
-        let rodata_file_offset = nso_header.rodata_segment.file_offset as usize;
-        let rodata_file_size = nso_header.rodata_file_size as usize;
-        let rodata_data = nso_data[rodata_file_offset..rodata_file_offset + rodata_file_size].to_vec();
-        let rodata = create_memory_region(rodata_data, rodata_address,
-            nso_header.flags.contains(ldr::NsoFlags::RodataCompressed()),
-            nso_header.rodata_segment.section_size as usize,
-            Permission::READ)?;
+        let rodata = create_shared_memory_region(&nso_header.module_id, nso_header.rodata_segment.memory_offset, rodata_address, Permission::READ, || {
+            let rodata_file_offset = nso_header.rodata_segment.file_offset as usize;
+            let rodata_file_size = nso_header.rodata_file_size as usize;
+            let rodata_data = nso_data[rodata_file_offset..rodata_file_offset + rodata_file_size].to_vec();
+            create_memory_region(rodata_data, rodata_address,
+                nso_header.flags.contains(ldr::NsoFlags::RodataCompressed()),
+                nso_header.rodata_segment.section_size as usize,
+                Permission::READ)
+        })?;
+
+        // Note: .data/.bss are always private copies, since guest writes to them can't be trapped to copy them on demand
 
         let data_address = base_address + nso_header.data_segment.memory_offset as u64;
         let data_file_offset = nso_header.data_segment.file_offset as usize;
@@ -320,7 +1268,9 @@ impl Context {
         
         let text_start_addr = text.start();
 
-        self.modules.push(ModuleMemory::new(file_name, vec![text, rodata, data, bss]));
+        let mut regions = vec![text, rodata, data, bss];
+        name_module_regions(&file_name, &mut regions);
+        self.modules.push(ModuleMemory::new(file_name, regions));
         Ok(text_start_addr)
     }
 
@@ -338,6 +1288,19 @@ impl Context {
     }
 
     pub fn load_program(&mut self, exefs: Shared<dyn FileSystem>, base_address: u64) -> Result<(u64, NpdmData)> {
+        // main.npdm must be present
+        let npdm = {
+            let npdm_file = exefs.get().open_file(PathBuf::from("main.npdm"), FileOpenMode::Read())?;
+            let mut npdm_data: Vec<u8> = vec![0; npdm_file.get().get_size()?];
+            npdm_file.get().read(0, &mut npdm_data, ReadOption::None)?;
+
+            NpdmData::new(&npdm_data)?
+        };
+
+        // Needed before any execution context is created
+        self.arch = Architecture::from_npdm(&npdm);
+        log_line!("Loading {:?} program (address space type {:?})...", self.arch, npdm.meta.flags.get_address_space());
+
         let mut cur_base_addr = base_address;
         let mut cur_start_addr: Option<u64> = None;
 
@@ -363,37 +1326,484 @@ impl Context {
             self.load_program_nso(&exefs, format!("subsdk{}", i), &mut cur_base_addr).ok_if_r::<fs_result::ResultPathNotFound>(0)?;
         }
 
-        // main.npdm must be present
-        let npdm = {
-            let npdm_file = exefs.get().open_file(PathBuf::from("main.npdm"), FileOpenMode::Read())?;
-            let mut npdm_data: Vec<u8> = vec![0; npdm_file.get().get_size()?];
-            npdm_file.get().read(0, &mut npdm_data, ReadOption::None)?;
-
-            NpdmData::new(&npdm_data)?
-        };
+        // 32-bit programs must fit in the 32-bit address space
+        if self.arch == Architecture::Aarch32 {
+            result_return_if!(cur_base_addr > u32::MAX as u64, result::ResultInvalidExecutionAddress);
+        }
 
         Ok((cur_start_addr.unwrap(), npdm))
     }
 
-    pub fn create_execution_context(&self, stack_size: usize, entry_addr: u64) -> Result<ExecutionContext> {
+    pub fn create_execution_context(&mut self, stack_size: usize, entry_addr: u64) -> Result<ExecutionContext> {
         // TODO: set proper address
         let stack_address = self.modules.last().as_ref().unwrap().regions.last().unwrap().end();
         let stack_data = vec![0; stack_size];
-        let stack = create_memory_region(stack_data, stack_address,
+        let mut stack = create_memory_region(stack_data, stack_address,
             false,
             stack_size,
             Permission::READ | Permission::WRITE)?;
+        stack.state = KMemoryState::Stack();
+        stack.name = Some(String::from("stack"));
 
         // TODO: set proper address
         let tlr_address = stack.end();
         let tlr_size = std::mem::size_of::<ThreadLocalRegion>();
         let tlr_data = vec![0; tlr_size];
-        let tlr = create_memory_region(tlr_data, tlr_address,
+        let mut tlr = create_memory_region(tlr_data, tlr_address,
             false,
             tlr_size,
             Permission::READ | Permission::WRITE)?;
+        tlr.state = KMemoryState::ThreadLocal();
+        tlr.name = Some(String::from("TLS page"));
+
+        self.exec_end_address = self.exec_end_address.max(tlr.end());
+        if cfg::get_config().detect_self_modifying_code && self.code_write_watch.is_none() {
+            self.code_write_watch = Some(Arc::new(CodeWriteWatch::new()));
+            self.update_code_write_watch();
+        }
+
+        let translation_stats = create_translation_stats();
+        let backend = create_guest_backend(self.arch, &self.modules, translation_stats.clone(), self.code_write_watch.clone())?;
+        let exec_ctx = ExecutionContext::from_backend(backend, entry_addr, &self.modules, stack, tlr, &self.initial_registers, translation_stats)?;
+
+        self.exec_handles.push((exec_ctx.id, exec_ctx.get_handle()));
+        if let Some(stats) = exec_ctx.translation_stats.clone() {
+            self.exec_translation_stats.push(stats);
+        }
+        Ok(exec_ctx)
+    }
+
+    pub fn release_execution_context(&mut self, exec_ctx: &ExecutionContext) {
+        self.exec_handles.retain(|(exec_id, _)| *exec_id != exec_ctx.id);
+        if let Some(stats) = exec_ctx.translation_stats.as_ref() {
+            self.exec_translation_stats.retain(|exec_stats| !Arc::ptr_eq(exec_stats, stats));
+        }
+    }
+
+    fn find_free_address(&self) -> u64 {
+        // TODO: set proper address (same as stacks/TLRs, this needs actual memory support in kern)
+        let modules_end_address = self.modules.iter().flat_map(|module| module.regions.iter()).map(|region| region.end()).max().unwrap_or(0);
+        util::align_up(modules_end_address.max(self.exec_end_address) as usize, PAGE_SIZE) as u64
+    }
+
+    // Note: the NRO data is expected to be already validated (see the ro service)
+    pub fn load_nro(&mut self, file_name: String, nro_data: &[u8], bss_size: usize) -> Result<u64> {
+        let nro_header: ldr::NroHeader = util::slice_read_val(nro_data, Some(std::mem::size_of::<ldr::NroStart>()))?;
+        result_return_unless!(nro_header.magic == ldr::NroHeader::MAGIC, ldr_result::ResultInvalidNro);
+
+        let base_address = self.find_free_address();
+
+        let text_offset = nro_header.text_segment.file_offset as usize;
+        let text_size = nro_header.text_segment.size as usize;
+        let text = create_memory_region(nro_data[text_offset..text_offset + text_size].to_vec(), base_address + text_offset as u64,
+            false,
+            text_size,
+            Permission::READ | Permission::EXEC)?;
+
+        let rodata_offset = nro_header.rodata_segment.file_offset as usize;
+        let rodata_size = nro_header.rodata_segment.size as usize;
+        let rodata = create_memory_region(nro_data[rodata_offset..rodata_offset + rodata_size].to_vec(), base_address + rodata_offset as u64,
+            false,
+            rodata_size,
+            Permission::READ)?;
+
+        let data_offset = nro_header.data_segment.file_offset as usize;
+        let data_size = nro_header.data_segment.size as usize;
+        let data = create_memory_region(nro_data[data_offset..data_offset + data_size].to_vec(), base_address + data_offset as u64,
+            false,
+            data_size,
+            Permission::READ | Permission::WRITE)?;
+
+        let mut regions = vec![text, rodata, data];
+        if bss_size > 0 {
+            let bss_address = regions.last().unwrap().end();
+            let bss = create_memory_region(vec![0; bss_size], bss_address,
+                false,
+                bss_size,
+                Permission::READ | Permission::WRITE)?;
+            regions.push(bss);
+        }
+        name_module_regions(&file_name, &mut regions);
+
+        // Modules are loaded at runtime, thus they must be mapped on every already existing execution context
+        self.map_regions_on_exec_handles(&regions)?;
+
+        self.modules.push(ModuleMemory::new(file_name, regions));
+        self.update_code_write_watch();
+        Ok(base_address)
+    }
+
+    pub fn unload_nro(&mut self, base_address: u64) -> Result<()> {
+        let module_idx = match self.modules.iter().position(|module| module.regions.first().map(|region| region.start()) == Some(base_address)) {
+            Some(idx) => idx,
+            None => return ldr_result::ResultNotLoaded::make_err()
+        };
+
+        let module = self.modules.remove(module_idx);
+        for (_, handle) in self.exec_handles.iter_mut() {
+            for region in module.regions.iter() {
+                handle.unmap_memory(region.address, region.len())?;
+            }
+        }
+
+        self.update_code_write_watch();
+        Ok(())
+    }
+
+    // Maps the regions on every execution context, leaving all of them untouched if any mapping fails
+    fn map_regions_on_exec_handles(&mut self, regions: &[MemoryRegion]) -> Result<()> {
+        for i in 0..self.exec_handles.len() {
+            if let Err((region_idx, rc)) = self.exec_handles[i].1.map_regions(regions) {
+                let region = &regions[region_idx];
+                log_line!("Unable to map region {:#X}-{:#X} ({:?}): {3} ({3:?})", region.start(), region.end(), region.state, rc);
+
+                for (_, handle) in self.exec_handles[..i].iter_mut() {
+                    for mapped_region in regions.iter() {
+                        let _ = handle.unmap_memory(mapped_region.address, mapped_region.len());
+                    }
+                }
+                return Err(rc);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Whether the whole range is backed by module memory
+    pub fn is_memory_mapped(&self, address: u64, size: usize) -> bool {
+        let end_address = address + size as u64;
+        let mut cur_address = address;
+        while cur_address < end_address {
+            match self.modules.iter().flat_map(|module| module.regions.iter()).find(|region| region.contains(cur_address)) {
+                Some(region) => cur_address = region.end(),
+                None => return false
+            };
+        }
+
+        true
+    }
+
+    fn is_memory_free(&self, address: u64, size: usize) -> bool {
+        let end_address = address + size as u64;
+        !self.modules.iter().flat_map(|module| module.regions.iter()).any(|region| (region.start() < end_address) && (region.end() > address))
+    }
+
+    // Splits the region containing the address (if any) so that a region starts right at it
+    // Note: this reallocates the region, thus any other process mapping it (see Context::share_memory) would no longer see the same memory
+    fn split_memory_at(&mut self, address: u64) -> Result<()> {
+        result_return_unless!(is_page_aligned(address), kern_result::ResultInvalidAddress);
+
+        for module_idx in 0..self.modules.len() {
+            if let Some(region_idx) = self.modules[module_idx].regions.iter().position(|region| region.contains(address) && (region.start() != address)) {
+                let region = self.modules[module_idx].regions.remove(region_idx);
+                let offset = (address - region.start()) as usize;
+                let mut left_region = MemoryRegion::from(region.start(), region.data[..offset].to_vec(), region.perm);
+                left_region.state = region.state;
+                left_region.name = region.name.clone();
+                let mut right_region = MemoryRegion::from(address, region.data[offset..].to_vec(), region.perm);
+                right_region.state = region.state;
+                right_region.name = region.name.clone();
+
+                // Both halves are backed by new memory, thus they need to be mapped again
+                for (_, handle) in self.exec_handles.iter_mut() {
+                    handle.unmap_memory(region.address, region.len())?;
+                }
+                let halves = vec![left_region, right_region];
+                if let Err(rc) = self.map_regions_on_exec_handles(&halves) {
+                    // Put the original region back
+                    for (_, handle) in self.exec_handles.iter_mut() {
+                        let _ = handle.map_region(&region);
+                    }
+                    self.modules[module_idx].regions.insert(region_idx, region);
+                    return Err(rc);
+                }
+
+                for (i, half) in halves.into_iter().enumerate() {
+                    self.modules[module_idx].regions.insert(region_idx + i, half);
+                }
+                return Ok(());
+            }
+        }
+
+        Ok(())
+    }
+
+    // Note: the following are meant for processes whose address space is built by the guest itself (see svc::create_process), thus addresses/sizes are expected to be page-aligned
+
+    pub fn map_memory(&mut self, file_name: String, address: u64, size: usize, perm: Permission, state: KMemoryState) -> Result<()> {
+        check_page_aligned_range(address, size)?;
+
+        let mut region = MemoryRegion::from(address, vec![0; size], perm);
+        region.state = state;
+        self.map_regions(file_name, vec![region])
+    }
+
+    // Note: the regions are expected to be sorted and contiguous
+    pub fn map_regions(&mut self, file_name: String, regions: Vec<MemoryRegion>) -> Result<()> {
+        let (start_address, end_address) = match (regions.first(), regions.last()) {
+            (Some(first_region), Some(last_region)) => (first_region.start(), last_region.end()),
+            _ => return kern_result::ResultInvalidSize::make_err()
+        };
+        for region in regions.iter() {
+            region.check_page_alignment()?;
+        }
+        result_return_unless!(self.is_memory_free(start_address, (end_address - start_address) as usize), kern_result::ResultInvalidMemoryRegion);
+
+        self.map_regions_on_exec_handles(&regions)?;
+
+        self.modules.push(ModuleMemory::new(file_name, regions));
+        self.exec_end_address = self.exec_end_address.max(end_address);
+        self.update_code_write_watch();
+        Ok(())
+    }
+
+    pub fn unmap_regions(&mut self, address: u64, size: usize, state: KMemoryState) -> Result<Vec<MemoryRegion>> {
+        check_page_aligned_range(address, size)?;
+
+        let end_address = address + size as u64;
+        let regions = self.get_regions(address, size);
+        let is_range_valid = regions.iter().all(|region| (region.start() >= address) && (region.end() <= end_address) && (region.state == state));
+        result_return_unless!(is_range_valid && self.is_memory_mapped(address, size), kern_result::ResultInvalidMemoryRegion);
+
+        let mut unmapped_regions: Vec<MemoryRegion> = Vec::new();
+        for module in self.modules.iter_mut() {
+            let (range_regions, kept_regions): (Vec<MemoryRegion>, Vec<MemoryRegion>) = module.regions.drain(..).partition(|region| (region.start() >= address) && (region.end() <= end_address));
+            module.regions = kept_regions;
+            unmapped_regions.extend(range_regions);
+        }
+        self.modules.retain(|module| !module.regions.is_empty());
+        unmapped_regions.sort_by_key(|region| region.start());
+
+        for (_, handle) in self.exec_handles.iter_mut() {
+            for region in unmapped_regions.iter() {
+                handle.unmap_memory(region.address, region.len())?;
+            }
+        }
+
+        self.update_code_write_watch();
+        Ok(unmapped_regions)
+    }
+
+    // Regions overlapping the range, sorted by address
+    pub fn get_regions(&self, address: u64, size: usize) -> Vec<&MemoryRegion> {
+        let end_address = address + size as u64;
+        let mut regions: Vec<&MemoryRegion> = self.modules.iter().flat_map(|module| module.regions.iter()).filter(|region| (region.start() < end_address) && (region.end() > address)).collect();
+        regions.sort_by_key(|region| region.start());
+        regions
+    }
+
+    // Returns regions backed by the same memory as the range, so that it can be mapped elsewhere (other processes, for instance)
+    pub fn share_memory(&mut self, address: u64, size: usize, required_state: KMemoryState) -> Result<Vec<MemoryRegion>> {
+        check_page_aligned_range(address, size)?;
+        result_return_unless!(self.is_memory_mapped(address, size), kern_result::ResultInvalidCurrentMemory);
+        let is_state_valid = self.get_regions(address, size).iter().all(|region| region.state.contains(required_state));
+        result_return_unless!(is_state_valid, kern_result::ResultInvalidCurrentMemory);
+
+        self.split_memory_at(address)?;
+        self.split_memory_at(address + size as u64)?;
+        self.update_code_write_watch();
+
+        Ok(self.get_regions(address, size).iter().map(|region| region.make_alias(region.address)).collect())
+    }
+
+    // Note: unlike the actual kernel (which aliases the source memory), the memory is moved to the new address, the source range being unmapped
+    pub fn move_memory(&mut self, file_name: String, dst_address: u64, src_address: u64, size: usize, perm: Permission, state: KMemoryState) -> Result<()> {
+        check_page_aligned_range(dst_address, size)?;
+        check_page_aligned_range(src_address, size)?;
+        result_return_unless!(self.is_memory_mapped(src_address, size), kern_result::ResultInvalidCurrentMemory);
+        result_return_unless!(self.is_memory_free(dst_address, size), kern_result::ResultInvalidMemoryRegion);
+
+        self.split_memory_at(src_address)?;
+        self.split_memory_at(src_address + size as u64)?;
+
+        let src_end_address = src_address + size as u64;
+        let mut moved_regions: Vec<MemoryRegion> = Vec::new();
+        for module in self.modules.iter_mut() {
+            let (src_regions, kept_regions): (Vec<MemoryRegion>, Vec<MemoryRegion>) = module.regions.drain(..).partition(|region| (region.start() >= src_address) && (region.end() <= src_end_address));
+            module.regions = kept_regions;
+            moved_regions.extend(src_regions);
+        }
+        self.modules.retain(|module| !module.regions.is_empty());
+        moved_regions.sort_by_key(|region| region.start());
+
+        for region in moved_regions.iter_mut() {
+            for (_, handle) in self.exec_handles.iter_mut() {
+                handle.unmap_memory(region.address, region.len())?;
+            }
+
+            region.address = dst_address + (region.address - src_address);
+            region.perm = perm;
+            region.state = state;
+        }
+        self.map_regions_on_exec_handles(&moved_regions)?;
+
+        self.modules.push(ModuleMemory::new(file_name, moved_regions));
+        self.exec_end_address = self.exec_end_address.max(dst_address + size as u64);
+        self.update_code_write_watch();
+        Ok(())
+    }
+
+    pub fn set_memory_permission(&mut self, address: u64, size: usize, perm: Permission) -> Result<()> {
+        check_page_aligned_range(address, size)?;
+        result_return_unless!(self.is_memory_mapped(address, size), kern_result::ResultInvalidCurrentMemory);
+
+        self.split_memory_at(address)?;
+        self.split_memory_at(address + size as u64)?;
+
+        let end_address = address + size as u64;
+        for module in self.modules.iter_mut() {
+            for region in module.regions.iter_mut().filter(|region| (region.start() >= address) && (region.end() <= end_address)) {
+                region.perm = perm;
+
+                // Read-only memory shared with other processes (see create_shared_memory_region) must be copied before it becomes writable, like write_memory does
+                // Note: shared memory (the kernel object) must keep being the same memory for every process mapping it, thus it's never copied
+                if perm.contains(Permission::WRITE) && region.is_shared() && (region.state != KMemoryState::Shared()) {
+                    Arc::make_mut(&mut region.data);
+                    for (_, handle) in self.exec_handles.iter_mut() {
+                        handle.unmap_memory(region.address, region.len())?;
+                        handle.map_region(region)?;
+                    }
+                    continue;
+                }
+
+                for (_, handle) in self.exec_handles.iter_mut() {
+                    handle.protect_memory(region.address, region.len(), perm)?;
+                }
+            }
+        }
+
+        self.update_code_write_watch();
+        Ok(())
+    }
+
+    // Drops the translated blocks of the executable memory backing the range, so that modified code (JITs, relocations...) gets translated again
+    // Note: aliases of the same memory (see Context::share_memory) are flushed too, since code is usually written through a writable alias of it
+    // TODO: use uc_ctl_remove_cache once on unicorn 2, for now unicorn 1 has no way to invalidate translated blocks other than remapping their memory (which gets a new RAM block, thus stale blocks are no longer looked up)
+    pub fn flush_code_cache(&mut self, address: u64, size: usize) -> Result<()> {
+        let end_address = address + size as u64;
+        let flushed_datas: Vec<Arc<Vec<u8>>> = self.modules.iter().flat_map(|module| module.regions.iter()).filter(|region| (region.start() < end_address) && (region.end() > address)).map(|region| region.data.clone()).collect();
+
+        let mut flushed_any = false;
+        for region in self.modules.iter().flat_map(|module| module.regions.iter()) {
+            if region.perm.contains(Permission::EXEC) && flushed_datas.iter().any(|data| Arc::ptr_eq(data, &region.data)) {
+                for (_, handle) in self.exec_handles.iter_mut() {
+                    handle.unmap_memory(region.address, region.len())?;
+                    handle.map_region(region)?;
+                }
+                flushed_any = true;
+            }
+        }
+
+        if flushed_any {
+            for stats in self.exec_translation_stats.iter() {
+                stats.record_code_flush();
+            }
+        }
+        Ok(())
+    }
+
+    // Writable memory executed elsewhere (writable aliases of code, RWX memory) or meant to be executed again later (code reprotected as writable) is watched
+    // Note: aliases in other processes (see Context::share_memory) aren't considered
+    fn update_code_write_watch(&self) {
+        if let Some(watch) = self.code_write_watch.as_ref() {
+            let regions: Vec<&MemoryRegion> = self.modules.iter().flat_map(|module| module.regions.iter()).collect();
+            let mut ranges: Vec<CodeWriteRange> = Vec::new();
+            for region in regions.iter().filter(|region| region.perm.contains(Permission::WRITE)) {
+                let mut exec_addresses: Vec<u64> = regions.iter().filter(|exec_region| exec_region.perm.contains(Permission::EXEC) && Arc::ptr_eq(&exec_region.data, &region.data)).map(|exec_region| exec_region.address).collect();
+                if ((region.state == KMemoryState::Code()) || (region.state == KMemoryState::AliasCode())) && !exec_addresses.contains(&region.address) {
+                    exec_addresses.push(region.address);
+                }
+
+                if !exec_addresses.is_empty() {
+                    ranges.push(CodeWriteRange {
+                        address: region.address,
+                        size: region.len(),
+                        exec_addresses: exec_addresses
+                    });
+                }
+            }
+
+            *watch.ranges.write() = ranges;
+        }
+    }
+
+    // Retranslates the code written by the guest since the last flush (see CodeWriteWatch)
+    pub fn flush_dirty_code(&mut self) -> Result<()> {
+        if let Some(watch) = self.code_write_watch.clone() {
+            for page in watch.take_dirty_pages() {
+                self.flush_code_cache(page, PAGE_SIZE)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Whether the whole range lies within a single region, thus whether read_memory/write_memory can access it
+    pub fn contains_range(&self, address: u64, size: usize) -> bool {
+        let last_address = match (size as u64).checked_sub(1).and_then(|last_offset| address.checked_add(last_offset)) {
+            Some(last_address) => last_address,
+            None => return false
+        };
+
+        self.modules.iter().any(|module| module.regions.iter().any(|region| region.contains(address) && region.contains(last_address)))
+    }
+
+    pub fn read_memory(&self, address: u64, data: &mut [u8]) -> Result<()> {
+        // Note: the last accessed address below would underflow otherwise
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        for module in self.modules.iter() {
+            for region in module.regions.iter() {
+                if region.contains(address) && region.contains(address + data.len() as u64 - 1) {
+                    let offset = (address - region.start()) as usize;
+                    data.copy_from_slice(&region.data[offset..offset + data.len()]);
+                    return Ok(());
+                }
+            }
+        }
+
+        result::ResultUnicornReadUnmappedMemory::make_err()
+    }
+
+    // Note: regions are mapped from their data (see CpuBackend::map_region), thus this is visible to all execution contexts
+    pub fn write_memory(&mut self, address: u64, data: &[u8]) -> Result<()> {
+        // Note: the last accessed address below would underflow otherwise
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        for module in self.modules.iter_mut() {
+            for region in module.regions.iter_mut() {
+                if region.contains(address) && region.contains(address + data.len() as u64 - 1) {
+                    let offset = (address - region.start()) as usize;
+
+                    // Shared memory must keep being the same memory for every process mapping it, thus it's never copied
+                    if region.state == KMemoryState::Shared() {
+                        unsafe {
+                            ptr::copy_nonoverlapping(data.as_ptr(), (region.data.as_ptr() as *mut u8).add(offset), data.len());
+                        }
+                        return Ok(());
+                    }
+
+                    let prev_data_ptr = region.data.as_ptr();
+                    Arc::make_mut(&mut region.data)[offset..offset + data.len()].copy_from_slice(data);
+
+                    // The region was shared with other processes, thus it got copied and the copy needs to be mapped instead
+                    if region.data.as_ptr() != prev_data_ptr {
+                        for (_, handle) in self.exec_handles.iter_mut() {
+                            handle.unmap_memory(region.address, region.len())?;
+                            handle.map_region(region)?;
+                        }
+                        self.update_code_write_watch();
+                    }
+                    return Ok(());
+                }
+            }
+        }
 
-        ExecutionContext::new(entry_addr, &self.modules, stack, tlr)
+        result::ResultUnicornWriteUnmappedMemory::make_err()
     }
 }
 
@@ -0,0 +1,860 @@
+use unicorn::{Engine, Handle, MemoryFault};
+use unicorn::unicorn_const::{Arch, Permission, Query};
+use core::result::Result as CoreResult;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::result::*;
+use super::{Architecture, ContextHandle, MemoryRegion, ModuleMemory, Register, GENERAL_PURPOSE_REGISTERS, get_arch_register_id, make_engine_builder, map_memory_region, map_memory_regions};
+use super::result;
+
+// CPU backends: the engines guest code can be run on, abstracted so that execution contexts (see ExecutionContext) don't depend on any of them, and for comparison/debugging purposes
+// Unicorn is the default (and the only one guest processes actually run on, see create_guest_backend), the interpreter being a slow but simple reference for the A64 instructions it supports
+// Note: registers are always specified with their AArch64 names (like with ContextHandle), and values are always read/written as 64-bit values
+
+pub trait CpuBackend {
+    fn get_kind(&self) -> BackendKind;
+    fn get_architecture(&self) -> Architecture;
+
+    // Another handle to this same backend, for code accessing it while it runs (hooks, SVC handlers...)
+    // Note: handles are only valid as long as the backend they were obtained from is alive
+    fn get_handle(&self) -> ContextHandle;
+
+    // Maps a copy of the given data with the given permissions (unicorn requires page-aligned addresses and sizes)
+    fn map_memory(&mut self, address: u64, data: &[u8], perm: Permission) -> Result<()>;
+
+    // Maps the region's own memory, thus it stays shared with everything else mapping it (other execution contexts, the process' Context...)
+    fn map_region(&mut self, region: &MemoryRegion) -> Result<()>;
+
+    // Maps either all the regions or none of them, failing with the index of the conflicting region otherwise
+    fn map_regions(&mut self, regions: &[MemoryRegion]) -> CoreResult<(), (usize, ResultCode)> {
+        for (i, region) in regions.iter().enumerate() {
+            if let Err(rc) = self.map_region(region) {
+                for mapped_region in regions[..i].iter() {
+                    let _ = self.unmap_memory(mapped_region.address, mapped_region.len());
+                }
+                return Err((i, rc));
+            }
+        }
+        Ok(())
+    }
+
+    fn unmap_memory(&mut self, address: u64, size: usize) -> Result<()>;
+    fn protect_memory(&mut self, address: u64, size: usize, perm: Permission) -> Result<()>;
+
+    // Returns how many bytes (up to max_size) starting at the given address are mapped (possibly across several contiguous regions) with at least the given permissions
+    fn get_accessible_size(&self, address: u64, max_size: usize, perm: Permission) -> Result<usize>;
+
+    // Host-side memory accesses, thus permissions are ignored
+    fn read_memory(&self, address: u64, data: &mut [u8]) -> Result<()>;
+    fn write_memory(&mut self, address: u64, data: &[u8]) -> Result<()>;
+
+    fn read_register(&self, reg: Register) -> Result<u64>;
+    fn write_register(&mut self, reg: Register, value: u64) -> Result<()>;
+
+    // Executes from the current PC until the end address is reached or the given amount of instructions (unless 0) gets executed
+    fn run(&mut self, end_address: u64, max_instruction_count: usize) -> Result<()>;
+
+    // Executes from the given address (with the Thumb bit set for T32 code on 32-bit guests) until the end address is reached or the execution is stopped
+    fn run_at(&mut self, start_address: u64, end_address: u64) -> Result<()>;
+
+    // Makes the current execution (if any) return once the current instruction (or hook) is done, meant to be called from hooks or other threads
+    fn stop(&self) -> Result<()>;
+
+    // The faulting access of the last failed execution, only known for memory errors (and only if the backend tracks them)
+    fn take_last_memory_fault(&self) -> Option<MemoryFault> {
+        None
+    }
+
+    fn step(&mut self) -> Result<()> {
+        self.run(u64::MAX, 1)
+    }
+
+    fn map_module(&mut self, module: &ModuleMemory) -> Result<()> {
+        for region in module.regions.iter() {
+            self.map_memory(region.address, &region.data, region.perm)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum BackendKind {
+    Unicorn,
+    Interpreter,
+    // Registers and memory only, for handlers called outside guest execution (see emu::harness::svc_env)
+    Mock
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unicorn => write!(f, "unicorn"),
+            Self::Interpreter => write!(f, "interpreter"),
+            Self::Mock => write!(f, "mock")
+        }
+    }
+}
+
+pub fn create_backend(kind: BackendKind, arch: Architecture) -> Result<Box<dyn CpuBackend>> {
+    match kind {
+        BackendKind::Unicorn => Ok(Box::new(UnicornBackend::new(arch)?)),
+        BackendKind::Interpreter => Ok(Box::new(InterpreterBackend::new(arch)?)),
+        BackendKind::Mock => result::ResultBackendUnsupportedOperation::make_err()
+    }
+}
+
+// Register state which is compared between backends
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct RegisterState {
+    pub gprs: [u64; 31],
+    pub sp: u64,
+    pub pc: u64,
+    pub nzcv: u64
+}
+
+impl RegisterState {
+    pub fn read(backend: &dyn CpuBackend) -> Result<Self> {
+        let mut gprs = [0u64; 31];
+        for (gpr, reg) in gprs.iter_mut().zip(GENERAL_PURPOSE_REGISTERS.iter()) {
+            *gpr = backend.read_register(*reg)?;
+        }
+
+        Ok(Self {
+            gprs: gprs,
+            sp: backend.read_register(Register::SP)?,
+            pc: backend.read_register(Register::PC)?,
+            nzcv: backend.read_register(Register::NZCV)?
+        })
+    }
+
+    // Lines like "X0: 0x1 != 0x2", one per differing register
+    pub fn find_differences(&self, other: &Self) -> Vec<String> {
+        let mut differences: Vec<String> = Vec::new();
+        for (i, (gpr, other_gpr)) in self.gprs.iter().zip(other.gprs.iter()).enumerate() {
+            if gpr != other_gpr {
+                differences.push(format!("X{}: {:#X} != {:#X}", i, gpr, other_gpr));
+            }
+        }
+        for (name, value, other_value) in [("SP", self.sp, other.sp), ("PC", self.pc, other.pc), ("NZCV", self.nzcv, other.nzcv)].iter() {
+            if value != other_value {
+                differences.push(format!("{}: {:#X} != {:#X}", name, value, other_value));
+            }
+        }
+        differences
+    }
+}
+
+pub struct UnicornBackend {
+    uc: Handle,
+    // Only present in the backend which created the engine, handles (see from_handle) just access it
+    engine: Option<Engine>,
+    arch: Architecture
+}
+
+// Unicorn reads/writes registers with their actual size, thus 32-bit ones can't be read as 64-bit values
+fn is_32bit_register(arch: Architecture, reg: Register) -> bool {
+    let reg_id = reg as i32;
+    match arch {
+        Architecture::Aarch64 => ((Register::W0 as i32 <= reg_id) && (reg_id <= Register::W30 as i32)) || (reg == Register::NZCV) || (reg == Register::CPACR_EL1),
+        Architecture::Aarch32 => true
+    }
+}
+
+impl UnicornBackend {
+    pub fn new(arch: Architecture) -> Result<Self> {
+        Ok(Self::from_engine(make_engine_builder(arch).build()?, arch))
+    }
+
+    pub fn from_engine(engine: Engine, arch: Architecture) -> Self {
+        Self {
+            uc: engine.handle,
+            engine: Some(engine),
+            arch: arch
+        }
+    }
+
+    // For unicorn hooks, which only get the engine's handle
+    pub fn from_handle(uc_h: Handle) -> Result<Self> {
+        let arch = match uc_h.query(Query::ARCH)? == Arch::ARM as usize {
+            true => Architecture::Aarch32,
+            false => Architecture::Aarch64
+        };
+
+        Ok(Self {
+            uc: uc_h,
+            engine: None,
+            arch: arch
+        })
+    }
+}
+
+impl CpuBackend for UnicornBackend {
+    fn get_kind(&self) -> BackendKind {
+        BackendKind::Unicorn
+    }
+
+    fn get_architecture(&self) -> Architecture {
+        self.arch
+    }
+
+    fn get_handle(&self) -> ContextHandle {
+        ContextHandle::new(Box::new(Self {
+            uc: self.uc,
+            engine: None,
+            arch: self.arch
+        }))
+    }
+
+    fn map_memory(&mut self, address: u64, data: &[u8], perm: Permission) -> Result<()> {
+        match self.engine.as_mut() {
+            Some(engine) => {
+                engine.mem_map(address, data.len(), perm)?;
+                engine.mem_write(address, data)?;
+                Ok(())
+            },
+            // Note: handles can't own the copied memory
+            None => result::ResultBackendUnsupportedOperation::make_err()
+        }
+    }
+
+    fn map_region(&mut self, region: &MemoryRegion) -> Result<()> {
+        map_memory_region(&mut self.uc, region)
+    }
+
+    fn map_regions(&mut self, regions: &[MemoryRegion]) -> CoreResult<(), (usize, ResultCode)> {
+        map_memory_regions(&mut self.uc, regions)
+    }
+
+    fn unmap_memory(&mut self, address: u64, size: usize) -> Result<()> {
+        self.uc.mem_unmap(address, size).map_err(ResultCode::from)
+    }
+
+    fn protect_memory(&mut self, address: u64, size: usize, perm: Permission) -> Result<()> {
+        self.uc.mem_protect(address, size, perm).map_err(ResultCode::from)
+    }
+
+    fn get_accessible_size(&self, address: u64, max_size: usize, perm: Permission) -> Result<usize> {
+        let mut regions = self.uc.mem_regions()?;
+        regions.sort_by_key(|region| region.begin);
+
+        // Note: unicorn region ends are inclusive
+        let mut cur_address = address;
+        let mut accessible_size: usize = 0;
+        for region in regions.iter() {
+            if accessible_size >= max_size {
+                break;
+            }
+
+            if (region.begin <= cur_address) && (cur_address <= region.end) {
+                if !region.perms.contains(perm) {
+                    break;
+                }
+
+                accessible_size = accessible_size.saturating_add((region.end - cur_address) as usize + 1);
+                cur_address = match region.end.checked_add(1) {
+                    Some(next_address) => next_address,
+                    None => break
+                };
+            }
+        }
+
+        Ok(accessible_size.min(max_size))
+    }
+
+    fn read_memory(&self, address: u64, data: &mut [u8]) -> Result<()> {
+        self.uc.mem_read(address, data).map_err(ResultCode::from)
+    }
+
+    fn write_memory(&mut self, address: u64, data: &[u8]) -> Result<()> {
+        self.uc.mem_write(address, data).map_err(ResultCode::from)
+    }
+
+    fn read_register(&self, reg: Register) -> Result<u64> {
+        let reg_id = get_arch_register_id(self.arch, reg)?;
+        match is_32bit_register(self.arch, reg) {
+            true => self.uc.reg_read::<u32>(reg_id).map(|value| value as u64).map_err(ResultCode::from),
+            false => self.uc.reg_read::<u64>(reg_id).map_err(ResultCode::from)
+        }
+    }
+
+    fn write_register(&mut self, reg: Register, value: u64) -> Result<()> {
+        let reg_id = get_arch_register_id(self.arch, reg)?;
+        match is_32bit_register(self.arch, reg) {
+            true => self.uc.reg_write::<u32>(reg_id, value as u32).map_err(ResultCode::from),
+            false => self.uc.reg_write::<u64>(reg_id, value).map_err(ResultCode::from)
+        }
+    }
+
+    fn run(&mut self, end_address: u64, max_instruction_count: usize) -> Result<()> {
+        let pc = self.read_register(Register::PC)?;
+        self.uc.emu_start(pc, end_address, 0, max_instruction_count).map_err(ResultCode::from)
+    }
+
+    fn run_at(&mut self, start_address: u64, end_address: u64) -> Result<()> {
+        self.uc.emu_start(start_address, end_address, 0, 0).map_err(ResultCode::from)
+    }
+
+    fn stop(&self) -> Result<()> {
+        let mut uc_h = self.uc;
+        uc_h.emu_stop().map_err(ResultCode::from)
+    }
+
+    fn take_last_memory_fault(&self) -> Option<MemoryFault> {
+        self.engine.as_ref().and_then(|engine| engine.take_last_memory_fault())
+    }
+}
+
+// Handle to a backend which can't otherwise share its state (see CpuBackend::get_handle), thus only valid while the backend is alive and not moved
+struct BackendRef<B: CpuBackend + 'static>(*mut B);
+
+impl<B: CpuBackend + 'static> BackendRef<B> {
+    #[inline]
+    fn get(&self) -> &B {
+        unsafe { &*self.0 }
+    }
+
+    #[inline]
+    #[allow(clippy::mut_from_ref)]
+    fn get_mut(&self) -> &mut B {
+        unsafe { &mut *self.0 }
+    }
+}
+
+impl<B: CpuBackend + 'static> CpuBackend for BackendRef<B> {
+    fn get_kind(&self) -> BackendKind {
+        self.get().get_kind()
+    }
+
+    fn get_architecture(&self) -> Architecture {
+        self.get().get_architecture()
+    }
+
+    fn get_handle(&self) -> ContextHandle {
+        ContextHandle::new(Box::new(Self(self.0)))
+    }
+
+    fn map_memory(&mut self, address: u64, data: &[u8], perm: Permission) -> Result<()> {
+        self.get_mut().map_memory(address, data, perm)
+    }
+
+    fn map_region(&mut self, region: &MemoryRegion) -> Result<()> {
+        self.get_mut().map_region(region)
+    }
+
+    fn map_regions(&mut self, regions: &[MemoryRegion]) -> CoreResult<(), (usize, ResultCode)> {
+        self.get_mut().map_regions(regions)
+    }
+
+    fn unmap_memory(&mut self, address: u64, size: usize) -> Result<()> {
+        self.get_mut().unmap_memory(address, size)
+    }
+
+    fn protect_memory(&mut self, address: u64, size: usize, perm: Permission) -> Result<()> {
+        self.get_mut().protect_memory(address, size, perm)
+    }
+
+    fn get_accessible_size(&self, address: u64, max_size: usize, perm: Permission) -> Result<usize> {
+        self.get().get_accessible_size(address, max_size, perm)
+    }
+
+    fn read_memory(&self, address: u64, data: &mut [u8]) -> Result<()> {
+        self.get().read_memory(address, data)
+    }
+
+    fn write_memory(&mut self, address: u64, data: &[u8]) -> Result<()> {
+        self.get_mut().write_memory(address, data)
+    }
+
+    fn read_register(&self, reg: Register) -> Result<u64> {
+        self.get().read_register(reg)
+    }
+
+    fn write_register(&mut self, reg: Register, value: u64) -> Result<()> {
+        self.get_mut().write_register(reg, value)
+    }
+
+    fn run(&mut self, end_address: u64, max_instruction_count: usize) -> Result<()> {
+        self.get_mut().run(end_address, max_instruction_count)
+    }
+
+    fn run_at(&mut self, start_address: u64, end_address: u64) -> Result<()> {
+        self.get_mut().run_at(start_address, end_address)
+    }
+
+    fn stop(&self) -> Result<()> {
+        self.get().stop()
+    }
+
+    fn take_last_memory_fault(&self) -> Option<MemoryFault> {
+        self.get().take_last_memory_fault()
+    }
+}
+
+struct InterpreterRegion {
+    address: u64,
+    perm: Permission,
+    data: Vec<u8>
+}
+
+impl InterpreterRegion {
+    fn end(&self) -> u64 {
+        self.address + self.data.len() as u64
+    }
+
+    fn contains(&self, address: u64, size: usize) -> bool {
+        (self.address <= address) && (address.saturating_add(size as u64) <= self.end())
+    }
+
+    fn overlaps(&self, address: u64, size: usize) -> bool {
+        (self.address < address.saturating_add(size as u64)) && (address < self.end())
+    }
+}
+
+// Note: only a small A64 subset is supported (moves, add/sub, logical ops, branches and plain loads/stores), anything else fails with ResultBackendUnsupportedInstruction
+// Memory is always copied into the interpreter, thus it can't map regions in place (and can't run guest execution contexts)
+pub struct InterpreterBackend {
+    regions: Vec<InterpreterRegion>,
+    gprs: [u64; 31],
+    sp: u64,
+    pc: u64,
+    nzcv: u64,
+    stop_requested: AtomicBool
+}
+
+const NOP_INSN: u32 = 0xD503201F;
+const ZERO_REGISTER: u32 = 31;
+
+const NZCV_N: u64 = 1 << 31;
+const NZCV_Z: u64 = 1 << 30;
+const NZCV_C: u64 = 1 << 29;
+const NZCV_V: u64 = 1 << 28;
+
+#[inline]
+const fn get_value_mask(is_64bit: bool) -> u64 {
+    match is_64bit {
+        true => u64::MAX,
+        false => u32::MAX as u64
+    }
+}
+
+#[inline]
+const fn sign_extend(value: u32, bits: u32) -> i64 {
+    ((value as i64) << (64 - bits)) >> (64 - bits)
+}
+
+fn make_nzcv(result: u64, is_64bit: bool, carry: bool, overflow: bool) -> u64 {
+    let sign_bit: u64 = match is_64bit {
+        true => 1 << 63,
+        false => 1 << 31
+    };
+
+    let mut nzcv: u64 = 0;
+    if (result & sign_bit) != 0 {
+        nzcv |= NZCV_N;
+    }
+    if result == 0 {
+        nzcv |= NZCV_Z;
+    }
+    if carry {
+        nzcv |= NZCV_C;
+    }
+    if overflow {
+        nzcv |= NZCV_V;
+    }
+    nzcv
+}
+
+// Returns the (masked) result and the NZCV flags it would set
+fn add_with_carry(x: u64, y: u64, carry_in: bool, is_64bit: bool) -> (u64, u64) {
+    let mask = get_value_mask(is_64bit);
+    let (x, y) = (x & mask, y & mask);
+    let unsigned_sum = x as u128 + y as u128 + carry_in as u128;
+    let result = (unsigned_sum as u64) & mask;
+
+    let sign_bit = (mask >> 1) + 1;
+    let carry = unsigned_sum > mask as u128;
+    let overflow = ((x ^ result) & (y ^ result) & sign_bit) != 0;
+    (result, make_nzcv(result, is_64bit, carry, overflow))
+}
+
+// Shift types as encoded in shifted register instructions (LSL, LSR, ASR, ROR)
+fn shift_value(value: u64, shift_type: u32, amount: u32, is_64bit: bool) -> u64 {
+    let value = value & get_value_mask(is_64bit);
+    let shifted_value = match (shift_type, is_64bit) {
+        (0, _) => value << amount,
+        (1, _) => value >> amount,
+        (2, true) => ((value as i64) >> amount) as u64,
+        (2, false) => ((value as u32 as i32) >> amount) as u32 as u64,
+        (_, true) => value.rotate_right(amount),
+        (_, false) => (value as u32).rotate_right(amount) as u64
+    };
+    shifted_value & get_value_mask(is_64bit)
+}
+
+fn condition_holds(nzcv: u64, cond: u32) -> bool {
+    let (n, z, c, v) = ((nzcv & NZCV_N) != 0, (nzcv & NZCV_Z) != 0, (nzcv & NZCV_C) != 0, (nzcv & NZCV_V) != 0);
+    let holds = match cond >> 1 {
+        0 => z,
+        1 => c,
+        2 => n,
+        3 => v,
+        4 => c && !z,
+        5 => n == v,
+        6 => (n == v) && !z,
+        _ => true
+    };
+
+    // Odd conditions are the negated ones, except for 0b1111 (which is "always" too)
+    match ((cond & 1) != 0) && (cond != 0b1111) {
+        true => !holds,
+        false => holds
+    }
+}
+
+impl InterpreterBackend {
+    pub fn new(arch: Architecture) -> Result<Self> {
+        result_return_unless!(arch == Architecture::Aarch64, result::ResultBackendUnsupportedArchitecture);
+
+        Ok(Self {
+            regions: Vec::new(),
+            gprs: [0; 31],
+            sp: 0,
+            pc: 0,
+            nzcv: 0,
+            stop_requested: AtomicBool::new(false)
+        })
+    }
+
+    fn find_region(&self, address: u64, size: usize) -> Result<&InterpreterRegion> {
+        match self.regions.iter().find(|region| region.contains(address, size)) {
+            Some(region) => Ok(region),
+            None => result::ResultBackendInvalidMemoryAccess::make_err()
+        }
+    }
+
+    fn find_region_mut(&mut self, address: u64, size: usize) -> Result<&mut InterpreterRegion> {
+        match self.regions.iter_mut().find(|region| region.contains(address, size)) {
+            Some(region) => Ok(region),
+            None => result::ResultBackendInvalidMemoryAccess::make_err()
+        }
+    }
+
+    // Regions can't be split, thus ranges partially covering any of them are invalid
+    fn check_whole_regions(&self, address: u64, size: usize) -> Result<()> {
+        let is_partial = self.regions.iter().any(|region| region.overlaps(address, size) && ((region.address < address) || (address.saturating_add(size as u64) < region.end())));
+        result_return_if!(is_partial, result::ResultBackendInvalidMemoryAccess);
+        Ok(())
+    }
+
+    // Guest accesses (unlike host ones) need the region to have the corresponding permissions
+    fn guest_read(&self, address: u64, size: usize, perm: Permission) -> Result<u64> {
+        let region = self.find_region(address, size)?;
+        result_return_unless!(region.perm.contains(perm), result::ResultBackendInvalidMemoryAccess);
+
+        let offset = (address - region.address) as usize;
+        let mut value_data = [0u8; 8];
+        value_data[..size].copy_from_slice(&region.data[offset..offset + size]);
+        Ok(u64::from_le_bytes(value_data))
+    }
+
+    fn guest_write(&mut self, address: u64, size: usize, value: u64) -> Result<()> {
+        let region = self.find_region_mut(address, size)?;
+        result_return_unless!(region.perm.contains(Permission::WRITE), result::ResultBackendInvalidMemoryAccess);
+
+        let offset = (address - region.address) as usize;
+        region.data[offset..offset + size].copy_from_slice(&value.to_le_bytes()[..size]);
+        Ok(())
+    }
+
+    // Register 31 is either the zero register or SP depending on the instruction
+    #[inline]
+    fn get_gpr(&self, reg_idx: u32) -> u64 {
+        match reg_idx {
+            ZERO_REGISTER => 0,
+            _ => self.gprs[reg_idx as usize]
+        }
+    }
+
+    #[inline]
+    fn get_gpr_or_sp(&self, reg_idx: u32) -> u64 {
+        match reg_idx {
+            ZERO_REGISTER => self.sp,
+            _ => self.gprs[reg_idx as usize]
+        }
+    }
+
+    // 32-bit (W) writes clear the upper half
+    #[inline]
+    fn set_gpr(&mut self, reg_idx: u32, value: u64, is_64bit: bool) {
+        if reg_idx != ZERO_REGISTER {
+            self.gprs[reg_idx as usize] = value & get_value_mask(is_64bit);
+        }
+    }
+
+    #[inline]
+    fn set_gpr_or_sp(&mut self, reg_idx: u32, value: u64, is_64bit: bool) {
+        match reg_idx {
+            ZERO_REGISTER => self.sp = value & get_value_mask(is_64bit),
+            _ => self.set_gpr(reg_idx, value, is_64bit)
+        };
+    }
+
+    fn execute_instruction(&mut self) -> Result<()> {
+        let pc = self.pc;
+        result_return_unless!((pc % 4) == 0, result::ResultBackendInvalidMemoryAccess);
+        let insn = self.guest_read(pc, 4, Permission::EXEC)? as u32;
+
+        let is_64bit = (insn >> 31) != 0;
+        let rd = insn & 0x1F;
+        let rn = (insn >> 5) & 0x1F;
+        let rm = (insn >> 16) & 0x1F;
+        let mut next_pc = pc.wrapping_add(4);
+
+        if insn == NOP_INSN {
+        }
+        // MOVN/MOVZ/MOVK
+        else if (insn & 0x1F800000) == 0x12800000 {
+            let opc = (insn >> 29) & 0b11;
+            let hw = (insn >> 21) & 0b11;
+            result_return_if!(!is_64bit && (hw > 1), result::ResultBackendUnsupportedInstruction);
+
+            let shift = hw * 16;
+            let imm = (((insn >> 5) & 0xFFFF) as u64) << shift;
+            let value = match opc {
+                0b00 => !imm,
+                0b10 => imm,
+                0b11 => (self.get_gpr(rd) & !(0xFFFF << shift)) | imm,
+                _ => return result::ResultBackendUnsupportedInstruction::make_err()
+            };
+            self.set_gpr(rd, value, is_64bit);
+        }
+        // ADD/ADDS/SUB/SUBS (immediate)
+        else if (insn & 0x1F800000) == 0x11000000 {
+            let is_sub = ((insn >> 30) & 1) != 0;
+            let set_flags = ((insn >> 29) & 1) != 0;
+            let imm = match (insn >> 22) & 1 {
+                0 => ((insn >> 10) & 0xFFF) as u64,
+                _ => (((insn >> 10) & 0xFFF) as u64) << 12
+            };
+
+            let operand = match is_sub {
+                true => !imm,
+                false => imm
+            };
+            let (value, nzcv) = add_with_carry(self.get_gpr_or_sp(rn), operand, is_sub, is_64bit);
+            match set_flags {
+                true => {
+                    self.nzcv = nzcv;
+                    self.set_gpr(rd, value, is_64bit);
+                },
+                false => self.set_gpr_or_sp(rd, value, is_64bit)
+            };
+        }
+        // ADD/ADDS/SUB/SUBS (shifted register)
+        else if (insn & 0x1F200000) == 0x0B000000 {
+            let is_sub = ((insn >> 30) & 1) != 0;
+            let set_flags = ((insn >> 29) & 1) != 0;
+            let shift_type = (insn >> 22) & 0b11;
+            let shift_amount = (insn >> 10) & 0x3F;
+            result_return_if!((shift_type == 0b11) || (!is_64bit && (shift_amount >= 32)), result::ResultBackendUnsupportedInstruction);
+
+            let shifted_value = shift_value(self.get_gpr(rm), shift_type, shift_amount, is_64bit);
+            let operand = match is_sub {
+                true => !shifted_value,
+                false => shifted_value
+            };
+            let (value, nzcv) = add_with_carry(self.get_gpr(rn), operand, is_sub, is_64bit);
+            if set_flags {
+                self.nzcv = nzcv;
+            }
+            self.set_gpr(rd, value, is_64bit);
+        }
+        // AND/BIC/ORR/ORN/EOR/EON/ANDS/BICS (shifted register)
+        else if (insn & 0x1F000000) == 0x0A000000 {
+            let opc = (insn >> 29) & 0b11;
+            let is_negated = ((insn >> 21) & 1) != 0;
+            let shift_type = (insn >> 22) & 0b11;
+            let shift_amount = (insn >> 10) & 0x3F;
+            result_return_if!(!is_64bit && (shift_amount >= 32), result::ResultBackendUnsupportedInstruction);
+
+            let mut operand = shift_value(self.get_gpr(rm), shift_type, shift_amount, is_64bit);
+            if is_negated {
+                operand = !operand & get_value_mask(is_64bit);
+            }
+            let value = match opc {
+                0b01 => self.get_gpr(rn) | operand,
+                0b10 => self.get_gpr(rn) ^ operand,
+                _ => self.get_gpr(rn) & operand
+            } & get_value_mask(is_64bit);
+            if opc == 0b11 {
+                self.nzcv = make_nzcv(value, is_64bit, false, false);
+            }
+            self.set_gpr(rd, value, is_64bit);
+        }
+        // B/BL
+        else if (insn & 0x7C000000) == 0x14000000 {
+            if is_64bit {
+                self.gprs[30] = pc.wrapping_add(4);
+            }
+            next_pc = pc.wrapping_add((sign_extend(insn & 0x3FFFFFF, 26) << 2) as u64);
+        }
+        // B.cond
+        else if (insn & 0xFF000010) == 0x54000000 {
+            if condition_holds(self.nzcv, insn & 0xF) {
+                next_pc = pc.wrapping_add((sign_extend((insn >> 5) & 0x7FFFF, 19) << 2) as u64);
+            }
+        }
+        // CBZ/CBNZ
+        else if (insn & 0x7E000000) == 0x34000000 {
+            let is_nonzero_branch = ((insn >> 24) & 1) != 0;
+            let is_zero = (self.get_gpr(rd) & get_value_mask(is_64bit)) == 0;
+            if is_zero != is_nonzero_branch {
+                next_pc = pc.wrapping_add((sign_extend((insn >> 5) & 0x7FFFF, 19) << 2) as u64);
+            }
+        }
+        // RET
+        else if (insn & 0xFFFFFC1F) == 0xD65F0000 {
+            next_pc = self.get_gpr(rn);
+        }
+        // LDR/STR (unsigned immediate, non-SIMD), only plain zero-extending loads
+        else if (insn & 0x3F000000) == 0x39000000 {
+            let size_log2 = insn >> 30;
+            let size = 1usize << size_log2;
+            let address = self.get_gpr_or_sp(rn).wrapping_add((((insn >> 10) & 0xFFF) as u64) << size_log2);
+            match (insn >> 22) & 0b11 {
+                0b00 => self.guest_write(address, size, self.get_gpr(rd))?,
+                0b01 => {
+                    let value = self.guest_read(address, size, Permission::READ)?;
+                    self.set_gpr(rd, value, true);
+                },
+                _ => return result::ResultBackendUnsupportedInstruction::make_err()
+            };
+        }
+        else {
+            return result::ResultBackendUnsupportedInstruction::make_err();
+        }
+
+        self.pc = next_pc;
+        Ok(())
+    }
+}
+
+impl CpuBackend for InterpreterBackend {
+    fn get_kind(&self) -> BackendKind {
+        BackendKind::Interpreter
+    }
+
+    fn get_architecture(&self) -> Architecture {
+        Architecture::Aarch64
+    }
+
+    fn get_handle(&self) -> ContextHandle {
+        ContextHandle::new(Box::new(BackendRef(self as *const Self as *mut Self)))
+    }
+
+    fn map_memory(&mut self, address: u64, data: &[u8], perm: Permission) -> Result<()> {
+        let overlaps = self.regions.iter().any(|region| region.overlaps(address, data.len()));
+        result_return_if!(data.is_empty() || overlaps, result::ResultBackendInvalidMemoryAccess);
+
+        self.regions.push(InterpreterRegion {
+            address: address,
+            perm: perm,
+            data: data.to_vec()
+        });
+        Ok(())
+    }
+
+    fn map_region(&mut self, _region: &MemoryRegion) -> Result<()> {
+        result::ResultBackendUnsupportedOperation::make_err()
+    }
+
+    fn unmap_memory(&mut self, address: u64, size: usize) -> Result<()> {
+        self.check_whole_regions(address, size)?;
+        self.regions.retain(|region| !region.overlaps(address, size));
+        Ok(())
+    }
+
+    fn protect_memory(&mut self, address: u64, size: usize, perm: Permission) -> Result<()> {
+        self.check_whole_regions(address, size)?;
+        for region in self.regions.iter_mut().filter(|region| region.overlaps(address, size)) {
+            region.perm = perm;
+        }
+        Ok(())
+    }
+
+    fn get_accessible_size(&self, address: u64, max_size: usize, perm: Permission) -> Result<usize> {
+        let mut cur_address = address;
+        let mut accessible_size: usize = 0;
+        while accessible_size < max_size {
+            match self.regions.iter().find(|region| region.contains(cur_address, 1) && region.perm.contains(perm)) {
+                Some(region) => {
+                    accessible_size += (region.end() - cur_address) as usize;
+                    cur_address = region.end();
+                },
+                None => break
+            };
+        }
+
+        Ok(accessible_size.min(max_size))
+    }
+
+    fn read_memory(&self, address: u64, data: &mut [u8]) -> Result<()> {
+        let region = self.find_region(address, data.len())?;
+        let offset = (address - region.address) as usize;
+        data.copy_from_slice(&region.data[offset..offset + data.len()]);
+        Ok(())
+    }
+
+    fn write_memory(&mut self, address: u64, data: &[u8]) -> Result<()> {
+        let region = self.find_region_mut(address, data.len())?;
+        let offset = (address - region.address) as usize;
+        region.data[offset..offset + data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    fn read_register(&self, reg: Register) -> Result<u64> {
+        match reg {
+            Register::SP => Ok(self.sp),
+            Register::PC => Ok(self.pc),
+            Register::NZCV => Ok(self.nzcv),
+            _ => match GENERAL_PURPOSE_REGISTERS.iter().position(|gpr| *gpr == reg) {
+                Some(gpr_idx) => Ok(self.gprs[gpr_idx]),
+                None => result::ResultUnsupportedRegister::make_err()
+            }
+        }
+    }
+
+    fn write_register(&mut self, reg: Register, value: u64) -> Result<()> {
+        match reg {
+            Register::SP => self.sp = value,
+            Register::PC => self.pc = value,
+            Register::NZCV => self.nzcv = value & (NZCV_N | NZCV_Z | NZCV_C | NZCV_V),
+            _ => match GENERAL_PURPOSE_REGISTERS.iter().position(|gpr| *gpr == reg) {
+                Some(gpr_idx) => self.gprs[gpr_idx] = value,
+                None => return result::ResultUnsupportedRegister::make_err()
+            }
+        };
+        Ok(())
+    }
+
+    fn run(&mut self, end_address: u64, max_instruction_count: usize) -> Result<()> {
+        self.stop_requested.store(false, Ordering::SeqCst);
+
+        let mut executed_count: usize = 0;
+        while (self.pc != end_address) && ((max_instruction_count == 0) || (executed_count < max_instruction_count)) && !self.stop_requested.load(Ordering::SeqCst) {
+            self.execute_instruction()?;
+            executed_count += 1;
+        }
+        Ok(())
+    }
+
+    fn run_at(&mut self, start_address: u64, end_address: u64) -> Result<()> {
+        self.pc = start_address;
+        self.run(end_address, 0)
+    }
+
+    fn stop(&self) -> Result<()> {
+        self.stop_requested.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
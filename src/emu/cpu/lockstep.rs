@@ -0,0 +1,137 @@
+use std::fmt;
+use crate::emu::disasm::{self, DisassembledInstruction, InstructionSet};
+use crate::result::*;
+use super::Architecture;
+use super::backend::{BackendKind, CpuBackend, RegisterState};
+use super::result;
+
+// Differential (lockstep) execution: two backends run the same code one instruction at a time, halting at the first instruction after which their state differs
+// This is meant to isolate engine bugs (mostly unicorn ones) which otherwise show up as mysterious guest misbehavior way after the actual wrong instruction
+// Note: comparing all memory after every instruction would be way too slow, thus only the given ranges are compared (like the stack and TLR)
+
+// Differing memory is only reported up to this amount of bytes
+const MAX_REPORTED_MEMORY_DIFFERENCES: usize = 8;
+
+#[derive(Clone, Debug)]
+pub struct Divergence {
+    pub instruction_index: usize,
+    pub pc: u64,
+    pub instruction: Option<DisassembledInstruction>,
+    pub differences: Vec<String>
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Divergence at instruction {} (PC {:#X}", self.instruction_index, self.pc)?;
+        if let Some(insn) = self.instruction.as_ref() {
+            write!(f, ", {}  {}", insn.format_data(), insn.text)?;
+        }
+        write!(f, "): {}", self.differences.join(", "))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum LockstepOutcome {
+    // The end address or the instruction limit was reached with both backends in the same state
+    Finished {
+        instruction_count: usize
+    },
+    // Some backend couldn't execute the current instruction (like instructions the interpreter doesn't support, or SVCs which aren't handled here), which isn't a divergence itself
+    Stopped {
+        instruction_count: usize,
+        pc: u64,
+        kind: BackendKind,
+        rc: ResultCode
+    },
+    Diverged(Divergence)
+}
+
+fn get_instruction_set(arch: Architecture) -> InstructionSet {
+    match arch {
+        Architecture::Aarch64 => InstructionSet::A64,
+        Architecture::Aarch32 => InstructionSet::A32
+    }
+}
+
+fn find_memory_differences(backend_a: &dyn CpuBackend, backend_b: &dyn CpuBackend, compared_ranges: &[(u64, usize)]) -> Result<Vec<String>> {
+    let mut differences: Vec<String> = Vec::new();
+    for (address, size) in compared_ranges.iter() {
+        let mut data_a: Vec<u8> = vec![0; *size];
+        let mut data_b: Vec<u8> = vec![0; *size];
+        backend_a.read_memory(*address, &mut data_a)?;
+        backend_b.read_memory(*address, &mut data_b)?;
+
+        for (offset, (byte_a, byte_b)) in data_a.iter().zip(data_b.iter()).enumerate() {
+            if byte_a != byte_b {
+                if differences.len() >= MAX_REPORTED_MEMORY_DIFFERENCES {
+                    differences.push(String::from("..."));
+                    return Ok(differences);
+                }
+                differences.push(format!("memory at {:#X}: {:#04X} != {:#04X}", address + offset as u64, byte_a, byte_b));
+            }
+        }
+    }
+
+    Ok(differences)
+}
+
+fn disassemble_at(cpu_backend: &dyn CpuBackend, isa: InstructionSet, address: u64) -> Option<DisassembledInstruction> {
+    let mut insn_data = [0u8; 4];
+    match cpu_backend.read_memory(address, &mut insn_data) {
+        Ok(()) => disasm::disassemble_instruction(isa, address, &insn_data),
+        Err(_) => None
+    }
+}
+
+// Both backends are expected to start with the same state (they're compared right away)
+pub fn run_lockstep(backend_a: &mut dyn CpuBackend, backend_b: &mut dyn CpuBackend, end_address: u64, max_instruction_count: usize, compared_ranges: &[(u64, usize)]) -> Result<LockstepOutcome> {
+    result_return_unless!(backend_a.get_architecture() == backend_b.get_architecture(), result::ResultBackendUnsupportedArchitecture);
+    let isa = get_instruction_set(backend_a.get_architecture());
+    let (kind_a, kind_b) = (backend_a.get_kind(), backend_b.get_kind());
+
+    let mut instruction_index: usize = 0;
+    let mut pc = RegisterState::read(backend_a)?.pc;
+    loop {
+        let state_a = RegisterState::read(backend_a)?;
+        let state_b = RegisterState::read(backend_b)?;
+        let mut differences: Vec<String> = state_a.find_differences(&state_b).into_iter().map(|difference| format!("{} ({} != {})", difference, kind_a, kind_b)).collect();
+        differences.extend(find_memory_differences(backend_a, backend_b, compared_ranges)?);
+        if !differences.is_empty() {
+            // Note: the reported instruction is the last executed one, after which the state differs
+            return Ok(LockstepOutcome::Diverged(Divergence {
+                instruction_index: instruction_index.saturating_sub(1),
+                pc: pc,
+                instruction: disassemble_at(backend_a, isa, pc),
+                differences: differences
+            }));
+        }
+
+        pc = state_a.pc;
+        if (pc == end_address) || ((max_instruction_count > 0) && (instruction_index >= max_instruction_count)) {
+            return Ok(LockstepOutcome::Finished {
+                instruction_count: instruction_index
+            });
+        }
+
+        match (backend_a.step(), backend_b.step()) {
+            (Ok(()), Ok(())) => {},
+            // The interpreter not supporting something says nothing about the other backend
+            (_, Err(rc)) if result::ResultBackendUnsupportedInstruction::matches(rc) => return Ok(LockstepOutcome::Stopped { instruction_count: instruction_index, pc: pc, kind: kind_b, rc: rc }),
+            (Err(rc), _) if result::ResultBackendUnsupportedInstruction::matches(rc) => return Ok(LockstepOutcome::Stopped { instruction_count: instruction_index, pc: pc, kind: kind_a, rc: rc }),
+            (Err(rc), Err(_)) => return Ok(LockstepOutcome::Stopped { instruction_count: instruction_index, pc: pc, kind: kind_a, rc: rc }),
+            (rc_a, rc_b) => {
+                let describe_step = |kind: BackendKind, r: Result<()>| match r {
+                    Ok(()) => format!("{} succeeded", kind),
+                    Err(rc) => format!("{} failed with {1} ({1:?})", kind, rc)
+                };
+                return Ok(LockstepOutcome::Diverged(Divergence {
+                    instruction_index: instruction_index,
+                    pc: pc,
+                    instruction: disassemble_at(backend_a, isa, pc),
+                    differences: vec![describe_step(kind_a, rc_a), describe_step(kind_b, rc_b)]
+                }));
+            }
+        };
+        instruction_index += 1;
+    }
+}
@@ -1,5 +1,6 @@
-use unicorn::unicorn_const::uc_error;
-use core::result::Result as CoreResult;
+use unicorn::MemoryFault;
+use unicorn::unicorn_const::{uc_error, MemType};
+use core::fmt;
 use crate::result::*;
 
 pub const RESULT_MODULE: u32 = 505;
@@ -8,6 +9,12 @@ pub const UNICORN_ERROR_BASE: u32 = 1000;
 
 result_define_group!(RESULT_MODULE => {
     InvalidExecutionAddress: 1,
+    UnsupportedRegister: 2,
+    InvalidCoreDump: 3,
+    BackendUnsupportedArchitecture: 4,
+    BackendUnsupportedInstruction: 5,
+    BackendInvalidMemoryAccess: 6,
+    BackendUnsupportedOperation: 7,
 
     UnicornOutOfMemory: UNICORN_ERROR_BASE + 1,
     UnicornUnsupportedArch: UNICORN_ERROR_BASE + 2,
@@ -32,29 +39,97 @@ result_define_group!(RESULT_MODULE => {
     UnicornCpuException: UNICORN_ERROR_BASE + 21
 });
 
-pub fn convert_unicorn_error<T>(r: CoreResult<T, uc_error>) -> Result<T> {
-    r.map_err(|err| match err {
-        uc_error::NOMEM => ResultUnicornOutOfMemory::make(),
-        uc_error::ARCH => ResultUnicornUnsupportedArch::make(),
-        uc_error::HANDLE => ResultUnicornInvalidHandle::make(),
-        uc_error::MODE => ResultUnicornInvalidMode::make(),
-        uc_error::VERSION => ResultUnicornUnsupportedVersion::make(),
-        uc_error::READ_UNMAPPED => ResultUnicornReadUnmappedMemory::make(),
-        uc_error::WRITE_UNMAPPED => ResultUnicornWriteUnmappedMemory::make(),
-        uc_error::FETCH_UNMAPPED => ResultUnicornFetchUnmappedMemory::make(),
-        uc_error::HOOK => ResultUnicornInvalidHookType::make(),
-        uc_error::INSN_INVALID => ResultUnicornInvalidInstruction::make(),
-        uc_error::MAP => ResultUnicornInvalidMemoryMapping::make(),
-        uc_error::WRITE_PROT => ResultUnicornWriteProtectedMemory::make(),
-        uc_error::READ_PROT => ResultUnicornReadProtectedMemory::make(),
-        uc_error::FETCH_PROT => ResultUnicornFetchProtectedMemory::make(),
-        uc_error::ARG => ResultUnicornInvalidArgument::make(),
-        uc_error::READ_UNALIGNED => ResultUnicornReadUnaligned::make(),
-        uc_error::WRITE_UNALIGNED => ResultUnicornWriteUnaligned::make(),
-        uc_error::FETCH_UNALIGNED => ResultUnicornFetchUnaligned::make(),
-        uc_error::HOOK_EXIST => ResultUnicornHookAlreadyExists::make(),
-        uc_error::RESOURCE => ResultUnicornInsufficientResource::make(),
-        uc_error::EXCEPTION => ResultUnicornCpuException::make(),
-        _ => panic!("Invalid uc_error value")
-    })
-}
\ No newline at end of file
+// Unicorn errors map to their own results, thus '?' works on unicorn calls within regular results (see uc_error's Display impl for the actual error messages)
+impl From<uc_error> for ResultCode {
+    fn from(err: uc_error) -> Self {
+        match err {
+            uc_error::NOMEM => ResultUnicornOutOfMemory::make(),
+            uc_error::ARCH => ResultUnicornUnsupportedArch::make(),
+            uc_error::HANDLE => ResultUnicornInvalidHandle::make(),
+            uc_error::MODE => ResultUnicornInvalidMode::make(),
+            uc_error::VERSION => ResultUnicornUnsupportedVersion::make(),
+            uc_error::READ_UNMAPPED => ResultUnicornReadUnmappedMemory::make(),
+            uc_error::WRITE_UNMAPPED => ResultUnicornWriteUnmappedMemory::make(),
+            uc_error::FETCH_UNMAPPED => ResultUnicornFetchUnmappedMemory::make(),
+            uc_error::HOOK => ResultUnicornInvalidHookType::make(),
+            uc_error::INSN_INVALID => ResultUnicornInvalidInstruction::make(),
+            uc_error::MAP => ResultUnicornInvalidMemoryMapping::make(),
+            uc_error::WRITE_PROT => ResultUnicornWriteProtectedMemory::make(),
+            uc_error::READ_PROT => ResultUnicornReadProtectedMemory::make(),
+            uc_error::FETCH_PROT => ResultUnicornFetchProtectedMemory::make(),
+            uc_error::ARG => ResultUnicornInvalidArgument::make(),
+            uc_error::READ_UNALIGNED => ResultUnicornReadUnaligned::make(),
+            uc_error::WRITE_UNALIGNED => ResultUnicornWriteUnaligned::make(),
+            uc_error::FETCH_UNALIGNED => ResultUnicornFetchUnaligned::make(),
+            uc_error::HOOK_EXIST => ResultUnicornHookAlreadyExists::make(),
+            uc_error::RESOURCE => ResultUnicornInsufficientResource::make(),
+            uc_error::EXCEPTION => ResultUnicornCpuException::make(),
+            // Not an error at all
+            uc_error::OK => panic!("Unicorn call succeeded but reported an error")
+        }
+    }
+}
+
+// Host-side errors (see result::Error) keep unicorn's own message as context
+impl From<uc_error> for Error {
+    fn from(err: uc_error) -> Self {
+        Error::with_message(ResultCode::from(err), err.to_string())
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum MemoryAccessType {
+    Read,
+    Write,
+    Fetch
+}
+
+impl MemoryAccessType {
+    pub fn from(mem_type: MemType) -> Self {
+        match mem_type {
+            MemType::WRITE | MemType::WRITE_UNMAPPED | MemType::WRITE_PROT => Self::Write,
+            MemType::FETCH | MemType::FETCH_UNMAPPED | MemType::FETCH_PROT => Self::Fetch,
+            _ => Self::Read
+        }
+    }
+}
+
+// Result codes can't carry anything else, thus failed guest executions keep the details (where, who and what memory access) here
+#[derive(Copy, Clone, Debug)]
+pub struct ExecutionErrorContext {
+    pub rc: ResultCode,
+    pub thread_id: Option<u64>,
+    pub pc: Option<u64>,
+    pub access_type: Option<MemoryAccessType>,
+    pub fault_address: Option<u64>,
+    pub access_size: usize
+}
+
+impl ExecutionErrorContext {
+    pub fn new(rc: ResultCode, thread_id: Option<u64>, pc: Option<u64>, fault: Option<MemoryFault>) -> Self {
+        Self {
+            rc: rc,
+            thread_id: thread_id,
+            pc: pc,
+            access_type: fault.map(|fault| MemoryAccessType::from(fault.mem_type)),
+            fault_address: fault.map(|fault| fault.address),
+            access_size: fault.map(|fault| fault.size).unwrap_or(0)
+        }
+    }
+}
+
+impl fmt::Display for ExecutionErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{0} ({0:?})", self.rc)?;
+        if let Some(thread_id) = self.thread_id {
+            write!(f, " on thread {}", thread_id)?;
+        }
+        if let Some(pc) = self.pc {
+            write!(f, " at PC {:#X}", pc)?;
+        }
+        if let (Some(access_type), Some(fault_address)) = (self.access_type, self.fault_address) {
+            write!(f, " ({:?} of {:#X} bytes at {:#X})", access_type, self.access_size, fault_address)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+use yaxpeax_arch::{Decoder, U8Reader};
+use yaxpeax_arm::armv7::InstDecoder as Armv7InstDecoder;
+use yaxpeax_arm::armv8::a64::InstDecoder as A64InstDecoder;
+use crate::emu::cfg;
+use crate::emu::cpu::{Architecture, ContextHandle, Register};
+use crate::emu::symbols;
+use crate::result::*;
+
+// Guest code disassembly (through yaxpeax-arm), for debugging views (crash logs, core dumps) and traces of small code ranges, annotated with symbols (see emu::symbols)
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum InstructionSet {
+    A64,
+    A32,
+    T32
+}
+
+impl InstructionSet {
+    // 32-bit guests might be running A32 or T32 code, told apart by the Thumb bit
+    pub fn get_current(ctx_h: &ContextHandle) -> Result<Self> {
+        match ctx_h.get_architecture()? {
+            Architecture::Aarch64 => Ok(Self::A64),
+            Architecture::Aarch32 => {
+                const CPSR_THUMB: u32 = 1 << 5;
+                let cpsr: u32 = ctx_h.read_register(Register::NZCV)?;
+                match (cpsr & CPSR_THUMB) != 0 {
+                    true => Ok(Self::T32),
+                    false => Ok(Self::A32)
+                }
+            }
+        }
+    }
+
+    // T32 instructions are either 16 or 32-bit, the first halfword telling which one
+    pub fn get_instruction_size(&self, data: &[u8]) -> Option<usize> {
+        match self {
+            Self::A64 | Self::A32 => Some(4),
+            Self::T32 => {
+                let first_halfword = u16::from_le_bytes([*data.first()?, *data.get(1)?]);
+                match first_halfword >> 11 {
+                    0b11101 | 0b11110 | 0b11111 => Some(4),
+                    _ => Some(2)
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DisassembledInstruction {
+    pub address: u64,
+    pub data: Vec<u8>,
+    pub text: String
+}
+
+impl DisassembledInstruction {
+    // T32 32-bit instructions are shown as their two halfwords, like other tools do
+    pub fn format_data(&self) -> String {
+        match self.data.len() {
+            2 => format!("{:04x}", u16::from_le_bytes([self.data[0], self.data[1]])),
+            4 => format!("{:08x}", u32::from_le_bytes([self.data[0], self.data[1], self.data[2], self.data[3]])),
+            _ => self.data.iter().map(|byte| format!("{:02x}", byte)).collect()
+        }
+    }
+}
+
+// Undecodable instructions are shown as raw data
+fn decode_instruction(isa: InstructionSet, data: &[u8]) -> Option<String> {
+    let mut reader = U8Reader::new(data);
+    match isa {
+        InstructionSet::A64 => A64InstDecoder::default().decode(&mut reader).ok().map(|insn| insn.to_string()),
+        InstructionSet::A32 => Armv7InstDecoder::default().decode(&mut reader).ok().map(|insn| insn.to_string()),
+        InstructionSet::T32 => Armv7InstDecoder::default_thumb().decode(&mut reader).ok().map(|insn| insn.to_string())
+    }
+}
+
+pub fn disassemble_instruction(isa: InstructionSet, address: u64, data: &[u8]) -> Option<DisassembledInstruction> {
+    let insn_size = isa.get_instruction_size(data)?;
+    let insn_data = data.get(..insn_size)?;
+    let text = match decode_instruction(isa, insn_data) {
+        Some(text) => text,
+        None => match insn_size {
+            2 => format!(".inst.n {:#x}", u16::from_le_bytes([insn_data[0], insn_data[1]])),
+            _ => format!(".inst {:#x}", u32::from_le_bytes([insn_data[0], insn_data[1], insn_data[2], insn_data[3]]))
+        }
+    };
+
+    Some(DisassembledInstruction {
+        address: address,
+        data: insn_data.to_vec(),
+        text: text
+    })
+}
+
+pub fn disassemble(isa: InstructionSet, address: u64, data: &[u8]) -> Vec<DisassembledInstruction> {
+    let mut insns: Vec<DisassembledInstruction> = Vec::new();
+    let mut offset: usize = 0;
+    while let Some(insn) = disassemble_instruction(isa, address + offset as u64, &data[offset..]) {
+        offset += insn.data.len();
+        insns.push(insn);
+    }
+    insns
+}
+
+// Instructions before the given address can only be told apart for fixed-size instruction sets, thus only the following ones are disassembled for T32 code
+// Note: unreadable memory (like past the end of a region) just ends the disassembly early
+pub fn disassemble_around(ctx_h: &ContextHandle, isa: InstructionSet, address: u64, before_count: usize, after_count: usize) -> Vec<DisassembledInstruction> {
+    let (start_address, max_size) = match isa {
+        InstructionSet::A64 | InstructionSet::A32 => (address.saturating_sub(before_count as u64 * 4), (before_count + after_count + 1) * 4),
+        InstructionSet::T32 => (address, (after_count + 1) * 4)
+    };
+
+    // Try with less instructions before the address if those aren't readable
+    let mut cur_start_address = start_address;
+    while cur_start_address <= address {
+        let size = max_size - (cur_start_address - start_address) as usize;
+        let mut data: Vec<u8> = vec![0; size];
+        if ctx_h.read_memory(cur_start_address, &mut data).is_ok() {
+            let mut insns = disassemble(isa, cur_start_address, &data);
+            insns.truncate(before_count + after_count + 1);
+            return insns;
+        }
+
+        cur_start_address += 4;
+    }
+
+    Vec::new()
+}
+
+// Lines like "-> 0x7100000010 (module!function+0x10)  d503201f  nop", the current instruction marked with the arrow
+pub fn format_instructions<F: Fn(u64) -> String>(insns: &[DisassembledInstruction], cur_address: Option<u64>, describe_address: F) -> Vec<String> {
+    insns.iter().map(|insn| {
+        let marker = match cur_address == Some(insn.address) {
+            true => "->",
+            false => "  "
+        };
+        format!("{} {:#012X} ({})  {:8}  {}", marker, insn.address, describe_address(insn.address), insn.format_data(), insn.text)
+    }).collect()
+}
+
+// Instruction count before/after the PC shown in crash logs and core dump views
+pub const CONTEXT_INSTRUCTION_COUNT: usize = 6;
+
+pub fn format_around_pc<F: Fn(u64) -> String>(ctx_h: &ContextHandle, pc: u64, describe_address: F) -> Result<Vec<String>> {
+    let isa = InstructionSet::get_current(ctx_h)?;
+    let insns = disassemble_around(ctx_h, isa, pc, CONTEXT_INSTRUCTION_COUNT, CONTEXT_INSTRUCTION_COUNT);
+    Ok(format_instructions(&insns, Some(pc), describe_address))
+}
+
+// Traced ranges are "<start>-<end>" (hex, end not included) entries, see cfg::Config::disasm_trace_ranges
+pub fn parse_trace_range(range: &str) -> Option<(u64, u64)> {
+    let parse_address = |address: &str| u64::from_str_radix(address.trim().trim_start_matches("0x").trim_start_matches("0X"), 16).ok();
+    let (start, end) = range.split_once('-')?;
+    match (parse_address(start)?, parse_address(end)?) {
+        (start, end) if start < end => Some((start, end)),
+        _ => None
+    }
+}
+
+pub fn get_trace_ranges() -> Vec<(u64, u64)> {
+    cfg::get_config().disasm_trace_ranges.iter().filter_map(|range| {
+        let parsed_range = parse_trace_range(range);
+        if parsed_range.is_none() {
+            log_line!("[disasm] Ignoring invalid trace range '{}'", range);
+        }
+        parsed_range
+    }).collect()
+}
+
+// Called (through a code hook over the traced ranges) before each traced instruction gets executed
+pub fn trace_instruction(ctx_h: &ContextHandle, process_id: u64, address: u64, size: usize) {
+    let isa = match InstructionSet::get_current(ctx_h) {
+        Ok(isa) => isa,
+        Err(_) => return
+    };
+
+    let mut data: Vec<u8> = vec![0; size];
+    if ctx_h.read_memory(address, &mut data).is_ok() {
+        if let Some(insn) = disassemble_instruction(isa, address, &data) {
+            log_line!("[disasm] {:#012X} ({})  {:8}  {}", address, symbols::format_address(process_id, address), insn.format_data(), insn.text);
+        }
+    }
+}
@@ -0,0 +1,334 @@
+use std::collections::BTreeMap;
+use std::mem;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use parking_lot::Mutex;
+use crate::ipc::{CommandContext, CommandHeader, CommandSpecialHeader, ObjectInfo, DATA_PADDING, get_msg_buffer};
+use crate::ipc::cmif;
+use crate::ipc::sf;
+use crate::ipc::sf::client::{new_named_port_object, sm};
+use crate::ipc::sf::sm::IUserInterface;
+use crate::kern::{proc::KProcess, thread::KThread, svc};
+use crate::ncm::ProgramId;
+use crate::os::ThreadLocalRegion;
+use crate::proc::EmulatedProcess;
+use crate::result::*;
+use crate::sm::ServiceName;
+
+// IPC fuzzing: malformed CMIF requests are sent to the emulated services from a process of our own, thus they go through the kernel and each service's actual ServerManager like any other request
+// Every case is generated from its own seed (the run seed plus the iteration), thus any case can be generated again
+// Note: requests never contain buffers, statics or move handles, since the kernel can't translate them yet (and moving random handles would only close our own ones)
+
+pub const DEFAULT_SEED: u64 = 0x70656761737573;
+pub const DEFAULT_ITERATION_COUNT: usize = 0x1000;
+
+// Every emulated service except fatal:u, since throwing fatal errors stops the emulation by design
+pub const DEFAULT_SERVICES: &[&str] = &["sm:", "lm", "spl:", "spl:fs", "ncm", "set", "set:sys", "acc:u0", "appletOE", "pl:u", "ns:am2", "ns:su", "ldr:ro"];
+
+// Named ports are connected to directly, everything else through sm
+const NAMED_PORTS: &[&str] = &["sm:"];
+
+const MESSAGE_WORD_COUNT: usize = ThreadLocalRegion::MESSAGE_BUFFER_SIZE / mem::size_of::<u32>();
+const MAX_PARAM_WORD_COUNT: u32 = 0x10;
+// Most services have less commands than this, thus most cases target actual commands
+const COMMON_COMMAND_ID_COUNT: u32 = 0x20;
+
+const FUZZ_TIMEOUT_PER_REQUEST: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Debug)]
+pub struct FuzzOptions {
+    pub seed: u64,
+    pub start_iteration: usize,
+    pub iteration_count: usize,
+    pub services: Vec<String>
+}
+
+impl FuzzOptions {
+    pub fn from_args(args: &[String]) -> Self {
+        let get_arg_value = |name: &str| args.iter().position(|arg| arg == name).and_then(|idx| args.get(idx + 1));
+        let services: Vec<String> = args.iter().enumerate().filter(|(_, arg)| arg.as_str() == "--service").filter_map(|(idx, _)| args.get(idx + 1).cloned()).collect();
+
+        Self {
+            seed: get_arg_value("--seed").map(|seed| seed.parse().unwrap()).unwrap_or(DEFAULT_SEED),
+            start_iteration: get_arg_value("--start-iteration").map(|start_iteration| start_iteration.parse().unwrap()).unwrap_or(0),
+            iteration_count: get_arg_value("--iterations").map(|iteration_count| iteration_count.parse().unwrap()).unwrap_or(DEFAULT_ITERATION_COUNT),
+            services: match services.is_empty() {
+                true => DEFAULT_SERVICES.iter().map(|service| String::from(*service)).collect(),
+                false => services
+            }
+        }
+    }
+}
+
+// xorshift64* (like the emulated secure monitor's), seeded through splitmix64 so that close seeds still give unrelated sequences
+struct FuzzRng {
+    state: u64
+}
+
+impl FuzzRng {
+    fn new(seed: u64) -> Self {
+        let mut state = seed.wrapping_add(0x9E3779B97F4A7C15);
+        state = (state ^ (state >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        state = (state ^ (state >> 27)).wrapping_mul(0x94D049BB133111EB);
+        state ^= state >> 31;
+
+        // The state must never be zero
+        Self {
+            state: state.max(1)
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+
+    #[inline]
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    #[inline]
+    fn below(&mut self, max: u32) -> u32 {
+        (self.next_u64() % max as u64) as u32
+    }
+
+    #[inline]
+    fn chance(&mut self, percent: u32) -> bool {
+        self.below(100) < percent
+    }
+}
+
+// A well-formed CMIF request (as far as the kernel is concerned) with anything but its HIPC layout being randomized
+fn generate_request(rng: &mut FuzzRng) -> [u32; MESSAGE_WORD_COUNT] {
+    let mut words = [0u32; MESSAGE_WORD_COUNT];
+
+    let command_type = match rng.below(100) {
+        0..=69 => cmif::CommandType::Request as u32,
+        70..=79 => cmif::CommandType::RequestWithContext as u32,
+        80..=87 => cmif::CommandType::Control as u32,
+        88..=89 => cmif::CommandType::Close as u32,
+        // Legacy, TIPC and invalid types
+        _ => rng.below(0x20)
+    };
+
+    // Command header, then the special header (if any) with its process ID slot and copy handles
+    let mut data_words_idx: usize = 2;
+    let has_special_header = rng.chance(15);
+    if has_special_header {
+        let send_process_id = rng.chance(50);
+        let copy_handle_count = rng.below(3);
+        words[data_words_idx] = unsafe { mem::transmute(CommandSpecialHeader::new(send_process_id, copy_handle_count, 0)) };
+        data_words_idx += 1;
+        if send_process_id {
+            // The kernel overwrites it anyway
+            data_words_idx += 2;
+        }
+        for _ in 0..copy_handle_count {
+            words[data_words_idx] = match rng.chance(50) {
+                true => svc::CURRENT_PROCESS_PSEUDO_HANDLE,
+                false => svc::CURRENT_THREAD_PSEUDO_HANDLE
+            };
+            data_words_idx += 1;
+        }
+    }
+
+    // The data is 16-byte aligned within the message, its padding is included in the data size
+    let mut data_idx = (data_words_idx + 3) & !3;
+    let data_start_idx = data_idx;
+
+    let is_domain_request = rng.chance(10);
+    if is_domain_request {
+        let object_count = rng.below(0x10);
+        let data_size = match rng.chance(50) {
+            true => (mem::size_of::<cmif::DataHeader>() as u32) + rng.below(MAX_PARAM_WORD_COUNT) * 4,
+            false => rng.below(0x10000)
+        };
+        // Raw values, so that invalid domain command types are generated as well
+        words[data_idx] = rng.below(4) | (object_count << 8) | (data_size << 16);
+        words[data_idx + 1] = rng.below(4);
+        words[data_idx + 3] = rng.next_u32();
+        data_idx += mem::size_of::<cmif::DomainInDataHeader>() / mem::size_of::<u32>();
+    }
+
+    words[data_idx] = match rng.chance(90) {
+        true => cmif::IN_DATA_HEADER_MAGIC,
+        false => rng.next_u32()
+    };
+    words[data_idx + 1] = rng.below(2);
+    words[data_idx + 2] = match rng.chance(80) {
+        true => rng.below(COMMON_COMMAND_ID_COUNT),
+        false => rng.next_u32()
+    };
+    words[data_idx + 3] = rng.next_u32();
+    data_idx += mem::size_of::<cmif::DataHeader>() / mem::size_of::<u32>();
+
+    let param_word_count = rng.below(MAX_PARAM_WORD_COUNT) as usize;
+    for _ in 0..param_word_count {
+        words[data_idx] = match rng.below(3) {
+            0 => 0,
+            1 => rng.below(0x100),
+            _ => rng.next_u32()
+        };
+        data_idx += 1;
+    }
+
+    // Exactly the data written above (plus its padding) most of the time, otherwise any size the message buffer can hold
+    let max_data_word_count = (MESSAGE_WORD_COUNT - data_words_idx) as u32;
+    let exact_data_word_count = (data_idx - data_start_idx) as u32 + (DATA_PADDING / mem::size_of::<u32>() as u32);
+    let data_word_count = match rng.chance(85) {
+        true => exact_data_word_count.min(max_data_word_count),
+        false => rng.below(max_data_word_count + 1)
+    };
+
+    let header = CommandHeader::new(command_type, 0, 0, 0, 0, data_word_count, 0, 0, has_special_header);
+    let header_words: [u32; 2] = unsafe { mem::transmute(header) };
+    words[..2].copy_from_slice(&header_words);
+    words
+}
+
+fn write_request(words: &[u32; MESSAGE_WORD_COUNT]) {
+    unsafe {
+        core::ptr::copy(words.as_ptr() as *const u8, get_msg_buffer(), ThreadLocalRegion::MESSAGE_BUFFER_SIZE);
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ServiceFuzzReport {
+    pub service_name: String,
+    pub request_count: usize,
+    // Sessions closed by the service (malformed requests, close requests...) are connected again
+    pub reconnect_count: usize,
+    // Result value (of the SVC, or of the response if the SVC succeeded) -> count
+    pub results: BTreeMap<u32, usize>
+}
+
+impl ServiceFuzzReport {
+    fn new(service_name: &str) -> Self {
+        Self {
+            service_name: String::from(service_name),
+            ..Default::default()
+        }
+    }
+
+    fn record_result(&mut self, rc: ResultCode) {
+        *self.results.entry(rc.get_value()).or_insert(0) += 1;
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct FuzzCase {
+    iteration: usize,
+    seed: u64
+}
+
+static mut G_CURRENT_CASE: Mutex<Option<(String, FuzzCase)>> = parking_lot::const_mutex(None);
+
+// Meant for the panic handler: a crashing service most likely crashed because of the case being sent
+pub fn log_current_case() {
+    let current_case = match unsafe { G_CURRENT_CASE.try_lock() } {
+        Some(current_case) => current_case.clone(),
+        None => return
+    };
+
+    if let Some((service_name, case)) = current_case {
+        println!("* Fuzzing '{}', iteration {} (seed {})", service_name, case.iteration, case.seed);
+        println!("* Retry it with: ipc-fuzz --seed {} --service {} --start-iteration {} --iterations 1 (earlier cases might have changed the session's state though)", case.seed, service_name, case.iteration);
+    }
+}
+
+fn set_current_case(service_name: &str, case: Option<FuzzCase>) {
+    unsafe {
+        *G_CURRENT_CASE.lock() = case.map(|case| (String::from(service_name), case));
+    }
+}
+
+fn connect_to_service(service_name: &str) -> Result<svc::Handle> {
+    if NAMED_PORTS.contains(&service_name) {
+        return svc::connect_to_named_port(service_name);
+    }
+
+    let sm = new_named_port_object::<sm::UserInterface>()?;
+    let session_handle = sm.get().get_service_handle(ServiceName::new(service_name))?;
+    sm.get().detach_client(sf::ProcessId::new())?;
+    Ok(session_handle.handle)
+}
+
+fn fuzz_service(service_name: &str, options: &FuzzOptions) -> Result<ServiceFuzzReport> {
+    let mut report = ServiceFuzzReport::new(service_name);
+    let mut session_handle = connect_to_service(service_name)?;
+
+    for iteration in options.start_iteration..(options.start_iteration + options.iteration_count) {
+        let case = FuzzCase {
+            iteration: iteration,
+            seed: options.seed
+        };
+        set_current_case(service_name, Some(case));
+
+        let mut rng = FuzzRng::new(options.seed.wrapping_add(iteration as u64));
+        write_request(&generate_request(&mut rng));
+        report.request_count += 1;
+
+        match svc::send_sync_request(session_handle) {
+            Ok(()) => {
+                let mut ctx = CommandContext::new_client(ObjectInfo::from_handle(session_handle));
+                let rc = ResultCode::from(cmif::client::read_request_command_response_from_msg_buffer(&mut ctx));
+                report.record_result(rc);
+            },
+            Err(rc) => {
+                report.record_result(rc);
+
+                // Whatever the reason, the session is most likely unusable now
+                let _ = svc::close_handle(session_handle);
+                session_handle = connect_to_service(service_name)?;
+                report.reconnect_count += 1;
+            }
+        };
+    }
+
+    set_current_case(service_name, None);
+    svc::close_handle(session_handle)?;
+    Ok(report)
+}
+
+pub fn log_report(report: &ServiceFuzzReport) {
+    log_line!("[fuzz] '{}': {} requests, {} reconnects", report.service_name, report.request_count, report.reconnect_count);
+    for (rc_value, count) in report.results.iter() {
+        log_line!("[fuzz] * {} x{}", ResultCode::new(*rc_value).describe(), count);
+    }
+}
+
+// Fuzzes every service in order, from a host thread of a process of our own (services check who their clients are)
+pub fn run(options: &FuzzOptions) -> Result<Vec<ServiceFuzzReport>> {
+    let npdm = EmulatedProcess::make_npdm("fuzz", 44, 0x4000, ProgramId(0x010000000000FFFD), Vec::new(), 0x200)?;
+    let process = KProcess::new(None, npdm)?;
+    let mut main_thread = KProcess::create_main_thread_host(&process, String::from("pg.emu.fuzz.MainThread"))?;
+
+    let reports: Arc<Mutex<Vec<ServiceFuzzReport>>> = Arc::new(Mutex::new(Vec::new()));
+    let is_finished = Arc::new(AtomicBool::new(false));
+
+    let thread_options = options.clone();
+    let thread_reports = reports.clone();
+    let thread_is_finished = is_finished.clone();
+    KThread::start_host(&mut main_thread, move || {
+        for service_name in thread_options.services.iter() {
+            log_line!("[fuzz] Fuzzing '{}' with {} requests (seed {}, starting at iteration {})...", service_name, thread_options.iteration_count, thread_options.seed, thread_options.start_iteration);
+            match fuzz_service(service_name, &thread_options) {
+                Ok(report) => thread_reports.lock().push(report),
+                Err(rc) => log_line!("[fuzz] Unable to fuzz '{}': {1} ({1:?})", service_name, rc)
+            };
+        }
+        thread_is_finished.store(true, Ordering::SeqCst);
+    })?;
+
+    // Note: a service which never replies makes this wait forever, which the IPC watchdog (if enabled) reports
+    while !is_finished.load(Ordering::SeqCst) {
+        std::thread::sleep(FUZZ_TIMEOUT_PER_REQUEST);
+    }
+
+    let reports = reports.lock().clone();
+    Ok(reports)
+}
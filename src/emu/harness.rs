@@ -0,0 +1,2164 @@
+use std::collections::VecDeque;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use parking_lot::Mutex;
+use crate::bsd::{self, SockAddrIn};
+use crate::emu::cfg;
+use crate::emu::cpu::{self, Architecture, MemoryRegion, ModuleMemory, MemoryPermission, Register};
+use crate::emu::cpu::backend::{self, BackendKind, RegisterState};
+use crate::emu::cpu::lockstep::{self, LockstepOutcome};
+use crate::emu::disasm::{self, InstructionSet};
+use crate::emu::heap::{self, HeapKind, HeapReport};
+use crate::emu::output::{self as emu_output, OutputChannel};
+use crate::emu::trace::{self, TraceEvent};
+#[cfg(debug_assertions)]
+use crate::kern::ipc::{KClientSession, KServerSession, KSession};
+use crate::kern::proc::{KProcess, get_current_process};
+use crate::kern::thread::{self as kern_thread, KConditionVariable, KThread, ThreadState};
+use crate::kern::svc::{self, SvcId};
+use crate::kern::result as kern_result;
+use crate::ldr::npdm;
+use crate::ncm::ProgramId;
+use crate::proc::EmulatedProcess;
+use crate::time::TimeZoneRule;
+use crate::util::{self, Shared};
+use crate::result::*;
+
+pub mod svc_env;
+use svc_env::{SvcTestEnvironment, expect_svc_result};
+
+// Small integration test framework: builds tiny AArch64 payloads, runs them as actual guest processes and checks the SVC/IPC trace and memory state they leave behind
+
+pub const TEXT_ADDRESS: u64 = 0x8000000;
+pub const DATA_ADDRESS: u64 = 0x8100000;
+
+const NOP_INSN: u32 = 0xD503201F;
+const SVC_INSN_BASE: u32 = 0xD4000001;
+// Note: this just catches hung payloads, thus it's generous enough for the ones running thousands of IPC round trips
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct PayloadBuilder {
+    code: Vec<u32>,
+    data: Vec<u8>
+}
+
+impl PayloadBuilder {
+    pub const fn new() -> Self {
+        Self {
+            code: Vec::new(),
+            data: Vec::new()
+        }
+    }
+
+    pub fn insn(mut self, insn: u32) -> Self {
+        self.code.push(insn);
+        self
+    }
+
+    pub fn nop(self) -> Self {
+        self.insn(NOP_INSN)
+    }
+
+    pub fn svc(self, svc_id: SvcId) -> Self {
+        self.insn(SVC_INSN_BASE | ((svc_id as u32) << 5))
+    }
+
+    // Loads a full 64-bit immediate through MOVZ + 3 MOVKs
+    pub fn mov_imm(mut self, reg: u32, value: u64) -> Self {
+        assert!(reg < 31);
+
+        for i in 0..4 {
+            let imm16 = ((value >> (i * 16)) & 0xFFFF) as u32;
+            let base: u32 = match i {
+                0 => 0xD2800000, // MOVZ
+                _ => 0xF2800000  // MOVK
+            };
+            self = self.insn(base | ((i as u32) << 21) | (imm16 << 5) | reg);
+        }
+        self
+    }
+
+    // MOV Wd, Wm (ORR Wd, WZR, Wm)
+    pub fn mov_reg_w(self, reg: u32, src_reg: u32) -> Self {
+        self.insn(0x2A0003E0 | (src_reg << 16) | reg)
+    }
+
+    // MRS Xt, TPIDRRO_EL0 (the thread's TLR, where IPC messages are placed)
+    pub fn read_tlr_address(self, reg: u32) -> Self {
+        self.insn(0xD53BD060 | reg)
+    }
+
+    // MRS Xt, PMCCNTR_EL0
+    pub fn read_cycle_counter(self, reg: u32) -> Self {
+        self.insn(0xD53B9D00 | reg)
+    }
+
+    // STR Wt, [Xn, #offset]
+    pub fn store_w(self, reg: u32, base_reg: u32, offset: u32) -> Self {
+        assert!(offset % 4 == 0);
+        self.insn(0xB9000000 | ((offset / 4) << 10) | (base_reg << 5) | reg)
+    }
+
+    // LDR Wt, [Xn, #offset]
+    pub fn load_w(self, reg: u32, base_reg: u32, offset: u32) -> Self {
+        assert!(offset % 4 == 0);
+        self.insn(0xB9400000 | ((offset / 4) << 10) | (base_reg << 5) | reg)
+    }
+
+    // ADD Wd, Wn, #imm
+    pub fn add_imm_w(self, reg: u32, src_reg: u32, imm: u32) -> Self {
+        assert!(imm < 0x1000);
+        self.insn(0x11000000 | (imm << 10) | (src_reg << 5) | reg)
+    }
+
+    // ORR Wd, Wn, Wm
+    pub fn orr_w(self, reg: u32, src_reg: u32, other_reg: u32) -> Self {
+        self.insn(0x2A000000 | (other_reg << 16) | (src_reg << 5) | reg)
+    }
+
+    // CMP Wn, Wm (SUBS WZR, Wn, Wm)
+    pub fn cmp_w(self, reg: u32, other_reg: u32) -> Self {
+        self.insn(0x6B00001F | (other_reg << 16) | (reg << 5))
+    }
+
+    // The (guest) address of the next instruction, to be used as a branch target (or as the entry of an extra thread)
+    pub fn get_next_address(&self) -> u64 {
+        TEXT_ADDRESS + (self.code.len() * 4) as u64
+    }
+
+    fn get_branch_offset(&self, target: u64) -> i64 {
+        (target as i64 - self.get_next_address() as i64) / 4
+    }
+
+    // B target
+    pub fn branch(self, target: u64) -> Self {
+        let offset = self.get_branch_offset(target);
+        self.insn(0x14000000 | (offset as u32 & 0x3FFFFFF))
+    }
+
+    // CBZ Wt, target
+    pub fn branch_if_zero_w(self, reg: u32, target: u64) -> Self {
+        let offset = self.get_branch_offset(target);
+        self.insn(0x34000000 | ((offset as u32 & 0x7FFFF) << 5) | reg)
+    }
+
+    // CBNZ Wt, target
+    pub fn branch_if_not_zero_w(self, reg: u32, target: u64) -> Self {
+        let offset = self.get_branch_offset(target);
+        self.insn(0x35000000 | ((offset as u32 & 0x7FFFF) << 5) | reg)
+    }
+
+    // B.EQ target (after a CMP)
+    pub fn branch_if_equal(self, target: u64) -> Self {
+        let offset = self.get_branch_offset(target);
+        self.insn(0x54000000 | ((offset as u32 & 0x7FFFF) << 5))
+    }
+
+    // B.NE target (after a CMP)
+    pub fn branch_if_not_equal(self, target: u64) -> Self {
+        let offset = self.get_branch_offset(target);
+        self.insn(0x54000001 | ((offset as u32 & 0x7FFFF) << 5))
+    }
+
+    // Turns a previously placed instruction (usually a NOP) into a B, for forward branches whose target wasn't known back then
+    pub fn patch_branch(&mut self, address: u64, target: u64) {
+        let idx = ((address - TEXT_ADDRESS) / 4) as usize;
+        let offset = (target as i64 - address as i64) / 4;
+        self.code[idx] = 0x14000000 | (offset as u32 & 0x3FFFFFF);
+    }
+
+    // Appends data to the payload's data region, returning the (guest) address where it will be placed
+    pub fn push_data(&mut self, data: &[u8]) -> u64 {
+        let address = DATA_ADDRESS + self.data.len() as u64;
+        self.data.extend_from_slice(data);
+        // Keep everything word-aligned so that it can be used with load/store instructions
+        self.data.resize(util::align_up(self.data.len(), 8), 0);
+        address
+    }
+
+    pub fn reserve_data(&mut self, size: usize) -> u64 {
+        self.push_data(&vec![0; size])
+    }
+
+    pub fn build(self) -> ModuleMemory {
+        let mut code_data: Vec<u8> = Vec::with_capacity(self.code.len() * 4);
+        for insn in self.code.iter() {
+            code_data.extend_from_slice(&insn.to_le_bytes());
+        }
+
+        // Pad with NOPs: execution finishes once the end of the text region is reached
+        let code_size = util::align_up(code_data.len() + 4, 0x1000);
+        while code_data.len() < code_size {
+            code_data.extend_from_slice(&NOP_INSN.to_le_bytes());
+        }
+
+        let mut data = self.data;
+        data.resize(util::align_up(data.len().max(1), 0x1000), 0);
+
+        ModuleMemory::new(String::from("harness"), vec![
+            MemoryRegion::from(TEXT_ADDRESS, code_data, MemoryPermission::READ | MemoryPermission::EXEC),
+            MemoryRegion::from(DATA_ADDRESS, data, MemoryPermission::READ | MemoryPermission::WRITE)
+        ])
+    }
+}
+
+pub struct TestRunOutput {
+    pub process: Shared<KProcess>,
+    pub events: Vec<TraceEvent>,
+    // Live Shareds by type right before the payload was started (only tracked in debug builds, see util::get_live_shared_object_counts)
+    pub initial_live_object_counts: Vec<(&'static str, usize)>
+}
+
+impl TestRunOutput {
+    pub fn get_svc_calls(&self) -> Vec<(SvcId, u32)> {
+        self.events.iter().filter_map(|event| match *event {
+            TraceEvent::Svc { svc_id, rc, .. } => Some((svc_id, rc)),
+            _ => None
+        }).collect()
+    }
+
+    pub fn get_ipc_requests(&self) -> Vec<(svc::Handle, u16)> {
+        self.events.iter().filter_map(|event| match *event {
+            TraceEvent::IpcRequest { session_handle, command_type, .. } => Some((session_handle, command_type)),
+            _ => None
+        }).collect()
+    }
+
+    pub fn read_memory(&self, address: u64, size: usize) -> Option<Vec<u8>> {
+        let process = self.process.get();
+        let cpu_ctx = process.cpu_ctx.as_ref()?;
+        for module in cpu_ctx.modules.iter() {
+            for region in module.regions.iter() {
+                if region.contains(address) && region.contains(address + size as u64 - 1) {
+                    let offset = (address - region.start()) as usize;
+                    return Some(region.data[offset..offset + size].to_vec());
+                }
+            }
+        }
+
+        None
+    }
+
+    pub fn read_memory_val<T: Copy>(&self, address: u64) -> Option<T> {
+        let data = self.read_memory(address, std::mem::size_of::<T>())?;
+        util::slice_read_val(&data, None).ok()
+    }
+}
+
+pub type TestCheckFn = fn(&TestRunOutput) -> std::result::Result<(), String>;
+
+pub struct TestCase {
+    pub name: &'static str,
+    pub payload: fn() -> ModuleMemory,
+    pub svcs: Vec<SvcId>,
+    pub check: TestCheckFn
+}
+
+// A payload process which was started but not waited for yet, several of them may run at once (each one with its own process and CPU context)
+pub struct RunningPayload {
+    process: Shared<KProcess>,
+    process_id: u64,
+    // The main thread plus any extra ones, the payload is done once all of them exited
+    thread_ids: Vec<u64>,
+    initial_live_object_counts: Vec<(&'static str, usize)>
+}
+
+impl RunningPayload {
+    fn has_svc_call(&self, svc_id: SvcId) -> bool {
+        trace::get_events().iter().any(|event| match *event {
+            TraceEvent::Svc { process_id, svc_id: event_svc_id, .. } => (process_id == self.process_id) && (event_svc_id == svc_id),
+            _ => false
+        })
+    }
+
+    fn has_exited(&self) -> bool {
+        let events = trace::get_events();
+        self.thread_ids.iter().all(|&exited_thread_id| events.iter().any(|event| match *event {
+            TraceEvent::ThreadExit { thread_id, .. } => thread_id == exited_thread_id,
+            _ => false
+        }))
+    }
+
+    // Useful to start other payloads only once this one reached some point (like registering a port)
+    pub fn wait_for_svc_call(&self, svc_id: SvcId, timeout: Duration) -> Result<()> {
+        let start = Instant::now();
+        while !self.has_svc_call(svc_id) {
+            result_return_if!(start.elapsed() >= timeout, kern_result::ResultTimedOut);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        Ok(())
+    }
+
+    pub fn wait(self, timeout: Duration) -> Result<TestRunOutput> {
+        let start = Instant::now();
+        while !self.has_exited() {
+            result_return_if!(start.elapsed() >= timeout, kern_result::ResultTimedOut);
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let events = trace::get_events().into_iter().filter(|event| event.get_process_id() == self.process_id).collect();
+        Ok(TestRunOutput {
+            process: self.process,
+            events: events,
+            initial_live_object_counts: self.initial_live_object_counts
+        })
+    }
+}
+
+// An extra thread running in the payload process (on the same core as the main thread)
+pub struct PayloadThread {
+    pub entry_address: u64,
+    pub priority: i32
+}
+
+pub fn start_payload(name: &str, module: ModuleMemory, svcs: Vec<SvcId>) -> Result<RunningPayload> {
+    start_payload_with_threads(name, module, svcs, &[])
+}
+
+// Extra threads get the main thread's handle in X0 and their own handle in X1, while the main thread gets the first extra thread's handle in X0 (and its own handle in X1, as usual)
+// Note: all threads (and their handles) are created before starting any of them, so that payloads don't need any thread creation SVCs
+pub fn start_payload_with_threads(name: &str, module: ModuleMemory, svcs: Vec<SvcId>, threads: &[PayloadThread]) -> Result<RunningPayload> {
+    let npdm = EmulatedProcess::make_npdm(name, 44, 0x4000, ProgramId(0x010000000000FFFF), svcs, 0x200)?;
+
+    let mut cpu_ctx = cpu::Context::new();
+    cpu_ctx.modules.push(module);
+
+    trace::set_enabled(true);
+    let initial_live_object_counts = util::get_live_shared_object_counts();
+
+    let mut process = KProcess::new(Some(cpu_ctx), npdm)?;
+    let process_id = process.get().id;
+    let (mut main_thread, main_thread_handle) = KProcess::create_main_thread(&mut process, format!("test.{}.MainThread", name), TEXT_ADDRESS)?;
+    let mut thread_ids = vec![main_thread.get().id];
+
+    let cpu_core = process.get().npdm.meta.main_thread_cpu_core as i32;
+    let stack_size = process.get().npdm.meta.main_thread_stack_size as usize;
+    let mut extra_threads: Vec<(Shared<KThread>, svc::Handle)> = Vec::with_capacity(threads.len());
+    for (i, thread) in threads.iter().enumerate() {
+        let extra_thread = KThread::new(Some(process.clone()), format!("test.{}.Thread{}", name, i), thread.priority, cpu_core, Some((thread.entry_address, stack_size)))?;
+        let extra_thread_handle = process.get().handle_table.allocate_handle_set(extra_thread.clone())?;
+        thread_ids.push(extra_thread.get().id);
+        extra_threads.push((extra_thread, extra_thread_handle));
+    }
+
+    let first_extra_thread_handle = extra_threads.first().map(|&(_, handle)| handle).unwrap_or(svc::INVALID_HANDLE);
+    for (extra_thread, extra_thread_handle) in extra_threads.iter_mut() {
+        KThread::start_exec(extra_thread, main_thread_handle, *extra_thread_handle)?;
+    }
+    KThread::start_exec(&mut main_thread, first_extra_thread_handle, main_thread_handle)?;
+
+    Ok(RunningPayload {
+        process: process,
+        process_id: process_id,
+        thread_ids: thread_ids,
+        initial_live_object_counts: initial_live_object_counts
+    })
+}
+
+pub fn run_payload(name: &str, module: ModuleMemory, svcs: Vec<SvcId>, timeout: Duration) -> Result<TestRunOutput> {
+    start_payload(name, module, svcs)?.wait(timeout)
+}
+
+pub fn run_test_case(test_case: &TestCase) -> std::result::Result<(), String> {
+    let output = match run_payload(test_case.name, (test_case.payload)(), test_case.svcs.clone(), DEFAULT_TIMEOUT) {
+        Ok(output) => output,
+        Err(rc) => return Err(format!("unable to run payload: {0} ({0:?})", rc))
+    };
+
+    let check_result = (test_case.check)(&output);
+    if check_result.is_err() {
+        log_line!("[harness] {} trace:", test_case.name);
+        for event in output.events.iter() {
+            log_line!("[harness]   {}", event.describe());
+        }
+    }
+    check_result
+}
+
+// Returns whether all the test cases passed
+pub fn run_test_cases(test_cases: &[TestCase]) -> bool {
+    let mut failed_count: usize = 0;
+    for test_case in test_cases {
+        match run_test_case(test_case) {
+            Ok(()) => log_line!("[harness] {} ... ok", test_case.name),
+            Err(msg) => {
+                log_line!("[harness] {} ... FAILED: {}", test_case.name, msg);
+                failed_count += 1;
+            }
+        }
+    }
+
+    log_line!("[harness] {} passed, {} failed", test_cases.len() - failed_count, failed_count);
+    failed_count == 0
+}
+
+// Host test cases are driven from the host side: kernel objects used from emulated (guest-less) host threads, or several payloads running at once
+
+pub type HostTestFn = fn() -> std::result::Result<(), String>;
+
+pub struct HostTestCase {
+    pub name: &'static str,
+    pub run: HostTestFn
+}
+
+// Runs the given function on several host threads (spread among all cores), waiting for all of them to finish
+pub fn run_host_threads<F: Fn(usize) + Send + Sync + 'static>(name: &str, count: usize, f: F, timeout: Duration) -> std::result::Result<(), String> {
+    let f = Arc::new(f);
+    let finished_count = Arc::new(AtomicUsize::new(0));
+    for i in 0..count {
+        let mut thread = match KThread::new_host(None, format!("test.{}.HostThread{}", name, i), 44, (i % kern_thread::CPU_CORE_COUNT) as i32) {
+            Ok(thread) => thread,
+            Err(rc) => return Err(format!("unable to create host thread: {0} ({0:?})", rc))
+        };
+
+        let thread_f = f.clone();
+        let thread_finished_count = finished_count.clone();
+        if let Err(rc) = KThread::start_host(&mut thread, move || {
+            thread_f(i);
+            thread_finished_count.fetch_add(1, Ordering::SeqCst);
+        }) {
+            return Err(format!("unable to start host thread: {0} ({0:?})", rc));
+        }
+    }
+
+    let start = Instant::now();
+    while finished_count.load(Ordering::SeqCst) < count {
+        if start.elapsed() >= timeout {
+            return Err(format!("only {} of {} host threads finished in time", finished_count.load(Ordering::SeqCst), count));
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    Ok(())
+}
+
+// Returns whether all the test cases passed
+pub fn run_host_test_cases(test_cases: &[HostTestCase]) -> bool {
+    let mut failed_count: usize = 0;
+    for test_case in test_cases {
+        match (test_case.run)() {
+            Ok(()) => log_line!("[harness] {} ... ok", test_case.name),
+            Err(msg) => {
+                log_line!("[harness] {} ... FAILED: {}", test_case.name, msg);
+                failed_count += 1;
+            }
+        }
+    }
+
+    log_line!("[harness] {} host tests passed, {} failed", test_cases.len() - failed_count, failed_count);
+    failed_count == 0
+}
+
+// Built-in test cases
+
+fn expect_svc_calls(output: &TestRunOutput, expected: &[(SvcId, u32)]) -> std::result::Result<(), String> {
+    let svc_calls = output.get_svc_calls();
+    if svc_calls.as_slice() != expected {
+        let format_svc_calls = |svc_calls: &[(SvcId, u32)]| svc_calls.iter().map(|(svc_id, rc)| format!("{:?} = {}", svc_id, ResultCode::new(*rc).describe())).collect::<Vec<String>>().join(", ");
+        return Err(format!("expected SVC calls [{}], got [{}]", format_svc_calls(expected), format_svc_calls(&svc_calls)));
+    }
+
+    Ok(())
+}
+
+const OUTPUT_DEBUG_STRING_MSG: &str = "Hello from the test harness!";
+
+fn output_debug_string_payload() -> ModuleMemory {
+    let mut builder = PayloadBuilder::new();
+    let msg_addr = builder.push_data(OUTPUT_DEBUG_STRING_MSG.as_bytes());
+    builder.mov_imm(0, msg_addr)
+        .mov_imm(1, OUTPUT_DEBUG_STRING_MSG.len() as u64)
+        .svc(SvcId::OutputDebugString)
+        .build()
+}
+
+fn output_debug_string_check(output: &TestRunOutput) -> std::result::Result<(), String> {
+    expect_svc_calls(output, &[(SvcId::OutputDebugString, ResultSuccess::get_value())])?;
+
+    let process_id = output.process.get().id;
+    let debug_output = emu_output::get_process_channel_output(process_id, OutputChannel::DebugString);
+    match debug_output.iter().any(|text| text == OUTPUT_DEBUG_STRING_MSG) {
+        true => Ok(()),
+        false => Err(format!("Expected the message in the process's debug output, got {:?}", debug_output))
+    }
+}
+
+fn close_invalid_handle_payload() -> ModuleMemory {
+    PayloadBuilder::new()
+        .mov_imm(0, 0xBAD)
+        .svc(SvcId::CloseHandle)
+        .build()
+}
+
+fn close_invalid_handle_check(output: &TestRunOutput) -> std::result::Result<(), String> {
+    expect_svc_calls(output, &[(SvcId::CloseHandle, kern_result::ResultInvalidHandle::get_value())])
+}
+
+fn connect_to_sm_payload() -> ModuleMemory {
+    let mut builder = PayloadBuilder::new();
+    let port_name_addr = builder.push_data(b"sm:\0");
+    let handle_addr = builder.reserve_data(4);
+
+    builder.mov_imm(1, port_name_addr)
+        .svc(SvcId::ConnectToNamedPort)
+        .mov_imm(2, handle_addr)
+        .store_w(1, 2, 0)
+        .load_w(0, 2, 0)
+        .svc(SvcId::CloseHandle)
+        .build()
+}
+
+fn connect_to_sm_check(output: &TestRunOutput) -> std::result::Result<(), String> {
+    expect_svc_calls(output, &[(SvcId::ConnectToNamedPort, ResultSuccess::get_value()), (SvcId::CloseHandle, ResultSuccess::get_value())])?;
+
+    // The handle must have been written by the payload into its data region
+    match output.read_memory_val::<svc::Handle>(DATA_ADDRESS + 8) {
+        Some(svc::INVALID_HANDLE) | None => Err(String::from("no valid session handle was stored")),
+        Some(_) => Ok(())
+    }
+}
+
+// Way more than the max session count of sm: (and than the session limit of the process), thus any session not being released when its handle is closed makes connecting fail at some point
+const SESSION_CYCLE_COUNT: usize = 0x1000;
+
+// Servers drop their side of closed sessions asynchronously, thus they're given some time to do so
+#[cfg(debug_assertions)]
+const SESSION_RELEASE_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[cfg(debug_assertions)]
+fn get_live_object_count(counts: &[(&'static str, usize)], type_name: &str) -> usize {
+    counts.iter().find(|(count_type_name, _)| *count_type_name == type_name).map_or(0, |(_, count)| *count)
+}
+
+// Besides the sessions not failing, no session object may outlive the payload, which the counts from before it ran are compared against
+#[cfg(debug_assertions)]
+fn expect_session_objects_released(output: &TestRunOutput) -> std::result::Result<(), String> {
+    let session_type_names = [std::any::type_name::<KSession>(), std::any::type_name::<KServerSession>(), std::any::type_name::<KClientSession>()];
+
+    let start = Instant::now();
+    loop {
+        let live_object_counts = util::get_live_shared_object_counts();
+        let leaked_objects: Vec<String> = session_type_names.iter().filter_map(|type_name| {
+            let initial_count = get_live_object_count(&output.initial_live_object_counts, type_name);
+            let count = get_live_object_count(&live_object_counts, type_name);
+            match count > initial_count {
+                true => Some(format!("{} ({} -> {})", type_name, initial_count, count)),
+                false => None
+            }
+        }).collect();
+
+        if leaked_objects.is_empty() {
+            return Ok(());
+        }
+        if start.elapsed() >= SESSION_RELEASE_TIMEOUT {
+            return Err(format!("session objects leaked: {}", leaked_objects.join(", ")));
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+// Note: objects are only tracked in debug builds, thus the leak check can't be done in release ones
+#[cfg(not(debug_assertions))]
+fn expect_session_objects_released(_output: &TestRunOutput) -> std::result::Result<(), String> {
+    log_line!("[harness] Skipping the session leak check, since live objects are only tracked in debug builds");
+    Ok(())
+}
+
+fn connect_close_sessions_payload() -> ModuleMemory {
+    let mut builder = PayloadBuilder::new();
+    let port_name_addr = builder.push_data(b"sm:\0");
+
+    for _ in 0..SESSION_CYCLE_COUNT {
+        builder = builder.mov_imm(1, port_name_addr)
+            .svc(SvcId::ConnectToNamedPort)
+            .mov_reg_w(0, 1)
+            .svc(SvcId::CloseHandle);
+    }
+    builder.build()
+}
+
+fn connect_close_sessions_check(output: &TestRunOutput) -> std::result::Result<(), String> {
+    let svc_calls = output.get_svc_calls();
+    if svc_calls.len() != SESSION_CYCLE_COUNT * 2 {
+        return Err(format!("expected {} SVC calls, got {}", SESSION_CYCLE_COUNT * 2, svc_calls.len()));
+    }
+
+    match svc_calls.iter().position(|&(_, rc)| rc != ResultSuccess::get_value()) {
+        Some(idx) => Err(format!("session cycle {} failed: {:?}", idx / 2, svc_calls[idx])),
+        None => expect_session_objects_released(output)
+    }
+}
+
+fn close_sessions_by_command_payload() -> ModuleMemory {
+    let mut builder = PayloadBuilder::new();
+    let port_name_addr = builder.push_data(b"sm:\0");
+
+    // CMIF close command: just the header, without any data
+    builder = builder.read_tlr_address(2)
+        .mov_imm(3, 2)
+        .store_w(3, 2, 0)
+        .mov_imm(3, 0)
+        .store_w(3, 2, 4);
+
+    for _ in 0..SESSION_CYCLE_COUNT {
+        builder = builder.mov_imm(1, port_name_addr)
+            .svc(SvcId::ConnectToNamedPort)
+            .mov_reg_w(19, 1)
+            .mov_reg_w(0, 19)
+            .svc(SvcId::SendSyncRequest)
+            .mov_reg_w(0, 19)
+            .svc(SvcId::CloseHandle);
+    }
+    builder.build()
+}
+
+fn close_sessions_by_command_check(output: &TestRunOutput) -> std::result::Result<(), String> {
+    // The server drops its side of every session once it's closed, otherwise it would run out of wait handles at some point and stop replying
+    let svc_calls = output.get_svc_calls();
+    if svc_calls.len() != SESSION_CYCLE_COUNT * 3 {
+        return Err(format!("expected {} SVC calls, got {}", SESSION_CYCLE_COUNT * 3, svc_calls.len()));
+    }
+
+    match svc_calls.iter().position(|&(_, rc)| rc != ResultSuccess::get_value()) {
+        Some(idx) => Err(format!("session cycle {} failed: {:?}", idx / 3, svc_calls[idx])),
+        None => expect_session_objects_released(output)
+    }
+}
+
+// Writes the two command header words (and the special header word, if any) of a raw IPC message on the TLR (x2 and x3 are used as scratch registers)
+fn write_message_headers(builder: PayloadBuilder, header_words: &[u32]) -> PayloadBuilder {
+    let mut builder = builder.read_tlr_address(2);
+    for (i, word) in header_words.iter().enumerate() {
+        builder = builder.mov_imm(3, *word as u64)
+            .store_w(3, 2, (i * 4) as u32);
+    }
+    builder
+}
+
+fn malformed_messages_payload() -> ModuleMemory {
+    let mut builder = PayloadBuilder::new();
+    let port_name_addr = builder.push_data(b"sm:\0");
+
+    builder = builder.mov_imm(1, port_name_addr)
+        .svc(SvcId::ConnectToNamedPort)
+        .mov_reg_w(19, 1);
+
+    // CMIF request claiming way more data words than the message buffer can hold
+    builder = write_message_headers(builder, &[4, 0x3FF])
+        .mov_reg_w(0, 19)
+        .svc(SvcId::SendSyncRequest);
+
+    // Handles and data which fit on their own but not together
+    builder = write_message_headers(builder, &[4, (1 << 31) | 0x30, (15 << 1) | (15 << 5)])
+        .mov_reg_w(0, 19)
+        .svc(SvcId::SendSyncRequest);
+
+    // The session (and its server) must still work afterwards
+    write_message_headers(builder, &[2, 0])
+        .mov_reg_w(0, 19)
+        .svc(SvcId::SendSyncRequest)
+        .mov_reg_w(0, 19)
+        .svc(SvcId::CloseHandle)
+        .build()
+}
+
+fn malformed_messages_check(output: &TestRunOutput) -> std::result::Result<(), String> {
+    // Malformed messages are rejected by the kernel before anything reaches the server
+    expect_svc_calls(output, &[(SvcId::ConnectToNamedPort, ResultSuccess::get_value()), (SvcId::SendSyncRequest, kern_result::ResultInvalidCombination::get_value()), (SvcId::SendSyncRequest, kern_result::ResultInvalidCombination::get_value()), (SvcId::SendSyncRequest, ResultSuccess::get_value()), (SvcId::CloseHandle, ResultSuccess::get_value())])
+}
+
+// More than the session limit of the process, thus any session reservation not released once both handles are closed makes creating sessions fail at some point
+const CREATE_SESSION_CYCLE_COUNT: usize = 0x400;
+
+fn create_close_sessions_payload() -> ModuleMemory {
+    let mut builder = PayloadBuilder::new();
+
+    // Both regular and light sessions
+    for i in 0..CREATE_SESSION_CYCLE_COUNT {
+        builder = builder.mov_imm(2, (i % 2) as u64)
+            .mov_imm(3, 0)
+            .svc(SvcId::CreateSession)
+            .mov_reg_w(19, 2)
+            .mov_reg_w(0, 1)
+            .svc(SvcId::CloseHandle)
+            .mov_reg_w(0, 19)
+            .svc(SvcId::CloseHandle);
+    }
+    builder.build()
+}
+
+fn create_close_sessions_check(output: &TestRunOutput) -> std::result::Result<(), String> {
+    let svc_calls = output.get_svc_calls();
+    if svc_calls.len() != CREATE_SESSION_CYCLE_COUNT * 3 {
+        return Err(format!("expected {} SVC calls, got {}", CREATE_SESSION_CYCLE_COUNT * 3, svc_calls.len()));
+    }
+
+    match svc_calls.iter().position(|&(_, rc)| rc != ResultSuccess::get_value()) {
+        Some(idx) => Err(format!("session cycle {} failed: {:?}", idx / 3, svc_calls[idx])),
+        None => expect_session_objects_released(output)
+    }
+}
+
+fn set_thread_activity_invalid_payload() -> ModuleMemory {
+    PayloadBuilder::new()
+        .mov_imm(0, 0xBAD)
+        .mov_imm(1, svc::ThreadActivity::Paused as u64)
+        .svc(SvcId::SetThreadActivity)
+        .mov_imm(0, 0xBAD)
+        .mov_imm(1, 0x2)
+        .svc(SvcId::SetThreadActivity)
+        .build()
+}
+
+fn set_thread_activity_invalid_check(output: &TestRunOutput) -> std::result::Result<(), String> {
+    // The activity value is checked before the handle
+    expect_svc_calls(output, &[(SvcId::SetThreadActivity, kern_result::ResultInvalidHandle::get_value()), (SvcId::SetThreadActivity, kern_result::ResultInvalidEnumValue::get_value())])
+}
+
+fn yield_thread_payload() -> ModuleMemory {
+    PayloadBuilder::new()
+        .mov_imm(0, 0)
+        .svc(SvcId::SleepThread)
+        .mov_imm(0, -1i64 as u64)
+        .svc(SvcId::SleepThread)
+        .mov_imm(0, -2i64 as u64)
+        .svc(SvcId::SleepThread)
+        .build()
+}
+
+fn yield_thread_check(output: &TestRunOutput) -> std::result::Result<(), String> {
+    // Yielding always succeeds, whether other threads got to run or not
+    expect_svc_calls(output, &[(SvcId::SleepThread, ResultSuccess::get_value()); 3])
+}
+
+fn sleep_and_wait_timeout_payload() -> ModuleMemory {
+    PayloadBuilder::new()
+        .mov_imm(0, 1_000_000)
+        .svc(SvcId::SleepThread)
+        // Waiting on no objects at all can only time out
+        .mov_imm(1, 0)
+        .mov_imm(2, 0)
+        .mov_imm(3, 1_000_000)
+        .svc(SvcId::WaitSynchronization)
+        .build()
+}
+
+fn sleep_and_wait_timeout_check(output: &TestRunOutput) -> std::result::Result<(), String> {
+    expect_svc_calls(output, &[(SvcId::SleepThread, ResultSuccess::get_value()), (SvcId::WaitSynchronization, kern_result::ResultTimedOut::get_value())])
+}
+
+fn arbitrate_misaligned_payload() -> ModuleMemory {
+    PayloadBuilder::new()
+        .mov_imm(0, 0)
+        .mov_imm(1, 0x1002)
+        .mov_imm(2, 0)
+        .svc(SvcId::ArbitrateLock)
+        .mov_imm(0, 0x1002)
+        .svc(SvcId::ArbitrateUnlock)
+        .build()
+}
+
+fn arbitrate_misaligned_check(output: &TestRunOutput) -> std::result::Result<(), String> {
+    // Mutex addresses must be 4-byte aligned, which is checked before accessing them
+    expect_svc_calls(output, &[(SvcId::ArbitrateLock, kern_result::ResultInvalidAddress::get_value()), (SvcId::ArbitrateUnlock, kern_result::ResultInvalidAddress::get_value())])
+}
+
+fn get_idle_tick_count_payload() -> ModuleMemory {
+    let mut builder = PayloadBuilder::new();
+    let cycle_count_addr = builder.reserve_data(4);
+
+    builder.read_cycle_counter(4)
+        .mov_imm(5, cycle_count_addr)
+        .store_w(4, 5, 0)
+        .mov_imm(1, svc::InfoType::IdleTickCount as u64)
+        .mov_imm(2, svc::INVALID_HANDLE as u64)
+        .mov_imm(3, svc::INFO_SUBTYPE_CURRENT_CORE)
+        .svc(SvcId::GetInfo)
+        // Only the current core can be queried, and there is no core 0xBAD at all
+        .mov_imm(1, svc::InfoType::IdleTickCount as u64)
+        .mov_imm(3, 0xBAD)
+        .svc(SvcId::GetInfo)
+        .mov_imm(1, svc::InfoType::IdleTickCount as u64)
+        .mov_imm(2, 0xBAD)
+        .mov_imm(3, svc::INFO_SUBTYPE_CURRENT_CORE)
+        .svc(SvcId::GetInfo)
+        .build()
+}
+
+fn get_idle_tick_count_check(output: &TestRunOutput) -> std::result::Result<(), String> {
+    expect_svc_calls(output, &[(SvcId::GetInfo, ResultSuccess::get_value()), (SvcId::GetInfo, kern_result::ResultInvalidCombination::get_value()), (SvcId::GetInfo, kern_result::ResultInvalidHandle::get_value())])?;
+
+    // The system tick (thus the cycle counter) starts when the kernel is initialized, way before any payload runs
+    match output.read_memory_val::<u32>(DATA_ADDRESS) {
+        Some(0) | None => Err(String::from("the cycle counter read wasn't emulated")),
+        Some(_) => Ok(())
+    }
+}
+
+const CREATED_PROCESS_CODE_ADDRESS: u64 = 0x10000000;
+
+fn push_create_process_params(builder: &mut PayloadBuilder) -> u64 {
+    let mut params: Vec<u8> = Vec::new();
+    params.extend_from_slice(b"created\0\0\0\0\0");
+    params.extend_from_slice(&0u32.to_le_bytes());
+    params.extend_from_slice(&0x010000000000FFFEu64.to_le_bytes());
+    params.extend_from_slice(&CREATED_PROCESS_CODE_ADDRESS.to_le_bytes());
+    params.extend_from_slice(&1u32.to_le_bytes());
+    params.extend_from_slice(&(svc::CreateProcessFlags::Is64Bit().get() | ((npdm::AddressSpaceType::AS64Bit as u32) << 1)).to_le_bytes());
+    params.extend_from_slice(&svc::INVALID_HANDLE.to_le_bytes());
+    params.extend_from_slice(&0u32.to_le_bytes());
+    builder.push_data(&params)
+}
+
+fn create_process_payload() -> ModuleMemory {
+    let mut builder = PayloadBuilder::new();
+    let params_addr = push_create_process_params(&mut builder);
+    // Note: the created process handle is returned in W1, right where GetProcessInfo expects it
+    builder.mov_imm(1, params_addr)
+        .mov_imm(2, 0)
+        .mov_imm(3, 0)
+        .svc(SvcId::CreateProcess)
+        .mov_imm(2, svc::ProcessInfoType::ProcessState as u64)
+        .svc(SvcId::GetProcessInfo)
+        .build()
+}
+
+fn create_process_check(output: &TestRunOutput) -> std::result::Result<(), String> {
+    expect_svc_calls(output, &[(SvcId::CreateProcess, ResultSuccess::get_value()), (SvcId::GetProcessInfo, ResultSuccess::get_value())])
+}
+
+fn map_process_memory_payload() -> ModuleMemory {
+    const MAP_ADDRESS: u64 = 0x20000000;
+
+    let mut builder = PayloadBuilder::new();
+    let params_addr = push_create_process_params(&mut builder);
+    // The created process handle (W1) is kept for the rest of the SVCs
+    builder.mov_imm(1, params_addr)
+        .mov_imm(2, 0)
+        .mov_imm(3, 0)
+        .svc(SvcId::CreateProcess)
+        .mov_imm(0, MAP_ADDRESS + 0x10)
+        .mov_imm(2, CREATED_PROCESS_CODE_ADDRESS)
+        .mov_imm(3, 0x1000)
+        .svc(SvcId::MapProcessMemory)
+        .mov_imm(0, MAP_ADDRESS)
+        .svc(SvcId::MapProcessMemory)
+        .mov_imm(0, MAP_ADDRESS)
+        .svc(SvcId::UnmapProcessMemory)
+        .build()
+}
+
+fn map_process_memory_check(output: &TestRunOutput) -> std::result::Result<(), String> {
+    expect_svc_calls(output, &[(SvcId::CreateProcess, ResultSuccess::get_value()), (SvcId::MapProcessMemory, kern_result::ResultInvalidAddress::get_value()), (SvcId::MapProcessMemory, ResultSuccess::get_value()), (SvcId::UnmapProcessMemory, ResultSuccess::get_value())])
+}
+
+fn flush_data_cache_payload() -> ModuleMemory {
+    PayloadBuilder::new()
+        .mov_imm(0, TEXT_ADDRESS)
+        .mov_imm(1, 0x1000)
+        .svc(SvcId::FlushDataCache)
+        .mov_imm(0, TEXT_ADDRESS)
+        .mov_imm(1, 0)
+        .svc(SvcId::FlushDataCache)
+        .svc(SvcId::FlushEntireDataCache)
+        .mov_imm(0, 0xBAD)
+        .mov_imm(1, TEXT_ADDRESS)
+        .mov_imm(2, 0x1000)
+        .svc(SvcId::FlushProcessDataCache)
+        .build()
+}
+
+fn flush_data_cache_check(output: &TestRunOutput) -> std::result::Result<(), String> {
+    // Flushing the code being executed must not break anything: execution carries on with the retranslated code
+    expect_svc_calls(output, &[(SvcId::FlushDataCache, ResultSuccess::get_value()), (SvcId::FlushDataCache, ResultSuccess::get_value()), (SvcId::FlushEntireDataCache, ResultSuccess::get_value()), (SvcId::FlushProcessDataCache, kern_result::ResultInvalidHandle::get_value())])
+}
+
+const QUERY_MEMORY_FREE_ADDRESS: u64 = TEXT_ADDRESS + 0x10000;
+
+fn query_memory_payload() -> ModuleMemory {
+    let mut builder = PayloadBuilder::new();
+    let text_info_addr = builder.reserve_data(std::mem::size_of::<svc::MemoryInfo>());
+    let free_info_addr = builder.reserve_data(std::mem::size_of::<svc::MemoryInfo>());
+
+    builder.mov_imm(0, text_info_addr)
+        .mov_imm(2, TEXT_ADDRESS + 0x10)
+        .svc(SvcId::QueryMemory)
+        .mov_imm(0, free_info_addr)
+        .mov_imm(2, QUERY_MEMORY_FREE_ADDRESS)
+        .svc(SvcId::QueryMemory)
+        .build()
+}
+
+fn query_memory_check(output: &TestRunOutput) -> std::result::Result<(), String> {
+    expect_svc_calls(output, &[(SvcId::QueryMemory, ResultSuccess::get_value()), (SvcId::QueryMemory, ResultSuccess::get_value())])?;
+
+    // The info buffers are the first data in the payload
+    let info_size = util::align_up(std::mem::size_of::<svc::MemoryInfo>(), 8) as u64;
+    let text_info: svc::MemoryInfo = output.read_memory_val(DATA_ADDRESS).ok_or("Unable to read the .text memory info")?;
+    let free_info: svc::MemoryInfo = output.read_memory_val(DATA_ADDRESS + info_size).ok_or("Unable to read the free memory info")?;
+
+    if (text_info.base_address != TEXT_ADDRESS) || (text_info.state != svc::MemoryState::Code) || (text_info.perm != (svc::MemoryPermission::Read() | svc::MemoryPermission::Execute())) {
+        return Err(format!("Unexpected .text memory info: {:?}", text_info));
+    }
+
+    // The payload's .text is a single page, followed by unmapped memory up to its data
+    let free_end_address = free_info.base_address + free_info.size as u64;
+    if (free_info.state != svc::MemoryState::Free) || (free_info.base_address != TEXT_ADDRESS + 0x1000) || (free_end_address != DATA_ADDRESS) {
+        return Err(format!("Unexpected free memory info: {:?}", free_info));
+    }
+
+    Ok(())
+}
+
+pub fn get_builtin_test_cases() -> Vec<TestCase> {
+    vec![
+        TestCase {
+            name: "output_debug_string",
+            payload: output_debug_string_payload,
+            svcs: vec![SvcId::OutputDebugString],
+            check: output_debug_string_check
+        },
+        TestCase {
+            name: "close_invalid_handle",
+            payload: close_invalid_handle_payload,
+            svcs: vec![SvcId::CloseHandle],
+            check: close_invalid_handle_check
+        },
+        TestCase {
+            name: "connect_to_sm",
+            payload: connect_to_sm_payload,
+            svcs: vec![SvcId::ConnectToNamedPort, SvcId::CloseHandle],
+            check: connect_to_sm_check
+        },
+        TestCase {
+            name: "connect_close_sessions",
+            payload: connect_close_sessions_payload,
+            svcs: vec![SvcId::ConnectToNamedPort, SvcId::CloseHandle],
+            check: connect_close_sessions_check
+        },
+        TestCase {
+            name: "close_sessions_by_command",
+            payload: close_sessions_by_command_payload,
+            svcs: vec![SvcId::ConnectToNamedPort, SvcId::SendSyncRequest, SvcId::CloseHandle],
+            check: close_sessions_by_command_check
+        },
+        TestCase {
+            name: "malformed_messages",
+            payload: malformed_messages_payload,
+            svcs: vec![SvcId::ConnectToNamedPort, SvcId::SendSyncRequest, SvcId::CloseHandle],
+            check: malformed_messages_check
+        },
+        TestCase {
+            name: "create_close_sessions",
+            payload: create_close_sessions_payload,
+            svcs: vec![SvcId::CreateSession, SvcId::CloseHandle],
+            check: create_close_sessions_check
+        },
+        TestCase {
+            name: "set_thread_activity_invalid",
+            payload: set_thread_activity_invalid_payload,
+            svcs: vec![SvcId::SetThreadActivity],
+            check: set_thread_activity_invalid_check
+        },
+        TestCase {
+            name: "yield_thread",
+            payload: yield_thread_payload,
+            svcs: vec![SvcId::SleepThread],
+            check: yield_thread_check
+        },
+        TestCase {
+            name: "sleep_and_wait_timeout",
+            payload: sleep_and_wait_timeout_payload,
+            svcs: vec![SvcId::SleepThread, SvcId::WaitSynchronization],
+            check: sleep_and_wait_timeout_check
+        },
+        TestCase {
+            name: "arbitrate_misaligned",
+            payload: arbitrate_misaligned_payload,
+            svcs: vec![SvcId::ArbitrateLock, SvcId::ArbitrateUnlock],
+            check: arbitrate_misaligned_check
+        },
+        TestCase {
+            name: "get_idle_tick_count",
+            payload: get_idle_tick_count_payload,
+            svcs: vec![SvcId::GetInfo],
+            check: get_idle_tick_count_check
+        },
+        TestCase {
+            name: "create_process",
+            payload: create_process_payload,
+            svcs: vec![SvcId::CreateProcess, SvcId::GetProcessInfo],
+            check: create_process_check
+        },
+        TestCase {
+            name: "map_process_memory",
+            payload: map_process_memory_payload,
+            svcs: vec![SvcId::CreateProcess, SvcId::MapProcessMemory, SvcId::UnmapProcessMemory],
+            check: map_process_memory_check
+        },
+        TestCase {
+            name: "flush_data_cache",
+            payload: flush_data_cache_payload,
+            svcs: vec![SvcId::FlushEntireDataCache, SvcId::FlushDataCache, SvcId::FlushProcessDataCache],
+            check: flush_data_cache_check
+        },
+        TestCase {
+            name: "query_memory",
+            payload: query_memory_payload,
+            svcs: vec![SvcId::QueryMemory],
+            check: query_memory_check
+        }
+    ]
+}
+
+// Built-in host test cases
+
+const CONDVAR_STRESS_THREAD_COUNT: usize = 4;
+const CONDVAR_STRESS_ITEM_COUNT: usize = 0x100;
+const CONDVAR_STRESS_QUEUE_CAPACITY: usize = 4;
+const CONDVAR_STRESS_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct StressQueue {
+    items: VecDeque<u64>,
+    consumed_count: usize,
+    consumed_sum: u64
+}
+
+// Bounded producer/consumer queue, with consumers waiting for items and producers waiting for free space
+fn condition_variable_stress_run() -> std::result::Result<(), String> {
+    // Note: Shared objects aren't meant to be contended outside the critical section, hence a plain mutex here
+    let queue = Arc::new(Mutex::new(StressQueue {
+        items: VecDeque::new(),
+        consumed_count: 0,
+        consumed_sum: 0
+    }));
+    let not_empty = KConditionVariable::new();
+    let not_full = KConditionVariable::new();
+
+    let thread_queue = queue.clone();
+    run_host_threads("condition_variable_stress", CONDVAR_STRESS_THREAD_COUNT * 2, move |i| {
+        let is_producer = i < CONDVAR_STRESS_THREAD_COUNT;
+        for j in 0..CONDVAR_STRESS_ITEM_COUNT {
+            let mut queue_ref = thread_queue.lock();
+            if is_producer {
+                while queue_ref.items.len() >= CONDVAR_STRESS_QUEUE_CAPACITY {
+                    not_full.wait(queue_ref, None);
+                    queue_ref = thread_queue.lock();
+                }
+
+                queue_ref.items.push_back((i * CONDVAR_STRESS_ITEM_COUNT + j) as u64);
+                drop(queue_ref);
+                not_empty.notify_one();
+            }
+            else {
+                while queue_ref.items.is_empty() {
+                    not_empty.wait(queue_ref, None);
+                    queue_ref = thread_queue.lock();
+                }
+
+                let item = queue_ref.items.pop_front().unwrap();
+                queue_ref.consumed_count += 1;
+                queue_ref.consumed_sum += item;
+                drop(queue_ref);
+                not_full.notify_one();
+            }
+        }
+    }, CONDVAR_STRESS_TIMEOUT)?;
+
+    let total_count = CONDVAR_STRESS_THREAD_COUNT * CONDVAR_STRESS_ITEM_COUNT;
+    let expected_sum = (total_count * (total_count - 1) / 2) as u64;
+    let queue_ref = queue.lock();
+    if (queue_ref.consumed_count != total_count) || (queue_ref.consumed_sum != expected_sum) || !queue_ref.items.is_empty() {
+        return Err(format!("expected {} items consumed (sum {}), got {} (sum {}) with {} left", total_count, expected_sum, queue_ref.consumed_count, queue_ref.consumed_sum, queue_ref.items.len()));
+    }
+
+    Ok(())
+}
+
+const CONDVAR_WAIT_TIMEOUT: Duration = Duration::from_millis(10);
+
+fn condition_variable_timeout_run() -> std::result::Result<(), String> {
+    let lock = Mutex::new(());
+    let cv = KConditionVariable::new();
+    let elapsed = Arc::new(Mutex::new(Duration::ZERO));
+
+    let thread_cv = cv.clone();
+    let thread_elapsed = elapsed.clone();
+    run_host_threads("condition_variable_timeout", 1, move |_| {
+        // Nobody notifies it, thus only the timeout can wake it up
+        let start = Instant::now();
+        thread_cv.wait(lock.lock(), Some(CONDVAR_WAIT_TIMEOUT));
+        *thread_elapsed.lock() = start.elapsed();
+    }, DEFAULT_TIMEOUT)?;
+
+    let elapsed = *elapsed.lock();
+    if elapsed < CONDVAR_WAIT_TIMEOUT {
+        return Err(format!("woke up after {:?}, before the timeout", elapsed));
+    }
+    if cv.get_waiting_thread_count() != 0 {
+        return Err(String::from("the timed out thread is still waiting"));
+    }
+
+    Ok(())
+}
+
+const TEST_PORT_NAME: &[u8] = b"pg:test\0";
+const TEST_REQUEST_VALUE: u32 = 0x12345678;
+const TEST_REPLY_VALUE: u32 = 0x87654321;
+
+// Writes a raw IPC message with a single data word on the TLR (x21 and x22 are used as scratch registers)
+fn write_single_word_message(builder: PayloadBuilder, value: u32) -> PayloadBuilder {
+    builder.read_tlr_address(21)
+        .mov_imm(22, 0)
+        .store_w(22, 21, 0)
+        .mov_imm(22, 1)
+        .store_w(22, 21, 4)
+        .mov_imm(22, value as u64)
+        .store_w(22, 21, 8)
+}
+
+// Serves a single request on a named port, storing the received data word
+fn port_server_payload() -> (ModuleMemory, u64) {
+    let mut builder = PayloadBuilder::new();
+    let port_name_addr = builder.push_data(TEST_PORT_NAME);
+    let port_handle_addr = builder.reserve_data(4);
+    let session_handle_addr = builder.reserve_data(4);
+    let received_value_addr = builder.reserve_data(4);
+
+    builder = builder.mov_imm(1, port_name_addr)
+        .mov_imm(2, 1)
+        .svc(SvcId::ManageNamedPort)
+        .mov_imm(20, port_handle_addr)
+        .store_w(1, 20, 0)
+        .mov_imm(1, port_handle_addr)
+        .mov_imm(2, 1)
+        .mov_imm(3, u64::MAX)
+        .svc(SvcId::WaitSynchronization)
+        .mov_imm(20, port_handle_addr)
+        .load_w(1, 20, 0)
+        .svc(SvcId::AcceptSession)
+        .mov_imm(20, session_handle_addr)
+        .store_w(1, 20, 0)
+        .mov_imm(1, session_handle_addr)
+        .mov_imm(2, 1)
+        .mov_imm(3, svc::INVALID_HANDLE as u64)
+        .mov_imm(4, u64::MAX)
+        .svc(SvcId::ReplyAndReceive)
+        .read_tlr_address(21)
+        .load_w(22, 21, 8)
+        .mov_imm(20, received_value_addr)
+        .store_w(22, 20, 0);
+
+    // Reply without waiting for anything else afterwards, thus timing out right away
+    builder = write_single_word_message(builder, TEST_REPLY_VALUE)
+        .mov_imm(1, session_handle_addr)
+        .mov_imm(2, 0)
+        .mov_imm(20, session_handle_addr)
+        .load_w(3, 20, 0)
+        .mov_imm(4, 0)
+        .svc(SvcId::ReplyAndReceive)
+        .mov_imm(20, session_handle_addr)
+        .load_w(0, 20, 0)
+        .svc(SvcId::CloseHandle)
+        .mov_imm(20, port_handle_addr)
+        .load_w(0, 20, 0)
+        .svc(SvcId::CloseHandle);
+
+    (builder.build(), received_value_addr)
+}
+
+// Sends a single request to the named port, storing the data word of the reply
+fn port_client_payload() -> (ModuleMemory, u64) {
+    let mut builder = PayloadBuilder::new();
+    let port_name_addr = builder.push_data(TEST_PORT_NAME);
+    let reply_value_addr = builder.reserve_data(4);
+
+    builder = builder.mov_imm(1, port_name_addr)
+        .svc(SvcId::ConnectToNamedPort)
+        .mov_reg_w(19, 1);
+
+    builder = write_single_word_message(builder, TEST_REQUEST_VALUE)
+        .mov_reg_w(0, 19)
+        .svc(SvcId::SendSyncRequest)
+        .read_tlr_address(21)
+        .load_w(22, 21, 8)
+        .mov_imm(20, reply_value_addr)
+        .store_w(22, 20, 0)
+        .mov_reg_w(0, 19)
+        .svc(SvcId::CloseHandle);
+
+    (builder.build(), reply_value_addr)
+}
+
+// Two guest processes (each with its own CPU context) running at once and talking through a named port
+fn named_port_processes_run() -> std::result::Result<(), String> {
+    let (server_module, received_value_addr) = port_server_payload();
+    let (client_module, reply_value_addr) = port_client_payload();
+
+    let server = start_payload("named_port_server", server_module, vec![SvcId::ManageNamedPort, SvcId::WaitSynchronization, SvcId::AcceptSession, SvcId::ReplyAndReceive, SvcId::CloseHandle]).map_err(|rc| format!("unable to start the server: {0} ({0:?})", rc))?;
+    server.wait_for_svc_call(SvcId::ManageNamedPort, DEFAULT_TIMEOUT).map_err(|_| String::from("the server didn't register its port"))?;
+
+    let client = start_payload("named_port_client", client_module, vec![SvcId::ConnectToNamedPort, SvcId::SendSyncRequest, SvcId::CloseHandle]).map_err(|rc| format!("unable to start the client: {0} ({0:?})", rc))?;
+    let client_output = client.wait(DEFAULT_TIMEOUT).map_err(|rc| format!("the client didn't finish: {0} ({0:?})", rc))?;
+    let server_output = server.wait(DEFAULT_TIMEOUT).map_err(|rc| format!("the server didn't finish: {0} ({0:?})", rc))?;
+
+    expect_svc_calls(&client_output, &[(SvcId::ConnectToNamedPort, ResultSuccess::get_value()), (SvcId::SendSyncRequest, ResultSuccess::get_value()), (SvcId::CloseHandle, ResultSuccess::get_value())]).map_err(|msg| format!("client: {}", msg))?;
+    expect_svc_calls(&server_output, &[(SvcId::ManageNamedPort, ResultSuccess::get_value()), (SvcId::WaitSynchronization, ResultSuccess::get_value()), (SvcId::AcceptSession, ResultSuccess::get_value()), (SvcId::ReplyAndReceive, ResultSuccess::get_value()), (SvcId::ReplyAndReceive, kern_result::ResultTimedOut::get_value()), (SvcId::CloseHandle, ResultSuccess::get_value()), (SvcId::CloseHandle, ResultSuccess::get_value())]).map_err(|msg| format!("server: {}", msg))?;
+
+    // Each process must have only touched its own memory (both payloads use the same addresses)
+    match server_output.read_memory_val::<u32>(received_value_addr) {
+        Some(TEST_REQUEST_VALUE) => {},
+        value => return Err(format!("the server received {:X?} instead of the request", value))
+    };
+    match client_output.read_memory_val::<u32>(reply_value_addr) {
+        Some(TEST_REPLY_VALUE) => Ok(()),
+        value => Err(format!("the client received {:X?} instead of the reply", value))
+    }
+}
+
+fn expect_thread_state(thread: &Shared<KThread>, expected_state: ThreadState, expected_force_paused: bool, step: &str) -> std::result::Result<(), String> {
+    let (state, is_force_paused) = {
+        let thread_ref = thread.get();
+        (thread_ref.state, thread_ref.is_force_paused())
+    };
+    if (state != expected_state) || (is_force_paused != expected_force_paused) {
+        return Err(format!("{}: expected {:?} (force-paused: {}), got {:?} (force-paused: {})", step, expected_state, expected_force_paused, state, is_force_paused));
+    }
+
+    Ok(())
+}
+
+// Suspensions stack and are only lifted all together, while termination overrides all of them (as in HOS)
+// Note: the thread is never started, thus only its state (and not its scheduling) is checked
+fn thread_force_pause_run() -> std::result::Result<(), String> {
+    let mut thread = KThread::new_host(None, String::from("test.thread_force_pause.HostThread"), 44, 0).map_err(|rc| format!("unable to create host thread: {0} ({0:?})", rc))?;
+
+    KThread::suspend(&mut thread, ThreadState::ProcessSuspended);
+    expect_thread_state(&thread, ThreadState::ProcessSuspended, true, "process suspension")?;
+
+    KThread::suspend(&mut thread, ThreadState::DebugSuspended);
+    expect_thread_state(&thread, ThreadState::ProcessSuspended.with_flags(ThreadState::DebugSuspended), true, "debug suspension on top")?;
+
+    KThread::resume(&mut thread, ThreadState::ProcessSuspended);
+    expect_thread_state(&thread, ThreadState::DebugSuspended, true, "process resumed while debug-suspended")?;
+
+    KThread::resume(&mut thread, ThreadState::DebugSuspended);
+    expect_thread_state(&thread, ThreadState::Initialized, false, "debug resumed")?;
+
+    // Resuming something which wasn't suspended changes nothing
+    KThread::resume(&mut thread, ThreadState::InitSuspended);
+    expect_thread_state(&thread, ThreadState::Initialized, false, "init resumed without suspension")?;
+
+    KThread::suspend(&mut thread, ThreadState::ThreadSuspended);
+    KThread::suspend(&mut thread, ThreadState::InitSuspended);
+    expect_thread_state(&thread, ThreadState::ThreadSuspended.with_flags(ThreadState::InitSuspended), true, "thread and init suspension")?;
+
+    KThread::request_termination(&mut thread);
+    expect_thread_state(&thread, ThreadState::Initialized, false, "termination requested")?;
+    if !thread.get().is_suspend_requested(ThreadState::ThreadSuspended) {
+        return Err(String::from("termination dropped the thread suspension request"));
+    }
+
+    // Suspending a thread about to terminate has no effect anymore
+    KThread::suspend(&mut thread, ThreadState::ProcessSuspended);
+    expect_thread_state(&thread, ThreadState::Initialized, false, "process suspension after termination")
+}
+
+const THREAD_ACTIVITY_SLEEP_NS: u64 = 10_000_000;
+
+// The other thread keeps counting (sleeping a bit on each iteration) until it's told to stop, while the main thread pauses it, checks that the count doesn't change anymore and resumes it
+fn thread_activity_payload() -> (ModuleMemory, u64, u64) {
+    let mut builder = PayloadBuilder::new();
+    // Counter, stop flag, pause result, count after pausing, count a while later, resume result
+    let data_addr = builder.reserve_data(6 * 4);
+
+    builder = builder.mov_reg_w(19, 0)
+        .mov_imm(20, data_addr)
+        .mov_imm(0, THREAD_ACTIVITY_SLEEP_NS)
+        .svc(SvcId::SleepThread)
+        .mov_reg_w(0, 19)
+        .mov_imm(1, svc::ThreadActivity::Paused as u64)
+        .svc(SvcId::SetThreadActivity)
+        .store_w(0, 20, 8)
+        .mov_imm(0, THREAD_ACTIVITY_SLEEP_NS)
+        .svc(SvcId::SleepThread)
+        .load_w(21, 20, 0)
+        .store_w(21, 20, 12)
+        .mov_imm(0, THREAD_ACTIVITY_SLEEP_NS)
+        .svc(SvcId::SleepThread)
+        .load_w(21, 20, 0)
+        .store_w(21, 20, 16)
+        .mov_reg_w(0, 19)
+        .mov_imm(1, svc::ThreadActivity::Runnable as u64)
+        .svc(SvcId::SetThreadActivity)
+        .store_w(0, 20, 20);
+
+    // Wait for the resumed thread to count again, then tell it to stop
+    let wait_resumed_addr = builder.get_next_address();
+    builder = builder.mov_imm(0, 1_000_000)
+        .svc(SvcId::SleepThread)
+        .load_w(22, 20, 0)
+        .cmp_w(22, 21)
+        .branch_if_equal(wait_resumed_addr)
+        .mov_imm(22, 1)
+        .store_w(22, 20, 4);
+    let main_end_addr = builder.get_next_address();
+    builder = builder.nop();
+
+    let counter_thread_addr = builder.get_next_address();
+    builder = builder.mov_imm(20, data_addr);
+    let count_addr = builder.get_next_address();
+    builder = builder.load_w(21, 20, 0)
+        .add_imm_w(21, 21, 1)
+        .store_w(21, 20, 0)
+        .mov_imm(0, 1_000_000)
+        .svc(SvcId::SleepThread)
+        .load_w(22, 20, 4)
+        .branch_if_zero_w(22, count_addr);
+
+    // Both threads finish once they reach the end of the text region
+    let end_addr = builder.get_next_address();
+    builder.patch_branch(main_end_addr, end_addr);
+    (builder.build(), counter_thread_addr, data_addr)
+}
+
+fn thread_activity_pause_resume_run() -> std::result::Result<(), String> {
+    let (module, counter_thread_addr, data_addr) = thread_activity_payload();
+    let threads = [PayloadThread { entry_address: counter_thread_addr, priority: 44 }];
+
+    let payload = start_payload_with_threads("thread_activity_pause_resume", module, vec![SvcId::SleepThread, SvcId::SetThreadActivity], &threads).map_err(|rc| format!("unable to start the payload: {0} ({0:?})", rc))?;
+    let output = payload.wait(DEFAULT_TIMEOUT).map_err(|rc| format!("the payload didn't finish: {0} ({0:?})", rc))?;
+
+    let read_val = |offset: u64| output.read_memory_val::<u32>(data_addr + offset).ok_or_else(|| format!("unable to read the payload data at offset {:#X}", offset));
+    for &(offset, activity) in [(8, "pausing"), (20, "resuming")].iter() {
+        let rc = ResultCode::new(read_val(offset)?);
+        if rc.is_failure() {
+            return Err(format!("{} the thread failed: {}", activity, rc.describe()));
+        }
+    }
+
+    let (count_after_pause, count_later) = (read_val(12)?, read_val(16)?);
+    if count_after_pause == 0 {
+        return Err(String::from("the thread never got to count before being paused"));
+    }
+    if count_later != count_after_pause {
+        return Err(format!("the paused thread kept counting ({} -> {})", count_after_pause, count_later));
+    }
+
+    Ok(())
+}
+
+// Yield timeouts of SleepThread
+const YIELD_TIMEOUTS: [i64; 3] = [0, -1, -2];
+
+// Both threads have the same priority and core, thus the other thread (which keeps counting and yielding until it's told to stop) only gets to count again if the main thread's yields let it run
+fn yield_handoff_payload() -> (ModuleMemory, u64, u64) {
+    let mut builder = PayloadBuilder::new();
+    // Counter, stop flag, yield variants the other thread ran on
+    let data_addr = builder.reserve_data(3 * 4);
+
+    builder = builder.mov_imm(20, data_addr);
+    for (i, &timeout) in YIELD_TIMEOUTS.iter().enumerate() {
+        builder = builder.load_w(21, 20, 0);
+        let yield_addr = builder.get_next_address();
+        builder = builder.mov_imm(0, timeout as u64)
+            .svc(SvcId::SleepThread)
+            .load_w(22, 20, 0)
+            .cmp_w(22, 21)
+            .branch_if_equal(yield_addr)
+            .mov_imm(22, (i + 1) as u64)
+            .store_w(22, 20, 8);
+    }
+    builder = builder.mov_imm(22, 1)
+        .store_w(22, 20, 4);
+    let main_end_addr = builder.get_next_address();
+    builder = builder.nop();
+
+    let counter_thread_addr = builder.get_next_address();
+    builder = builder.mov_imm(20, data_addr);
+    let count_addr = builder.get_next_address();
+    builder = builder.load_w(21, 20, 0)
+        .add_imm_w(21, 21, 1)
+        .store_w(21, 20, 0)
+        .mov_imm(0, 0)
+        .svc(SvcId::SleepThread)
+        .load_w(22, 20, 4)
+        .branch_if_zero_w(22, count_addr);
+
+    let end_addr = builder.get_next_address();
+    builder.patch_branch(main_end_addr, end_addr);
+    (builder.build(), counter_thread_addr, data_addr)
+}
+
+fn yield_thread_handoff_run() -> std::result::Result<(), String> {
+    let (module, counter_thread_addr, data_addr) = yield_handoff_payload();
+    let threads = [PayloadThread { entry_address: counter_thread_addr, priority: 44 }];
+
+    let payload = start_payload_with_threads("yield_thread_handoff", module, vec![SvcId::SleepThread], &threads).map_err(|rc| format!("unable to start the payload: {0} ({0:?})", rc))?;
+    let output = payload.wait(DEFAULT_TIMEOUT).map_err(|rc| format!("the payload didn't finish (yielding never let the other thread run): {0} ({0:?})", rc))?;
+
+    match output.read_memory_val::<u32>(data_addr + 8) {
+        Some(count) if count as usize == YIELD_TIMEOUTS.len() => {},
+        count => return Err(format!("the other thread only ran after {:?} of the {} yield variants", count, YIELD_TIMEOUTS.len()))
+    };
+    match output.get_svc_calls().into_iter().find(|&(_, rc)| rc != ResultSuccess::get_value()) {
+        Some((svc_id, rc)) => Err(format!("{:?} failed: {}", svc_id, ResultCode::new(rc).describe())),
+        None => Ok(())
+    }
+}
+
+const MUTEX_OWNER_PRIORITY: i32 = 44;
+const MUTEX_WAITER_PRIORITY: i32 = 28;
+
+// The main thread takes the mutex and waits until the (higher priority) waiter blocks on it and boosts it, then unlocks it, handing it to the waiter
+fn mutex_priority_inheritance_payload() -> (ModuleMemory, u64, u64) {
+    let mut builder = PayloadBuilder::new();
+    // Mutex, owner priority before locking, owner priority after unlocking, handoff done flag, mutex value after the handoff, waiter handle
+    let data_addr = builder.reserve_data(6 * 4);
+
+    builder = builder.mov_reg_w(19, 1)
+        .mov_imm(20, data_addr)
+        .mov_imm(1, svc::CURRENT_THREAD_PSEUDO_HANDLE as u64)
+        .svc(SvcId::GetThreadPriority)
+        .store_w(1, 20, 4)
+        .store_w(19, 20, 0)
+        .mov_imm(21, MUTEX_WAITER_PRIORITY as u64);
+    let wait_boost_addr = builder.get_next_address();
+    builder = builder.mov_imm(0, 1_000_000)
+        .svc(SvcId::SleepThread)
+        .mov_imm(1, svc::CURRENT_THREAD_PSEUDO_HANDLE as u64)
+        .svc(SvcId::GetThreadPriority)
+        .cmp_w(1, 21)
+        .branch_if_not_equal(wait_boost_addr)
+        .mov_imm(0, data_addr)
+        .svc(SvcId::ArbitrateUnlock)
+        .mov_imm(1, svc::CURRENT_THREAD_PSEUDO_HANDLE as u64)
+        .svc(SvcId::GetThreadPriority)
+        .store_w(1, 20, 8);
+    let wait_handoff_addr = builder.get_next_address();
+    builder = builder.mov_imm(0, 1_000_000)
+        .svc(SvcId::SleepThread)
+        .load_w(22, 20, 12)
+        .branch_if_zero_w(22, wait_handoff_addr);
+    let main_end_addr = builder.get_next_address();
+    builder = builder.nop();
+
+    // Like userland mutexes, the waiter flags the mutex as contended before waiting on it
+    let waiter_thread_addr = builder.get_next_address();
+    builder = builder.mov_reg_w(19, 0)
+        .mov_reg_w(23, 1)
+        .mov_imm(20, data_addr);
+    let wait_owned_addr = builder.get_next_address();
+    builder = builder.mov_imm(0, 1_000_000)
+        .svc(SvcId::SleepThread)
+        .load_w(21, 20, 0)
+        .branch_if_zero_w(21, wait_owned_addr)
+        .mov_imm(22, svc::MUTEX_HAS_WAITERS_FLAG as u64)
+        .orr_w(22, 19, 22)
+        .store_w(22, 20, 0)
+        .mov_reg_w(0, 19)
+        .mov_imm(1, data_addr)
+        .mov_reg_w(2, 23)
+        .svc(SvcId::ArbitrateLock)
+        .load_w(21, 20, 0)
+        .store_w(21, 20, 16)
+        .store_w(23, 20, 20)
+        .mov_imm(22, 1)
+        .store_w(22, 20, 12);
+
+    let end_addr = builder.get_next_address();
+    builder.patch_branch(main_end_addr, end_addr);
+    (builder.build(), waiter_thread_addr, data_addr)
+}
+
+fn mutex_priority_inheritance_run() -> std::result::Result<(), String> {
+    let (module, waiter_thread_addr, data_addr) = mutex_priority_inheritance_payload();
+    let threads = [PayloadThread { entry_address: waiter_thread_addr, priority: MUTEX_WAITER_PRIORITY }];
+
+    let payload = start_payload_with_threads("mutex_priority_inheritance", module, vec![SvcId::SleepThread, SvcId::GetThreadPriority, SvcId::ArbitrateLock, SvcId::ArbitrateUnlock], &threads).map_err(|rc| format!("unable to start the payload: {0} ({0:?})", rc))?;
+    // Note: if the waiter never boosts the owner, the owner keeps waiting for it until this times out
+    let output = payload.wait(DEFAULT_TIMEOUT).map_err(|rc| format!("the payload didn't finish (the owner's priority was never raised): {0} ({0:?})", rc))?;
+
+    if let Some((svc_id, rc)) = output.get_svc_calls().into_iter().find(|&(_, rc)| rc != ResultSuccess::get_value()) {
+        return Err(format!("{:?} failed: {}", svc_id, ResultCode::new(rc).describe()));
+    }
+
+    let read_val = |offset: u64| output.read_memory_val::<u32>(data_addr + offset).ok_or_else(|| format!("unable to read the payload data at offset {:#X}", offset));
+    let (initial_priority, restored_priority) = (read_val(4)? as i32, read_val(8)? as i32);
+    if initial_priority != MUTEX_OWNER_PRIORITY {
+        return Err(format!("the owner started with priority {} instead of {}", initial_priority, MUTEX_OWNER_PRIORITY));
+    }
+    if restored_priority != MUTEX_OWNER_PRIORITY {
+        return Err(format!("the owner's priority wasn't restored after unlocking: {} instead of {}", restored_priority, MUTEX_OWNER_PRIORITY));
+    }
+
+    // The waiter was the only one, thus the mutex is handed to it without the waiters flag
+    let (handoff_value, waiter_handle) = (read_val(16)?, read_val(20)?);
+    if handoff_value != waiter_handle {
+        return Err(format!("the mutex was handed off as {:#X} instead of the waiter's handle {:#X}", handoff_value, waiter_handle));
+    }
+
+    Ok(())
+}
+
+fn bsd_address_allowlist_run() -> std::result::Result<(), String> {
+    let addr = SocketAddrV4::new(Ipv4Addr::LOCALHOST, 8080);
+    let mut addr_data = [0u8; 0x10];
+    SockAddrIn::from_socket_addr(addr).write_bytes(&mut addr_data);
+    let read_addr = SockAddrIn::from_bytes(&addr_data).map_err(|errno| format!("unable to read the written address: {:?}", errno))?.to_socket_addr();
+    if read_addr != addr {
+        return Err(format!("expected {} to be read back, got {}", addr, read_addr));
+    }
+
+    let prev_allowed_hosts = cfg::get_config().network_allowed_hosts.take();
+    let check_allowed = |allowed_hosts: &[&str], addr: SocketAddrV4| {
+        cfg::get_config().network_allowed_hosts = Some(allowed_hosts.iter().map(|host| String::from(*host)).collect());
+        bsd::is_address_allowed(addr)
+    };
+    let results = [
+        (check_allowed(&["127.0.0.1:8080"], addr), true),
+        (check_allowed(&["127.0.0.1:8081"], addr), false),
+        (check_allowed(&["127.0.0.1"], addr), true),
+        (check_allowed(&["10.0.0.1", "127.0.0.1:8080"], addr), true),
+        (check_allowed(&[], addr), false)
+    ];
+    cfg::get_config().network_allowed_hosts = prev_allowed_hosts;
+
+    for (i, (allowed, expected_allowed)) in results.iter().enumerate() {
+        if allowed != expected_allowed {
+            return Err(format!("allowlist check {}: expected {}, got {}", i, expected_allowed, allowed));
+        }
+    }
+
+    Ok(())
+}
+
+const SVC_DETECTION_LOOP_ITERATION_COUNT: u64 = 0x200000;
+
+// A (long) loop, so that the overhead of hooking every instruction shows up, with a cycle counter read (emulated through a code hook in both modes) and some SVCs around it
+fn svc_detection_payload() -> ModuleMemory {
+    let mut builder = PayloadBuilder::new();
+    let cycle_count_addr = builder.reserve_data(4);
+
+    builder.mov_imm(6, SVC_DETECTION_LOOP_ITERATION_COUNT)
+        // SUBS X6, X6, #1
+        .insn(0xF10004C6)
+        // B.NE <the SUBS above>
+        .insn(0x54FFFFE1)
+        .mov_imm(0, 0xBAD)
+        .svc(SvcId::CloseHandle)
+        .read_cycle_counter(4)
+        .mov_imm(5, cycle_count_addr)
+        .store_w(4, 5, 0)
+        .mov_imm(0, 0xBAD)
+        .svc(SvcId::CloseHandle)
+        .build()
+}
+
+fn svc_detection_modes_run() -> std::result::Result<(), String> {
+    let prev_mode = cfg::get_config().svc_detection_mode;
+    let mut run_times: Vec<(cfg::SvcDetectionMode, Duration)> = Vec::new();
+    let mut run_error: Option<String> = None;
+    for mode in [cfg::SvcDetectionMode::CodeHook, cfg::SvcDetectionMode::Interrupt].iter() {
+        // Note: the mode is applied when the payload's execution context gets created
+        cfg::get_config().svc_detection_mode = *mode;
+        let start = Instant::now();
+        let output = match run_payload("svc_detection", svc_detection_payload(), vec![SvcId::CloseHandle], Duration::from_secs(60)) {
+            Ok(output) => output,
+            Err(rc) => {
+                run_error = Some(format!("{:?}: unable to run payload: {1} ({1:?})", mode, rc));
+                break;
+            }
+        };
+        run_times.push((*mode, start.elapsed()));
+
+        let invalid_handle_rc = kern_result::ResultInvalidHandle::get_value();
+        if let Err(msg) = expect_svc_calls(&output, &[(SvcId::CloseHandle, invalid_handle_rc), (SvcId::CloseHandle, invalid_handle_rc)]) {
+            run_error = Some(format!("{:?}: {}", mode, msg));
+            break;
+        }
+        if let Some(0) | None = output.read_memory_val::<u32>(DATA_ADDRESS) {
+            run_error = Some(format!("{:?}: the cycle counter read wasn't emulated", mode));
+            break;
+        }
+    }
+    cfg::get_config().svc_detection_mode = prev_mode;
+
+    if let Some(msg) = run_error {
+        return Err(msg);
+    }
+
+    // Timings depend on the host (and whether instructions are counted anyway, see cfg::SvcDetectionMode), thus they're just reported
+    for (mode, run_time) in run_times.iter() {
+        log_line!("[harness] svc_detection_modes: {:?} took {:?}", mode, run_time);
+    }
+    if let [(_, code_hook_time), (_, interrupt_time)] = run_times.as_slice() {
+        log_line!("[harness] svc_detection_modes: speedup of {:.2}x", code_hook_time.as_secs_f64() / interrupt_time.as_secs_f64().max(f64::EPSILON));
+    }
+
+    Ok(())
+}
+
+// A "slim" TZif file (like recent tzdata ones), without any transitions, thus relying on its footer for DST
+fn make_test_tzif() -> Vec<u8> {
+    let mut header = Vec::new();
+    header.extend_from_slice(b"TZif2");
+    header.resize(0x14, 0);
+    for count in [0u32, 0, 0, 0, 1, 4].iter() {
+        header.extend_from_slice(&count.to_be_bytes());
+    }
+
+    let mut data = Vec::new();
+    data.extend_from_slice(&3600u32.to_be_bytes());
+    data.extend_from_slice(&[0, 0]);
+    data.extend_from_slice(b"CET\0");
+
+    let mut tzif = Vec::new();
+    for _ in 0..2 {
+        tzif.extend_from_slice(&header);
+        tzif.extend_from_slice(&data);
+    }
+    tzif.extend_from_slice(b"\nCET-1CEST,M3.5.0,M10.5.0/3\n");
+    tzif
+}
+
+fn time_zone_conversion_run() -> std::result::Result<(), String> {
+    let rule = TimeZoneRule::from_tzif(&make_test_tzif()).map_err(|rc| format!("unable to parse the TZif data: {0} ({0:?})", rc))?;
+    let rule = TimeZoneRule::from_bytes(&rule.to_bytes()).map_err(|rc| format!("unable to read back the rule: {0} ({0:?})", rc))?;
+
+    // (posix time, expected local (hour, minute, second), DST)
+    let cases = [
+        // 2021-01-15 12:00:00 UTC
+        (1610712000, (13, 0, 0), false),
+        // 2021-07-01 12:00:00 UTC
+        (1625140800, (14, 0, 0), true),
+        // Right before/after DST starts (2021-03-28 01:00:00 UTC)
+        (1616893199, (1, 59, 59), false),
+        (1616893200, (3, 0, 0), true)
+    ];
+    for (posix_time, (hour, minute, second), is_dst) in cases.iter() {
+        let (calendar_time, additional_info) = rule.to_calendar_time(*posix_time).map_err(|rc| format!("unable to convert {}: {1} ({1:?})", posix_time, rc))?;
+        let local_time = (calendar_time.hour as i32, calendar_time.minute as i32, calendar_time.second as i32);
+        if (local_time != (*hour, *minute, *second)) || ((additional_info.is_dst != 0) != *is_dst) {
+            return Err(format!("{}: expected {:?} (DST: {}), got {:?} ({:?})", posix_time, (hour, minute, second), is_dst, calendar_time, additional_info));
+        }
+    }
+
+    // 2021-07-01 was a Thursday, the 182nd day of the year
+    let (calendar_time, additional_info) = rule.to_calendar_time(1625140800).map_err(|rc| format!("unable to convert: {0} ({0:?})", rc))?;
+    if (calendar_time.year, calendar_time.month, calendar_time.day, additional_info.day_of_week, additional_info.day_of_year) != (2021, 7, 1, 4, 181) {
+        return Err(format!("unexpected date: {:?} ({:?})", calendar_time, additional_info));
+    }
+
+    Ok(())
+}
+
+fn disassembly_run() -> std::result::Result<(), String> {
+    // NOP, then an undefined (UDF #0) instruction which must still be shown (as raw data)
+    let mut code: Vec<u8> = Vec::new();
+    code.extend_from_slice(&0xD503201Fu32.to_le_bytes());
+    code.extend_from_slice(&0x00000000u32.to_le_bytes());
+    let insns = disasm::disassemble(InstructionSet::A64, TEXT_ADDRESS, &code);
+    if insns.len() != 2 {
+        return Err(format!("expected 2 A64 instructions, got {}", insns.len()));
+    }
+    if (insns[0].address != TEXT_ADDRESS) || (insns[0].text != "nop") || (insns[0].format_data() != "d503201f") {
+        return Err(format!("unexpected first A64 instruction: {:?}", insns[0]));
+    }
+    if insns[1].address != TEXT_ADDRESS + 4 {
+        return Err(format!("unexpected second A64 instruction: {:?}", insns[1]));
+    }
+
+    // T32: a 16-bit NOP followed by a 32-bit BL, and a truncated 32-bit instruction which isn't disassembled
+    let thumb_code: [u8; 8] = [0x00, 0xBF, 0x00, 0xF0, 0x00, 0xF8, 0x00, 0xF0];
+    let thumb_sizes: Vec<usize> = disasm::disassemble(InstructionSet::T32, TEXT_ADDRESS, &thumb_code).iter().map(|insn| insn.data.len()).collect();
+    if thumb_sizes != [2, 4] {
+        return Err(format!("unexpected T32 instruction sizes: {:?}", thumb_sizes));
+    }
+
+    let lines = disasm::format_instructions(&insns, Some(TEXT_ADDRESS + 4), |address| format!("test+{:#x}", address - TEXT_ADDRESS));
+    if !lines[0].starts_with("  ") || !lines[0].contains("(test+0x0)") || !lines[1].starts_with("->") {
+        return Err(format!("unexpected formatted instructions: {:?}", lines));
+    }
+
+    match (disasm::parse_trace_range("0x7100000000-7100000100"), disasm::parse_trace_range("0x100-0x100"), disasm::parse_trace_range("0x100")) {
+        (Some((0x7100000000, 0x7100000100)), None, None) => Ok(()),
+        ranges => Err(format!("unexpected parsed trace ranges: {:?}", ranges))
+    }
+}
+
+// Fixed A64 corpus which every CPU backend must run the same way (see emu::cpu::backend)
+// Note: only instructions the interpreter backend supports can be used here
+fn make_backend_corpus() -> Vec<(&'static str, PayloadBuilder)> {
+    let move_wide = PayloadBuilder::new()
+        .mov_imm(0, 0x0123456789ABCDEF)
+        .insn(0x92800000 | (0x1234 << 5) | 1) // MOVN X1, #0x1234
+        .insn(0x52800000 | (1 << 21) | (0xFFFF << 5) | 2) // MOVZ W2, #0xFFFF, LSL #16
+        .insn(0x72800000 | (1 << 5) | 2) // MOVK W2, #0x1
+        .insn(0x12800000 | 3); // MOVN W3, #0
+
+    let add_sub = PayloadBuilder::new()
+        .mov_imm(0, u64::MAX)
+        .mov_imm(1, 0x7FFFFFFF)
+        .insn(0xB1000000 | (1 << 10) | 2) // ADDS X2, X0, #1
+        .insn(0x31000000 | (1 << 10) | (1 << 5) | 3) // ADDS W3, W1, #1
+        .insn(0xD1000000 | (0x10 << 10) | (31 << 5) | 4) // SUB X4, SP, #0x10
+        .insn(0x91400000 | (1 << 10) | (31 << 5) | 31) // ADD SP, SP, #1, LSL #12
+        .insn(0xCB000000 | (1 << 16) | (4 << 10) | 5) // SUB X5, X0, X1, LSL #4
+        .insn(0x6B000000 | (2 << 22) | (3 << 10) | (1 << 5) | 6) // SUBS W6, W1, W0, ASR #3
+        .insn(0x8B000000 | (1 << 22) | (1 << 16) | (63 << 10) | 7); // ADD X7, X0, X1, LSR #63
+
+    let logical = PayloadBuilder::new()
+        .mov_imm(0, 0xF0F0123456789ABC)
+        .mov_imm(1, 0x80000000FFFF0001)
+        .insn(0xAA000000 | (3 << 22) | (1 << 16) | (8 << 10) | 2) // ORR X2, X0, X1, ROR #8
+        .insn(0x8A200000 | (1 << 16) | 3) // BIC X3, X0, X1
+        .insn(0x4A000000 | (2 << 22) | (1 << 16) | (3 << 10) | 4) // EOR W4, W0, W1, ASR #3
+        .insn(0x2A200000 | (7 << 10) | (1 << 5) | 5) // ORN W5, W1, W0, LSL #7
+        .insn(0xEA000000 | (1 << 16) | 6) // ANDS X6, X0, X1
+        .mov_reg_w(7, 0);
+
+    let branches = PayloadBuilder::new()
+        .mov_imm(0, 10)
+        .mov_imm(1, 0)
+        .insn(0x91000C21) // ADD X1, X1, #3
+        .insn(0xF1000400) // SUBS X0, X0, #1
+        .insn(0x54FFFFC1) // B.NE -8
+        .insn(0xF1000000 | (30 << 10) | (1 << 5) | 31) // CMP X1, #30
+        .insn(0x5400004C) // B.GT +8
+        .insn(0x91000442) // ADD X2, X2, #1
+        .insn(0x5400004D) // B.LE +8
+        .insn(0x91000442) // ADD X2, X2, #1 (skipped)
+        .insn(0xB4000040) // CBZ X0, +8
+        .insn(0x91000442) // ADD X2, X2, #1 (skipped)
+        .insn(0x35000041) // CBNZ W1, +8
+        .insn(0x91000442) // ADD X2, X2, #1 (skipped)
+        .insn(0x94000003) // BL +12
+        .insn(0x14000004) // B +16 (the end)
+        .insn(0x91000442) // ADD X2, X2, #1 (skipped)
+        .insn(0x91000463) // ADD X3, X3, #1
+        .insn(0xD65F03C0); // RET
+
+    let mut load_store = PayloadBuilder::new();
+    let data_address = load_store.reserve_data(0x20);
+    let load_store = load_store
+        .mov_imm(0, data_address)
+        .mov_imm(1, 0x1122334455667788)
+        .insn(0xF9000000 | (1 << 10) | 1) // STR X1, [X0, #8]
+        .insn(0x39000000 | (1 << 10) | 1) // STRB W1, [X0, #1]
+        .insn(0x79000000 | (1 << 10) | 1) // STRH W1, [X0, #2]
+        .store_w(1, 0, 16)
+        .load_w(2, 0, 8)
+        .insn(0x39400000 | (1 << 10) | 3) // LDRB W3, [X0, #1]
+        .insn(0x79400000 | (5 << 10) | 4) // LDRH W4, [X0, #10]
+        .insn(0xF9400000 | (1 << 10) | 5) // LDR X5, [X0, #8]
+        .insn(0xD10043FF) // SUB SP, SP, #0x10
+        .insn(0xF9000000 | (1 << 10) | (31 << 5) | 5) // STR X5, [SP, #8]
+        .insn(0xF9400000 | (1 << 10) | (31 << 5) | 6); // LDR X6, [SP, #8]
+
+    vec![
+        ("move_wide", move_wide),
+        ("add_sub", add_sub),
+        ("logical", logical),
+        ("branches", branches),
+        ("load_store", load_store)
+    ]
+}
+
+const BACKEND_STACK_ADDRESS: u64 = 0x8200000;
+const BACKEND_STACK_SIZE: usize = 0x1000;
+// Bounds execution in case some backend gets stuck
+const BACKEND_MAX_INSTRUCTION_COUNT: usize = 0x10000;
+
+fn prepare_backend_payload(kind: BackendKind, module: &ModuleMemory) -> Result<Box<dyn backend::CpuBackend>> {
+    let mut cpu_backend = backend::create_backend(kind, Architecture::Aarch64)?;
+    cpu_backend.map_module(module)?;
+    cpu_backend.map_memory(BACKEND_STACK_ADDRESS, &vec![0; BACKEND_STACK_SIZE], MemoryPermission::READ | MemoryPermission::WRITE)?;
+    cpu_backend.write_register(Register::SP, BACKEND_STACK_ADDRESS + BACKEND_STACK_SIZE as u64)?;
+    cpu_backend.write_register(Register::PC, TEXT_ADDRESS)?;
+    Ok(cpu_backend)
+}
+
+// Registers and the contents of every writable region (the payload's data and the stack) after running the payload
+fn run_backend_payload(kind: BackendKind, module: &ModuleMemory, end_address: u64) -> Result<(RegisterState, Vec<u8>)> {
+    let mut cpu_backend = prepare_backend_payload(kind, module)?;
+    cpu_backend.run(end_address, BACKEND_MAX_INSTRUCTION_COUNT)?;
+
+    let mut memory: Vec<u8> = Vec::new();
+    let writable_ranges = module.regions.iter().filter(|region| region.perm.contains(MemoryPermission::WRITE)).map(|region| (region.address, region.len())).chain(std::iter::once((BACKEND_STACK_ADDRESS, BACKEND_STACK_SIZE)));
+    for (address, size) in writable_ranges {
+        let mut data: Vec<u8> = vec![0; size];
+        cpu_backend.read_memory(address, &mut data)?;
+        memory.extend_from_slice(&data);
+    }
+
+    Ok((RegisterState::read(cpu_backend.as_ref())?, memory))
+}
+
+fn cpu_backend_corpus_run() -> std::result::Result<(), String> {
+    for (name, builder) in make_backend_corpus() {
+        let end_address = TEXT_ADDRESS + (builder.code.len() * 4) as u64;
+        let module = builder.build();
+
+        let (unicorn_registers, unicorn_memory) = run_backend_payload(BackendKind::Unicorn, &module, end_address).map_err(|rc| format!("'{}' failed on {}: {2} ({2:?})", name, BackendKind::Unicorn, rc))?;
+        let (interpreter_registers, interpreter_memory) = run_backend_payload(BackendKind::Interpreter, &module, end_address).map_err(|rc| format!("'{}' failed on {}: {2} ({2:?})", name, BackendKind::Interpreter, rc))?;
+
+        if unicorn_registers.pc != end_address {
+            return Err(format!("'{}' didn't reach its end on {} (PC {:#X})", name, BackendKind::Unicorn, unicorn_registers.pc));
+        }
+        let differences = unicorn_registers.find_differences(&interpreter_registers);
+        if !differences.is_empty() {
+            return Err(format!("'{}' registers differ between {} and {}: {}", name, BackendKind::Unicorn, BackendKind::Interpreter, differences.join(", ")));
+        }
+        if unicorn_memory != interpreter_memory {
+            return Err(format!("'{}' memory differs between {} and {}", name, BackendKind::Unicorn, BackendKind::Interpreter));
+        }
+    }
+
+    Ok(())
+}
+
+fn cpu_backend_lockstep_run() -> std::result::Result<(), String> {
+    let compared_ranges = [(DATA_ADDRESS, 0x1000), (BACKEND_STACK_ADDRESS, BACKEND_STACK_SIZE)];
+    for (name, builder) in make_backend_corpus() {
+        let end_address = TEXT_ADDRESS + (builder.code.len() * 4) as u64;
+        let module = builder.build();
+
+        let mut unicorn_backend = prepare_backend_payload(BackendKind::Unicorn, &module).map_err(|rc| format!("unable to prepare '{}': {1} ({1:?})", name, rc))?;
+        let mut interpreter_backend = prepare_backend_payload(BackendKind::Interpreter, &module).map_err(|rc| format!("unable to prepare '{}': {1} ({1:?})", name, rc))?;
+        match lockstep::run_lockstep(unicorn_backend.as_mut(), interpreter_backend.as_mut(), end_address, BACKEND_MAX_INSTRUCTION_COUNT, &compared_ranges) {
+            Ok(LockstepOutcome::Finished { .. }) => {},
+            outcome => return Err(format!("unexpected lockstep outcome for '{}': {:?}", name, outcome))
+        };
+    }
+
+    // A value only one backend sees must be caught right at the load reading it (the 5th instruction, after the MOVZ + 3 MOVKs)
+    let mut builder = PayloadBuilder::new();
+    let value_address = builder.reserve_data(8);
+    let builder = builder.mov_imm(0, value_address).load_w(1, 0, 0).nop();
+    let end_address = TEXT_ADDRESS + (builder.code.len() * 4) as u64;
+    let module = builder.build();
+
+    let mut unicorn_backend = prepare_backend_payload(BackendKind::Unicorn, &module).map_err(|rc| format!("unable to prepare the divergent payload: {0} ({0:?})", rc))?;
+    let mut interpreter_backend = prepare_backend_payload(BackendKind::Interpreter, &module).map_err(|rc| format!("unable to prepare the divergent payload: {0} ({0:?})", rc))?;
+    interpreter_backend.write_memory(value_address, &0xCAFEu32.to_le_bytes()).map_err(|rc| format!("unable to write the divergent value: {0} ({0:?})", rc))?;
+    match lockstep::run_lockstep(unicorn_backend.as_mut(), interpreter_backend.as_mut(), end_address, BACKEND_MAX_INSTRUCTION_COUNT, &[]) {
+        Ok(LockstepOutcome::Diverged(divergence)) if (divergence.instruction_index == 4) && (divergence.pc == TEXT_ADDRESS + 0x10) && divergence.differences.iter().any(|difference| difference.starts_with("X1:")) => Ok(()),
+        outcome => Err(format!("unexpected lockstep outcome for the divergent payload: {:?}", outcome))
+    }
+}
+
+fn read_svc_output_handle(svc_ctx: &svc_env::SvcTestContext, reg: Register) -> std::result::Result<svc::Handle, String> {
+    svc_ctx.read_register(reg).map(|value| value as svc::Handle).map_err(|rc| format!("unable to read {:?}: {1} ({1:?})", reg, rc))
+}
+
+fn svc_events_run() -> std::result::Result<(), String> {
+    let env = SvcTestEnvironment::new("svc_events", vec![SvcId::CreateEvent, SvcId::SignalEvent, SvcId::ClearEvent, SvcId::ResetSignal, SvcId::CloseHandle]).map_err(|rc| format!("unable to create the environment: {0} ({0:?})", rc))?;
+    let base_handle_count = env.get_used_handle_count();
+
+    let (writable_event_handle, readable_event_handle) = env.run(|svc_ctx| {
+        expect_svc_result(SvcId::CreateEvent, svc_ctx.call_svc(SvcId::CreateEvent, &[]), ResultSuccess::make())?;
+        let writable_event_handle = read_svc_output_handle(svc_ctx, Register::X1)?;
+        let readable_event_handle = read_svc_output_handle(svc_ctx, Register::X2)?;
+
+        // Events can only be reset while signaled
+        expect_svc_result(SvcId::ResetSignal, svc_ctx.call_svc(SvcId::ResetSignal, &[(Register::X0, readable_event_handle as u64)]), kern_result::ResultInvalidState::make())?;
+        expect_svc_result(SvcId::SignalEvent, svc_ctx.call_svc(SvcId::SignalEvent, &[(Register::X0, writable_event_handle as u64)]), ResultSuccess::make())?;
+        expect_svc_result(SvcId::ResetSignal, svc_ctx.call_svc(SvcId::ResetSignal, &[(Register::X0, readable_event_handle as u64)]), ResultSuccess::make())?;
+        expect_svc_result(SvcId::ClearEvent, svc_ctx.call_svc(SvcId::ClearEvent, &[(Register::X0, readable_event_handle as u64)]), ResultSuccess::make())?;
+
+        // SVCs not enabled for the process can't be called
+        if svc_ctx.call_svc(SvcId::SleepThread, &[]).is_ok() {
+            return Err(String::from("a non-enabled SVC was called"));
+        }
+        Ok((writable_event_handle, readable_event_handle))
+    }, Duration::from_secs(5))?;
+
+    // The SVC thread's own handle is there too
+    if env.get_used_handle_count() != base_handle_count + 3 {
+        return Err(format!("expected {} used handles, got {}", base_handle_count + 3, env.get_used_handle_count()));
+    }
+
+    env.run(move |svc_ctx| {
+        expect_svc_result(SvcId::CloseHandle, svc_ctx.call_svc(SvcId::CloseHandle, &[(Register::X0, writable_event_handle as u64)]), ResultSuccess::make())?;
+        expect_svc_result(SvcId::CloseHandle, svc_ctx.call_svc(SvcId::CloseHandle, &[(Register::X0, readable_event_handle as u64)]), ResultSuccess::make())?;
+        expect_svc_result(SvcId::CloseHandle, svc_ctx.call_svc(SvcId::CloseHandle, &[(Register::X0, writable_event_handle as u64)]), kern_result::ResultInvalidHandle::make())?;
+        expect_svc_result(SvcId::SignalEvent, svc_ctx.call_svc(SvcId::SignalEvent, &[(Register::X0, writable_event_handle as u64)]), kern_result::ResultInvalidHandle::make())
+    }, Duration::from_secs(5))?;
+
+    // Only both SVC threads' handles are left
+    match env.get_used_handle_count() == base_handle_count + 2 {
+        true => Ok(()),
+        false => Err(format!("expected {} used handles, got {}", base_handle_count + 2, env.get_used_handle_count()))
+    }
+}
+
+fn svc_ports_run() -> std::result::Result<(), String> {
+    let env = SvcTestEnvironment::new("svc_ports", vec![SvcId::ManageNamedPort, SvcId::ConnectToNamedPort, SvcId::AcceptSession, SvcId::CreatePort, SvcId::ConnectToPort, SvcId::CloseHandle]).map_err(|rc| format!("unable to create the environment: {0} ({0:?})", rc))?;
+
+    env.run(|svc_ctx| {
+        let port_name_address = svc_ctx.write_scratch(0, b"svc_test\0").map_err(|rc| format!("unable to write the port name: {0} ({0:?})", rc))?;
+
+        // A named port with a single session
+        expect_svc_result(SvcId::ManageNamedPort, svc_ctx.call_svc(SvcId::ManageNamedPort, &[(Register::X1, port_name_address), (Register::X2, 1)]), ResultSuccess::make())?;
+        let server_port_handle = read_svc_output_handle(svc_ctx, Register::X1)?;
+        expect_svc_result(SvcId::ConnectToNamedPort, svc_ctx.call_svc(SvcId::ConnectToNamedPort, &[(Register::X1, port_name_address)]), ResultSuccess::make())?;
+        expect_svc_result(SvcId::ConnectToNamedPort, svc_ctx.call_svc(SvcId::ConnectToNamedPort, &[(Register::X1, port_name_address)]), kern_result::ResultOutOfSessions::make())?;
+        expect_svc_result(SvcId::AcceptSession, svc_ctx.call_svc(SvcId::AcceptSession, &[(Register::X1, server_port_handle as u64)]), ResultSuccess::make())?;
+        expect_svc_result(SvcId::AcceptSession, svc_ctx.call_svc(SvcId::AcceptSession, &[(Register::X1, server_port_handle as u64)]), kern_result::ResultNotFound::make())?;
+
+        // Invalid pointers are returned as results, not faults
+        match svc_ctx.call_svc(SvcId::ConnectToNamedPort, &[(Register::X1, 0)]) {
+            Ok(rc) if rc.is_failure() => {},
+            r => return Err(format!("unexpected ConnectToNamedPort result for an invalid pointer: {:?}", r))
+        };
+
+        // Unnamed ports
+        expect_svc_result(SvcId::CreatePort, svc_ctx.call_svc(SvcId::CreatePort, &[(Register::X2, 2), (Register::X3, 0), (Register::X4, 0)]), ResultSuccess::make())?;
+        let client_port_handle = read_svc_output_handle(svc_ctx, Register::X2)?;
+        expect_svc_result(SvcId::ConnectToPort, svc_ctx.call_svc(SvcId::ConnectToPort, &[(Register::X1, client_port_handle as u64)]), ResultSuccess::make())?;
+        expect_svc_result(SvcId::ConnectToPort, svc_ctx.call_svc(SvcId::ConnectToPort, &[(Register::X1, 0)]), kern_result::ResultInvalidHandle::make())
+    }, Duration::from_secs(5))
+}
+
+fn svc_pseudo_handles_run() -> std::result::Result<(), String> {
+    let env = SvcTestEnvironment::new("svc_pseudo_handles", vec![SvcId::GetProcessInfo, SvcId::WaitSynchronization, SvcId::CloseHandle]).map_err(|rc| format!("unable to create the environment: {0} ({0:?})", rc))?;
+    let initial_handle_count = env.get_used_handle_count();
+
+    env.run(|svc_ctx| {
+        // Pseudo-handles are accepted like any actual handle to the current process/thread
+        expect_svc_result(SvcId::GetProcessInfo, svc_ctx.call_svc(SvcId::GetProcessInfo, &[(Register::X1, svc::CURRENT_PROCESS_PSEUDO_HANDLE as u64), (Register::X2, svc::ProcessInfoType::ProcessState as u64)]), ResultSuccess::make())?;
+
+        // The current thread isn't signaled while running, thus a wait without timeout just times out
+        let mut handles_data: Vec<u8> = Vec::new();
+        handles_data.extend_from_slice(&svc::CURRENT_THREAD_PSEUDO_HANDLE.to_le_bytes());
+        handles_data.extend_from_slice(&svc_ctx.thread_handle.to_le_bytes());
+        let handles_address = svc_ctx.write_scratch(0, &handles_data).map_err(|rc| format!("unable to write the handles: {0} ({0:?})", rc))?;
+        expect_svc_result(SvcId::WaitSynchronization, svc_ctx.call_svc(SvcId::WaitSynchronization, &[(Register::X1, handles_address), (Register::X2, 2), (Register::X3, 0)]), kern_result::ResultTimedOut::make())?;
+
+        // ...but they can't be closed
+        expect_svc_result(SvcId::CloseHandle, svc_ctx.call_svc(SvcId::CloseHandle, &[(Register::X0, svc::CURRENT_PROCESS_PSEUDO_HANDLE as u64)]), kern_result::ResultInvalidHandle::make())?;
+        expect_svc_result(SvcId::CloseHandle, svc_ctx.call_svc(SvcId::CloseHandle, &[(Register::X0, svc::CURRENT_THREAD_PSEUDO_HANDLE as u64)]), kern_result::ResultInvalidHandle::make())
+    }, Duration::from_secs(5))?;
+
+    // Only the SVC thread's own handle was allocated
+    match env.get_used_handle_count() {
+        handle_count if handle_count == initial_handle_count + 1 => Ok(()),
+        handle_count => Err(format!("unexpected used handle count: {} (initially {})", handle_count, initial_handle_count))
+    }
+}
+
+fn log_locked_objects_run() -> std::result::Result<(), String> {
+    let env = SvcTestEnvironment::new("log_locked_objects", Vec::new()).map_err(|rc| format!("unable to create the environment: {0} ({0:?})", rc))?;
+
+    // Like SVCs or hooks logging something while holding the current process/thread, which must not panic nor deadlock
+    env.run(|_| {
+        let cur_thread = kern_thread::get_current_thread();
+        let cur_process = get_current_process();
+        let _process_guard = cur_process.get();
+        let _thread_guard = cur_thread.get();
+        log_line!("[test] Logging with the current process and thread locked");
+        Ok(())
+    }, Duration::from_secs(5))?;
+
+    util::flush_log();
+    Ok(())
+}
+
+// Synthetic guest memory with an ExpHeap, a UnitHeap and a newlib arena (each in its own 0x1000 bytes)
+const HEAP_TEST_ADDRESS: u64 = 0x10000000;
+const HEAP_TEST_EXP_HEAP_ADDRESS: u64 = HEAP_TEST_ADDRESS;
+const HEAP_TEST_UNIT_HEAP_ADDRESS: u64 = HEAP_TEST_ADDRESS + 0x1000;
+const HEAP_TEST_NEWLIB_HEAP_ADDRESS: u64 = HEAP_TEST_ADDRESS + 0x2000;
+const HEAP_TEST_SIZE: usize = 0x3000;
+
+fn write_heap_test_val<const N: usize>(memory: &mut [u8], address: u64, data: [u8; N]) {
+    let offset = (address - HEAP_TEST_ADDRESS) as usize;
+    memory[offset..offset + N].copy_from_slice(&data);
+}
+
+fn read_heap_test_memory(memory: &[u8], address: u64, data: &mut [u8]) -> Result<()> {
+    let offset = address.wrapping_sub(HEAP_TEST_ADDRESS) as usize;
+    result_return_unless!(offset.saturating_add(data.len()) <= memory.len(), ResultReadOutOfBounds);
+    data.copy_from_slice(&memory[offset..offset + data.len()]);
+    Ok(())
+}
+
+fn make_heap_test_memory() -> Vec<u8> {
+    let mut memory: Vec<u8> = vec![0; HEAP_TEST_SIZE];
+
+    // ExpHeap: two used blocks (0x40 and 0x20 bytes) followed by a free one up to the heap end
+    let exp_start = HEAP_TEST_EXP_HEAP_ADDRESS + 0x100;
+    let exp_end = HEAP_TEST_EXP_HEAP_ADDRESS + 0x1000;
+    let (free_list, used_list) = (HEAP_TEST_EXP_HEAP_ADDRESS + 0x40, HEAP_TEST_EXP_HEAP_ADDRESS + 0x50);
+    let (used_a, used_b, free_c) = (exp_start, exp_start + 0x60, exp_start + 0xA0);
+    write_heap_test_val(&mut memory, HEAP_TEST_EXP_HEAP_ADDRESS, *b"EXPH");
+    write_heap_test_val(&mut memory, HEAP_TEST_EXP_HEAP_ADDRESS + 0x28, exp_start.to_le_bytes());
+    write_heap_test_val(&mut memory, HEAP_TEST_EXP_HEAP_ADDRESS + 0x30, exp_end.to_le_bytes());
+    for (block, magic, group_id, size, prev_node, next_node) in [
+        (used_a, 0x5544u16, 3u32, 0x40u64, used_list, used_b + 0x10),
+        (used_b, 0x5544u16, 0u32, 0x20u64, used_a + 0x10, used_list),
+        (free_c, 0x4652u16, 0u32, exp_end - free_c - 0x20, free_list, free_list)
+    ] {
+        write_heap_test_val(&mut memory, block, magic.to_le_bytes());
+        write_heap_test_val(&mut memory, block + 0x4, group_id.to_le_bytes());
+        write_heap_test_val(&mut memory, block + 0x8, size.to_le_bytes());
+        write_heap_test_val(&mut memory, block + 0x10, prev_node.to_le_bytes());
+        write_heap_test_val(&mut memory, block + 0x18, next_node.to_le_bytes());
+    }
+    write_heap_test_val(&mut memory, free_list, (free_c + 0x10).to_le_bytes());
+    write_heap_test_val(&mut memory, free_list + 0x8, (free_c + 0x10).to_le_bytes());
+    write_heap_test_val(&mut memory, used_list, (used_b + 0x10).to_le_bytes());
+    write_heap_test_val(&mut memory, used_list + 0x8, (used_a + 0x10).to_le_bytes());
+
+    // UnitHeap: 8 units of 0x20 bytes, units 1 and 5 being free
+    let unit_start = HEAP_TEST_UNIT_HEAP_ADDRESS + 0x100;
+    write_heap_test_val(&mut memory, HEAP_TEST_UNIT_HEAP_ADDRESS, *b"UNTH");
+    write_heap_test_val(&mut memory, HEAP_TEST_UNIT_HEAP_ADDRESS + 0x28, unit_start.to_le_bytes());
+    write_heap_test_val(&mut memory, HEAP_TEST_UNIT_HEAP_ADDRESS + 0x30, (unit_start + 0x100).to_le_bytes());
+    write_heap_test_val(&mut memory, HEAP_TEST_UNIT_HEAP_ADDRESS + 0x40, (unit_start + 0x20).to_le_bytes());
+    write_heap_test_val(&mut memory, HEAP_TEST_UNIT_HEAP_ADDRESS + 0x48, 0x20u64.to_le_bytes());
+    write_heap_test_val(&mut memory, HEAP_TEST_UNIT_HEAP_ADDRESS + 0x54, 8u32.to_le_bytes());
+    write_heap_test_val(&mut memory, unit_start + 0x20, (unit_start + 0xA0).to_le_bytes());
+
+    // newlib arena: used (0x30), free (0x40), used (0x20) and top chunks
+    let chunks = HEAP_TEST_NEWLIB_HEAP_ADDRESS;
+    write_heap_test_val(&mut memory, chunks + 0x8, (0x30u64 | 1).to_le_bytes());
+    write_heap_test_val(&mut memory, chunks + 0x38, (0x40u64 | 1).to_le_bytes());
+    write_heap_test_val(&mut memory, chunks + 0x70, 0x40u64.to_le_bytes());
+    write_heap_test_val(&mut memory, chunks + 0x78, 0x20u64.to_le_bytes());
+    write_heap_test_val(&mut memory, chunks + 0x98, ((0x1000 - 0x90) as u64 | 1).to_le_bytes());
+
+    memory
+}
+
+fn check_heap_report(report: &HeapReport, kind: HeapKind, used_sizes: &[u64], free_count: usize) -> std::result::Result<(), String> {
+    let report_used_sizes: Vec<u64> = report.get_used_blocks().map(|block| block.size).collect();
+    if (report.kind != kind) || (report_used_sizes != used_sizes) || (report.get_free_blocks().count() != free_count) || report.is_corrupted() {
+        return Err(format!("unexpected {} report: {} (used block sizes {:?}, problems {:?})", kind, report, report_used_sizes, report.problems));
+    }
+    Ok(())
+}
+
+fn heap_walkers_run() -> std::result::Result<(), String> {
+    let mut memory = make_heap_test_memory();
+    let memory_map = vec![cpu::MemoryMapEntry::new(&MemoryRegion::from(HEAP_TEST_ADDRESS, memory.clone(), MemoryPermission::READ | MemoryPermission::WRITE), String::from("heap"))];
+
+    {
+        let read_memory = |address: u64, data: &mut [u8]| read_heap_test_memory(&memory, address, data);
+
+        // lmem heaps are found by their heads
+        let head_addresses = heap::find_lmem_heaps(&read_memory, &memory_map);
+        if head_addresses != vec![HEAP_TEST_EXP_HEAP_ADDRESS, HEAP_TEST_UNIT_HEAP_ADDRESS] {
+            return Err(format!("unexpected lmem heap heads: {:X?}", head_addresses));
+        }
+
+        let exp_report = heap::walk_lmem_heap(&read_memory, HEAP_TEST_EXP_HEAP_ADDRESS).map_err(|rc| format!("unable to walk the ExpHeap: {0} ({0:?})", rc))?;
+        check_heap_report(&exp_report, HeapKind::ExpHeap, &[0x40, 0x20], 1)?;
+        if exp_report.get_used_blocks().next().and_then(|block| block.group_id) != Some(3) {
+            return Err(format!("unexpected ExpHeap block group: {:?}", exp_report.blocks));
+        }
+        let unit_report = heap::walk_lmem_heap(&read_memory, HEAP_TEST_UNIT_HEAP_ADDRESS).map_err(|rc| format!("unable to walk the UnitHeap: {0} ({0:?})", rc))?;
+        check_heap_report(&unit_report, HeapKind::UnitHeap, &[0x20; 6], 2)?;
+        let newlib_report = heap::walk_newlib_heap(&read_memory, HEAP_TEST_NEWLIB_HEAP_ADDRESS, HEAP_TEST_NEWLIB_HEAP_ADDRESS + 0x1000).map_err(|rc| format!("unable to walk the newlib heap: {0} ({0:?})", rc))?;
+        check_heap_report(&newlib_report, HeapKind::Newlib, &[0x20, 0x10], 2)?;
+    }
+
+    // Corruption: a used ExpHeap block with a broken magic, a looping UnitHeap free list and a free newlib chunk disagreeing with the following one about its size
+    write_heap_test_val(&mut memory, HEAP_TEST_EXP_HEAP_ADDRESS + 0x160, 0u16.to_le_bytes());
+    write_heap_test_val(&mut memory, HEAP_TEST_UNIT_HEAP_ADDRESS + 0x1A0, (HEAP_TEST_UNIT_HEAP_ADDRESS + 0x120).to_le_bytes());
+    write_heap_test_val(&mut memory, HEAP_TEST_NEWLIB_HEAP_ADDRESS + 0x70, 0x50u64.to_le_bytes());
+    let read_memory = |address: u64, data: &mut [u8]| read_heap_test_memory(&memory, address, data);
+    let reports = vec![
+        heap::walk_lmem_heap(&read_memory, HEAP_TEST_EXP_HEAP_ADDRESS),
+        heap::walk_lmem_heap(&read_memory, HEAP_TEST_UNIT_HEAP_ADDRESS),
+        heap::walk_newlib_heap(&read_memory, HEAP_TEST_NEWLIB_HEAP_ADDRESS, HEAP_TEST_NEWLIB_HEAP_ADDRESS + 0x1000)
+    ];
+    for report in reports.into_iter() {
+        match report {
+            Ok(report) if report.is_corrupted() => {},
+            r => return Err(format!("corruption not detected: {:?}", r))
+        };
+    }
+    Ok(())
+}
+
+pub fn get_builtin_host_test_cases() -> Vec<HostTestCase> {
+    vec![
+        HostTestCase {
+            name: "condition_variable_stress",
+            run: condition_variable_stress_run
+        },
+        HostTestCase {
+            name: "condition_variable_timeout",
+            run: condition_variable_timeout_run
+        },
+        HostTestCase {
+            name: "named_port_processes",
+            run: named_port_processes_run
+        },
+        HostTestCase {
+            name: "thread_force_pause",
+            run: thread_force_pause_run
+        },
+        HostTestCase {
+            name: "thread_activity_pause_resume",
+            run: thread_activity_pause_resume_run
+        },
+        HostTestCase {
+            name: "yield_thread_handoff",
+            run: yield_thread_handoff_run
+        },
+        HostTestCase {
+            name: "mutex_priority_inheritance",
+            run: mutex_priority_inheritance_run
+        },
+        HostTestCase {
+            name: "bsd_address_allowlist",
+            run: bsd_address_allowlist_run
+        },
+        HostTestCase {
+            name: "time_zone_conversion",
+            run: time_zone_conversion_run
+        },
+        HostTestCase {
+            name: "svc_detection_modes",
+            run: svc_detection_modes_run
+        },
+        HostTestCase {
+            name: "disassembly",
+            run: disassembly_run
+        },
+        HostTestCase {
+            name: "cpu_backend_corpus",
+            run: cpu_backend_corpus_run
+        },
+        HostTestCase {
+            name: "cpu_backend_lockstep",
+            run: cpu_backend_lockstep_run
+        },
+        HostTestCase {
+            name: "svc_events",
+            run: svc_events_run
+        },
+        HostTestCase {
+            name: "svc_ports",
+            run: svc_ports_run
+        },
+        HostTestCase {
+            name: "svc_pseudo_handles",
+            run: svc_pseudo_handles_run
+        },
+        HostTestCase {
+            name: "log_locked_objects",
+            run: log_locked_objects_run
+        },
+        HostTestCase {
+            name: "heap_walkers",
+            run: heap_walkers_run
+        }
+    ]
+}
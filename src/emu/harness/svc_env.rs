@@ -0,0 +1,318 @@
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+use core::result::Result as CoreResult;
+use parking_lot::Mutex;
+use crate::emu::cpu::{Architecture, ContextHandle, MemoryPermission, MemoryRegion, Register};
+use crate::emu::cpu::backend::{BackendKind, CpuBackend};
+use crate::emu::cpu::result as cpu_result;
+use crate::emu::kern as emu_kern;
+use crate::kern::proc::KProcess;
+use crate::kern::result as kern_result;
+use crate::kern::svc::{Handle, SvcId};
+use crate::kern::thread::KThread;
+use crate::ncm::ProgramId;
+use crate::proc::EmulatedProcess;
+use crate::util::Shared;
+use crate::result::*;
+
+// SVC handler test scaffolding: handlers (see emu::kern) are called directly from a host thread of a mock process, without booting any guest binary
+// The mock process is like emulated ones (no CPU context at all), and handlers get a context backed by a mock backend, which just has registers and a scratch memory region for the pointers passed to them (no modules, hooks or guest execution)
+
+pub const SCRATCH_ADDRESS: u64 = 0x9000000;
+pub const SCRATCH_SIZE: usize = 0x1000;
+
+// SVC arguments/return values are passed in X0-X7
+const ARGUMENT_REGISTERS: [Register; 8] = [
+    Register::X0, Register::X1, Register::X2, Register::X3, Register::X4, Register::X5, Register::X6, Register::X7
+];
+
+struct MockRegion {
+    address: u64,
+    perm: MemoryPermission,
+    data: Vec<u8>
+}
+
+impl MockRegion {
+    fn end(&self) -> u64 {
+        self.address + self.data.len() as u64
+    }
+
+    fn contains(&self, address: u64, size: usize) -> bool {
+        (self.address <= address) && (address.saturating_add(size as u64) <= self.end())
+    }
+}
+
+struct MockState {
+    gprs: [u64; 31],
+    // Any other register (SP, PC, system registers...) is just stored as is
+    other_registers: Vec<(Register, u64)>,
+    regions: Vec<MockRegion>
+}
+
+// Xn/Wn are the same register, Wn writes clearing the upper half (like on the actual CPU)
+fn get_gpr_index(reg: Register) -> Option<(usize, bool)> {
+    let reg_id = reg as i32;
+    if (Register::X0 as i32 <= reg_id) && (reg_id <= Register::X28 as i32) {
+        return Some(((reg_id - Register::X0 as i32) as usize, true));
+    }
+    if (Register::W0 as i32 <= reg_id) && (reg_id <= Register::W30 as i32) {
+        return Some(((reg_id - Register::W0 as i32) as usize, false));
+    }
+
+    match reg {
+        Register::X29 => Some((29, true)),
+        Register::X30 => Some((30, true)),
+        _ => None
+    }
+}
+
+// Handles (see CpuBackend::get_handle) share the state, thus everything is behind a lock
+#[derive(Clone)]
+struct MockBackend {
+    state: Arc<Mutex<MockState>>
+}
+
+impl MockBackend {
+    fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(MockState {
+                gprs: [0; 31],
+                other_registers: Vec::new(),
+                regions: Vec::new()
+            }))
+        }
+    }
+
+    fn access_region<R, F: FnOnce(&mut [u8]) -> R>(&self, address: u64, size: usize, f: F) -> Result<R> {
+        let mut state = self.state.lock();
+        match state.regions.iter_mut().find(|region| region.contains(address, size)) {
+            Some(region) => {
+                let offset = (address - region.address) as usize;
+                Ok(f(&mut region.data[offset..offset + size]))
+            },
+            None => cpu_result::ResultBackendInvalidMemoryAccess::make_err()
+        }
+    }
+}
+
+impl CpuBackend for MockBackend {
+    fn get_kind(&self) -> BackendKind {
+        BackendKind::Mock
+    }
+
+    fn get_architecture(&self) -> Architecture {
+        Architecture::Aarch64
+    }
+
+    fn get_handle(&self) -> ContextHandle {
+        ContextHandle::new(Box::new(self.clone()))
+    }
+
+    fn map_memory(&mut self, address: u64, data: &[u8], perm: MemoryPermission) -> Result<()> {
+        let mut state = self.state.lock();
+        let end_address = address.saturating_add(data.len() as u64);
+        let overlaps = state.regions.iter().any(|region| (region.address < end_address) && (address < region.end()));
+        result_return_if!(data.is_empty() || overlaps, cpu_result::ResultBackendInvalidMemoryAccess);
+
+        state.regions.push(MockRegion {
+            address: address,
+            perm: perm,
+            data: data.to_vec()
+        });
+        Ok(())
+    }
+
+    // Note: SVCs only (un)map memory on the execution contexts of their process, which the mock process has none of
+    fn map_region(&mut self, _region: &MemoryRegion) -> Result<()> {
+        cpu_result::ResultBackendUnsupportedOperation::make_err()
+    }
+
+    fn map_regions(&mut self, _regions: &[MemoryRegion]) -> CoreResult<(), (usize, ResultCode)> {
+        Err((0, cpu_result::ResultBackendUnsupportedOperation::make()))
+    }
+
+    fn unmap_memory(&mut self, _address: u64, _size: usize) -> Result<()> {
+        cpu_result::ResultBackendUnsupportedOperation::make_err()
+    }
+
+    fn protect_memory(&mut self, _address: u64, _size: usize, _perm: MemoryPermission) -> Result<()> {
+        cpu_result::ResultBackendUnsupportedOperation::make_err()
+    }
+
+    fn get_accessible_size(&self, address: u64, max_size: usize, perm: MemoryPermission) -> Result<usize> {
+        let state = self.state.lock();
+        let mut cur_address = address;
+        let mut accessible_size: usize = 0;
+        while accessible_size < max_size {
+            match state.regions.iter().find(|region| region.contains(cur_address, 1) && region.perm.contains(perm)) {
+                Some(region) => {
+                    accessible_size += (region.end() - cur_address) as usize;
+                    cur_address = region.end();
+                },
+                None => break
+            };
+        }
+
+        Ok(accessible_size.min(max_size))
+    }
+
+    fn read_memory(&self, address: u64, data: &mut [u8]) -> Result<()> {
+        self.access_region(address, data.len(), |region_data| data.copy_from_slice(region_data))
+    }
+
+    fn write_memory(&mut self, address: u64, data: &[u8]) -> Result<()> {
+        self.access_region(address, data.len(), |region_data| region_data.copy_from_slice(data))
+    }
+
+    fn read_register(&self, reg: Register) -> Result<u64> {
+        let state = self.state.lock();
+        match get_gpr_index(reg) {
+            Some((gpr_idx, true)) => Ok(state.gprs[gpr_idx]),
+            Some((gpr_idx, false)) => Ok(state.gprs[gpr_idx] & u32::MAX as u64),
+            None => Ok(state.other_registers.iter().find(|(other_reg, _)| *other_reg == reg).map(|(_, value)| *value).unwrap_or(0))
+        }
+    }
+
+    fn write_register(&mut self, reg: Register, value: u64) -> Result<()> {
+        let mut state = self.state.lock();
+        match get_gpr_index(reg) {
+            Some((gpr_idx, true)) => state.gprs[gpr_idx] = value,
+            Some((gpr_idx, false)) => state.gprs[gpr_idx] = value & u32::MAX as u64,
+            None => {
+                state.other_registers.retain(|(other_reg, _)| *other_reg != reg);
+                state.other_registers.push((reg, value));
+            }
+        };
+        Ok(())
+    }
+
+    fn run(&mut self, _end_address: u64, _max_instruction_count: usize) -> Result<()> {
+        cpu_result::ResultBackendUnsupportedOperation::make_err()
+    }
+
+    fn run_at(&mut self, _start_address: u64, _end_address: u64) -> Result<()> {
+        cpu_result::ResultBackendUnsupportedOperation::make_err()
+    }
+
+    // Nothing is ever running (handlers stop the execution after exiting threads, for instance)
+    fn stop(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub struct SvcTestContext {
+    backend: MockBackend,
+    enabled_svcs: Vec<SvcId>,
+    pub thread_handle: Handle
+}
+
+impl SvcTestContext {
+    fn new(enabled_svcs: Vec<SvcId>, thread_handle: Handle) -> Result<Self> {
+        let mut backend = MockBackend::new();
+        backend.map_memory(SCRATCH_ADDRESS, &vec![0; SCRATCH_SIZE], MemoryPermission::READ | MemoryPermission::WRITE)?;
+
+        Ok(Self {
+            backend: backend,
+            enabled_svcs: enabled_svcs,
+            thread_handle: thread_handle
+        })
+    }
+
+    pub fn get_handle(&self) -> ContextHandle {
+        self.backend.get_handle()
+    }
+
+    pub fn read_register(&self, reg: Register) -> Result<u64> {
+        self.backend.read_register(reg)
+    }
+
+    // Returns the (guest) address the data was written to, for passing it to SVCs
+    pub fn write_scratch(&mut self, offset: usize, data: &[u8]) -> Result<u64> {
+        result_return_unless!(offset.saturating_add(data.len()) <= SCRATCH_SIZE, kern_result::ResultInvalidPointer);
+
+        let address = SCRATCH_ADDRESS + offset as u64;
+        self.backend.write_memory(address, data)?;
+        Ok(address)
+    }
+
+    pub fn read_scratch(&self, offset: usize, data: &mut [u8]) -> Result<()> {
+        result_return_unless!(offset.saturating_add(data.len()) <= SCRATCH_SIZE, kern_result::ResultInvalidPointer);
+
+        self.backend.read_memory(SCRATCH_ADDRESS + offset as u64, data)
+    }
+
+    // Argument registers which aren't given are zeroed, thus every call starts from the same state
+    // Returns the result left in W0 (the call itself only fails if the handler fails, like it would be a guest fault, or if the SVC can't be called at all)
+    pub fn call_svc(&mut self, svc_id: SvcId, args: &[(Register, u64)]) -> Result<ResultCode> {
+        result_return_unless!(self.enabled_svcs.contains(&svc_id), kern_result::ResultNotImplemented);
+        let svc_handler = match emu_kern::try_find_svc_handler(&svc_id) {
+            Some(svc_handler) => svc_handler,
+            None => return kern_result::ResultNotImplemented::make_err()
+        };
+
+        for reg in ARGUMENT_REGISTERS.iter() {
+            self.backend.write_register(*reg, 0)?;
+        }
+        for (reg, value) in args.iter() {
+            self.backend.write_register(*reg, *value)?;
+        }
+
+        (svc_handler)(self.get_handle())?;
+        let rc = self.backend.read_register(Register::X0)? as u32;
+        Ok(ResultCode::new(rc))
+    }
+}
+
+pub struct SvcTestEnvironment {
+    pub process: Shared<KProcess>,
+    name: String,
+    enabled_svcs: Vec<SvcId>
+}
+
+impl SvcTestEnvironment {
+    pub fn new(name: &str, enabled_svcs: Vec<SvcId>) -> Result<Self> {
+        let npdm = EmulatedProcess::make_npdm(name, 44, 0x4000, ProgramId(0x010000000000FFFF), enabled_svcs.clone(), 0x200)?;
+        let process = KProcess::new(None, npdm)?;
+
+        Ok(Self {
+            process: process,
+            name: String::from(name),
+            enabled_svcs: enabled_svcs
+        })
+    }
+
+    // Runs the given function on a new host thread of the mock process (thus the current process/thread are the mock ones, like within actual SVCs), waiting for it to finish
+    // The thread gets a handle to itself in the process's handle table (like main threads do)
+    pub fn run<T: Send + 'static, F: FnOnce(&mut SvcTestContext) -> std::result::Result<T, String> + Send + 'static>(&self, f: F, timeout: Duration) -> std::result::Result<T, String> {
+        let mut thread = KProcess::create_main_thread_host(&self.process, format!("test.{}.SvcThread", self.name)).map_err(|rc| format!("unable to create the SVC thread: {0} ({0:?})", rc))?;
+        let thread_handle = self.process.get().handle_table.allocate_handle_set(thread.clone()).map_err(|rc| format!("unable to allocate the SVC thread handle: {0} ({0:?})", rc))?;
+
+        let enabled_svcs = self.enabled_svcs.clone();
+        let (result_sender, result_receiver) = mpsc::channel();
+        KThread::start_host(&mut thread, move || {
+            let result = match SvcTestContext::new(enabled_svcs, thread_handle) {
+                Ok(mut svc_ctx) => f(&mut svc_ctx),
+                Err(rc) => Err(format!("unable to create the SVC context: {0} ({0:?})", rc))
+            };
+            let _ = result_sender.send(result);
+        }).map_err(|rc| format!("unable to start the SVC thread: {0} ({0:?})", rc))?;
+
+        match result_receiver.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(_) => Err(String::from("the SVC thread didn't finish in time"))
+        }
+    }
+
+    pub fn get_used_handle_count(&self) -> usize {
+        self.process.get().handle_table.get_used_entry_count()
+    }
+}
+
+// Checks the result a call left in W0, for tests to use with '?'
+pub fn expect_svc_result(svc_id: SvcId, r: Result<ResultCode>, expected_rc: ResultCode) -> std::result::Result<(), String> {
+    match r {
+        Ok(rc) if rc == expected_rc => Ok(()),
+        Ok(rc) => Err(format!("{:?} returned {}, expected {}", svc_id, rc.describe(), expected_rc.describe())),
+        Err(rc) => Err(format!("{:?} couldn't be called: {1} ({1:?})", svc_id, rc))
+    }
+}
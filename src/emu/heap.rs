@@ -0,0 +1,374 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use crate::emu::cpu::{MemoryMapEntry, MemoryPermission};
+use crate::result::*;
+
+// Guest heap introspection: common guest allocator layouts are located and walked from guest memory, listing their blocks and reporting anything inconsistent (which is what heap corruption looks like)
+// Supported layouts are nn::lmem heaps (ExpHeap/UnitHeap, used by nn::mem and SDK code) and newlib's malloc arena (used by libnx homebrew)
+// Memory is read through the given function, thus this works the same with running processes and with core dumps (see emu::coredump)
+// Note: only 64-bit layouts are supported
+
+// nn::lmem layouts, as reimplemented by Atmosphère's lmem (HeapHead, then the implementation-specific head)
+const LMEM_EXP_HEAP_MAGIC: [u8; 4] = *b"EXPH";
+const LMEM_UNIT_HEAP_MAGIC: [u8; 4] = *b"UNTH";
+const LMEM_HEAP_HEAD_START_OFFSET: u64 = 0x28;
+const LMEM_HEAP_HEAD_END_OFFSET: u64 = 0x30;
+const LMEM_IMPL_HEAD_OFFSET: u64 = 0x40;
+
+// ExpHeap: blocks are kept in two intrusive lists (free/used), each block preceded by its header
+const EXP_HEAP_FREE_LIST_OFFSET: u64 = LMEM_IMPL_HEAD_OFFSET;
+const EXP_HEAP_USED_LIST_OFFSET: u64 = LMEM_IMPL_HEAD_OFFSET + 0x10;
+const EXP_BLOCK_FREE_MAGIC: u16 = 0x4652; // "FR"
+const EXP_BLOCK_USED_MAGIC: u16 = 0x5544; // "UD"
+const EXP_BLOCK_HEAD_SIZE: u64 = 0x20;
+const EXP_BLOCK_SIZE_OFFSET: u64 = 0x8;
+const EXP_BLOCK_LIST_NODE_OFFSET: u64 = 0x10;
+
+// UnitHeap: same-sized units from the heap start, free ones linked through their first word
+const UNIT_HEAP_FREE_LIST_OFFSET: u64 = LMEM_IMPL_HEAD_OFFSET;
+const UNIT_HEAP_UNIT_SIZE_OFFSET: u64 = LMEM_IMPL_HEAD_OFFSET + 0x8;
+const UNIT_HEAP_UNIT_COUNT_OFFSET: u64 = LMEM_IMPL_HEAD_OFFSET + 0x14;
+
+// newlib's malloc (dlmalloc): chunks ("prev_size" and "size" words, then the data) one after another, the last one being the top (free) chunk
+const NEWLIB_CHUNK_HEAD_SIZE: u64 = 0x10;
+const NEWLIB_CHUNK_ALIGNMENT: u64 = 0x10;
+const NEWLIB_MIN_CHUNK_SIZE: u64 = 0x20;
+const NEWLIB_PREV_IN_USE: u64 = 0x1;
+const NEWLIB_CHUNK_FLAGS_MASK: u64 = 0x7;
+
+// Heap heads are searched in chunks of writable memory this big
+const HEAP_SCAN_CHUNK_SIZE: usize = 0x10000;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum HeapKind {
+    ExpHeap,
+    UnitHeap,
+    Newlib
+}
+
+impl fmt::Display for HeapKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ExpHeap => write!(f, "ExpHeap"),
+            Self::UnitHeap => write!(f, "UnitHeap"),
+            Self::Newlib => write!(f, "newlib heap")
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct HeapBlock {
+    // Address/size of the block's data (what the allocation returned), headers excluded
+    pub address: u64,
+    pub size: u64,
+    pub is_used: bool,
+    // Only ExpHeap blocks have a group ID
+    pub group_id: Option<u8>
+}
+
+#[derive(Clone, Debug)]
+pub struct HeapReport {
+    pub kind: HeapKind,
+    // Heap head for lmem heaps, arena start for newlib ones
+    pub address: u64,
+    pub start: u64,
+    pub end: u64,
+    pub blocks: Vec<HeapBlock>,
+    // Inconsistencies found while walking the heap (broken links, bad magics, blocks out of bounds or overlapping...)
+    pub problems: Vec<String>
+}
+
+impl HeapReport {
+    fn new(kind: HeapKind, address: u64, start: u64, end: u64) -> Self {
+        Self {
+            kind: kind,
+            address: address,
+            start: start,
+            end: end,
+            blocks: Vec::new(),
+            problems: Vec::new()
+        }
+    }
+
+    pub fn get_used_blocks(&self) -> impl Iterator<Item = &HeapBlock> {
+        self.blocks.iter().filter(|block| block.is_used)
+    }
+
+    pub fn get_free_blocks(&self) -> impl Iterator<Item = &HeapBlock> {
+        self.blocks.iter().filter(|block| !block.is_used)
+    }
+
+    pub fn get_used_size(&self) -> u64 {
+        self.get_used_blocks().map(|block| block.size).sum()
+    }
+
+    pub fn get_free_size(&self) -> u64 {
+        self.get_free_blocks().map(|block| block.size).sum()
+    }
+
+    pub fn is_corrupted(&self) -> bool {
+        !self.problems.is_empty()
+    }
+
+    // Blocks are expected to be sorted by address (they are after walking)
+    fn check_overlaps(&mut self) {
+        let overlaps: Vec<String> = self.blocks.windows(2).filter(|blocks| blocks[0].address + blocks[0].size > blocks[1].address).map(|blocks| format!("block at {:#X} (size {:#X}) overlaps block at {:#X}", blocks[0].address, blocks[0].size, blocks[1].address)).collect();
+        self.problems.extend(overlaps);
+    }
+}
+
+impl fmt::Display for HeapReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {:#X} ({:#X}-{:#X}): {} used blocks ({:#X} bytes), {} free blocks ({:#X} bytes)", self.kind, self.address, self.start, self.end, self.get_used_blocks().count(), self.get_used_size(), self.get_free_blocks().count(), self.get_free_size())?;
+        if self.is_corrupted() {
+            write!(f, ", {} problems", self.problems.len())?;
+        }
+        Ok(())
+    }
+}
+
+fn read_u16<R: Fn(u64, &mut [u8]) -> Result<()>>(read_memory: &R, address: u64) -> Result<u16> {
+    let mut data = [0u8; 2];
+    read_memory(address, &mut data)?;
+    Ok(u16::from_le_bytes(data))
+}
+
+fn read_u32<R: Fn(u64, &mut [u8]) -> Result<()>>(read_memory: &R, address: u64) -> Result<u32> {
+    let mut data = [0u8; 4];
+    read_memory(address, &mut data)?;
+    Ok(u32::from_le_bytes(data))
+}
+
+fn read_u64<R: Fn(u64, &mut [u8]) -> Result<()>>(read_memory: &R, address: u64) -> Result<u64> {
+    let mut data = [0u8; 8];
+    read_memory(address, &mut data)?;
+    Ok(u64::from_le_bytes(data))
+}
+
+// Walks one of the (circular, with the list head as sentinel) block lists of an ExpHeap
+fn walk_exp_heap_list<R: Fn(u64, &mut [u8]) -> Result<()>>(read_memory: &R, report: &mut HeapReport, list_address: u64, is_used: bool) -> Result<()> {
+    let (expected_magic, list_name) = match is_used {
+        true => (EXP_BLOCK_USED_MAGIC, "used"),
+        false => (EXP_BLOCK_FREE_MAGIC, "free")
+    };
+
+    // Every block takes at least its header, which bounds how many there can be (in case the list loops without going through the head)
+    let max_block_count = (report.end - report.start) / EXP_BLOCK_HEAD_SIZE;
+    let mut prev_node = list_address;
+    let mut node = read_u64(read_memory, list_address + 0x8)?;
+    let mut block_count: u64 = 0;
+    while node != list_address {
+        if block_count > max_block_count {
+            report.problems.push(format!("{} list loops without reaching its head", list_name));
+            break;
+        }
+
+        let block_head = node.wrapping_sub(EXP_BLOCK_LIST_NODE_OFFSET);
+        if (block_head < report.start) || (block_head.saturating_add(EXP_BLOCK_HEAD_SIZE) > report.end) {
+            report.problems.push(format!("{} list node {:#X} is outside the heap", list_name, node));
+            break;
+        }
+
+        let node_prev = read_u64(read_memory, node)?;
+        if node_prev != prev_node {
+            report.problems.push(format!("{} list node {:#X} links back to {:#X} instead of {:#X}", list_name, node, node_prev, prev_node));
+        }
+
+        let magic = read_u16(read_memory, block_head)?;
+        if magic != expected_magic {
+            report.problems.push(format!("{} block at {:#X} has an invalid magic ({:#06X})", list_name, block_head, magic));
+        }
+
+        let group_id = (read_u32(read_memory, block_head + 0x4)? & 0xFF) as u8;
+        let size = read_u64(read_memory, block_head + EXP_BLOCK_SIZE_OFFSET)?;
+        let address = block_head + EXP_BLOCK_HEAD_SIZE;
+        if address.saturating_add(size) > report.end {
+            report.problems.push(format!("{} block at {:#X} (size {:#X}) goes past the heap end", list_name, address, size));
+        }
+        report.blocks.push(HeapBlock {
+            address: address,
+            size: size,
+            is_used: is_used,
+            group_id: Some(group_id)
+        });
+
+        prev_node = node;
+        node = read_u64(read_memory, node + 0x8)?;
+        block_count += 1;
+    }
+
+    Ok(())
+}
+
+fn walk_exp_heap<R: Fn(u64, &mut [u8]) -> Result<()>>(read_memory: &R, head_address: u64, start: u64, end: u64) -> Result<HeapReport> {
+    let mut report = HeapReport::new(HeapKind::ExpHeap, head_address, start, end);
+    walk_exp_heap_list(read_memory, &mut report, head_address + EXP_HEAP_FREE_LIST_OFFSET, false)?;
+    walk_exp_heap_list(read_memory, &mut report, head_address + EXP_HEAP_USED_LIST_OFFSET, true)?;
+
+    report.blocks.sort_by_key(|block| block.address);
+    report.check_overlaps();
+    Ok(report)
+}
+
+fn walk_unit_heap<R: Fn(u64, &mut [u8]) -> Result<()>>(read_memory: &R, head_address: u64, start: u64, end: u64) -> Result<HeapReport> {
+    let mut report = HeapReport::new(HeapKind::UnitHeap, head_address, start, end);
+    let unit_size = read_u64(read_memory, head_address + UNIT_HEAP_UNIT_SIZE_OFFSET)?;
+    let unit_count = read_u32(read_memory, head_address + UNIT_HEAP_UNIT_COUNT_OFFSET)? as u64;
+    if (unit_size == 0) || (unit_count.saturating_mul(unit_size) > (end - start)) {
+        report.problems.push(format!("invalid unit layout ({} units of size {:#X})", unit_count, unit_size));
+        return Ok(report);
+    }
+
+    let units_end = start + unit_count * unit_size;
+    let mut free_units: BTreeSet<u64> = BTreeSet::new();
+    let mut unit = read_u64(read_memory, head_address + UNIT_HEAP_FREE_LIST_OFFSET)?;
+    while unit != 0 {
+        if (unit < start) || (unit >= units_end) || (((unit - start) % unit_size) != 0) {
+            report.problems.push(format!("free list entry {:#X} is not a unit of the heap", unit));
+            break;
+        }
+        if !free_units.insert(unit) {
+            report.problems.push(format!("free list loops at unit {:#X}", unit));
+            break;
+        }
+
+        unit = read_u64(read_memory, unit)?;
+    }
+
+    report.blocks = (0..unit_count).map(|i| {
+        let address = start + i * unit_size;
+        HeapBlock {
+            address: address,
+            size: unit_size,
+            is_used: !free_units.contains(&address),
+            group_id: None
+        }
+    }).collect();
+    Ok(report)
+}
+
+// Walks the lmem heap (ExpHeap or UnitHeap) whose head is at the given address
+pub fn walk_lmem_heap<R: Fn(u64, &mut [u8]) -> Result<()>>(read_memory: &R, head_address: u64) -> Result<HeapReport> {
+    let mut magic = [0u8; 4];
+    read_memory(head_address, &mut magic)?;
+    let start = read_u64(read_memory, head_address + LMEM_HEAP_HEAD_START_OFFSET)?;
+    let end = read_u64(read_memory, head_address + LMEM_HEAP_HEAD_END_OFFSET)?;
+    result_return_unless!(start <= end, ResultNotSupported);
+
+    match magic {
+        LMEM_EXP_HEAP_MAGIC => walk_exp_heap(read_memory, head_address, start, end),
+        LMEM_UNIT_HEAP_MAGIC => walk_unit_heap(read_memory, head_address, start, end),
+        _ => ResultNotSupported::make_err()
+    }
+}
+
+// Walks a newlib malloc arena (like the one libnx sets up over the process heap) within the given range
+pub fn walk_newlib_heap<R: Fn(u64, &mut [u8]) -> Result<()>>(read_memory: &R, start: u64, end: u64) -> Result<HeapReport> {
+    let mut report = HeapReport::new(HeapKind::Newlib, start, start, end);
+
+    let mut chunk = (start + NEWLIB_CHUNK_ALIGNMENT - 1) & !(NEWLIB_CHUNK_ALIGNMENT - 1);
+    let mut prev_is_free = false;
+    while chunk + NEWLIB_CHUNK_HEAD_SIZE <= end {
+        let size = read_u64(read_memory, chunk + 0x8)? & !NEWLIB_CHUNK_FLAGS_MASK;
+        // Whatever follows the top chunk was never handed out by sbrk
+        if size == 0 {
+            break;
+        }
+        if (size < NEWLIB_MIN_CHUNK_SIZE) || ((size % NEWLIB_CHUNK_ALIGNMENT) != 0) || (chunk.saturating_add(size) > end) {
+            report.problems.push(format!("chunk at {:#X} has an invalid size ({:#X})", chunk, size));
+            break;
+        }
+
+        // Chunks are in use unless the following one says otherwise (the top chunk being always free)
+        let next_chunk = chunk + size;
+        let next_size_field = match next_chunk + NEWLIB_CHUNK_HEAD_SIZE <= end {
+            true => read_u64(read_memory, next_chunk + 0x8)?,
+            false => 0
+        };
+        let is_top = (next_size_field & !NEWLIB_CHUNK_FLAGS_MASK) == 0;
+        let is_used = !is_top && ((next_size_field & NEWLIB_PREV_IN_USE) != 0);
+        if !is_used {
+            if prev_is_free {
+                report.problems.push(format!("chunk at {:#X} is free right after another free chunk (they should have been merged)", chunk));
+            }
+            if !is_top {
+                let next_prev_size = read_u64(read_memory, next_chunk)?;
+                if next_prev_size != size {
+                    report.problems.push(format!("free chunk at {:#X} has size {:#X}, but the following chunk says {:#X}", chunk, size, next_prev_size));
+                }
+            }
+        }
+
+        report.blocks.push(HeapBlock {
+            address: chunk + NEWLIB_CHUNK_HEAD_SIZE,
+            size: size - NEWLIB_CHUNK_HEAD_SIZE,
+            is_used: is_used,
+            group_id: None
+        });
+
+        if is_top {
+            break;
+        }
+        prev_is_free = !is_used;
+        chunk = next_chunk;
+    }
+
+    Ok(report)
+}
+
+fn is_valid_lmem_head<R: Fn(u64, &mut [u8]) -> Result<()>>(read_memory: &R, memory_map: &[MemoryMapEntry], head_address: u64) -> bool {
+    let start = read_u64(read_memory, head_address + LMEM_HEAP_HEAD_START_OFFSET).unwrap_or(0);
+    let end = read_u64(read_memory, head_address + LMEM_HEAP_HEAD_END_OFFSET).unwrap_or(0);
+    (start > 0) && (start < end) && memory_map.iter().any(|entry| entry.contains(start) && (end <= entry.end()))
+}
+
+// Searches writable memory for lmem heap heads, returning their addresses
+pub fn find_lmem_heaps<R: Fn(u64, &mut [u8]) -> Result<()>>(read_memory: &R, memory_map: &[MemoryMapEntry]) -> Vec<u64> {
+    let mut head_addresses: Vec<u64> = Vec::new();
+    for entry in memory_map.iter().filter(|entry| entry.perm.contains(MemoryPermission::WRITE)) {
+        let mut offset: usize = 0;
+        while offset < entry.size {
+            let chunk_size = HEAP_SCAN_CHUNK_SIZE.min(entry.size - offset);
+            let mut data: Vec<u8> = vec![0; chunk_size];
+            if read_memory(entry.address + offset as u64, &mut data).is_ok() {
+                // Heads are pointer-aligned
+                for (i, magic) in data.chunks_exact(8).map(|word| &word[..4]).enumerate() {
+                    if (magic == &LMEM_EXP_HEAP_MAGIC[..]) || (magic == &LMEM_UNIT_HEAP_MAGIC[..]) {
+                        let head_address = entry.address + (offset + i * 8) as u64;
+                        if is_valid_lmem_head(read_memory, memory_map, head_address) {
+                            head_addresses.push(head_address);
+                        }
+                    }
+                }
+            }
+            offset += chunk_size;
+        }
+    }
+    head_addresses
+}
+
+// Walks every lmem heap found in memory
+pub fn analyze_lmem_heaps<R: Fn(u64, &mut [u8]) -> Result<()>>(read_memory: &R, memory_map: &[MemoryMapEntry]) -> Vec<HeapReport> {
+    find_lmem_heaps(read_memory, memory_map).into_iter().filter_map(|head_address| walk_lmem_heap(read_memory, head_address).ok()).collect()
+}
+
+// Heap blocks are only listed up to this amount, summaries being shown otherwise
+pub const MAX_LISTED_BLOCK_COUNT: usize = 32;
+
+// Report lines for logs and the CLI: the summary, then the problems and the used blocks
+pub fn format_report(report: &HeapReport) -> Vec<String> {
+    let mut lines = vec![report.to_string()];
+    lines.extend(report.problems.iter().map(|problem| format!(" !! {}", problem)));
+
+    let used_block_count = report.get_used_blocks().count();
+    for block in report.get_used_blocks().take(MAX_LISTED_BLOCK_COUNT) {
+        match block.group_id {
+            Some(group_id) => lines.push(format!(" -- {:#012X} size {:#X} (group {})", block.address, block.size, group_id)),
+            None => lines.push(format!(" -- {:#012X} size {:#X}", block.address, block.size))
+        };
+    }
+    if used_block_count > MAX_LISTED_BLOCK_COUNT {
+        lines.push(format!(" -- ... ({} more used blocks)", used_block_count - MAX_LISTED_BLOCK_COUNT));
+    }
+    lines
+}
@@ -0,0 +1,217 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+use parking_lot::Mutex;
+use crate::emu::cfg;
+use crate::result::*;
+use crate::util::convert_io_result;
+
+// Host profiler: spans around the emulator's own hot paths (SVC dispatch, IPC translation/dispatch, scheduling, fs reads) are timed, and the time spent in each stack of spans is saved at exit as folded stacks ("<thread>;<span>;<span> <time>" lines)
+// Those are what flamegraph tools (inferno, flamegraph.pl) take, thus no profiling crate is needed at all
+// Note: unlike emu::profiler this doesn't sample, every span is timed while enabled (and nothing is done otherwise)
+
+static mut G_HOST_PROFILER_ENABLED: AtomicBool = AtomicBool::new(false);
+// Folded stack -> self time (in nanoseconds)
+static mut G_FOLDED_STACKS: Mutex<BTreeMap<String, u64>> = parking_lot::const_mutex(BTreeMap::new());
+
+// Like profiler samples, span times are accumulated per host thread and only published every now and then
+const SPAN_FLUSH_INTERVAL: u64 = 0x400;
+
+struct SpanFrame {
+    name: Cow<'static, str>,
+    start_instant: Instant,
+    child_time_ns: u64
+}
+
+#[thread_local]
+static mut G_SPAN_STACK: Vec<SpanFrame> = Vec::new();
+
+#[thread_local]
+static mut G_PENDING_STACKS: Option<HashMap<String, u64>> = None;
+
+#[thread_local]
+static mut G_PENDING_SPAN_COUNT: u64 = 0;
+
+#[inline]
+pub fn is_enabled() -> bool {
+    unsafe {
+        G_HOST_PROFILER_ENABLED.load(Ordering::Relaxed)
+    }
+}
+
+pub fn set_enabled(enabled: bool) {
+    unsafe {
+        G_HOST_PROFILER_ENABLED.store(enabled, Ordering::SeqCst);
+    }
+}
+
+// Ends the span when dropped, thus spans are meant to be held as "let _span = host_profiler::enter(...);"
+pub struct HostSpan {
+    is_active: bool
+}
+
+impl Drop for HostSpan {
+    fn drop(&mut self) {
+        if self.is_active {
+            exit_span();
+        }
+    }
+}
+
+#[inline]
+pub fn enter(name: &'static str) -> HostSpan {
+    enter_with(|| name)
+}
+
+// The name is only made if the profiler is enabled (for names which need formatting)
+#[inline]
+pub fn enter_with<N: Into<Cow<'static, str>>, F: FnOnce() -> N>(make_name: F) -> HostSpan {
+    // Note: whether the span was actually entered is kept, in case the profiler gets enabled/disabled meanwhile
+    let is_active = is_enabled();
+    if is_active {
+        unsafe {
+            G_SPAN_STACK.push(SpanFrame {
+                name: make_name().into(),
+                start_instant: Instant::now(),
+                child_time_ns: 0
+            });
+        }
+    }
+
+    HostSpan {
+        is_active: is_active
+    }
+}
+
+// Folded stack frames can't contain the separators of the format
+fn get_frame_name(name: &str) -> String {
+    name.replace(';', ":").replace(' ', "_")
+}
+
+fn exit_span() {
+    unsafe {
+        let frame = match G_SPAN_STACK.pop() {
+            Some(frame) => frame,
+            None => return
+        };
+
+        let time_ns = frame.start_instant.elapsed().as_nanos() as u64;
+        if let Some(parent_frame) = G_SPAN_STACK.last_mut() {
+            parent_frame.child_time_ns += time_ns;
+        }
+
+        // Spans are attributed to the host thread they ran on (thus to the guest thread or emulated process it belongs to)
+        let mut folded_stack = get_frame_name(std::thread::current().name().unwrap_or("<unnamed>"));
+        for stack_frame in G_SPAN_STACK.iter().chain(std::iter::once(&frame)) {
+            folded_stack.push(';');
+            folded_stack.push_str(&get_frame_name(&stack_frame.name));
+        }
+
+        let self_time_ns = time_ns.saturating_sub(frame.child_time_ns);
+        *G_PENDING_STACKS.get_or_insert_with(HashMap::new).entry(folded_stack).or_insert(0) += self_time_ns;
+        G_PENDING_SPAN_COUNT += 1;
+        if G_PENDING_SPAN_COUNT >= SPAN_FLUSH_INTERVAL {
+            flush_spans();
+        }
+    }
+}
+
+pub fn flush_spans() {
+    unsafe {
+        let pending_stacks = match G_PENDING_STACKS.take() {
+            Some(pending_stacks) => pending_stacks,
+            None => return
+        };
+        G_PENDING_SPAN_COUNT = 0;
+
+        let mut folded_stacks = G_FOLDED_STACKS.lock();
+        for (folded_stack, time_ns) in pending_stacks.into_iter() {
+            *folded_stacks.entry(folded_stack).or_insert(0) += time_ns;
+        }
+    }
+}
+
+pub fn reset() {
+    unsafe {
+        G_FOLDED_STACKS.lock().clear();
+    }
+}
+
+// Times are saved in microseconds, stacks which took less than that overall are left out
+// Returns how many stacks were saved
+pub fn save_folded_stacks(path: &str) -> Result<usize> {
+    // Note: span times still pending in other threads (less than the flush interval) are not included
+    flush_spans();
+    let folded_stacks = unsafe {
+        G_FOLDED_STACKS.lock().clone()
+    };
+
+    let mut file = convert_io_result(File::create(path))?;
+    let mut stack_count: usize = 0;
+    for (folded_stack, time_ns) in folded_stacks.iter() {
+        let time_us = time_ns / 1000;
+        if time_us > 0 {
+            convert_io_result(writeln!(file, "{} {}", folded_stack, time_us))?;
+            stack_count += 1;
+        }
+    }
+
+    Ok(stack_count)
+}
+
+pub fn save_report() {
+    if is_enabled() {
+        let output_path = cfg::get_config().host_profiler_output_path.clone();
+        match save_folded_stacks(&output_path) {
+            Ok(stack_count) => log_line!("[host-profiler] Saved {} folded stacks to '{}'", stack_count, output_path),
+            Err(rc) => log_line!("[host-profiler] Unable to save the folded stacks to '{}': {1} ({1:?})", output_path, rc)
+        };
+    }
+}
+
+pub fn initialize() {
+    if cfg::get_config().host_profiler_enabled {
+        set_enabled(true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::util::TestTempDir;
+    use super::*;
+
+    #[test]
+    fn folded_stacks() {
+        let temp_dir = TestTempDir::new("host_profiler_folded_stacks");
+        let path = temp_dir.join_str("host_profile.folded");
+        let was_enabled = is_enabled();
+
+        set_enabled(true);
+        {
+            let _outer_span = enter("test_outer");
+            std::thread::sleep(Duration::from_millis(2));
+            let _inner_span = enter_with(|| format!("test_inner:{}", 1));
+            std::thread::sleep(Duration::from_millis(2));
+        }
+        set_enabled(was_enabled);
+
+        save_folded_stacks(&path).unwrap();
+        let folded_stacks = std::fs::read_to_string(&path).unwrap();
+
+        // Each stack is "<thread>;<span>;<span> <self time in us>", nested spans not counting towards the time of their parents
+        for expected_stack in [";test_outer", ";test_outer;test_inner:1"].iter() {
+            let stack_time = folded_stacks.lines().find_map(|line| {
+                let (stack, time) = line.rsplit_once(' ')?;
+                match stack.ends_with(expected_stack) {
+                    true => time.parse::<u64>().ok(),
+                    false => None
+                }
+            });
+            assert!(matches!(stack_time, Some(time_us) if (time_us >= 2000) && (time_us < 1000000)), "unexpected time for stack '{}': {:?}", expected_stack, stack_time);
+        }
+    }
+}
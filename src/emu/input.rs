@@ -0,0 +1,206 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use parking_lot::Mutex;
+use crate::kern::get_system_tick;
+use crate::result::*;
+use crate::util::convert_io_result;
+
+// TAS-style input recording/replay: every change of a controller's input state is recorded along with the system tick it happened at, and replaying feeds the recorded states back at the same ticks instead of the host input
+// Note: hid isn't emulated yet, it's meant to get the state of each npad through get_npad_state whenever it updates its shared memory
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct NpadInputState {
+    pub buttons: u64,
+    pub left_stick: (i32, i32),
+    pub right_stick: (i32, i32)
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct InputRecordEntry {
+    pub tick: u64,
+    pub npad_id: u32,
+    pub state: NpadInputState
+}
+
+impl InputRecordEntry {
+    // Recording line format: "<tick> npad=<id> buttons=<hex> lx=<x> ly=<y> rx=<x> ry=<y>"
+    pub fn to_line(&self) -> String {
+        format!("{} npad={} buttons={:#X} lx={} ly={} rx={} ry={}", self.tick, self.npad_id, self.state.buttons, self.state.left_stick.0, self.state.left_stick.1, self.state.right_stick.0, self.state.right_stick.1)
+    }
+
+    pub fn from_line(line: &str) -> Result<Self> {
+        let mut tokens = line.split_whitespace();
+        let tick = tokens.next().and_then(|tick| tick.parse::<u64>().ok());
+
+        let mut values: BTreeMap<&str, &str> = BTreeMap::new();
+        for token in tokens {
+            let (key, value) = token.split_once('=').ok_or(ResultInvalidInputRecording::make())?;
+            values.insert(key, value);
+        }
+        let get_i32 = |key: &str| values.get(key).and_then(|value| value.parse::<i32>().ok()).ok_or(ResultInvalidInputRecording::make());
+        let buttons = values.get("buttons").and_then(|buttons| u64::from_str_radix(buttons.trim_start_matches("0x").trim_start_matches("0X"), 16).ok());
+
+        match (tick, values.get("npad").and_then(|npad_id| npad_id.parse::<u32>().ok()), buttons) {
+            (Some(tick), Some(npad_id), Some(buttons)) => Ok(Self {
+                tick: tick,
+                npad_id: npad_id,
+                state: NpadInputState {
+                    buttons: buttons,
+                    left_stick: (get_i32("lx")?, get_i32("ly")?),
+                    right_stick: (get_i32("rx")?, get_i32("ry")?)
+                }
+            }),
+            _ => ResultInvalidInputRecording::make_err()
+        }
+    }
+}
+
+pub fn save_input_recording(path: &str, entries: &[InputRecordEntry]) -> Result<()> {
+    let mut file = convert_io_result(File::create(path))?;
+    for entry in entries {
+        convert_io_result(writeln!(file, "{}", entry.to_line()))?;
+    }
+
+    Ok(())
+}
+
+pub fn load_input_recording(path: &str) -> Result<Vec<InputRecordEntry>> {
+    let file = convert_io_result(File::open(path))?;
+    let mut entries: Vec<InputRecordEntry> = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = convert_io_result(line)?;
+        if !line.trim().is_empty() {
+            entries.push(InputRecordEntry::from_line(&line)?);
+        }
+    }
+
+    // Entries are applied in tick order, regardless of how the file was written
+    entries.sort_by_key(|entry| entry.tick);
+    Ok(entries)
+}
+
+enum InputMode {
+    Passthrough,
+    Recording {
+        path: String,
+        entries: Vec<InputRecordEntry>,
+        last_states: BTreeMap<u32, NpadInputState>
+    },
+    Replaying {
+        entries: Vec<InputRecordEntry>,
+        next_idx: usize,
+        cur_states: BTreeMap<u32, NpadInputState>
+    }
+}
+
+static mut G_INPUT_MODE: Mutex<InputMode> = parking_lot::const_mutex(InputMode::Passthrough);
+
+// The recording is only written once stopped (see stop_recording)
+pub fn start_recording(path: String) {
+    unsafe {
+        *G_INPUT_MODE.lock() = InputMode::Recording {
+            path: path,
+            entries: Vec::new(),
+            last_states: BTreeMap::new()
+        };
+    }
+}
+
+// Returns how many entries were saved, if recording at all
+pub fn stop_recording() -> Result<Option<usize>> {
+    let mode = unsafe { std::mem::replace(&mut *G_INPUT_MODE.lock(), InputMode::Passthrough) };
+    match mode {
+        InputMode::Recording { path, entries, .. } => {
+            save_input_recording(&path, &entries)?;
+            Ok(Some(entries.len()))
+        },
+        _ => Ok(None)
+    }
+}
+
+pub fn start_replay(path: &str) -> Result<()> {
+    let entries = load_input_recording(path)?;
+    unsafe {
+        *G_INPUT_MODE.lock() = InputMode::Replaying {
+            entries: entries,
+            next_idx: 0,
+            cur_states: BTreeMap::new()
+        };
+    }
+
+    Ok(())
+}
+
+pub fn is_replay_finished() -> bool {
+    match unsafe { &*G_INPUT_MODE.lock() } {
+        InputMode::Replaying { entries, next_idx, .. } => *next_idx >= entries.len(),
+        _ => true
+    }
+}
+
+// The state hid should report for the npad: the host's one (recorded if it changed, when recording) or the recorded one for the current tick (when replaying)
+// Note: npads without any recorded state yet are neutral (nothing pressed) while replaying
+pub fn get_npad_state(npad_id: u32, host_state: NpadInputState) -> NpadInputState {
+    let tick = get_system_tick();
+    let mut mode = unsafe { G_INPUT_MODE.lock() };
+    match &mut *mode {
+        InputMode::Passthrough => host_state,
+        InputMode::Recording { entries, last_states, .. } => {
+            let last_state = last_states.get(&npad_id).copied().unwrap_or_default();
+            if host_state != last_state {
+                entries.push(InputRecordEntry {
+                    tick: tick,
+                    npad_id: npad_id,
+                    state: host_state
+                });
+                last_states.insert(npad_id, host_state);
+            }
+            host_state
+        },
+        InputMode::Replaying { entries, next_idx, cur_states } => {
+            while (*next_idx < entries.len()) && (entries[*next_idx].tick <= tick) {
+                let entry = entries[*next_idx];
+                cur_states.insert(entry.npad_id, entry.state);
+                *next_idx += 1;
+            }
+            cur_states.get(&npad_id).copied().unwrap_or_default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::TestTempDir;
+    use super::*;
+
+    // Recorded input changes are replayed as they were, while the host input is ignored meanwhile
+    #[test]
+    fn record_replay() {
+        let temp_dir = TestTempDir::new("input_record_replay");
+        let path = temp_dir.join_str("recording.txt");
+        let pressed_state = NpadInputState {
+            buttons: 0x1,
+            left_stick: (0x7FFF, 0),
+            right_stick: (0, -0x7FFF)
+        };
+
+        start_recording(path.clone());
+        get_npad_state(0, NpadInputState::default());
+        get_npad_state(0, pressed_state);
+        get_npad_state(0, pressed_state);
+        let entry_count = stop_recording().unwrap();
+        assert_eq!(entry_count, Some(1), "expected a single recorded change");
+
+        start_replay(&path).unwrap();
+
+        // The recorded tick already passed, thus the change applies right away
+        let replayed_state = get_npad_state(0, NpadInputState::default());
+        let other_npad_state = get_npad_state(1, pressed_state);
+        let is_finished = is_replay_finished();
+        let _ = stop_recording();
+        assert_eq!(replayed_state, pressed_state);
+        assert_eq!(other_npad_state, NpadInputState::default(), "expected the unrecorded npad to be neutral");
+        assert!(is_finished, "the replay didn't finish");
+    }
+}
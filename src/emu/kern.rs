@@ -1,11 +1,27 @@
 use std::collections::BTreeMap;
-use std::mem;
-use crate::emu::cpu;
+use crate::emu::cpu::{self, GuestPtr, StringDecodeMode};
 use crate::kern::svc::{self, BreakReason, Handle};
+use crate::ldr::npdm::NpdmData;
 use crate::result::*;
 
 static mut G_SVC_HANDLERS: BTreeMap<svc::SvcId, cpu::HookedInstructionHandlerFn> = BTreeMap::new();
 
+// Port names are at most 11 characters long, plus the NUL terminator
+const MAX_PORT_NAME_LEN: usize = 0xC;
+
+// Failing to access guest memory (through GuestPtr) means the guest passed an invalid pointer, which is returned as the SVC result
+macro_rules! guest_try {
+    ($ctx_h:expr, $r:expr) => {
+        match $r {
+            Ok(val) => val,
+            Err(rc) => {
+                $ctx_h.write_register(cpu::Register::W0, rc)?;
+                return Ok(());
+            }
+        }
+    };
+}
+
 fn do_sleep_thread(mut ctx_h: cpu::ContextHandle) -> Result<()> {
     let timeout: i64 = ctx_h.read_register(cpu::Register::X0)?;
 
@@ -14,6 +30,51 @@ fn do_sleep_thread(mut ctx_h: cpu::ContextHandle) -> Result<()> {
     Ok(())
 }
 
+fn do_get_thread_priority(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let thread_handle: Handle = ctx_h.read_register(cpu::Register::W1)?;
+
+    match svc::get_thread_priority(thread_handle) {
+        Ok(priority) => {
+            ctx_h.write_register(cpu::Register::W0, ResultSuccess::make())?;
+            ctx_h.write_register(cpu::Register::W1, priority as u32)?;
+        },
+        Err(rc) => {
+            ctx_h.write_register(cpu::Register::W0, rc)?;
+        }
+    };
+    Ok(())
+}
+
+fn do_get_current_processor_number(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let cpu_core = svc::get_current_processor_number();
+    ctx_h.write_register(cpu::Register::W0, cpu_core as u32)?;
+    Ok(())
+}
+
+fn do_get_system_tick(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let tick = svc::get_system_tick();
+    ctx_h.write_register(cpu::Register::X0, tick)?;
+    Ok(())
+}
+
+fn do_get_info(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let info_type: u32 = ctx_h.read_register(cpu::Register::W1)?;
+    let handle: Handle = ctx_h.read_register(cpu::Register::W2)?;
+    let info_subtype: u64 = ctx_h.read_register(cpu::Register::X3)?;
+
+    match svc::get_info(info_type, handle, info_subtype) {
+        Ok(info) => {
+            ctx_h.write_register(cpu::Register::W0, ResultSuccess::make())?;
+            ctx_h.write_register(cpu::Register::X1, info)?;
+        },
+        Err(rc) => {
+            ctx_h.write_register(cpu::Register::W0, rc)?;
+        }
+    };
+
+    Ok(())
+}
+
 fn do_close_handle(mut ctx_h: cpu::ContextHandle) -> Result<()> {
     let handle: Handle = ctx_h.read_register(cpu::Register::W0)?;
 
@@ -27,13 +88,7 @@ fn do_wait_synchronization(mut ctx_h: cpu::ContextHandle) -> Result<()> {
     let handles_count: u32 = ctx_h.read_register(cpu::Register::W2)?;
     let timeout: i64 = ctx_h.read_register(cpu::Register::X3)?;
 
-    let mut handles: Vec<Handle> = Vec::with_capacity(handles_count as usize);
-    let mut read_offset = handles_addr;
-    for _ in 0..handles_count {
-        let handle: Handle = ctx_h.read_memory_val(read_offset)?;
-        handles.push(handle);
-        read_offset += mem::size_of_val(&handle) as u64;
-    }
+    let handles = guest_try!(ctx_h, GuestPtr::<Handle>::new(handles_addr).read_array(&ctx_h, handles_count as usize));
 
     match svc::wait_synchronization(&handles, timeout) {
         Ok(idx) => {
@@ -48,23 +103,36 @@ fn do_wait_synchronization(mut ctx_h: cpu::ContextHandle) -> Result<()> {
     Ok(())
 }
 
+fn do_signal_event(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let event_handle: Handle = ctx_h.read_register(cpu::Register::W0)?;
+
+    let rc = ResultCode::from(svc::signal_event(event_handle));
+    ctx_h.write_register(cpu::Register::W0, rc)?;
+    Ok(())
+}
+
+fn do_clear_event(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let event_handle: Handle = ctx_h.read_register(cpu::Register::W0)?;
+
+    let rc = ResultCode::from(svc::clear_event(event_handle));
+    ctx_h.write_register(cpu::Register::W0, rc)?;
+    Ok(())
+}
+
+fn do_reset_signal(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let handle: Handle = ctx_h.read_register(cpu::Register::W0)?;
+
+    let rc = ResultCode::from(svc::reset_signal(handle));
+    ctx_h.write_register(cpu::Register::W0, rc)?;
+    Ok(())
+}
+
 fn do_connect_to_named_port(mut ctx_h: cpu::ContextHandle) -> Result<()> {
     let port_name_addr: u64 = ctx_h.read_register(cpu::Register::X1)?;
 
-    let mut port_name_buf: Vec<u8> = Vec::new();
-    let mut read_offset = port_name_addr;
-    loop {
-        let byte: u8 = ctx_h.read_memory_val(read_offset)?;
-        if byte == 0 {
-            break;
-        }
-        port_name_buf.push(byte);
-        read_offset += mem::size_of_val(&byte) as u64;
-    }
+    let port_name = guest_try!(ctx_h, GuestPtr::<u8>::new(port_name_addr).read_c_str(&ctx_h, MAX_PORT_NAME_LEN, StringDecodeMode::Strict));
 
-    let port_name = std::str::from_utf8(&port_name_buf).unwrap();
-
-    match svc::connect_to_named_port(port_name) {
+    match svc::connect_to_named_port(&port_name) {
         Ok(handle) => {
             ctx_h.write_register(cpu::Register::W0, ResultSuccess::make())?;
             ctx_h.write_register(cpu::Register::W1, handle)?;
@@ -85,36 +153,116 @@ fn do_send_sync_request(mut ctx_h: cpu::ContextHandle) -> Result<()> {
     Ok(())
 }
 
+fn do_arbitrate_lock(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let owner_thread_handle: Handle = ctx_h.read_register(cpu::Register::W0)?;
+    let mutex_address: u64 = ctx_h.read_register(cpu::Register::X1)?;
+    let requester_thread_handle: Handle = ctx_h.read_register(cpu::Register::W2)?;
+
+    let rc = ResultCode::from(svc::arbitrate_lock(owner_thread_handle, mutex_address, requester_thread_handle));
+    ctx_h.write_register(cpu::Register::W0, rc)?;
+    Ok(())
+}
+
+fn do_arbitrate_unlock(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let mutex_address: u64 = ctx_h.read_register(cpu::Register::X0)?;
+
+    let rc = ResultCode::from(svc::arbitrate_unlock(mutex_address));
+    ctx_h.write_register(cpu::Register::W0, rc)?;
+    Ok(())
+}
+
 fn do_break(mut ctx_h: cpu::ContextHandle) -> Result<()> {
     let reason: BreakReason = ctx_h.read_register(cpu::Register::W0)?;
     let arg_addr: u64 = ctx_h.read_register(cpu::Register::X1)?;
     let arg_len: usize = ctx_h.read_register(cpu::Register::X2)?;
 
-    let mut arg: Vec<u8> = vec![0; arg_len];
-    if arg_len > 0 {
-        ctx_h.read_memory(arg_addr, &mut arg)?;
-    }
+    let arg = guest_try!(ctx_h, GuestPtr::<u8>::new(arg_addr).read_bytes(&ctx_h, arg_len));
 
     let rc = ResultCode::from(svc::break_(reason, &arg));
     ctx_h.write_register(cpu::Register::W0, rc)?;
     Ok(())
 }
 
+fn do_set_thread_activity(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let thread_handle: Handle = ctx_h.read_register(cpu::Register::W0)?;
+    let activity: u32 = ctx_h.read_register(cpu::Register::W1)?;
+
+    let rc = ResultCode::from(svc::set_thread_activity(thread_handle, activity));
+    ctx_h.write_register(cpu::Register::W0, rc)?;
+    Ok(())
+}
+
+fn do_set_process_activity(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let process_handle: Handle = ctx_h.read_register(cpu::Register::W0)?;
+    let activity: u32 = ctx_h.read_register(cpu::Register::W1)?;
+
+    let rc = ResultCode::from(svc::set_process_activity(process_handle, activity));
+    ctx_h.write_register(cpu::Register::W0, rc)?;
+    Ok(())
+}
+
+fn do_return_from_exception(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let rc: ResultCode = ctx_h.read_register(cpu::Register::W0)?;
+
+    // Note: on success, the registers are restored to the state before the exception (thus nothing is written here)
+    if let Err(rc) = svc::return_from_exception(rc) {
+        ctx_h.write_register(cpu::Register::W0, rc)?;
+    }
+    Ok(())
+}
+
 fn do_output_debug_string(mut ctx_h: cpu::ContextHandle) -> Result<()> {
     let str_addr: u64 = ctx_h.read_register(cpu::Register::X0)?;
     let str_len: usize = ctx_h.read_register(cpu::Register::X1)?;
 
-    let mut str_buf: Vec<u8> = vec![0; str_len];
-    if str_len > 0 {
-        ctx_h.read_memory(str_addr, &mut str_buf)?;
-    }
-    let msg = std::str::from_utf8(&str_buf).unwrap();
+    // Note: debug strings may contain anything, so they're never rejected because of their encoding
+    let msg = guest_try!(ctx_h, GuestPtr::<u8>::new(str_addr).read_str(&ctx_h, str_len, StringDecodeMode::Lossy));
 
-    let rc = ResultCode::from(svc::output_debug_string(msg));
+    let rc = ResultCode::from(svc::output_debug_string(&msg));
     ctx_h.write_register(cpu::Register::W0, rc)?;
     Ok(())
 }
 
+fn write_last_thread_context(ctx_h: &mut cpu::ContextHandle, context: svc::LastThreadContext) -> Result<()> {
+    ctx_h.write_register(cpu::Register::X1, context.fp)?;
+    ctx_h.write_register(cpu::Register::X2, context.sp)?;
+    ctx_h.write_register(cpu::Register::X3, context.lr)?;
+    ctx_h.write_register(cpu::Register::X4, context.pc)
+}
+
+fn do_get_future_thread_info(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let timeout: i64 = ctx_h.read_register(cpu::Register::X0)?;
+
+    match svc::get_future_thread_info(timeout) {
+        Ok((context, thread_id)) => {
+            ctx_h.write_register(cpu::Register::W0, ResultSuccess::make())?;
+            write_last_thread_context(&mut ctx_h, context)?;
+            ctx_h.write_register(cpu::Register::X5, thread_id)?;
+        },
+        Err(rc) => {
+            ctx_h.write_register(cpu::Register::W0, rc)?;
+        }
+    };
+
+    Ok(())
+}
+
+fn do_get_last_thread_info(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    match svc::get_last_thread_info() {
+        Ok((context, tls_address, flags)) => {
+            ctx_h.write_register(cpu::Register::W0, ResultSuccess::make())?;
+            write_last_thread_context(&mut ctx_h, context)?;
+            ctx_h.write_register(cpu::Register::X5, tls_address)?;
+            ctx_h.write_register(cpu::Register::W6, flags.get())?;
+        },
+        Err(rc) => {
+            ctx_h.write_register(cpu::Register::W0, rc)?;
+        }
+    };
+
+    Ok(())
+}
+
 fn do_create_session(mut ctx_h: cpu::ContextHandle) -> Result<()> {
     let is_light: bool = ctx_h.read_register(cpu::Register::W2)?;
     let name_addr: u64 = ctx_h.read_register(cpu::Register::X3)?;
@@ -133,6 +281,21 @@ fn do_create_session(mut ctx_h: cpu::ContextHandle) -> Result<()> {
     Ok(())
 }
 
+fn do_create_event(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    match svc::create_event() {
+        Ok((writable_event_handle, readable_event_handle)) => {
+            ctx_h.write_register(cpu::Register::W0, ResultSuccess::make())?;
+            ctx_h.write_register(cpu::Register::W1, writable_event_handle)?;
+            ctx_h.write_register(cpu::Register::W2, readable_event_handle)?;
+        },
+        Err(rc) => {
+            ctx_h.write_register(cpu::Register::W0, rc)?;
+        }
+    }
+
+    Ok(())
+}
+
 fn do_accept_session(mut ctx_h: cpu::ContextHandle) -> Result<()> {
     let server_port_handle: Handle = ctx_h.read_register(cpu::Register::W1)?;
 
@@ -155,13 +318,7 @@ fn do_reply_and_receive(mut ctx_h: cpu::ContextHandle) -> Result<()> {
     let reply_target_session_handle: Handle = ctx_h.read_register(cpu::Register::W3)?;
     let timeout: i64 = ctx_h.read_register(cpu::Register::X4)?;
 
-    let mut handles: Vec<Handle> = Vec::with_capacity(handles_count as usize);
-    let mut read_offset = handles_addr;
-    for _ in 0..handles_count {
-        let handle: Handle = ctx_h.read_memory_val(read_offset)?;
-        handles.push(handle);
-        read_offset += mem::size_of_val(&handle) as u64;
-    }
+    let handles = guest_try!(ctx_h, GuestPtr::<Handle>::new(handles_addr).read_array(&ctx_h, handles_count as usize));
 
     match svc::reply_and_receive(&handles, reply_target_session_handle, timeout) {
         Ok(idx) => {
@@ -199,20 +356,9 @@ fn do_manage_named_port(mut ctx_h: cpu::ContextHandle) -> Result<()> {
     let port_name_addr: u64 = ctx_h.read_register(cpu::Register::X1)?;
     let max_sessions: u32 = ctx_h.read_register(cpu::Register::W2)?;
 
-    let mut port_name_buf: Vec<u8> = Vec::new();
-    let mut read_offset = port_name_addr;
-    loop {
-        let byte: u8 = ctx_h.read_memory_val(read_offset)?;
-        if byte == 0 {
-            break;
-        }
-        port_name_buf.push(byte);
-        read_offset += mem::size_of_val(&byte) as u64;
-    }
-
-    let port_name = std::str::from_utf8(&port_name_buf).unwrap();
+    let port_name = guest_try!(ctx_h, GuestPtr::<u8>::new(port_name_addr).read_c_str(&ctx_h, MAX_PORT_NAME_LEN, StringDecodeMode::Strict));
     
-    match svc::manage_named_port(port_name, max_sessions) {
+    match svc::manage_named_port(&port_name, max_sessions) {
         Ok(handle) => {
             ctx_h.write_register(cpu::Register::W0, ResultSuccess::make())?;
             ctx_h.write_register(cpu::Register::W1, handle)?;
@@ -241,20 +387,309 @@ fn do_connect_to_port(mut ctx_h: cpu::ContextHandle) -> Result<()> {
     Ok(())
 }
 
+fn do_debug_active_process(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let process_id: u64 = ctx_h.read_register(cpu::Register::X1)?;
+
+    match svc::debug_active_process(process_id) {
+        Ok(debug_handle) => {
+            ctx_h.write_register(cpu::Register::W0, ResultSuccess::make())?;
+            ctx_h.write_register(cpu::Register::W1, debug_handle)?;
+        },
+        Err(rc) => {
+            ctx_h.write_register(cpu::Register::W0, rc)?;
+        }
+    };
+
+    Ok(())
+}
+
+fn do_break_debug_process(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let debug_handle: Handle = ctx_h.read_register(cpu::Register::W0)?;
+
+    let rc = ResultCode::from(svc::break_debug_process(debug_handle));
+    ctx_h.write_register(cpu::Register::W0, rc)?;
+    Ok(())
+}
+
+fn do_continue_debug_event(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let debug_handle: Handle = ctx_h.read_register(cpu::Register::W0)?;
+    let flags: svc::ContinueDebugFlag = ctx_h.read_register(cpu::Register::W1)?;
+    let thread_ids_addr: u64 = ctx_h.read_register(cpu::Register::X2)?;
+    let thread_id_count: u32 = ctx_h.read_register(cpu::Register::W3)?;
+
+    let thread_ids = guest_try!(ctx_h, GuestPtr::<u64>::new(thread_ids_addr).read_array(&ctx_h, thread_id_count as usize));
+
+    let rc = ResultCode::from(svc::continue_debug_event(debug_handle, flags, &thread_ids));
+    ctx_h.write_register(cpu::Register::W0, rc)?;
+    Ok(())
+}
+
+fn do_get_debug_event(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let event_info_addr: u64 = ctx_h.read_register(cpu::Register::X0)?;
+    let debug_handle: Handle = ctx_h.read_register(cpu::Register::W1)?;
+
+    match svc::get_debug_event(debug_handle) {
+        Ok(event_info) => {
+            guest_try!(ctx_h, GuestPtr::new(event_info_addr).write(&mut ctx_h, event_info));
+            ctx_h.write_register(cpu::Register::W0, ResultSuccess::make())?;
+        },
+        Err(rc) => {
+            ctx_h.write_register(cpu::Register::W0, rc)?;
+        }
+    };
+
+    Ok(())
+}
+
+// Note: the guest controls the size, thus the range is checked before anything is allocated and the memory is copied in bounded chunks
+const DEBUG_PROCESS_MEMORY_CHUNK_SIZE: usize = 0x1000;
+
+fn do_read_debug_process_memory(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let buf_addr: u64 = ctx_h.read_register(cpu::Register::X0)?;
+    let debug_handle: Handle = ctx_h.read_register(cpu::Register::W1)?;
+    let address: u64 = ctx_h.read_register(cpu::Register::X2)?;
+    let size: usize = ctx_h.read_register(cpu::Register::X3)?;
+
+    guest_try!(ctx_h, svc::check_debug_process_memory(debug_handle, address, size));
+
+    let mut buf: Vec<u8> = vec![0; size.min(DEBUG_PROCESS_MEMORY_CHUNK_SIZE)];
+    let mut offset: usize = 0;
+    while offset < size {
+        let chunk = &mut buf[..(size - offset).min(DEBUG_PROCESS_MEMORY_CHUNK_SIZE)];
+        guest_try!(ctx_h, svc::read_debug_process_memory(debug_handle, address + offset as u64, chunk));
+        guest_try!(ctx_h, GuestPtr::<u8>::new(buf_addr.wrapping_add(offset as u64)).write_bytes(&mut ctx_h, chunk));
+        offset += chunk.len();
+    }
+
+    ctx_h.write_register(cpu::Register::W0, ResultSuccess::make())?;
+    Ok(())
+}
+
+fn do_write_debug_process_memory(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let debug_handle: Handle = ctx_h.read_register(cpu::Register::W0)?;
+    let buf_addr: u64 = ctx_h.read_register(cpu::Register::X1)?;
+    let address: u64 = ctx_h.read_register(cpu::Register::X2)?;
+    let size: usize = ctx_h.read_register(cpu::Register::X3)?;
+
+    guest_try!(ctx_h, svc::check_debug_process_memory(debug_handle, address, size));
+
+    let mut offset: usize = 0;
+    while offset < size {
+        let chunk_size = (size - offset).min(DEBUG_PROCESS_MEMORY_CHUNK_SIZE);
+        let chunk = guest_try!(ctx_h, GuestPtr::<u8>::new(buf_addr.wrapping_add(offset as u64)).read_bytes(&ctx_h, chunk_size));
+        guest_try!(ctx_h, svc::write_debug_process_memory(debug_handle, address + offset as u64, &chunk));
+        offset += chunk_size;
+    }
+
+    ctx_h.write_register(cpu::Register::W0, ResultSuccess::make())?;
+    Ok(())
+}
+
+fn do_set_process_memory_permission(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let process_handle: Handle = ctx_h.read_register(cpu::Register::W0)?;
+    let address: u64 = ctx_h.read_register(cpu::Register::X1)?;
+    let size: usize = ctx_h.read_register(cpu::Register::X2)?;
+    let perm: svc::MemoryPermission = ctx_h.read_register(cpu::Register::W3)?;
+
+    let rc = ResultCode::from(svc::set_process_memory_permission(process_handle, address, size, perm));
+    ctx_h.write_register(cpu::Register::W0, rc)?;
+    Ok(())
+}
+
+fn do_map_process_memory(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let dst_address: u64 = ctx_h.read_register(cpu::Register::X0)?;
+    let process_handle: Handle = ctx_h.read_register(cpu::Register::W1)?;
+    let src_address: u64 = ctx_h.read_register(cpu::Register::X2)?;
+    let size: usize = ctx_h.read_register(cpu::Register::X3)?;
+
+    let rc = ResultCode::from(svc::map_process_memory(dst_address, process_handle, src_address, size));
+    ctx_h.write_register(cpu::Register::W0, rc)?;
+    Ok(())
+}
+
+fn do_unmap_process_memory(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let dst_address: u64 = ctx_h.read_register(cpu::Register::X0)?;
+    let process_handle: Handle = ctx_h.read_register(cpu::Register::W1)?;
+    let src_address: u64 = ctx_h.read_register(cpu::Register::X2)?;
+    let size: usize = ctx_h.read_register(cpu::Register::X3)?;
+
+    let rc = ResultCode::from(svc::unmap_process_memory(dst_address, process_handle, src_address, size));
+    ctx_h.write_register(cpu::Register::W0, rc)?;
+    Ok(())
+}
+
+fn do_query_memory(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let mem_info_addr: u64 = ctx_h.read_register(cpu::Register::X0)?;
+    let address: u64 = ctx_h.read_register(cpu::Register::X2)?;
+
+    match svc::query_memory(address) {
+        Ok((mem_info, page_info)) => {
+            guest_try!(ctx_h, GuestPtr::new(mem_info_addr).write(&mut ctx_h, mem_info));
+            ctx_h.write_register(cpu::Register::W0, ResultSuccess::make())?;
+            ctx_h.write_register(cpu::Register::W1, page_info)?;
+        },
+        Err(rc) => {
+            ctx_h.write_register(cpu::Register::W0, rc)?;
+        }
+    };
+
+    Ok(())
+}
+
+fn do_map_shared_memory(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let shmem_handle: Handle = ctx_h.read_register(cpu::Register::W0)?;
+    let address: u64 = ctx_h.read_register(cpu::Register::X1)?;
+    let size: usize = ctx_h.read_register(cpu::Register::X2)?;
+    let perm: svc::MemoryPermission = ctx_h.read_register(cpu::Register::W3)?;
+
+    let rc = ResultCode::from(svc::map_shared_memory(shmem_handle, address, size, perm));
+    ctx_h.write_register(cpu::Register::W0, rc)?;
+    Ok(())
+}
+
+fn do_unmap_shared_memory(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let shmem_handle: Handle = ctx_h.read_register(cpu::Register::W0)?;
+    let address: u64 = ctx_h.read_register(cpu::Register::X1)?;
+    let size: usize = ctx_h.read_register(cpu::Register::X2)?;
+
+    let rc = ResultCode::from(svc::unmap_shared_memory(shmem_handle, address, size));
+    ctx_h.write_register(cpu::Register::W0, rc)?;
+    Ok(())
+}
+
+fn do_flush_entire_data_cache(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let rc = ResultCode::from(svc::flush_entire_data_cache());
+    ctx_h.write_register(cpu::Register::W0, rc)?;
+    Ok(())
+}
+
+fn do_flush_data_cache(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let address: u64 = ctx_h.read_register(cpu::Register::X0)?;
+    let size: usize = ctx_h.read_register(cpu::Register::X1)?;
+
+    let rc = ResultCode::from(svc::flush_data_cache(address, size));
+    ctx_h.write_register(cpu::Register::W0, rc)?;
+    Ok(())
+}
+
+fn do_flush_process_data_cache(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let process_handle: Handle = ctx_h.read_register(cpu::Register::W0)?;
+    let address: u64 = ctx_h.read_register(cpu::Register::X1)?;
+    let size: usize = ctx_h.read_register(cpu::Register::X2)?;
+
+    let rc = ResultCode::from(svc::flush_process_data_cache(process_handle, address, size));
+    ctx_h.write_register(cpu::Register::W0, rc)?;
+    Ok(())
+}
+
+fn do_map_process_code_memory(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let process_handle: Handle = ctx_h.read_register(cpu::Register::W0)?;
+    let dst_address: u64 = ctx_h.read_register(cpu::Register::X1)?;
+    let src_address: u64 = ctx_h.read_register(cpu::Register::X2)?;
+    let size: usize = ctx_h.read_register(cpu::Register::X3)?;
+
+    let rc = ResultCode::from(svc::map_process_code_memory(process_handle, dst_address, src_address, size));
+    ctx_h.write_register(cpu::Register::W0, rc)?;
+    Ok(())
+}
+
+fn do_create_process(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let params_addr: u64 = ctx_h.read_register(cpu::Register::X1)?;
+    let kernel_caps_addr: u64 = ctx_h.read_register(cpu::Register::X2)?;
+    let kernel_cap_count: u32 = ctx_h.read_register(cpu::Register::W3)?;
+
+    let params = guest_try!(ctx_h, GuestPtr::<svc::CreateProcessParameter>::new(params_addr).read(&ctx_h));
+    let kernel_caps = guest_try!(ctx_h, GuestPtr::<u32>::new(kernel_caps_addr).read_array(&ctx_h, kernel_cap_count as usize));
+
+    match svc::create_process(params, &kernel_caps) {
+        Ok(process_handle) => {
+            ctx_h.write_register(cpu::Register::W0, ResultSuccess::make())?;
+            ctx_h.write_register(cpu::Register::W1, process_handle)?;
+        },
+        Err(rc) => {
+            ctx_h.write_register(cpu::Register::W0, rc)?;
+        }
+    };
+
+    Ok(())
+}
+
+fn do_start_process(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let process_handle: Handle = ctx_h.read_register(cpu::Register::W0)?;
+    let main_thread_priority: i32 = ctx_h.read_register(cpu::Register::W1)?;
+    let main_thread_cpu_core: i32 = ctx_h.read_register(cpu::Register::W2)?;
+    let main_thread_stack_size: usize = ctx_h.read_register(cpu::Register::X3)?;
+
+    let rc = ResultCode::from(svc::start_process(process_handle, main_thread_priority, main_thread_cpu_core, main_thread_stack_size));
+    ctx_h.write_register(cpu::Register::W0, rc)?;
+    Ok(())
+}
+
+fn do_get_process_info(mut ctx_h: cpu::ContextHandle) -> Result<()> {
+    let process_handle: Handle = ctx_h.read_register(cpu::Register::W1)?;
+    let info_type: u32 = ctx_h.read_register(cpu::Register::W2)?;
+
+    match svc::get_process_info(process_handle, info_type) {
+        Ok(info) => {
+            ctx_h.write_register(cpu::Register::W0, ResultSuccess::make())?;
+            ctx_h.write_register(cpu::Register::X1, info)?;
+        },
+        Err(rc) => {
+            ctx_h.write_register(cpu::Register::W0, rc)?;
+        }
+    };
+
+    Ok(())
+}
+
 unsafe fn create_svc_handlers() {
     G_SVC_HANDLERS.insert(svc::SvcId::SleepThread, Box::new(do_sleep_thread));
+    G_SVC_HANDLERS.insert(svc::SvcId::GetThreadPriority, Box::new(do_get_thread_priority));
+    G_SVC_HANDLERS.insert(svc::SvcId::GetCurrentProcessorNumber, Box::new(do_get_current_processor_number));
     G_SVC_HANDLERS.insert(svc::SvcId::CloseHandle, Box::new(do_close_handle));
+    G_SVC_HANDLERS.insert(svc::SvcId::GetSystemTick, Box::new(do_get_system_tick));
+    G_SVC_HANDLERS.insert(svc::SvcId::GetInfo, Box::new(do_get_info));
     G_SVC_HANDLERS.insert(svc::SvcId::WaitSynchronization, Box::new(do_wait_synchronization));
+    G_SVC_HANDLERS.insert(svc::SvcId::SignalEvent, Box::new(do_signal_event));
+    G_SVC_HANDLERS.insert(svc::SvcId::ClearEvent, Box::new(do_clear_event));
+    G_SVC_HANDLERS.insert(svc::SvcId::ResetSignal, Box::new(do_reset_signal));
     G_SVC_HANDLERS.insert(svc::SvcId::ConnectToNamedPort, Box::new(do_connect_to_named_port));
     G_SVC_HANDLERS.insert(svc::SvcId::SendSyncRequest, Box::new(do_send_sync_request));
+    G_SVC_HANDLERS.insert(svc::SvcId::ArbitrateLock, Box::new(do_arbitrate_lock));
+    G_SVC_HANDLERS.insert(svc::SvcId::ArbitrateUnlock, Box::new(do_arbitrate_unlock));
     G_SVC_HANDLERS.insert(svc::SvcId::Break, Box::new(do_break));
     G_SVC_HANDLERS.insert(svc::SvcId::OutputDebugString, Box::new(do_output_debug_string));
+    G_SVC_HANDLERS.insert(svc::SvcId::ReturnFromException, Box::new(do_return_from_exception));
+    G_SVC_HANDLERS.insert(svc::SvcId::GetFutureThreadInfo, Box::new(do_get_future_thread_info));
+    G_SVC_HANDLERS.insert(svc::SvcId::GetLastThreadInfo, Box::new(do_get_last_thread_info));
+    G_SVC_HANDLERS.insert(svc::SvcId::SetThreadActivity, Box::new(do_set_thread_activity));
+    G_SVC_HANDLERS.insert(svc::SvcId::SetProcessActivity, Box::new(do_set_process_activity));
     G_SVC_HANDLERS.insert(svc::SvcId::CreateSession, Box::new(do_create_session));
+    G_SVC_HANDLERS.insert(svc::SvcId::CreateEvent, Box::new(do_create_event));
     G_SVC_HANDLERS.insert(svc::SvcId::AcceptSession, Box::new(do_accept_session));
     G_SVC_HANDLERS.insert(svc::SvcId::ReplyAndReceive, Box::new(do_reply_and_receive));
     G_SVC_HANDLERS.insert(svc::SvcId::CreatePort, Box::new(do_create_port));
     G_SVC_HANDLERS.insert(svc::SvcId::ManageNamedPort, Box::new(do_manage_named_port));
     G_SVC_HANDLERS.insert(svc::SvcId::ConnectToPort, Box::new(do_connect_to_port));
+    G_SVC_HANDLERS.insert(svc::SvcId::DebugActiveProcess, Box::new(do_debug_active_process));
+    G_SVC_HANDLERS.insert(svc::SvcId::BreakDebugProcess, Box::new(do_break_debug_process));
+    G_SVC_HANDLERS.insert(svc::SvcId::GetDebugEvent, Box::new(do_get_debug_event));
+    G_SVC_HANDLERS.insert(svc::SvcId::ContinueDebugEvent, Box::new(do_continue_debug_event));
+    G_SVC_HANDLERS.insert(svc::SvcId::ReadDebugProcessMemory, Box::new(do_read_debug_process_memory));
+    G_SVC_HANDLERS.insert(svc::SvcId::WriteDebugProcessMemory, Box::new(do_write_debug_process_memory));
+    G_SVC_HANDLERS.insert(svc::SvcId::SetProcessMemoryPermission, Box::new(do_set_process_memory_permission));
+    G_SVC_HANDLERS.insert(svc::SvcId::MapProcessMemory, Box::new(do_map_process_memory));
+    G_SVC_HANDLERS.insert(svc::SvcId::UnmapProcessMemory, Box::new(do_unmap_process_memory));
+    G_SVC_HANDLERS.insert(svc::SvcId::QueryMemory, Box::new(do_query_memory));
+    G_SVC_HANDLERS.insert(svc::SvcId::MapSharedMemory, Box::new(do_map_shared_memory));
+    G_SVC_HANDLERS.insert(svc::SvcId::UnmapSharedMemory, Box::new(do_unmap_shared_memory));
+    G_SVC_HANDLERS.insert(svc::SvcId::FlushEntireDataCache, Box::new(do_flush_entire_data_cache));
+    G_SVC_HANDLERS.insert(svc::SvcId::FlushDataCache, Box::new(do_flush_data_cache));
+    G_SVC_HANDLERS.insert(svc::SvcId::FlushProcessDataCache, Box::new(do_flush_process_data_cache));
+    G_SVC_HANDLERS.insert(svc::SvcId::MapProcessCodeMemory, Box::new(do_map_process_code_memory));
+    G_SVC_HANDLERS.insert(svc::SvcId::CreateProcess, Box::new(do_create_process));
+    G_SVC_HANDLERS.insert(svc::SvcId::StartProcess, Box::new(do_start_process));
+    G_SVC_HANDLERS.insert(svc::SvcId::GetProcessInfo, Box::new(do_get_process_info));
 }
 
 pub fn try_find_svc_handler(key: &svc::SvcId) -> Option<&cpu::HookedInstructionHandlerFn> {
@@ -265,4 +700,72 @@ pub fn try_find_svc_handler(key: &svc::SvcId) -> Option<&cpu::HookedInstructionH
 
         G_SVC_HANDLERS.get(key)
     }
-}
\ No newline at end of file
+}
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SvcImplementationStatus {
+    Implemented,
+    // A handler exists, but some cases aren't supported yet (todo!s, faked results...)
+    Stubbed,
+    Missing
+}
+
+// Keep this updated as these get completed
+const STUBBED_SVCS: &[svc::SvcId] = &[];
+
+pub fn get_svc_implementation_status(svc_id: svc::SvcId) -> SvcImplementationStatus {
+    if try_find_svc_handler(&svc_id).is_none() {
+        SvcImplementationStatus::Missing
+    }
+    else if STUBBED_SVCS.contains(&svc_id) {
+        SvcImplementationStatus::Stubbed
+    }
+    else {
+        SvcImplementationStatus::Implemented
+    }
+}
+
+// Prints the implementation status of every SVC (see the 'svc-coverage' command), returning whether all the SVCs enabled by the given NPDM (if any) have a handler
+pub fn print_svc_coverage_report(npdm: Option<&NpdmData>) -> bool {
+    let svc_ids: Vec<svc::SvcId> = (0..=u8::MAX).filter_map(svc::SvcId::from).collect();
+    let get_status_count = |status: SvcImplementationStatus| svc_ids.iter().filter(|svc_id| get_svc_implementation_status(**svc_id) == status).count();
+
+    println!("SVC coverage: {} implemented, {} stubbed, {} missing (out of {})", get_status_count(SvcImplementationStatus::Implemented), get_status_count(SvcImplementationStatus::Stubbed), get_status_count(SvcImplementationStatus::Missing), svc_ids.len());
+    println!();
+
+    for svc_id in svc_ids.iter() {
+        let status = get_svc_implementation_status(*svc_id);
+        let enabled_str = match npdm {
+            Some(npdm) => match npdm.aci0_kernel_capabilities.enabled_svcs.contains(svc_id) {
+                true => " (enabled)",
+                false => ""
+            },
+            None => ""
+        };
+        println!("{:#04X} {:<32} {:?}{}", *svc_id as u8, format!("{:?}", svc_id), status, enabled_str);
+    }
+
+    match npdm {
+        Some(npdm) => {
+            let enabled_svcs = &npdm.aci0_kernel_capabilities.enabled_svcs;
+            let missing_svcs: Vec<svc::SvcId> = enabled_svcs.iter().copied().filter(|svc_id| get_svc_implementation_status(*svc_id) == SvcImplementationStatus::Missing).collect();
+            let stubbed_svcs: Vec<svc::SvcId> = enabled_svcs.iter().copied().filter(|svc_id| get_svc_implementation_status(*svc_id) == SvcImplementationStatus::Stubbed).collect();
+
+            println!();
+            println!("Program '{}' ({}) enables {} SVCs: {} missing, {} stubbed", npdm.meta.name.get_str().unwrap_or("<invalid>"), npdm.aci0.program_id, enabled_svcs.len(), missing_svcs.len(), stubbed_svcs.len());
+            if !missing_svcs.is_empty() {
+                println!("Missing: {:?}", missing_svcs);
+            }
+            if !stubbed_svcs.is_empty() {
+                println!("Stubbed: {:?}", stubbed_svcs);
+            }
+
+            // Note: enabled SVCs aren't necessarily used, thus this is just a pessimistic estimation
+            match missing_svcs.is_empty() {
+                true => println!("All the enabled SVCs are implemented, the program can possibly run"),
+                false => println!("The program will fail if it calls any of the missing SVCs")
+            };
+            missing_svcs.is_empty()
+        },
+        None => true
+    }
+}
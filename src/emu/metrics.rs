@@ -0,0 +1,450 @@
+use std::collections::{BTreeMap, HashSet};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use parking_lot::Mutex;
+use crate::result::*;
+use crate::util::convert_io_result;
+use crate::emu::cfg;
+use crate::kern::svc;
+use crate::kern::thread::try_get_current_thread;
+
+// Counters/histograms of where emulation time goes (SVCs, IPC, scheduling, guest code)
+// Like tracing, collection is disabled by default, since counting every single instruction isn't free
+
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    #[inline]
+    pub fn increment(&self) {
+        self.add(1);
+    }
+
+    #[inline]
+    pub fn add(&self, value: u64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// Power-of-two buckets: bucket N holds values <= 2^N, the last one holds everything else
+pub const HISTOGRAM_BUCKET_COUNT: usize = 28;
+
+pub struct Histogram {
+    buckets: [AtomicU64; HISTOGRAM_BUCKET_COUNT],
+    count: AtomicU64,
+    sum: AtomicU64
+}
+
+impl Histogram {
+    pub const fn new() -> Self {
+        const ZERO: AtomicU64 = AtomicU64::new(0);
+        Self {
+            buckets: [ZERO; HISTOGRAM_BUCKET_COUNT],
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0)
+        }
+    }
+
+    pub const fn get_bucket_upper_bound(bucket_idx: usize) -> Option<u64> {
+        match bucket_idx < HISTOGRAM_BUCKET_COUNT - 1 {
+            true => Some(1 << bucket_idx),
+            false => None
+        }
+    }
+
+    pub fn record(&self, value: u64) {
+        let bucket_idx = match value {
+            0 | 1 => 0,
+            _ => ((64 - (value - 1).leading_zeros()) as usize).min(HISTOGRAM_BUCKET_COUNT - 1)
+        };
+
+        self.buckets[bucket_idx].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+    }
+
+    // Durations are recorded in microseconds
+    pub fn record_duration(&self, duration: Duration) {
+        self.record(duration.as_micros() as u64);
+    }
+
+    pub fn get_count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub fn get_sum(&self) -> u64 {
+        self.sum.load(Ordering::Relaxed)
+    }
+
+    pub fn get_bucket_counts(&self) -> Vec<u64> {
+        self.buckets.iter().map(|bucket| bucket.load(Ordering::Relaxed)).collect()
+    }
+
+    // Upper bound of the bucket containing the given percentile (0-100), thus an approximation
+    pub fn get_percentile(&self, percentile: f64) -> Option<u64> {
+        let count = self.get_count();
+        if count == 0 {
+            return None;
+        }
+
+        let target_count = ((count as f64) * percentile / 100.0).ceil().max(1.0) as u64;
+        let mut cur_count: u64 = 0;
+        for (bucket_idx, bucket_count) in self.get_bucket_counts().iter().enumerate() {
+            cur_count += bucket_count;
+            if cur_count >= target_count {
+                return Some(Self::get_bucket_upper_bound(bucket_idx).unwrap_or(u64::MAX));
+            }
+        }
+
+        None
+    }
+}
+
+// Translation statistics of an execution context (thus of a guest thread, see cpu::ExecutionContext), to tell why some guest runs slowly
+// Note: unicorn 1 has no uc_ctl to query the actual ones, thus they are approximated through a block hook: a block counts as translated the first time it's executed since the context's code cache was last flushed
+pub struct TranslationStats {
+    executed_block_count: Counter,
+    translated_block_count: Counter,
+    code_flush_count: Counter,
+    exit_count: Counter,
+    translated_block_addresses: Mutex<HashSet<u64>>
+}
+
+impl TranslationStats {
+    pub fn new() -> Self {
+        Self {
+            executed_block_count: Counter::new(),
+            translated_block_count: Counter::new(),
+            code_flush_count: Counter::new(),
+            exit_count: Counter::new(),
+            translated_block_addresses: Mutex::new(HashSet::new())
+        }
+    }
+
+    pub fn record_block(&self, address: u64) {
+        self.executed_block_count.increment();
+        if self.translated_block_addresses.lock().insert(address) {
+            self.translated_block_count.increment();
+        }
+    }
+
+    pub fn record_code_flush(&self) {
+        self.code_flush_count.increment();
+        self.translated_block_addresses.lock().clear();
+    }
+
+    // Every time the engine stops running guest code (SVCs, exceptions, scheduling...)
+    pub fn record_exit(&self) {
+        self.exit_count.increment();
+    }
+
+    pub fn take_snapshot(&self) -> TranslationStatsSnapshot {
+        TranslationStatsSnapshot {
+            executed_block_count: self.executed_block_count.get(),
+            translated_block_count: self.translated_block_count.get(),
+            code_flush_count: self.code_flush_count.get(),
+            exit_count: self.exit_count.get()
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct TranslationStatsSnapshot {
+    pub executed_block_count: u64,
+    pub translated_block_count: u64,
+    pub code_flush_count: u64,
+    pub exit_count: u64
+}
+
+const SVC_ID_COUNT: usize = 0x80;
+
+static mut G_METRICS_ENABLED: AtomicBool = AtomicBool::new(false);
+static mut G_SVC_COUNTS: [Counter; SVC_ID_COUNT] = {
+    const ZERO: Counter = Counter::new();
+    [ZERO; SVC_ID_COUNT]
+};
+static mut G_IPC_REQUEST_LATENCY: Histogram = Histogram::new();
+static mut G_CONTEXT_SWITCH_COUNT: Counter = Counter::new();
+static mut G_THREAD_INSTRUCTION_COUNTS: Mutex<BTreeMap<u64, u64>> = parking_lot::const_mutex(BTreeMap::new());
+static mut G_THREAD_TRANSLATION_STATS: Mutex<BTreeMap<u64, Arc<TranslationStats>>> = parking_lot::const_mutex(BTreeMap::new());
+
+// Instruction counts are accumulated per host thread (thus per guest thread) and only published every now and then
+const INSTRUCTION_COUNT_FLUSH_INTERVAL: u64 = 0x10000;
+
+#[thread_local]
+static mut G_PENDING_INSTRUCTION_COUNT: u64 = 0;
+
+#[thread_local]
+static mut G_CURRENT_THREAD_ID: Option<u64> = None;
+
+#[inline]
+pub fn is_enabled() -> bool {
+    unsafe {
+        G_METRICS_ENABLED.load(Ordering::Relaxed)
+    }
+}
+
+pub fn set_enabled(enabled: bool) {
+    unsafe {
+        G_METRICS_ENABLED.store(enabled, Ordering::SeqCst);
+    }
+}
+
+pub fn record_svc(svc_id: svc::SvcId) {
+    if is_enabled() {
+        unsafe {
+            G_SVC_COUNTS[svc_id as usize].increment();
+        }
+    }
+}
+
+pub fn record_ipc_request_latency(latency: Duration) {
+    if is_enabled() {
+        unsafe {
+            G_IPC_REQUEST_LATENCY.record_duration(latency);
+        }
+    }
+}
+
+pub fn record_context_switch() {
+    if is_enabled() {
+        unsafe {
+            G_CONTEXT_SWITCH_COUNT.increment();
+        }
+    }
+}
+
+#[inline]
+pub fn record_instruction() {
+    if is_enabled() {
+        unsafe {
+            G_PENDING_INSTRUCTION_COUNT += 1;
+            if G_PENDING_INSTRUCTION_COUNT >= INSTRUCTION_COUNT_FLUSH_INTERVAL {
+                flush_instruction_count();
+            }
+        }
+    }
+}
+
+pub fn flush_instruction_count() {
+    unsafe {
+        if G_PENDING_INSTRUCTION_COUNT == 0 {
+            return;
+        }
+
+        if G_CURRENT_THREAD_ID.is_none() {
+            if let Some(thread) = try_get_current_thread() {
+                G_CURRENT_THREAD_ID = Some(thread.get().id);
+            }
+        }
+
+        if let Some(thread_id) = G_CURRENT_THREAD_ID {
+            *G_THREAD_INSTRUCTION_COUNTS.lock().entry(thread_id).or_insert(0) += G_PENDING_INSTRUCTION_COUNT;
+        }
+        G_PENDING_INSTRUCTION_COUNT = 0;
+    }
+}
+
+// Stats are only present on execution contexts created while metrics are enabled
+pub fn register_translation_stats(thread_id: u64, stats: Arc<TranslationStats>) {
+    unsafe {
+        G_THREAD_TRANSLATION_STATS.lock().insert(thread_id, stats);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MetricsSnapshot {
+    pub instant: Instant,
+    pub svc_counts: Vec<(svc::SvcId, u64)>,
+    pub ipc_request_count: u64,
+    pub ipc_request_latency_sum: u64,
+    pub ipc_request_latency_p99: Option<u64>,
+    pub context_switch_count: u64,
+    pub thread_instruction_counts: BTreeMap<u64, u64>,
+    pub thread_translation_stats: BTreeMap<u64, TranslationStatsSnapshot>
+}
+
+impl MetricsSnapshot {
+    pub fn take() -> Self {
+        unsafe {
+            let svc_counts = G_SVC_COUNTS.iter().enumerate().filter_map(|(raw_id, counter)| {
+                match (svc::SvcId::from(raw_id as u8), counter.get()) {
+                    (Some(svc_id), count) if count > 0 => Some((svc_id, count)),
+                    _ => None
+                }
+            }).collect();
+
+            Self {
+                instant: Instant::now(),
+                svc_counts: svc_counts,
+                ipc_request_count: G_IPC_REQUEST_LATENCY.get_count(),
+                ipc_request_latency_sum: G_IPC_REQUEST_LATENCY.get_sum(),
+                ipc_request_latency_p99: G_IPC_REQUEST_LATENCY.get_percentile(99.0),
+                context_switch_count: G_CONTEXT_SWITCH_COUNT.get(),
+                thread_instruction_counts: G_THREAD_INSTRUCTION_COUNTS.lock().clone(),
+                thread_translation_stats: G_THREAD_TRANSLATION_STATS.lock().iter().map(|(thread_id, stats)| (*thread_id, stats.take_snapshot())).collect()
+            }
+        }
+    }
+
+    fn get_svc_count(&self, svc_id: svc::SvcId) -> u64 {
+        self.svc_counts.iter().find(|(id, _)| *id == svc_id).map(|(_, count)| *count).unwrap_or(0)
+    }
+}
+
+// Logs what happened between both snapshots
+pub fn log_summary(prev: &MetricsSnapshot, cur: &MetricsSnapshot) {
+    const TOP_ENTRY_COUNT: usize = 8;
+
+    let elapsed_secs = cur.instant.duration_since(prev.instant).as_secs_f64().max(f64::EPSILON);
+
+    let mut svc_deltas: Vec<(svc::SvcId, u64)> = cur.svc_counts.iter().map(|(svc_id, count)| (*svc_id, count - prev.get_svc_count(*svc_id))).filter(|(_, delta)| *delta > 0).collect();
+    svc_deltas.sort_by(|(_, a), (_, b)| b.cmp(a));
+    let svc_summary: Vec<String> = svc_deltas.iter().take(TOP_ENTRY_COUNT).map(|(svc_id, delta)| format!("{:?} {:.1}/s", svc_id, *delta as f64 / elapsed_secs)).collect();
+    log_line!("[metrics] SVCs: {}", match svc_summary.is_empty() {
+        true => String::from("none"),
+        false => svc_summary.join(", ")
+    });
+
+    let ipc_request_delta = cur.ipc_request_count - prev.ipc_request_count;
+    let ipc_latency_mean = match ipc_request_delta {
+        0 => 0,
+        _ => (cur.ipc_request_latency_sum - prev.ipc_request_latency_sum) / ipc_request_delta
+    };
+    log_line!("[metrics] IPC requests: {:.1}/s, mean latency {}us, p99 (overall) <= {}us", ipc_request_delta as f64 / elapsed_secs, ipc_latency_mean, cur.ipc_request_latency_p99.unwrap_or(0));
+
+    log_line!("[metrics] Context switches: {:.1}/s", (cur.context_switch_count - prev.context_switch_count) as f64 / elapsed_secs);
+
+    let mut instruction_deltas: Vec<(u64, u64)> = cur.thread_instruction_counts.iter().map(|(thread_id, count)| (*thread_id, count - prev.thread_instruction_counts.get(thread_id).copied().unwrap_or(0))).filter(|(_, delta)| *delta > 0).collect();
+    instruction_deltas.sort_by(|(_, a), (_, b)| b.cmp(a));
+    for (thread_id, delta) in instruction_deltas.iter().take(TOP_ENTRY_COUNT) {
+        log_line!("[metrics] Thread {:#X}: {:.0} instructions/s", thread_id, *delta as f64 / elapsed_secs);
+    }
+
+    // Threads translating the most blocks come first, since translating is what's actually slow
+    let mut translation_deltas: Vec<(u64, TranslationStatsSnapshot)> = cur.thread_translation_stats.iter().map(|(thread_id, stats)| {
+        let prev_stats = prev.thread_translation_stats.get(thread_id).copied().unwrap_or_default();
+        (*thread_id, TranslationStatsSnapshot {
+            executed_block_count: stats.executed_block_count - prev_stats.executed_block_count,
+            translated_block_count: stats.translated_block_count - prev_stats.translated_block_count,
+            code_flush_count: stats.code_flush_count - prev_stats.code_flush_count,
+            exit_count: stats.exit_count - prev_stats.exit_count
+        })
+    }).filter(|(_, delta)| delta.executed_block_count > 0).collect();
+    translation_deltas.sort_by(|(_, a), (_, b)| b.translated_block_count.cmp(&a.translated_block_count));
+    for (thread_id, delta) in translation_deltas.iter().take(TOP_ENTRY_COUNT) {
+        log_line!("[metrics] Thread {:#X}: {:.0} blocks/s ({:.0} translated/s), {} code flushes, {:.1} exits/s", thread_id, delta.executed_block_count as f64 / elapsed_secs, delta.translated_block_count as f64 / elapsed_secs, delta.code_flush_count, delta.exit_count as f64 / elapsed_secs);
+    }
+}
+
+// Prometheus text exposition format
+pub fn format_prometheus() -> String {
+    let snapshot = MetricsSnapshot::take();
+    let mut out = String::new();
+
+    out.push_str("# TYPE pegasus_svc_calls_total counter\n");
+    for (svc_id, count) in snapshot.svc_counts.iter() {
+        out.push_str(&format!("pegasus_svc_calls_total{{svc=\"{:?}\"}} {}\n", svc_id, count));
+    }
+
+    out.push_str("# TYPE pegasus_ipc_request_latency_microseconds histogram\n");
+    let bucket_counts = unsafe { G_IPC_REQUEST_LATENCY.get_bucket_counts() };
+    let mut cumulative_count: u64 = 0;
+    for (bucket_idx, bucket_count) in bucket_counts.iter().enumerate() {
+        cumulative_count += bucket_count;
+        let upper_bound = match Histogram::get_bucket_upper_bound(bucket_idx) {
+            Some(upper_bound) => format!("{}", upper_bound),
+            None => String::from("+Inf")
+        };
+        out.push_str(&format!("pegasus_ipc_request_latency_microseconds_bucket{{le=\"{}\"}} {}\n", upper_bound, cumulative_count));
+    }
+    out.push_str(&format!("pegasus_ipc_request_latency_microseconds_sum {}\n", snapshot.ipc_request_latency_sum));
+    out.push_str(&format!("pegasus_ipc_request_latency_microseconds_count {}\n", snapshot.ipc_request_count));
+
+    out.push_str("# TYPE pegasus_context_switches_total counter\n");
+    out.push_str(&format!("pegasus_context_switches_total {}\n", snapshot.context_switch_count));
+
+    out.push_str("# TYPE pegasus_thread_instructions_total counter\n");
+    for (thread_id, count) in snapshot.thread_instruction_counts.iter() {
+        out.push_str(&format!("pegasus_thread_instructions_total{{thread_id=\"{:#X}\"}} {}\n", thread_id, count));
+    }
+
+    let translation_metrics: [(&str, fn(&TranslationStatsSnapshot) -> u64); 4] = [
+        ("pegasus_thread_executed_blocks_total", |stats| stats.executed_block_count),
+        ("pegasus_thread_translated_blocks_total", |stats| stats.translated_block_count),
+        ("pegasus_thread_code_flushes_total", |stats| stats.code_flush_count),
+        ("pegasus_thread_execution_exits_total", |stats| stats.exit_count)
+    ];
+    for (name, get_value) in translation_metrics.iter() {
+        out.push_str(&format!("# TYPE {} counter\n", name));
+        for (thread_id, stats) in snapshot.thread_translation_stats.iter() {
+            out.push_str(&format!("{}{{thread_id=\"{:#X}\"}} {}\n", name, thread_id, get_value(stats)));
+        }
+    }
+
+    out
+}
+
+fn handle_http_client(mut stream: TcpStream) -> Result<()> {
+    // The request itself doesn't matter, every path serves the metrics
+    let mut request_buf = [0u8; 0x400];
+    let _ = convert_io_result(stream.read(&mut request_buf))?;
+
+    let body = format_prometheus();
+    let response = format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+    convert_io_result(stream.write_all(response.as_bytes()))
+}
+
+fn http_server_thread_fn(listener: TcpListener) {
+    for stream in listener.incoming() {
+        if let Ok(stream) = stream {
+            if let Err(rc) = handle_http_client(stream) {
+                log_line!("[metrics] Failed to serve metrics: {0} ({0:?})", rc);
+            }
+        }
+    }
+}
+
+fn summary_thread_fn(interval: Duration) {
+    let mut prev_snapshot = MetricsSnapshot::take();
+    loop {
+        thread::sleep(interval);
+
+        let cur_snapshot = MetricsSnapshot::take();
+        log_summary(&prev_snapshot, &cur_snapshot);
+        prev_snapshot = cur_snapshot;
+    }
+}
+
+pub fn initialize() -> Result<()> {
+    let config = cfg::get_config();
+    if !config.metrics_enabled {
+        return Ok(());
+    }
+
+    set_enabled(true);
+
+    if config.metrics_summary_interval_secs > 0 {
+        let interval = Duration::from_secs(config.metrics_summary_interval_secs);
+        convert_io_result(thread::Builder::new().name(String::from("pg.emu.MetricsSummary")).spawn(move || summary_thread_fn(interval)))?;
+    }
+
+    if let Some(http_port) = config.metrics_http_port {
+        let listener = convert_io_result(TcpListener::bind(("127.0.0.1", http_port)))?;
+        log_line!("[metrics] Serving metrics at http://127.0.0.1:{}/metrics", http_port);
+        convert_io_result(thread::Builder::new().name(String::from("pg.emu.MetricsServer")).spawn(move || http_server_thread_fn(listener)))?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,111 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use parking_lot::Mutex;
+use crate::emu::cfg;
+use crate::ncm::ProgramId;
+
+// Guest output, following the usual nx debug output conventions: every channel (svcOutputDebugString, lm logs, fatal throws) is collected per process, and optionally written to a host file per program and channel (see cfg::Config::process_output_path)
+// Note: those files are opened for appending, thus named pipes created there beforehand work as well, letting build systems/test harnesses capture a specific process's output as it is produced
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum OutputChannel {
+    // svcOutputDebugString
+    DebugString,
+    // Log packets sent to lm
+    Log,
+    // fatal:u throws
+    Fatal
+}
+
+impl OutputChannel {
+    pub const fn get_name(&self) -> &'static str {
+        match *self {
+            Self::DebugString => "debug",
+            Self::Log => "log",
+            Self::Fatal => "fatal"
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct OutputEntry {
+    pub channel: OutputChannel,
+    pub text: String
+}
+
+struct ProcessOutput {
+    entries: VecDeque<OutputEntry>
+}
+
+// Only the most recent entries of each process are kept in memory, the host files get everything
+const MAX_PROCESS_OUTPUT_ENTRY_COUNT: usize = 0x400;
+
+// Process ID -> output
+static mut G_PROCESS_OUTPUTS: Mutex<BTreeMap<u64, ProcessOutput>> = parking_lot::const_mutex(BTreeMap::new());
+// Note: files are kept per program (not per process) so that a program launched several times keeps writing to the same file, and failed opens (None) are not retried
+static mut G_OUTPUT_FILES: Mutex<BTreeMap<(ProgramId, OutputChannel), Option<File>>> = parking_lot::const_mutex(BTreeMap::new());
+
+fn make_output_file_path(output_path: &str, program_id: ProgramId, channel: OutputChannel) -> PathBuf {
+    PathBuf::from(output_path).join(format!("{:016X}.{}.log", program_id.0, channel.get_name()))
+}
+
+fn open_output_file(output_path: &str, program_id: ProgramId, channel: OutputChannel) -> Option<File> {
+    let path = make_output_file_path(output_path, program_id, channel);
+    match OpenOptions::new().create(true).append(true).open(&path) {
+        Ok(file) => Some(file),
+        Err(err) => {
+            log_line!("Unable to open process output file '{}': {}", path.display(), err);
+            None
+        }
+    }
+}
+
+fn write_output_file(program_id: ProgramId, channel: OutputChannel, text: &str) {
+    if let Some(output_path) = cfg::get_config().process_output_path.as_ref() {
+        let mut output_files = unsafe { G_OUTPUT_FILES.lock() };
+        let file = output_files.entry((program_id, channel)).or_insert_with(|| open_output_file(output_path, program_id, channel));
+
+        if let Some(file) = file.as_mut() {
+            let mut data = String::from(text);
+            if !data.ends_with('\n') {
+                data.push('\n');
+            }
+
+            // Note: a pipe whose reader went away is not worth bringing the emulator down for
+            let _ = file.write_all(data.as_bytes());
+        }
+    }
+}
+
+pub fn write_output(process_id: u64, program_id: ProgramId, channel: OutputChannel, text: &str) {
+    {
+        let mut process_outputs = unsafe { G_PROCESS_OUTPUTS.lock() };
+        let process_output = process_outputs.entry(process_id).or_insert_with(|| ProcessOutput {
+            entries: VecDeque::new()
+        });
+
+        if process_output.entries.len() >= MAX_PROCESS_OUTPUT_ENTRY_COUNT {
+            process_output.entries.pop_front();
+        }
+        process_output.entries.push_back(OutputEntry {
+            channel: channel,
+            text: String::from(text)
+        });
+    }
+
+    write_output_file(program_id, channel, text);
+}
+
+pub fn get_process_output(process_id: u64) -> Vec<OutputEntry> {
+    let process_outputs = unsafe { G_PROCESS_OUTPUTS.lock() };
+    match process_outputs.get(&process_id) {
+        Some(process_output) => process_output.entries.iter().cloned().collect(),
+        None => Vec::new()
+    }
+}
+
+pub fn get_process_channel_output(process_id: u64, channel: OutputChannel) -> Vec<String> {
+    get_process_output(process_id).into_iter().filter(|entry| entry.channel == channel).map(|entry| entry.text).collect()
+}
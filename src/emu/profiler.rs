@@ -0,0 +1,169 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use parking_lot::Mutex;
+use crate::emu::cfg;
+use crate::emu::symbols;
+use crate::kern::proc::try_get_current_process;
+
+// Sampling profiler: every N-th executed instruction of each guest thread is sampled, and samples are attributed to modules/functions (see emu::symbols) when reporting
+
+static mut G_PROFILER_ENABLED: AtomicBool = AtomicBool::new(false);
+static mut G_SAMPLE_INTERVAL: u64 = 1;
+// (process ID, PC) -> sample count
+static mut G_SAMPLES: Mutex<BTreeMap<(u64, u64), u64>> = parking_lot::const_mutex(BTreeMap::new());
+
+// Samples are accumulated per host thread (thus per guest thread) and only published every now and then
+const SAMPLE_FLUSH_INTERVAL: u64 = 0x1000;
+
+#[thread_local]
+static mut G_INSTRUCTIONS_UNTIL_SAMPLE: u64 = 0;
+
+#[thread_local]
+static mut G_PENDING_SAMPLES: Option<HashMap<u64, u64>> = None;
+
+#[thread_local]
+static mut G_PENDING_SAMPLE_COUNT: u64 = 0;
+
+#[thread_local]
+static mut G_CURRENT_PROCESS_ID: Option<u64> = None;
+
+#[inline]
+pub fn is_enabled() -> bool {
+    unsafe {
+        G_PROFILER_ENABLED.load(Ordering::Relaxed)
+    }
+}
+
+pub fn set_enabled(enabled: bool) {
+    unsafe {
+        G_PROFILER_ENABLED.store(enabled, Ordering::SeqCst);
+    }
+}
+
+#[inline]
+pub fn record_instruction(address: u64) {
+    if is_enabled() {
+        unsafe {
+            if G_INSTRUCTIONS_UNTIL_SAMPLE > 0 {
+                G_INSTRUCTIONS_UNTIL_SAMPLE -= 1;
+                return;
+            }
+            G_INSTRUCTIONS_UNTIL_SAMPLE = G_SAMPLE_INTERVAL - 1;
+
+            *G_PENDING_SAMPLES.get_or_insert_with(HashMap::new).entry(address).or_insert(0) += 1;
+            G_PENDING_SAMPLE_COUNT += 1;
+            if G_PENDING_SAMPLE_COUNT >= SAMPLE_FLUSH_INTERVAL {
+                flush_samples();
+            }
+        }
+    }
+}
+
+pub fn flush_samples() {
+    unsafe {
+        let pending_samples = match G_PENDING_SAMPLES.take() {
+            Some(pending_samples) => pending_samples,
+            None => return
+        };
+        G_PENDING_SAMPLE_COUNT = 0;
+
+        if G_CURRENT_PROCESS_ID.is_none() {
+            if let Some(process) = try_get_current_process() {
+                G_CURRENT_PROCESS_ID = Some(process.get().id);
+            }
+        }
+
+        if let Some(process_id) = G_CURRENT_PROCESS_ID {
+            let mut samples = G_SAMPLES.lock();
+            for (address, count) in pending_samples.into_iter() {
+                *samples.entry((process_id, address)).or_insert(0) += count;
+            }
+        }
+    }
+}
+
+pub fn reset() {
+    unsafe {
+        G_SAMPLES.lock().clear();
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ProfileReport {
+    pub total_sample_count: u64,
+    // Both sorted by sample count
+    pub modules: Vec<(String, u64)>,
+    pub functions: Vec<(String, u64)>
+}
+
+impl ProfileReport {
+    pub fn make() -> Self {
+        // Note: samples still pending in other threads (less than the flush interval) are not included
+        flush_samples();
+        let samples = unsafe {
+            G_SAMPLES.lock().clone()
+        };
+
+        let mut total_sample_count: u64 = 0;
+        let mut modules: HashMap<String, u64> = HashMap::new();
+        let mut functions: HashMap<String, u64> = HashMap::new();
+        for ((process_id, address), count) in samples.into_iter() {
+            total_sample_count += count;
+
+            let (module_name, function_name) = match symbols::find_module(process_id, address) {
+                Some(module) => {
+                    let function_name = match module.lookup(address) {
+                        Some(symbol) => format!("{}!{}", module.module_name, symbol.name),
+                        None => format!("{}!<unknown>", module.module_name)
+                    };
+                    (module.module_name.clone(), function_name)
+                },
+                None => (String::from("<unknown>"), format!("{:#X}", address))
+            };
+            *modules.entry(module_name).or_insert(0) += count;
+            *functions.entry(function_name).or_insert(0) += count;
+        }
+
+        let sort_entries = |entries: HashMap<String, u64>| {
+            let mut entries: Vec<(String, u64)> = entries.into_iter().collect();
+            entries.sort_by(|(name_a, count_a), (name_b, count_b)| count_b.cmp(count_a).then(name_a.cmp(name_b)));
+            entries
+        };
+
+        Self {
+            total_sample_count: total_sample_count,
+            modules: sort_entries(modules),
+            functions: sort_entries(functions)
+        }
+    }
+
+    pub fn log(&self, entry_count: usize) {
+        let get_percentage = |count: u64| (count as f64) * 100.0 / (self.total_sample_count.max(1) as f64);
+
+        log_line!("[profiler] {} samples (1 every {} instructions)", self.total_sample_count, unsafe { G_SAMPLE_INTERVAL });
+        log_line!("[profiler] Modules:");
+        for (module_name, count) in self.modules.iter().take(entry_count) {
+            log_line!("[profiler]   {:6.2}% {:10} {}", get_percentage(*count), count, module_name);
+        }
+        log_line!("[profiler] Top functions:");
+        for (function_name, count) in self.functions.iter().take(entry_count) {
+            log_line!("[profiler]   {:6.2}% {:10} {}", get_percentage(*count), count, function_name);
+        }
+    }
+}
+
+pub fn log_report() {
+    if is_enabled() {
+        ProfileReport::make().log(cfg::get_config().profiler_report_entry_count);
+    }
+}
+
+pub fn initialize() {
+    let config = cfg::get_config();
+    if config.profiler_enabled {
+        unsafe {
+            G_SAMPLE_INTERVAL = config.profiler_sample_interval.max(1);
+        }
+        set_enabled(true);
+    }
+}
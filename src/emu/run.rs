@@ -0,0 +1,54 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+use parking_lot::Mutex;
+
+// Run modes, mostly meant for automated test pipelines: exiting with the program's exit code once its main thread finishes, skipping the periodic status output and limiting the wall-clock runtime
+// Note: the exit code is the value the main thread's entrypoint returned (W0), since ExitProcess is not supported yet
+
+// Same exit code as timeout(1), so that pipelines can tell timeouts apart from actual failures
+pub const TIMED_OUT_EXIT_CODE: i32 = 124;
+
+#[derive(Clone, Debug, Default)]
+pub struct RunOptions {
+    pub exit_on_main_exit: bool,
+    // No periodic status output, also implies exit_on_main_exit
+    pub headless: bool,
+    pub max_runtime: Option<Duration>
+}
+
+impl RunOptions {
+    pub fn from_args(args: &[String]) -> Self {
+        let has_arg = |name: &str| args.iter().any(|arg| arg == name);
+        let headless = has_arg("--headless");
+        let max_runtime = args.iter().position(|arg| arg == "--max-runtime").and_then(|idx| args.get(idx + 1)).map(|max_runtime_secs| Duration::from_secs(max_runtime_secs.parse().unwrap()));
+
+        Self {
+            exit_on_main_exit: headless || has_arg("--exit-on-main-exit"),
+            headless: headless,
+            max_runtime: max_runtime
+        }
+    }
+}
+
+// Thread ID -> exit code, once it exited (the CLI watches the program's main thread, embedders the main thread of every process they launch, see embed::Process)
+static mut G_WATCHED_THREADS: Mutex<BTreeMap<u64, Option<u32>>> = parking_lot::const_mutex(BTreeMap::new());
+
+// Must be called before the thread is started, otherwise its exit may be missed
+pub fn watch_thread_exit(thread_id: u64) {
+    unsafe {
+        G_WATCHED_THREADS.lock().insert(thread_id, None);
+    }
+}
+
+pub fn notify_thread_exit(thread_id: u64, exit_code: u32) {
+    let mut watched_threads = unsafe { G_WATCHED_THREADS.lock() };
+    if let Some(watched_exit_code) = watched_threads.get_mut(&thread_id) {
+        *watched_exit_code = Some(exit_code);
+    }
+}
+
+pub fn get_thread_exit_code(thread_id: u64) -> Option<u32> {
+    unsafe {
+        G_WATCHED_THREADS.lock().get(&thread_id).copied().flatten()
+    }
+}
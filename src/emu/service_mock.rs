@@ -0,0 +1,224 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::sync::atomic::{AtomicBool, Ordering};
+use parking_lot::Mutex;
+use serde::{Serialize, Deserialize};
+use crate::result::*;
+use crate::util::{convert_io_result, convert_serde_json_result};
+
+// Service call recording/replay: the raw replies of the given services are recorded (along with the requests they answered) while running on a complete setup, and can later be replayed by a mock server (see proc::service_mock) instead of the actual service, for working on titles without the full firmware/contents
+// Note: only plain data is recorded, since buffers aren't supported by the kernel yet and handles (like sub-interfaces) can't be recreated from a recording, thus calls whose reply carries handles are skipped
+
+// A request as recorded/looked up: the raw data words come right after the CMIF alignment padding (thus including the domain/data headers), or are all of them for TIPC
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub struct ServiceRequest {
+    pub command_type: u32,
+    pub command_id: Option<u32>,
+    pub data: Vec<u32>
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct RecordedServiceCall {
+    pub service_name: String,
+    pub request: ServiceRequest,
+    // The whole reply message, as words
+    pub reply: Vec<u32>
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default)]
+pub struct ServiceRecording {
+    pub calls: Vec<RecordedServiceCall>
+}
+
+impl ServiceRecording {
+    pub fn load(path: &str) -> Result<Self> {
+        let file = convert_io_result(File::open(path))?;
+        convert_serde_json_result(serde_json::from_reader(file))
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        let file = convert_io_result(File::create(path))?;
+        convert_serde_json_result(serde_json::to_writer_pretty(file, self))
+    }
+
+    // In order of first appearance
+    pub fn get_service_names(&self) -> Vec<String> {
+        let mut service_names: Vec<String> = Vec::new();
+        for call in self.calls.iter() {
+            if !service_names.contains(&call.service_name) {
+                service_names.push(call.service_name.clone());
+            }
+        }
+
+        service_names
+    }
+}
+
+// Replies to the requests of a single service from a recording
+// Requests with the exact same data as a recorded one get its reply, otherwise the recorded calls of the same command are replied in order (repeating the last one), since unused data words (like trailing padding) aren't necessarily the same on every run
+pub struct ServiceMock {
+    calls: Vec<RecordedServiceCall>,
+    next_call_indices: BTreeMap<(u32, Option<u32>), usize>
+}
+
+impl ServiceMock {
+    pub fn new(recording: &ServiceRecording, service_name: &str) -> Self {
+        Self {
+            calls: recording.calls.iter().filter(|call| call.service_name == service_name).cloned().collect(),
+            next_call_indices: BTreeMap::new()
+        }
+    }
+
+    pub fn get_call_count(&self) -> usize {
+        self.calls.len()
+    }
+
+    pub fn find_reply(&mut self, request: &ServiceRequest) -> Option<Vec<u32>> {
+        let candidates: Vec<&RecordedServiceCall> = self.calls.iter().filter(|call| (call.request.command_type == request.command_type) && (call.request.command_id == request.command_id)).collect();
+        if candidates.is_empty() {
+            return None;
+        }
+
+        if let Some(call) = candidates.iter().find(|call| call.request.data == request.data) {
+            return Some(call.reply.clone());
+        }
+
+        let next_call_idx = self.next_call_indices.entry((request.command_type, request.command_id)).or_insert(0);
+        let call = candidates[(*next_call_idx).min(candidates.len() - 1)];
+        *next_call_idx += 1;
+        Some(call.reply.clone())
+    }
+}
+
+struct RecordingState {
+    path: String,
+    // Empty meaning every service
+    service_names: Vec<String>,
+    recording: ServiceRecording
+}
+
+static mut G_RECORDING_ENABLED: AtomicBool = AtomicBool::new(false);
+static mut G_RECORDING_STATE: Mutex<Option<RecordingState>> = parking_lot::const_mutex(None);
+static mut G_REPLAY_RECORDING: Mutex<Option<ServiceRecording>> = parking_lot::const_mutex(None);
+
+#[inline]
+pub fn is_recording() -> bool {
+    unsafe {
+        G_RECORDING_ENABLED.load(Ordering::Relaxed)
+    }
+}
+
+// The recording is only written once stopped (see stop_recording)
+pub fn start_recording(path: String, service_names: Vec<String>) {
+    unsafe {
+        *G_RECORDING_STATE.lock() = Some(RecordingState {
+            path: path,
+            service_names: service_names,
+            recording: ServiceRecording::default()
+        });
+        G_RECORDING_ENABLED.store(true, Ordering::Relaxed);
+    }
+}
+
+pub fn is_recorded_service(service_name: &str) -> bool {
+    unsafe {
+        match G_RECORDING_STATE.lock().as_ref() {
+            Some(state) => state.service_names.is_empty() || state.service_names.iter().any(|name| name == service_name),
+            None => false
+        }
+    }
+}
+
+pub fn record_call(call: RecordedServiceCall) {
+    unsafe {
+        if let Some(state) = G_RECORDING_STATE.lock().as_mut() {
+            state.recording.calls.push(call);
+        }
+    }
+}
+
+// Returns how many calls were saved, if recording at all
+pub fn stop_recording() -> Result<Option<usize>> {
+    let state = unsafe {
+        G_RECORDING_ENABLED.store(false, Ordering::Relaxed);
+        G_RECORDING_STATE.lock().take()
+    };
+
+    match state {
+        Some(state) => {
+            state.recording.save(&state.path)?;
+            Ok(Some(state.recording.calls.len()))
+        },
+        None => Ok(None)
+    }
+}
+
+// The mock server (see proc::service_mock) serves the services in the recording loaded here
+pub fn load_replay_recording(path: &str) -> Result<()> {
+    let recording = ServiceRecording::load(path)?;
+    unsafe {
+        *G_REPLAY_RECORDING.lock() = Some(recording);
+    }
+
+    Ok(())
+}
+
+pub fn get_replay_recording() -> Option<ServiceRecording> {
+    unsafe {
+        G_REPLAY_RECORDING.lock().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ipc::cmif;
+    use crate::util::TestTempDir;
+    use super::*;
+
+    fn make_request(command_id: u32, data: &[u32]) -> ServiceRequest {
+        ServiceRequest {
+            command_type: cmif::CommandType::Request as u32,
+            command_id: Some(command_id),
+            data: data.to_vec()
+        }
+    }
+
+    #[test]
+    fn record_replay() {
+        let temp_dir = TestTempDir::new("service_mock_record_replay");
+        let path = temp_dir.join_str("recording.json");
+        let recorded_calls = [
+            (make_request(1, &[0x1]), vec![0x10]),
+            (make_request(1, &[0x2]), vec![0x20]),
+            (make_request(2, &[]), vec![0x30])
+        ];
+
+        start_recording(path.clone(), vec![String::from("mock:t")]);
+        assert!(is_recorded_service("mock:t") && !is_recorded_service("mock:u"), "the recorded services weren't the given ones");
+        for (request, reply) in recorded_calls.iter() {
+            record_call(RecordedServiceCall {
+                service_name: String::from("mock:t"),
+                request: request.clone(),
+                reply: reply.clone()
+            });
+        }
+        let call_count = stop_recording().unwrap();
+        assert_eq!(call_count, Some(recorded_calls.len()));
+
+        let recording = ServiceRecording::load(&path).unwrap();
+        assert_eq!(recording.get_service_names(), vec![String::from("mock:t")]);
+
+        // Exact requests get their reply, otherwise the command's calls are replied in order (repeating the last one), while unrecorded commands/services get nothing
+        let mut mock = ServiceMock::new(&recording, "mock:t");
+        let replies = vec![
+            mock.find_reply(&make_request(1, &[0x2])),
+            mock.find_reply(&make_request(1, &[0x3])),
+            mock.find_reply(&make_request(1, &[0x3])),
+            mock.find_reply(&make_request(1, &[0x3])),
+            mock.find_reply(&make_request(2, &[0x4])),
+            mock.find_reply(&make_request(3, &[]))
+        ];
+        assert_eq!(replies, vec![Some(vec![0x20]), Some(vec![0x10]), Some(vec![0x20]), Some(vec![0x20]), Some(vec![0x30]), None]);
+        assert_eq!(ServiceMock::new(&recording, "mock:u").get_call_count(), 0, "unrecorded service has recorded calls");
+    }
+}
@@ -0,0 +1,101 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use crate::kern::proc::{KProcess, get_process_list};
+use crate::kern::thread::{KThread, get_running_guest_thread_count};
+use crate::util::{self, Shared};
+
+// Graceful shutdown: Ctrl+C (or SIGTERM) only requests it, the main loop then terminates the guest processes and finishes the run normally (see main)
+// A second signal while shutting down exits right away, in case the shutdown itself gets stuck
+
+// Same exit code shells use for processes interrupted by SIGINT
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+const GUEST_UNWIND_TIMEOUT: Duration = Duration::from_secs(5);
+
+static mut G_SHUTDOWN_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+#[inline]
+pub fn is_shutdown_requested() -> bool {
+    unsafe {
+        G_SHUTDOWN_REQUESTED.load(Ordering::SeqCst)
+    }
+}
+
+// Returns whether a shutdown was already requested
+fn request_shutdown() -> bool {
+    unsafe {
+        G_SHUTDOWN_REQUESTED.swap(true, Ordering::SeqCst)
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn handle_signal(_signal: libc::c_int) {
+    // Note: only async-signal-safe calls are allowed here
+    if request_shutdown() {
+        unsafe {
+            libc::_exit(INTERRUPTED_EXIT_CODE);
+        }
+    }
+}
+
+#[cfg(unix)]
+pub fn install_signal_handler() {
+    unsafe {
+        libc::signal(libc::SIGINT, handle_signal as libc::sighandler_t);
+        libc::signal(libc::SIGTERM, handle_signal as libc::sighandler_t);
+    }
+}
+
+#[cfg(windows)]
+unsafe extern "system" fn handle_console_ctrl(_ctrl_type: winapi::shared::minwindef::DWORD) -> winapi::shared::minwindef::BOOL {
+    // Note: this already runs on its own thread, unlike unix signal handlers
+    if request_shutdown() {
+        std::process::exit(INTERRUPTED_EXIT_CODE);
+    }
+    winapi::shared::minwindef::TRUE
+}
+
+#[cfg(windows)]
+pub fn install_signal_handler() {
+    unsafe {
+        winapi::um::consoleapi::SetConsoleCtrlHandler(Some(handle_console_ctrl), winapi::shared::minwindef::TRUE);
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ShutdownSummary {
+    pub terminated_process_count: usize,
+    pub terminated_thread_count: usize,
+    // Guest threads which didn't unwind in time
+    pub remaining_thread_count: u64
+}
+
+// Note: only guest processes are terminated, emulated system processes (host code) would just fail their pending IPC and are simply gone once the emulator exits
+pub fn terminate_guest_processes() -> ShutdownSummary {
+    let guest_processes: Vec<Shared<KProcess>> = get_process_list().into_iter().filter(|process| process.get().cpu_ctx.is_some()).collect();
+
+    let mut summary = ShutdownSummary::default();
+    for process in guest_processes.iter() {
+        let threads = process.get().get_threads();
+        for mut thread in threads {
+            KThread::request_termination(&mut thread);
+            summary.terminated_thread_count += 1;
+        }
+        summary.terminated_process_count += 1;
+    }
+
+    let start = Instant::now();
+    while (get_running_guest_thread_count() > 0) && (start.elapsed() < GUEST_UNWIND_TIMEOUT) {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    summary.remaining_thread_count = get_running_guest_thread_count();
+
+    summary
+}
+
+pub fn flush_log_output() {
+    util::flush_log();
+    let _ = std::io::stdout().flush();
+    let _ = std::io::stderr().flush();
+}
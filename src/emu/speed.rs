@@ -0,0 +1,140 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use parking_lot::Mutex;
+use serde::{Serialize, Deserialize};
+use crate::emu::cfg;
+use crate::kern::SYSTEM_TICK_FREQUENCY;
+
+// Emulation speed control: guest instructions are budgeted per host frame, and guest threads exceeding the budget of the current frame sleep until the next one
+// The console's speed is approximated as a fixed amount of instructions per tick of its 19.2MHz system counter (see cfg::Config::speed_instructions_per_tick)
+// Note: frames that were missed (because emulation was slower than the target speed) are not caught up afterwards, which would only cause bursts
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum SpeedMode {
+    // As fast as possible
+    Unlimited,
+    // Approximately the speed of the console
+    RealTime,
+    // A multiple of the console's speed (see cfg::Config::speed_fast_forward_multiplier)
+    FastForward
+}
+
+struct FrameState {
+    start: Instant,
+    instruction_count: u64
+}
+
+struct Throttle {
+    frame_duration: Duration,
+    frame_instruction_budget: u64,
+    frame: FrameState
+}
+
+static mut G_THROTTLE_ENABLED: AtomicBool = AtomicBool::new(false);
+static mut G_THROTTLE: Mutex<Option<Throttle>> = parking_lot::const_mutex(None);
+
+// Instructions are accumulated per host thread (thus per guest thread) and only taken from the frame budget every now and then
+const INSTRUCTION_BUDGET_CHUNK: u64 = 0x400;
+
+#[thread_local]
+static mut G_PENDING_INSTRUCTION_COUNT: u64 = 0;
+
+#[inline]
+pub fn is_enabled() -> bool {
+    unsafe {
+        G_THROTTLE_ENABLED.load(Ordering::Relaxed)
+    }
+}
+
+pub fn set_mode(mode: SpeedMode, fast_forward_multiplier: f64) {
+    let config = cfg::get_config();
+    let multiplier = match mode {
+        SpeedMode::Unlimited => {
+            unsafe {
+                G_THROTTLE_ENABLED.store(false, Ordering::SeqCst);
+                *G_THROTTLE.lock() = None;
+            }
+            log_line!("[speed] Running at unlimited speed");
+            return;
+        },
+        SpeedMode::RealTime => 1.0,
+        SpeedMode::FastForward => fast_forward_multiplier
+    };
+
+    let frame_rate = config.speed_frame_rate.max(1);
+    let instructions_per_second = (SYSTEM_TICK_FREQUENCY * config.speed_instructions_per_tick) as f64 * multiplier;
+    let frame_instruction_budget = ((instructions_per_second / frame_rate as f64) as u64).max(INSTRUCTION_BUDGET_CHUNK);
+
+    unsafe {
+        *G_THROTTLE.lock() = Some(Throttle {
+            frame_duration: Duration::from_secs(1) / frame_rate,
+            frame_instruction_budget: frame_instruction_budget,
+            frame: FrameState {
+                start: Instant::now(),
+                instruction_count: 0
+            }
+        });
+        G_THROTTLE_ENABLED.store(true, Ordering::SeqCst);
+    }
+    log_line!("[speed] Running at {}x speed ({} instructions per frame, {} frames/s)", multiplier, frame_instruction_budget, frame_rate);
+}
+
+// Parses the speed given through the command line: "unlimited", "realtime" or a fast-forward multiplier like "4x"
+pub fn parse_speed(speed: &str) -> Option<(SpeedMode, f64)> {
+    match speed {
+        "unlimited" => Some((SpeedMode::Unlimited, 1.0)),
+        "realtime" => Some((SpeedMode::RealTime, 1.0)),
+        _ => match speed.strip_suffix('x').and_then(|multiplier| multiplier.parse::<f64>().ok()) {
+            Some(multiplier) if multiplier > 0.0 => Some((SpeedMode::FastForward, multiplier)),
+            _ => None
+        }
+    }
+}
+
+#[inline]
+pub fn record_instruction() {
+    if is_enabled() {
+        unsafe {
+            G_PENDING_INSTRUCTION_COUNT += 1;
+            if G_PENDING_INSTRUCTION_COUNT >= INSTRUCTION_BUDGET_CHUNK {
+                let instruction_count = G_PENDING_INSTRUCTION_COUNT;
+                G_PENDING_INSTRUCTION_COUNT = 0;
+                consume_budget(instruction_count);
+            }
+        }
+    }
+}
+
+fn consume_budget(instruction_count: u64) {
+    loop {
+        let sleep_duration = {
+            let mut throttle_guard = unsafe { G_THROTTLE.lock() };
+            let throttle = match throttle_guard.as_mut() {
+                Some(throttle) => throttle,
+                // The mode was changed to unlimited meanwhile
+                None => return
+            };
+
+            let now = Instant::now();
+            let frame_elapsed = now.duration_since(throttle.frame.start);
+            if frame_elapsed >= throttle.frame_duration {
+                throttle.frame.start = now;
+                throttle.frame.instruction_count = 0;
+            }
+
+            if throttle.frame.instruction_count < throttle.frame_instruction_budget {
+                throttle.frame.instruction_count += instruction_count;
+                return;
+            }
+
+            throttle.frame_duration - frame_elapsed
+        };
+
+        std::thread::sleep(sleep_duration);
+    }
+}
+
+pub fn initialize() {
+    let config = cfg::get_config();
+    set_mode(config.speed_mode, config.speed_fast_forward_multiplier);
+}
@@ -0,0 +1,196 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use parking_lot::Mutex;
+use crate::result::*;
+use crate::ldr;
+use crate::ldr::result as ldr_result;
+use crate::emu::cpu::ModuleMemory;
+
+// Function symbols of the loaded modules of every process, taken from their dynamic symbol tables
+// Note: stripped modules (most of them) only have the symbols they export/import, thus addresses may get attributed to the previous exported function
+
+const DT_NULL: i64 = 0;
+const DT_HASH: i64 = 4;
+const DT_STRTAB: i64 = 5;
+const DT_SYMTAB: i64 = 6;
+const DT_STRSZ: i64 = 10;
+
+const STT_FUNC: u8 = 2;
+const SHN_UNDEF: u16 = 0;
+
+const MAX_DYNAMIC_ENTRY_COUNT: usize = 0x400;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(C)]
+struct DynamicEntry {
+    tag: i64,
+    value: u64
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(C)]
+struct SymbolEntry {
+    name_offset: u32,
+    info: u8,
+    other: u8,
+    section_idx: u16,
+    value: u64,
+    size: u64
+}
+
+#[derive(Clone, Debug)]
+pub struct Symbol {
+    pub name: String,
+    pub address: u64,
+    pub size: u64
+}
+
+pub struct ModuleSymbols {
+    pub module_name: String,
+    pub start_address: u64,
+    pub end_address: u64,
+    // Sorted by address
+    symbols: Vec<Symbol>
+}
+
+impl ModuleSymbols {
+    fn read_symbols(module: &ModuleMemory) -> Result<Vec<Symbol>> {
+        let base_address = module.start();
+        let mod0_offset: u32 = module.read_memory_val(base_address + std::mem::size_of::<u32>() as u64)?;
+        let mod0_address = base_address + mod0_offset as u64;
+        let mod0: ldr::Mod0Header = module.read_memory_val(mod0_address)?;
+        result_return_unless!(mod0.magic == ldr::Mod0Header::MAGIC, ldr_result::ResultInvalidNso);
+
+        let dynamic_address = (mod0_address as i64 + mod0.dynamic_offset as i64) as u64;
+        let mut hash_offset: Option<u64> = None;
+        let mut strtab_offset: Option<u64> = None;
+        let mut symtab_offset: Option<u64> = None;
+        let mut strtab_size: usize = 0;
+        for i in 0..MAX_DYNAMIC_ENTRY_COUNT {
+            let entry: DynamicEntry = module.read_memory_val(dynamic_address + (i * std::mem::size_of::<DynamicEntry>()) as u64)?;
+            match entry.tag {
+                DT_NULL => break,
+                DT_HASH => hash_offset = Some(entry.value),
+                DT_STRTAB => strtab_offset = Some(entry.value),
+                DT_SYMTAB => symtab_offset = Some(entry.value),
+                DT_STRSZ => strtab_size = entry.value as usize,
+                _ => {}
+            };
+        }
+
+        let (strtab_offset, symtab_offset) = match (strtab_offset, symtab_offset) {
+            (Some(strtab_offset), Some(symtab_offset)) => (strtab_offset, symtab_offset),
+            _ => return Ok(Vec::new())
+        };
+
+        // The symbol count is the hash table's chain count, otherwise assume the usual layout (.dynsym right before .dynstr)
+        let symbol_count = match hash_offset {
+            Some(hash_offset) => module.read_memory_val::<u32>(base_address + hash_offset + std::mem::size_of::<u32>() as u64)? as usize,
+            None => match strtab_offset > symtab_offset {
+                true => (strtab_offset - symtab_offset) as usize / std::mem::size_of::<SymbolEntry>(),
+                false => 0
+            }
+        };
+
+        let mut strtab: Vec<u8> = vec![0; strtab_size];
+        if strtab_size > 0 {
+            module.read_memory(base_address + strtab_offset, &mut strtab)?;
+        }
+
+        let mut symbols: Vec<Symbol> = Vec::new();
+        for i in 0..symbol_count {
+            let entry: SymbolEntry = module.read_memory_val(base_address + symtab_offset + (i * std::mem::size_of::<SymbolEntry>()) as u64)?;
+            if ((entry.info & 0xF) != STT_FUNC) || (entry.section_idx == SHN_UNDEF) || (entry.value == 0) {
+                continue;
+            }
+
+            let name_offset = entry.name_offset as usize;
+            if name_offset >= strtab.len() {
+                continue;
+            }
+            let name_len = strtab[name_offset..].iter().position(|&ch| ch == 0).unwrap_or(strtab.len() - name_offset);
+            symbols.push(Symbol {
+                name: String::from_utf8_lossy(&strtab[name_offset..name_offset + name_len]).into_owned(),
+                address: base_address + entry.value,
+                size: entry.size
+            });
+        }
+
+        symbols.sort_by_key(|symbol| symbol.address);
+        Ok(symbols)
+    }
+
+    pub fn from_module(module: &ModuleMemory) -> Self {
+        let symbols = match Self::read_symbols(module) {
+            Ok(symbols) => symbols,
+            Err(rc) => {
+                log_line!("[symbols] Unable to read symbols of module '{}': {1} ({1:?})", module.file_name, rc);
+                Vec::new()
+            }
+        };
+
+        Self {
+            module_name: module.get_name().unwrap_or(module.file_name.clone()),
+            start_address: module.start(),
+            end_address: module.end(),
+            symbols: symbols
+        }
+    }
+
+    pub fn contains(&self, address: u64) -> bool {
+        (self.start_address <= address) && (self.end_address > address)
+    }
+
+    pub fn get_symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    pub fn lookup(&self, address: u64) -> Option<&Symbol> {
+        let symbol_idx = match self.symbols.binary_search_by_key(&address, |symbol| symbol.address) {
+            Ok(idx) => idx,
+            Err(0) => return None,
+            Err(idx) => idx - 1
+        };
+
+        let symbol = &self.symbols[symbol_idx];
+        match (symbol.size == 0) || (address < symbol.address + symbol.size) {
+            true => Some(symbol),
+            false => None
+        }
+    }
+}
+
+// Symbols are kept after their processes exit, since reports (see emu::profiler) are usually made at the end
+static mut G_MODULE_SYMBOLS: Mutex<BTreeMap<u64, Vec<Arc<ModuleSymbols>>>> = parking_lot::const_mutex(BTreeMap::new());
+
+pub fn register_module(process_id: u64, module: &ModuleMemory) {
+    let module_symbols = Arc::new(ModuleSymbols::from_module(module));
+    unsafe {
+        G_MODULE_SYMBOLS.lock().entry(process_id).or_insert_with(Vec::new).push(module_symbols);
+    }
+}
+
+pub fn unregister_module(process_id: u64, start_address: u64) {
+    unsafe {
+        if let Some(modules) = G_MODULE_SYMBOLS.lock().get_mut(&process_id) {
+            modules.retain(|module| module.start_address != start_address);
+        }
+    }
+}
+
+pub fn find_module(process_id: u64, address: u64) -> Option<Arc<ModuleSymbols>> {
+    unsafe {
+        G_MODULE_SYMBOLS.lock().get(&process_id).and_then(|modules| modules.iter().find(|module| module.contains(address)).cloned())
+    }
+}
+
+// Formats an address as "module!symbol+offset" (or "module+offset" if no symbol is found)
+pub fn format_address(process_id: u64, address: u64) -> String {
+    match find_module(process_id, address) {
+        Some(module) => match module.lookup(address) {
+            Some(symbol) => format!("{}!{}+{:#X}", module.module_name, symbol.name, address - symbol.address),
+            None => format!("{}+{:#X}", module.module_name, address - module.start_address)
+        },
+        None => format!("{:#X}", address)
+    }
+}
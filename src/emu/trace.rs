@@ -0,0 +1,319 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use parking_lot::Mutex;
+use crate::result::*;
+use crate::util::{self, convert_io_result};
+use crate::kern::svc;
+use crate::kern::thread::try_get_current_thread;
+use crate::kern::proc::try_get_current_process;
+
+// Records SVC/IPC activity of emulated processes, mostly meant for testing/regression purposes
+// Recording is disabled by default, since it's not free to keep every single event around
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TraceEvent {
+    Svc {
+        process_id: u64,
+        thread_id: u64,
+        svc_id: svc::SvcId,
+        rc: u32
+    },
+    IpcRequest {
+        process_id: u64,
+        thread_id: u64,
+        session_handle: svc::Handle,
+        command_type: u16
+    },
+    ThreadExit {
+        process_id: u64,
+        thread_id: u64
+    }
+}
+
+impl TraceEvent {
+    pub fn get_process_id(&self) -> u64 {
+        match *self {
+            Self::Svc { process_id, .. } => process_id,
+            Self::IpcRequest { process_id, .. } => process_id,
+            Self::ThreadExit { process_id, .. } => process_id
+        }
+    }
+
+    pub fn get_thread_id(&self) -> u64 {
+        match *self {
+            Self::Svc { thread_id, .. } => thread_id,
+            Self::IpcRequest { thread_id, .. } => thread_id,
+            Self::ThreadExit { thread_id, .. } => thread_id
+        }
+    }
+
+    // Golden trace line format: "<kind> <key>=<value> ...", see TraceMask for masking specific keys
+    pub fn to_trace_line(&self) -> String {
+        match *self {
+            Self::Svc { process_id, thread_id, svc_id, rc } => format!("svc pid={:#X} tid={:#X} id={:?} rc={:#X}", process_id, thread_id, svc_id, rc),
+            Self::IpcRequest { process_id, thread_id, session_handle, command_type } => format!("ipc pid={:#X} tid={:#X} handle={:#X} type={}", process_id, thread_id, session_handle, command_type),
+            Self::ThreadExit { process_id, thread_id } => format!("exit pid={:#X} tid={:#X}", process_id, thread_id)
+        }
+    }
+
+    // Human-readable (strace-like) line, with results described (see ResultCode::describe)
+    pub fn describe(&self) -> String {
+        match *self {
+            Self::Svc { thread_id, svc_id, rc, .. } => format!("[{:#X}] {:?}() = {}", thread_id, svc_id, ResultCode::new(rc).describe()),
+            Self::IpcRequest { thread_id, session_handle, command_type, .. } => format!("[{:#X}] <ipc request> handle {:#X}, type {}", thread_id, session_handle, command_type),
+            Self::ThreadExit { thread_id, .. } => format!("[{:#X}] <exit>", thread_id)
+        }
+    }
+}
+
+// Keys whose values are replaced by '*' before comparing traces, since they aren't deterministic between runs (IDs, handles...)
+#[derive(Clone, Debug, Default)]
+pub struct TraceMask {
+    pub keys: Vec<String>
+}
+
+impl TraceMask {
+    pub const fn new() -> Self {
+        Self {
+            keys: Vec::new()
+        }
+    }
+
+    // Parses a comma-separated key list, like "pid,tid,handle"
+    pub fn from_str(mask: &str) -> Self {
+        Self {
+            keys: mask.split(',').map(|key| key.trim()).filter(|key| !key.is_empty()).map(String::from).collect()
+        }
+    }
+
+    pub fn apply(&self, line: &str) -> String {
+        let tokens: Vec<String> = line.split_whitespace().map(|token| {
+            if let Some((key, _)) = token.split_once('=') {
+                if self.keys.iter().any(|mask_key| mask_key == key) {
+                    return format!("{}=*", key);
+                }
+            }
+            String::from(token)
+        }).collect();
+
+        tokens.join(" ")
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum TraceDiffLine {
+    Same(String),
+    Missing(String),
+    Unexpected(String)
+}
+
+pub fn save_trace(path: &str, events: &[TraceEvent]) -> Result<()> {
+    let mut file = convert_io_result(File::create(path))?;
+    for event in events {
+        convert_io_result(writeln!(file, "{}", event.to_trace_line()))?;
+    }
+
+    Ok(())
+}
+
+pub fn load_trace_lines(path: &str) -> Result<Vec<String>> {
+    let file = convert_io_result(File::open(path))?;
+    let mut lines: Vec<String> = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = convert_io_result(line)?;
+        if !line.trim().is_empty() {
+            lines.push(line);
+        }
+    }
+
+    Ok(lines)
+}
+
+// Beyond this, an LCS table would take too much memory, so only a positional comparison is done
+const MAX_DIFF_TABLE_SIZE: usize = 0x1000000;
+
+pub fn diff_trace_lines(golden: &[String], current: &[String], mask: &TraceMask) -> Vec<TraceDiffLine> {
+    let golden: Vec<String> = golden.iter().map(|line| mask.apply(line)).collect();
+    let current: Vec<String> = current.iter().map(|line| mask.apply(line)).collect();
+
+    // Common prefix/suffix are quite likely to be most of the trace, skip them before the actual diff
+    let mut prefix_len: usize = 0;
+    while (prefix_len < golden.len()) && (prefix_len < current.len()) && (golden[prefix_len] == current[prefix_len]) {
+        prefix_len += 1;
+    }
+    let mut suffix_len: usize = 0;
+    while (suffix_len < golden.len() - prefix_len) && (suffix_len < current.len() - prefix_len) && (golden[golden.len() - 1 - suffix_len] == current[current.len() - 1 - suffix_len]) {
+        suffix_len += 1;
+    }
+
+    let golden_mid = &golden[prefix_len..golden.len() - suffix_len];
+    let current_mid = &current[prefix_len..current.len() - suffix_len];
+
+    let mut diff: Vec<TraceDiffLine> = golden[..prefix_len].iter().cloned().map(TraceDiffLine::Same).collect();
+
+    if (golden_mid.len() + 1) * (current_mid.len() + 1) <= MAX_DIFF_TABLE_SIZE {
+        let (n, m) = (golden_mid.len(), current_mid.len());
+        let mut lcs = vec![0u32; (n + 1) * (m + 1)];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lcs[i * (m + 1) + j] = match golden_mid[i] == current_mid[j] {
+                    true => lcs[(i + 1) * (m + 1) + j + 1] + 1,
+                    false => lcs[(i + 1) * (m + 1) + j].max(lcs[i * (m + 1) + j + 1])
+                };
+            }
+        }
+
+        let (mut i, mut j) = (0, 0);
+        while (i < n) && (j < m) {
+            if golden_mid[i] == current_mid[j] {
+                diff.push(TraceDiffLine::Same(golden_mid[i].clone()));
+                i += 1;
+                j += 1;
+            }
+            else if lcs[(i + 1) * (m + 1) + j] >= lcs[i * (m + 1) + j + 1] {
+                diff.push(TraceDiffLine::Missing(golden_mid[i].clone()));
+                i += 1;
+            }
+            else {
+                diff.push(TraceDiffLine::Unexpected(current_mid[j].clone()));
+                j += 1;
+            }
+        }
+        diff.extend(golden_mid[i..].iter().cloned().map(TraceDiffLine::Missing));
+        diff.extend(current_mid[j..].iter().cloned().map(TraceDiffLine::Unexpected));
+    }
+    else {
+        for i in 0..golden_mid.len().max(current_mid.len()) {
+            match (golden_mid.get(i), current_mid.get(i)) {
+                (Some(golden_line), Some(current_line)) if golden_line == current_line => diff.push(TraceDiffLine::Same(golden_line.clone())),
+                (golden_line, current_line) => {
+                    if let Some(golden_line) = golden_line {
+                        diff.push(TraceDiffLine::Missing(golden_line.clone()));
+                    }
+                    if let Some(current_line) = current_line {
+                        diff.push(TraceDiffLine::Unexpected(current_line.clone()));
+                    }
+                }
+            }
+        }
+    }
+
+    diff.extend(golden[golden.len() - suffix_len..].iter().cloned().map(TraceDiffLine::Same));
+    diff
+}
+
+// Returns whether the current trace matches the golden one, printing the differences (with some context) otherwise
+pub fn compare_with_golden_trace(path: &str, events: &[TraceEvent], mask: &TraceMask) -> Result<bool> {
+    const CONTEXT_LINES: usize = 3;
+    const COLOR_RED: &str = "\x1b[31m";
+    const COLOR_GREEN: &str = "\x1b[32m";
+    const COLOR_RESET: &str = "\x1b[0m";
+
+    let golden = load_trace_lines(path)?;
+    let current: Vec<String> = events.iter().map(|event| event.to_trace_line()).collect();
+    let diff = diff_trace_lines(&golden, &current, mask);
+
+    let is_changed = |line: &TraceDiffLine| !matches!(line, TraceDiffLine::Same(_));
+    if !diff.iter().any(is_changed) {
+        log_line!("[trace] Trace matches the golden trace ({} events)", current.len());
+        return Ok(true);
+    }
+
+    log_line!("[trace] Trace differs from the golden trace '{}':", path);
+    // The differences are printed right away, thus pending log lines go first
+    util::flush_log();
+    let mut last_printed: Option<usize> = None;
+    for (i, line) in diff.iter().enumerate() {
+        let start = i.saturating_sub(CONTEXT_LINES);
+        let end = (i + CONTEXT_LINES + 1).min(diff.len());
+        if !diff[start..end].iter().any(is_changed) {
+            continue;
+        }
+
+        if let Some(last_i) = last_printed {
+            if i > last_i + 1 {
+                println!("...");
+            }
+        }
+        match line {
+            TraceDiffLine::Same(line) => println!("  {}", line),
+            TraceDiffLine::Missing(line) => println!("{}- {}{}", COLOR_RED, line, COLOR_RESET),
+            TraceDiffLine::Unexpected(line) => println!("{}+ {}{}", COLOR_GREEN, line, COLOR_RESET)
+        };
+        last_printed = Some(i);
+    }
+
+    Ok(false)
+}
+
+static mut G_TRACE_ENABLED: AtomicBool = AtomicBool::new(false);
+static mut G_TRACE_EVENTS: Mutex<Vec<TraceEvent>> = parking_lot::const_mutex(Vec::new());
+
+pub fn is_enabled() -> bool {
+    unsafe {
+        G_TRACE_ENABLED.load(Ordering::SeqCst)
+    }
+}
+
+pub fn set_enabled(enabled: bool) {
+    unsafe {
+        G_TRACE_ENABLED.store(enabled, Ordering::SeqCst);
+    }
+}
+
+pub fn record_event(event: TraceEvent) {
+    if is_enabled() {
+        unsafe {
+            G_TRACE_EVENTS.lock().push(event);
+        }
+    }
+}
+
+pub fn get_events() -> Vec<TraceEvent> {
+    unsafe {
+        G_TRACE_EVENTS.lock().clone()
+    }
+}
+
+pub fn take_events() -> Vec<TraceEvent> {
+    unsafe {
+        let mut events = G_TRACE_EVENTS.lock();
+        std::mem::take(&mut *events)
+    }
+}
+
+#[inline]
+pub fn get_current_ids() -> (u64, u64) {
+    let process_id = match try_get_current_process() {
+        Some(process) => process.get().id,
+        None => 0
+    };
+    let thread_id = match try_get_current_thread() {
+        Some(thread) => thread.get().id,
+        None => 0
+    };
+
+    (process_id, thread_id)
+}
+
+pub fn record_svc(svc_id: svc::SvcId, rc: u32) {
+    if is_enabled() {
+        let (process_id, thread_id) = get_current_ids();
+        record_event(TraceEvent::Svc { process_id: process_id, thread_id: thread_id, svc_id: svc_id, rc: rc });
+    }
+}
+
+pub fn record_ipc_request(session_handle: svc::Handle, command_type: u16) {
+    if is_enabled() {
+        let (process_id, thread_id) = get_current_ids();
+        record_event(TraceEvent::IpcRequest { process_id: process_id, thread_id: thread_id, session_handle: session_handle, command_type: command_type });
+    }
+}
+
+pub fn record_thread_exit() {
+    if is_enabled() {
+        let (process_id, thread_id) = get_current_ids();
+        record_event(TraceEvent::ThreadExit { process_id: process_id, thread_id: thread_id });
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use parking_lot::Mutex;
+use crate::result::*;
+use crate::util::convert_io_result;
+use crate::emu::cfg;
+
+// IPC watchdog: sync requests pending for longer than the configured timeout get reported (once each), which usually points at a missing/unfinished service implementation when boot hangs
+// Note: everything reported is captured when the request is sent/received, so that the watchdog thread never has to lock any kernel object
+
+#[derive(Clone, Debug)]
+pub struct PendingRequest {
+    pub client_process_id: u64,
+    pub client_process_name: String,
+    pub client_thread_id: u64,
+    // Name of the port the session was connected through, if it has one (see KPort::set_name)
+    pub service_name: Option<String>,
+    pub command_type: u32,
+    pub command_id: Option<u32>,
+    // Only known once a server thread received the request
+    pub server_process: Option<(u64, String)>,
+    pub sent_instant: Instant,
+    pub is_reported: bool
+}
+
+static mut G_WATCHDOG_ENABLED: AtomicBool = AtomicBool::new(false);
+// Request ID -> request
+static mut G_PENDING_REQUESTS: Mutex<BTreeMap<u64, PendingRequest>> = parking_lot::const_mutex(BTreeMap::new());
+
+const MIN_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+const MAX_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[inline]
+pub fn is_enabled() -> bool {
+    unsafe {
+        G_WATCHDOG_ENABLED.load(Ordering::Relaxed)
+    }
+}
+
+pub fn register_request(request_id: u64, request: PendingRequest) {
+    if is_enabled() {
+        unsafe {
+            G_PENDING_REQUESTS.lock().insert(request_id, request);
+        }
+    }
+}
+
+pub fn record_request_received(request_id: u64, server_process_id: u64, server_process_name: String) {
+    if is_enabled() {
+        let mut pending_requests = unsafe { G_PENDING_REQUESTS.lock() };
+        if let Some(request) = pending_requests.get_mut(&request_id) {
+            request.server_process = Some((server_process_id, server_process_name));
+        }
+    }
+}
+
+pub fn unregister_request(request_id: u64) {
+    if is_enabled() {
+        let request = unsafe { G_PENDING_REQUESTS.lock().remove(&request_id) };
+        if let Some(request) = request {
+            // Slow requests which eventually complete are worth knowing about as well
+            if request.is_reported {
+                log_line!("[watchdog] {} completed after {:?}", format_request(&request), request.sent_instant.elapsed());
+            }
+        }
+    }
+}
+
+pub fn get_pending_requests() -> Vec<PendingRequest> {
+    unsafe {
+        G_PENDING_REQUESTS.lock().values().cloned().collect()
+    }
+}
+
+fn format_request(request: &PendingRequest) -> String {
+    let service = match request.service_name.as_ref() {
+        Some(service_name) => format!("'{}'", service_name),
+        None => String::from("<unnamed session>")
+    };
+    let command = match request.command_id {
+        Some(command_id) => format!("command {} (type {})", command_id, request.command_type),
+        None => format!("command type {}", request.command_type)
+    };
+    let server = match request.server_process.as_ref() {
+        Some((server_process_id, server_process_name)) => format!("server process {} ('{}')", server_process_id, server_process_name),
+        None => String::from("not received by any server yet")
+    };
+
+    format!("Request from process {} ('{}', thread {}) to {}, {}, {}", request.client_process_id, request.client_process_name, request.client_thread_id, service, command, server)
+}
+
+fn check_pending_requests(timeout: Duration) {
+    let mut pending_requests = unsafe { G_PENDING_REQUESTS.lock() };
+    for request in pending_requests.values_mut() {
+        let elapsed = request.sent_instant.elapsed();
+        if !request.is_reported && (elapsed >= timeout) {
+            log_line!("[watchdog] {} has been pending for {:?}", format_request(request), elapsed);
+            request.is_reported = true;
+        }
+    }
+}
+
+fn watchdog_thread_fn(timeout: Duration) {
+    let check_interval = (timeout / 4).max(MIN_CHECK_INTERVAL).min(MAX_CHECK_INTERVAL);
+    loop {
+        thread::sleep(check_interval);
+        check_pending_requests(timeout);
+    }
+}
+
+pub fn initialize() -> Result<()> {
+    let timeout = match cfg::get_config().ipc_watchdog_timeout_ms {
+        Some(timeout_ms) => Duration::from_millis(timeout_ms),
+        None => return Ok(())
+    };
+
+    unsafe {
+        G_WATCHDOG_ENABLED.store(true, Ordering::SeqCst);
+    }
+
+    convert_io_result(thread::Builder::new().name(String::from("pg.emu.IpcWatchdog")).spawn(move || watchdog_thread_fn(timeout)))?;
+    Ok(())
+}
@@ -0,0 +1,56 @@
+// Note: https://switchbrew.org/wiki/Fatal_services
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum FatalPolicy {
+    ErrorReportAndErrorScreen = 0,
+    ErrorReport = 1,
+    ErrorScreen = 2
+}
+
+impl FatalPolicy {
+    pub const fn from(raw: u32) -> Option<Self> {
+        match raw {
+            0 => Some(Self::ErrorReportAndErrorScreen),
+            1 => Some(Self::ErrorReport),
+            2 => Some(Self::ErrorScreen),
+            _ => None
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(C)]
+pub struct Aarch64CpuContext {
+    // X0-X28, FP, LR and SP
+    pub x: [u64; 32],
+    pub pc: u64,
+    pub pstate: u64,
+    pub afsr0: u64,
+    pub afsr1: u64,
+    pub esr: u64,
+    pub far: u64,
+    pub stack_trace: [u64; 32],
+    pub start_address: u64,
+    pub register_set_flags: u64,
+    pub stack_trace_size: u32
+}
+
+impl Aarch64CpuContext {
+    pub const SIZE: usize = 0x248;
+
+    // The context is followed by the is_aarch32 flag (32-bit contexts have a different layout) and the context type
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        let is_aarch32 = data.get(Self::SIZE).map(|is_aarch32| *is_aarch32 != 0).unwrap_or(false);
+        match (data.len() >= Self::SIZE) && !is_aarch32 {
+            true => Some(unsafe { (data.as_ptr() as *const Self).read_unaligned() }),
+            false => None
+        }
+    }
+
+    pub fn get_stack_trace(&self) -> &[u64] {
+        &self.stack_trace[..(self.stack_trace_size as usize).min(self.stack_trace.len())]
+    }
+}
+
+const _: () = assert!(std::mem::size_of::<Aarch64CpuContext>() == Aarch64CpuContext::SIZE);
@@ -1,17 +1,29 @@
 use std::path::PathBuf;
 use std::fs::{self, DirEntry, File as StdFile, OpenOptions};
 use std::io::{Read, Result as IoResult, Seek, SeekFrom, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
 use cntx::nca::NCA;
 use cntx::pfs0::PFS0;
 use cntx::romfs::{RomFs, RomFsDirectoryIterator};
+use crate::emu::cfg;
+use crate::ncm::{self, ProgramId};
 use crate::util;
 use crate::util::{Shared, convert_io_result};
 use crate::result::*;
 
 pub mod result;
 
+pub mod path;
+
+pub mod cache;
+
+pub mod aio;
+
+pub mod bis;
+
 bit_enum! {
     CreateOption (u32) {
+        None = 0,
         ConcatenationFile = bit!(0)
     }
 }
@@ -121,11 +133,51 @@ pub fn file_read_val<T>(file: &Shared<dyn File>, offset: u64, option: ReadOption
     Ok(t)
 }
 
+// Raw data, unlike files always accessed in full (no partial reads)
+pub trait Storage {
+    fn read(&mut self, offset: u64, data: &mut [u8]) -> Result<()>;
+    fn write(&mut self, offset: u64, data: &[u8]) -> Result<()>;
+    fn flush(&mut self) -> Result<()>;
+    fn set_size(&mut self, size: usize) -> Result<()>;
+    fn get_size(&mut self) -> Result<usize>;
+}
+
+// Note: like the actual fs service, reads continue where the previous one ended, and an empty result means all entries were already read
 pub trait Directory {
     fn read(&mut self, count: usize) -> Result<Vec<DirectoryEntry>>;
     fn get_entry_count(&mut self) -> Result<usize>;
 }
 
+// Lists all the entries inside a directory and its subdirectories, with their paths relative to the filesystem root
+pub fn read_directory_recursively(fs: &Shared<dyn FileSystem>, dir_path: PathBuf, open_mode: DirectoryOpenMode) -> Result<Vec<(PathBuf, DirectoryEntry)>> {
+    // Subdirectories must always be listed in order to recurse into them
+    let dir = fs.get().open_directory(dir_path.clone(), open_mode | DirectoryOpenMode::ReadDirectories())?;
+    let entry_count = dir.get().get_entry_count()?;
+    let entries = dir.get().read(entry_count)?;
+
+    let mut all_entries: Vec<(PathBuf, DirectoryEntry)> = Vec::new();
+    for entry in entries {
+        let entry_path = dir_path.join(entry.path.get_string()?);
+        if entry.entry_type == DirectoryEntryType::Directory {
+            let sub_entries = read_directory_recursively(fs, entry_path.clone(), open_mode)?;
+            if open_mode.contains(DirectoryOpenMode::ReadDirectories()) {
+                all_entries.push((entry_path, entry));
+            }
+            all_entries.extend(sub_entries);
+        }
+        else {
+            all_entries.push((entry_path, entry));
+        }
+    }
+
+    Ok(all_entries)
+}
+
+// In-memory filesystems store their paths like "dir/file", which is what normalized paths look like
+fn make_path_str(path: &PathBuf) -> Result<String> {
+    Ok(path::normalize(path)?.display().to_string())
+}
+
 pub trait FileSystem {
     fn create_file(&mut self, path: PathBuf, size: usize, create_option: CreateOption) -> Result<()>;
     fn delete_file(&mut self, path: PathBuf) -> Result<()>;
@@ -146,6 +198,56 @@ pub trait FileSystem {
 
 // Host
 
+// (free size, total size) of the host disk containing the path
+#[cfg(unix)]
+fn get_host_disk_space(path: &PathBuf) -> Result<(usize, usize)> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path_c = CString::new(path.as_os_str().as_bytes()).map_err(|_| result::ResultInvalidCharacter::make())?;
+    let mut stat: libc::statvfs = unsafe {
+        std::mem::zeroed()
+    };
+    let rc = unsafe {
+        libc::statvfs(path_c.as_ptr(), &mut stat)
+    };
+    result_return_unless!(rc == 0, result::ResultPathNotFound);
+
+    let free_size = stat.f_bavail as u64 * stat.f_frsize as u64;
+    let total_size = stat.f_blocks as u64 * stat.f_frsize as u64;
+    Ok((free_size as usize, total_size as usize))
+}
+
+#[cfg(windows)]
+fn get_host_disk_space(path: &PathBuf) -> Result<(usize, usize)> {
+    use std::os::windows::ffi::OsStrExt;
+    use winapi::um::fileapi::GetDiskFreeSpaceExW;
+    use winapi::um::winnt::ULARGE_INTEGER;
+
+    let path_w: Vec<u16> = path.as_os_str().encode_wide().chain(std::iter::once(0)).collect();
+    let mut free_size: ULARGE_INTEGER = unsafe {
+        std::mem::zeroed()
+    };
+    let mut total_size: ULARGE_INTEGER = unsafe {
+        std::mem::zeroed()
+    };
+    let ok = unsafe {
+        GetDiskFreeSpaceExW(path_w.as_ptr(), &mut free_size, &mut total_size, std::ptr::null_mut())
+    };
+    result_return_unless!(ok != 0, result::ResultPathNotFound);
+
+    let (free_size, total_size) = unsafe {
+        (*free_size.QuadPart(), *total_size.QuadPart())
+    };
+    Ok((free_size as usize, total_size as usize))
+}
+
+// Timestamps are POSIX times (seconds)
+fn convert_host_time_stamp(time: IoResult<SystemTime>) -> Option<u64> {
+    let duration = time.ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some(duration.as_secs())
+}
+
 pub struct HostFile {
     inner_file: StdFile
 }
@@ -194,27 +296,30 @@ impl File for HostFile {
 
 pub struct HostDirectory {
     entries: Vec<DirEntry>,
-    open_mode: DirectoryOpenMode
+    open_mode: DirectoryOpenMode,
+    read_idx: usize
 }
 
 impl HostDirectory {
     pub fn new(entries: Vec<DirEntry>, open_mode: DirectoryOpenMode) -> Self {
         Self {
             entries: entries,
-            open_mode: open_mode
+            open_mode: open_mode,
+            read_idx: 0
         }
     }
 }
 
 impl Directory for HostDirectory {
     fn read(&mut self, count: usize) -> Result<Vec<DirectoryEntry>> {
-        let actual_count = count.min(self.entries.len());
-        let mut dir_entries: Vec<DirectoryEntry> = Vec::with_capacity(actual_count);
+        let mut dir_entries: Vec<DirectoryEntry> = Vec::with_capacity(count.min(self.entries.len()));
 
-        for i in 0..actual_count {
-            let entry = &self.entries[i];
+        while (dir_entries.len() < count) && (self.read_idx < self.entries.len()) {
+            let entry = &self.entries[self.read_idx];
+            self.read_idx += 1;
 
-            let entry_path = entry.path().into_os_string().into_string().unwrap();
+            // Only the entry name, the actual host path must not be exposed
+            let entry_path = entry.file_name().into_string().unwrap();
             let entry_metadata = convert_io_result(entry.metadata())?;
             let is_dir = entry_metadata.is_dir();
 
@@ -281,56 +386,58 @@ impl HostFileSystem {
         })
     }
 
-    fn make_path(&self, path: PathBuf) -> PathBuf {
-        PathBuf::from(self.base_dir.clone()).join(path)
+    // Note: normalizing first ensures the resulting path never leaves the base directory
+    fn make_path(&self, path: PathBuf) -> Result<PathBuf> {
+        let normalized_path = path::normalize(&path)?;
+        Ok(PathBuf::from(self.base_dir.clone()).join(normalized_path))
     }
 }
 
 impl FileSystem for HostFileSystem {
     fn create_file(&mut self, path: PathBuf, size: usize, _create_option: CreateOption) -> Result<()> {
-        // Note: no need for concatenation file support
-        let abs_path = self.make_path(path);
+        // Note: concatenation files are handled by wrapping this in a ConcatenationFileSystem
+        let abs_path = self.make_path(path)?;
         result_return_if!(abs_path.exists(), result::ResultPathAlreadyExists);
 
-        let file = convert_io_result(StdFile::open(abs_path))?;
+        let file = convert_io_result(StdFile::create(abs_path))?;
         convert_io_result(file.set_len(size as u64))?;
         Ok(())
     }
 
     fn delete_file(&mut self, path: PathBuf) -> Result<()> {
-        let abs_path = self.make_path(path);
+        let abs_path = self.make_path(path)?;
         convert_io_result(fs::remove_file(abs_path))
     }
 
     fn create_directory(&mut self, path: PathBuf) -> Result<()> {
-        let abs_path = self.make_path(path);
+        let abs_path = self.make_path(path)?;
         convert_io_result(fs::create_dir(abs_path))
     }
 
     fn delete_directory(&mut self, path: PathBuf) -> Result<()> {
-        let abs_path = self.make_path(path);
+        let abs_path = self.make_path(path)?;
         convert_io_result(fs::remove_dir(abs_path))
     }
 
     fn delete_directory_recursively(&mut self, path: PathBuf) -> Result<()> {
-        let abs_path = self.make_path(path);
+        let abs_path = self.make_path(path)?;
         convert_io_result(fs::remove_dir_all(abs_path))
     }
 
     fn rename_file(&mut self, old_path: PathBuf, new_path: PathBuf) -> Result<()> {
-        let abs_old_path = self.make_path(old_path);
-        let abs_new_path = self.make_path(new_path);
+        let abs_old_path = self.make_path(old_path)?;
+        let abs_new_path = self.make_path(new_path)?;
         convert_io_result(fs::rename(abs_old_path, abs_new_path))
     }
 
     fn rename_directory(&mut self, old_path: PathBuf, new_path: PathBuf) -> Result<()> {
-        let abs_old_path = self.make_path(old_path);
-        let abs_new_path = self.make_path(new_path);
+        let abs_old_path = self.make_path(old_path)?;
+        let abs_new_path = self.make_path(new_path)?;
         convert_io_result(fs::rename(abs_old_path, abs_new_path))
     }
 
     fn get_entry_type(&mut self, path: PathBuf) -> Result<DirectoryEntryType> {
-        let abs_path = self.make_path(path);
+        let abs_path = self.make_path(path)?;
         let metadata = convert_io_result(fs::metadata(abs_path))?;
 
         let entry_type = match metadata.is_dir() {
@@ -342,16 +449,17 @@ impl FileSystem for HostFileSystem {
     }
 
     fn open_file(&mut self, path: PathBuf, open_mode: FileOpenMode) -> Result<Shared<dyn File>> {
-        let abs_path = self.make_path(path);
+        let abs_path = self.make_path(path)?;
 
-        let std_file = convert_io_result(OpenOptions::new().read(open_mode.contains(FileOpenMode::Read())).write(open_mode.contains(FileOpenMode::Write())).append(open_mode.contains(FileOpenMode::Append())).open(abs_path))?;
+        // Note: Append just allows writes to extend the file, unlike the host append mode (where writes would ignore their offsets)
+        let std_file = convert_io_result(OpenOptions::new().read(open_mode.contains(FileOpenMode::Read())).write(open_mode.contains(FileOpenMode::Write())).open(abs_path))?;
 
         let file = Shared::new(HostFile::new(std_file));
         Ok(file)
     }
 
     fn open_directory(&mut self, path: PathBuf, open_mode: DirectoryOpenMode) -> Result<Shared<dyn Directory>> {
-        let abs_path = self.make_path(path);
+        let abs_path = self.make_path(path)?;
 
         let entries = convert_io_result(convert_io_result(fs::read_dir(abs_path))?.collect::<IoResult<Vec<_>>>())?;
 
@@ -364,12 +472,18 @@ impl FileSystem for HostFileSystem {
     }
 
 
-    fn get_free_space_size(&mut self, _path: PathBuf) -> Result<usize> {
-        todo!("GetFreeSpaceSize for HostFileSystem");
+    fn get_free_space_size(&mut self, path: PathBuf) -> Result<usize> {
+        let abs_path = self.make_path(path)?;
+
+        let (free_size, _) = get_host_disk_space(&abs_path)?;
+        Ok(free_size)
     }
 
-    fn get_total_space_size(&mut self, _path: PathBuf) -> Result<usize> {
-        todo!("GetTotalSpaceSize for HostFileSystem");
+    fn get_total_space_size(&mut self, path: PathBuf) -> Result<usize> {
+        let abs_path = self.make_path(path)?;
+
+        let (_, total_size) = get_host_disk_space(&abs_path)?;
+        Ok(total_size)
     }
 
     fn clean_directory_recursively(&mut self, path: PathBuf) -> Result<()> {
@@ -379,8 +493,24 @@ impl FileSystem for HostFileSystem {
         Ok(())
     }
 
-    fn get_file_time_stamp_raw(&mut self, _path: PathBuf) -> Result<TimeStampRaw> {
-        todo!("GetFileTimeStampRaw for HostFileSystem");
+    fn get_file_time_stamp_raw(&mut self, path: PathBuf) -> Result<TimeStampRaw> {
+        let abs_path = self.make_path(path)?;
+
+        let metadata = convert_io_result(fs::metadata(abs_path))?;
+        result_return_unless!(metadata.is_file(), result::ResultPathNotFound);
+
+        let modified = convert_host_time_stamp(metadata.modified());
+        // Note: not all host platforms/filesystems keep creation/access times, so the modification time is used instead in those cases
+        let created = convert_host_time_stamp(metadata.created()).or(modified);
+        let accessed = convert_host_time_stamp(metadata.accessed()).or(modified);
+
+        Ok(TimeStampRaw {
+            created: created.unwrap_or(0),
+            modified: modified.unwrap_or(0),
+            accessed: accessed.unwrap_or(0),
+            is_valid: modified.is_some(),
+            pad: [0; 0x7]
+        })
     }
 }
 
@@ -430,25 +560,27 @@ impl File for PartitionFile {
 
 pub struct PartitionRootDirectory {
     file_info: Vec<(String, usize)>,
-    mode: DirectoryOpenMode
+    mode: DirectoryOpenMode,
+    read_idx: usize
 }
 
 impl PartitionRootDirectory {
     pub fn new(file_info: Vec<(String, usize)>, mode: DirectoryOpenMode) -> Self {
         Self {
             file_info: file_info,
-            mode: mode
+            mode: mode,
+            read_idx: 0
         }
     }
 }
 
 impl Directory for PartitionRootDirectory {
     fn read(&mut self, count: usize) -> Result<Vec<DirectoryEntry>> {
-        let actual_count = count.min(self.file_info.len());
+        let actual_count = count.min(self.file_info.len() - self.read_idx);
         let mut dir_entries: Vec<DirectoryEntry> = Vec::with_capacity(actual_count);
 
         if self.mode.contains(DirectoryOpenMode::ReadFiles()) {
-            for i in 0..actual_count {
+            for i in self.read_idx..self.read_idx + actual_count {
                 let (file_name, file_size) = &self.file_info[i];
     
                 let dir_entry = DirectoryEntry {
@@ -462,6 +594,7 @@ impl Directory for PartitionRootDirectory {
     
                 dir_entries.push(dir_entry);
             }
+            self.read_idx += actual_count;
         }
 
         Ok(dir_entries)
@@ -526,7 +659,7 @@ impl FileSystem for PartitionFileSystem {
     }
 
     fn get_entry_type(&mut self, path: PathBuf) -> Result<DirectoryEntryType> {
-        let path_str = path.as_path().display().to_string();
+        let path_str = make_path_str(&path)?;
 
         if path_str.is_empty() {
             Ok(DirectoryEntryType::Directory)
@@ -542,7 +675,7 @@ impl FileSystem for PartitionFileSystem {
     fn open_file(&mut self, path: PathBuf, open_mode: FileOpenMode) -> Result<Shared<dyn File>> {
         result_return_if!(open_mode != FileOpenMode::Read(), result::ResultWriteNotPermitted);
 
-        let path_str = path.as_path().display().to_string();
+        let path_str = make_path_str(&path)?;
 
         if let Some(file_idx) = self.files.iter().position(|file_name| file_name.eq(&path_str)) {
             let file = Shared::new(PartitionFile::new(self.base_fs.clone(), file_idx));
@@ -555,7 +688,7 @@ impl FileSystem for PartitionFileSystem {
 
     fn open_directory(&mut self, path: PathBuf, open_mode: DirectoryOpenMode) -> Result<Shared<dyn Directory>> {
         // The only directory in a PFS0 is the root directory
-        let path_str = path.as_path().display().to_string();
+        let path_str = make_path_str(&path)?;
         result_return_unless!(path_str.is_empty(), result::ResultPathNotFound);
 
         let mut file_info: Vec<(String, usize)> = Vec::new();
@@ -657,12 +790,8 @@ impl Directory for RomFsDirectory {
     fn read(&mut self, count: usize) -> Result<Vec<DirectoryEntry>> {
         let mut dir_entries: Vec<DirectoryEntry> = Vec::new();
 
-        loop {
-            if dir_entries.len() == count {
-                break;
-            }
-
-            let mut entry_added = false;
+        // Note: a single entry is read per iteration, so that reads never go past the requested count (remaining entries are left for the next read)
+        while dir_entries.len() < count {
             if self.mode.contains(DirectoryOpenMode::ReadDirectories()) {
                 if let Ok(dir_name) = self.dir_iter.next_dir() {
                     let dir_entry = DirectoryEntry {
@@ -673,9 +802,9 @@ impl Directory for RomFsDirectory {
                         pad_2: [0; 0x3],
                         file_size: 0
                     };
-        
+
                     dir_entries.push(dir_entry);
-                    entry_added = true;
+                    continue;
                 }
             }
 
@@ -689,15 +818,13 @@ impl Directory for RomFsDirectory {
                         pad_2: [0; 0x3],
                         file_size: if self.mode.contains(DirectoryOpenMode::NoFileSize()) { 0 } else { file_size }
                     };
-        
+
                     dir_entries.push(dir_entry);
-                    entry_added = true;
+                    continue;
                 }
             }
 
-            if !entry_added {
-                break;
-            }
+            break;
         }
 
         Ok(dir_entries)
@@ -759,7 +886,7 @@ impl FileSystem for RomFsFileSystem {
     }
 
     fn get_entry_type(&mut self, path: PathBuf) -> Result<DirectoryEntryType> {
-        let path_str = path.as_path().display().to_string();
+        let path_str = make_path_str(&path)?;
 
         if path_str.is_empty() {
             Ok(DirectoryEntryType::Directory)
@@ -784,7 +911,7 @@ impl FileSystem for RomFsFileSystem {
 
     fn open_file(&mut self, path: PathBuf, open_mode: FileOpenMode) -> Result<Shared<dyn File>> {
         result_return_if!(open_mode != FileOpenMode::Read(), result::ResultWriteNotPermitted);
-        let path_str = path.as_path().display().to_string();
+        let path_str = make_path_str(&path)?;
 
         let mut base_fs_v = self.base_fs.get();
         if let Ok(file_offset) = base_fs_v.get_file_offset(path_str.clone()) {
@@ -798,7 +925,7 @@ impl FileSystem for RomFsFileSystem {
     }
 
     fn open_directory(&mut self, path: PathBuf, open_mode: DirectoryOpenMode) -> Result<Shared<dyn Directory>> {
-        let path_str = path.as_path().display().to_string();
+        let path_str = make_path_str(&path)?;
 
         if let Ok(dir_iter) = self.base_fs.get().open_dir_iterator(path_str) {
             let dir = Shared::new(RomFsDirectory::new(dir_iter, open_mode));
@@ -832,4 +959,829 @@ impl FileSystem for RomFsFileSystem {
     }
 }
 
-// ---
\ No newline at end of file
+// ---
+
+// Concatenation
+
+// Console FAT filesystems can't hold files bigger than 4GB, thus big files are instead directories (with the archive attribute set) containing the actual file split in parts ("00", "01"...)
+// Note: host filesystems have no (portable) archive attribute, so a marker file inside the directory is used instead
+
+pub const CONCATENATION_FILE_PART_SIZE: usize = 0xFFFF0000;
+pub const CONCATENATION_FILE_MARKER: &str = ".concatenation_file";
+
+#[inline]
+fn make_concatenation_file_part_name(part_idx: usize) -> String {
+    format!("{:02}", part_idx)
+}
+
+#[inline]
+fn get_concatenation_file_part_count(size: usize) -> usize {
+    // Even empty files have their first part
+    ((size + CONCATENATION_FILE_PART_SIZE - 1) / CONCATENATION_FILE_PART_SIZE).max(1)
+}
+
+pub struct ConcatenationFile {
+    base_fs: Shared<dyn FileSystem>,
+    path: PathBuf,
+    open_mode: FileOpenMode,
+    parts: Vec<Shared<dyn File>>
+}
+
+// Note: the base filesystem/files are only accessed through their mutexes
+unsafe impl Send for ConcatenationFile {}
+unsafe impl Sync for ConcatenationFile {}
+
+impl ConcatenationFile {
+    pub fn new(base_fs: Shared<dyn FileSystem>, path: PathBuf, open_mode: FileOpenMode) -> Result<Self> {
+        let mut parts: Vec<Shared<dyn File>> = Vec::new();
+        loop {
+            let part_path = path.join(make_concatenation_file_part_name(parts.len()));
+            match base_fs.get().open_file(part_path, open_mode) {
+                Ok(part) => parts.push(part),
+                Err(rc) => {
+                    if result::ResultPathNotFound::matches(rc) && !parts.is_empty() {
+                        break;
+                    }
+                    return Err(rc);
+                }
+            };
+        }
+
+        Ok(Self {
+            base_fs: base_fs,
+            path: path,
+            open_mode: open_mode,
+            parts: parts
+        })
+    }
+}
+
+impl File for ConcatenationFile {
+    fn read(&mut self, offset: u64, data: &mut [u8], option: ReadOption) -> Result<usize> {
+        let mut read_size: usize = 0;
+        while read_size < data.len() {
+            let cur_offset = offset as usize + read_size;
+            let part_idx = cur_offset / CONCATENATION_FILE_PART_SIZE;
+            if part_idx >= self.parts.len() {
+                break;
+            }
+
+            let part_offset = cur_offset % CONCATENATION_FILE_PART_SIZE;
+            let part_read_size = (data.len() - read_size).min(CONCATENATION_FILE_PART_SIZE - part_offset);
+            let part_data = &mut data[read_size..read_size + part_read_size];
+            let actual_read_size = self.parts[part_idx].get().read(part_offset as u64, part_data, option)?;
+            read_size += actual_read_size;
+
+            // Reached the end of the file
+            if actual_read_size < part_read_size {
+                break;
+            }
+        }
+
+        Ok(read_size)
+    }
+
+    fn write(&mut self, offset: u64, data: &[u8], option: WriteOption) -> Result<usize> {
+        result_return_unless!(self.open_mode.contains(FileOpenMode::Write()), result::ResultWriteNotPermitted);
+
+        // Writing past the end extends the file (which may need new parts), which is only allowed when opened for appending
+        let end_offset = (offset as usize).checked_add(data.len()).ok_or(result::ResultOutOfRange::make())?;
+        if end_offset > self.get_size()? {
+            result_return_unless!(self.open_mode.contains(FileOpenMode::Append()), result::ResultFileExtensionWithoutOpenModeAllowAppend);
+            self.set_size(end_offset)?;
+        }
+
+        let mut written_size: usize = 0;
+        while written_size < data.len() {
+            let cur_offset = offset as usize + written_size;
+            let part_idx = cur_offset / CONCATENATION_FILE_PART_SIZE;
+            let part_offset = cur_offset % CONCATENATION_FILE_PART_SIZE;
+            let part_write_size = (data.len() - written_size).min(CONCATENATION_FILE_PART_SIZE - part_offset);
+            let part_data = &data[written_size..written_size + part_write_size];
+            let part_written_size = self.parts[part_idx].get().write(part_offset as u64, part_data, option)?;
+
+            // Note: the parts were already sized above, thus a part not accepting any data would otherwise be retried forever
+            result_return_if!(part_written_size == 0, result::ResultOutOfRange);
+            written_size += part_written_size;
+        }
+
+        Ok(written_size)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        for part in self.parts.iter() {
+            part.get().flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn set_size(&mut self, size: usize) -> Result<()> {
+        result_return_unless!(self.open_mode.contains(FileOpenMode::Write()), result::ResultWriteNotPermitted);
+
+        let part_count = get_concatenation_file_part_count(size);
+
+        // Remove the parts which aren't needed anymore
+        while self.parts.len() > part_count {
+            let part_idx = self.parts.len() - 1;
+            self.parts.pop();
+            self.base_fs.get().delete_file(self.path.join(make_concatenation_file_part_name(part_idx)))?;
+        }
+
+        // Create the missing ones
+        while self.parts.len() < part_count {
+            let part_path = self.path.join(make_concatenation_file_part_name(self.parts.len()));
+            self.base_fs.get().create_file(part_path.clone(), 0, CreateOption::None())?;
+            let part = self.base_fs.get().open_file(part_path, self.open_mode)?;
+            self.parts.push(part);
+        }
+
+        // All the parts but the last one are full
+        for (part_idx, part) in self.parts.iter().enumerate() {
+            let part_size = match part_idx == part_count - 1 {
+                true => size - part_idx * CONCATENATION_FILE_PART_SIZE,
+                false => CONCATENATION_FILE_PART_SIZE
+            };
+            part.get().set_size(part_size)?;
+        }
+
+        Ok(())
+    }
+
+    fn get_size(&mut self) -> Result<usize> {
+        let mut size: usize = 0;
+        for part in self.parts.iter() {
+            size += part.get().get_size()?;
+        }
+
+        Ok(size)
+    }
+
+    fn operate_range(&mut self, op_id: OperationId, offset: u64, size: usize) -> Result<RangeInfo> {
+        result_return_unless!(op_id == OperationId::QueryRange, result::ResultUnsupportedOperationInFileServiceObjectAdapterA);
+
+        // Merge the info of all the parts involved
+        let mut range_info = RangeInfo {
+            aes_ctr_key_type: 0,
+            speed_emulation_type: 0,
+            reserved: [0; 0x38]
+        };
+        let end_offset = offset as usize + size;
+        let mut cur_offset = offset as usize;
+        while cur_offset < end_offset {
+            let part_idx = cur_offset / CONCATENATION_FILE_PART_SIZE;
+            if part_idx >= self.parts.len() {
+                break;
+            }
+
+            let part_offset = cur_offset % CONCATENATION_FILE_PART_SIZE;
+            let part_size = (end_offset - cur_offset).min(CONCATENATION_FILE_PART_SIZE - part_offset);
+            let part_range_info = self.parts[part_idx].get().operate_range(op_id, part_offset as u64, part_size)?;
+            range_info.aes_ctr_key_type |= part_range_info.aes_ctr_key_type;
+            range_info.speed_emulation_type |= part_range_info.speed_emulation_type;
+            cur_offset += part_size;
+        }
+
+        Ok(range_info)
+    }
+}
+
+pub struct ConcatenationDirectory {
+    base_fs: Shared<dyn FileSystem>,
+    path: PathBuf,
+    base_dir: Shared<dyn Directory>,
+    open_mode: DirectoryOpenMode
+}
+
+unsafe impl Send for ConcatenationDirectory {}
+unsafe impl Sync for ConcatenationDirectory {}
+
+impl ConcatenationDirectory {
+    pub fn new(base_fs: Shared<dyn FileSystem>, path: PathBuf, open_mode: DirectoryOpenMode) -> Result<Self> {
+        // Concatenation files are directories in the base filesystem, thus both kinds of entries are needed to find them
+        let base_dir = base_fs.get().open_directory(path.clone(), DirectoryOpenMode::ReadDirectories() | DirectoryOpenMode::ReadFiles())?;
+
+        Ok(Self {
+            base_fs: base_fs,
+            path: path,
+            base_dir: base_dir,
+            open_mode: open_mode
+        })
+    }
+
+    fn get_entry_path(&self, entry: &DirectoryEntry) -> Result<PathBuf> {
+        let entry_path = PathBuf::from(entry.path.get_string()?);
+        match entry_path.file_name() {
+            Some(entry_name) => Ok(self.path.join(entry_name)),
+            None => result::ResultInvalidPathFormat::make_err()
+        }
+    }
+
+    fn fix_entry(&self, mut entry: DirectoryEntry) -> Result<Option<DirectoryEntry>> {
+        if entry.entry_type == DirectoryEntryType::Directory {
+            let entry_path = self.get_entry_path(&entry)?;
+            if is_concatenation_file(&self.base_fs, &entry_path) {
+                entry.entry_type = DirectoryEntryType::File;
+                entry.file_attr = FileAttribute::ArchiveBit();
+                entry.file_size = match self.open_mode.contains(DirectoryOpenMode::NoFileSize()) {
+                    true => 0,
+                    false => ConcatenationFile::new(self.base_fs.clone(), entry_path, FileOpenMode::Read())?.get_size()?
+                };
+            }
+        }
+
+        let is_wanted = match entry.entry_type {
+            DirectoryEntryType::Directory => self.open_mode.contains(DirectoryOpenMode::ReadDirectories()),
+            DirectoryEntryType::File => self.open_mode.contains(DirectoryOpenMode::ReadFiles())
+        };
+        match is_wanted {
+            true => Ok(Some(entry)),
+            false => Ok(None)
+        }
+    }
+}
+
+impl Directory for ConcatenationDirectory {
+    fn read(&mut self, count: usize) -> Result<Vec<DirectoryEntry>> {
+        let mut dir_entries: Vec<DirectoryEntry> = Vec::with_capacity(count);
+        while dir_entries.len() < count {
+            let base_entries = self.base_dir.get().read(count - dir_entries.len())?;
+            if base_entries.is_empty() {
+                break;
+            }
+
+            for base_entry in base_entries {
+                if let Some(entry) = self.fix_entry(base_entry)? {
+                    dir_entries.push(entry);
+                }
+            }
+        }
+
+        Ok(dir_entries)
+    }
+
+    fn get_entry_count(&mut self) -> Result<usize> {
+        // Entries must be classified one by one, so list them all through a new directory
+        let mut dir = Self::new(self.base_fs.clone(), self.path.clone(), self.open_mode | DirectoryOpenMode::NoFileSize())?;
+        let base_count = dir.base_dir.get().get_entry_count()?;
+        Ok(dir.read(base_count)?.len())
+    }
+}
+
+fn is_concatenation_file(base_fs: &Shared<dyn FileSystem>, path: &PathBuf) -> bool {
+    let mut base_fs_v = base_fs.get();
+    match base_fs_v.get_entry_type(path.clone()) {
+        Ok(DirectoryEntryType::Directory) => matches!(base_fs_v.get_entry_type(path.join(CONCATENATION_FILE_MARKER)), Ok(DirectoryEntryType::File)),
+        _ => false
+    }
+}
+
+pub struct ConcatenationFileSystem {
+    base_fs: Shared<dyn FileSystem>
+}
+
+unsafe impl Send for ConcatenationFileSystem {}
+unsafe impl Sync for ConcatenationFileSystem {}
+
+impl ConcatenationFileSystem {
+    pub fn new(base_fs: Shared<dyn FileSystem>) -> Shared<Self> {
+        Shared::new(Self {
+            base_fs: base_fs
+        })
+    }
+
+    #[inline]
+    fn is_concatenation_file(&self, path: &PathBuf) -> bool {
+        is_concatenation_file(&self.base_fs, path)
+    }
+}
+
+impl FileSystem for ConcatenationFileSystem {
+    fn create_file(&mut self, path: PathBuf, size: usize, create_option: CreateOption) -> Result<()> {
+        if !create_option.contains(CreateOption::ConcatenationFile()) {
+            return self.base_fs.get().create_file(path, size, create_option);
+        }
+
+        result_return_if!(self.base_fs.get().get_entry_type(path.clone()).is_ok(), result::ResultPathAlreadyExists);
+        self.base_fs.get().create_directory(path.clone())?;
+        self.base_fs.get().create_file(path.join(CONCATENATION_FILE_MARKER), 0, CreateOption::None())?;
+
+        let part_count = get_concatenation_file_part_count(size);
+        for part_idx in 0..part_count {
+            let part_size = match part_idx == part_count - 1 {
+                true => size - part_idx * CONCATENATION_FILE_PART_SIZE,
+                false => CONCATENATION_FILE_PART_SIZE
+            };
+            self.base_fs.get().create_file(path.join(make_concatenation_file_part_name(part_idx)), part_size, CreateOption::None())?;
+        }
+
+        Ok(())
+    }
+
+    fn delete_file(&mut self, path: PathBuf) -> Result<()> {
+        match self.is_concatenation_file(&path) {
+            true => self.base_fs.get().delete_directory_recursively(path),
+            false => self.base_fs.get().delete_file(path)
+        }
+    }
+
+    fn create_directory(&mut self, path: PathBuf) -> Result<()> {
+        self.base_fs.get().create_directory(path)
+    }
+
+    fn delete_directory(&mut self, path: PathBuf) -> Result<()> {
+        result_return_if!(self.is_concatenation_file(&path), result::ResultPathNotFound);
+        self.base_fs.get().delete_directory(path)
+    }
+
+    fn delete_directory_recursively(&mut self, path: PathBuf) -> Result<()> {
+        result_return_if!(self.is_concatenation_file(&path), result::ResultPathNotFound);
+        self.base_fs.get().delete_directory_recursively(path)
+    }
+
+    fn rename_file(&mut self, old_path: PathBuf, new_path: PathBuf) -> Result<()> {
+        match self.is_concatenation_file(&old_path) {
+            true => self.base_fs.get().rename_directory(old_path, new_path),
+            false => self.base_fs.get().rename_file(old_path, new_path)
+        }
+    }
+
+    fn rename_directory(&mut self, old_path: PathBuf, new_path: PathBuf) -> Result<()> {
+        result_return_if!(self.is_concatenation_file(&old_path), result::ResultPathNotFound);
+        self.base_fs.get().rename_directory(old_path, new_path)
+    }
+
+    fn get_entry_type(&mut self, path: PathBuf) -> Result<DirectoryEntryType> {
+        match self.is_concatenation_file(&path) {
+            true => Ok(DirectoryEntryType::File),
+            false => self.base_fs.get().get_entry_type(path)
+        }
+    }
+
+    fn open_file(&mut self, path: PathBuf, open_mode: FileOpenMode) -> Result<Shared<dyn File>> {
+        match self.is_concatenation_file(&path) {
+            true => Ok(Shared::new(ConcatenationFile::new(self.base_fs.clone(), path, open_mode)?)),
+            false => self.base_fs.get().open_file(path, open_mode)
+        }
+    }
+
+    fn open_directory(&mut self, path: PathBuf, open_mode: DirectoryOpenMode) -> Result<Shared<dyn Directory>> {
+        result_return_if!(self.is_concatenation_file(&path), result::ResultPathNotFound);
+        Ok(Shared::new(ConcatenationDirectory::new(self.base_fs.clone(), path, open_mode)?))
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        self.base_fs.get().commit()
+    }
+
+    fn get_free_space_size(&mut self, path: PathBuf) -> Result<usize> {
+        self.base_fs.get().get_free_space_size(path)
+    }
+
+    fn get_total_space_size(&mut self, path: PathBuf) -> Result<usize> {
+        self.base_fs.get().get_total_space_size(path)
+    }
+
+    fn clean_directory_recursively(&mut self, path: PathBuf) -> Result<()> {
+        result_return_if!(self.is_concatenation_file(&path), result::ResultPathNotFound);
+        self.base_fs.get().clean_directory_recursively(path)
+    }
+
+    fn get_file_time_stamp_raw(&mut self, path: PathBuf) -> Result<TimeStampRaw> {
+        // Note: for concatenation files, this is the directory's timestamp
+        self.base_fs.get().get_file_time_stamp_raw(path)
+    }
+}
+
+// ---
+
+// Subdirectory
+
+// View of a directory inside another filesystem as its root (for instance, SD card subdirectories mounted as saves)
+
+pub struct SubdirectoryFileSystem {
+    base_fs: Shared<dyn FileSystem>,
+    base_path: PathBuf
+}
+
+unsafe impl Send for SubdirectoryFileSystem {}
+unsafe impl Sync for SubdirectoryFileSystem {}
+
+impl SubdirectoryFileSystem {
+    pub fn new(base_fs: Shared<dyn FileSystem>, base_path: PathBuf) -> Result<Shared<Self>> {
+        let base_path = path::normalize(&base_path)?;
+        result_return_unless!(base_fs.get().get_entry_type(base_path.clone())? == DirectoryEntryType::Directory, result::ResultPathNotFound);
+
+        Ok(Shared::new(Self {
+            base_fs: base_fs,
+            base_path: base_path
+        }))
+    }
+
+    // Note: normalizing first ensures the resulting path never leaves the subdirectory
+    fn make_path(&self, path: PathBuf) -> Result<PathBuf> {
+        let normalized_path = path::normalize(&path)?;
+        Ok(self.base_path.join(normalized_path))
+    }
+}
+
+impl FileSystem for SubdirectoryFileSystem {
+    fn create_file(&mut self, path: PathBuf, size: usize, create_option: CreateOption) -> Result<()> {
+        let base_path = self.make_path(path)?;
+        self.base_fs.get().create_file(base_path, size, create_option)
+    }
+
+    fn delete_file(&mut self, path: PathBuf) -> Result<()> {
+        let base_path = self.make_path(path)?;
+        self.base_fs.get().delete_file(base_path)
+    }
+
+    fn create_directory(&mut self, path: PathBuf) -> Result<()> {
+        let base_path = self.make_path(path)?;
+        self.base_fs.get().create_directory(base_path)
+    }
+
+    fn delete_directory(&mut self, path: PathBuf) -> Result<()> {
+        let base_path = self.make_path(path)?;
+        result_return_if!(base_path == self.base_path, result::ResultDirectoryNotDeletable);
+        self.base_fs.get().delete_directory(base_path)
+    }
+
+    fn delete_directory_recursively(&mut self, path: PathBuf) -> Result<()> {
+        let base_path = self.make_path(path)?;
+        result_return_if!(base_path == self.base_path, result::ResultDirectoryNotDeletable);
+        self.base_fs.get().delete_directory_recursively(base_path)
+    }
+
+    fn rename_file(&mut self, old_path: PathBuf, new_path: PathBuf) -> Result<()> {
+        let base_old_path = self.make_path(old_path)?;
+        let base_new_path = self.make_path(new_path)?;
+        self.base_fs.get().rename_file(base_old_path, base_new_path)
+    }
+
+    fn rename_directory(&mut self, old_path: PathBuf, new_path: PathBuf) -> Result<()> {
+        let base_old_path = self.make_path(old_path)?;
+        let base_new_path = self.make_path(new_path)?;
+        result_return_if!(base_old_path == self.base_path, result::ResultDirectoryNotRenamable);
+        self.base_fs.get().rename_directory(base_old_path, base_new_path)
+    }
+
+    fn get_entry_type(&mut self, path: PathBuf) -> Result<DirectoryEntryType> {
+        let base_path = self.make_path(path)?;
+        self.base_fs.get().get_entry_type(base_path)
+    }
+
+    fn open_file(&mut self, path: PathBuf, open_mode: FileOpenMode) -> Result<Shared<dyn File>> {
+        let base_path = self.make_path(path)?;
+        self.base_fs.get().open_file(base_path, open_mode)
+    }
+
+    fn open_directory(&mut self, path: PathBuf, open_mode: DirectoryOpenMode) -> Result<Shared<dyn Directory>> {
+        let base_path = self.make_path(path)?;
+        self.base_fs.get().open_directory(base_path, open_mode)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        self.base_fs.get().commit()
+    }
+
+    fn get_free_space_size(&mut self, path: PathBuf) -> Result<usize> {
+        let base_path = self.make_path(path)?;
+        self.base_fs.get().get_free_space_size(base_path)
+    }
+
+    fn get_total_space_size(&mut self, path: PathBuf) -> Result<usize> {
+        let base_path = self.make_path(path)?;
+        self.base_fs.get().get_total_space_size(base_path)
+    }
+
+    fn clean_directory_recursively(&mut self, path: PathBuf) -> Result<()> {
+        let base_path = self.make_path(path)?;
+        self.base_fs.get().clean_directory_recursively(base_path)
+    }
+
+    fn get_file_time_stamp_raw(&mut self, path: PathBuf) -> Result<TimeStampRaw> {
+        let base_path = self.make_path(path)?;
+        self.base_fs.get().get_file_time_stamp_raw(base_path)
+    }
+}
+
+// ---
+
+// ReadOnly
+
+pub struct ReadOnlyFile {
+    base_file: Shared<dyn File>
+}
+
+unsafe impl Send for ReadOnlyFile {}
+unsafe impl Sync for ReadOnlyFile {}
+
+impl ReadOnlyFile {
+    pub fn new(base_file: Shared<dyn File>) -> Self {
+        Self {
+            base_file: base_file
+        }
+    }
+}
+
+impl File for ReadOnlyFile {
+    fn read(&mut self, offset: u64, data: &mut [u8], option: ReadOption) -> Result<usize> {
+        self.base_file.get().read(offset, data, option)
+    }
+
+    fn write(&mut self, _offset: u64, _data: &[u8], _option: WriteOption) -> Result<usize> {
+        result::ResultUnsupportedOperationInReadOnlyFileA::make_err()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_size(&mut self, _size: usize) -> Result<()> {
+        result::ResultUnsupportedOperationInReadOnlyFileA::make_err()
+    }
+
+    fn get_size(&mut self) -> Result<usize> {
+        self.base_file.get().get_size()
+    }
+
+    fn operate_range(&mut self, op_id: OperationId, offset: u64, size: usize) -> Result<RangeInfo> {
+        // Only querying is allowed, anything else may modify the file
+        result_return_unless!(op_id == OperationId::QueryRange, result::ResultUnsupportedOperationInReadOnlyFileB);
+        self.base_file.get().operate_range(op_id, offset, size)
+    }
+}
+
+pub struct ReadOnlyFileSystem {
+    base_fs: Shared<dyn FileSystem>
+}
+
+unsafe impl Send for ReadOnlyFileSystem {}
+unsafe impl Sync for ReadOnlyFileSystem {}
+
+impl ReadOnlyFileSystem {
+    pub fn new(base_fs: Shared<dyn FileSystem>) -> Shared<Self> {
+        Shared::new(Self {
+            base_fs: base_fs
+        })
+    }
+}
+
+impl FileSystem for ReadOnlyFileSystem {
+    fn create_file(&mut self, _path: PathBuf, _size: usize, _create_option: CreateOption) -> Result<()> {
+        result::ResultUnsupportedOperationInReadOnlyFileSystemTemplateA::make_err()
+    }
+
+    fn delete_file(&mut self, _path: PathBuf) -> Result<()> {
+        result::ResultUnsupportedOperationInReadOnlyFileSystemTemplateA::make_err()
+    }
+
+    fn create_directory(&mut self, _path: PathBuf) -> Result<()> {
+        result::ResultUnsupportedOperationInReadOnlyFileSystemTemplateA::make_err()
+    }
+
+    fn delete_directory(&mut self, _path: PathBuf) -> Result<()> {
+        result::ResultUnsupportedOperationInReadOnlyFileSystemTemplateA::make_err()
+    }
+
+    fn delete_directory_recursively(&mut self, _path: PathBuf) -> Result<()> {
+        result::ResultUnsupportedOperationInReadOnlyFileSystemTemplateA::make_err()
+    }
+
+    fn rename_file(&mut self, _old_path: PathBuf, _new_path: PathBuf) -> Result<()> {
+        result::ResultUnsupportedOperationInReadOnlyFileSystemTemplateA::make_err()
+    }
+
+    fn rename_directory(&mut self, _old_path: PathBuf, _new_path: PathBuf) -> Result<()> {
+        result::ResultUnsupportedOperationInReadOnlyFileSystemTemplateA::make_err()
+    }
+
+    fn get_entry_type(&mut self, path: PathBuf) -> Result<DirectoryEntryType> {
+        self.base_fs.get().get_entry_type(path)
+    }
+
+    fn open_file(&mut self, path: PathBuf, open_mode: FileOpenMode) -> Result<Shared<dyn File>> {
+        result_return_unless!(open_mode == FileOpenMode::Read(), result::ResultInvalidOpenMode);
+
+        let base_file = self.base_fs.get().open_file(path, open_mode)?;
+        Ok(Shared::new(ReadOnlyFile::new(base_file)))
+    }
+
+    fn open_directory(&mut self, path: PathBuf, open_mode: DirectoryOpenMode) -> Result<Shared<dyn Directory>> {
+        self.base_fs.get().open_directory(path, open_mode)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn get_free_space_size(&mut self, _path: PathBuf) -> Result<usize> {
+        result::ResultUnsupportedOperationInReadOnlyFileSystemTemplateB::make_err()
+    }
+
+    fn get_total_space_size(&mut self, path: PathBuf) -> Result<usize> {
+        self.base_fs.get().get_total_space_size(path)
+    }
+
+    fn clean_directory_recursively(&mut self, _path: PathBuf) -> Result<()> {
+        result::ResultUnsupportedOperationInReadOnlyFileSystemTemplateA::make_err()
+    }
+
+    fn get_file_time_stamp_raw(&mut self, path: PathBuf) -> Result<TimeStampRaw> {
+        self.base_fs.get().get_file_time_stamp_raw(path)
+    }
+}
+
+// ---
+
+// Layered
+
+// Read-only overlay of several filesystems: entries of upper layers (the first ones) hide the same entries of lower layers, while directories present on several layers get their contents merged
+
+pub struct LayeredDirectory {
+    entries: Vec<DirectoryEntry>,
+    read_count: usize
+}
+
+impl LayeredDirectory {
+    pub fn new(entries: Vec<DirectoryEntry>) -> Self {
+        Self {
+            entries: entries,
+            read_count: 0
+        }
+    }
+}
+
+impl Directory for LayeredDirectory {
+    fn read(&mut self, count: usize) -> Result<Vec<DirectoryEntry>> {
+        let actual_count = count.min(self.entries.len() - self.read_count);
+        let dir_entries = self.entries[self.read_count..self.read_count + actual_count].to_vec();
+        self.read_count += actual_count;
+        Ok(dir_entries)
+    }
+
+    fn get_entry_count(&mut self) -> Result<usize> {
+        Ok(self.entries.len())
+    }
+}
+
+pub struct LayeredFileSystem {
+    layers: Vec<Shared<dyn FileSystem>>
+}
+
+unsafe impl Send for LayeredFileSystem {}
+unsafe impl Sync for LayeredFileSystem {}
+
+impl LayeredFileSystem {
+    // Layers are given from the top to the bottom one
+    pub fn new(layers: Vec<Shared<dyn FileSystem>>) -> Shared<Self> {
+        Shared::new(Self {
+            layers: layers
+        })
+    }
+
+    fn find_layer(&self, path: &PathBuf) -> Result<(Shared<dyn FileSystem>, DirectoryEntryType)> {
+        for layer in self.layers.iter() {
+            if let Ok(entry_type) = layer.get().get_entry_type(path.clone()) {
+                return Ok((layer.clone(), entry_type));
+            }
+        }
+
+        result::ResultPathNotFound::make_err()
+    }
+}
+
+impl FileSystem for LayeredFileSystem {
+    fn create_file(&mut self, _path: PathBuf, _size: usize, _create_option: CreateOption) -> Result<()> {
+        result::ResultUnsupportedOperationInReadOnlyFileSystemTemplateA::make_err()
+    }
+
+    fn delete_file(&mut self, _path: PathBuf) -> Result<()> {
+        result::ResultUnsupportedOperationInReadOnlyFileSystemTemplateA::make_err()
+    }
+
+    fn create_directory(&mut self, _path: PathBuf) -> Result<()> {
+        result::ResultUnsupportedOperationInReadOnlyFileSystemTemplateA::make_err()
+    }
+
+    fn delete_directory(&mut self, _path: PathBuf) -> Result<()> {
+        result::ResultUnsupportedOperationInReadOnlyFileSystemTemplateA::make_err()
+    }
+
+    fn delete_directory_recursively(&mut self, _path: PathBuf) -> Result<()> {
+        result::ResultUnsupportedOperationInReadOnlyFileSystemTemplateA::make_err()
+    }
+
+    fn rename_file(&mut self, _old_path: PathBuf, _new_path: PathBuf) -> Result<()> {
+        result::ResultUnsupportedOperationInReadOnlyFileSystemTemplateA::make_err()
+    }
+
+    fn rename_directory(&mut self, _old_path: PathBuf, _new_path: PathBuf) -> Result<()> {
+        result::ResultUnsupportedOperationInReadOnlyFileSystemTemplateA::make_err()
+    }
+
+    fn get_entry_type(&mut self, path: PathBuf) -> Result<DirectoryEntryType> {
+        let (_, entry_type) = self.find_layer(&path)?;
+        Ok(entry_type)
+    }
+
+    fn open_file(&mut self, path: PathBuf, open_mode: FileOpenMode) -> Result<Shared<dyn File>> {
+        result_return_unless!(open_mode == FileOpenMode::Read(), result::ResultInvalidOpenMode);
+
+        let (layer, entry_type) = self.find_layer(&path)?;
+        result_return_unless!(entry_type == DirectoryEntryType::File, result::ResultPathNotFound);
+
+        let base_file = layer.get().open_file(path, open_mode)?;
+        Ok(Shared::new(ReadOnlyFile::new(base_file)))
+    }
+
+    fn open_directory(&mut self, path: PathBuf, open_mode: DirectoryOpenMode) -> Result<Shared<dyn Directory>> {
+        let mut entries: Vec<DirectoryEntry> = Vec::new();
+        let mut found_dir = false;
+        for layer in self.layers.iter() {
+            // A file on an upper layer hides any directories below, and vice versa
+            match layer.get().get_entry_type(path.clone()) {
+                Ok(DirectoryEntryType::Directory) => {},
+                Ok(DirectoryEntryType::File) => break,
+                Err(_) => continue
+            };
+            found_dir = true;
+
+            let dir = layer.get().open_directory(path.clone(), open_mode)?;
+            let entry_count = dir.get().get_entry_count()?;
+            for entry in dir.get().read(entry_count)? {
+                if !entries.iter().any(|existing_entry| existing_entry.path == entry.path) {
+                    entries.push(entry);
+                }
+            }
+        }
+        result_return_unless!(found_dir, result::ResultPathNotFound);
+
+        Ok(Shared::new(LayeredDirectory::new(entries)))
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn get_free_space_size(&mut self, _path: PathBuf) -> Result<usize> {
+        result::ResultUnsupportedOperationInReadOnlyFileSystemTemplateB::make_err()
+    }
+
+    fn get_total_space_size(&mut self, _path: PathBuf) -> Result<usize> {
+        result::ResultUnsupportedOperationInReadOnlyFileSystemTemplateB::make_err()
+    }
+
+    fn clean_directory_recursively(&mut self, _path: PathBuf) -> Result<()> {
+        result::ResultUnsupportedOperationInReadOnlyFileSystemTemplateA::make_err()
+    }
+
+    fn get_file_time_stamp_raw(&mut self, path: PathBuf) -> Result<TimeStampRaw> {
+        let (layer, _) = self.find_layer(&path)?;
+        let time_stamp = layer.get().get_file_time_stamp_raw(path)?;
+        Ok(time_stamp)
+    }
+}
+
+// ---
+
+// Content overrides
+
+// Like LayeredFS on actual consoles: files under <mods_path>/<program-id>/romfs (program ID as 16 hex digits) replace/extend the ones in the program's RomFS
+
+pub const PROGRAM_NCA_ROMFS_INDEX: usize = 1;
+
+pub fn get_romfs_override_path(program_id: ProgramId) -> PathBuf {
+    PathBuf::from(cfg::get_config().mods_path.clone()).join(format!("{:016X}", program_id.0)).join("romfs")
+}
+
+pub fn open_program_romfs(storage_id: ncm::StorageId, program_id: ProgramId) -> Result<Shared<dyn FileSystem>> {
+    let romfs = cache::open_nca_filesystem(storage_id, program_id, cntx::nca::ContentType::Program, PROGRAM_NCA_ROMFS_INDEX, cache::NcaFileSystemKind::RomFs)?;
+    Ok(apply_romfs_overrides(program_id, romfs))
+}
+
+pub fn apply_romfs_overrides(program_id: ProgramId, romfs: Shared<dyn FileSystem>) -> Shared<dyn FileSystem> {
+    let override_path = get_romfs_override_path(program_id);
+    if !override_path.is_dir() {
+        return romfs;
+    }
+
+    log_line!("Applying RomFS overrides for program {} from '{}'", program_id, override_path.display());
+    let host_override_fs: Shared<dyn FileSystem> = HostFileSystem::new(override_path.display().to_string());
+    // Note: overrides must stay read-only like the RomFS they replace, otherwise guests could modify the mods directory
+    let override_fs: Shared<dyn FileSystem> = ReadOnlyFileSystem::new(host_override_fs);
+    LayeredFileSystem::new(vec![override_fs, romfs])
+}
+
+// ---
+
+// SD card
+
+// Note: guests create big files as concatenation files (see above), which get laid out like on actual SD cards
+pub fn open_sd_card_filesystem() -> Shared<dyn FileSystem> {
+    let host_fs: Shared<dyn FileSystem> = HostFileSystem::new(cfg::get_config().sd_card_path.clone());
+    ConcatenationFileSystem::new(host_fs)
+}
+
+// ---
@@ -0,0 +1,113 @@
+use std::sync::Arc;
+use std::sync::mpsc::{self, Sender, Receiver};
+use std::thread::Builder;
+use parking_lot::Mutex;
+use crate::emu::cfg;
+use crate::kern::event::KReadableEvent;
+use crate::util::Shared;
+use crate::result::*;
+use super::*;
+
+// Asynchronous file reads: they are performed by host worker threads, and their completion is signaled through a kernel event
+// This way the fs IPC dispatcher isn't blocked on big reads: it defers their requests instead, handling them again once the event is signaled
+
+type AsyncJob = Box<dyn FnOnce() + Send>;
+
+struct AsyncWorkerPool {
+    job_sender: Sender<AsyncJob>
+}
+
+impl AsyncWorkerPool {
+    fn new(worker_count: usize) -> Result<Self> {
+        let (job_sender, job_receiver) = mpsc::channel::<AsyncJob>();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+        for i in 0..worker_count.max(1) {
+            let job_receiver = job_receiver.clone();
+            convert_io_result(Builder::new().name(format!("pg.fs.AsyncWorker{}", i)).spawn(move || worker_thread_fn(job_receiver)))?;
+        }
+
+        Ok(Self {
+            job_sender: job_sender
+        })
+    }
+}
+
+fn worker_thread_fn(job_receiver: Arc<Mutex<Receiver<AsyncJob>>>) {
+    loop {
+        // Note: the receiver lock is released before running the job, so that other workers can pick up jobs meanwhile
+        let job = job_receiver.lock().recv();
+        match job {
+            Ok(job) => job(),
+            // The pool was dropped
+            Err(_) => break
+        }
+    }
+}
+
+static mut G_WORKER_POOL: Mutex<Option<AsyncWorkerPool>> = parking_lot::const_mutex(None);
+
+fn submit_job(job: AsyncJob) -> Result<()> {
+    unsafe {
+        let mut worker_pool = G_WORKER_POOL.lock();
+        if worker_pool.is_none() {
+            *worker_pool = Some(AsyncWorkerPool::new(cfg::get_config().fs_async_worker_count)?);
+        }
+
+        // Note: sending only fails if all workers are gone, which doesn't happen while the pool is alive
+        let _ = worker_pool.as_ref().unwrap().job_sender.send(job);
+        Ok(())
+    }
+}
+
+pub struct AsyncReadOperation {
+    result: Mutex<Option<Result<Vec<u8>>>>
+}
+
+impl AsyncReadOperation {
+    pub fn is_completed(&self) -> bool {
+        self.result.lock().is_some()
+    }
+
+    // Returns the read data (already truncated to the actual read size) once the operation is completed
+    pub fn take_result(&self) -> Option<Result<Vec<u8>>> {
+        self.result.lock().take()
+    }
+}
+
+// Note: files are only accessed through their mutexes and events only inside the critical section (see KReadableEvent::signal), thus it's fine to move them to the worker threads
+struct AsyncReadJob {
+    file: Shared<dyn File>,
+    completion_event: Shared<KReadableEvent>,
+    operation: Arc<AsyncReadOperation>
+}
+
+unsafe impl Send for AsyncReadJob {}
+
+// The completion event is signaled once the operation completes, and may be shared by several operations (thus it's never cleared here)
+pub fn read_async(file: Shared<dyn File>, offset: u64, size: usize, option: ReadOption, completion_event: Shared<KReadableEvent>) -> Result<Arc<AsyncReadOperation>> {
+    let operation = Arc::new(AsyncReadOperation {
+        result: Mutex::new(None)
+    });
+
+    let job = AsyncReadJob {
+        file: file,
+        completion_event: completion_event,
+        operation: operation.clone()
+    };
+    submit_job(Box::new(move || {
+        let mut data: Vec<u8> = vec![0; size];
+
+        // Note: the file might be being accessed by the dispatcher at the same time, thus we wait for it instead of using Shared::get (which would panic)
+        let read_result = job.file.get_blocking().read(offset, &mut data, option);
+        let result = read_result.map(|read_size| {
+            data.truncate(read_size);
+            data
+        });
+
+        *job.operation.result.lock() = Some(result);
+        KReadableEvent::signal(&job.completion_event);
+    }))?;
+
+    Ok(operation)
+}
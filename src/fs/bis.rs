@@ -0,0 +1,276 @@
+use std::path::PathBuf;
+use std::fs::File as StdFile;
+use std::io::{Read, Seek, SeekFrom};
+use crate::emu::cfg;
+use crate::util::{self, Shared, convert_io_result};
+use crate::result::*;
+use super::*;
+
+// BIS (built-in storage) partitions, which are either taken from a raw NAND dump or from the host directories set in the config (the directory-expanded layout)
+// Note: the FAT partitions inside raw dumps are encrypted with the console's BIS keys, which aren't supported yet, thus raw dumps can only be opened as storages
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[repr(u32)]
+pub enum BisPartitionId {
+    BootPartition1Root = 0,
+
+    BootPartition2Root = 10,
+
+    UserDataRoot = 20,
+    BootConfigAndPackage2Part1 = 21,
+    BootConfigAndPackage2Part2 = 22,
+    BootConfigAndPackage2Part3 = 23,
+    BootConfigAndPackage2Part4 = 24,
+    BootConfigAndPackage2Part5 = 25,
+    BootConfigAndPackage2Part6 = 26,
+    CalibrationBinary = 27,
+    CalibrationFile = 28,
+    SafeMode = 29,
+    User = 30,
+    System = 31,
+    SystemProperEncryption = 32,
+    SystemProperPartition = 33,
+    SignedSystemPartitionOnSafeMode = 34
+}
+
+impl BisPartitionId {
+    pub const fn from(raw: u32) -> Option<Self> {
+        match raw {
+            0 => Some(Self::BootPartition1Root),
+            10 => Some(Self::BootPartition2Root),
+            20 => Some(Self::UserDataRoot),
+            21 => Some(Self::BootConfigAndPackage2Part1),
+            22 => Some(Self::BootConfigAndPackage2Part2),
+            23 => Some(Self::BootConfigAndPackage2Part3),
+            24 => Some(Self::BootConfigAndPackage2Part4),
+            25 => Some(Self::BootConfigAndPackage2Part5),
+            26 => Some(Self::BootConfigAndPackage2Part6),
+            27 => Some(Self::CalibrationBinary),
+            28 => Some(Self::CalibrationFile),
+            29 => Some(Self::SafeMode),
+            30 => Some(Self::User),
+            31 => Some(Self::System),
+            32 => Some(Self::SystemProperEncryption),
+            33 => Some(Self::SystemProperPartition),
+            34 => Some(Self::SignedSystemPartitionOnSafeMode),
+            _ => None
+        }
+    }
+
+    // Names of the partitions in the GPT of the NAND's user data area
+    pub fn get_gpt_name(&self) -> Option<&'static str> {
+        match self {
+            Self::BootConfigAndPackage2Part1 => Some("BCPKG2-1-Normal-Main"),
+            Self::BootConfigAndPackage2Part2 => Some("BCPKG2-2-Normal-Sub"),
+            Self::BootConfigAndPackage2Part3 => Some("BCPKG2-3-SafeMode-Main"),
+            Self::BootConfigAndPackage2Part4 => Some("BCPKG2-4-SafeMode-Sub"),
+            Self::BootConfigAndPackage2Part5 => Some("BCPKG2-5-Repair-Main"),
+            Self::BootConfigAndPackage2Part6 => Some("BCPKG2-6-Repair-Sub"),
+            Self::CalibrationBinary => Some("PRODINFO"),
+            Self::CalibrationFile => Some("PRODINFOF"),
+            Self::SafeMode => Some("SAFE"),
+            Self::User => Some("USER"),
+            Self::System => Some("SYSTEM"),
+            _ => None
+        }
+    }
+}
+
+// GPT
+
+pub const SECTOR_SIZE: u64 = 0x200;
+
+// Note: the header comes from the (untrusted) dump, thus the entry table it describes is bounded before being read
+// Actual GPTs have 128 entries of 0x80 bytes, these limits just leave some room for unusual (yet sane) layouts
+pub const MAX_PARTITION_ENTRY_COUNT: u32 = 0x400;
+pub const MAX_PARTITION_ENTRY_SIZE: u32 = 0x1000;
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct GptHeader {
+    pub magic: [u8; 8],
+    pub revision: u32,
+    pub header_size: u32,
+    pub header_crc32: u32,
+    pub reserved: u32,
+    pub current_lba: u64,
+    pub backup_lba: u64,
+    pub first_usable_lba: u64,
+    pub last_usable_lba: u64,
+    pub disk_guid: [u8; 0x10],
+    pub partition_entries_lba: u64,
+    pub partition_entry_count: u32,
+    pub partition_entry_size: u32,
+    pub partition_entries_crc32: u32
+}
+
+impl GptHeader {
+    pub const MAGIC: [u8; 8] = *b"EFI PART";
+}
+
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct GptPartitionEntry {
+    pub type_guid: [u8; 0x10],
+    pub unique_guid: [u8; 0x10],
+    pub first_lba: u64,
+    pub last_lba: u64,
+    pub attributes: u64,
+    pub name: [u16; 36]
+}
+
+impl GptPartitionEntry {
+    pub fn get_name(&self) -> String {
+        let name_len = self.name.iter().position(|&ch| ch == 0).unwrap_or(self.name.len());
+        String::from_utf16_lossy(&self.name[..name_len])
+    }
+}
+
+fn read_host_file_at(file: &mut StdFile, offset: u64, data: &mut [u8]) -> Result<()> {
+    convert_io_result(file.seek(SeekFrom::Start(offset)))?;
+    convert_io_result(file.read_exact(data))
+}
+
+// Returns the (offset, size) of every partition in the dump, by name
+pub fn read_gpt_partitions(nand_file: &mut StdFile) -> Result<Vec<(String, u64, u64)>> {
+    // The GPT header is at LBA 1
+    let mut header_data: Vec<u8> = vec![0; std::mem::size_of::<GptHeader>()];
+    read_host_file_at(nand_file, SECTOR_SIZE, &mut header_data)?;
+    let header: GptHeader = util::slice_read_val(&header_data, None)?;
+    result_return_unless!(header.magic == GptHeader::MAGIC, result::ResultGptHeaderVerificationFailed);
+    result_return_unless!(header.partition_entry_size as usize >= std::mem::size_of::<GptPartitionEntry>(), result::ResultGptHeaderVerificationFailed);
+    result_return_unless!(header.partition_entry_size <= MAX_PARTITION_ENTRY_SIZE, result::ResultGptHeaderVerificationFailed);
+    result_return_unless!(header.partition_entry_count <= MAX_PARTITION_ENTRY_COUNT, result::ResultGptHeaderVerificationFailed);
+
+    let entry_size = header.partition_entry_size as usize;
+    let entries_size = entry_size.checked_mul(header.partition_entry_count as usize).ok_or(result::ResultGptHeaderVerificationFailed::make())?;
+    let entries_offset = header.partition_entries_lba.checked_mul(SECTOR_SIZE).ok_or(result::ResultGptHeaderVerificationFailed::make())?;
+    let mut entries_data: Vec<u8> = vec![0; entries_size];
+    read_host_file_at(nand_file, entries_offset, &mut entries_data)?;
+
+    let mut partitions: Vec<(String, u64, u64)> = Vec::new();
+    for i in 0..header.partition_entry_count as usize {
+        let entry: GptPartitionEntry = util::slice_read_val(&entries_data, Some(i * entry_size))?;
+        // Unused entries have no type
+        if entry.type_guid == [0; 0x10] {
+            continue;
+        }
+        result_return_unless!(entry.last_lba >= entry.first_lba, result::ResultGptHeaderVerificationFailed);
+
+        let offset = entry.first_lba.checked_mul(SECTOR_SIZE).ok_or(result::ResultGptHeaderVerificationFailed::make())?;
+        let size = (entry.last_lba - entry.first_lba).checked_add(1).and_then(|sector_count| sector_count.checked_mul(SECTOR_SIZE)).ok_or(result::ResultGptHeaderVerificationFailed::make())?;
+        partitions.push((entry.get_name(), offset, size));
+    }
+
+    Ok(partitions)
+}
+
+// ---
+
+// Storage
+
+// Read-only view of a range of a host file
+pub struct HostSubStorage {
+    inner_file: StdFile,
+    offset: u64,
+    size: u64
+}
+
+impl HostSubStorage {
+    pub fn new(inner_file: StdFile, offset: u64, size: u64) -> Self {
+        Self {
+            inner_file: inner_file,
+            offset: offset,
+            size: size
+        }
+    }
+}
+
+impl Storage for HostSubStorage {
+    fn read(&mut self, offset: u64, data: &mut [u8]) -> Result<()> {
+        result_return_unless!(offset.checked_add(data.len() as u64).map_or(false, |end| end <= self.size), result::ResultOutOfRange);
+
+        read_host_file_at(&mut self.inner_file, self.offset + offset, data)
+    }
+
+    fn write(&mut self, _offset: u64, _data: &[u8]) -> Result<()> {
+        // Note: dumps are never modified
+        result::ResultUnsupportedOperationInSubStorageA::make_err()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_size(&mut self, _size: usize) -> Result<()> {
+        result::ResultUnsupportedOperationInSubStorageB::make_err()
+    }
+
+    fn get_size(&mut self) -> Result<usize> {
+        Ok(self.size as usize)
+    }
+}
+
+// ---
+
+fn get_nand_dump_path() -> Option<String> {
+    cfg::get_config().nand_dump_path.clone()
+}
+
+fn open_nand_dump() -> Result<StdFile> {
+    match get_nand_dump_path() {
+        Some(nand_dump_path) => convert_io_result(StdFile::open(nand_dump_path)),
+        None => result::ResultPartitionNotFound::make_err()
+    }
+}
+
+pub fn open_bis_storage(partition_id: BisPartitionId) -> Result<Shared<dyn Storage>> {
+    let mut nand_file = open_nand_dump()?;
+
+    let storage = match partition_id {
+        // The whole user data area
+        BisPartitionId::UserDataRoot => {
+            let nand_size = convert_io_result(nand_file.seek(SeekFrom::End(0)))?;
+            HostSubStorage::new(nand_file, 0, nand_size)
+        },
+        partition_id => {
+            let gpt_name = match partition_id.get_gpt_name() {
+                Some(gpt_name) => gpt_name,
+                // TODO: boot partitions (they are dumped separately from the user data area)
+                None => return result::ResultPartitionNotFound::make_err()
+            };
+
+            let partitions = read_gpt_partitions(&mut nand_file)?;
+            match partitions.into_iter().find(|(name, _, _)| name == gpt_name) {
+                Some((_, offset, size)) => HostSubStorage::new(nand_file, offset, size),
+                None => return result::ResultPartitionNotFound::make_err()
+            }
+        }
+    };
+
+    Ok(Shared::new(storage))
+}
+
+fn get_bis_directory_path(partition_id: BisPartitionId) -> Result<String> {
+    let config = cfg::get_config();
+    match partition_id {
+        BisPartitionId::System => Ok(config.nand_system_path.clone()),
+        BisPartitionId::User => Ok(config.nand_user_path.clone()),
+        BisPartitionId::SafeMode => Ok(config.nand_safe_path.clone()),
+        BisPartitionId::CalibrationFile => Ok(config.nand_calibration_path.clone()),
+        _ => result::ResultPartitionNotFound::make_err()
+    }
+}
+
+pub fn open_bis_filesystem(partition_id: BisPartitionId, root_path: PathBuf) -> Result<Shared<dyn FileSystem>> {
+    let base_fs: Shared<dyn FileSystem> = HostFileSystem::new(get_bis_directory_path(partition_id)?);
+
+    // Note: like the actual fs service, the filesystem may be opened at a subdirectory of the partition
+    let root_path = path::normalize(&root_path)?;
+    if root_path.as_os_str().is_empty() {
+        Ok(base_fs)
+    }
+    else {
+        Ok(SubdirectoryFileSystem::new(base_fs, root_path)?)
+    }
+}
@@ -0,0 +1,412 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use parking_lot::Mutex;
+use cntx::nca::ContentType as CntxContentType;
+use crate::emu::cfg;
+use crate::emu::host_profiler;
+use crate::ncm::{self, ProgramId, StorageId};
+use crate::util::Shared;
+use crate::result::*;
+use super::*;
+
+// Opening NCA filesystems means parsing/decrypting headers and setting up section readers, and reading their files means decrypting the same blocks over and over
+// Thus, opened NCA filesystems are kept around (LRU), and reads through them go through a (LRU) cache of decrypted file blocks
+
+pub const BLOCK_SIZE: usize = 0x4000;
+
+// LRU cache: entries are looked up by key, while their last uses are kept ordered so that the least recently used one is found right away
+pub struct LruCache<K: Clone + Eq + Hash, V: Clone> {
+    // Note: lazily created, since HashMap::new isn't const (the caches are statics)
+    entries: Option<HashMap<K, (u64, V)>>,
+    // Last use -> key, the least recently used entry being the first one
+    uses: BTreeMap<u64, K>,
+    next_use: u64
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> LruCache<K, V> {
+    pub const fn new() -> Self {
+        Self {
+            entries: None,
+            uses: BTreeMap::new(),
+            next_use: 0
+        }
+    }
+
+    fn get_entries(&mut self) -> &mut HashMap<K, (u64, V)> {
+        self.entries.get_or_insert_with(HashMap::new)
+    }
+
+    fn allocate_use(&mut self) -> u64 {
+        let cur_use = self.next_use;
+        self.next_use += 1;
+        cur_use
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        let new_use = self.allocate_use();
+        let (last_use, value) = self.get_entries().get_mut(key)?;
+        let old_use = std::mem::replace(last_use, new_use);
+        let value = value.clone();
+
+        self.uses.remove(&old_use);
+        self.uses.insert(new_use, key.clone());
+        Some(value)
+    }
+
+    pub fn insert(&mut self, key: K, value: V, capacity: usize) {
+        if let Some((old_use, _)) = self.get_entries().remove(&key) {
+            self.uses.remove(&old_use);
+        }
+        if capacity == 0 {
+            return;
+        }
+
+        while self.len() >= capacity {
+            let lru_use = *self.uses.keys().next().unwrap();
+            let lru_key = self.uses.remove(&lru_use).unwrap();
+            self.get_entries().remove(&lru_key);
+        }
+
+        let new_use = self.allocate_use();
+        self.uses.insert(new_use, key.clone());
+        self.get_entries().insert(key, (new_use, value));
+    }
+
+    pub fn retain<F: FnMut(&K) -> bool>(&mut self, mut f: F) {
+        let entries = self.entries.get_or_insert_with(HashMap::new);
+        self.uses.retain(|_, key| {
+            let keep = f(key);
+            if !keep {
+                entries.remove(key);
+            }
+            keep
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.entries = None;
+        self.uses.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.uses.len()
+    }
+}
+
+// Note: files are identified by an ID assigned by their filesystem (see CachingFileSystem::get_file_id), which is way cheaper to hash/compare than their paths
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+struct BlockKey {
+    fs_id: u64,
+    file_id: u64,
+    block_idx: u64
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum NcaFileSystemKind {
+    Partition,
+    RomFs
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+struct NcaFileSystemKey {
+    storage_id: StorageId,
+    program_id: ProgramId,
+    cnt_type: CntxContentType,
+    fs_idx: usize,
+    kind: NcaFileSystemKind
+}
+
+// Note: the content type is left out of the hash, since cntx doesn't implement Hash for it (keys only differing in it just share a bucket)
+impl Hash for NcaFileSystemKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.storage_id.hash(state);
+        self.program_id.hash(state);
+        self.fs_idx.hash(state);
+        self.kind.hash(state);
+    }
+}
+
+static mut G_NEXT_FS_ID: AtomicU64 = AtomicU64::new(1);
+static mut G_FILESYSTEM_CACHE: Mutex<LruCache<NcaFileSystemKey, Shared<CachingFileSystem>>> = parking_lot::const_mutex(LruCache::new());
+static mut G_BLOCK_CACHE: Mutex<LruCache<BlockKey, Arc<[u8]>>> = parking_lot::const_mutex(LruCache::new());
+
+fn allocate_fs_id() -> u64 {
+    unsafe {
+        G_NEXT_FS_ID.fetch_add(1, Ordering::SeqCst)
+    }
+}
+
+fn find_cached_block(key: &BlockKey) -> Option<Arc<[u8]>> {
+    unsafe {
+        G_BLOCK_CACHE.lock().get(key)
+    }
+}
+
+fn cache_block(key: BlockKey, block: Arc<[u8]>) {
+    unsafe {
+        G_BLOCK_CACHE.lock().insert(key, block, cfg::get_config().fs_cache_block_count);
+    }
+}
+
+fn purge_blocks(fs_id: u64) {
+    unsafe {
+        G_BLOCK_CACHE.lock().retain(|key| key.fs_id != fs_id);
+    }
+}
+
+pub fn clear_caches() {
+    unsafe {
+        G_FILESYSTEM_CACHE.lock().clear();
+        G_BLOCK_CACHE.lock().clear();
+    }
+}
+
+// (cached filesystems, cached blocks)
+pub fn get_cache_stats() -> (usize, usize) {
+    unsafe {
+        (G_FILESYSTEM_CACHE.lock().len(), G_BLOCK_CACHE.lock().len())
+    }
+}
+
+// Read-only file whose reads go through the block cache
+pub struct CachedFile {
+    base_file: Shared<dyn File>,
+    fs_id: u64,
+    file_id: u64
+}
+
+unsafe impl Send for CachedFile {}
+unsafe impl Sync for CachedFile {}
+
+impl CachedFile {
+    pub fn new(base_file: Shared<dyn File>, fs_id: u64, file_id: u64) -> Self {
+        Self {
+            base_file: base_file,
+            fs_id: fs_id,
+            file_id: file_id
+        }
+    }
+
+    fn read_block(&mut self, block_idx: u64) -> Result<Arc<[u8]>> {
+        let key = BlockKey {
+            fs_id: self.fs_id,
+            file_id: self.file_id,
+            block_idx: block_idx
+        };
+        if let Some(block) = find_cached_block(&key) {
+            return Ok(block);
+        }
+
+        let _span = host_profiler::enter("fs_read_block");
+        let mut block: Vec<u8> = vec![0; BLOCK_SIZE];
+        let read_size = self.base_file.get().read(block_idx * BLOCK_SIZE as u64, &mut block, ReadOption::None)?;
+        block.truncate(read_size);
+        let block: Arc<[u8]> = Arc::from(block);
+        cache_block(key, block.clone());
+        Ok(block)
+    }
+}
+
+impl File for CachedFile {
+    fn read(&mut self, offset: u64, data: &mut [u8], _option: ReadOption) -> Result<usize> {
+        let mut read_size: usize = 0;
+        while read_size < data.len() {
+            let cur_offset = offset + read_size as u64;
+            let block = self.read_block(cur_offset / BLOCK_SIZE as u64)?;
+            let block_offset = (cur_offset % BLOCK_SIZE as u64) as usize;
+            if block_offset >= block.len() {
+                break;
+            }
+
+            let copy_size = (data.len() - read_size).min(block.len() - block_offset);
+            data[read_size..read_size + copy_size].copy_from_slice(&block[block_offset..block_offset + copy_size]);
+            read_size += copy_size;
+        }
+
+        Ok(read_size)
+    }
+
+    fn write(&mut self, _offset: u64, _data: &[u8], _option: WriteOption) -> Result<usize> {
+        result::ResultUnsupportedOperationInReadOnlyFileA::make_err()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_size(&mut self, _size: usize) -> Result<()> {
+        result::ResultUnsupportedOperationInReadOnlyFileA::make_err()
+    }
+
+    fn get_size(&mut self) -> Result<usize> {
+        self.base_file.get().get_size()
+    }
+
+    fn operate_range(&mut self, op_id: OperationId, offset: u64, size: usize) -> Result<RangeInfo> {
+        self.base_file.get().operate_range(op_id, offset, size)
+    }
+}
+
+// Read-only (NCA filesystems are) wrapper making file reads go through the block cache
+pub struct CachingFileSystem {
+    id: u64,
+    base_fs: Shared<dyn FileSystem>,
+    // Normalized path -> file ID (see BlockKey)
+    file_ids: HashMap<PathBuf, u64>
+}
+
+unsafe impl Send for CachingFileSystem {}
+unsafe impl Sync for CachingFileSystem {}
+
+impl CachingFileSystem {
+    pub fn new(base_fs: Shared<dyn FileSystem>) -> Shared<Self> {
+        Shared::new(Self {
+            id: allocate_fs_id(),
+            base_fs: base_fs,
+            file_ids: HashMap::new()
+        })
+    }
+
+    // Note: paths are normalized so that different spellings of the same file share their blocks
+    fn get_file_id(&mut self, path: &Path) -> Result<u64> {
+        let normalized_path = path::normalize(path)?;
+        let next_file_id = self.file_ids.len() as u64;
+        Ok(*self.file_ids.entry(normalized_path).or_insert(next_file_id))
+    }
+}
+
+impl Drop for CachingFileSystem {
+    fn drop(&mut self) {
+        purge_blocks(self.id);
+    }
+}
+
+impl FileSystem for CachingFileSystem {
+    fn create_file(&mut self, path: PathBuf, size: usize, create_option: CreateOption) -> Result<()> {
+        self.base_fs.get().create_file(path, size, create_option)
+    }
+
+    fn delete_file(&mut self, path: PathBuf) -> Result<()> {
+        self.base_fs.get().delete_file(path)
+    }
+
+    fn create_directory(&mut self, path: PathBuf) -> Result<()> {
+        self.base_fs.get().create_directory(path)
+    }
+
+    fn delete_directory(&mut self, path: PathBuf) -> Result<()> {
+        self.base_fs.get().delete_directory(path)
+    }
+
+    fn delete_directory_recursively(&mut self, path: PathBuf) -> Result<()> {
+        self.base_fs.get().delete_directory_recursively(path)
+    }
+
+    fn rename_file(&mut self, old_path: PathBuf, new_path: PathBuf) -> Result<()> {
+        self.base_fs.get().rename_file(old_path, new_path)
+    }
+
+    fn rename_directory(&mut self, old_path: PathBuf, new_path: PathBuf) -> Result<()> {
+        self.base_fs.get().rename_directory(old_path, new_path)
+    }
+
+    fn get_entry_type(&mut self, path: PathBuf) -> Result<DirectoryEntryType> {
+        self.base_fs.get().get_entry_type(path)
+    }
+
+    fn open_file(&mut self, path: PathBuf, open_mode: FileOpenMode) -> Result<Shared<dyn File>> {
+        let base_file = self.base_fs.get().open_file(path.clone(), open_mode)?;
+        let file_id = self.get_file_id(&path)?;
+        Ok(Shared::new(CachedFile::new(base_file, self.id, file_id)))
+    }
+
+    fn open_directory(&mut self, path: PathBuf, open_mode: DirectoryOpenMode) -> Result<Shared<dyn Directory>> {
+        self.base_fs.get().open_directory(path, open_mode)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        self.base_fs.get().commit()
+    }
+
+    fn get_free_space_size(&mut self, path: PathBuf) -> Result<usize> {
+        self.base_fs.get().get_free_space_size(path)
+    }
+
+    fn get_total_space_size(&mut self, path: PathBuf) -> Result<usize> {
+        self.base_fs.get().get_total_space_size(path)
+    }
+
+    fn clean_directory_recursively(&mut self, path: PathBuf) -> Result<()> {
+        self.base_fs.get().clean_directory_recursively(path)
+    }
+
+    fn get_file_time_stamp_raw(&mut self, path: PathBuf) -> Result<TimeStampRaw> {
+        self.base_fs.get().get_file_time_stamp_raw(path)
+    }
+}
+
+// Opens a filesystem of a content NCA, reusing an already opened one if possible
+pub fn open_nca_filesystem(storage_id: StorageId, program_id: ProgramId, cnt_type: CntxContentType, fs_idx: usize, kind: NcaFileSystemKind) -> Result<Shared<dyn FileSystem>> {
+    let key = NcaFileSystemKey {
+        storage_id: storage_id,
+        program_id: program_id,
+        cnt_type: cnt_type,
+        fs_idx: fs_idx,
+        kind: kind
+    };
+
+    let cached_fs = unsafe {
+        G_FILESYSTEM_CACHE.lock().get(&key)
+    };
+    if let Some(fs) = cached_fs {
+        return Ok(fs);
+    }
+
+    let mut cnt = ncm::lookup_content(storage_id, program_id, cnt_type)?;
+    let base_fs = match kind {
+        NcaFileSystemKind::Partition => cnt.open_partition_filesystem(fs_idx)?,
+        NcaFileSystemKind::RomFs => cnt.open_romfs_filesystem(fs_idx)?
+    };
+
+    // Virtual contents are host directories, which are not worth caching (and might change while running)
+    if let ncm::Content::Virtual(_) = cnt {
+        return Ok(base_fs);
+    }
+
+    let fs = CachingFileSystem::new(base_fs);
+
+    unsafe {
+        G_FILESYSTEM_CACHE.lock().insert(key, fs.clone(), cfg::get_config().fs_cache_filesystem_count);
+    }
+    Ok(fs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lru_eviction() {
+        let mut cache: LruCache<u32, u32> = LruCache::new();
+        cache.insert(1, 10, 2);
+        cache.insert(2, 20, 2);
+
+        // Using an entry makes the other one the least recently used
+        assert_eq!(cache.get(&1), Some(10));
+        cache.insert(3, 30, 2);
+        assert_eq!((cache.get(&1), cache.get(&2), cache.get(&3)), (Some(10), None, Some(30)));
+
+        // Reinserting replaces the entry instead of adding another one
+        cache.insert(1, 11, 2);
+        assert_eq!((cache.len(), cache.get(&1)), (2, Some(11)));
+
+        cache.retain(|key| *key != 1);
+        assert_eq!((cache.len(), cache.get(&1), cache.get(&3)), (1, None, Some(30)));
+
+        cache.insert(4, 40, 0);
+        assert_eq!((cache.len(), cache.get(&4)), (1, None));
+    }
+}
@@ -0,0 +1,106 @@
+use std::path::{Path, PathBuf};
+use crate::result::*;
+use super::result;
+
+// Guest paths come straight from (untrusted) guest code, thus they must be normalized before being used with any host path
+
+pub const MAX_PATH_LEN: usize = 0x301;
+
+pub const SEPARATOR: char = '/';
+
+// Characters which are either invalid on console paths or have special meanings on some host (mostly Windows) filesystems
+const INVALID_CHARACTERS: &[char] = &['\\', ':', '*', '?', '<', '>', '|', '"'];
+
+// Returns the path relative to the mount root, with "." and empty components removed and ".." ones resolved
+// Resolving ".." past the mount root is an error, so the result can always be safely joined onto the root
+pub fn normalize(path: &Path) -> Result<PathBuf> {
+    let path_str = match path.to_str() {
+        Some(path_str) => path_str,
+        None => return result::ResultInvalidCharacter::make_err()
+    };
+    result_return_unless!(path_str.len() < MAX_PATH_LEN, result::ResultTooLongPath);
+    result_return_if!(path_str.chars().any(|ch| INVALID_CHARACTERS.contains(&ch) || ch.is_control()), result::ResultInvalidCharacter);
+
+    let mut components: Vec<&str> = Vec::new();
+    for component in path_str.split(SEPARATOR) {
+        match component {
+            "" | "." => {},
+            ".." => {
+                result_return_unless!(components.pop().is_some(), result::ResultDirectoryUnobtainable);
+            },
+            _ => {
+                // Windows silently drops trailing dots/spaces, which would make different guest paths point to the same host file
+                result_return_if!(component.ends_with('.') || component.ends_with(' '), result::ResultInvalidPathFormat);
+                components.push(component);
+            }
+        }
+    }
+
+    Ok(components.iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expect_normalized(path: &str, expected_path: &str) {
+        assert_eq!(normalize(Path::new(path)), Ok(PathBuf::from(expected_path)), "unexpected normalization of '{}'", path);
+    }
+
+    fn expect_error(path: &str, matches: fn(ResultCode) -> bool) {
+        match normalize(Path::new(path)) {
+            Err(rc) if matches(rc) => {},
+            r => panic!("unexpected normalization of '{}': {:?}", path, r)
+        }
+    }
+
+    #[test]
+    fn dot_and_empty_components() {
+        expect_normalized("", "");
+        expect_normalized("/", "");
+        expect_normalized("/a/b", "a/b");
+        expect_normalized("a//b/", "a/b");
+        expect_normalized("./a/./b/.", "a/b");
+        expect_normalized("/a/../b", "b");
+        expect_normalized("a/b/../../c", "c");
+    }
+
+    #[test]
+    fn parent_components_escaping_root() {
+        expect_error("..", result::ResultDirectoryUnobtainable::matches);
+        expect_error("/../a", result::ResultDirectoryUnobtainable::matches);
+        expect_error("a/../../b", result::ResultDirectoryUnobtainable::matches);
+        expect_error("a/./.././../b", result::ResultDirectoryUnobtainable::matches);
+    }
+
+    #[test]
+    fn backslashes_and_drive_prefixes() {
+        expect_error("a\\..\\..\\b", result::ResultInvalidCharacter::matches);
+        expect_error("\\a", result::ResultInvalidCharacter::matches);
+        expect_error("C:/a", result::ResultInvalidCharacter::matches);
+        expect_error("/C:", result::ResultInvalidCharacter::matches);
+    }
+
+    #[test]
+    fn invalid_characters() {
+        for path in ["a*", "a?b", "<a>", "a|b", "\"a\"", "a\nb", "a\0"].iter() {
+            expect_error(path, result::ResultInvalidCharacter::matches);
+        }
+    }
+
+    #[test]
+    fn trailing_dots_and_spaces() {
+        expect_error("a./b", result::ResultInvalidPathFormat::matches);
+        expect_error("a/b ", result::ResultInvalidPathFormat::matches);
+        expect_error("a/...", result::ResultInvalidPathFormat::matches);
+        expect_normalized("a.b/ c", "a.b/ c");
+    }
+
+    #[test]
+    fn overlong_paths() {
+        expect_normalized(&"a".repeat(MAX_PATH_LEN - 1), &"a".repeat(MAX_PATH_LEN - 1));
+        expect_error(&"a".repeat(MAX_PATH_LEN), result::ResultTooLongPath::matches);
+        // The length is checked before resolving components, thus paths which would get shorter aren't allowed either
+        expect_error(&"./".repeat(MAX_PATH_LEN), result::ResultTooLongPath::matches);
+    }
+}
@@ -149,53 +149,138 @@ macro_rules! ipc_control_interface_define_command {
 #[macro_export]
 macro_rules! ipc_cmif_interface_make_command_meta {
     ($name:ident: $id:expr) => {
-        paste::paste! {
-            $crate::ipc::sf::CommandMetadata::new($crate::ipc::CommandProtocol::Cmif, $id, unsafe { core::mem::transmute(Self::[<$name _cmif_impl>] as fn(&mut Self, &mut $crate::ipc::server::ServerContext) -> $crate::result::Result<()>) } /* , None, None  */ )
-        }
-    };
-    /*
-    ($name:ident: $id:expr, [($major:expr, $minor:expr, $micro:expr) =>]) => {
-        paste::paste! {
-            $crate::ipc::sf::CommandMetadata::new($crate::ipc::CommandProtocol::Cmif, $id, unsafe { core::mem::transmute(Self::[<$name _cmif_impl>] as fn(&mut Self, &mut $crate::ipc::server::ServerContext) -> $crate::result::Result<()>) }, Some($crate::version::Version::new($major, $minor, $micro)), None)
-        }
+        ipc_cmif_interface_make_command_meta!($name: $id, None, None)
     };
-    ($name:ident: $id:expr, [=> ($major:expr, $minor:expr, $micro:expr)]) => {
+    // Commands only available in a range of firmware versions (both Option<Version>, bounds included)
+    ($name:ident: $id:expr, $min_ver:expr, $max_ver:expr) => {
         paste::paste! {
-            $crate::ipc::sf::CommandMetadata::new($crate::ipc::CommandProtocol::Cmif, $id, unsafe { core::mem::transmute(Self::[<$name _cmif_impl>] as fn(&mut Self, &mut $crate::ipc::server::ServerContext) -> $crate::result::Result<()>) }, None, Some($crate::version::Version::new($major, $minor, $micro)))
+            $crate::ipc::sf::CommandMetadata::new($crate::ipc::CommandProtocol::Cmif, $id, unsafe { core::mem::transmute(Self::[<$name _cmif_impl>] as fn(&mut Self, &mut $crate::ipc::server::ServerContext) -> $crate::result::Result<()>) }, $min_ver, $max_ver)
         }
     };
-    ($name:ident: $id:expr, [($major_a:expr, $minor_a:expr, $micro_a:expr) => ($major_b:expr, $minor_b:expr, $micro_b:expr)]) => {
-        paste::paste! {
-            $crate::ipc::sf::CommandMetadata::new($crate::ipc::CommandProtocol::Cmif, $id, unsafe { core::mem::transmute(Self::[<$name _cmif_impl>] as fn(&mut Self, &mut $crate::ipc::server::ServerContext) -> $crate::result::Result<()>) }, Some($crate::version::Version::new($major_a, $minor_a, $micro_a)), Some($crate::version::Version::new($major_b, $minor_b, $micro_b)))
-        }
-    };
-    */
 }
 
 #[macro_export]
 macro_rules! ipc_tipc_interface_make_command_meta {
     ($name:ident: $id:expr) => {
-        paste::paste! {
-            $crate::ipc::sf::CommandMetadata::new($crate::ipc::CommandProtocol::Tipc, $id, unsafe { core::mem::transmute(Self::[<$name _tipc_impl>] as fn(&mut Self, &mut $crate::ipc::server::ServerContext) -> $crate::result::Result<()>) } /* , None, None */ )
-        }
+        ipc_tipc_interface_make_command_meta!($name: $id, None, None)
     };
-    /*
-    ($name:ident: $id:expr, [($major:expr, $minor:expr, $micro:expr) =>]) => {
+    // Same as above
+    ($name:ident: $id:expr, $min_ver:expr, $max_ver:expr) => {
         paste::paste! {
-            $crate::ipc::sf::CommandMetadata::new($crate::ipc::CommandProtocol::Tipc, $id, unsafe { core::mem::transmute(Self::[<$name _tipc_impl>] as fn(&mut Self, &mut $crate::ipc::server::ServerContext) -> $crate::result::Result<()>) }, Some($crate::version::Version::new($major, $minor, $micro)), None)
+            $crate::ipc::sf::CommandMetadata::new($crate::ipc::CommandProtocol::Tipc, $id, unsafe { core::mem::transmute(Self::[<$name _tipc_impl>] as fn(&mut Self, &mut $crate::ipc::server::ServerContext) -> $crate::result::Result<()>) }, $min_ver, $max_ver)
         }
     };
-    ($name:ident: $id:expr, [=> ($major:expr, $minor:expr, $micro:expr)]) => {
-        paste::paste! {
-            $crate::ipc::sf::CommandMetadata::new($crate::ipc::CommandProtocol::Tipc, $id, unsafe { core::mem::transmute(Self::[<$name _tipc_impl>] as fn(&mut Self, &mut $crate::ipc::server::ServerContext) -> $crate::result::Result<()>) }, None, Some($crate::version::Version::new($major, $minor, $micro)))
+}
+
+#[macro_export]
+macro_rules! ipc_sf_interface_define_command {
+    (cmif $( $command:tt )*) => {
+        ipc_cmif_interface_define_command!($( $command )*);
+    };
+    (tipc $( $command:tt )*) => {
+        ipc_tipc_interface_define_command!($( $command )*);
+    };
+    (cmif_tipc $( $command:tt )*) => {
+        ipc_cmif_tipc_interface_define_command!($( $command )*);
+    };
+}
+
+#[macro_export]
+macro_rules! ipc_sf_interface_make_command_metas {
+    (cmif $name:ident: $id:expr, $min_ver:expr, $max_ver:expr) => {
+        vec![ ipc_cmif_interface_make_command_meta!($name: $id, $min_ver, $max_ver) ]
+    };
+    (tipc $name:ident: $id:expr, $min_ver:expr, $max_ver:expr) => {
+        vec![ ipc_tipc_interface_make_command_meta!($name: $id, $min_ver, $max_ver) ]
+    };
+    (cmif_tipc $name:ident: $id:expr, $min_ver:expr, $max_ver:expr) => {
+        vec![ ipc_cmif_interface_make_command_meta!($name: $id, $min_ver, $max_ver), ipc_tipc_interface_make_command_meta!($name: $id, $min_ver, $max_ver) ]
+    };
+}
+
+#[macro_export]
+macro_rules! ipc_sf_version {
+    () => {
+        None
+    };
+    (_) => {
+        None
+    };
+    (($major:expr, $minor:expr, $micro:expr)) => {
+        Some($crate::version::Version::new($major, $minor, $micro))
+    };
+}
+
+// Buffer type with the given attributes (and fixed size type, if any), like ipc_sf_buffer!(In, MapAlias) or ipc_sf_buffer!(Out, Pointer, FixedSize; FirmwareVersion)
+#[macro_export]
+macro_rules! ipc_sf_buffer {
+    ($( $attr:ident ),+) => {
+        $crate::ipc::sf::Buffer<{bit_group!{ $crate::ipc::BufferAttribute [ $( $attr ),+ ] }}, 0>
+    };
+    ($( $attr:ident ),+; $fixed_type:ty) => {
+        $crate::ipc::sf::Buffer<{bit_group!{ $crate::ipc::BufferAttribute [ $( $attr ),+ ] }}, {core::mem::size_of::<$fixed_type>()}>
+    };
+}
+
+// Declares an interface in a single place: the trait (with the serverside command implementations), its command table and optionally a client proxy type
+// Usage:
+// ipc_sf_define_interface!(IName {
+//     command_name: protocol rq_id [min_version => max_version] => (in_params) => (out_params),
+//     ...
+// }, client ProxyName);
+// - protocol is one of cmif, tipc or cmif_tipc
+// - the version range is optional, each bound being (major, minor, micro) or _ (unbounded)
+// - buffers are just parameters with buffer types (see ipc_sf_buffer), output objects are Shared<dyn sf::IObject> output parameters
+// - the client part is optional, servers implement the trait and return get_interface_command_table() as their command table
+#[macro_export]
+macro_rules! ipc_sf_define_interface {
+    ($interface:ident { $( $command_name:ident: $protocol:ident $rq_id:literal $( [$min_ver:tt => $max_ver:tt] )? => ( $( $in_param_name:ident: $in_param_type:ty ),* ) => ( $( $out_param_name:ident: $out_param_type:ty ),* ) ),* $(,)? }) => {
+        pub trait $interface {
+            $(
+                ipc_sf_interface_define_command!($protocol $command_name: ( $( $in_param_name: $in_param_type ),* ) => ( $( $out_param_name: $out_param_type ),* ));
+            )*
+
+            fn get_interface_command_table(&self) -> $crate::ipc::sf::CommandMetadataTable where Self: Sized {
+                let mut command_table: $crate::ipc::sf::CommandMetadataTable = Vec::new();
+                $(
+                    command_table.extend(ipc_sf_interface_make_command_metas!($protocol $command_name: $rq_id, ipc_sf_version!($( $min_ver )?), ipc_sf_version!($( $max_ver )?)));
+                )*
+                command_table
+            }
         }
     };
-    ($name:ident: $id:expr, [($major_a:expr, $minor_a:expr, $micro_a:expr) => ($major_b:expr, $minor_b:expr, $micro_b:expr)]) => {
-        paste::paste! {
-            $crate::ipc::sf::CommandMetadata::new($crate::ipc::CommandProtocol::Tipc, $id, unsafe { core::mem::transmute(Self::[<$name _tipc_impl>] as fn(&mut Self, &mut $crate::ipc::server::ServerContext) -> $crate::result::Result<()>) }, Some($crate::version::Version::new($major_a, $minor_a, $micro_a)), Some($crate::version::Version::new($major_b, $minor_b, $micro_b)))
+    ($interface:ident { $( $command_name:ident: $protocol:ident $rq_id:literal $( [$min_ver:tt => $max_ver:tt] )? => ( $( $in_param_name:ident: $in_param_type:ty ),* ) => ( $( $out_param_name:ident: $out_param_type:ty ),* ) ),* $(,)? }, client $client_name:ident) => {
+        ipc_sf_define_interface!($interface { $( $command_name: $protocol $rq_id $( [$min_ver => $max_ver] )? => ( $( $in_param_name: $in_param_type ),* ) => ( $( $out_param_name: $out_param_type ),* ) ),* });
+
+        pub struct $client_name {
+            session: $crate::ipc::sf::Session
+        }
+
+        impl $crate::ipc::sf::IObject for $client_name {
+            fn get_session(&mut self) -> &mut $crate::ipc::sf::Session {
+                &mut self.session
+            }
+
+            fn get_command_table(&self) -> $crate::ipc::sf::CommandMetadataTable {
+                self.get_interface_command_table()
+            }
+        }
+
+        impl $crate::ipc::sf::client::IClientObject for $client_name {
+            fn new(session: $crate::ipc::sf::Session) -> Self {
+                Self { session: session }
+            }
+        }
+
+        impl $interface for $client_name {
+            $(
+                #[allow(unused_parens)]
+                fn $command_name(&mut self, $( $in_param_name: $in_param_type ),* ) -> $crate::result::Result<( $( $out_param_type ),* )> {
+                    ipc_client_send_request_command!([self.session.object_info; $rq_id] ( $( $in_param_name ),* ) => ( $( $out_param_name: $out_param_type ),* ))
+                }
+            )*
         }
     };
-    */
 }
 
 #[macro_use]
@@ -695,6 +780,28 @@ impl CommandIn {
         }
     }
 
+    pub fn pop_copy_handle(&mut self) -> Result<svc::Handle> {
+        match self.copy_handles.pop_at(0) {
+            Some(handle) => Ok(handle),
+            None => result::ResultUnsupportedOperation::make_err()
+        }
+    }
+
+    pub fn pop_move_handle(&mut self) -> Result<svc::Handle> {
+        match self.move_handles.pop_at(0) {
+            Some(handle) => Ok(handle),
+            None => result::ResultUnsupportedOperation::make_err()
+        }
+    }
+
+    pub fn pop_handle<const M: HandleMode>(&mut self) -> Result<sf::Handle<M>> {
+        let handle = match M {
+            HandleMode::Copy => sf::Handle::from(self.pop_copy_handle()?),
+            HandleMode::Move => sf::Handle::from(self.pop_move_handle()?),
+        };
+        Ok(handle)
+    }
+
     pub fn add_domain_object(&mut self, domain_object_id: cmif::DomainObjectId) -> Result<()> {
         match self.objects.try_push(domain_object_id) {
             Ok(()) => Ok(()),
@@ -711,6 +818,13 @@ impl CommandIn {
         }
     }
 
+    pub fn pop_domain_object(&mut self) -> Result<cmif::DomainObjectId> {
+        match self.objects.pop_at(0) {
+            Some(domain_object_id) => Ok(domain_object_id),
+            None => result::ResultUnsupportedOperation::make_err()
+        }
+    }
+
     pub fn add_out_pointer_size(&mut self, pointer_size: u16) -> Result<()> {
         match self.out_pointer_sizes.try_push(pointer_size) {
             Ok(()) => Ok(()),
@@ -89,6 +89,44 @@ macro_rules! ipc_client_send_control_command {
     }};
 }
 
+// Generates a client proxy type for an interface trait, so that host-side code can call emulated services with typed parameters
+// Usage: ipc_client_define_object!(ProxyName: ITraitName { command_name: rq_id => (in_params) => (out_params), ... });
+#[macro_export]
+macro_rules! ipc_client_define_object {
+    ($name:ident: $interface:ident { $( $command_name:ident: $rq_id:expr => ( $( $in_param_name:ident: $in_param_type:ty ),* ) => ( $( $out_param_name:ident: $out_param_type:ty ),* ) ),* $(,)? }) => {
+        pub struct $name {
+            session: $crate::ipc::sf::Session
+        }
+
+        impl $crate::ipc::sf::IObject for $name {
+            fn get_session(&mut self) -> &mut $crate::ipc::sf::Session {
+                &mut self.session
+            }
+
+            fn get_command_table(&self) -> $crate::ipc::sf::CommandMetadataTable {
+                vec! [
+                    $( ipc_cmif_interface_make_command_meta!($command_name: $rq_id) ),*
+                ]
+            }
+        }
+
+        impl $crate::ipc::sf::client::IClientObject for $name {
+            fn new(session: $crate::ipc::sf::Session) -> Self {
+                Self { session: session }
+            }
+        }
+
+        impl $interface for $name {
+            $(
+                #[allow(unused_parens)]
+                fn $command_name(&mut self, $( $in_param_name: $in_param_type ),* ) -> $crate::result::Result<( $( $out_param_type ),* )> {
+                    ipc_client_send_request_command!([self.session.object_info; $rq_id] ( $( $in_param_name ),* ) => ( $( $out_param_name: $out_param_type ),* ))
+                }
+            )*
+        }
+    };
+}
+
 pub trait CommandParameter<O> {
     fn before_request_write(var: &Self, walker: &mut DataWalker, ctx: &mut CommandContext) -> Result<()>;
     fn before_send_sync_request(var: &Self, walker: &mut DataWalker, ctx: &mut CommandContext) -> Result<()>;
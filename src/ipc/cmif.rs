@@ -24,6 +24,17 @@ pub enum DomainCommandType {
     Close = 2
 }
 
+impl DomainCommandType {
+    pub const fn from(raw: u8) -> Option<Self> {
+        match raw {
+            0 => Some(Self::Invalid),
+            1 => Some(Self::SendMessage),
+            2 => Some(Self::Close),
+            _ => None
+        }
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
 #[repr(C)]
 pub struct DomainInDataHeader {
@@ -11,5 +11,8 @@ result_define_group!(RESULT_MODULE => {
 
     TargetNotFound: 261,
 
-    OutOfDomainEntries: 301
+    OutOfDomainEntries: 301,
+
+    RequestDeferred: 811,
+    RequestDeferredByUser: 812
 });
\ No newline at end of file
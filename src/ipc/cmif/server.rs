@@ -96,13 +96,22 @@ pub fn read_request_command_from_msg_buffer(ctx: &mut CommandContext) -> Result<
 
         let mut data_header = data_offset as *mut DataHeader;
         if ctx.object_info.is_domain() {
+            // Note: everything here comes straight from the client, thus nothing past the request's data may be accessed
+            result_return_unless!(ctx.in_params.data_size >= mem::size_of::<DomainInDataHeader>() as u32, result::ResultInvalidHeaderSize);
             let domain_header = data_offset as *mut DomainInDataHeader;
             data_offset = domain_header.offset(1) as *mut u8;
             ctx.in_params.data_size -= mem::size_of::<DomainInDataHeader>() as u32;
 
-            domain_command_type = (*domain_header).command_type;
+            // The command type is read as a raw value, since any value might be there
+            domain_command_type = match DomainCommandType::from(*(domain_header as *const u8)) {
+                Some(command_type) => command_type,
+                None => return result::ResultInvalidInHeader::make_err()
+            };
             let object_count = (*domain_header).object_count;
+            result_return_unless!(object_count as usize <= MAX_COUNT, result::ResultInvalidNumInObjects);
             domain_object_id = (*domain_header).domain_object_id;
+            let objects_size = (*domain_header).data_size as u32 + object_count as u32 * mem::size_of::<DomainObjectId>() as u32;
+            result_return_unless!(objects_size <= ctx.in_params.data_size, result::ResultInvalidHeaderSize);
             let objects_offset = data_offset.offset((*domain_header).data_size as isize);
             read_array_from_buffer(objects_offset, object_count as u32, &mut ctx.in_params.objects);
 
@@ -159,12 +168,14 @@ pub fn write_request_command_response_on_msg_buffer(ctx: &mut CommandContext, re
     }
 }
 
+// Returns the raw control request ID (see ControlRequestId), since unknown ones are answered with ResultUnknownCommandId like unknown commands
 #[inline(always)]
-pub fn read_control_command_from_msg_buffer(ctx: &mut CommandContext) -> Result<ControlRequestId> {
+pub fn read_control_command_from_msg_buffer(ctx: &mut CommandContext) -> Result<u32> {
     unsafe {
         let ipc_buf = get_msg_buffer();
         let mut data_offset = get_aligned_data_offset(ctx.in_params.data_words_offset, ipc_buf);
 
+        result_return_unless!(ctx.in_params.data_size >= DATA_PADDING + mem::size_of::<DataHeader>() as u32, result::ResultInvalidHeaderSize);
         let data_header = data_offset as *mut DataHeader;
         data_offset = data_header.offset(1) as *mut u8;
 
@@ -173,7 +184,7 @@ pub fn read_control_command_from_msg_buffer(ctx: &mut CommandContext) -> Result<
 
         ctx.in_params.data_offset = data_offset;
         ctx.in_params.data_size -= DATA_PADDING + mem::size_of::<DataHeader>() as u32;
-        Ok(mem::transmute(control_rq_id))
+        Ok(control_rq_id)
     }
 }
 
@@ -5,11 +5,14 @@ use crate::ipc::sf::client;
 use crate::ipc::sf::client::sm;
 use crate::ipc::sf::client::sm::IUserInterface;
 use crate::ipc::cmif::result as cmif_result;
+use crate::emu::host_profiler;
 use crate::kern::result as kern_result;
+use crate::kern::event::{KEvent, KReadableEvent};
+use crate::kern::proc::resolve_handle;
 use crate::util::Shared;
 use super::*;
 
-// TODO: tipc support, implement remaining control commands
+// TODO: tipc support
 
 const MAX_COUNT: usize = 0x40;
 
@@ -71,9 +74,8 @@ impl<const A: BufferAttribute, const S: usize> CommandParameter<sf::Buffer<A, S>
 }
 
 impl<const M: HandleMode> CommandParameter<sf::Handle<M>> for sf::Handle<M> {
-    fn after_request_read(_ctx: &mut ServerContext) -> Result<Self> {
-        // TODO: pop copy/move
-        result::ResultUnsupportedOperation::make_err()
+    fn after_request_read(ctx: &mut ServerContext) -> Result<Self> {
+        ctx.ctx.in_params.pop_handle()
     }
 
     fn before_response_write(handle: &Self, ctx: &mut ServerContext) -> Result<()> {
@@ -107,8 +109,15 @@ impl CommandParameter<sf::ProcessId> for sf::ProcessId {
 }
 
 impl CommandParameter<Shared<dyn sf::IObject>> for Shared<dyn sf::IObject> {
-    fn after_request_read(_ctx: &mut ServerContext) -> Result<Self> {
-        result::ResultUnsupportedOperation::make_err()
+    fn after_request_read(ctx: &mut ServerContext) -> Result<Self> {
+        // Input objects are only supported within domains, where they are just IDs of objects in the same domain
+        if ctx.ctx.object_info.is_domain() {
+            let domain_object_id = ctx.ctx.in_params.pop_domain_object()?;
+            ctx.domain_table.get().find_domain(domain_object_id).map_err(|_| cmif_result::ResultInvalidInObject::make())
+        }
+        else {
+            result::ResultUnsupportedOperation::make_err()
+        }
     }
 
     fn before_response_write(session: &Self, ctx: &mut ServerContext) -> Result<()> {
@@ -280,20 +289,16 @@ pub struct HipcManager<'a> {
     session: sf::Session,
     server_holder: &'a mut ServerHolder,
     pointer_buf_size: usize,
-    pub cloned_object_server_handle: svc::Handle
+    pub new_sessions: Vec<ServerHolder>
 }
 
 impl<'a> HipcManager<'a> {
     pub fn new(server_holder: &'a mut ServerHolder, pointer_buf_size: usize) -> Self {
-        Self { session: sf::Session::new(), server_holder: server_holder, pointer_buf_size: pointer_buf_size, cloned_object_server_handle: 0 }
+        Self { session: sf::Session::new(), server_holder: server_holder, pointer_buf_size: pointer_buf_size, new_sessions: Vec::new() }
     }
 
-    pub fn has_cloned_object(&self) -> bool {
-        self.cloned_object_server_handle != 0
-    }
-
-    pub fn clone_object(&self) -> Result<ServerHolder> {
-        self.server_holder.clone_self(self.cloned_object_server_handle)
+    pub fn has_new_sessions(&self) -> bool {
+        !self.new_sessions.is_empty()
     }
 }
 
@@ -303,17 +308,37 @@ impl<'a> IHipcManager for HipcManager<'a> {
         self.server_holder.convert_to_domain()
     }
 
-    fn copy_from_current_domain(&mut self, _domain_object_id: cmif::DomainObjectId) -> Result<sf::MoveHandle> {
-        log_line!("copy_from_current_domain!");
-        // TODO
-        lib_result::ResultNotSupported::make_err()
+    fn copy_from_current_domain(&mut self, domain_object_id: cmif::DomainObjectId) -> Result<sf::MoveHandle> {
+        log_line!("copy_from_current_domain! domain object ID: {}", domain_object_id);
+        result_return_unless!(self.server_holder.info.is_domain(), result::ResultTargetNotDomain);
+
+        // Note: the copied session references the same object, it's not a new instance of it
+        let object = self.server_holder.domain_table.get().find_domain(domain_object_id).map_err(|_| result::ResultDomainObjectNotFound::make())?;
+
+        let (server_handle, client_handle) = svc::create_session(false, 0)?;
+        // The holder owns the server handle from now on, so it gets closed if anything fails later
+        let mut copied_holder = ServerHolder::new_session(server_handle, object);
+        copied_holder.new_server_fn = self.server_holder.new_server_fn;
+        self.new_sessions.push(copied_holder);
+
+        Ok(sf::Handle::from(client_handle))
     }
 
     fn clone_current_object(&mut self) -> Result<sf::MoveHandle> {
         log_line!("clone_current_object!");
         let (server_handle, client_handle) = svc::create_session(false, 0)?;
 
-        self.cloned_object_server_handle = server_handle;
+        // Clones of domain sessions share the same domain table (they refer to the same domain), and never own the service registration
+        let cloned_holder = match self.server_holder.clone_self(server_handle) {
+            Ok(holder) => holder,
+            Err(rc) => {
+                svc::close_handle(server_handle)?;
+                svc::close_handle(client_handle)?;
+                return Err(rc);
+            }
+        };
+        self.new_sessions.push(cloned_holder);
+
         Ok(sf::Handle::from(client_handle))
     }
 
@@ -357,20 +382,70 @@ pub trait INamedPort: IServerObject {
 
 // TODO: use const generics to reduce memory usage, like libstratosphere does?
 
+// Requests whose handling was deferred by the command itself (see ResultRequestDeferredByUser), which are handled again after any other request is handled (or a wake event is signaled)
+struct DeferredRequest<const P: usize> {
+    handle: svc::Handle,
+    msg_buffer: [u8; 0x100],
+    pointer_buffer: [u8; P]
+}
+
+fn reply_to_session(handle: svc::Handle) -> Result<()> {
+    match svc::reply_and_receive(&[], handle, 0) {
+        Err(rc) => {
+            if kern_result::ResultTimedOut::matches(rc) || result::ResultSessionClosed::matches(rc) {
+                Ok(())
+            }
+            else {
+                Err(rc)
+            }
+        },
+        _ => Ok(())
+    }
+}
+
+// Like the official server implementation does, sessions sending malformed requests are closed, instead of bringing the whole server down
+// Note: the request is still replied with the failure, since the client would otherwise keep waiting for a reply forever
+fn fail_invalid_request(ctx: &mut CommandContext, handle: svc::Handle, rc: ResultCode) -> Result<()> {
+    log_line!("Closing session {:#X} after an invalid request: {0} ({0:?})", handle, rc);
+    cmif::server::write_request_command_response_on_msg_buffer(ctx, rc, cmif::CommandType::Request);
+    reply_to_session(handle)
+}
+
+fn read_request_info(server_holder: &ServerHolder, ctx: &mut CommandContext) -> Result<(u32, cmif::DomainCommandType, Shared<DomainTable>)> {
+    let server_info = server_holder.info;
+    let (request_id, domain_command_type, domain_object_id) = cmif::server::read_request_command_from_msg_buffer(ctx)?;
+
+    let mut base_info = server_info;
+    if server_info.is_domain() {
+        // This is a domain request
+        base_info.domain_object_id = domain_object_id;
+        base_info.owns_handle = server_info.domain_object_id == domain_object_id;
+    }
+    ctx.object_info = base_info;
+    Ok((request_id, domain_command_type, server_holder.domain_table.clone()))
+}
+
 pub struct ServerManager<const P: usize> {
     server_holders: Vec<ServerHolder>,
+    deferred_requests: Vec<DeferredRequest<P>>,
+    // Readable sides of the events which, once signaled, get deferred requests handled again (see register_wake_event)
+    wake_event_handles: Vec<svc::Handle>,
     wait_handles: [svc::Handle; MAX_COUNT],
     pointer_buffer: [u8; P]
 }
 
 impl<const P: usize> ServerManager<P> {
     pub fn new() -> Result<Self> {
-        Ok(Self { server_holders: Vec::new(), wait_handles: [0; MAX_COUNT], pointer_buffer: [0; P] })
+        Ok(Self { server_holders: Vec::new(), deferred_requests: Vec::new(), wake_event_handles: Vec::new(), wait_handles: [0; MAX_COUNT], pointer_buffer: [0; P] })
     }
     
     #[inline(always)]
     fn prepare_wait_handles(&mut self) -> &[svc::Handle] {
         let mut handles_index: usize = 0;
+        for wake_event_handle in &self.wake_event_handles {
+            self.wait_handles[handles_index] = *wake_event_handle;
+            handles_index += 1;
+        }
         for server_holder in &mut self.server_holders {
             let server_info = server_holder.info;
             if server_info.handle != svc::INVALID_HANDLE {
@@ -382,10 +457,13 @@ impl<const P: usize> ServerManager<P> {
         unsafe { core::slice::from_raw_parts(self.wait_handles.as_ptr(), handles_index) }
     }
 
+    // Returns whether the request was deferred, in which case no response must be sent (yet)
     #[inline(always)]
-    fn handle_request_command(&mut self, ctx: &mut CommandContext, rq_id: u32, command_type: cmif::CommandType, domain_command_type: cmif::DomainCommandType, domain_table: Shared<DomainTable>) -> Result<()> {
+    fn handle_request_command(&mut self, ctx: &mut CommandContext, rq_id: u32, command_type: cmif::CommandType, domain_command_type: cmif::DomainCommandType, domain_table: Shared<DomainTable>) -> Result<bool> {
+        let _span = host_profiler::enter("ipc_dispatch");
         let is_domain = ctx.object_info.is_domain();
         let domain_table_clone = domain_table.clone();
+        let mut is_deferred = false;
         let mut do_handle_request = || -> Result<()> {
             let mut new_sessions: Vec<ServerHolder> = Vec::new();
             for server_holder in &mut self.server_holders {
@@ -406,7 +484,12 @@ impl<const P: usize> ServerManager<P> {
                             command_found = true;
                             let mut server_ctx = ServerContext::new(ctx, DataWalker::empty(), domain_table_clone.clone(), &mut new_sessions);
                             if let Err(rc) = target_server.get().call_self_command(command.command_fn, &mut server_ctx) {
-                                cmif::server::write_request_command_response_on_msg_buffer(ctx, rc, command_type);
+                                if cmif_result::ResultRequestDeferredByUser::matches(rc) {
+                                    is_deferred = true;
+                                }
+                                else {
+                                    cmif::server::write_request_command_response_on_msg_buffer(ctx, rc, command_type);
+                                }
                             }
                         }
                     }
@@ -441,11 +524,53 @@ impl<const P: usize> ServerManager<P> {
             }
         }
 
+        Ok(is_deferred)
+    }
+
+    fn defer_request(&mut self, handle: svc::Handle) {
+        let mut deferred_request = DeferredRequest {
+            handle: handle,
+            msg_buffer: [0; 0x100],
+            pointer_buffer: [0; P]
+        };
+        unsafe {
+            core::ptr::copy(get_msg_buffer(), deferred_request.msg_buffer.as_mut_ptr(), deferred_request.msg_buffer.len());
+        }
+        deferred_request.pointer_buffer.copy_from_slice(&self.pointer_buffer);
+        self.deferred_requests.push(deferred_request);
+    }
+
+    fn process_deferred_requests(&mut self) -> Result<()> {
+        let deferred_requests = core::mem::take(&mut self.deferred_requests);
+        for deferred_request in deferred_requests {
+            // The session might have been closed meanwhile
+            let server_holder = match self.server_holders.iter().find(|server_holder| server_holder.info.handle == deferred_request.handle) {
+                Some(server_holder) => server_holder,
+                None => continue
+            };
+
+            // Restore the request as if it was just received
+            unsafe {
+                core::ptr::copy(deferred_request.msg_buffer.as_ptr(), get_msg_buffer(), deferred_request.msg_buffer.len());
+            }
+            self.pointer_buffer.copy_from_slice(&deferred_request.pointer_buffer);
+
+            let mut ctx = CommandContext::new_server(server_holder.info, self.pointer_buffer.as_mut_ptr());
+            let command_type = cmif::server::read_command_from_msg_buffer(&mut ctx);
+            let (rq_id, domain_cmd_type, domain_table) = read_request_info(server_holder, &mut ctx)?;
+
+            match self.handle_request_command(&mut ctx, rq_id, command_type, domain_cmd_type, domain_table)? {
+                true => self.deferred_requests.push(deferred_request),
+                false => reply_to_session(deferred_request.handle)?
+            };
+        }
+
         Ok(())
     }
 
     #[inline(always)]
     fn handle_control_command(&mut self, ctx: &mut CommandContext, rq_id: u32, command_type: cmif::CommandType) -> Result<()> {
+        let mut new_sessions: Vec<ServerHolder> = Vec::new();
         for server_holder in &mut self.server_holders {
             let server_info = server_holder.info;
             if server_info.handle == ctx.object_info.handle {
@@ -460,6 +585,8 @@ impl<const P: usize> ServerManager<P> {
                         let mut server_ctx = ServerContext::new(ctx, DataWalker::empty(), unused_domain_table, &mut unused_new_sessions);
                         if let Err(rc) = hipc_manager.call_self_command(command.command_fn, &mut server_ctx) {
                             cmif::server::write_control_command_response_on_msg_buffer(ctx, rc, command_type);
+                            // Sessions created by a failed command are dropped (thus closed) instead of being registered
+                            hipc_manager.new_sessions.clear();
                         }
                     }
                 }
@@ -467,17 +594,39 @@ impl<const P: usize> ServerManager<P> {
                     cmif::server::write_control_command_response_on_msg_buffer(ctx, cmif_result::ResultUnknownCommandId::make(), command_type);
                 }
 
-                if hipc_manager.has_cloned_object() {
-                    let cloned_holder = hipc_manager.clone_object()?;
-                    self.server_holders.push(cloned_holder);
+                if hipc_manager.has_new_sessions() {
+                    new_sessions.append(&mut hipc_manager.new_sessions);
                 }
                 break;
             }
         }
 
+        self.server_holders.append(&mut new_sessions);
+
         Ok(())
     }
 
+    // Note: objects (and domain tables) shared with other sessions (see clone_current_object) are only notified once no session is left using them
+    fn close_session_holder(&mut self, index: usize) {
+        let server_holder = self.server_holders.remove(index);
+        self.deferred_requests.retain(|deferred_request| deferred_request.handle != server_holder.info.handle);
+
+        let is_object_shared = self.server_holders.iter().any(|holder| holder.server.ptr_eq(&server_holder.server));
+        if !is_object_shared {
+            server_holder.server.get().on_client_disconnected();
+        }
+
+        let is_domain_table_shared = self.server_holders.iter().any(|holder| holder.domain_table.ptr_eq(&server_holder.domain_table));
+        if server_holder.info.is_domain() && !is_domain_table_shared {
+            let domain_objects: Vec<Shared<dyn sf::IObject>> = server_holder.domain_table.get().domains.iter().map(|domain_holder| domain_holder.server.clone()).collect();
+            for domain_object in domain_objects.iter().filter(|domain_object| !domain_object.ptr_eq(&server_holder.server)) {
+                domain_object.get().on_client_disconnected();
+            }
+        }
+
+        // The holder is dropped here, closing our server session handle only after the objects were notified
+    }
+
     fn process_signaled_handle(&mut self, handle: svc::Handle) -> Result<()> {
         let mut server_found = false;
         let mut index: usize = 0;
@@ -485,6 +634,7 @@ impl<const P: usize> ServerManager<P> {
         let mut new_sessions: Vec<ServerHolder> = Vec::new();
 
         let mut ctx = CommandContext::empty();
+        let mut invalid_request_rc: Option<ResultCode> = None;
         let mut command_type = cmif::CommandType::Invalid;
         let mut domain_cmd_type = cmif::DomainCommandType::Invalid;
         let mut rq_id: u32 = 0;
@@ -520,34 +670,27 @@ impl<const P: usize> ServerManager<P> {
                         command_type = cmif::server::read_command_from_msg_buffer(&mut ctx);
                         match command_type {
                             cmif::CommandType::Request | cmif::CommandType::RequestWithContext => {
-                                match cmif::server::read_request_command_from_msg_buffer(&mut ctx) {
-                                    Ok((request_id, domain_command_type, domain_object_id)) => {
-                                        let mut base_info = server_info;
-                                        if server_info.is_domain() {
-                                            // This is a domain request
-                                            base_info.domain_object_id = domain_object_id;
-                                            base_info.owns_handle = server_info.domain_object_id == domain_object_id;
-                                        }
-                                        ctx.object_info = base_info;
+                                match read_request_info(server_holder, &mut ctx) {
+                                    Ok((request_id, domain_command_type, request_domain_table)) => {
                                         domain_cmd_type = domain_command_type;
                                         rq_id = request_id;
-                                        domain_table = server_holder.domain_table.clone();
+                                        domain_table = request_domain_table;
                                     },
-                                    Err(rc) => return Err(rc)
+                                    Err(rc) => invalid_request_rc = Some(rc)
                                 };
                             },
                             cmif::CommandType::Control | cmif::CommandType::ControlWithContext => {
                                 match cmif::server::read_control_command_from_msg_buffer(&mut ctx) {
                                     Ok(control_rq_id) => {
-                                        rq_id = control_rq_id as u32;
+                                        rq_id = control_rq_id;
                                     },
-                                    Err(rc) => return Err(rc),
+                                    Err(rc) => invalid_request_rc = Some(rc)
                                 };
                             },
                             cmif::CommandType::Close => {
                                 should_close_session = true;
                             },
-                            _ => return result::ResultUnknownCommandType::make_err()
+                            _ => invalid_request_rc = Some(result::ResultUnknownCommandType::make())
                         }
                     },
                     WaitHandleType::Server => {
@@ -560,32 +703,34 @@ impl<const P: usize> ServerManager<P> {
             index += 1;
         }
 
-        let reply_impl = || -> Result<()> {
-            match svc::reply_and_receive(&[], handle, 0) {
-                Err(rc) => {
-                    if kern_result::ResultTimedOut::matches(rc) || result::ResultSessionClosed::matches(rc) {
-                        Ok(())
-                    }
-                    else {
-                        Err(rc)
-                    }
-                },
-                _ => Ok(())
-            }
-        };
+        let mut is_request_handled = false;
+        if let Some(rc) = invalid_request_rc {
+            fail_invalid_request(&mut ctx, handle, rc)?;
+            should_close_session = true;
+            command_type = cmif::CommandType::Invalid;
+        }
 
         match command_type {
             cmif::CommandType::Request | cmif::CommandType::RequestWithContext => {
-                self.handle_request_command(&mut ctx, rq_id, command_type, domain_cmd_type, domain_table)?;
-                reply_impl()?;
+                match self.handle_request_command(&mut ctx, rq_id, command_type, domain_cmd_type, domain_table) {
+                    Ok(true) => self.defer_request(handle),
+                    Ok(false) => {
+                        reply_to_session(handle)?;
+                        is_request_handled = true;
+                    },
+                    Err(rc) => {
+                        fail_invalid_request(&mut ctx, handle, rc)?;
+                        should_close_session = true;
+                    }
+                };
             },
             cmif::CommandType::Control | cmif::CommandType::ControlWithContext => {
                 self.handle_control_command(&mut ctx, rq_id, command_type)?;
-                reply_impl()?;
+                reply_to_session(handle)?;
             },
             cmif::CommandType::Close => {
                 cmif::server::write_close_command_response_on_msg_buffer(&mut ctx);
-                reply_impl()?;
+                reply_to_session(handle)?;
             }
             _ => {
                 // Do nothing, since it might not be set at all without having failed (for instance, if a new session was accepted)
@@ -594,11 +739,16 @@ impl<const P: usize> ServerManager<P> {
         };
 
         if should_close_session {
-            self.server_holders.remove(index);
+            self.close_session_holder(index);
         }
 
         self.server_holders.append(&mut new_sessions);
 
+        // Any handled request might be what deferred requests were waiting for (like a service registration in sm)
+        if is_request_handled && !self.deferred_requests.is_empty() {
+            self.process_deferred_requests()?;
+        }
+
         match server_found {
             true => Ok(()),
             false => result::ResultUnsupportedOperation::make_err()
@@ -630,12 +780,32 @@ impl<const P: usize> ServerManager<P> {
         Ok(())
     }
 
+    // Returns an event to signal whenever deferred requests might be ready to be handled, like once some work they wait for is done outside the server thread
+    // Note: it's meant to be signaled through KReadableEvent::signal, which can be done from any (even non-emulated) thread
+    pub fn register_wake_event(&mut self) -> Result<Shared<KReadableEvent>> {
+        let (writable_event_handle, readable_event_handle) = svc::create_event()?;
+        let event = resolve_handle::<KEvent>(writable_event_handle);
+
+        // Note: only the readable side is kept (alive through its handle), which is all that's needed to signal it
+        svc::close_handle(writable_event_handle)?;
+        let readable_event = event?.get().readable_event.clone();
+
+        self.wake_event_handles.push(readable_event_handle);
+        Ok(readable_event)
+    }
+
     pub fn process(&mut self) -> Result<()> {
         let handles = self.prepare_wait_handles();
         let index = svc::wait_synchronization(handles, -1)?;
 
         let signaled_handle = self.wait_handles[index];
-        self.process_signaled_handle(signaled_handle)?;
+        match self.wake_event_handles.contains(&signaled_handle) {
+            true => {
+                svc::clear_event(signaled_handle)?;
+                self.process_deferred_requests()?;
+            },
+            false => self.process_signaled_handle(signaled_handle)?
+        };
 
         Ok(())
     }
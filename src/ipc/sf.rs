@@ -1,5 +1,6 @@
 use super::*;
 use crate::kern::svc;
+use crate::version::{self, Version};
 use core::mem;
 
 pub mod client;
@@ -10,6 +11,32 @@ pub mod sm;
 
 pub mod set;
 
+pub mod spl;
+
+pub mod ro;
+
+pub mod lm;
+
+pub mod fatal;
+
+pub mod ncm;
+
+pub mod account;
+
+pub mod am;
+
+pub mod pl;
+
+pub mod ns;
+
+pub mod bsd;
+
+pub mod nifm;
+
+pub mod time;
+
+pub mod fs;
+
 #[derive(Clone, Debug)]
 pub struct Buffer<const A: BufferAttribute, const S: usize> {
     pub buf: *const u8,
@@ -202,36 +229,36 @@ pub struct CommandMetadata {
     pub protocol: CommandProtocol,
     pub rq_id: u32,
     pub command_fn: CommandFn,
-    // pub min_ver: Option<version::Version>,
-    // pub max_ver: Option<version::Version>
+    pub min_ver: Option<Version>,
+    pub max_ver: Option<Version>
 }
 
 pub type CommandMetadataTable = Vec<CommandMetadata>;
 
 impl CommandMetadata {
-    pub fn new(protocol: CommandProtocol, rq_id: u32, command_fn: CommandFn /* , min_ver: Option<version::Version>, max_ver: Option<version::Version> */ ) -> Self {
-        Self { protocol: protocol, rq_id: rq_id, command_fn: command_fn /* , min_ver: min_ver, max_ver: max_ver */ }
+    pub fn new(protocol: CommandProtocol, rq_id: u32, command_fn: CommandFn, min_ver: Option<Version>, max_ver: Option<Version>) -> Self {
+        Self { protocol: protocol, rq_id: rq_id, command_fn: command_fn, min_ver: min_ver, max_ver: max_ver }
     }
 
     pub fn validate_version(&self) -> bool {
-        /*
-        let ver = version::get_version();
-        if let Some(min_v) = self.min_ver {
-            if ver < min_v {
-                return false;
+        // Note: all commands are available while the emulated firmware version is unknown
+        if let Some(ver) = version::try_get_version() {
+            if let Some(min_ver) = self.min_ver {
+                if ver < min_ver {
+                    return false;
+                }
             }
-        }
-        if let Some(max_v) = self.max_ver {
-            if ver > max_v {
-                return false;
+            if let Some(max_ver) = self.max_ver {
+                if ver > max_ver {
+                    return false;
+                }
             }
         }
-        */
         true
     }
 
     pub fn matches(&self, protocol: CommandProtocol, rq_id: u32) -> bool {
-        self.validate_version() && (self.protocol == protocol) && (self.rq_id == rq_id)
+        (self.protocol == protocol) && (self.rq_id == rq_id) && self.validate_version()
     }
 }
 
@@ -262,6 +289,9 @@ pub trait IObject: Send + Sync {
         self.get_session().close()
     }
 
+    // Called on server objects once their client is gone (session closed or client disconnected), right before they're dropped
+    fn on_client_disconnected(&mut self) {}
+
     fn is_valid(&mut self) -> bool {
         self.get_info().is_valid()
     }
@@ -0,0 +1,20 @@
+use crate::account::*;
+use crate::util::Shared;
+use super::*;
+
+ipc_sf_define_interface!(IProfile {
+    get: cmif 0 => (out_user_data: sf::OutFixedPointerBuffer<UserData>) => (base: ProfileBase),
+    get_base: cmif 1 => () => (base: ProfileBase),
+    get_image_size: cmif 10 => () => (size: u32),
+    load_image: cmif 11 => (out_image: sf::OutMapAliasBuffer) => (size: u32)
+});
+
+ipc_sf_define_interface!(IAccountServiceForApplication {
+    get_user_count: cmif 0 => () => (count: u32),
+    get_user_existence: cmif 1 => (uid: Uid) => (exists: bool),
+    list_all_users: cmif 2 => (out_uids: sf::OutPointerBuffer) => (),
+    list_open_users: cmif 3 => (out_uids: sf::OutPointerBuffer) => (),
+    get_last_opened_user: cmif 4 => () => (uid: Uid),
+    get_profile: cmif 5 => (uid: Uid) => (profile: Shared<dyn sf::IObject>),
+    initialize_application_info_v0: cmif 100 => (process_id: sf::ProcessId) => ()
+});
@@ -0,0 +1,36 @@
+use crate::util::Shared;
+use super::*;
+
+ipc_sf_define_interface!(IStorageAccessor {
+    get_size: cmif 0 => () => (size: i64),
+    write: cmif 10 => (offset: i64, in_buf: sf::InAutoSelectBuffer) => (),
+    read: cmif 11 => (offset: i64, out_buf: sf::OutAutoSelectBuffer) => ()
+});
+
+ipc_sf_define_interface!(IStorage {
+    open: cmif 0 => () => (accessor: Shared<dyn sf::IObject>)
+});
+
+ipc_sf_define_interface!(ILibraryAppletAccessor {
+    get_applet_state_changed_event: cmif 0 => () => (event_handle: sf::CopyHandle),
+    is_completed: cmif 1 => () => (is_completed: bool),
+    start: cmif 10 => () => (),
+    request_exit: cmif 20 => () => (),
+    terminate: cmif 25 => () => (),
+    get_result: cmif 30 => () => (),
+    push_in_data: cmif 100 => (storage: Shared<dyn sf::IObject>) => (),
+    pop_out_data: cmif 101 => () => (storage: Shared<dyn sf::IObject>)
+});
+
+ipc_sf_define_interface!(ILibraryAppletCreator {
+    create_library_applet: cmif 0 => (applet_id: u32, mode: u32) => (accessor: Shared<dyn sf::IObject>),
+    create_storage: cmif 10 => (size: i64) => (storage: Shared<dyn sf::IObject>)
+});
+
+ipc_sf_define_interface!(IApplicationProxy {
+    get_library_applet_creator: cmif 11 => () => (creator: Shared<dyn sf::IObject>)
+});
+
+ipc_sf_define_interface!(IApplicationProxyService {
+    open_application_proxy: cmif 0 => (process_id: sf::ProcessId, self_process_handle: sf::CopyHandle) => (proxy: Shared<dyn sf::IObject>)
+});
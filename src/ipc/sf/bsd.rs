@@ -0,0 +1,27 @@
+use crate::bsd::LibraryConfigData;
+use super::*;
+
+// Socket calls return BSD-style results: the actual return value (-1 on failure) along with the errno
+ipc_sf_define_interface!(IClient {
+    register_client: cmif 0 => (config: LibraryConfigData, process_id: sf::ProcessId, transfer_mem_size: u64, transfer_mem_handle: sf::CopyHandle) => (client_id: u64),
+    start_monitoring: cmif 1 => (process_id: sf::ProcessId) => (),
+    socket: cmif 2 => (domain: u32, sock_type: u32, protocol: u32) => (ret: i32, errno: u32),
+    poll: cmif 6 => (fds: sf::InAutoSelectBuffer, out_fds: sf::OutAutoSelectBuffer, fd_count: u32, timeout: i32) => (ret: i32, errno: u32),
+    recv: cmif 8 => (fd: i32, flags: u32, out_buf: sf::OutAutoSelectBuffer) => (ret: i32, errno: u32),
+    recv_from: cmif 9 => (fd: i32, flags: u32, out_buf: sf::OutAutoSelectBuffer, out_addr: sf::OutAutoSelectBuffer) => (ret: i32, errno: u32, addr_len: u32),
+    send: cmif 10 => (fd: i32, flags: u32, buf: sf::InAutoSelectBuffer) => (ret: i32, errno: u32),
+    send_to: cmif 11 => (fd: i32, flags: u32, buf: sf::InAutoSelectBuffer, addr: sf::InAutoSelectBuffer) => (ret: i32, errno: u32),
+    accept: cmif 12 => (fd: i32, out_addr: sf::OutAutoSelectBuffer) => (ret: i32, errno: u32, addr_len: u32),
+    bind: cmif 13 => (fd: i32, addr: sf::InAutoSelectBuffer) => (ret: i32, errno: u32),
+    connect: cmif 14 => (fd: i32, addr: sf::InAutoSelectBuffer) => (ret: i32, errno: u32),
+    get_peer_name: cmif 15 => (fd: i32, out_addr: sf::OutAutoSelectBuffer) => (ret: i32, errno: u32, addr_len: u32),
+    get_sock_name: cmif 16 => (fd: i32, out_addr: sf::OutAutoSelectBuffer) => (ret: i32, errno: u32, addr_len: u32),
+    get_sock_opt: cmif 17 => (fd: i32, level: u32, opt_name: u32, out_opt_val: sf::OutAutoSelectBuffer) => (ret: i32, errno: u32, opt_len: u32),
+    listen: cmif 18 => (fd: i32, backlog: i32) => (ret: i32, errno: u32),
+    fcntl: cmif 20 => (fd: i32, cmd: u32, flags: u32) => (ret: i32, errno: u32),
+    set_sock_opt: cmif 21 => (fd: i32, level: u32, opt_name: u32, opt_val: sf::InAutoSelectBuffer) => (ret: i32, errno: u32),
+    shutdown: cmif 22 => (fd: i32, how: u32) => (ret: i32, errno: u32),
+    write: cmif 24 => (fd: i32, buf: sf::InAutoSelectBuffer) => (ret: i32, errno: u32),
+    read: cmif 25 => (fd: i32, out_buf: sf::OutAutoSelectBuffer) => (ret: i32, errno: u32),
+    close: cmif 26 => (fd: i32) => (ret: i32, errno: u32)
+});
@@ -4,6 +4,8 @@ use crate::result::*;
 use crate::util::Shared;
 
 pub mod sm;
+
+pub mod set;
 use crate::sm::ServiceName;
 use super::sm::IUserInterface;
 
@@ -0,0 +1,34 @@
+use crate::result::*;
+use crate::ipc::sf::client;
+
+pub use crate::set::*;
+// Note: the SettingsServer/SystemSettingsServer proxy types are generated along with the interfaces
+pub use crate::ipc::sf::set::*;
+
+impl client::IService for SettingsServer {
+    fn get_name() -> &'static str {
+        "set"
+    }
+
+    fn as_domain() -> bool {
+        false
+    }
+
+    fn post_initialize(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl client::IService for SystemSettingsServer {
+    fn get_name() -> &'static str {
+        "set:sys"
+    }
+
+    fn as_domain() -> bool {
+        false
+    }
+
+    fn post_initialize(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
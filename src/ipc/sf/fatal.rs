@@ -0,0 +1,8 @@
+use crate::result::ResultCode;
+use super::*;
+
+ipc_sf_define_interface!(IService {
+    throw_fatal: cmif 0 => (rc: ResultCode, process_id: sf::ProcessId) => (),
+    throw_fatal_with_policy: cmif 1 => (rc: ResultCode, policy: u32, process_id: sf::ProcessId) => (),
+    throw_fatal_with_cpu_context: cmif 2 => (rc: ResultCode, policy: u32, process_id: sf::ProcessId, ctx_buf: sf::InAutoSelectBuffer) => ()
+});
@@ -0,0 +1,54 @@
+use crate::fs::TimeStampRaw;
+use crate::util::{CString, Shared};
+use super::*;
+
+// Note: raw parameters are declared in the order they are laid out (by ascending alignment), and open modes/options are taken as raw values since they come straight from guests
+
+pub type Path = CString<0x301>;
+
+ipc_sf_define_interface!(IFile {
+    read: cmif 0 => (out_buf: sf::OutNonSecureMapAliasBuffer, option: u32, offset: i64, size: i64) => (read_size: i64),
+    write: cmif 1 => (buf: sf::InNonSecureMapAliasBuffer, option: u32, offset: i64, size: i64) => (),
+    flush: cmif 2 => () => (),
+    set_size: cmif 3 => (size: i64) => (),
+    get_size: cmif 4 => () => (size: i64)
+});
+
+ipc_sf_define_interface!(IDirectory {
+    read: cmif 0 => (out_entries: sf::OutMapAliasBuffer) => (count: i64),
+    get_entry_count: cmif 1 => () => (count: i64)
+});
+
+ipc_sf_define_interface!(IFileSystem {
+    create_file: cmif 0 => (path: sf::InFixedPointerBuffer<Path>, option: u32, size: i64) => (),
+    delete_file: cmif 1 => (path: sf::InFixedPointerBuffer<Path>) => (),
+    create_directory: cmif 2 => (path: sf::InFixedPointerBuffer<Path>) => (),
+    delete_directory: cmif 3 => (path: sf::InFixedPointerBuffer<Path>) => (),
+    delete_directory_recursively: cmif 4 => (path: sf::InFixedPointerBuffer<Path>) => (),
+    rename_file: cmif 5 => (old_path: sf::InFixedPointerBuffer<Path>, new_path: sf::InFixedPointerBuffer<Path>) => (),
+    rename_directory: cmif 6 => (old_path: sf::InFixedPointerBuffer<Path>, new_path: sf::InFixedPointerBuffer<Path>) => (),
+    get_entry_type: cmif 7 => (path: sf::InFixedPointerBuffer<Path>) => (entry_type: u32),
+    open_file: cmif 8 => (path: sf::InFixedPointerBuffer<Path>, open_mode: u32) => (file: Shared<dyn sf::IObject>),
+    open_directory: cmif 9 => (path: sf::InFixedPointerBuffer<Path>, open_mode: u32) => (dir: Shared<dyn sf::IObject>),
+    commit: cmif 10 => () => (),
+    get_free_space_size: cmif 11 => (path: sf::InFixedPointerBuffer<Path>) => (size: i64),
+    get_total_space_size: cmif 12 => (path: sf::InFixedPointerBuffer<Path>) => (size: i64),
+    clean_directory_recursively: cmif 13 [(3, 0, 0) => _] => (path: sf::InFixedPointerBuffer<Path>) => (),
+    get_file_time_stamp_raw: cmif 14 [(3, 0, 0) => _] => (path: sf::InFixedPointerBuffer<Path>) => (time_stamp: TimeStampRaw)
+});
+
+ipc_sf_define_interface!(IStorage {
+    read: cmif 0 => (out_buf: sf::OutNonSecureMapAliasBuffer, offset: i64, size: i64) => (),
+    write: cmif 1 => (buf: sf::InNonSecureMapAliasBuffer, offset: i64, size: i64) => (),
+    flush: cmif 2 => () => (),
+    set_size: cmif 3 => (size: i64) => (),
+    get_size: cmif 4 => () => (size: i64)
+});
+
+ipc_sf_define_interface!(IFileSystemProxy {
+    set_current_process: cmif 1 => (process_id: sf::ProcessId) => (),
+    open_bis_file_system: cmif 11 => (root_path: sf::InFixedPointerBuffer<Path>, partition_id: u32) => (bis_fs: Shared<dyn sf::IObject>),
+    open_bis_storage: cmif 12 => (partition_id: u32) => (bis_storage: Shared<dyn sf::IObject>),
+    open_sd_card_file_system: cmif 18 => () => (sd_fs: Shared<dyn sf::IObject>),
+    get_global_access_log_mode: cmif 1005 => () => (mode: u32)
+});
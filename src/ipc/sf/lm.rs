@@ -0,0 +1,11 @@
+use crate::util::Shared;
+use super::*;
+
+ipc_sf_define_interface!(ILogger {
+    log: cmif 0 => (log_buf: sf::InAutoSelectBuffer) => (),
+    set_destination: cmif 1 [(3, 0, 0) => _] => (destination: u32) => ()
+});
+
+ipc_sf_define_interface!(ILogService {
+    open_logger: cmif 0 => (process_id: sf::ProcessId) => (logger: Shared<dyn sf::IObject>)
+});
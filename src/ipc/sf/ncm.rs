@@ -0,0 +1,54 @@
+use crate::ncm::*;
+use crate::util::Shared;
+use super::*;
+
+// Note: raw parameters are declared in the order they are laid out (by ascending alignment), and storage IDs/types are taken as raw values since they come straight from guests
+
+ipc_sf_define_interface!(IContentStorage {
+    has: cmif 7 => (content_id: ContentId) => (has: bool),
+    get_path: cmif 8 => (out_path: sf::OutFixedPointerBuffer<ContentPath>, content_id: ContentId) => (),
+    get_content_count: cmif 12 => () => (count: i32),
+    list_content_id: cmif 13 => (out_ids: sf::OutMapAliasBuffer, offset: i32) => (count: i32),
+    get_size_from_content_id: cmif 14 => (content_id: ContentId) => (size: i64),
+    disable_forcibly: cmif 15 => () => (),
+    read_content_id_file: cmif 18 => (out_buf: sf::OutMapAliasBuffer, content_id: ContentId, offset: i64) => (),
+    get_free_space_size: cmif 22 => () => (size: i64),
+    get_total_space_size: cmif 23 => () => (size: i64),
+    flush_place_holder: cmif 24 [(3, 0, 0) => _] => () => ()
+});
+
+ipc_sf_define_interface!(IContentMetaDatabase {
+    get: cmif 1 => (out_buf: sf::OutMapAliasBuffer, key: ContentMetaKey) => (size: u64),
+    get_content_id_by_type: cmif 3 => (cnt_type: u8, key: ContentMetaKey) => (content_id: ContentId),
+    list_content_info: cmif 4 => (out_infos: sf::OutMapAliasBuffer, offset: i32, key: ContentMetaKey) => (count: i32),
+    list: cmif 5 => (out_keys: sf::OutMapAliasBuffer, cnt_meta_type: u8, install_type: u8, application_id: ProgramId, min_id: ProgramId, max_id: ProgramId) => (total_count: i32, count: i32),
+    get_latest_content_meta_key: cmif 6 => (program_id: ProgramId) => (key: ContentMetaKey),
+    list_application: cmif 7 => (out_keys: sf::OutMapAliasBuffer, cnt_meta_type: u8) => (total_count: i32, count: i32),
+    has: cmif 8 => (key: ContentMetaKey) => (has: bool),
+    has_all: cmif 9 => (keys: sf::InMapAliasBuffer) => (has: bool),
+    get_size: cmif 10 => (key: ContentMetaKey) => (size: u64),
+    get_required_system_version: cmif 11 => (key: ContentMetaKey) => (version: u32),
+    get_patch_id: cmif 12 => (key: ContentMetaKey) => (patch_id: ProgramId),
+    disable_forcibly: cmif 13 => () => (),
+    commit: cmif 15 => () => (),
+    has_content: cmif 16 => (content_id: ContentId, key: ContentMetaKey) => (has: bool),
+    list_content_meta_info: cmif 17 => (out_infos: sf::OutMapAliasBuffer, offset: i32, key: ContentMetaKey) => (count: i32),
+    get_attributes: cmif 18 => (key: ContentMetaKey) => (attributes: ContentMetaAttribute),
+    get_required_application_version: cmif 19 [(2, 0, 0) => _] => (key: ContentMetaKey) => (version: u32),
+    get_content_id_by_type_and_id_offset: cmif 20 [(5, 0, 0) => _] => (cnt_type: u8, id_offset: u8, key: ContentMetaKey) => (content_id: ContentId)
+});
+
+ipc_sf_define_interface!(IContentManager {
+    create_content_storage: cmif 0 => (storage_id: u8) => (),
+    create_content_meta_database: cmif 1 => (storage_id: u8) => (),
+    verify_content_storage: cmif 2 => (storage_id: u8) => (),
+    verify_content_meta_database: cmif 3 => (storage_id: u8) => (),
+    open_content_storage: cmif 4 => (storage_id: u8) => (storage: Shared<dyn sf::IObject>),
+    open_content_meta_database: cmif 5 => (storage_id: u8) => (db: Shared<dyn sf::IObject>),
+    cleanup_content_meta_database: cmif 8 => (storage_id: u8) => (),
+    activate_content_storage: cmif 9 [(2, 0, 0) => _] => (storage_id: u8) => (),
+    inactivate_content_storage: cmif 10 [(2, 0, 0) => _] => (storage_id: u8) => (),
+    activate_content_meta_database: cmif 11 [(2, 0, 0) => _] => (storage_id: u8) => (),
+    inactivate_content_meta_database: cmif 12 [(2, 0, 0) => _] => (storage_id: u8) => (),
+    invalidate_rights_id_cache: cmif 13 [(9, 0, 0) => _] => () => ()
+});
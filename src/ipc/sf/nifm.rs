@@ -0,0 +1,26 @@
+use crate::nifm::*;
+use crate::util::Shared;
+use super::*;
+
+ipc_sf_define_interface!(IRequest {
+    get_request_state: cmif 0 => () => (state: RequestState),
+    get_result: cmif 1 => () => (),
+    get_system_event_readable_handles: cmif 2 => () => (state_event_handle: sf::CopyHandle, event_handle: sf::CopyHandle),
+    cancel: cmif 3 => () => (),
+    submit: cmif 4 => () => (),
+    set_requirement_preset: cmif 6 => (preset: u32) => (),
+    set_connection_confirmation_option: cmif 11 => (option: i8) => ()
+});
+
+ipc_sf_define_interface!(IGeneralService {
+    get_client_id: cmif 1 => (out_client_id: sf::OutFixedPointerBuffer<ClientId>) => (),
+    create_request: cmif 4 => (requirement_preset: u32) => (request: Shared<dyn sf::IObject>),
+    get_current_ip_address: cmif 12 => () => (ip_address: u32),
+    get_internet_connection_status: cmif 18 => () => (status: InternetConnectionStatus),
+    is_any_internet_request_accepted: cmif 21 => (client_id: sf::InFixedPointerBuffer<ClientId>) => (accepted: bool)
+});
+
+ipc_sf_define_interface!(IStaticService {
+    create_general_service_old: cmif 4 => () => (general_service: Shared<dyn sf::IObject>),
+    create_general_service: cmif 5 [(3, 0, 0) => _] => (process_id: sf::ProcessId) => (general_service: Shared<dyn sf::IObject>)
+});
@@ -0,0 +1,21 @@
+use crate::ncm::ProgramId;
+use crate::util::Shared;
+use super::*;
+
+ipc_sf_define_interface!(IApplicationManagerInterface {
+    list_application_record: cmif 0 => (out_records: sf::OutMapAliasBuffer, entry_offset: i32) => (count: i32),
+    get_application_control_data: cmif 400 => (out_data: sf::OutMapAliasBuffer, source: u8, application_id: ProgramId) => (size: u32)
+});
+
+ipc_sf_define_interface!(IReadOnlyApplicationControlDataInterface {
+    get_application_control_data: cmif 0 => (out_data: sf::OutMapAliasBuffer, source: u8, application_id: ProgramId) => (size: u32)
+});
+
+ipc_sf_define_interface!(IServiceGetterInterface {
+    get_read_only_application_control_data_interface: cmif 7989 [(5, 1, 0) => _] => () => (interface: Shared<dyn sf::IObject>),
+    get_application_manager_interface: cmif 7996 [(4, 0, 0) => _] => () => (interface: Shared<dyn sf::IObject>)
+});
+
+ipc_sf_define_interface!(ISystemUpdateInterface {
+    get_background_network_update_state: cmif 0 => () => (state: u8)
+});
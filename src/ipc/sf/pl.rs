@@ -0,0 +1,11 @@
+use crate::set::LanguageCode;
+use super::*;
+
+ipc_sf_define_interface!(IPlatformServiceManager {
+    request_load: cmif 0 => (font_type: u32) => (),
+    get_load_state: cmif 1 => (font_type: u32) => (load_state: u32),
+    get_size: cmif 2 => (font_type: u32) => (size: u32),
+    get_shared_memory_address_offset: cmif 3 => (font_type: u32) => (offset: u32),
+    get_shared_memory_native_handle: cmif 4 => () => (shmem_handle: sf::CopyHandle),
+    get_shared_font_in_order_of_priority: cmif 5 => (out_types: sf::OutMapAliasBuffer, out_offsets: sf::OutMapAliasBuffer, out_sizes: sf::OutMapAliasBuffer, language_code: LanguageCode) => (fonts_loaded: bool, count: u32)
+});
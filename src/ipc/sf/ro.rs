@@ -0,0 +1,9 @@
+use super::*;
+
+pub trait IRoInterface {
+    ipc_cmif_interface_define_command!(map_manual_load_module_memory: (process_id: sf::ProcessId, nro_address: u64, nro_size: u64, bss_address: u64, bss_size: u64) => (out_address: u64));
+    ipc_cmif_interface_define_command!(unmap_manual_load_module_memory: (process_id: sf::ProcessId, nro_address: u64) => ());
+    ipc_cmif_interface_define_command!(register_module_info: (process_id: sf::ProcessId, nrr_address: u64, nrr_size: u64) => ());
+    ipc_cmif_interface_define_command!(unregister_module_info: (process_id: sf::ProcessId, nrr_address: u64) => ());
+    ipc_cmif_interface_define_command!(register_process_handle: (process_id: sf::ProcessId, process_handle: sf::CopyHandle) => ());
+}
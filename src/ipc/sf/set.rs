@@ -1,7 +1,13 @@
 use crate::set::*;
 use super::*;
 
-pub trait ISystemSettingsServer {
-    ipc_cmif_interface_define_command!(get_firmware_version: (out_version: sf::OutFixedPointerBuffer<FirmwareVersion>) => ());
-    ipc_cmif_interface_define_command!(get_firmware_version_2: (out_version: sf::OutFixedPointerBuffer<FirmwareVersion>) => ());
-}
\ No newline at end of file
+ipc_sf_define_interface!(ISettingsServer {
+    get_language_code: cmif 0 => () => (language_code: LanguageCode),
+    get_region_code: cmif 4 => () => (region_code: RegionCode)
+}, client SettingsServer);
+
+ipc_sf_define_interface!(ISystemSettingsServer {
+    get_firmware_version: cmif 3 => (out_version: sf::OutFixedPointerBuffer<FirmwareVersion>) => (),
+    get_firmware_version_2: cmif 4 [(3, 0, 0) => _] => (out_version: sf::OutFixedPointerBuffer<FirmwareVersion>) => (),
+    get_device_nick_name: cmif 77 => (out_name: sf::OutAutoSelectBuffer) => ()
+}, client SystemSettingsServer);
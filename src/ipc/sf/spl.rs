@@ -0,0 +1,35 @@
+use crate::spl::*;
+use super::*;
+
+// Note: before 4.0.0 every command was served by "spl:", afterwards the crypto ones were split into per-client services like "spl:fs"
+ipc_sf_define_interface!(IGeneralInterface {
+    get_config: cmif 0 => (item: u32) => (value: u64),
+    generate_aes_kek: cmif 2 [_ => (3, 0, 2)] => (kek_source: KeySource, generation: u32, option: u32) => (access_key: AccessKey),
+    load_aes_key: cmif 3 [_ => (3, 0, 2)] => (keyslot: u32, access_key: AccessKey, key_source: KeySource) => (),
+    generate_aes_key: cmif 4 [_ => (3, 0, 2)] => (access_key: AccessKey, key_source: KeySource) => (key: AesKey),
+    set_config: cmif 5 => (item: u32, value: u64) => (),
+    generate_random_bytes: cmif 7 => (out_buf: sf::OutPointerBuffer) => (),
+    is_development: cmif 11 => () => (is_development: bool),
+    decrypt_aes_key: cmif 14 [_ => (3, 0, 2)] => (key_source: KeySource, generation: u32, option: u32) => (key: AesKey),
+    compute_ctr: cmif 15 [_ => (3, 0, 2)] => (out_buf: sf::OutNonSecureMapAliasBuffer, keyslot: u32, in_buf: sf::InNonSecureMapAliasBuffer, iv_ctr: IvCtr) => (),
+    allocate_aes_keyslot: cmif 21 [(2, 0, 0) => (3, 0, 2)] => () => (keyslot: u32),
+    deallocate_aes_keyslot: cmif 22 [(2, 0, 0) => (3, 0, 2)] => (keyslot: u32) => (),
+    set_boot_reason: cmif 24 [(3, 0, 0) => _] => (boot_reason: BootReasonValue) => (),
+    get_boot_reason: cmif 25 [(3, 0, 0) => _] => () => (boot_reason: BootReasonValue)
+}, client GeneralInterface);
+
+ipc_sf_define_interface!(IFsInterface {
+    get_config: cmif 0 => (item: u32) => (value: u64),
+    generate_aes_kek: cmif 2 => (kek_source: KeySource, generation: u32, option: u32) => (access_key: AccessKey),
+    load_aes_key: cmif 3 => (keyslot: u32, access_key: AccessKey, key_source: KeySource) => (),
+    generate_aes_key: cmif 4 => (access_key: AccessKey, key_source: KeySource) => (key: AesKey),
+    set_config: cmif 5 => (item: u32, value: u64) => (),
+    generate_random_bytes: cmif 7 => (out_buf: sf::OutPointerBuffer) => (),
+    is_development: cmif 11 => () => (is_development: bool),
+    decrypt_aes_key: cmif 14 => (key_source: KeySource, generation: u32, option: u32) => (key: AesKey),
+    compute_ctr: cmif 15 => (out_buf: sf::OutNonSecureMapAliasBuffer, keyslot: u32, in_buf: sf::InNonSecureMapAliasBuffer, iv_ctr: IvCtr) => (),
+    allocate_aes_keyslot: cmif 21 => () => (keyslot: u32),
+    deallocate_aes_keyslot: cmif 22 => (keyslot: u32) => (),
+    set_boot_reason: cmif 24 => (boot_reason: BootReasonValue) => (),
+    get_boot_reason: cmif 25 => () => (boot_reason: BootReasonValue)
+}, client FsInterface);
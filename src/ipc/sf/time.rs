@@ -0,0 +1,27 @@
+use crate::time::*;
+use crate::util::Shared;
+use super::*;
+
+ipc_sf_define_interface!(ISystemClock {
+    get_current_time: cmif 0 => () => (posix_time: i64)
+});
+
+ipc_sf_define_interface!(ISteadyClock {
+    get_current_time_point: cmif 0 => () => (time_point: SteadyClockTimePoint)
+});
+
+ipc_sf_define_interface!(ITimeZoneService {
+    get_device_location_name: cmif 0 => () => (location_name: LocationName),
+    set_device_location_name: cmif 1 => (location_name: LocationName) => (),
+    load_time_zone_rule: cmif 4 => (location_name: LocationName, out_rule: sf::OutMapAliasBuffer) => (),
+    to_calendar_time: cmif 100 => (posix_time: i64, rule: sf::InMapAliasBuffer) => (calendar_time: CalendarTime, additional_info: CalendarAdditionalInfo),
+    to_calendar_time_with_my_rule: cmif 101 => (posix_time: i64) => (calendar_time: CalendarTime, additional_info: CalendarAdditionalInfo)
+});
+
+ipc_sf_define_interface!(IStaticService {
+    get_standard_user_system_clock: cmif 0 => () => (clock: Shared<dyn sf::IObject>),
+    get_standard_network_system_clock: cmif 1 => () => (clock: Shared<dyn sf::IObject>),
+    get_standard_steady_clock: cmif 2 => () => (clock: Shared<dyn sf::IObject>),
+    get_time_zone_service: cmif 3 => () => (time_zone_service: Shared<dyn sf::IObject>),
+    get_standard_local_system_clock: cmif 4 => () => (clock: Shared<dyn sf::IObject>)
+});
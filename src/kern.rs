@@ -1,4 +1,5 @@
-use std::collections::BTreeMap;
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::{BTreeMap, BinaryHeap};
 use std::sync::atomic::{AtomicI32, Ordering};
 use std::time::{Duration, Instant};
 use parking_lot::Mutex;
@@ -25,6 +26,12 @@ pub mod ipc;
 
 pub mod svc;
 
+pub mod debug;
+
+pub mod event;
+
+pub mod shmem;
+
 pub mod result;
 
 pub trait KAutoObject: Send + Sync {
@@ -178,9 +185,8 @@ pub fn wait_for_sync_objects(objs: &mut [Shared<dyn KSynchronizationObject>], ti
         return result::ResultCancelled::make_err();
     }
     else {
-        let mut thread_idxs: Vec<usize> = Vec::new();
         for obj in objs.iter_mut() {
-            thread_idxs.push(obj.get().add_waiting_thread(cur_thread.clone()));
+            obj.get().add_waiting_thread(cur_thread.clone());
         }
 
         cur_thread.get().waiting_sync = true;
@@ -190,7 +196,7 @@ pub fn wait_for_sync_objects(objs: &mut [Shared<dyn KSynchronizationObject>], ti
         KThread::reschedule(&mut cur_thread, ThreadState::Waiting);
 
         if timeout > 0 {
-            todo!("ScheduleFutureInvocation");
+            get_time_manager().schedule_future_invocation(cur_thread.clone(), Duration::from_nanos(timeout as u64));
         }
 
         get_critical_section().leave();
@@ -198,21 +204,22 @@ pub fn wait_for_sync_objects(objs: &mut [Shared<dyn KSynchronizationObject>], ti
         cur_thread.get().waiting_sync = false;
 
         if timeout > 0 {
-            todo!("UnscheduleFutureInvocation");
+            get_time_manager().unschedule_future_invocation(cur_thread.clone());
         }
 
         get_critical_section().enter();
 
+        // Note: signaling doesn't remove waiting threads, thus the thread is still in the waiting list of every object (and the indices returned when adding it may have changed meanwhile)
+        for obj in objs.iter_mut() {
+            obj.get().get_waiting_threads().retain(|thread| !thread.ptr_eq(&cur_thread));
+        }
+
         cur_thread.get().sync_result.to(0)?;
 
-        if let Some(signaled_obj) = cur_thread.get().signaled_obj.as_ref() {
+        let signaled_obj = cur_thread.get().signaled_obj.clone();
+        if let Some(signaled_obj) = signaled_obj {
             for i in 0..objs.len() {
-                let obj = &mut objs[i];
-                let index = thread_idxs[i];
-
-                obj.get().remove_waiting_thread(index);
-     
-                if obj.ptr_eq(signaled_obj) {
+                if objs[i].ptr_eq(&signaled_obj) {
                     return Ok(i);
                 }
             }
@@ -250,16 +257,57 @@ pub fn initialize_time_manager() -> Result<()> {
 // KFutureSchedulerObject
 
 pub trait KFutureSchedulerObject: KAutoObject {
-    fn time_up(&mut self);
+    // Note: the object isn't locked when this is invoked, since waking it up (like rescheduling a thread) needs to lock it
+    fn time_up(obj: &mut Shared<Self>) where Self: Sized;
+
+    fn as_thread(&self) -> Option<&KThread> {
+        None
+    }
 }
 
 // ---
 
 // KTimeManager
 
+struct FutureInvocation {
+    deadline: Instant,
+    // Keeps invocations with the same deadline in the order they were scheduled
+    sequence: u64,
+    obj: Shared<dyn KFutureSchedulerObject>,
+    time_up_fn: Box<dyn FnOnce()>
+}
+
+impl PartialEq for FutureInvocation {
+    fn eq(&self, other: &Self) -> bool {
+        (self.deadline == other.deadline) && (self.sequence == other.sequence)
+    }
+}
+
+impl Eq for FutureInvocation {}
+
+impl PartialOrd for FutureInvocation {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for FutureInvocation {
+    // Note: reversed, since BinaryHeap is a max-heap and we want the earliest deadline on top
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        other.deadline.cmp(&self.deadline).then_with(|| other.sequence.cmp(&self.sequence))
+    }
+}
+
+struct FutureInvocationQueue {
+    invocations: BinaryHeap<FutureInvocation>,
+    next_sequence: u64
+}
+
+// Note: the invocation queue has its own lock, thus scheduling invocations and waiting for the next deadline don't contend for the critical section
+// Still, invocations are popped and invoked inside the critical section, and unscheduling also enters it, so that an invocation can't fire after being unscheduled
 pub struct KTimeManager {
     wait_event: AutoResetEvent,
-    waiting_objs: Vec<(Shared<dyn KFutureSchedulerObject>, Instant)>,
+    queue: Mutex<FutureInvocationQueue>,
     work_thread: Shared<KThread>
 }
 
@@ -269,7 +317,10 @@ impl KTimeManager {
 
         Ok(Self {
             wait_event: AutoResetEvent::new(State::Unset),
-            waiting_objs: Vec::new(),
+            queue: Mutex::new(FutureInvocationQueue {
+                invocations: BinaryHeap::new(),
+                next_sequence: 0
+            }),
             work_thread: work_thread
         })
     }
@@ -279,34 +330,41 @@ impl KTimeManager {
 
         let time_manager = get_time_manager();
         loop {
-            let next = {
-                let _guard = make_critical_section_guard();
-
-                time_manager.waiting_objs.sort_by(|(_, a), (_, b)| a.cmp(b));
-                time_manager.waiting_objs.first()
+            let next_deadline = time_manager.queue.lock().invocations.peek().map(|invocation| invocation.deadline);
+
+            match next_deadline {
+                Some(deadline) => {
+                    let cur_instant = Instant::now();
+                    if deadline > cur_instant {
+                        // The wait is interrupted if an earlier invocation gets scheduled meanwhile
+                        time_manager.wait_event.wait_for(deadline.duration_since(cur_instant));
+                        continue;
+                    }
+                },
+                None => {
+                    time_manager.wait_event.wait();
+                    continue;
+                }
             };
 
-            if let Some((next_obj, next_instant)) = next {
-                let cur_instant = Instant::now();
-                if *next_instant > cur_instant {
-                    time_manager.wait_event.wait_for(next_instant.duration_since(cur_instant));
-                }
-                
-                if Instant::now() >= *next_instant {
-                    let _guard = make_critical_section_guard();
-
-                    for i in 0..time_manager.waiting_objs.len() {
-                        let (obj, _) = &time_manager.waiting_objs[i];
-                        if next_obj.ptr_eq(obj) {
-                            let (r_obj, _) = time_manager.waiting_objs.remove(i);
-                            r_obj.get().time_up();
-                            break;
-                        }
+            // Note: invocations happen inside the critical section, so that they can't race with them being unscheduled
+            let _guard = make_critical_section_guard();
+
+            let cur_instant = Instant::now();
+            loop {
+                // The queue must not be locked while invoking, since waking things up may schedule invocations again
+                let invocation = {
+                    let mut queue = time_manager.queue.lock();
+                    match queue.invocations.peek() {
+                        Some(invocation) if invocation.deadline <= cur_instant => queue.invocations.pop(),
+                        _ => None
                     }
-                }
-            }
-            else {
-                time_manager.wait_event.wait();
+                };
+
+                match invocation {
+                    Some(invocation) => (invocation.time_up_fn)(),
+                    None => break
+                };
             }
         }
     }
@@ -315,19 +373,89 @@ impl KTimeManager {
         KThread::start_host(&mut self.work_thread, Self::work_thread_fn)
     }
 
-    pub fn schedule_future_invocation(&mut self, _obj: Shared<dyn KFutureSchedulerObject>, _timeout: Duration) {
-        todo!("schedule_future_invocation");
+    pub fn schedule_future_invocation<T: KFutureSchedulerObject + 'static>(&mut self, obj: Shared<T>, timeout: Duration) {
+        // Timeouts too big to be represented are just infinite ones
+        let deadline = match Instant::now().checked_add(timeout) {
+            Some(deadline) => deadline,
+            None => return
+        };
+
+        let mut queue = self.queue.lock();
+        let is_earliest = match queue.invocations.peek() {
+            Some(next_invocation) => deadline < next_invocation.deadline,
+            None => true
+        };
+
+        let mut time_up_obj = obj.clone();
+        let sequence = queue.next_sequence;
+        queue.invocations.push(FutureInvocation {
+            deadline: deadline,
+            sequence: sequence,
+            obj: obj,
+            time_up_fn: Box::new(move || T::time_up(&mut time_up_obj))
+        });
+        queue.next_sequence += 1;
+
+        // The work thread needs to wait for a closer deadline now
+        if is_earliest {
+            self.wait_event.set();
+        }
+    }
+
+    // Finds the first thread of the given process which is scheduled to wake up before the given deadline, returning its context and ID
+    pub fn find_future_thread_info(&mut self, process_id: u64, deadline: Instant) -> Option<(svc::LastThreadContext, u64)> {
+        let queue = self.queue.lock();
+
+        let mut invocations: Vec<&FutureInvocation> = queue.invocations.iter().filter(|invocation| invocation.deadline <= deadline).collect();
+        invocations.sort_by_key(|invocation| (invocation.deadline, invocation.sequence));
+        for invocation in invocations.iter() {
+            let obj_ref = invocation.obj.get();
+            if let Some(thread) = obj_ref.as_thread() {
+                let is_owned_by_process = match thread.owner_process.as_ref() {
+                    Some(owner_process) => owner_process.get().id == process_id,
+                    None => false
+                };
+                if is_owned_by_process {
+                    if let Ok(context) = thread.get_last_thread_context() {
+                        return Some((context, thread.id));
+                    }
+                }
+            }
+        }
+
+        None
     }
 
     pub fn unschedule_future_invocation(&mut self, obj: Shared<dyn KFutureSchedulerObject>) {
         let _guard = make_critical_section_guard();
 
-        self.waiting_objs.retain(|(wait_obj, _)| !obj.ptr_eq(wait_obj));
+        // Note: the earliest deadline may be gone, but the work thread just wakes up for nothing then
+        let mut queue = self.queue.lock();
+        let invocations = std::mem::take(&mut queue.invocations);
+        queue.invocations = invocations.into_iter().filter(|invocation| !obj.ptr_eq(&invocation.obj)).collect();
     }
 }
 
 // ---
 
+// System tick: the console's 19.2MHz system counter, emulated as running since the kernel was initialized
+// Note: it follows the host's clock, thus it isn't affected by the emulation speed (see emu::speed)
+
+pub const SYSTEM_TICK_FREQUENCY: u64 = 19200000;
+
+static mut G_BOOT_INSTANT: Option<Instant> = None;
+
+pub fn convert_to_ticks(duration: Duration) -> u64 {
+    ((duration.as_nanos() * SYSTEM_TICK_FREQUENCY as u128) / 1_000_000_000) as u64
+}
+
+pub fn get_system_tick() -> u64 {
+    let boot_instant = unsafe { G_BOOT_INSTANT.unwrap_or_else(Instant::now) };
+    convert_to_ticks(boot_instant.elapsed())
+}
+
+// ---
+
 // KResourceLimit
 
 pub const LIMITABLE_RESOURCE_COUNT: usize = 5;
@@ -338,8 +466,7 @@ pub struct KResourceLimit {
     current_values: [u64; LIMITABLE_RESOURCE_COUNT],
     current_hints: [u64; LIMITABLE_RESOURCE_COUNT],
     peak_values: [u64; LIMITABLE_RESOURCE_COUNT],
-    waiting_threads: Vec<Shared<KThread>>,
-    waiting_thread_count: usize
+    waiting_threads: KConditionVariable
 }
 
 impl KAutoObject for KResourceLimit {
@@ -358,35 +485,38 @@ impl KResourceLimit {
             current_values: [0; LIMITABLE_RESOURCE_COUNT],
             current_hints: [0; LIMITABLE_RESOURCE_COUNT],
             peak_values: [0; LIMITABLE_RESOURCE_COUNT],
-            waiting_threads: Vec::new(),
-            waiting_thread_count: 0
+            waiting_threads: KConditionVariable::new()
         })
     }
 
-    pub fn reserve(&mut self, kind: LimitableResource, value: u64, custom_timeout: Option<Duration>) -> Result<()> {
+    // Note: the resource limit is unlocked while waiting for other threads to release the resource, thus it must not be locked by the caller
+    pub fn reserve(resource_limit: &Shared<Self>, kind: LimitableResource, value: u64, custom_timeout: Option<Duration>) -> Result<()> {
         let timeout = custom_timeout.unwrap_or(Self::DEFAULT_TIMEOUT);
         let instant = Instant::now() + timeout;
         let idx = kind as usize;
-        result_return_unless!(self.current_hints[idx] < self.limit_values[idx], result::ResultInvalidState);
-        
-        let mut new_current_value = self.current_values[idx] + value;
-        while (new_current_value > self.limit_values[idx]) && ((self.current_hints[idx] + value) <= self.limit_values[idx]) {
-            self.waiting_thread_count += 1;
-            KConditionVariable::wait(&mut self.waiting_threads, timeout);
-            self.waiting_thread_count -= 1;
-
-            new_current_value = self.current_values[idx] + value;
 
-            if Instant::now() > instant {
+        let mut limit = resource_limit.get();
+        result_return_unless!(limit.current_hints[idx] < limit.limit_values[idx], result::ResultInvalidState);
+        
+        let mut new_current_value = limit.current_values[idx] + value;
+        while (new_current_value > limit.limit_values[idx]) && ((limit.current_hints[idx] + value) <= limit.limit_values[idx]) {
+            let cur_instant = Instant::now();
+            if cur_instant >= instant {
                 break;
             }
+
+            let waiting_threads = limit.waiting_threads.clone();
+            waiting_threads.wait(limit, Some(instant - cur_instant));
+            limit = resource_limit.get();
+
+            new_current_value = limit.current_values[idx] + value;
         }
 
-        result_return_unless!(new_current_value <= self.limit_values[idx], result::ResultLimitReached);
+        result_return_unless!(new_current_value <= limit.limit_values[idx], result::ResultLimitReached);
 
-        self.current_values[idx] += value;
-        self.peak_values[idx] = self.peak_values[idx].max(new_current_value);
-        self.current_hints[idx] += value;
+        limit.current_values[idx] += value;
+        limit.peak_values[idx] = limit.peak_values[idx].max(new_current_value);
+        limit.current_hints[idx] += value;
         Ok(())
     }
 
@@ -396,9 +526,7 @@ impl KResourceLimit {
         self.current_values[idx] -= value;
         self.current_hints[idx] -= hint;
 
-        if self.waiting_thread_count > 0 {
-            KConditionVariable::notify_all(&mut self.waiting_threads);
-        }
+        self.waiting_threads.notify_all();
     }
 
     pub fn get_remaining_value(&self, kind: LimitableResource) -> u64 {
@@ -419,6 +547,10 @@ impl KResourceLimit {
 // ---
 
 pub fn initialize() -> Result<()> {
+    unsafe {
+        G_BOOT_INSTANT = Some(Instant::now());
+    }
+
     initialize_schedulers()?;
     initialize_time_manager()?;
 
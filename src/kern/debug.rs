@@ -0,0 +1,279 @@
+use std::collections::VecDeque;
+use std::sync::atomic::AtomicI32;
+use crate::util::{self, Shared};
+use crate::result::*;
+use super::KAutoObject;
+use super::KSynchronizationObject;
+use super::proc::KProcess;
+use super::thread::{KThread, ThreadState};
+use super::result;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[repr(u32)]
+pub enum DebugEventType {
+    #[default]
+    CreateProcess = 0,
+    CreateThread = 1,
+    ExitProcess = 2,
+    ExitThread = 3,
+    Exception = 4
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum DebugExceptionType {
+    UndefinedInstruction = 0,
+    InstructionAbort = 1,
+    DataAbort = 2,
+    AlignmentFault = 3,
+    DebuggerAttached = 4,
+    BreakPoint = 5,
+    UserBreak = 6,
+    DebuggerBreak = 7,
+    UndefinedSystemCall = 8,
+    MemorySystemError = 9
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum ThreadExitReason {
+    ExitThread = 0,
+    TerminateThread = 1,
+    ExitProcess = 2,
+    TerminateProcess = 3
+}
+
+bit_enum! {
+    DebugEventFlag (u32) {
+        None = 0,
+        Stopped = bit!(0)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[repr(C)]
+pub struct DebugInfoCreateProcess {
+    pub program_id: u64,
+    pub process_id: u64,
+    pub name: [u8; 0xC],
+    pub flags: u32,
+    pub user_exception_context_address: u64
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[repr(C)]
+pub struct DebugInfoCreateThread {
+    pub thread_id: u64,
+    pub tls_address: u64,
+    pub entrypoint: u64
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[repr(C)]
+pub struct DebugInfoExitThread {
+    pub reason: u32
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[repr(C)]
+pub struct DebugInfoException {
+    pub exception_type: u32,
+    pub pad: u32,
+    pub address: u64,
+    pub specific: [u64; 4]
+}
+
+// Same layout as the one SVCs write into guest memory
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[repr(C)]
+pub struct DebugEventInfo {
+    pub event_type: DebugEventType,
+    pub flags: DebugEventFlag,
+    pub thread_id: u64,
+    pub info: [u8; 0x30]
+}
+
+impl DebugEventInfo {
+    fn new<T: Copy>(event_type: DebugEventType, flags: DebugEventFlag, thread_id: u64, info: T) -> Self {
+        let mut event = Self {
+            event_type: event_type,
+            flags: flags,
+            thread_id: thread_id,
+            info: [0; 0x30]
+        };
+        // All the info types fit in the info area, so this can't fail
+        util::slice_write_val(&mut event.info, None, info).unwrap();
+        event
+    }
+
+    pub fn create_process(process: &KProcess) -> Self {
+        let mut name: [u8; 0xC] = [0; 0xC];
+        let process_name = process.npdm.meta.name.get_string().unwrap_or_default();
+        let name_len = process_name.len().min(name.len());
+        name[..name_len].copy_from_slice(&process_name.as_bytes()[..name_len]);
+
+        Self::new(DebugEventType::CreateProcess, DebugEventFlag::None(), 0, DebugInfoCreateProcess {
+            program_id: process.npdm.aci0.program_id.0,
+            process_id: process.id,
+            name: name,
+            flags: 0,
+            user_exception_context_address: 0
+        })
+    }
+
+    pub fn create_thread(thread: &mut KThread) -> Self {
+        let entrypoint = match thread.cpu_exec_ctx.as_ref() {
+            Some(exec_ctx) => exec_ctx.exec_start_addr,
+            None => 0
+        };
+
+        Self::new(DebugEventType::CreateThread, DebugEventFlag::None(), thread.id, DebugInfoCreateThread {
+            thread_id: thread.id,
+            tls_address: thread.get_tls_address(),
+            entrypoint: entrypoint
+        })
+    }
+
+    pub fn exit_thread(thread_id: u64, reason: ThreadExitReason) -> Self {
+        Self::new(DebugEventType::ExitThread, DebugEventFlag::None(), thread_id, DebugInfoExitThread {
+            reason: reason as u32
+        })
+    }
+
+    pub fn exception(thread_id: u64, exception_type: DebugExceptionType, address: u64, specific: [u64; 4]) -> Self {
+        Self::new(DebugEventType::Exception, DebugEventFlag::Stopped(), thread_id, DebugInfoException {
+            exception_type: exception_type as u32,
+            pad: 0,
+            address: address,
+            specific: specific
+        })
+    }
+}
+
+// KDebug
+
+pub struct KDebug {
+    refcount: AtomicI32,
+    waiting_threads: Vec<Shared<KThread>>,
+    events: VecDeque<DebugEventInfo>,
+    pub process: Shared<KProcess>
+}
+
+impl KAutoObject for KDebug {
+    fn get_refcount(&mut self) -> &mut AtomicI32 {
+        &mut self.refcount
+    }
+}
+
+impl KSynchronizationObject for KDebug {
+    fn get_waiting_threads(&mut self) -> &mut Vec<Shared<KThread>> {
+        &mut self.waiting_threads
+    }
+
+    fn is_signaled(&self) -> bool {
+        !self.events.is_empty()
+    }
+}
+
+impl KDebug {
+    pub fn new(process: Shared<KProcess>) -> Shared<Self> {
+        Shared::new(Self {
+            refcount: AtomicI32::new(1),
+            waiting_threads: Vec::new(),
+            events: VecDeque::new(),
+            process: process
+        })
+    }
+
+    pub fn attach(process: &Shared<KProcess>) -> Result<Shared<Self>> {
+        result_return_if!(process.get().is_debugged(), result::ResultBusy);
+
+        let debug = Self::new(process.clone());
+        process.get().set_debug(&debug);
+
+        let create_process_event = DebugEventInfo::create_process(&process.get());
+        Self::push_event(&debug, create_process_event);
+        Ok(debug)
+    }
+
+    pub fn push_event(debug: &Shared<Self>, event: DebugEventInfo) {
+        debug.get().events.push_back(event);
+
+        let mut debug_clone = debug.clone();
+        KSynchronizationObject::signal(&mut debug_clone);
+    }
+
+    pub fn pop_event(&mut self) -> Result<DebugEventInfo> {
+        match self.events.pop_front() {
+            Some(event) => Ok(event),
+            None => result::ResultNoEvent::make_err()
+        }
+    }
+
+    // Break-all: every thread of the process stops running guest code (see ExecutionContext::pause) until the debugger continues it
+    pub fn break_process(debug: &Shared<Self>) {
+        let process = debug.get().process.clone();
+        let threads = process.get().get_threads();
+        for mut thread in threads {
+            let is_debug_suspended = thread.get().is_suspend_requested(ThreadState::DebugSuspended);
+            if !is_debug_suspended {
+                KThread::suspend(&mut thread, ThreadState::DebugSuspended);
+            }
+        }
+
+        Self::push_event(debug, DebugEventInfo::exception(0, DebugExceptionType::DebuggerBreak, 0, [0; 4]));
+    }
+
+    // Only the threads matching the filter are continued, which includes the ones created while debugging which didn't run yet (see KThread::new)
+    pub fn continue_process<F: Fn(u64) -> bool>(debug: &Shared<Self>, thread_filter: F) {
+        let process = debug.get().process.clone();
+        let threads = process.get().get_threads();
+        for mut thread in threads {
+            let (thread_id, is_debug_suspended, is_init_suspended) = {
+                let thread_guard = thread.get();
+                (thread_guard.id, thread_guard.is_suspend_requested(ThreadState::DebugSuspended), thread_guard.is_suspend_requested(ThreadState::InitSuspended))
+            };
+            if thread_filter(thread_id) {
+                if is_debug_suspended {
+                    KThread::resume(&mut thread, ThreadState::DebugSuspended);
+                }
+                if is_init_suspended {
+                    KThread::resume(&mut thread, ThreadState::InitSuspended);
+                }
+            }
+        }
+    }
+}
+
+// Closing the debug handle detaches the debugger, thus the threads it stopped get to run again
+impl Drop for KDebug {
+    fn drop(&mut self) {
+        let threads = self.process.get().get_threads();
+        for mut thread in threads {
+            let (is_debug_suspended, is_init_suspended) = {
+                let thread_guard = thread.get();
+                (thread_guard.is_suspend_requested(ThreadState::DebugSuspended), thread_guard.is_suspend_requested(ThreadState::InitSuspended))
+            };
+            if is_debug_suspended {
+                KThread::resume(&mut thread, ThreadState::DebugSuspended);
+            }
+            if is_init_suspended {
+                KThread::resume(&mut thread, ThreadState::InitSuspended);
+            }
+        }
+    }
+}
+
+// Queues the event if the process is currently being debugged, returning whether it was
+pub fn notify_debug_event(process: &Shared<KProcess>, event: DebugEventInfo) -> bool {
+    let debug = process.get().get_debug();
+    match debug {
+        Some(debug) => {
+            KDebug::push_event(&debug, event);
+            true
+        },
+        None => false
+    }
+}
+
+// ---
@@ -0,0 +1,123 @@
+use std::sync::atomic::AtomicI32;
+use crate::util::Shared;
+use crate::result::*;
+use super::KAutoObject;
+use super::KResourceLimit;
+use super::KSynchronizationObject;
+use super::svc;
+use super::thread::{KThread, make_critical_section_guard};
+use super::result;
+
+// KReadableEvent
+
+pub struct KReadableEvent {
+    refcount: AtomicI32,
+    waiting_threads: Vec<Shared<KThread>>,
+    is_signaled: bool,
+    // The event reserved by the process which created it (if any), released once both sides are gone
+    resource_limit: Option<Shared<KResourceLimit>>
+}
+
+impl KAutoObject for KReadableEvent {
+    fn get_refcount(&mut self) -> &mut AtomicI32 {
+        &mut self.refcount
+    }
+}
+
+impl KSynchronizationObject for KReadableEvent {
+    fn get_waiting_threads(&mut self) -> &mut Vec<Shared<KThread>> {
+        &mut self.waiting_threads
+    }
+
+    fn is_signaled(&self) -> bool {
+        self.is_signaled
+    }
+}
+
+impl Drop for KReadableEvent {
+    fn drop(&mut self) {
+        if let Some(resource_limit) = self.resource_limit.as_ref() {
+            resource_limit.get().release(svc::LimitableResource::Event, 1, 1);
+        }
+    }
+}
+
+impl KReadableEvent {
+    pub fn new(resource_limit: Option<Shared<KResourceLimit>>) -> Shared<Self> {
+        Shared::new(Self {
+            refcount: AtomicI32::new(1),
+            waiting_threads: Vec::new(),
+            is_signaled: false,
+            resource_limit: resource_limit
+        })
+    }
+
+    // Note: events may be signaled from host threads outside the emulated ones (like fs async workers), thus the signaled state is only ever accessed inside the critical section, like waiting threads do
+    pub fn signal(event: &Shared<Self>) {
+        let _guard = make_critical_section_guard();
+
+        if !event.get().is_signaled {
+            event.get().is_signaled = true;
+
+            let mut event_clone = event.clone();
+            KSynchronizationObject::signal(&mut event_clone);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.is_signaled = false;
+    }
+
+    // Same as clearing, but fails if the event wasn't signaled
+    pub fn reset(&mut self) -> Result<()> {
+        result_return_unless!(self.is_signaled, result::ResultInvalidState);
+
+        self.is_signaled = false;
+        Ok(())
+    }
+}
+
+// ---
+
+// KEvent
+
+// Note: this is the writable side of the event, the readable side (the one threads wait on) is its KReadableEvent
+pub struct KEvent {
+    refcount: AtomicI32,
+    pub readable_event: Shared<KReadableEvent>
+}
+
+impl KAutoObject for KEvent {
+    fn get_refcount(&mut self) -> &mut AtomicI32 {
+        &mut self.refcount
+    }
+}
+
+impl KEvent {
+    pub fn new() -> Shared<Self> {
+        Self::new_impl(None)
+    }
+
+    // Note: the caller must have reserved the event in the given resource limit, which the event takes over
+    pub fn new_reserved(resource_limit: Shared<KResourceLimit>) -> Shared<Self> {
+        Self::new_impl(Some(resource_limit))
+    }
+
+    fn new_impl(resource_limit: Option<Shared<KResourceLimit>>) -> Shared<Self> {
+        Shared::new(Self {
+            refcount: AtomicI32::new(1),
+            readable_event: KReadableEvent::new(resource_limit)
+        })
+    }
+
+    pub fn signal(&self) {
+        KReadableEvent::signal(&self.readable_event);
+    }
+
+    pub fn clear(&self) {
+        let _guard = make_critical_section_guard();
+        self.readable_event.get().clear();
+    }
+}
+
+// ---
@@ -1,8 +1,10 @@
-use std::sync::atomic::AtomicI32;
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+use std::time::Instant;
 use std::mem;
 use scopeguard::{guard, ScopeGuard};
 use super::KAutoObject;
 use super::KSynchronizationObject;
+use super::KResourceLimit;
 use super::proc::KProcess;
 use super::thread::KThread;
 use super::thread::ThreadState;
@@ -13,11 +15,17 @@ use crate::ipc::BufferDescriptor;
 use crate::ipc::CommandHeader;
 use crate::ipc::CommandSpecialHeader;
 use crate::ipc::SendStaticDescriptor;
+use crate::ipc::cmif;
+use crate::emu::host_profiler;
+use crate::emu::watchdog;
+use crate::emu::service_mock::{self, RecordedServiceCall, ServiceRequest};
 use crate::kern::svc::CURRENT_PROCESS_PSEUDO_HANDLE;
 use crate::kern::svc::CURRENT_THREAD_PSEUDO_HANDLE;
 use crate::kern::svc::Handle;
+use crate::os::{self, ThreadLocalRegion};
 use crate::util::Shared;
 use crate::util::SharedAny;
+use crate::util::WeakShared;
 use super::svc;
 use super::result;
 use crate::result::*;
@@ -32,11 +40,14 @@ pub enum ChannelState {
 
 // KPort
 
+// Note: both sides of the port keep the port alive, while the port only holds (weak) backlinks to them, thus everything gets dropped once both sides are closed
 pub struct KPort {
     refcount: AtomicI32,
-    pub server_port: Shared<KServerPort>,
-    pub client_port: Shared<KClientPort>,
+    server_port: WeakShared<KServerPort>,
+    client_port: WeakShared<KClientPort>,
     name_addr: u64,
+    // Only for diagnostics (like the name of the service the port belongs to), unrelated to named ports
+    name: Option<String>,
     pub is_light: bool
 }
 
@@ -47,43 +58,62 @@ impl KAutoObject for KPort {
 }
 
 impl KPort {
-    pub fn new(max_sessions: u32, is_light: bool, name_addr: u64) -> Shared<Self> {
-        let server_port = KServerPort::new(None, is_light);
-        let client_port = KClientPort::new(None, max_sessions);
-
+    // Returns both sides of the new port
+    pub fn new(max_sessions: u32, is_light: bool, name_addr: u64) -> (Shared<KServerPort>, Shared<KClientPort>) {
         let port = Shared::new(Self {
             refcount: AtomicI32::new(1),
-            server_port: server_port.clone(),
-            client_port: client_port.clone(),
+            server_port: WeakShared::new(),
+            client_port: WeakShared::new(),
             name_addr: name_addr,
+            name: None,
             is_light: is_light
         });
 
-        server_port.get().parent = Some(port.clone());
-        client_port.get().parent = Some(port.clone());
-        port
+        let server_port = KServerPort::new(port.clone(), is_light);
+        let client_port = KClientPort::new(port.clone(), max_sessions);
+
+        port.get().server_port = server_port.downgrade();
+        port.get().client_port = client_port.downgrade();
+        (server_port, client_port)
     }
 
-    pub fn ready_for_drop(&mut self) {
-        // Need to do this for the Shareds to actually drop
-        self.server_port.get().parent = None;
-        self.client_port.get().parent = None;
+    #[inline]
+    pub fn get_server_port(&self) -> Option<Shared<KServerPort>> {
+        self.server_port.upgrade()
     }
 
     #[inline]
-    pub fn enqueue_incoming_session(&mut self, session: Shared<KServerSession>) {
-        KServerPort::enqueue_incoming_session(&mut self.server_port, session)
+    pub fn get_client_port(&self) -> Option<Shared<KClientPort>> {
+        self.client_port.upgrade()
     }
 
     #[inline]
-    pub fn enqueue_incoming_light_session(&mut self, session: Shared<KLightServerSession>) {
-        KServerPort::enqueue_incoming_light_session(&mut self.server_port, session)
+    pub fn get_name(&self) -> Option<String> {
+        self.name.clone()
     }
-}
 
-impl Drop for KPort {
-    fn drop(&mut self) {
-        println!("Dropping KPort!");
+    pub fn set_name(&mut self, name: String) {
+        self.name = Some(name);
+    }
+
+    pub fn enqueue_incoming_session(&self, session: Shared<KServerSession>) -> Result<()> {
+        match self.get_server_port() {
+            Some(mut server_port) => {
+                KServerPort::enqueue_incoming_session(&mut server_port, session);
+                Ok(())
+            },
+            None => result::ResultPortClosed::make_err()
+        }
+    }
+
+    pub fn enqueue_incoming_light_session(&self, session: Shared<KLightServerSession>) -> Result<()> {
+        match self.get_server_port() {
+            Some(mut server_port) => {
+                KServerPort::enqueue_incoming_light_session(&mut server_port, session);
+                Ok(())
+            },
+            None => result::ResultPortClosed::make_err()
+        }
     }
 }
 
@@ -94,7 +124,7 @@ impl Drop for KPort {
 pub struct KServerPort {
     refcount: AtomicI32,
     waiting_threads: Vec<Shared<KThread>>,
-    pub parent: Option<Shared<KPort>>,
+    pub parent: Shared<KPort>,
     pub is_light: bool,
     incoming_connections: Vec<Shared<KServerSession>>,
     incoming_light_connections: Vec<Shared<KLightServerSession>>
@@ -120,7 +150,7 @@ impl KSynchronizationObject for KServerPort {
 }
 
 impl KServerPort {
-    pub fn new(parent: Option<Shared<KPort>>, is_light: bool) -> Shared<Self> {
+    pub fn new(parent: Shared<KPort>, is_light: bool) -> Shared<Self> {
         Shared::new(Self {
             refcount: AtomicI32::new(1),
             waiting_threads: Vec::new(),
@@ -154,8 +184,7 @@ impl KServerPort {
     }
 
     pub fn accept_incoming_connection(&mut self) -> Option<Shared<KServerSession>> {
-        let _guard = make_critical_section_guard();
-
+        // Note: only the port itself is accessed (and it's already locked here), and taking sessions out can't make any waiter miss a signal, thus there's no need for the critical section
         let session = match self.incoming_connections.first() {
             Some(session_ref) => Some(session_ref.clone()),
             None => None
@@ -169,8 +198,6 @@ impl KServerPort {
     }
 
     pub fn accept_incoming_light_connection(&mut self) -> Option<Shared<KLightServerSession>> {
-        let _guard = make_critical_section_guard();
-
         let session = match self.incoming_light_connections.first() {
             Some(session_ref) => Some(session_ref.clone()),
             None => None
@@ -184,12 +211,6 @@ impl KServerPort {
     }
 }
 
-impl Drop for KServerPort {
-    fn drop(&mut self) {
-        println!("Dropping KServerPort!");
-    }
-}
-
 // ---
 
 // KClientPort
@@ -199,7 +220,7 @@ pub struct KClientPort {
     waiting_threads: Vec<Shared<KThread>>,
     max_sessions: u32,
     session_count: u32,
-    pub parent: Option<Shared<KPort>>
+    pub parent: Shared<KPort>
 }
 
 impl KAutoObject for KClientPort {
@@ -215,7 +236,7 @@ impl KSynchronizationObject for KClientPort {
 }
 
 impl KClientPort {
-    pub fn new(parent: Option<Shared<KPort>>, max_sessions: u32) -> Shared<Self> {
+    pub fn new(parent: Shared<KPort>, max_sessions: u32) -> Shared<Self> {
         Shared::new(Self {
             refcount: AtomicI32::new(1),
             waiting_threads: Vec::new(),
@@ -226,8 +247,8 @@ impl KClientPort {
     }
 
     pub fn connect(client_port: &mut Shared<KClientPort>) -> Result<Shared<KClientSession>> {
-        result_return_unless!(client_port.get().parent.is_some(), result::ResultInvalidState);
-        get_current_process().get().resource_limit.get().reserve(svc::LimitableResource::Session, 1, None)?;
+        let resource_limit = get_current_process().get().resource_limit.clone();
+        KResourceLimit::reserve(&resource_limit, svc::LimitableResource::Session, 1, None)?;
 
         let connect_fail_guard = guard((), |()| {
             get_current_process().get().resource_limit.get().release(svc::LimitableResource::Session, 1, 1);
@@ -238,75 +259,111 @@ impl KClientPort {
         result_return_unless!(port_session_count < port_max_sessions, result::ResultOutOfSessions);
         client_port.get().session_count += 1;
 
-        let session = KSession::new(Some(client_port.clone()));
-        client_port.get().parent.as_ref().unwrap().get().enqueue_incoming_session(session.get().server_session.clone());
-
+        let (server_session, client_session) = KSession::new(Some(client_port.clone()));
+        // The session takes over the reservation
         ScopeGuard::into_inner(connect_fail_guard);
-        let client_session = session.get().client_session.clone();
+
+        let port = client_port.get().parent.clone();
+        // Note: if the server side is already closed, dropping the new session undoes the session count increment above
+        port.get().enqueue_incoming_session(server_session)?;
+
         Ok(client_session)
     }
 
     pub fn disconnect(port: &mut Shared<KClientPort>) {
         let _guard = make_critical_section_guard();
 
-        // Signal if max sessions reached
+        // Signal if max sessions were reached (thus a new session can be connected now)
         port.get().session_count -= 1;
         let decremented_count = port.get().session_count;
-        if decremented_count == port.get().max_sessions {
+        if (decremented_count + 1) == port.get().max_sessions {
             KSynchronizationObject::signal(port);
         }
     }
 }
 
-impl Drop for KClientPort {
-    fn drop(&mut self) {
-        println!("Dropping KClientPort!");
-    }
-}
-
 // ---
 
 // KSession
 
+// Note: like ports, both sides keep the session alive while it only holds (weak) backlinks to them
 pub struct KSession {
     refcount: AtomicI32,
-    pub server_session: Shared<KServerSession>,
-    pub client_session: Shared<KClientSession>,
-    state: ChannelState
+    server_session: WeakShared<KServerSession>,
+    client_session: WeakShared<KClientSession>,
+    state: ChannelState,
+    // The session reserved by the process which created it, released once both sides are gone
+    resource_limit: Shared<KResourceLimit>
 }
 
 impl KAutoObject for KSession {
     fn get_refcount(&mut self) -> &mut AtomicI32 {
         &mut self.refcount
     }
+}
 
-    fn destroy(&mut self) {
-        self.client_session.get().disconnect_from_port();
+impl Drop for KSession {
+    fn drop(&mut self) {
+        self.resource_limit.get().release(svc::LimitableResource::Session, 1, 1);
     }
 }
 
 impl KSession {
-    pub fn new(parent_port: Option<Shared<KClientPort>>) -> Shared<Self> {
-        let server_session = KServerSession::new(None);
-        let client_session = KClientSession::new(None, parent_port);
-
+    // Returns both sides of the new session
+    // Note: the caller must have reserved the session in the current process's resource limit, which the session takes over
+    pub fn new(parent_port: Option<Shared<KClientPort>>) -> (Shared<KServerSession>, Shared<KClientSession>) {
         let session = Shared::new(Self {
             refcount: AtomicI32::new(1),
-            server_session: server_session.clone(),
-            client_session: client_session.clone(),
-            state: ChannelState::Open
+            server_session: WeakShared::new(),
+            client_session: WeakShared::new(),
+            state: ChannelState::Open,
+            resource_limit: get_current_process().get().resource_limit.clone()
         });
 
-        server_session.get().parent = Some(session.clone());
-        client_session.get().parent = Some(session.clone());
-        session
+        let server_session = KServerSession::new(session.clone());
+        let client_session = KClientSession::new(session.clone(), parent_port);
+
+        session.get().server_session = server_session.downgrade();
+        session.get().client_session = client_session.downgrade();
+        (server_session, client_session)
     }
 
-    pub fn disconnect_client(&mut self) {
-        if self.state == ChannelState::Open {
-            self.state = ChannelState::ClientDisconnected;
+    #[inline]
+    pub fn get_server_session(&self) -> Option<Shared<KServerSession>> {
+        self.server_session.upgrade()
+    }
 
-            self.server_session.get().cancel_all_requests_due_to_client_disconnect();
+    #[inline]
+    pub fn get_client_session(&self) -> Option<Shared<KClientSession>> {
+        self.client_session.upgrade()
+    }
+
+    pub fn disconnect_client(session: &Shared<Self>) {
+        // Note: the session must not be locked while accessing the server session, since the latter accesses the session when checking whether it's signaled
+        let server_session = {
+            let mut session_guard = session.get();
+            if session_guard.state != ChannelState::Open {
+                return;
+            }
+
+            session_guard.state = ChannelState::ClientDisconnected;
+            session_guard.get_server_session()
+        };
+
+        if let Some(mut server_session) = server_session {
+            let has_pending_requests = server_session.get().has_pending_requests();
+            if has_pending_requests {
+                server_session.get().cancel_all_requests_due_to_client_disconnect();
+            }
+
+            // Wake up the server so that it notices the disconnection
+            KSynchronizationObject::signal(&mut server_session);
+        }
+    }
+
+    pub fn disconnect_server(&mut self) {
+        if self.state == ChannelState::Open {
+            self.state = ChannelState::ServerDisconnected;
         }
     }
 }
@@ -329,7 +386,7 @@ impl Message {
                 // (custom_addr as *mut u8, custom_size)
                 todo!("Custom UserBuffer IPC requests")
             },
-            None => (thread.get().get_tlr_ptr(), 0x100)
+            None => (thread.get().get_thread_local_region().get_message_buffer().as_mut_ptr(), ThreadLocalRegion::MESSAGE_BUFFER_SIZE)
         };
 
         Self {
@@ -350,13 +407,27 @@ impl Message {
         Self::new(&request.client_thread, request.custom_cmd_buf)
     }
 
+    // Whether both messages (partially) share the same memory, in which case copying one into the other would corrupt it
+    pub fn overlaps(&self, other: &Message) -> bool {
+        os::ranges_overlap(self.buf as usize, self.size, other.buf as usize, other.size)
+    }
+
+    #[inline]
+    fn is_valid_access(&self, offset: isize, size: usize) -> bool {
+        (offset >= 0) && os::is_range_within(offset as usize, size, self.size) && (self.is_custom || ThreadLocalRegion::is_in_message_buffer(offset as usize, size))
+    }
+
+    // Note: every offset/count comes from the message itself (thus from the guest), so messages must be validated before accessing anything past their headers (see validate)
+    // Still, going past the message buffer would silently overwrite the rest of the TLR (disable counter, TLS...), thus accesses are always checked as well
     fn do_write<T: Copy>(&self, offset: isize, t: T) {
+        assert!(self.is_valid_access(offset, mem::size_of::<T>()), "IPC message write out of bounds (offset {:#X}, size {:#X}, buffer size {:#X})", offset, mem::size_of::<T>(), self.size);
         unsafe {
             *(self.buf.offset(offset) as *mut T) = t;
         }
     }
 
     fn do_read<T: Copy>(&self, offset: isize) -> T {
+        assert!(self.is_valid_access(offset, mem::size_of::<T>()), "IPC message read out of bounds (offset {:#X}, size {:#X}, buffer size {:#X})", offset, mem::size_of::<T>(), self.size);
         unsafe {
             *(self.buf.offset(offset) as *mut T)
         }
@@ -485,6 +556,59 @@ impl Message {
         self.do_set_array(self.get_raw_data_offset() as isize, data)
     }
 
+    // Best-effort command ID of a request, only meant for diagnostics
+    // TIPC command types are the command ID plus 16, while CMIF requests have it in their data header (16-byte aligned within the message, maybe after a domain header)
+    pub fn get_command_id(&self) -> Option<u32> {
+        // Requests are only validated once they are received
+        if self.validate().is_err() {
+            return None;
+        }
+
+        let command_type = self.get_header().get_command_type();
+        if command_type >= 16 {
+            return Some(command_type - 16);
+        }
+
+        let raw_data = self.get_raw_data();
+        let data_header_word_idx = self.get_aligned_data_word_index();
+        let domain_header_word_count = mem::size_of::<cmif::DomainInDataHeader>() / mem::size_of::<u32>();
+        for word_idx in [data_header_word_idx, data_header_word_idx + domain_header_word_count].iter() {
+            if raw_data.get(*word_idx) == Some(&cmif::IN_DATA_HEADER_MAGIC) {
+                return raw_data.get(*word_idx + 2).copied();
+            }
+        }
+
+        None
+    }
+
+    // CMIF data starts 16-byte aligned within the message, after some padding words
+    fn get_aligned_data_word_index(&self) -> usize {
+        let raw_data_offset = self.get_raw_data_offset();
+        (((raw_data_offset + 0xF) & !0xF) - raw_data_offset) / mem::size_of::<u32>()
+    }
+
+    // Requests are told apart this way by service call recording/mocking (see emu::service_mock)
+    pub fn get_service_request(&self) -> Option<ServiceRequest> {
+        self.validate().ok()?;
+
+        let command_type = self.get_header().get_command_type();
+        let raw_data = self.get_raw_data();
+        let data = match command_type >= 16 {
+            true => raw_data,
+            false => raw_data.into_iter().skip(self.get_aligned_data_word_index()).collect()
+        };
+
+        Some(ServiceRequest {
+            command_type: command_type,
+            command_id: self.get_command_id(),
+            data: data
+        })
+    }
+
+    pub fn get_words(&self) -> Vec<u32> {
+        self.do_get_array(0, (self.get_size() / mem::size_of::<u32>()) as u32)
+    }
+
     pub fn get_size(&self) -> usize {
         let header = self.get_header();
         let special_header = self.get_special_header();
@@ -495,7 +619,7 @@ impl Message {
         }
     }
 
-    pub fn get_receive_statics(&self) -> Vec<u64> {
+    fn get_receive_static_list_range(&self) -> (usize, usize) {
         let count = match self.get_header().get_receive_static_count() {
             0xFF => 1,
             c => c
@@ -506,8 +630,31 @@ impl Message {
             o => o as usize
         };
 
+        (offset, count)
+    }
+
+    // Checks that everything the headers describe (including the receive static list) fits in the message buffer
+    pub fn validate(&self) -> Result<()> {
+        result_return_unless!(self.size >= mem::size_of::<CommandHeader>() + mem::size_of::<CommandSpecialHeader>(), result::ResultInvalidCombination);
+        result_return_unless!(self.is_valid_access(0, self.get_size()), result::ResultInvalidCombination);
+
+        let (receive_statics_offset, receive_static_count) = self.get_receive_static_list_range();
+        result_return_unless!(self.is_valid_access(receive_statics_offset as isize, receive_static_count * mem::size_of::<u64>()), result::ResultInvalidCombination);
+
+        Ok(())
+    }
+
+    // Checks that a (valid) message can be copied into this one
+    pub fn can_hold(&self, other: &Message) -> Result<()> {
+        result_return_unless!(other.get_size() <= self.size, result::ResultInvalidCombination);
+        Ok(())
+    }
+
+    pub fn get_receive_statics(&self) -> Vec<u64> {
+        let (offset, count) = self.get_receive_static_list_range();
         let mut statics = vec![0u64; count];
 
+        assert!(self.is_valid_access(offset as isize, count * mem::size_of::<u64>()), "IPC receive static list out of bounds (offset {:#X}, count {})", offset, count);
         let mut read_ptr = unsafe {
             self.buf.offset(offset as isize) as *mut u64
         };
@@ -522,10 +669,24 @@ impl Message {
     }
 }
 
+// For servers handling raw messages in their own message buffer (like service mocks, see proc::service_mock)
+pub fn get_current_service_request() -> Option<ServiceRequest> {
+    Message::new(&get_current_thread(), None).get_service_request()
+}
+
+pub fn write_current_message(words: &[u32]) -> Result<()> {
+    let msg = Message::new(&get_current_thread(), None);
+    result_return_unless!(words.len() * mem::size_of::<u32>() <= msg.size, result::ResultInvalidCombination);
+
+    msg.clear();
+    msg.do_set_array(0, &words.to_vec());
+    Ok(())
+}
+
 pub struct KServerSession {
     refcount: AtomicI32,
     waiting_threads: Vec<Shared<KThread>>,
-    parent: Option<Shared<KSession>>,
+    parent: Shared<KSession>,
     requests: Vec<KSessionRequest>,
     active_request: Option<KSessionRequest>
 }
@@ -535,12 +696,12 @@ impl KAutoObject for KServerSession {
         &mut self.refcount
     }
 
-    fn destroy(&mut self) {
-        // _parent.DisconnectServer();
-        // CancelAllRequestsServerDisconnected();
-        if let Some(session) = self.parent.as_ref() {
-            session.get().decrement_refcount();
-        }
+}
+
+impl Drop for KServerSession {
+    fn drop(&mut self) {
+        // TODO: CancelAllRequestsServerDisconnected
+        self.parent.get().disconnect_server();
     }
 }
 
@@ -550,22 +711,17 @@ impl KSynchronizationObject for KServerSession {
     }
 
     fn is_signaled(&self) -> bool {
-        if let Some(session) = self.parent.as_ref() {
-            let client_session_state = session.get().state;
-            if client_session_state != ChannelState::Open {
-                return true;
-            }
-
-            !self.requests.is_empty() && self.active_request.is_none()
-        }
-        else {
-            false
+        let client_session_state = self.parent.get().state;
+        if client_session_state != ChannelState::Open {
+            return true;
         }
+
+        !self.requests.is_empty() && self.active_request.is_none()
     }
 }
 
 impl KServerSession {
-    pub fn new(parent: Option<Shared<KSession>>) -> Shared<Self> {
+    pub fn new(parent: Shared<KSession>) -> Shared<Self> {
         Shared::new(Self {
             refcount: AtomicI32::new(1),
             waiting_threads: Vec::new(),
@@ -575,8 +731,26 @@ impl KServerSession {
         })
     }
 
-    pub fn cancel_all_requests_due_to_client_disconnect(&self) {
-        todo!("cancel_all_requests_due_to_client_disconnect");
+    pub fn has_pending_requests(&self) -> bool {
+        !self.requests.is_empty() || self.active_request.is_some()
+    }
+
+    // Fails every request with ResultSessionClosed, since no reply will ever reach the client
+    pub fn cancel_all_requests_due_to_client_disconnect(&mut self) {
+        let _guard = make_critical_section_guard();
+
+        // Note: the active request is kept (the server will still reply to it), it's only marked so that the reply is neither copied to nor woken on the client again
+        if let Some(request) = self.active_request.as_mut() {
+            if !request.is_cancelled {
+                request.is_cancelled = true;
+                Self::wake_client_thread(request, result::ResultSessionClosed::make());
+            }
+        }
+
+        for mut request in self.requests.drain(..) {
+            request.is_cancelled = true;
+            Self::finish_request(&mut request, result::ResultSessionClosed::make());
+        }
     }
 
     pub fn enqueue_request(server_session: &mut Shared<KServerSession>, mut request: KSessionRequest) -> Result<()> {
@@ -643,12 +817,16 @@ impl KServerSession {
     }
 
     fn finish_request(request: &mut KSessionRequest, result: ResultCode) {
-        // TODO: unmap buffers
+        // TODO: unmap buffers (once buffer translation is supported, no buffers are mapped yet)
 
-        Self::wake_client_thread(request, result);
+        // Cancelled requests already had their client woken up
+        if !request.is_cancelled {
+            Self::wake_client_thread(request, result);
+        }
     }
 
     fn do_reply(server_session: &mut Shared<KServerSession>, custom_cmd_buf: Option<(u64, usize)>) -> Result<()> {
+        let _span = host_profiler::enter("ipc_reply");
         let server_thread = get_current_thread();
         let server_process = get_current_process();
 
@@ -657,6 +835,10 @@ impl KServerSession {
 
             result_return_unless!(server_session.get().active_request.is_some(), result::ResultInvalidState);
 
+            // The client already got ResultSessionClosed and moved on, thus its message buffer must be left untouched
+            let is_cancelled = server_session.get().active_request.as_ref().unwrap().is_cancelled;
+            result_return_if!(is_cancelled, result::ResultSessionClosed);
+
             let request = server_session.get().active_request.take().unwrap();
             let client_process = request.client_thread.get().owner_process.as_ref().unwrap().clone();
 
@@ -670,10 +852,15 @@ impl KServerSession {
 
         let client_msg = Message::from_request(&request);
         let server_msg = Message::new(&server_thread, custom_cmd_buf);
+        debug_assert!(!client_msg.overlaps(&server_msg), "Client and server IPC message buffers overlap");
 
-        let server_header = server_msg.get_header();
+        // The client gets the failure (see reply), thus the request must be stored again
+        if let Err(rc) = server_msg.validate().and_then(|()| client_msg.can_hold(&server_msg)) {
+            server_session.get().active_request = Some(request);
+            return Err(rc);
+        }
 
-        // TODO: check bounds in receive count, etc.
+        let server_header = server_msg.get_header();
 
         client_msg.clear();
 
@@ -739,15 +926,25 @@ impl KServerSession {
     }
 
     pub fn receive(&mut self, custom_cmd_buf: Option<(u64, usize)>) -> Result<()> {
+        let _span = host_profiler::enter("ipc_receive");
         let server_thread = get_current_thread();
         let server_process = get_current_process();
 
-        let (request, client_thread, client_process) = {
+        let (mut request, client_thread, client_process) = {
             let _guard = make_critical_section_guard();
 
             result_return_unless!(self.active_request.is_none(), result::ResultNotFound);
 
+            // Nothing else will be received once the client is gone
+            let client_session_state = self.parent.get().state;
+            result_return_if!(self.requests.is_empty() && (client_session_state != ChannelState::Open), result::ResultSessionClosed);
+
             let request = self.dequeue_request()?;
+            if watchdog::is_enabled() {
+                let server_process_name = server_process.get().npdm.meta.name.get_string().unwrap_or_default();
+                let server_process_id = server_process.get().id;
+                watchdog::record_request_received(request.id, server_process_id, server_process_name);
+            }
             let client_thread = request.client_thread.clone();
             let client_process = client_thread.get().owner_process.as_ref().unwrap().clone();
 
@@ -756,10 +953,15 @@ impl KServerSession {
 
         let client_msg = Message::from_request(&request);
         let server_msg = Message::new(&server_thread, custom_cmd_buf);
+        debug_assert!(!client_msg.overlaps(&server_msg), "Client and server IPC message buffers overlap");
 
-        let client_header = client_msg.get_header();
+        // Malformed requests fail right away on the client side, while the server just keeps waiting for valid ones
+        if let Err(rc) = client_msg.validate().and_then(|()| server_msg.can_hold(&client_msg)) {
+            Self::finish_request(&mut request, rc);
+            return result::ResultNotFound::make_err();
+        }
 
-        // TODO: check bounds in receive count, etc.
+        let client_header = client_msg.get_header();
 
         server_msg.clear();
 
@@ -825,7 +1027,7 @@ impl KServerSession {
 pub struct KClientSession {
     refcount: AtomicI32,
     waiting_threads: Vec<Shared<KThread>>,
-    parent: Option<Shared<KSession>>,
+    parent: Shared<KSession>,
     parent_port: Option<Shared<KClientPort>>
 }
 
@@ -833,12 +1035,12 @@ impl KAutoObject for KClientSession {
     fn get_refcount(&mut self) -> &mut AtomicI32 {
         &mut self.refcount
     }
+}
 
-    fn destroy(&mut self) {
-        if let Some(session) = self.parent.as_ref() {
-            session.get().disconnect_client();
-            session.get().decrement_refcount();
-        }
+impl Drop for KClientSession {
+    fn drop(&mut self) {
+        KSession::disconnect_client(&self.parent);
+        self.disconnect_from_port();
     }
 }
 
@@ -849,7 +1051,7 @@ impl KSynchronizationObject for KClientSession {
 }
 
 impl KClientSession {
-    pub fn new(parent: Option<Shared<KSession>>, parent_port: Option<Shared<KClientPort>>) -> Shared<Self> {
+    pub fn new(parent: Shared<KSession>, parent_port: Option<Shared<KClientPort>>) -> Shared<Self> {
         if let Some(port) = parent_port.as_ref() {
             port.get().increment_refcount();
         }
@@ -866,6 +1068,17 @@ impl KClientSession {
 
     pub fn send_sync_request(&mut self, custom_cmd_buf: Option<(u64, usize)>) -> Result<()> {
         let request = KSessionRequest::new(get_current_thread(), custom_cmd_buf);
+        let request_id = request.id;
+        if watchdog::is_enabled() {
+            self.register_watchdog_request(&request);
+        }
+        let _watchdog_guard = guard((), |()| watchdog::unregister_request(request_id));
+
+        // The request must be kept before sending it, since the reply overwrites it
+        let recorded_request = match service_mock::is_recording() {
+            true => self.get_recorded_request(&request),
+            false => None
+        };
 
         {
             let _guard = make_critical_section_guard();
@@ -873,11 +1086,83 @@ impl KClientSession {
             get_current_thread().get().signaled_obj = None;
             get_current_thread().get().sync_result = ResultSuccess::make();
 
-            let mut server_session = self.parent.as_ref().unwrap().get().server_session.clone();
+            let server_session = self.parent.get().get_server_session();
+            let mut server_session = match server_session {
+                Some(server_session) => server_session,
+                None => return result::ResultSessionClosed::make_err()
+            };
             KServerSession::enqueue_request(&mut server_session, request)?;
         }
 
-        get_current_thread().get().sync_result.to(())
+        let rc = get_current_thread().get().sync_result;
+        if let Some((service_name, request)) = recorded_request {
+            if rc.is_success() {
+                Self::record_service_call(service_name, request, custom_cmd_buf);
+            }
+        }
+
+        rc.to(())
+    }
+
+    // Only sessions to named services (not sub-interfaces) can be recorded
+    fn get_service_name(&self) -> Option<String> {
+        match self.parent_port.as_ref() {
+            Some(client_port) => client_port.get().parent.get().get_name(),
+            None => None
+        }
+    }
+
+    fn get_recorded_request(&self, request: &KSessionRequest) -> Option<(String, ServiceRequest)> {
+        let service_name = self.get_service_name()?;
+        if !service_mock::is_recorded_service(&service_name) {
+            return None;
+        }
+
+        let msg = Message::from_request(request);
+        // Closing a session isn't a call itself
+        if msg.get_header().get_command_type() == cmif::CommandType::Close as u32 {
+            return None;
+        }
+        Some((service_name, msg.get_service_request()?))
+    }
+
+    fn record_service_call(service_name: String, request: ServiceRequest, custom_cmd_buf: Option<(u64, usize)>) {
+        let reply_msg = Message::new(&get_current_thread(), custom_cmd_buf);
+        if reply_msg.validate().is_err() {
+            return;
+        }
+        if reply_msg.get_header().get_has_special_header() {
+            log_line!("[service_mock] Not recording command {:?} of '{}', since its reply carries handles", request.command_id, service_name);
+            return;
+        }
+
+        service_mock::record_call(RecordedServiceCall {
+            service_name: service_name,
+            request: request,
+            reply: reply_msg.get_words()
+        });
+    }
+
+    fn register_watchdog_request(&self, request: &KSessionRequest) {
+        let client_process = get_current_process();
+        let client_process_name = client_process.get().npdm.meta.name.get_string().unwrap_or_default();
+        let client_process_id = client_process.get().id;
+        let client_thread_id = request.client_thread.get().id;
+
+        let service_name = self.get_service_name();
+
+        let msg = Message::from_request(request);
+        watchdog::register_request(request.id, watchdog::PendingRequest {
+            client_process_id: client_process_id,
+            client_process_name: client_process_name,
+            client_thread_id: client_thread_id,
+            service_name: service_name,
+            command_type: msg.get_header().get_command_type(),
+            command_id: msg.get_command_id(),
+            server_process: None,
+            sent_instant: request.sent_instant,
+            is_reported: false
+        });
     }
 
     pub fn disconnect_from_port(&mut self) {
@@ -891,8 +1176,13 @@ impl KClientSession {
 
 // KLightSession
 
+// Note: same layout as regular sessions, light IPC itself (SendSyncRequestLight, ReplyAndReceiveLight) is not supported yet
 pub struct KLightSession {
-    refcount: AtomicI32
+    refcount: AtomicI32,
+    server_session: WeakShared<KLightServerSession>,
+    client_session: WeakShared<KLightClientSession>,
+    state: ChannelState,
+    resource_limit: Shared<KResourceLimit>
 }
 
 impl KAutoObject for KLightSession {
@@ -901,12 +1191,67 @@ impl KAutoObject for KLightSession {
     }
 }
 
+impl Drop for KLightSession {
+    fn drop(&mut self) {
+        self.resource_limit.get().release(svc::LimitableResource::Session, 1, 1);
+    }
+}
+
+impl KLightSession {
+    // Returns both sides of the new session
+    // Note: as with regular sessions, the caller must have reserved the session in the current process's resource limit
+    pub fn new() -> (Shared<KLightServerSession>, Shared<KLightClientSession>) {
+        let session = Shared::new(Self {
+            refcount: AtomicI32::new(1),
+            server_session: WeakShared::new(),
+            client_session: WeakShared::new(),
+            state: ChannelState::Open,
+            resource_limit: get_current_process().get().resource_limit.clone()
+        });
+
+        let server_session = KLightServerSession::new(session.clone());
+        let client_session = KLightClientSession::new(session.clone());
+
+        session.get().server_session = server_session.downgrade();
+        session.get().client_session = client_session.downgrade();
+        (server_session, client_session)
+    }
+
+    #[inline]
+    pub fn get_server_session(&self) -> Option<Shared<KLightServerSession>> {
+        self.server_session.upgrade()
+    }
+
+    #[inline]
+    pub fn get_client_session(&self) -> Option<Shared<KLightClientSession>> {
+        self.client_session.upgrade()
+    }
+
+    #[inline]
+    pub fn get_state(&self) -> ChannelState {
+        self.state
+    }
+
+    pub fn disconnect_client(&mut self) {
+        if self.state == ChannelState::Open {
+            self.state = ChannelState::ClientDisconnected;
+        }
+    }
+
+    pub fn disconnect_server(&mut self) {
+        if self.state == ChannelState::Open {
+            self.state = ChannelState::ServerDisconnected;
+        }
+    }
+}
+
 // ---
 
 // KLightServerSession
 
 pub struct KLightServerSession {
-    refcount: AtomicI32
+    refcount: AtomicI32,
+    pub parent: Shared<KLightSession>
 }
 
 impl KAutoObject for KLightServerSession {
@@ -915,12 +1260,28 @@ impl KAutoObject for KLightServerSession {
     }
 }
 
+impl Drop for KLightServerSession {
+    fn drop(&mut self) {
+        self.parent.get().disconnect_server();
+    }
+}
+
+impl KLightServerSession {
+    pub fn new(parent: Shared<KLightSession>) -> Shared<Self> {
+        Shared::new(Self {
+            refcount: AtomicI32::new(1),
+            parent: parent
+        })
+    }
+}
+
 // ---
 
 // KLightClientSession
 
 pub struct KLightClientSession {
-    refcount: AtomicI32
+    refcount: AtomicI32,
+    pub parent: Shared<KLightSession>
 }
 
 impl KAutoObject for KLightClientSession {
@@ -929,20 +1290,45 @@ impl KAutoObject for KLightClientSession {
     }
 }
 
+impl Drop for KLightClientSession {
+    fn drop(&mut self) {
+        self.parent.get().disconnect_client();
+    }
+}
+
+impl KLightClientSession {
+    pub fn new(parent: Shared<KLightSession>) -> Shared<Self> {
+        Shared::new(Self {
+            refcount: AtomicI32::new(1),
+            parent: parent
+        })
+    }
+}
+
 // ---
 
 // KSessionRequest
 
+static mut G_NEXT_SESSION_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
 pub struct KSessionRequest {
+    // Only used to identify requests in diagnostics (see emu::watchdog)
+    pub id: u64,
+    pub sent_instant: Instant,
     pub client_thread: Shared<KThread>,
-    pub custom_cmd_buf: Option<(u64, usize)>
+    pub custom_cmd_buf: Option<(u64, usize)>,
+    // Set when the client disconnected before the request was replied to
+    pub is_cancelled: bool
 }
 
 impl KSessionRequest {
     pub fn new(client_thread: Shared<KThread>, custom_cmd_buf: Option<(u64, usize)>) -> Self {
         Self {
+            id: unsafe { G_NEXT_SESSION_REQUEST_ID.fetch_add(1, Ordering::SeqCst) },
+            sent_instant: Instant::now(),
             client_thread: client_thread,
-            custom_cmd_buf: custom_cmd_buf
+            custom_cmd_buf: custom_cmd_buf,
+            is_cancelled: false
         }
     }
 }
@@ -1,7 +1,23 @@
+use crate::result::*;
+use super::result;
 use super::svc;
 
 pub const PAGE_SIZE: usize = 0x1000;
 
+#[inline]
+pub const fn is_page_aligned(value: u64) -> bool {
+    (value & (PAGE_SIZE as u64 - 1)) == 0
+}
+
+// Every mapping (made by loaders, SVCs...) must span whole pages, since unicorn would otherwise fail with a meaningless argument error
+pub fn check_page_aligned_range(address: u64, size: usize) -> Result<()> {
+    result_return_unless!(is_page_aligned(address), result::ResultInvalidAddress);
+    result_return_unless!(is_page_aligned(size as u64), result::ResultInvalidSize);
+    result_return_if!(size == 0, result::ResultInvalidSize);
+    result_return_if!(address.checked_add(size as u64).is_none(), result::ResultInvalidCurrentMemory);
+    Ok(())
+}
+
 // KMemoryBlock
 
 bit_enum! {
@@ -1,17 +1,23 @@
 use std::sync::atomic::AtomicI32;
 use parking_lot::Mutex;
 use crate::emu::cpu;
+use crate::emu::coredump;
+use crate::emu::symbols;
 use crate::ldr::npdm::NpdmData;
-use crate::util::{Shared, SharedAny};
+use crate::util::{Shared, SharedAny, WeakShared};
 use crate::result::*;
 use crate::result as lib_result;
 use super::KAutoObject;
 use super::KResourceLimit;
 use super::KSynchronizationObject;
 use super::ipc::{KClientPort, KClientSession, KServerPort, KServerSession};
+use super::debug::KDebug;
+use super::event::KReadableEvent;
 use super::thread::{KThread, try_get_current_thread};
 use super::thread::get_current_thread;
+use super::thread::{ThreadState, make_critical_section_guard};
 use super::svc::LimitableResource;
+use super::svc::ProcessState;
 use super::svc::Handle;
 use super::svc::CURRENT_PROCESS_PSEUDO_HANDLE;
 use super::svc::CURRENT_THREAD_PSEUDO_HANDLE;
@@ -48,6 +54,7 @@ impl KHandleTableEntry {
 pub struct KHandleTable {
     entry_table: Mutex<Vec<KHandleTableEntry>>,
     used_entry_count: u32,
+    peak_used_entry_count: u32,
     linear_id_counter: u16
 }
 
@@ -73,6 +80,7 @@ impl KHandleTable {
         Ok(Self {
             entry_table: Mutex::new(entry_table),
             used_entry_count: 0,
+            peak_used_entry_count: 0,
             linear_id_counter: KHandleTableEntry::MIN_LINEAR_ID
         })
     }
@@ -96,6 +104,7 @@ impl KHandleTable {
                 // obj.get().increment_refcount();
                 entry.obj = Some(obj.clone());
                 self.used_entry_count += 1;
+                self.peak_used_entry_count = self.peak_used_entry_count.max(self.used_entry_count);
 
                 return Ok(handle);
             }
@@ -126,6 +135,7 @@ impl KHandleTable {
                 let handle = Self::encode_handle(i as u32, entry.linear_id);
                 entry.obj = None;
                 self.used_entry_count += 1;
+                self.peak_used_entry_count = self.peak_used_entry_count.max(self.used_entry_count);
 
                 return Ok(handle);
             }
@@ -156,6 +166,7 @@ impl KHandleTable {
         result_return_unless!(entry.linear_id == linear_id, result::ResultInvalidHandle);
 
         *entry = KHandleTableEntry::new();
+        self.used_entry_count -= 1;
         Ok(())
     }
 
@@ -192,36 +203,106 @@ impl KHandleTable {
         self.get_handle_obj_any(handle)?.cast::<K>()
     }
     
-    pub fn get_handle_sync_obj(&self, handle: Handle) -> Result<Shared<dyn KSynchronizationObject>> {
-        // Due to how great Rust is with downcasting, we have to do this with all KSynchronizationObject types. Luckily there aren't that many of them...
-        let obj = self.get_handle_obj_any(handle)?;
+    #[inline]
+    pub fn get_size(&self) -> usize {
+        self.entry_table.lock().len()
+    }
 
-        if let Ok(thread) = obj.cast::<KThread>() {
-            return Ok(thread);
-        }
+    #[inline]
+    pub fn get_used_entry_count(&self) -> usize {
+        self.used_entry_count as usize
+    }
 
-        if let Ok(process) = obj.cast::<KProcess>() {
-            return Ok(process);
-        }
+    #[inline]
+    pub fn get_peak_used_entry_count(&self) -> usize {
+        self.peak_used_entry_count as usize
+    }
 
-        if let Ok(server_port) = obj.cast::<KServerPort>() {
-            return Ok(server_port);
-        }
-        if let Ok(client_port) = obj.cast::<KClientPort>() {
-            return Ok(client_port);
-        }
+    // Snapshot of the handles currently in use (allocated-but-unset ones have no object yet)
+    pub fn get_entries(&self) -> Vec<(Handle, Option<SharedAny>)> {
+        let entry_table = self.entry_table.lock();
 
-        if let Ok(server_session) = obj.cast::<KServerSession>() {
-            return Ok(server_session);
-        }
-        if let Ok(client_session) = obj.cast::<KClientSession>() {
-            return Ok(client_session);
+        entry_table.iter().enumerate().filter(|(_, entry)| !entry.is_empty()).map(|(idx, entry)| (Self::encode_handle(idx as u32, entry.linear_id), entry.obj.clone())).collect()
+    }
+
+    // Count of objects by type name, most frequent ones first
+    pub fn get_object_counts(&self) -> Vec<(&'static str, usize)> {
+        let mut counts: Vec<(&'static str, usize)> = Vec::new();
+        for (_, obj) in self.get_entries() {
+            let type_name = match obj.as_ref() {
+                Some(obj) => obj.get_type_name(),
+                None => "<unset>"
+            };
+
+            match counts.iter_mut().find(|(count_type_name, _)| *count_type_name == type_name) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((type_name, 1))
+            }
         }
 
-        lib_result::ResultInvalidCast::make_err()
+        counts.sort_by(|(_, a), (_, b)| b.cmp(a));
+        counts
+    }
+
+    pub fn get_handle_sync_obj(&self, handle: Handle) -> Result<Shared<dyn KSynchronizationObject>> {
+        cast_sync_obj(self.get_handle_obj_any(handle)?)
     }
 }
 
+fn cast_sync_obj(obj: SharedAny) -> Result<Shared<dyn KSynchronizationObject>> {
+    // Due to how great Rust is with downcasting, we have to do this with all KSynchronizationObject types. Luckily there aren't that many of them...
+    if let Ok(thread) = obj.cast::<KThread>() {
+        return Ok(thread);
+    }
+
+    if let Ok(process) = obj.cast::<KProcess>() {
+        return Ok(process);
+    }
+
+    if let Ok(server_port) = obj.cast::<KServerPort>() {
+        return Ok(server_port);
+    }
+    if let Ok(client_port) = obj.cast::<KClientPort>() {
+        return Ok(client_port);
+    }
+
+    if let Ok(server_session) = obj.cast::<KServerSession>() {
+        return Ok(server_session);
+    }
+    if let Ok(client_session) = obj.cast::<KClientSession>() {
+        return Ok(client_session);
+    }
+
+    if let Ok(debug) = obj.cast::<KDebug>() {
+        return Ok(debug);
+    }
+
+    if let Ok(readable_event) = obj.cast::<KReadableEvent>() {
+        return Ok(readable_event);
+    }
+
+    lib_result::ResultInvalidCast::make_err()
+}
+
+// Pseudo-handles are not actual handle table entries: they always refer to the current process/thread, and guests may pass them to any SVC taking a handle
+// Thus handles coming from SVCs must be resolved through these instead of the current process's handle table
+pub fn resolve_handle_any(handle: Handle) -> Result<SharedAny> {
+    match handle {
+        CURRENT_PROCESS_PSEUDO_HANDLE => Ok(get_current_process().as_any()),
+        CURRENT_THREAD_PSEUDO_HANDLE => Ok(get_current_thread().as_any()),
+        _ => get_current_process().get().handle_table.get_handle_obj_any(handle)
+    }
+}
+
+#[inline]
+pub fn resolve_handle<K: KAutoObject + 'static>(handle: Handle) -> Result<Shared<K>> {
+    resolve_handle_any(handle)?.cast::<K>()
+}
+
+pub fn resolve_sync_handle(handle: Handle) -> Result<Shared<dyn KSynchronizationObject>> {
+    cast_sync_obj(resolve_handle_any(handle)?)
+}
+
 // ---
 
 // KProcess
@@ -238,6 +319,57 @@ pub fn new_process_id() -> u64 {
     }
 }
 
+// Note: processes are listed along with their IDs, since looking them up must not lock every process (their own threads, on any core, may have them locked meanwhile)
+// Note: the list only holds weak references, thus processes are still dropped once they're gone (no threads running and no handles left)
+static mut G_PROCESS_LIST: Mutex<Vec<(u64, WeakShared<KProcess>)>> = parking_lot::const_mutex(Vec::new());
+
+fn register_process(process_id: u64, process: &Shared<KProcess>) {
+    unsafe {
+        let mut process_list = G_PROCESS_LIST.lock();
+        process_list.retain(|(_, process)| process.upgrade().is_some());
+        process_list.push((process_id, process.downgrade()));
+    }
+}
+
+pub fn get_process_list() -> Vec<Shared<KProcess>> {
+    unsafe {
+        G_PROCESS_LIST.lock().iter().filter_map(|(_, process)| process.upgrade()).collect()
+    }
+}
+
+// Logs the handle table usage of every process, which helps finding leaked objects
+pub fn dump_handle_tables() {
+    for process in get_process_list().iter() {
+        // Note: logging accesses the current process, thus the process can't be kept locked meanwhile
+        let (process_id, process_name, used_count, size, peak_used_count, object_counts) = {
+            let process_guard = process.get();
+            let handle_table = &process_guard.handle_table;
+            (process_guard.id, process_guard.npdm.meta.name.get_string().unwrap_or_default(), handle_table.get_used_entry_count(), handle_table.get_size(), handle_table.get_peak_used_entry_count(), handle_table.get_object_counts())
+        };
+
+        log_line!("Process {:#X} ('{}') handle table: {}/{} used (peak {})", process_id, process_name, used_count, size, peak_used_count);
+        for (type_name, count) in object_counts {
+            log_line!("  {} x{}", type_name, count);
+        }
+    }
+}
+
+pub fn find_process_by_id(process_id: u64) -> Result<Shared<KProcess>> {
+    unsafe {
+        let process_list = G_PROCESS_LIST.lock();
+
+        for (list_process_id, process) in process_list.iter() {
+            if *list_process_id == process_id {
+                if let Some(process) = process.upgrade() {
+                    return Ok(process);
+                }
+            }
+        }
+    }
+
+    result::ResultInvalidProcessId::make_err()
+}
+
 pub struct KProcess {
     refcount: AtomicI32,
     waiting_threads: Vec<Shared<KThread>>,
@@ -245,6 +377,15 @@ pub struct KProcess {
     pub npdm: NpdmData,
     pub handle_table: KHandleTable,
     pub resource_limit: Shared<KResourceLimit>,
+    // Weak, since the debugger's handle is what keeps it attached (see KDebug's Drop)
+    debug: WeakShared<KDebug>,
+    pub in_user_exception: bool,
+    // Weak, since threads already hold their owner process
+    threads: Vec<WeakShared<KThread>>,
+    pub is_paused: bool,
+    pub state: ProcessState,
+    // Start of the code region of processes created by the guest, where their main thread starts running (see svc::start_process)
+    pub code_address: u64,
     pub id: u64
 }
 
@@ -274,15 +415,76 @@ impl KProcess {
         resource_limit.get().set_limit_value(LimitableResource::TransferMemory, 128)?;
         resource_limit.get().set_limit_value(LimitableResource::Session, 894)?;
 
-        Ok(Shared::new(Self {
+        let process_id = new_process_id();
+        if let Some(cpu_ctx) = cpu_ctx.as_ref() {
+            for module in cpu_ctx.modules.iter() {
+                symbols::register_module(process_id, module);
+            }
+        }
+
+        let process = Shared::new(Self {
             refcount: AtomicI32::new(1),
             waiting_threads: Vec::new(),
             cpu_ctx: cpu_ctx,
             npdm: npdm,
             handle_table: KHandleTable::new(handle_table_size)?,
             resource_limit: resource_limit,
-            id: new_process_id()
-        }))
+            debug: WeakShared::new(),
+            in_user_exception: false,
+            threads: Vec::new(),
+            is_paused: false,
+            state: ProcessState::Created,
+            code_address: 0,
+            id: process_id
+        });
+        register_process(process_id, &process);
+        Ok(process)
+    }
+
+    pub fn register_thread(&mut self, thread: &Shared<KThread>) {
+        self.threads.retain(|thread| thread.upgrade().is_some());
+        self.threads.push(thread.downgrade());
+    }
+
+    #[inline]
+    pub fn get_debug(&self) -> Option<Shared<KDebug>> {
+        self.debug.upgrade()
+    }
+
+    #[inline]
+    pub fn is_debugged(&self) -> bool {
+        self.get_debug().is_some()
+    }
+
+    #[inline]
+    pub fn set_debug(&mut self, debug: &Shared<KDebug>) {
+        self.debug = debug.downgrade();
+    }
+
+    pub fn get_threads(&self) -> Vec<Shared<KThread>> {
+        self.threads.iter().filter_map(|thread| thread.upgrade()).collect()
+    }
+
+    pub fn set_activity(process: &mut Shared<KProcess>, pause: bool) -> Result<()> {
+        let _guard = make_critical_section_guard();
+
+        let is_paused = process.get().is_paused;
+        let threads = process.get().get_threads();
+        if pause {
+            result_return_if!(is_paused, result::ResultInvalidState);
+            for mut thread in threads {
+                KThread::suspend(&mut thread, ThreadState::ProcessSuspended);
+            }
+        }
+        else {
+            result_return_unless!(is_paused, result::ResultInvalidState);
+            for mut thread in threads {
+                KThread::resume(&mut thread, ThreadState::ProcessSuspended);
+            }
+        }
+
+        process.get().is_paused = pause;
+        Ok(())
     }
 
     pub fn create_main_thread(proc: &mut Shared<KProcess>, host_thread_name: String, entry_addr: u64) -> Result<(Shared<KThread>, Handle)> {
@@ -292,6 +494,7 @@ impl KProcess {
 
         let thread = KThread::new(Some(proc.clone()), host_thread_name, priority, cpu_core, Some((entry_addr, stack_size)))?;
         let thread_handle = proc.get().handle_table.allocate_handle_set(thread.clone())?;
+        proc.get().state = ProcessState::Started;
         Ok((thread, thread_handle))
     }
 
@@ -299,7 +502,37 @@ impl KProcess {
         let priority = proc.get().npdm.meta.main_thread_priority as i32;
         let cpu_core = proc.get().npdm.meta.main_thread_cpu_core as i32;
 
-        KThread::new_host(Some(proc.clone()), host_thread_name, priority, cpu_core)
+        let thread = KThread::new_host(Some(proc.clone()), host_thread_name, priority, cpu_core)?;
+        proc.get().state = ProcessState::Started;
+        Ok(thread)
+    }
+
+    // Every mapped region of the process, thread stacks/TLRs included
+    // Note: threads which are currently locked (like a panicking one) are skipped, since this is also used for crash reports
+    pub fn get_memory_map(proc: &Shared<KProcess>) -> Vec<cpu::MemoryMapEntry> {
+        let (modules_map, threads) = {
+            let proc_ref = proc.get();
+            let modules_map = match proc_ref.cpu_ctx.as_ref() {
+                Some(cpu_ctx) => cpu::make_memory_map(&cpu_ctx.modules, std::iter::empty()),
+                None => Vec::new()
+            };
+            (modules_map, proc_ref.get_threads())
+        };
+
+        let mut memory_map = modules_map;
+        for thread in threads.iter().filter(|thread| !thread.is_locked()) {
+            if let Some(exec_ctx) = thread.get().cpu_exec_ctx.as_ref() {
+                memory_map.extend(cpu::make_memory_map(&[], [&exec_ctx.stack, &exec_ctx.tlr].iter().copied()));
+            }
+        }
+        memory_map.sort_by_key(|entry| entry.address);
+        memory_map
+    }
+
+    // Note: the process should be paused first (see set_activity) for a consistent dump
+    pub fn dump_core(proc: &Shared<KProcess>, path: String) -> Result<()> {
+        let core_dump = coredump::CoreDump::from_process(proc)?;
+        core_dump.save(path)
     }
 }
 
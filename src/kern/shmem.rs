@@ -0,0 +1,46 @@
+use std::sync::Arc;
+use std::sync::atomic::AtomicI32;
+use crate::util::Shared;
+use crate::result::*;
+use super::KAutoObject;
+use super::mem::PAGE_SIZE;
+use super::result;
+use super::svc::MemoryPermission;
+
+// KSharedMemory
+
+// Note: the memory is mapped straight from its data in every process (see cpu::Context::write_memory regarding shared memory writes)
+pub struct KSharedMemory {
+    refcount: AtomicI32,
+    pub data: Arc<Vec<u8>>,
+    pub owner_perm: MemoryPermission,
+    pub remote_perm: MemoryPermission
+}
+
+impl KAutoObject for KSharedMemory {
+    fn get_refcount(&mut self) -> &mut AtomicI32 {
+        &mut self.refcount
+    }
+}
+
+impl KSharedMemory {
+    // The data is padded to the page size
+    pub fn new(mut data: Vec<u8>, owner_perm: MemoryPermission, remote_perm: MemoryPermission) -> Result<Shared<Self>> {
+        result_return_if!(data.is_empty(), result::ResultInvalidSize);
+
+        let size = (data.len() + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        data.resize(size, 0);
+        Ok(Shared::new(Self {
+            refcount: AtomicI32::new(1),
+            data: Arc::new(data),
+            owner_perm: owner_perm,
+            remote_perm: remote_perm
+        }))
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.data.len()
+    }
+}
+
+// ---
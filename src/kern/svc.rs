@@ -1,8 +1,12 @@
 use core::mem;
 use core::panic;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use scopeguard::{guard, ScopeGuard};
 use crate::emu::cpu;
+use crate::emu::metrics;
+use crate::emu::output::{self, OutputChannel};
+use crate::emu::trace;
 use crate::kern::KAutoObject;
 use crate::kern::KSynchronizationObject;
 use crate::kern::find_named_object;
@@ -11,15 +15,36 @@ use crate::kern::ipc::KServerPort;
 use crate::kern::ipc::KPort;
 use crate::kern::ipc::KClientSession;
 use crate::kern::ipc::KServerSession;
+use crate::kern::KResourceLimit;
+use crate::kern::proc::KProcess;
+use crate::kern::proc::KHandleTable;
 use crate::kern::proc::get_current_process;
+use crate::kern::proc::find_process_by_id;
+use crate::kern::proc::{resolve_handle, resolve_sync_handle};
+use crate::kern::debug::{self, KDebug, DebugEventInfo, DebugExceptionType};
+use crate::kern::event::{KEvent, KReadableEvent};
+use crate::kern::shmem::KSharedMemory;
 use crate::kern::register_named_object;
 use crate::kern::result;
 use crate::kern::wait_for_sync_objects;
+use crate::ldr::npdm;
+use crate::ncm::ProgramId;
+use crate::proc::EmulatedProcess;
 use crate::result::*;
 use crate::util::Shared;
 use crate::util;
 use super::ipc::KSession;
+use super::ipc::KLightSession;
 use super::thread::get_current_thread;
+use super::thread::get_critical_section;
+use super::thread::make_critical_section_guard;
+use super::thread::KThread;
+use super::thread::KScheduler;
+use super::thread::get_scheduler;
+use super::thread::ThreadState;
+use super::thread::{PRIORITY_COUNT, CPU_CORE_COUNT};
+use super::mem::{PAGE_SIZE, KMemoryState, convert_memory_state, is_page_aligned, check_page_aligned_range};
+use super::get_time_manager;
 
 pub type Handle = u32;
 pub const INVALID_HANDLE: Handle = 0;
@@ -270,6 +295,166 @@ pub struct MemoryInfo {
     pub pad: u32,
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[repr(C)]
+pub struct LastThreadContext {
+    pub fp: u64,
+    pub sp: u64,
+    pub lr: u64,
+    pub pc: u64
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum ExceptionType {
+    Init = 0x000,
+    InstructionAbort = 0x100,
+    DataAbort = 0x101,
+    UnalignedInstruction = 0x102,
+    UnalignedData = 0x103,
+    UndefinedInstruction = 0x104,
+    ExceptionInstruction = 0x105,
+    MemorySystemError = 0x106,
+    FpuException = 0x200,
+    InvalidSystemCall = 0x301,
+    SystemCallBreak = 0x302
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum ThreadActivity {
+    Runnable = 0,
+    Paused = 1
+}
+
+impl ThreadActivity {
+    pub const fn from(raw: u32) -> Option<Self> {
+        match raw {
+            0 => Some(Self::Runnable),
+            1 => Some(Self::Paused),
+            _ => None
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum ProcessActivity {
+    Runnable = 0,
+    Paused = 1
+}
+
+impl ProcessActivity {
+    pub const fn from(raw: u32) -> Option<Self> {
+        match raw {
+            0 => Some(Self::Runnable),
+            1 => Some(Self::Paused),
+            _ => None
+        }
+    }
+}
+
+bit_enum! {
+    ContinueDebugFlag (u32) {
+        None = 0,
+        ExceptionHandled = bit!(0),
+        EnableExceptionEvent = bit!(1),
+        ContinueAll = bit!(2),
+        ContinueOthers = bit!(3)
+    }
+}
+
+bit_enum! {
+    CreateProcessFlags (u32) {
+        None = 0,
+        Is64Bit = bit!(0),
+        AddressSpaceMask = 0b1110,
+        EnableDebug = bit!(4),
+        EnableAslr = bit!(5),
+        IsApplication = bit!(6)
+    }
+}
+
+impl CreateProcessFlags {
+    pub const fn get_address_space(self) -> Option<npdm::AddressSpaceType> {
+        match (self.get() & Self::AddressSpaceMask().get()) >> 1 {
+            0 => Some(npdm::AddressSpaceType::AS32Bit),
+            1 => Some(npdm::AddressSpaceType::AS64BitLegacy),
+            2 => Some(npdm::AddressSpaceType::AS32BitNoReserved),
+            3 => Some(npdm::AddressSpaceType::AS64Bit),
+            _ => None
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+#[repr(C)]
+pub struct CreateProcessParameter {
+    pub name: util::CString<0xC>,
+    pub version: u32,
+    pub program_id: u64,
+    pub code_address: u64,
+    pub code_num_pages: u32,
+    pub flags: CreateProcessFlags,
+    pub resource_limit_handle: Handle,
+    pub system_resource_num_pages: u32
+}
+const _: () = assert!(std::mem::size_of::<CreateProcessParameter>() == 0x30);
+
+// Note: only the info types below are supported for now
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum InfoType {
+    IdleTickCount = 10
+}
+
+impl InfoType {
+    pub const fn from(raw: u32) -> Option<Self> {
+        match raw {
+            10 => Some(Self::IdleTickCount),
+            _ => None
+        }
+    }
+}
+
+// Subtype of the per-core info types meaning the current core
+pub const INFO_SUBTYPE_CURRENT_CORE: u64 = u64::MAX;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum ProcessInfoType {
+    ProcessState = 0
+}
+
+impl ProcessInfoType {
+    pub const fn from(raw: u32) -> Option<Self> {
+        match raw {
+            0 => Some(Self::ProcessState),
+            _ => None
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u64)]
+pub enum ProcessState {
+    Created = 0,
+    CreatedAttached = 1,
+    Started = 2,
+    Crashed = 3,
+    StartedAttached = 4,
+    Exiting = 5,
+    Exited = 6,
+    DebugSuspended = 7
+}
+
+bit_enum! {
+    LastThreadInfoFlag (u32) {
+        None = 0,
+        ThreadInSystemCall = bit!(0)
+    }
+}
+
 // Normal processes reschedule themselves as an interrupt after an SVC call -- since this is necessary for any process/thread, we use this guard/macro so that emulated processes behave the same
 macro_rules! register_emu_proc_post_svc_guard {
     () => {
@@ -288,13 +473,72 @@ macro_rules! register_emu_proc_post_svc_guard {
 // Note: the actual impl of SVCs would have (ptr, size) for args/bufs/strings, but Rust's slice, &str, etc. types make my life way easier here ;)
 
 pub fn sleep_thread(timeout: i64) -> Result<()> {
+    register_emu_proc_post_svc_guard!();
+
     match timeout {
-        0 => todo!("Yield"),
-        -1 => todo!("YieldWithLoadBalancing"),
-        -2 => todo!("YieldToAnyThread"),
-        timeout => {
+        0 => {
+            KScheduler::yield_thread();
+            Ok(())
+        },
+        -1 => {
+            KScheduler::yield_with_load_balancing();
+            Ok(())
+        },
+        -2 => {
+            KScheduler::yield_to_any_thread();
+            Ok(())
+        },
+        timeout if timeout > 0 => {
             let duration = Duration::from_nanos(timeout as u64);
-            todo!("SleepThread with timeout = {}ns", duration.as_nanos());
+            KThread::sleep(&mut get_current_thread(), duration)
+        },
+        // Note: any other negative timeout is just ignored by the kernel
+        _ => Ok(())
+    }
+}
+
+// Note: this is the thread's current priority, which might be boosted by priority inheritance (see arbitrate_lock)
+pub fn get_thread_priority(thread_handle: Handle) -> Result<i32> {
+    register_emu_proc_post_svc_guard!();
+
+    let thread = resolve_handle::<KThread>(thread_handle)?;
+    let priority = thread.get().priority;
+    Ok(priority)
+}
+
+// Note: this SVC has no result, it just returns the core number
+pub fn get_current_processor_number() -> i32 {
+    register_emu_proc_post_svc_guard!();
+
+    let cpu_core = get_current_thread().get().cur_core;
+    cpu_core
+}
+
+// Note: this SVC has no result either
+pub fn get_system_tick() -> u64 {
+    register_emu_proc_post_svc_guard!();
+
+    super::get_system_tick()
+}
+
+pub fn get_info(raw_info_type: u32, handle: Handle, info_subtype: u64) -> Result<u64> {
+    register_emu_proc_post_svc_guard!();
+
+    let info_type = match InfoType::from(raw_info_type) {
+        Some(info_type) => info_type,
+        None => return result::ResultInvalidEnumValue::make_err()
+    };
+
+    match info_type {
+        InfoType::IdleTickCount => {
+            result_return_unless!(handle == INVALID_HANDLE, result::ResultInvalidHandle);
+
+            // Only the idle time of the current core can be queried
+            let cpu_core = get_current_thread().get().cur_core;
+            result_return_unless!((info_subtype == INFO_SUBTYPE_CURRENT_CORE) || (info_subtype == cpu_core as u64), result::ResultInvalidCombination);
+
+            let idle_stats = get_scheduler(cpu_core).get_idle_stats();
+            Ok(super::convert_to_ticks(idle_stats.parked_time))
         }
     }
 }
@@ -305,6 +549,39 @@ pub fn close_handle(handle: Handle) -> Result<()> {
     get_current_process().get().handle_table.close_handle(handle)
 }
 
+pub fn signal_event(event_handle: Handle) -> Result<()> {
+    register_emu_proc_post_svc_guard!();
+
+    let event = resolve_handle::<KEvent>(event_handle)?;
+    event.get().signal();
+    Ok(())
+}
+
+pub fn clear_event(event_handle: Handle) -> Result<()> {
+    register_emu_proc_post_svc_guard!();
+
+    // Note: both sides of the event can be cleared
+    if let Ok(event) = resolve_handle::<KEvent>(event_handle) {
+        event.get().clear();
+        return Ok(());
+    }
+
+    let readable_event = resolve_handle::<KReadableEvent>(event_handle)?;
+    let _guard = make_critical_section_guard();
+    readable_event.get().clear();
+    Ok(())
+}
+
+pub fn reset_signal(handle: Handle) -> Result<()> {
+    register_emu_proc_post_svc_guard!();
+
+    // TODO: processes can also be reset
+    let readable_event = resolve_handle::<KReadableEvent>(handle)?;
+    let _guard = make_critical_section_guard();
+    readable_event.get().reset()?;
+    Ok(())
+}
+
 pub fn wait_synchronization(handles: &[Handle], timeout: i64) -> Result<usize> {
     register_emu_proc_post_svc_guard!();
     
@@ -312,7 +589,7 @@ pub fn wait_synchronization(handles: &[Handle], timeout: i64) -> Result<usize> {
 
     let mut sync_objs: Vec<Shared<dyn KSynchronizationObject>> = Vec::with_capacity(handles.len());
     for handle in handles {
-        let sync_obj = get_current_process().get().handle_table.get_handle_sync_obj(*handle)?;
+        let sync_obj = resolve_sync_handle(*handle)?;
         // sync_obj.get().increment_refcount();
 
         sync_objs.push(sync_obj);
@@ -345,12 +622,59 @@ pub fn send_sync_request(client_session_handle: Handle) -> Result<()> {
     register_emu_proc_post_svc_guard!();
     
     // log_line!("SendSyncRequest with handle {:#X}", client_session_handle);
-    let client_session = get_current_process().get().handle_table.get_handle_obj::<KClientSession>(client_session_handle)?;
+    if trace::is_enabled() {
+        let command_type = get_current_thread().get().get_thread_local_region().get_ipc_command_type();
+        trace::record_ipc_request(client_session_handle, command_type);
+    }
+
+    let client_session = resolve_handle::<KClientSession>(client_session_handle)?;
     
+    let start_instant = Instant::now();
     let rc = client_session.get().send_sync_request(None);
+    metrics::record_ipc_request_latency(start_instant.elapsed());
     rc
 }
 
+pub fn get_future_thread_info(timeout: i64) -> Result<(LastThreadContext, u64)> {
+    register_emu_proc_post_svc_guard!();
+
+    result_return_unless!(timeout >= 0, result::ResultInvalidArgument);
+
+    // Look for the first thread of this process which is scheduled to wake up within the given timeout
+    let process_id = get_current_process().get().id;
+    let deadline = Instant::now() + Duration::from_nanos(timeout as u64);
+    match get_time_manager().find_future_thread_info(process_id, deadline) {
+        Some(info) => Ok(info),
+        None => result::ResultNoThread::make_err()
+    }
+}
+
+pub fn get_last_thread_info() -> Result<(LastThreadContext, u64, LastThreadInfoFlag)> {
+    register_emu_proc_post_svc_guard!();
+
+    let cur_core = get_current_thread().get().cur_core;
+    let prev_thread = match get_scheduler(cur_core).prev_thread.clone() {
+        Some(thread) => thread,
+        None => return result::ResultNoThread::make_err()
+    };
+
+    let is_owned_by_current_process = match prev_thread.get().owner_process.as_ref() {
+        Some(owner_process) => owner_process.ptr_eq(&get_current_process()),
+        None => false
+    };
+    result_return_unless!(is_owned_by_current_process, result::ResultUnknownThread);
+
+    let context = prev_thread.get().get_last_thread_context()?;
+    let tls_address = prev_thread.get().get_tls_address();
+    // A non-runnable thread was switched out while waiting inside a SVC, instead of being preempted
+    let flags = match prev_thread.get().state.get_low_flags() == ThreadState::Runnable {
+        true => LastThreadInfoFlag::None(),
+        false => LastThreadInfoFlag::ThreadInSystemCall()
+    };
+
+    Ok((context, tls_address, flags))
+}
+
 pub fn break_(reason: BreakReason, arg: &[u8]) -> Result<()> {
     register_emu_proc_post_svc_guard!();
     
@@ -358,6 +682,14 @@ pub fn break_(reason: BreakReason, arg: &[u8]) -> Result<()> {
         let actual_reason = reason.without_notification_flag();
         log_line!("[Break] Notified, reason: {:?}", actual_reason);
     }
+    else if get_current_process().get().is_debugged() {
+        // Debugged processes report the break to their debugger instead of aborting
+        let thread_id = get_current_thread().get().id;
+        let address = get_current_thread().get().get_last_thread_context()?.pc;
+        let event = DebugEventInfo::exception(thread_id, DebugExceptionType::UserBreak, address, [reason as u64, 0, arg.len() as u64, 0]);
+        debug::notify_debug_event(&get_current_process(), event);
+        log_line!("[Break] Reported to debugger, reason: {:?}", reason);
+    }
     else {
         if arg.len() == mem::size_of::<ResultCode>() {
             let rc: ResultCode = util::slice_read_val(arg, None)?;
@@ -371,26 +703,52 @@ pub fn break_(reason: BreakReason, arg: &[u8]) -> Result<()> {
     Ok(())
 }
 
+pub fn return_from_exception(rc: ResultCode) -> Result<()> {
+    register_emu_proc_post_svc_guard!();
+
+    let mut thread = get_current_thread();
+    result_return_unless!(get_current_process().get().in_user_exception, result::ResultNotHandled);
+
+    if rc.is_failure() {
+        // The handler wasn't able to handle the exception, same as an unhandled one
+        panic!("[ReturnFromException] Unhandled user exception, with result code {0} ({0:?})", rc);
+    }
+
+    KThread::return_from_user_exception(&mut thread)?;
+    get_current_process().get().in_user_exception = false;
+    Ok(())
+}
+
 pub fn output_debug_string(msg: &str) -> Result<()> {
     register_emu_proc_post_svc_guard!();
     
     log_line!("[OutputDebugString] {}", msg);
+
+    let (process_id, program_id) = {
+        let process = get_current_process();
+        let process_guard = process.get();
+        (process_guard.id, process_guard.npdm.aci0.program_id)
+    };
+    output::write_output(process_id, program_id, OutputChannel::DebugString, msg);
     Ok(())
 }
 
+// Anonymous sessions (not connected through any port), like the ones backing objects returned by services
 pub fn create_session(is_light: bool, _name_addr: u64) -> Result<(Handle, Handle)> {
     register_emu_proc_post_svc_guard!();
     
-    get_current_process().get().resource_limit.get().reserve(LimitableResource::Session, 1, None)?;
+    let resource_limit = get_current_process().get().resource_limit.clone();
+    KResourceLimit::reserve(&resource_limit, LimitableResource::Session, 1, None)?;
 
+    // Note: from here on the session owns the reservation, thus closing both handles (or failing to allocate them) releases it
     let (server_session, client_session) = match is_light {
         true => {
-            todo!("Light IPC support for CreateSession")
+            let (server_session, client_session) = KLightSession::new();
+
+            (server_session.as_any(), client_session.as_any())
         },
         false => {
-            let session = KSession::new(None);
-            let server_session = session.get().server_session.clone();
-            let client_session = session.get().client_session.clone();
+            let (server_session, client_session) = KSession::new(None);
 
             (server_session.as_any(), client_session.as_any())
         }
@@ -409,10 +767,33 @@ pub fn create_session(is_light: bool, _name_addr: u64) -> Result<(Handle, Handle
     Ok((server_session_handle, client_session_handle))
 }
 
+pub fn create_event() -> Result<(Handle, Handle)> {
+    register_emu_proc_post_svc_guard!();
+
+    let resource_limit = get_current_process().get().resource_limit.clone();
+    KResourceLimit::reserve(&resource_limit, LimitableResource::Event, 1, None)?;
+
+    // Note: from here on the event owns the reservation, thus closing both handles (or failing to allocate them) releases it
+    let event = KEvent::new_reserved(resource_limit);
+    let readable_event = event.get().readable_event.clone();
+
+    let writable_event_handle = get_current_process().get().handle_table.allocate_handle_set(event)?;
+
+    let readable_event_handle_fail_guard = guard((), |()| {
+        let _ = get_current_process().get().handle_table.close_handle(writable_event_handle);
+    });
+
+    let readable_event_handle = get_current_process().get().handle_table.allocate_handle_set(readable_event)?;
+
+    ScopeGuard::into_inner(readable_event_handle_fail_guard);
+
+    Ok((writable_event_handle, readable_event_handle))
+}
+
 pub fn accept_session(server_port_handle: Handle) -> Result<Handle> {
     register_emu_proc_post_svc_guard!();
     
-    let server_port = get_current_process().get().handle_table.get_handle_obj::<KServerPort>(server_port_handle)?;
+    let server_port = resolve_handle::<KServerPort>(server_port_handle)?;
 
     let server_session_handle = get_current_process().get().handle_table.allocate_handle()?;
 
@@ -443,7 +824,7 @@ pub fn reply_and_receive(handles: &[Handle], reply_target_session_handle: Handle
 
     let mut sync_objs: Vec<Shared<dyn KSynchronizationObject>> = Vec::with_capacity(handles.len());
     for handle in handles {
-        let sync_obj = get_current_process().get().handle_table.get_handle_sync_obj(*handle)?;
+        let sync_obj = resolve_sync_handle(*handle)?;
         // sync_obj.get().increment_refcount();
 
         sync_objs.push(sync_obj);
@@ -451,7 +832,7 @@ pub fn reply_and_receive(handles: &[Handle], reply_target_session_handle: Handle
 
     if reply_target_session_handle != INVALID_HANDLE {
         // log_line!("Reply with {:#X}", reply_target_session_handle);
-        let mut reply_target_session = get_current_process().get().handle_table.get_handle_obj::<KServerSession>(reply_target_session_handle)?;
+        let mut reply_target_session = resolve_handle::<KServerSession>(reply_target_session_handle)?;
 
         KServerSession::reply(&mut reply_target_session, None)?;
     }
@@ -459,7 +840,7 @@ pub fn reply_and_receive(handles: &[Handle], reply_target_session_handle: Handle
     'w: loop {
         let idx = wait_for_sync_objects(&mut sync_objs, timeout)?;
         // log_line!("Receive with {:#X}", handles[idx]);
-        let server_session = get_current_process().get().handle_table.get_handle_obj::<KServerSession>(handles[idx])?;
+        let server_session = resolve_handle::<KServerSession>(handles[idx])?;
 
         match server_session.get().receive(None) {
             Ok(()) => return Ok(idx),
@@ -477,15 +858,15 @@ pub fn reply_and_receive(handles: &[Handle], reply_target_session_handle: Handle
 pub fn create_port(max_sessions: u32, is_light: bool, name_addr: u64) -> Result<(Handle, Handle)> {
     register_emu_proc_post_svc_guard!();
     
-    let port = KPort::new(max_sessions, is_light, name_addr);
+    let (server_port, client_port) = KPort::new(max_sessions, is_light, name_addr);
 
-    let server_port_handle = get_current_process().get().handle_table.allocate_handle_set(port.get().server_port.clone())?;
+    let server_port_handle = get_current_process().get().handle_table.allocate_handle_set(server_port)?;
 
     let alloc_client_handle_fail_guard = guard((), |()| {
         let _ = get_current_process().get().handle_table.close_handle(server_port_handle);
     });
 
-    let client_port_handle = get_current_process().get().handle_table.allocate_handle_set(port.get().client_port.clone())?;
+    let client_port_handle = get_current_process().get().handle_table.allocate_handle_set(client_port)?;
 
     ScopeGuard::into_inner(alloc_client_handle_fail_guard);
     Ok((server_port_handle, client_port_handle))
@@ -496,15 +877,16 @@ pub fn manage_named_port(name: &str, max_sessions: u32) -> Result<Handle> {
     
     result_return_unless!(name.len() <= 11, result::ResultOutOfRange);
 
-    let port = KPort::new(max_sessions, false, 0);
+    let (server_port, client_port) = KPort::new(max_sessions, false, 0);
+    client_port.get().parent.get().set_name(String::from(name));
 
-    let server_port_handle = get_current_process().get().handle_table.allocate_handle_set(port.get().server_port.clone())?;
+    let server_port_handle = get_current_process().get().handle_table.allocate_handle_set(server_port)?;
     
     let register_name_fail_guard = guard((), |()| {
         let _ = get_current_process().get().handle_table.close_handle(server_port_handle);
     });
 
-    register_named_object(port.get().client_port.clone(), name)?;
+    register_named_object(client_port, name)?;
 
     ScopeGuard::into_inner(register_name_fail_guard);
     Ok(server_port_handle)
@@ -513,7 +895,7 @@ pub fn manage_named_port(name: &str, max_sessions: u32) -> Result<Handle> {
 pub fn connect_to_port(client_port_handle: Handle) -> Result<Handle> {
     register_emu_proc_post_svc_guard!();
     
-    let mut client_port = get_current_process().get().handle_table.get_handle_obj::<KClientPort>(client_port_handle)?;
+    let mut client_port = resolve_handle::<KClientPort>(client_port_handle)?;
     let client_session_handle = get_current_process().get().handle_table.allocate_handle()?;
 
     let connect_fail_guard = guard((), |()| {
@@ -525,4 +907,575 @@ pub fn connect_to_port(client_port_handle: Handle) -> Result<Handle> {
     ScopeGuard::into_inner(connect_fail_guard);
     client_session.get().decrement_refcount();
     Ok(client_session_handle)
-}
\ No newline at end of file
+}
+pub fn set_thread_activity(thread_handle: Handle, raw_activity: u32) -> Result<()> {
+    register_emu_proc_post_svc_guard!();
+
+    let activity = match ThreadActivity::from(raw_activity) {
+        Some(activity) => activity,
+        None => return result::ResultInvalidEnumValue::make_err()
+    };
+
+    let mut thread = resolve_handle::<KThread>(thread_handle)?;
+    let is_current_process_thread = match thread.get().owner_process.as_ref() {
+        Some(owner_process) => owner_process.ptr_eq(&get_current_process()),
+        None => false
+    };
+    result_return_unless!(is_current_process_thread, result::ResultInvalidHandle);
+    result_return_if!(thread.ptr_eq(&get_current_thread()), result::ResultBusy);
+
+    KThread::set_activity(&mut thread, activity == ThreadActivity::Paused)
+}
+
+pub fn set_process_activity(process_handle: Handle, raw_activity: u32) -> Result<()> {
+    register_emu_proc_post_svc_guard!();
+
+    let activity = match ProcessActivity::from(raw_activity) {
+        Some(activity) => activity,
+        None => return result::ResultInvalidEnumValue::make_err()
+    };
+
+    let mut process = resolve_handle::<KProcess>(process_handle)?;
+    result_return_if!(process.ptr_eq(&get_current_process()), result::ResultBusy);
+
+    KProcess::set_activity(&mut process, activity == ProcessActivity::Paused)
+}
+
+pub fn debug_active_process(process_id: u64) -> Result<Handle> {
+    register_emu_proc_post_svc_guard!();
+
+    let process = find_process_by_id(process_id)?;
+    result_return_if!(process.ptr_eq(&get_current_process()), result::ResultInvalidState);
+
+    let debug = KDebug::attach(&process)?;
+    get_current_process().get().handle_table.allocate_handle_set(debug)
+}
+
+pub fn break_debug_process(debug_handle: Handle) -> Result<()> {
+    register_emu_proc_post_svc_guard!();
+
+    let debug = resolve_handle::<KDebug>(debug_handle)?;
+    KDebug::break_process(&debug);
+    Ok(())
+}
+
+// Continues the given threads, every other thread (ContinueOthers) or all of them (ContinueAll)
+pub fn continue_debug_event(debug_handle: Handle, flags: ContinueDebugFlag, thread_ids: &[u64]) -> Result<()> {
+    register_emu_proc_post_svc_guard!();
+
+    let continue_all = flags.contains(ContinueDebugFlag::ContinueAll());
+    let continue_others = flags.contains(ContinueDebugFlag::ContinueOthers());
+    result_return_if!(continue_all && continue_others, result::ResultInvalidEnumValue);
+
+    let debug = resolve_handle::<KDebug>(debug_handle)?;
+    KDebug::continue_process(&debug, |thread_id| continue_all || (thread_ids.contains(&thread_id) != continue_others));
+    Ok(())
+}
+
+pub fn get_debug_event(debug_handle: Handle) -> Result<DebugEventInfo> {
+    register_emu_proc_post_svc_guard!();
+
+    let debug = resolve_handle::<KDebug>(debug_handle)?;
+    let event = debug.get().pop_event()?;
+    Ok(event)
+}
+
+// Checked before accessing anything, since the size comes from the guest (and accesses are split in chunks, see emu::kern)
+pub fn check_debug_process_memory(debug_handle: Handle, address: u64, size: usize) -> Result<()> {
+    result_return_if!(size == 0, result::ResultInvalidSize);
+    result_return_if!(address.checked_add(size as u64).is_none(), result::ResultInvalidCurrentMemory);
+
+    let debug = resolve_handle::<KDebug>(debug_handle)?;
+    let process = debug.get().process.clone();
+    let process_guard = process.get();
+    match process_guard.cpu_ctx.as_ref() {
+        Some(cpu_ctx) => {
+            result_return_unless!(cpu_ctx.contains_range(address, size), result::ResultInvalidCurrentMemory);
+            Ok(())
+        },
+        None => result::ResultInvalidState::make_err()
+    }
+}
+
+pub fn read_debug_process_memory(debug_handle: Handle, address: u64, data: &mut [u8]) -> Result<()> {
+    register_emu_proc_post_svc_guard!();
+
+    result_return_if!(data.is_empty(), result::ResultInvalidSize);
+    result_return_if!(address.checked_add(data.len() as u64).is_none(), result::ResultInvalidCurrentMemory);
+
+    let debug = resolve_handle::<KDebug>(debug_handle)?;
+    let process = debug.get().process.clone();
+    let process_guard = process.get();
+    match process_guard.cpu_ctx.as_ref() {
+        // Note: only module memory is accessible this way (thread stacks/TLRs aren't part of the process context)
+        Some(cpu_ctx) => cpu_ctx.read_memory(address, data).map_err(|_| result::ResultInvalidCurrentMemory::make()),
+        None => result::ResultInvalidState::make_err()
+    }
+}
+
+pub fn write_debug_process_memory(debug_handle: Handle, address: u64, data: &[u8]) -> Result<()> {
+    register_emu_proc_post_svc_guard!();
+
+    result_return_if!(data.is_empty(), result::ResultInvalidSize);
+    result_return_if!(address.checked_add(data.len() as u64).is_none(), result::ResultInvalidCurrentMemory);
+
+    let debug = resolve_handle::<KDebug>(debug_handle)?;
+    let process = debug.get().process.clone();
+    let mut process_guard = process.get();
+    match process_guard.cpu_ctx.as_mut() {
+        Some(cpu_ctx) => cpu_ctx.write_memory(address, data).map_err(|_| result::ResultInvalidCurrentMemory::make()),
+        None => result::ResultInvalidState::make_err()
+    }
+}
+
+// Note: the current thread's execution context is used for mutex values, since (unlike the process context) it has all the guest memory mapped, thread stacks/TLRs included
+
+fn read_current_thread_memory_val<T: Copy>(address: u64) -> Result<T> {
+    let cur_thread = get_current_thread();
+    let exec_ctx_handle = match cur_thread.get().cpu_exec_ctx.as_ref() {
+        Some(exec_ctx) => exec_ctx.get_handle(),
+        None => return result::ResultInvalidState::make_err()
+    };
+    exec_ctx_handle.read_memory_val(address).map_err(|_| result::ResultInvalidCurrentMemory::make())
+}
+
+fn write_current_thread_memory_val<T: Copy>(address: u64, t: T) -> Result<()> {
+    let cur_thread = get_current_thread();
+    let mut exec_ctx_handle = match cur_thread.get().cpu_exec_ctx.as_ref() {
+        Some(exec_ctx) => exec_ctx.get_handle(),
+        None => return result::ResultInvalidState::make_err()
+    };
+    exec_ctx_handle.write_memory_val(address, t).map_err(|_| result::ResultInvalidCurrentMemory::make())
+}
+
+pub const MUTEX_HAS_WAITERS_FLAG: u32 = 0x40000000;
+
+pub fn arbitrate_lock(owner_thread_handle: Handle, mutex_address: u64, requester_thread_handle: Handle) -> Result<()> {
+    register_emu_proc_post_svc_guard!();
+
+    result_return_unless!((mutex_address & 0x3) == 0, result::ResultInvalidAddress);
+
+    let mut cur_thread = get_current_thread();
+    {
+        let _guard = make_critical_section_guard();
+
+        result_return_if!(cur_thread.get().is_termination_requested(), result::ResultTerminationRequested);
+
+        cur_thread.get().signaled_obj = None;
+        cur_thread.get().sync_result = ResultSuccess::make();
+
+        // The mutex might have been released (or taken by someone else) in the meantime
+        let mutex_value: u32 = read_current_thread_memory_val(mutex_address)?;
+        if mutex_value != (owner_thread_handle | MUTEX_HAS_WAITERS_FLAG) {
+            return Ok(());
+        }
+
+        let mut owner_thread = resolve_handle::<KThread>(owner_thread_handle)?;
+        cur_thread.get().mutex_address = mutex_address;
+        cur_thread.get().mutex_requester_handle = requester_thread_handle;
+
+        // This boosts the owner (and whoever it's waiting on) if we have a higher priority
+        KThread::add_mutex_waiter(&mut owner_thread, &cur_thread);
+        KThread::reschedule(&mut cur_thread, ThreadState::Waiting);
+
+        get_critical_section().leave();
+        get_critical_section().enter();
+
+        // We were woken up without being handed the mutex (termination, etc.)
+        let mutex_owner = cur_thread.get().mutex_owner.clone();
+        if let Some(mut mutex_owner) = mutex_owner {
+            KThread::remove_mutex_waiter(&mut mutex_owner, &cur_thread);
+        }
+    }
+
+    let sync_result = cur_thread.get().sync_result;
+    sync_result.to(())
+}
+
+pub fn arbitrate_unlock(mutex_address: u64) -> Result<()> {
+    register_emu_proc_post_svc_guard!();
+
+    result_return_unless!((mutex_address & 0x3) == 0, result::ResultInvalidAddress);
+
+    let _guard = make_critical_section_guard();
+
+    let mut cur_thread = get_current_thread();
+    let (mut new_owner, waiter_count) = KThread::relinquish_mutex(&mut cur_thread, mutex_address);
+
+    let mut mutex_value: u32 = 0;
+    if let Some(new_owner) = new_owner.as_mut() {
+        mutex_value = new_owner.get().mutex_requester_handle;
+        if waiter_count > 1 {
+            mutex_value |= MUTEX_HAS_WAITERS_FLAG;
+        }
+
+        new_owner.get().signaled_obj = None;
+        new_owner.get().sync_result = ResultSuccess::make();
+        KThread::release_and_resume(new_owner);
+    }
+
+    let rc = write_current_thread_memory_val(mutex_address, mutex_value);
+    if let Err(rc) = rc {
+        if let Some(new_owner) = new_owner.as_ref() {
+            new_owner.get().sync_result = rc;
+        }
+    }
+    rc
+}
+
+// Memory size of retail consoles
+const MAX_PHYSICAL_MEMORY_SIZE: u64 = 0x100000000;
+
+// Processes created by the guest (a loader, for instance) start with an empty address space besides their code region, which their creator populates before starting them
+
+pub fn create_process(params: CreateProcessParameter, kernel_caps: &[u32]) -> Result<Handle> {
+    register_emu_proc_post_svc_guard!();
+
+    let address_space = match params.flags.get_address_space() {
+        Some(address_space) => address_space,
+        None => return result::ResultInvalidEnumValue::make_err()
+    };
+    let is_64bit = params.flags.contains(CreateProcessFlags::Is64Bit());
+
+    result_return_unless!(is_page_aligned(params.code_address), result::ResultInvalidAddress);
+    result_return_if!(params.code_num_pages == 0, result::ResultInvalidSize);
+    let code_size = params.code_num_pages as usize * PAGE_SIZE;
+    let code_end_address = match params.code_address.checked_add(code_size as u64) {
+        Some(end_address) => end_address,
+        None => return result::ResultInvalidMemoryRegion::make_err()
+    };
+    if !is_64bit {
+        result_return_if!(code_end_address > u32::MAX as u64, result::ResultInvalidMemoryRegion);
+    }
+    result_return_if!(code_end_address > get_address_space_end(address_space), result::ResultInvalidMemoryRegion);
+
+    let kernel_caps_data: Vec<u8> = kernel_caps.iter().flat_map(|cap| cap.to_le_bytes()).collect();
+    let aci0_kernel_caps = npdm::KernelCapabilityData::new(&kernel_caps_data).map_err(|_| result::ResultInvalidArgument::make())?;
+    let acid_kernel_caps = npdm::KernelCapabilityData::new(&kernel_caps_data).map_err(|_| result::ResultInvalidArgument::make())?;
+
+    // Note: an unspecified (or zero) handle table size means the maximum one
+    let handle_table_size = match aci0_kernel_caps.handle_table_size {
+        Some(size) if size > 0 => size as usize,
+        _ => KHandleTable::MAX_SIZE
+    };
+
+    // The main thread values are only known when the process is started (see start_process)
+    let process_name = params.name.get_string()?;
+    let mut npdm = EmulatedProcess::make_npdm(&process_name, 0, 0, ProgramId(params.program_id), aci0_kernel_caps.enabled_svcs.clone(), handle_table_size)?;
+    npdm.meta.flags = npdm::MetaFlags::new(is_64bit, address_space, false, false);
+    npdm.meta.version = params.version;
+    npdm.aci0_kernel_capabilities = aci0_kernel_caps;
+    npdm.acid_kernel_capabilities = acid_kernel_caps;
+    npdm.aci0_kernel_capabilities.handle_table_size = Some(handle_table_size as u16);
+
+    let resource_limit = match params.resource_limit_handle {
+        INVALID_HANDLE => None,
+        resource_limit_handle => Some(resolve_handle::<KResourceLimit>(resource_limit_handle)?)
+    };
+
+    // The code memory is allocated right away (and in full), thus its size must be checked first
+    // Note: without a resource limit (thus the system one on the actual kernel) it can't be bigger than the console memory anyway
+    let remaining_memory_size = match resource_limit.as_ref() {
+        Some(resource_limit) => resource_limit.get().get_remaining_value(LimitableResource::PhysicalMemory),
+        None => MAX_PHYSICAL_MEMORY_SIZE
+    };
+    result_return_if!(code_size as u64 > remaining_memory_size, result::ResultLimitReached);
+
+    let mut cpu_ctx = cpu::Context::new();
+    cpu_ctx.arch = cpu::Architecture::from_npdm(&npdm);
+    cpu_ctx.map_memory(String::from("code"), params.code_address, code_size, cpu::MemoryPermission::READ | cpu::MemoryPermission::WRITE, KMemoryState::Code())?;
+
+    let process = KProcess::new(Some(cpu_ctx), npdm)?;
+    process.get().code_address = params.code_address;
+    if let Some(resource_limit) = resource_limit {
+        process.get().resource_limit = resource_limit;
+    }
+
+    get_current_process().get().handle_table.allocate_handle_set(process)
+}
+
+pub fn start_process(process_handle: Handle, main_thread_priority: i32, main_thread_cpu_core: i32, main_thread_stack_size: usize) -> Result<()> {
+    register_emu_proc_post_svc_guard!();
+
+    result_return_unless!((0..PRIORITY_COUNT as i32).contains(&main_thread_priority), result::ResultInvalidPriority);
+    result_return_unless!((0..CPU_CORE_COUNT as i32).contains(&main_thread_cpu_core), result::ResultInvalidCoreId);
+    result_return_if!(main_thread_stack_size > u32::MAX as usize, result::ResultOutOfMemory);
+
+    let mut process = resolve_handle::<KProcess>(process_handle)?;
+    let (process_name, code_address) = {
+        let mut process_guard = process.get();
+        result_return_unless!(process_guard.state == ProcessState::Created, result::ResultInvalidState);
+
+        process_guard.npdm.meta.main_thread_priority = main_thread_priority as u8;
+        process_guard.npdm.meta.main_thread_cpu_core = main_thread_cpu_core as u8;
+        process_guard.npdm.meta.main_thread_stack_size = util::align_up(main_thread_stack_size, PAGE_SIZE) as u32;
+        (process_guard.npdm.meta.name.get_string()?, process_guard.code_address)
+    };
+
+    let (mut main_thread, main_thread_handle) = KProcess::create_main_thread(&mut process, format!("ext.{}.MainThread", process_name), code_address)?;
+    KThread::start_exec(&mut main_thread, 0u64, main_thread_handle)
+}
+
+pub fn get_process_info(process_handle: Handle, raw_info_type: u32) -> Result<u64> {
+    register_emu_proc_post_svc_guard!();
+
+    let info_type = match ProcessInfoType::from(raw_info_type) {
+        Some(info_type) => info_type,
+        None => return result::ResultInvalidEnumValue::make_err()
+    };
+
+    let process = resolve_handle::<KProcess>(process_handle)?;
+    let process_guard = process.get();
+    match info_type {
+        ProcessInfoType::ProcessState => {
+            let state = match (process_guard.state, process_guard.is_debugged()) {
+                (ProcessState::Created, true) => ProcessState::CreatedAttached,
+                (ProcessState::Started, true) => ProcessState::StartedAttached,
+                (state, _) => state
+            };
+            Ok(state as u64)
+        }
+    }
+}
+
+pub fn map_process_code_memory(process_handle: Handle, dst_address: u64, src_address: u64, size: usize) -> Result<()> {
+    register_emu_proc_post_svc_guard!();
+
+    check_page_aligned_range(dst_address, size)?;
+    check_page_aligned_range(src_address, size)?;
+
+    let process = resolve_handle::<KProcess>(process_handle)?;
+    let mut process_guard = process.get();
+    match process_guard.cpu_ctx.as_mut() {
+        // Note: the code is mapped as read-write, its creator is expected to set the final permissions afterwards
+        Some(cpu_ctx) => cpu_ctx.move_memory(String::from("code"), dst_address, src_address, size, cpu::MemoryPermission::READ | cpu::MemoryPermission::WRITE, KMemoryState::AliasCode()),
+        None => result::ResultInvalidState::make_err()
+    }
+}
+
+pub fn set_process_memory_permission(process_handle: Handle, address: u64, size: usize, perm: MemoryPermission) -> Result<()> {
+    register_emu_proc_post_svc_guard!();
+
+    check_page_aligned_range(address, size)?;
+
+    let new_perm = match perm {
+        perm if perm == MemoryPermission::None() => cpu::MemoryPermission::NONE,
+        perm if perm == MemoryPermission::Read() => cpu::MemoryPermission::READ,
+        perm if perm == (MemoryPermission::Read() | MemoryPermission::Write()) => cpu::MemoryPermission::READ | cpu::MemoryPermission::WRITE,
+        perm if perm == (MemoryPermission::Read() | MemoryPermission::Execute()) => cpu::MemoryPermission::READ | cpu::MemoryPermission::EXEC,
+        _ => return result::ResultInvalidNewMemoryPermission::make_err()
+    };
+
+    let process = resolve_handle::<KProcess>(process_handle)?;
+    let mut process_guard = process.get();
+    match process_guard.cpu_ctx.as_mut() {
+        Some(cpu_ctx) => cpu_ctx.set_memory_permission(address, size, new_perm),
+        None => result::ResultInvalidState::make_err()
+    }
+}
+
+pub fn map_process_memory(dst_address: u64, process_handle: Handle, src_address: u64, size: usize) -> Result<()> {
+    register_emu_proc_post_svc_guard!();
+
+    check_page_aligned_range(dst_address, size)?;
+    check_page_aligned_range(src_address, size)?;
+
+    // Note: the source regions are gathered before locking the current process, since both processes might be the same one
+    let process = resolve_handle::<KProcess>(process_handle)?;
+    let src_regions = {
+        let mut process_guard = process.get();
+        match process_guard.cpu_ctx.as_mut() {
+            Some(cpu_ctx) => cpu_ctx.share_memory(src_address, size, KMemoryState::CanMapProcess())?,
+            None => return result::ResultInvalidState::make_err()
+        }
+    };
+
+    let dst_regions: Vec<cpu::MemoryRegion> = src_regions.into_iter().map(|mut region| {
+        region.address = dst_address + (region.address - src_address);
+        region.perm = cpu::MemoryPermission::READ | cpu::MemoryPermission::WRITE;
+        region.state = KMemoryState::SharedCode();
+        region
+    }).collect();
+
+    let cur_process = get_current_process();
+    let mut cur_process_guard = cur_process.get();
+    match cur_process_guard.cpu_ctx.as_mut() {
+        Some(cpu_ctx) => cpu_ctx.map_regions(String::from("process_memory"), dst_regions),
+        None => result::ResultInvalidState::make_err()
+    }
+}
+
+const fn get_address_space_end(address_space: npdm::AddressSpaceType) -> u64 {
+    match address_space {
+        npdm::AddressSpaceType::AS32Bit | npdm::AddressSpaceType::AS32BitNoReserved => 1 << 32,
+        npdm::AddressSpaceType::AS64BitLegacy => 1 << 36,
+        npdm::AddressSpaceType::AS64Bit => 1 << 39
+    }
+}
+
+// Unmapped ranges are reported as free memory between the surrounding mappings, and as inaccessible past the address space end
+// Note: there's no actual page table, thus every mapping is reported on its own (the actual kernel merges identical adjacent blocks) and attributes/refcounts are always zero
+pub fn query_memory(address: u64) -> Result<(MemoryInfo, u32)> {
+    register_emu_proc_post_svc_guard!();
+
+    let cur_process = get_current_process();
+    let address_space_end = get_address_space_end(cur_process.get().npdm.meta.flags.get_address_space());
+    let memory_map = KProcess::get_memory_map(&cur_process);
+
+    let (base_address, end_address, state, perm) = match memory_map.iter().find(|entry| entry.contains(address)) {
+        Some(entry) => {
+            log_line!("[QueryMemory] {:#X} -> {}", address, entry);
+            (entry.address, entry.end(), convert_memory_state(entry.state), MemoryPermission::from(entry.perm.bits()))
+        },
+        None if address >= address_space_end => {
+            log_line!("[QueryMemory] {:#X} -> past the address space end", address);
+            (address_space_end, 0, MemoryState::Inaccessible, MemoryPermission::None())
+        },
+        None => {
+            log_line!("[QueryMemory] {:#X} -> unmapped", address);
+            let base_address = memory_map.iter().map(|entry| entry.end()).filter(|&end| end <= address).max().unwrap_or(0);
+            let end_address = memory_map.iter().map(|entry| entry.address).filter(|&start| start > address).min().unwrap_or(address_space_end);
+            (base_address, end_address, MemoryState::Free, MemoryPermission::None())
+        }
+    };
+
+    let info = MemoryInfo {
+        base_address: base_address,
+        // Note: the inaccessible range ends at the end of the (64-bit) address space, thus it wraps around
+        size: end_address.wrapping_sub(base_address) as usize,
+        state: state,
+        attr: MemoryAttribute::None(),
+        perm: perm,
+        ipc_refcount: 0,
+        device_refcount: 0,
+        pad: 0
+    };
+    // Page info (always zero)
+    Ok((info, 0))
+}
+
+// Shared memory is mapped with the owner permission by the process which created it, and with the remote one by the rest
+pub fn map_shared_memory(shmem_handle: Handle, address: u64, size: usize, perm: MemoryPermission) -> Result<()> {
+    register_emu_proc_post_svc_guard!();
+
+    check_page_aligned_range(address, size)?;
+
+    let cur_process = get_current_process();
+    let shmem = resolve_handle::<KSharedMemory>(shmem_handle)?;
+    let (data, owner_perm, remote_perm) = {
+        let shmem_guard = shmem.get();
+        (shmem_guard.data.clone(), shmem_guard.owner_perm, shmem_guard.remote_perm)
+    };
+    result_return_unless!(size == data.len(), result::ResultInvalidSize);
+
+    // Note: there's no actual owner process tracking, thus either permission is accepted
+    let is_perm_valid = (perm == owner_perm) || (perm == remote_perm) || (remote_perm == MemoryPermission::DontCare());
+    result_return_unless!(is_perm_valid, result::ResultInvalidNewMemoryPermission);
+    let region_perm = match perm {
+        perm if perm == MemoryPermission::Read() => cpu::MemoryPermission::READ,
+        perm if perm == (MemoryPermission::Read() | MemoryPermission::Write()) => cpu::MemoryPermission::READ | cpu::MemoryPermission::WRITE,
+        _ => return result::ResultInvalidNewMemoryPermission::make_err()
+    };
+
+    let mut region = cpu::MemoryRegion::from_shared(address, data, region_perm);
+    region.state = KMemoryState::Shared();
+
+    let mut cur_process_guard = cur_process.get();
+    match cur_process_guard.cpu_ctx.as_mut() {
+        Some(cpu_ctx) => cpu_ctx.map_regions(String::from("shared_memory"), vec![region]),
+        None => result::ResultInvalidState::make_err()
+    }
+}
+
+pub fn unmap_shared_memory(shmem_handle: Handle, address: u64, size: usize) -> Result<()> {
+    register_emu_proc_post_svc_guard!();
+
+    check_page_aligned_range(address, size)?;
+
+    let cur_process = get_current_process();
+    let shmem = resolve_handle::<KSharedMemory>(shmem_handle)?;
+    let data = shmem.get().data.clone();
+    result_return_unless!(size == data.len(), result::ResultInvalidSize);
+
+    let mut cur_process_guard = cur_process.get();
+    match cur_process_guard.cpu_ctx.as_mut() {
+        Some(cpu_ctx) => {
+            // The range must actually be this shared memory
+            let is_mapped = cpu_ctx.get_regions(address, size).iter().all(|region| (region.address == address) && Arc::ptr_eq(&region.data, &data));
+            result_return_unless!(is_mapped, result::ResultInvalidMemoryRegion);
+
+            cpu_ctx.unmap_regions(address, size, KMemoryState::Shared()).map(|_| ())
+        },
+        None => result::ResultInvalidState::make_err()
+    }
+}
+
+pub fn unmap_process_memory(dst_address: u64, process_handle: Handle, src_address: u64, size: usize) -> Result<()> {
+    register_emu_proc_post_svc_guard!();
+
+    check_page_aligned_range(dst_address, size)?;
+    check_page_aligned_range(src_address, size)?;
+
+    let process = resolve_handle::<KProcess>(process_handle)?;
+    let cur_process = get_current_process();
+
+    // The mapping must actually alias the given source range
+    let dst_regions: Vec<(u64, cpu::MemoryRegion)> = match cur_process.get().cpu_ctx.as_ref() {
+        Some(cpu_ctx) => cpu_ctx.get_regions(dst_address, size).iter().map(|region| (src_address + (region.address - dst_address), cpu::MemoryRegion::from_shared(region.address, region.data.clone(), region.perm))).collect(),
+        None => return result::ResultInvalidState::make_err()
+    };
+    let is_aliased = match process.get().cpu_ctx.as_ref() {
+        Some(cpu_ctx) => dst_regions.iter().all(|(region_src_address, dst_region)| cpu_ctx.get_regions(*region_src_address, dst_region.len()).iter().any(|src_region| (src_region.address == *region_src_address) && Arc::ptr_eq(&src_region.data, &dst_region.data))),
+        None => return result::ResultInvalidState::make_err()
+    };
+    result_return_unless!(is_aliased, result::ResultInvalidMemoryRegion);
+
+    let mut cur_process_guard = cur_process.get();
+    match cur_process_guard.cpu_ctx.as_mut() {
+        Some(cpu_ctx) => cpu_ctx.unmap_regions(dst_address, size, KMemoryState::SharedCode()).map(|_| ()),
+        None => result::ResultInvalidState::make_err()
+    }
+}
+
+// Note: guest data caches aren't emulated, but executable memory in the flushed range must be translated again (see cpu::Context::flush_code_cache)
+pub fn flush_entire_data_cache() -> Result<()> {
+    register_emu_proc_post_svc_guard!();
+
+    let cur_process = get_current_process();
+    let mut cur_process_guard = cur_process.get();
+    match cur_process_guard.cpu_ctx.as_mut() {
+        Some(cpu_ctx) => cpu_ctx.flush_code_cache(0, usize::MAX),
+        None => result::ResultInvalidState::make_err()
+    }
+}
+
+pub fn flush_data_cache(address: u64, size: usize) -> Result<()> {
+    register_emu_proc_post_svc_guard!();
+
+    // Nothing to flush
+    if size == 0 {
+        return Ok(());
+    }
+    result_return_if!(address.checked_add(size as u64).is_none(), result::ResultInvalidCurrentMemory);
+
+    let cur_process = get_current_process();
+    let mut cur_process_guard = cur_process.get();
+    match cur_process_guard.cpu_ctx.as_mut() {
+        Some(cpu_ctx) => cpu_ctx.flush_code_cache(address, size),
+        None => result::ResultInvalidState::make_err()
+    }
+}
+
+pub fn flush_process_data_cache(process_handle: Handle, address: u64, size: usize) -> Result<()> {
+    register_emu_proc_post_svc_guard!();
+
+    result_return_if!(size == 0, result::ResultInvalidSize);
+    result_return_if!(address.checked_add(size as u64).is_none(), result::ResultInvalidCurrentMemory);
+
+    let process = resolve_handle::<KProcess>(process_handle)?;
+    let mut process_guard = process.get();
+    match process_guard.cpu_ctx.as_mut() {
+        Some(cpu_ctx) => cpu_ctx.flush_code_cache(address, size),
+        None => result::ResultInvalidState::make_err()
+    }
+}
@@ -1,24 +1,38 @@
-use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicUsize, Ordering};
 use std::thread::Builder;
 use std::thread::JoinHandle;
 use std::time::{self, Duration};
-use parking_lot::Mutex;
+use parking_lot::{Mutex, MutexGuard};
 use rsevents::AutoResetEvent;
 use rsevents::Awaitable;
 use rsevents::ManualResetEvent;
 use rsevents::State;
+use crate::emu::cfg;
 use crate::emu::cpu;
-use crate::util::{Shared, RecursiveLock, new_recursive_lock};
+use crate::emu::disasm;
+use crate::emu::host_profiler;
+use crate::emu::metrics;
+use crate::emu::profiler;
+use crate::emu::run;
+use crate::emu::symbols;
+use crate::emu::trace;
+use crate::util::{self, Shared, WeakShared, RecursiveLock, new_recursive_lock};
 use crate::result::*;
-use crate::os::ThreadLocalRegion;
+use crate::os::{ExceptionInfo, ThreadContextDump, ThreadLocalRegion, ThreadType};
 use super::{KAutoObject, KFutureSchedulerObject, get_time_manager};
 use super::KSynchronizationObject;
+use super::svc;
+use super::debug::{self, DebugEventInfo, DebugExceptionType, ThreadExitReason};
 use super::proc::KProcess;
 use super::proc::has_current_process;
 use super::result;
 
 // KCriticalSection
 // Note: thanks Rust for only supporting mutex functionality through guards/wrapping objects, luckily parking_lot exposes raw mutex typea
+// The critical section protects the global scheduling state: thread states, the priority queue and the waiting lists of synchronization objects
+// Anything else has its own finer-grained lock (per-core scheduler state, condition variable queues, the time manager's invocations...), which may be taken inside the critical section but never the other way around
 
 pub struct KCriticalSection {
     lock: RecursiveLock,
@@ -122,36 +136,69 @@ pub const INVALID_CPU_CORE: i32 = -1;
 pub const PRIORITY_COUNT: usize = 0x40;
 pub const IDLE_THREAD_PRIORITY: i32 = 0x40;
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
-#[repr(u16)]
-pub enum ThreadState {
-    Initialized = 0,
-    Waiting = 1,
-    Runnable = 2,
-    Terminated = 3,
-
-    ProcessSuspended = 1 << 4,
-    ThreadSuspended = 1 << 5,
-    DebugSuspended = 1 << 6,
-    BacktraceSuspended = 1 << 7,
-    InitSuspended = 1 << 8,
-
-    LowMask = 0xF,
-    HighMask = 0xFFF0,
-    ForcePauseMask = 0x70,
+// The low nibble is the actual state, while the rest are the reasons the thread is force-paused for (suspend flags), which keep it out of the scheduler even if it's runnable
+// Note: this isn't an enum since any combination of a state with suspend flags is valid
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(transparent)]
+pub struct ThreadState(u16);
+
+#[allow(non_upper_case_globals)]
+impl ThreadState {
+    pub const Initialized: Self = Self(0);
+    pub const Waiting: Self = Self(1);
+    pub const Runnable: Self = Self(2);
+    pub const Terminated: Self = Self(3);
+
+    pub const ProcessSuspended: Self = Self(1 << 4);
+    pub const ThreadSuspended: Self = Self(1 << 5);
+    pub const DebugSuspended: Self = Self(1 << 6);
+    pub const BacktraceSuspended: Self = Self(1 << 7);
+    pub const InitSuspended: Self = Self(1 << 8);
+
+    pub const LowMask: Self = Self(0xF);
+    pub const HighMask: Self = Self(0xFFF0);
+    pub const ForcePauseMask: Self = Self(0x1F0);
 }
 
 impl ThreadState {
     pub fn update_flags(&mut self, other: Self) {
-        *self = unsafe {
-            core::mem::transmute(((*self as u16) & (ThreadState::HighMask as u16)) | ((other as u16) & (ThreadState::LowMask as u16)))
-        };
+        *self = Self((self.0 & Self::HighMask.0) | (other.0 & Self::LowMask.0));
     }
 
     pub fn get_low_flags(self) -> Self {
-        unsafe {
-            core::mem::transmute((self as u16) & (ThreadState::LowMask as u16))
+        Self(self.0 & Self::LowMask.0)
+    }
+
+    pub fn has_flags(self, flags: Self) -> bool {
+        (self.0 & flags.0) != 0
+    }
+
+    pub fn with_flags(self, flags: Self) -> Self {
+        Self(self.0 | flags.0)
+    }
+
+    pub fn without_flags(self, flags: Self) -> Self {
+        Self(self.0 & !flags.0)
+    }
+}
+
+impl std::fmt::Debug for ThreadState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.get_low_flags() {
+            Self::Initialized => write!(f, "Initialized")?,
+            Self::Waiting => write!(f, "Waiting")?,
+            Self::Runnable => write!(f, "Runnable")?,
+            Self::Terminated => write!(f, "Terminated")?,
+            low_flags => write!(f, "{:#X}", low_flags.0)?
+        };
+
+        let suspend_flags = [(Self::ProcessSuspended, "ProcessSuspended"), (Self::ThreadSuspended, "ThreadSuspended"), (Self::DebugSuspended, "DebugSuspended"), (Self::BacktraceSuspended, "BacktraceSuspended"), (Self::InitSuspended, "InitSuspended")];
+        for (flag, name) in suspend_flags.iter() {
+            if self.has_flags(*flag) {
+                write!(f, " + {}", name)?;
+            }
         }
+        Ok(())
     }
 }
 
@@ -165,6 +212,15 @@ pub fn new_thread_id() -> u64 {
     }
 }
 
+// Guest threads whose execution didn't finish yet, mostly to know when they all unwound on shutdown
+static mut G_RUNNING_GUEST_THREAD_COUNT: AtomicU64 = AtomicU64::new(0);
+
+pub fn get_running_guest_thread_count() -> u64 {
+    unsafe {
+        G_RUNNING_GUEST_THREAD_COUNT.load(Ordering::SeqCst)
+    }
+}
+
 static mut G_THREAD_RESELECTION_REQUESTED: bool = false;
 
 #[inline]
@@ -181,25 +237,29 @@ pub fn thread_reselection_requested() -> bool {
     }
 }
 
-static mut G_THREAD_SCHEDULER_WAIT_EVENTS: Vec<(Shared<KThread>, ManualResetEvent)> = Vec::new();
+// Note: kept outside of the threads themselves, since schedulers of every core (running threads of any process) signal/wait on these without locking the threads, which their own host threads may have locked meanwhile
+// Threads are keyed by their address (only weakly referenced, thus not kept alive by this), and the returned events stay valid even if threads are created/destroyed concurrently
+static mut G_THREAD_SCHEDULER_WAIT_EVENTS: Mutex<BTreeMap<usize, (WeakShared<KThread>, Arc<ManualResetEvent>)>> = parking_lot::const_mutex(BTreeMap::new());
+
+#[inline]
+fn get_thread_key(thread: &Shared<KThread>) -> usize {
+    Arc::as_ptr(&thread.0) as usize
+}
 
 fn register_scheduler_wait_event(thread: &Shared<KThread>) {
-    unsafe {
-        G_THREAD_SCHEDULER_WAIT_EVENTS.push((thread.clone(), ManualResetEvent::new(State::Unset)));
-    }
+    let mut wait_events = unsafe { G_THREAD_SCHEDULER_WAIT_EVENTS.lock() };
+
+    // A new thread might reuse the address of a destroyed one, whose entry is simply replaced
+    wait_events.retain(|_, (s_thread, _)| s_thread.0.strong_count() > 0);
+    wait_events.insert(get_thread_key(thread), (thread.downgrade(), Arc::new(ManualResetEvent::new(State::Unset))));
 }
 
-pub fn get_scheduler_wait_event(thread: &Shared<KThread>) -> &'static mut ManualResetEvent {
-    unsafe {
-        for i in 0..G_THREAD_SCHEDULER_WAIT_EVENTS.len() {
-            let (s_thread, s_event) = &mut G_THREAD_SCHEDULER_WAIT_EVENTS[i];
-            if s_thread.ptr_eq(thread) {
-                return s_event;
-            }
-        }
+pub fn get_scheduler_wait_event(thread: &Shared<KThread>) -> Arc<ManualResetEvent> {
+    let wait_events = unsafe { G_THREAD_SCHEDULER_WAIT_EVENTS.lock() };
+    match wait_events.get(&get_thread_key(thread)) {
+        Some((_, s_event)) => s_event.clone(),
+        None => panic!("Scheduler wait event not found!")
     }
-
-    panic!("Scheduler wait event not found!");
 }
 
 pub struct KThread {
@@ -207,7 +267,9 @@ pub struct KThread {
     waiting_threads: Vec<Shared<KThread>>,
     has_exited: bool,
     pub is_schedulable: bool,
+    // Suspend flags requested for the thread, and the ones actually allowed to pause it (see combine_force_pause_flags)
     force_pause_state: ThreadState,
+    force_pause_permission: ThreadState,
     pub sync_result: ResultCode,
     base_priority: i32,
     pub should_be_terminated: bool,
@@ -221,10 +283,26 @@ pub struct KThread {
     pub affinity_mask: i64,
     pub owner_process: Option<Shared<KProcess>>,
     pub cpu_exec_ctx: Option<cpu::ExecutionContext>,
-    pub emu_tlr: [u8; 0x100],
+    pub exception_resume_addr: Option<u64>,
+    // Exception raised by the emulator itself (type, PC, result if it can't be dispatched), see KThread::exec_thread_fn
+    pub pending_exception: Option<(svc::ExceptionType, u64, ResultCode)>,
+    // Set when execution was stopped right before running code modified by the guest, which must be retranslated first (see cpu::CodeWriteWatch)
+    pub pending_code_flush: bool,
+    // Details of the last failed guest execution, until it's dispatched as an exception
+    pub last_execution_error: Option<cpu::result::ExecutionErrorContext>,
+    pub emu_tlr: [u8; ThreadLocalRegion::SIZE],
+    // Name given by the guest (see KThread::update_guest_name), if any
+    pub guest_name: Option<String>,
+    // Name of the owner process as shown in logs, kept since logging can't access the process (see util::set_log_names)
+    pub log_process_name: String,
     pub siblings_per_core: Vec<Option<Shared<KThread>>>,
-    pub withholder: Option<Vec<Shared<KThread>>>,
-    pub withholder_entry: Option<Shared<KThread>>,
+    // Condition variable the thread is waiting on, if any
+    pub withholder: Option<KConditionVariable>,
+    // Threads waiting on mutexes owned by this thread, sorted by priority
+    mutex_waiters: Vec<Shared<KThread>>,
+    pub mutex_owner: Option<Shared<KThread>>,
+    pub mutex_address: u64,
+    pub mutex_requester_handle: u32,
     pub priority: i32,
     pub host_thread_builder: Option<Builder>,
     pub host_thread_handle: Option<JoinHandle<()>>,
@@ -253,21 +331,29 @@ impl KSynchronizationObject for KThread {
 }
 
 impl KFutureSchedulerObject for KThread {
-    fn time_up(&mut self) {
-        todo!("time_up");
+    fn time_up(thread: &mut Shared<Self>) {
+        Self::release_and_resume(thread);
+    }
+
+    fn as_thread(&self) -> Option<&KThread> {
+        Some(self)
     }
 }
 
 impl KThread {
     pub fn new(owner_process: Option<Shared<KProcess>>, host_thread_name: String, priority: i32, cpu_core: i32, exec_ctx_args: Option<(u64, usize)>) -> Result<Shared<Self>> {
         let host_builder = Builder::new().name(host_thread_name);
+        let id = new_thread_id();
 
         let cpu_exec_ctx = match owner_process.as_ref() {
             Some(owner_proc) => match exec_ctx_args {
-                Some((entry_addr, stack_size)) => match owner_proc.get().cpu_ctx.as_ref() {
+                Some((entry_addr, stack_size)) => match owner_proc.get().cpu_ctx.as_mut() {
                     Some(cpu_ctx) => {
                         // owner_proc.get().increment_refcount();
-                        Some(cpu_ctx.create_execution_context(stack_size, entry_addr)?)
+                        let mut exec_ctx = cpu_ctx.create_execution_context(stack_size, entry_addr)?;
+                        exec_ctx.stack.name = Some(format!("stack (thread {})", id));
+                        exec_ctx.tlr.name = Some(format!("TLS page (thread {})", id));
+                        Some(exec_ctx)
                     },
                     None => None
                 },
@@ -282,7 +368,23 @@ impl KThread {
             siblings_per_core.push(None);
         }
 
-        // TODO: force pause flags if owner paused...
+        // Threads created in a paused process start paused too, and the ones created in a debugged process don't run until the debugger continues them (see KDebug::continue_process), since it has to get their creation event first
+        let force_pause_state = match owner_process.as_ref() {
+            Some(owner_proc) => {
+                let owner_proc_ref = owner_proc.get();
+                let mut force_pause_state = ThreadState::Initialized;
+                if owner_proc_ref.is_paused {
+                    force_pause_state = force_pause_state.with_flags(ThreadState::ProcessSuspended);
+                }
+                if owner_proc_ref.is_debugged() {
+                    force_pause_state = force_pause_state.with_flags(ThreadState::InitSuspended);
+                }
+                force_pause_state
+            },
+            None => ThreadState::Initialized
+        };
+
+        let log_process_name = owner_process.as_ref().and_then(|owner_proc| owner_proc.get().npdm.meta.name.get_str().ok().map(String::from)).unwrap_or_else(|| String::from("Host~pegasus"));
 
         let thread = Shared::new(Self {
             refcount: AtomicI32::new(1),
@@ -290,7 +392,8 @@ impl KThread {
             has_exited: false,
             should_be_terminated: false,
             is_schedulable: true,
-            force_pause_state: ThreadState::Initialized,
+            force_pause_state: force_pause_state,
+            force_pause_permission: ThreadState::ForcePauseMask,
             sync_result: result::ResultNoThread::make(),
             base_priority: priority,
             state: ThreadState::Initialized,
@@ -303,18 +406,32 @@ impl KThread {
             affinity_mask: bit!(cpu_core as i64),
             owner_process: owner_process,
             cpu_exec_ctx: cpu_exec_ctx,
-            emu_tlr: [0; 0x100],
+            exception_resume_addr: None,
+            pending_exception: None,
+            pending_code_flush: false,
+            last_execution_error: None,
+            emu_tlr: [0; ThreadLocalRegion::SIZE],
+            guest_name: None,
+            log_process_name: log_process_name,
             siblings_per_core: siblings_per_core,
             withholder: None,
-            withholder_entry: None,
+            mutex_waiters: Vec::new(),
+            mutex_owner: None,
+            mutex_address: 0,
+            mutex_requester_handle: 0,
             priority: priority,
             host_thread_builder: Some(host_builder),
             host_thread_handle: None,
             ctx: KThreadContext::new(),
-            id: new_thread_id()
+            id: id
         });
 
         register_scheduler_wait_event(&thread);
+
+        let owner_process = thread.get().owner_process.clone();
+        if let Some(owner_proc) = owner_process {
+            owner_proc.get().register_thread(&thread);
+        }
         Ok(thread)
     }
 
@@ -383,6 +500,112 @@ impl KThread {
         set_thread_reselection_requested(true);
     }
 
+    // The state's suspend flags become the requested ones which are allowed, the thread being (un)scheduled accordingly
+    fn combine_force_pause_flags(thread: &mut Shared<KThread>) {
+        let old_state = thread.get().state;
+        let suspend_flags = thread.get().get_active_suspend_flags();
+        thread.get().state = old_state.get_low_flags().with_flags(suspend_flags);
+
+        Self::adjust_scheduling(thread, old_state);
+    }
+
+    #[inline]
+    fn get_active_suspend_flags(&self) -> ThreadState {
+        ThreadState(self.force_pause_state.0 & self.force_pause_permission.0 & ThreadState::ForcePauseMask.0)
+    }
+
+    #[inline]
+    pub fn is_force_paused(&self) -> bool {
+        self.get_active_suspend_flags() != ThreadState::Initialized
+    }
+
+    #[inline]
+    pub fn is_suspend_requested(&self, suspend_flag: ThreadState) -> bool {
+        self.force_pause_state.has_flags(suspend_flag)
+    }
+
+    // Suspensions stack: the thread stays paused until every one of them is lifted (see resume)
+    pub fn suspend(thread: &mut Shared<KThread>, suspend_flag: ThreadState) {
+        let _guard = make_critical_section_guard();
+
+        let force_pause_state = thread.get().force_pause_state;
+        thread.get().force_pause_state = force_pause_state.with_flags(suspend_flag);
+        Self::combine_force_pause_flags(thread);
+
+        // Guest code stops running right away instead of on the thread's next SVC
+        let is_force_paused = thread.get().is_force_paused();
+        if is_force_paused {
+            if let Some(cpu_exec_ctx) = thread.get().cpu_exec_ctx.as_ref() {
+                let _ = cpu_exec_ctx.pause();
+            }
+        }
+    }
+
+    pub fn resume(thread: &mut Shared<KThread>, suspend_flag: ThreadState) {
+        let _guard = make_critical_section_guard();
+
+        let force_pause_state = thread.get().force_pause_state;
+        thread.get().force_pause_state = force_pause_state.without_flags(suspend_flag);
+        Self::combine_force_pause_flags(thread);
+
+        // The thread stays paused while any other suspension is still in place
+        let is_force_paused = thread.get().is_force_paused();
+        if !is_force_paused {
+            if let Some(cpu_exec_ctx) = thread.get().cpu_exec_ctx.as_ref() {
+                cpu_exec_ctx.resume();
+            }
+        }
+    }
+
+    // Note: unlike the real kernel, this doesn't wait for the thread to stop running when pausing it, the thread will actually stop after its current instruction (see ExecutionContext::pause)
+    pub fn set_activity(thread: &mut Shared<KThread>, pause: bool) -> Result<()> {
+        let _guard = make_critical_section_guard();
+
+        let low_flags = thread.get().state.get_low_flags();
+        result_return_unless!((low_flags == ThreadState::Waiting) || (low_flags == ThreadState::Runnable), result::ResultInvalidState);
+
+        let is_termination_requested = thread.get().is_termination_requested();
+        if !is_termination_requested {
+            let is_paused = thread.get().force_pause_state.has_flags(ThreadState::ThreadSuspended);
+            if pause {
+                result_return_if!(is_paused, result::ResultInvalidState);
+                Self::suspend(thread, ThreadState::ThreadSuspended);
+            }
+            else {
+                result_return_unless!(is_paused, result::ResultInvalidState);
+                Self::resume(thread, ThreadState::ThreadSuspended);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Waiting threads are woken up (their waits failing with ResultTerminationRequested), and guest code stops running after the current instruction
+    // Note: guest threads also stop right after their next SVC (see cpu::handle_svc_insn), in case they weren't running guest code at this point
+    pub fn request_termination(thread: &mut Shared<KThread>) {
+        let _guard = make_critical_section_guard();
+
+        thread.get().should_be_terminated = true;
+
+        // Paused threads are woken up too, otherwise they would never get to terminate: no suspension is allowed anymore
+        let is_force_paused = thread.get().is_force_paused();
+        if is_force_paused {
+            thread.get().force_pause_permission = ThreadState::Initialized;
+            Self::combine_force_pause_flags(thread);
+        }
+
+        let low_state = thread.get().state.get_low_flags();
+        if low_state == ThreadState::Waiting {
+            thread.get().sync_result = result::ResultTerminationRequested::make();
+            Self::release_and_resume(thread);
+        }
+
+        if let Some(cpu_exec_ctx) = thread.get().cpu_exec_ctx.as_ref() {
+            let _ = cpu_exec_ctx.get_handle().stop();
+            cpu_exec_ctx.resume();
+        }
+    }
+
     pub fn reschedule(thread: &mut Shared<KThread>, new_state_flags: ThreadState) {
         let _guard = make_critical_section_guard();
 
@@ -391,23 +614,301 @@ impl KThread {
         Self::adjust_scheduling(thread, old_state);
     }
 
-    fn exec_thread_fn<T: Copy + Send + Sync + 'static, U: Copy + Send + Sync + 'static>(thread: Shared<KThread>, arg_x0: T, arg_x1: U) {
+    pub fn release_and_resume(thread: &mut Shared<KThread>) {
+        let _guard = make_critical_section_guard();
+
+        let low_state = thread.get().state.get_low_flags();
+        if low_state == ThreadState::Waiting {
+            let withholder = thread.get().withholder.take();
+            if let Some(withholder) = withholder {
+                withholder.remove(thread);
+            }
+            Self::reschedule(thread, ThreadState::Runnable);
+        }
+    }
+
+    pub fn sleep(thread: &mut Shared<KThread>, timeout: Duration) -> Result<()> {
+        get_critical_section().enter();
+
+        if thread.get().is_termination_requested() {
+            get_critical_section().leave();
+            return result::ResultTerminationRequested::make_err();
+        }
+
+        Self::reschedule(thread, ThreadState::Waiting);
+        get_time_manager().schedule_future_invocation(thread.clone(), timeout);
+
+        get_critical_section().leave();
+
+        get_time_manager().unschedule_future_invocation(thread.clone());
+        Ok(())
+    }
+
+    fn adjust_scheduling_for_new_priority(thread: &mut Shared<KThread>, old_priority: i32) {
+        let cur_state = thread.get().state;
+        let is_schedulable = thread.get().is_schedulable;
+        if (cur_state != ThreadState::Runnable) || !is_schedulable {
+            return;
+        }
+
+        let active_core = thread.get().active_core;
+        let priority = thread.get().priority;
+        let affinity_mask = thread.get().affinity_mask;
+
+        if active_core >= 0 {
+            get_priority_queue().unschedule(old_priority, active_core, thread.clone());
+        }
+
+        for core in 0..CPU_CORE_COUNT as i32 {
+            if (core != active_core) && (((affinity_mask >> core as i64) & 1) != 0) {
+                get_priority_queue().unsuggest(old_priority, core, thread.clone());
+            }
+        }
+
+        if active_core >= 0 {
+            let is_current_thread = match try_get_current_thread() {
+                Some(cur_thread) => cur_thread.ptr_eq(thread),
+                None => false
+            };
+
+            // The running thread keeps its place at the front of its new priority level
+            if is_current_thread {
+                get_priority_queue().schedule_prepend(priority, active_core, thread.clone());
+            }
+            else {
+                get_priority_queue().schedule(priority, active_core, thread.clone());
+            }
+        }
+
+        for core in 0..CPU_CORE_COUNT as i32 {
+            if (core != active_core) && (((affinity_mask >> core as i64) & 1) != 0) {
+                get_priority_queue().suggest(priority, core, thread.clone());
+            }
+        }
+
+        set_thread_reselection_requested(true);
+    }
+
+    // Mutex waiters / priority inheritance (see ArbitrateLock/ArbitrateUnlock)
+
+    fn insert_mutex_waiter(thread: &Shared<KThread>, waiter: Shared<KThread>) {
+        let waiter_priority = waiter.get().priority;
+        let waiter_priorities: Vec<i32> = thread.get().mutex_waiters.iter().map(|mutex_waiter| mutex_waiter.get().priority).collect();
+
+        // Waiters with the same priority are kept in FIFO order
+        let waiter_idx = waiter_priorities.iter().position(|&priority| priority > waiter_priority).unwrap_or(waiter_priorities.len());
+        thread.get().mutex_waiters.insert(waiter_idx, waiter);
+    }
+
+    pub fn add_mutex_waiter(thread: &mut Shared<KThread>, waiter: &Shared<KThread>) {
+        Self::insert_mutex_waiter(thread, waiter.clone());
+        waiter.get().mutex_owner = Some(thread.clone());
+
+        Self::update_priority_inheritance(thread);
+    }
+
+    pub fn remove_mutex_waiter(thread: &mut Shared<KThread>, waiter: &Shared<KThread>) {
+        thread.get().mutex_waiters.retain(|mutex_waiter| !mutex_waiter.ptr_eq(waiter));
+        waiter.get().mutex_owner = None;
+
+        Self::update_priority_inheritance(thread);
+    }
+
+    // Hands the mutex over to the highest priority waiter, which becomes the owner of the remaining waiters of the same mutex
+    pub fn relinquish_mutex(thread: &mut Shared<KThread>, mutex_address: u64) -> (Option<Shared<KThread>>, usize) {
+        let mutex_waiters = thread.get().mutex_waiters.clone();
+
+        let mut new_owner: Option<Shared<KThread>> = None;
+        let mut waiter_count: usize = 0;
+        for waiter in mutex_waiters.into_iter() {
+            let waiter_mutex_address = waiter.get().mutex_address;
+            if waiter_mutex_address != mutex_address {
+                continue;
+            }
+
+            thread.get().mutex_waiters.retain(|mutex_waiter| !mutex_waiter.ptr_eq(&waiter));
+            waiter.get().mutex_owner = new_owner.clone();
+            if new_owner.is_none() {
+                new_owner = Some(waiter);
+            }
+            else {
+                Self::insert_mutex_waiter(new_owner.as_ref().unwrap(), waiter);
+            }
+
+            waiter_count += 1;
+        }
+
+        if let Some(new_owner) = new_owner.as_mut() {
+            Self::update_priority_inheritance(thread);
+            Self::update_priority_inheritance(new_owner);
+        }
+
+        (new_owner, waiter_count)
+    }
+
+    // A mutex owner runs with the highest priority among its own base priority and the ones of the threads waiting on it, which is propagated along the owner chain
+    fn update_priority_inheritance(thread: &mut Shared<KThread>) {
+        let base_priority = thread.get().base_priority;
+        let old_priority = thread.get().priority;
+        let highest_waiter_priority = thread.get().mutex_waiters.first().map(|waiter| waiter.get().priority);
+
+        let new_priority = match highest_waiter_priority {
+            Some(waiter_priority) if waiter_priority < base_priority => waiter_priority,
+            _ => base_priority
+        };
+
+        if new_priority != old_priority {
+            thread.get().priority = new_priority;
+            Self::adjust_scheduling_for_new_priority(thread, old_priority);
+
+            let mutex_owner = thread.get().mutex_owner.clone();
+            if let Some(mut mutex_owner) = mutex_owner {
+                // Re-sort this thread among its owner's waiters with its new priority
+                mutex_owner.get().mutex_waiters.retain(|mutex_waiter| !mutex_waiter.ptr_eq(thread));
+                Self::insert_mutex_waiter(&mutex_owner, thread.clone());
+
+                Self::update_priority_inheritance(&mut mutex_owner);
+            }
+        }
+    }
+
+    fn exec_thread_fn<T: Copy + Send + Sync + 'static, U: Copy + Send + Sync + 'static>(mut thread: Shared<KThread>, arg_x0: T, arg_x1: U) {
         set_current_thread(thread.clone());
+        let cur_core = thread.get().cur_core;
+        pin_current_host_thread(cur_core);
+
+        unsafe {
+            G_RUNNING_GUEST_THREAD_COUNT.fetch_add(1, Ordering::SeqCst);
+        }
 
         let mut cpu_exec_ctx_handle = thread.get().cpu_exec_ctx.as_mut().unwrap().get_handle();
         let exec_start_addr = thread.get().cpu_exec_ctx.as_mut().unwrap().exec_start_addr;
         let exec_end_addr = thread.get().cpu_exec_ctx.as_mut().unwrap().exec_end_addr;
+        let translation_stats = thread.get().cpu_exec_ctx.as_ref().unwrap().translation_stats.clone();
+        let pause_state = thread.get().cpu_exec_ctx.as_ref().unwrap().pause_state.clone();
+        if let Some(stats) = translation_stats.clone() {
+            metrics::register_translation_stats(thread.get().id, stats);
+        }
 
-        cpu_exec_ctx_handle.start(arg_x0, arg_x1, exec_start_addr, exec_end_addr).unwrap();
+        // Threads started while suspended (see KThread::do_start) don't run any guest code until resumed
+        if pause_state.is_paused() {
+            Self::park_while_paused(&pause_state);
+        }
 
+        let mut exec_rc = cpu_exec_ctx_handle.start(arg_x0, arg_x1, exec_start_addr, exec_end_addr);
+        loop {
+            if let Some(stats) = translation_stats.as_ref() {
+                stats.record_exit();
+            }
+
+            let resume_addr = match exec_rc {
+                // Execution is also stopped when returning from an exception (see ReturnFromException) or when the emulator raises one
+                Ok(()) => {
+                    let pending_exception = thread.get().pending_exception.take();
+                    match pending_exception {
+                        Some((exception_type, pc, rc)) => match Self::dispatch_user_exception(&mut thread, exception_type, Some(pc), rc) {
+                            Ok(handler_addr) => Some(handler_addr),
+                            Err(_) => panic!("Unhandled guest exception {:?}: {1} ({1:?})", exception_type, rc)
+                        },
+                        None => {
+                            let pending_code_flush = std::mem::take(&mut thread.get().pending_code_flush);
+                            if pending_code_flush {
+                                Self::flush_dirty_code(&thread);
+                            }
+
+                            let exception_resume_addr = thread.get().exception_resume_addr.take();
+                            match (exception_resume_addr, pending_code_flush) {
+                                (Some(addr), _) => Some(addr),
+                                (None, true) => Some(cpu_exec_ctx_handle.get_resume_address().unwrap()),
+                                (None, false) => None
+                            }
+                        }
+                    }
+                },
+                Err(rc) => {
+                    let error_ctx = Self::record_execution_error(&mut thread, rc);
+                    match Self::enter_user_exception(&mut thread, rc) {
+                        Ok(handler_addr) => Some(handler_addr),
+                        Err(_) => panic!("Unhandled guest exception: {}", error_ctx)
+                    }
+                }
+            };
+
+            // Paused execution contexts park here, resuming afterwards wherever they were going to (or right where they stopped)
+            let was_paused = pause_state.is_paused();
+            if was_paused {
+                Self::park_while_paused(&pause_state);
+            }
+
+            // Same for threads interrupted by other cores in order to reschedule this one (see KScheduler::request_reschedule)
+            let cur_core = thread.get().cur_core;
+            let was_rescheduled = get_scheduler(cur_core).take_reschedule_request();
+            if was_rescheduled {
+                cpu::on_interrupt();
+            }
+
+            let resume_addr = match resume_addr {
+                None if (was_paused || was_rescheduled) && !thread.get().is_termination_requested() => Some(cpu_exec_ctx_handle.get_resume_address().unwrap()),
+                _ => resume_addr
+            };
+
+            match resume_addr {
+                Some(addr) => exec_rc = cpu_exec_ctx_handle.resume(addr, exec_end_addr),
+                None => break
+            };
+        }
+
+        // The entrypoint's return value, if it returned at all
+        let exit_code: u32 = cpu_exec_ctx_handle.read_register(cpu::Register::W0).unwrap_or(0);
+        let thread_id = thread.get().id;
+        run::notify_thread_exit(thread_id, exit_code);
+
+        let owner_process = thread.get().owner_process.clone();
+        if let Some(owner_proc) = owner_process {
+            if let Some(cpu_ctx) = owner_proc.get().cpu_ctx.as_mut() {
+                cpu_ctx.release_execution_context(thread.get().cpu_exec_ctx.as_ref().unwrap());
+            }
+
+            debug::notify_debug_event(&owner_proc, DebugEventInfo::exit_thread(thread_id, ThreadExitReason::ExitThread));
+        }
+
+        trace::record_thread_exit();
+        metrics::flush_instruction_count();
+        profiler::flush_samples();
+        host_profiler::flush_spans();
         reset_current_thread();
+
+        unsafe {
+            G_RUNNING_GUEST_THREAD_COUNT.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    // Threads suspended meanwhile are switched out first, so that their core keeps running other threads while they are parked
+    fn park_while_paused(pause_state: &cpu::ExecutionPauseState) {
+        cpu::on_interrupt();
+        pause_state.wait_while_paused();
+    }
+
+    fn flush_dirty_code(thread: &Shared<KThread>) {
+        let owner_process = thread.get().owner_process.clone();
+        if let Some(owner_proc) = owner_process {
+            if let Some(cpu_ctx) = owner_proc.get().cpu_ctx.as_mut() {
+                cpu_ctx.flush_dirty_code().unwrap();
+            }
+        }
     }
 
     fn host_thread_fn<F: FnOnce() + Send + 'static>(thread: Shared<KThread>, f: F) {
         set_current_thread(thread.clone());
+        let cur_core = thread.get().cur_core;
+        pin_current_host_thread(cur_core);
 
         f();
 
+        trace::record_thread_exit();
+        metrics::flush_instruction_count();
+        profiler::flush_samples();
+        host_profiler::flush_spans();
         reset_current_thread();
     }
 
@@ -418,7 +919,7 @@ impl KThread {
 
         let should_be_terminated = thread.get().should_be_terminated;
         if !should_be_terminated {
-            let cur_thread = try_get_current_thread();
+            let mut cur_thread = try_get_current_thread();
             
             loop {
                 let cur_state = thread.get().state;
@@ -435,10 +936,22 @@ impl KThread {
 
                 result_return_unless!(cur_state.get_low_flags() == ThreadState::Initialized, result::ResultInvalidState);
                 
-                if cur_thread.is_none() || (cur_thread.as_ref().unwrap().get().force_pause_state == ThreadState::Initialized) {
+                let is_cur_thread_force_paused = match cur_thread.as_ref() {
+                    Some(cur_thread) => cur_thread.get().is_force_paused(),
+                    None => false
+                };
+                if !is_cur_thread_force_paused {
+                    // The new thread keeps any suspension it was created with (see KThread::new), thus being runnable but not scheduled
                     let force_pause_state = thread.get().force_pause_state;
                     if thread.get().owner_process.is_some() && (force_pause_state != ThreadState::Initialized) {
-                        todo!("CombineForcePauseFlags");
+                        Self::combine_force_pause_flags(thread);
+
+                        let is_force_paused = thread.get().is_force_paused();
+                        if is_force_paused {
+                            if let Some(cpu_exec_ctx) = thread.get().cpu_exec_ctx.as_ref() {
+                                let _ = cpu_exec_ctx.pause();
+                            }
+                        }
                     }
 
                     Self::set_new_state(thread, ThreadState::Runnable);
@@ -448,6 +961,18 @@ impl KThread {
 
                     return Ok(());
                 }
+                else {
+                    // The starting thread got paused itself: let it be suspended before retrying, so that the new thread doesn't escape the suspension
+                    let cur_thread = cur_thread.as_mut().unwrap();
+                    Self::combine_force_pause_flags(cur_thread);
+                    let pause_state = cur_thread.get().cpu_exec_ctx.as_ref().map(|cpu_exec_ctx| cpu_exec_ctx.pause_state.clone());
+
+                    get_critical_section().leave();
+                    if let Some(pause_state) = pause_state {
+                        pause_state.wait_while_paused();
+                    }
+                    get_critical_section().enter();
+                }
             }
         }
 
@@ -460,7 +985,14 @@ impl KThread {
         let thread_entry_clone = thread.clone();
         Self::do_start(thread, move || {
             Self::exec_thread_fn(thread_entry_clone, arg_x0, arg_x1);
-        })
+        })?;
+
+        let owner_process = thread.get().owner_process.clone();
+        if let Some(owner_proc) = owner_process {
+            let create_thread_event = DebugEventInfo::create_thread(&mut thread.get());
+            debug::notify_debug_event(&owner_proc, create_thread_event);
+        }
+        Ok(())
     }
 
     pub fn start_host<F: FnOnce() + Send + 'static>(thread: &mut Shared<KThread>, f: F) -> Result<()> {
@@ -484,30 +1016,328 @@ impl KThread {
 
     pub fn get_tlr_ptr(&mut self) -> *mut u8 {
         if let Some(exec_ctx) = self.cpu_exec_ctx.as_mut() {
-            exec_ctx.tlr.data.as_mut_ptr()
+            exec_ctx.tlr.as_mut_ptr()
         }
         else {
             self.emu_tlr.as_mut_ptr()
         }
     }
 
+    pub fn get_tls_address(&mut self) -> u64 {
+        if let Some(exec_ctx) = self.cpu_exec_ctx.as_ref() {
+            exec_ctx.tlr.start()
+        }
+        else {
+            self.emu_tlr.as_ptr() as u64
+        }
+    }
+
+    pub fn get_last_thread_context(&self) -> Result<svc::LastThreadContext> {
+        if let Some(exec_ctx) = self.cpu_exec_ctx.as_ref() {
+            // The thread isn't running (it's been switched out), thus reading its registers is safe
+            let ctx_h = exec_ctx.get_handle();
+            Ok(svc::LastThreadContext {
+                fp: ctx_h.read_register(cpu::Register::FP)?,
+                sp: ctx_h.read_register(cpu::Register::SP)?,
+                lr: ctx_h.read_register(cpu::Register::LR)?,
+                pc: ctx_h.read_register(cpu::Register::PC)?
+            })
+        }
+        else {
+            // Emulated (host) threads have no guest context at all
+            Ok(svc::LastThreadContext::default())
+        }
+    }
+
+    // Dispatches a guest exception to the process's userland exception handler (its entrypoint, as SDK/libnx expect), returning the address to resume execution at
+    fn record_execution_error(thread: &mut Shared<KThread>, rc: ResultCode) -> cpu::result::ExecutionErrorContext {
+        let thread_id = thread.get().id;
+        let error_ctx = thread.get().cpu_exec_ctx.as_ref().unwrap().get_error_context(rc, Some(thread_id));
+        log_line!("Guest execution failed: {}", error_ctx);
+
+        let owner_process = thread.get().owner_process.clone();
+        if let Some(owner_proc) = owner_process {
+            let memory_map = KProcess::get_memory_map(&owner_proc);
+            if let Some(pc) = error_ctx.pc {
+                log_line!("* PC location: {}", cpu::describe_address(&memory_map, pc));
+
+                let process_id = owner_proc.get().id;
+                let ctx_h = thread.get().cpu_exec_ctx.as_ref().unwrap().get_handle();
+                if let Ok(disasm_lines) = disasm::format_around_pc(&ctx_h, pc, |address| symbols::format_address(process_id, address)) {
+                    log_line!("* Disassembly:");
+                    for disasm_line in disasm_lines.iter() {
+                        log_line!("  {}", disasm_line);
+                    }
+                }
+            }
+            if let Some(fault_address) = error_ctx.fault_address {
+                log_line!("* Fault address location: {}", cpu::describe_address(&memory_map, fault_address));
+            }
+        }
+
+        thread.get().last_execution_error = Some(error_ctx);
+        error_ctx
+    }
+
+    pub fn enter_user_exception(thread: &mut Shared<KThread>, rc: ResultCode) -> Result<u64> {
+        match get_user_exception_type(rc) {
+            Some(exception_type) => Self::dispatch_user_exception(thread, exception_type, None, rc),
+            None => Err(rc)
+        }
+    }
+
+    // Same as above, for an already known exception type (the PC is taken from the registers unless specified), failing with the given result if it can't be dispatched
+    pub fn dispatch_user_exception(thread: &mut Shared<KThread>, exception_type: svc::ExceptionType, pc: Option<u64>, rc: ResultCode) -> Result<u64> {
+        let owner_process = match thread.get().owner_process.clone() {
+            Some(owner_process) => owner_process,
+            None => return Err(rc)
+        };
+        // Exceptions inside the exception handler itself can't be handled
+        result_return_if!(owner_process.get().in_user_exception, result::ResultNotHandled);
+
+        let handler_addr = match owner_process.get().cpu_ctx.as_ref().and_then(|cpu_ctx| cpu_ctx.modules.first()).and_then(|module| module.regions.first()) {
+            Some(region) => region.start(),
+            None => return Err(rc)
+        };
+
+        let mut ctx_h = match thread.get().cpu_exec_ctx.as_ref() {
+            Some(exec_ctx) => exec_ctx.get_handle(),
+            None => return Err(rc)
+        };
+
+        // TODO: 32-bit processes expect their own (smaller) exception frame layout
+        let mut info = ExceptionInfo::default();
+        for (i, reg) in EXCEPTION_INFO_REGISTERS.iter().enumerate() {
+            info.x[i] = ctx_h.read_register(*reg)?;
+        }
+        info.lr = ctx_h.read_register(cpu::Register::LR)?;
+        info.sp = ctx_h.read_register(cpu::Register::SP)?;
+        info.pc = match pc {
+            Some(pc) => pc,
+            None => ctx_h.read_register(cpu::Register::PC)?
+        };
+        info.pstate = ctx_h.read_register::<u64>(cpu::Register::NZCV)? as u32;
+        // Note: only known for exceptions caused by invalid memory accesses
+        info.far = thread.get().last_execution_error.take().and_then(|error_ctx| error_ctx.fault_address).unwrap_or(0);
+
+        let tls_address = thread.get().get_tls_address();
+        thread.get().get_thread_local_region().write_exception_info(info);
+
+        let thread_id = thread.get().id;
+        let debug_exception_type = match exception_type {
+            svc::ExceptionType::InstructionAbort => DebugExceptionType::InstructionAbort,
+            svc::ExceptionType::DataAbort => DebugExceptionType::DataAbort,
+            svc::ExceptionType::UnalignedInstruction | svc::ExceptionType::UnalignedData => DebugExceptionType::AlignmentFault,
+            svc::ExceptionType::UndefinedInstruction => DebugExceptionType::UndefinedInstruction,
+            svc::ExceptionType::InvalidSystemCall => DebugExceptionType::UndefinedSystemCall,
+            _ => DebugExceptionType::MemorySystemError
+        };
+        debug::notify_debug_event(&owner_process, DebugEventInfo::exception(thread_id, debug_exception_type, info.pc, [info.far, 0, 0, 0]));
+
+        ctx_h.write_register(cpu::Register::X0, exception_type as u64)?;
+        ctx_h.write_register(cpu::Register::X1, tls_address + ThreadLocalRegion::USER_EXCEPTION_INFO_OFFSET as u64)?;
+        owner_process.get().in_user_exception = true;
+
+        log_line!("Dispatching guest exception {:?} (PC {:#X}, FAR {:#X}) to the handler at {:#X}...", exception_type, info.pc, info.far, handler_addr);
+        Ok(handler_addr)
+    }
+
+    // Restores the context saved when the exception was dispatched, the actual resume happens once the guest execution is stopped
+    pub fn return_from_user_exception(thread: &mut Shared<KThread>) -> Result<()> {
+        let mut ctx_h = match thread.get().cpu_exec_ctx.as_ref() {
+            Some(exec_ctx) => exec_ctx.get_handle(),
+            None => return result::ResultNotHandled::make_err()
+        };
+
+        let info = thread.get().get_thread_local_region().read_exception_info();
+
+        for (i, reg) in EXCEPTION_INFO_REGISTERS.iter().enumerate() {
+            ctx_h.write_register(*reg, info.x[i])?;
+        }
+        ctx_h.write_register(cpu::Register::LR, info.lr)?;
+        ctx_h.write_register(cpu::Register::SP, info.sp)?;
+        ctx_h.write_register(cpu::Register::NZCV, info.pstate as u64)?;
+
+        thread.get().exception_resume_addr = Some(info.pc);
+        ctx_h.stop()
+    }
+
     pub fn get_thread_local_region(&mut self) -> &'static mut ThreadLocalRegion {
         unsafe {
             &mut *(self.get_tlr_ptr() as *mut ThreadLocalRegion)
         }
     }
 
+    // Only guest threads have a context to dump
+    pub fn get_context_dump(&mut self) -> Result<Option<ThreadContextDump>> {
+        let ctx_h = match self.cpu_exec_ctx.as_ref() {
+            Some(exec_ctx) => exec_ctx.get_handle(),
+            None => return Ok(None)
+        };
+
+        let mut dump = ThreadContextDump {
+            thread_id: self.id,
+            name: self.guest_name.clone(),
+            sp: ctx_h.read_register(cpu::Register::SP)?,
+            pc: ctx_h.read_register(cpu::Register::PC)?,
+            pstate: ctx_h.read_register::<u64>(cpu::Register::NZCV)? as u32,
+            tls_address: self.get_tls_address(),
+            ..Default::default()
+        };
+        for (i, reg) in CONTEXT_DUMP_REGISTERS.iter().enumerate() {
+            // Note: registers without an AArch32 equivalent are left as zero for 32-bit processes
+            dump.x[i] = ctx_h.read_register(*reg).unwrap_or(0);
+        }
+
+        let tlr = self.get_thread_local_region();
+        dump.thread_type_address = tlr.thread_ref;
+        let exception_info = tlr.read_exception_info();
+        if exception_info != ExceptionInfo::default() {
+            dump.exception_info = Some(exception_info);
+        }
+        Ok(Some(dump))
+    }
+
+    // nn::os::SetThreadName just updates the ThreadType in guest memory, thus the name needs to be read again to notice any changes, returning whether it changed
+    pub fn update_guest_name(&mut self) -> bool {
+        let thread_type_address = match self.get_thread_local_region().get_thread_type_address() {
+            Some(address) => address,
+            None => return false
+        };
+        let ctx_h = match self.cpu_exec_ctx.as_ref() {
+            Some(exec_ctx) => exec_ctx.get_handle(),
+            None => return false
+        };
+
+        let mut thread_type_data = [0u8; ThreadType::SIZE];
+        if ctx_h.read_memory(thread_type_address, &mut thread_type_data).is_err() {
+            return false;
+        }
+
+        let guest_name = ThreadType::from_bytes(&thread_type_data).and_then(|thread_type| thread_type.get_name(thread_type_address)).filter(|name| !name.is_empty());
+        if guest_name != self.guest_name {
+            self.guest_name = guest_name;
+            true
+        }
+        else {
+            false
+        }
+    }
+
     #[inline]
     pub fn get_host_name(&self) -> &str {
         self.host_thread_handle.as_ref().unwrap().thread().name().unwrap()
     }
 }
 
+// Registers saved in the exception frame (besides LR/SP/PC/PSTATE)
+const EXCEPTION_INFO_REGISTERS: [cpu::Register; 9] = [cpu::Register::X0, cpu::Register::X1, cpu::Register::X2, cpu::Register::X3, cpu::Register::X4, cpu::Register::X5, cpu::Register::X6, cpu::Register::X7, cpu::Register::X8];
+
+const CONTEXT_DUMP_REGISTERS: [cpu::Register; 31] = [
+    cpu::Register::X0, cpu::Register::X1, cpu::Register::X2, cpu::Register::X3, cpu::Register::X4, cpu::Register::X5, cpu::Register::X6, cpu::Register::X7,
+    cpu::Register::X8, cpu::Register::X9, cpu::Register::X10, cpu::Register::X11, cpu::Register::X12, cpu::Register::X13, cpu::Register::X14, cpu::Register::X15,
+    cpu::Register::X16, cpu::Register::X17, cpu::Register::X18, cpu::Register::X19, cpu::Register::X20, cpu::Register::X21, cpu::Register::X22, cpu::Register::X23,
+    cpu::Register::X24, cpu::Register::X25, cpu::Register::X26, cpu::Register::X27, cpu::Register::X28, cpu::Register::X29, cpu::Register::X30
+];
+
+fn get_user_exception_type(rc: ResultCode) -> Option<svc::ExceptionType> {
+    if cpu::result::ResultUnicornReadUnmappedMemory::matches(rc) || cpu::result::ResultUnicornWriteUnmappedMemory::matches(rc) || cpu::result::ResultUnicornReadProtectedMemory::matches(rc) || cpu::result::ResultUnicornWriteProtectedMemory::matches(rc) {
+        Some(svc::ExceptionType::DataAbort)
+    }
+    else if cpu::result::ResultUnicornFetchUnmappedMemory::matches(rc) || cpu::result::ResultUnicornFetchProtectedMemory::matches(rc) {
+        Some(svc::ExceptionType::InstructionAbort)
+    }
+    else if cpu::result::ResultUnicornReadUnaligned::matches(rc) || cpu::result::ResultUnicornWriteUnaligned::matches(rc) {
+        Some(svc::ExceptionType::UnalignedData)
+    }
+    else if cpu::result::ResultUnicornFetchUnaligned::matches(rc) {
+        Some(svc::ExceptionType::UnalignedInstruction)
+    }
+    else if cpu::result::ResultUnicornInvalidInstruction::matches(rc) {
+        Some(svc::ExceptionType::UndefinedInstruction)
+    }
+    else {
+        None
+    }
+}
+
 #[thread_local]
 static mut G_CURRENT_THREAD: Option<Shared<KThread>> = None;
 
+// Host thread pinning
+
+#[thread_local]
+static mut G_PINNED_CPU_CORE: i32 = INVALID_CPU_CORE;
+
+static mut G_HOST_CORE_IDS: Mutex<Option<Vec<core_affinity::CoreId>>> = parking_lot::const_mutex(None);
+
+// Host CPU backing the emulated core, either the one set in the config or simply the n-th available one
+fn get_host_core_id(cpu_core: i32) -> Option<core_affinity::CoreId> {
+    unsafe {
+        let mut host_core_ids = G_HOST_CORE_IDS.lock();
+        if host_core_ids.is_none() {
+            *host_core_ids = Some(core_affinity::get_core_ids().unwrap_or_default());
+        }
+        let host_core_ids = host_core_ids.as_ref().unwrap();
+        if host_core_ids.is_empty() {
+            return None;
+        }
+
+        let host_core_idx = match cfg::get_config().host_cpu_ids.as_ref() {
+            Some(host_cpu_ids) => *host_cpu_ids.get(cpu_core as usize)?,
+            None => cpu_core as usize % host_core_ids.len()
+        };
+        host_core_ids.iter().find(|core_id| core_id.id == host_core_idx).copied()
+    }
+}
+
+// Reflects any change of the current thread's guest name into the host thread name
+pub fn update_current_guest_thread_name() {
+    let cur_thread = get_current_thread();
+    let guest_name = {
+        let mut cur_thread_ref = cur_thread.get();
+        match cur_thread_ref.update_guest_name() {
+            true => cur_thread_ref.guest_name.clone(),
+            false => return
+        }
+    };
+
+    util::set_log_guest_thread_name(guest_name.clone());
+    if let Some(guest_name) = guest_name {
+        log_line!("Guest thread name set to '{}'", guest_name);
+        util::set_current_host_thread_name(&guest_name);
+    }
+}
+
+// Pins the current host thread to the host CPU backing the emulated core (if enabled in the config), which makes scheduling/timings more stable
+pub fn pin_current_host_thread(cpu_core: i32) {
+    if !cfg::get_config().pin_host_threads || (cpu_core < 0) {
+        return;
+    }
+
+    unsafe {
+        // Note: avoid the syscall if the thread keeps running on the same core
+        if G_PINNED_CPU_CORE == cpu_core {
+            return;
+        }
+
+        if let Some(host_core_id) = get_host_core_id(cpu_core) {
+            core_affinity::set_for_current(host_core_id);
+            G_PINNED_CPU_CORE = cpu_core;
+        }
+    }
+}
+
+// ---
+
+// Logging can't access the current thread (see util::set_log_names), thus its names are cached right away
 #[inline]
 fn set_current_thread(thread: Shared<KThread>) {
+    {
+        let thread_ref = thread.get();
+        util::set_log_names(thread_ref.log_process_name.clone(), thread_ref.guest_name.clone());
+    }
+
     unsafe {
         G_CURRENT_THREAD = Some(thread);
     }
@@ -515,6 +1345,7 @@ fn set_current_thread(thread: Shared<KThread>) {
 
 #[inline]
 fn reset_current_thread() {
+    util::reset_log_names();
     unsafe {
         G_CURRENT_THREAD = None;
     }
@@ -761,6 +1592,13 @@ pub fn get_scheduler(cpu_core: i32) -> &'static mut KScheduler {
     }
 }
 
+pub fn log_idle_stats() {
+    for core in 0..CPU_CORE_COUNT as i32 {
+        let idle_stats = get_scheduler(core).get_idle_stats();
+        log_line!("Core {}: {:.2}% busy, {} idle wakeups", core, idle_stats.get_busy_ratio() * 100.0, idle_stats.wakeup_count);
+    }
+}
+
 pub fn initialize_schedulers() -> Result<()> {
     unsafe {
         if G_SCHEDULERS.is_empty() {
@@ -776,15 +1614,40 @@ pub fn initialize_schedulers() -> Result<()> {
     Ok(())
 }
 
+// Idle thread statistics of a core: when guests aren't doing anything, the idle thread should be parked almost all the time (thus barely using any host CPU)
+#[derive(Copy, Clone, Debug)]
+pub struct IdleStats {
+    pub wakeup_count: u64,
+    pub parked_time: Duration,
+    pub uptime: Duration
+}
+
+impl IdleStats {
+    pub fn get_busy_ratio(&self) -> f64 {
+        match self.uptime.is_zero() {
+            true => 0.0,
+            false => 1.0 - (self.parked_time.as_secs_f64() / self.uptime.as_secs_f64()).min(1.0)
+        }
+    }
+}
+
 pub struct KScheduler {
     cpu_core: i32,
-    needs_scheduling: Mutex<bool>,
+    // Note: only set while selecting threads (thus inside the critical section), while the core itself clears it without locking anything
+    needs_scheduling: AtomicBool,
     selected_thread: Mutex<Option<Shared<KThread>>>,
     idle_interrupt_event: AutoResetEvent,
     cur_thread: Shared<KThread>,
     idle_thread: Shared<KThread>,
+    // Engine of the guest thread running on this core (if any), so that other cores can interrupt it (see request_reschedule)
+    // Note: always updated before cur_thread, thus the engine is kept alive by it while the lock is held
+    cur_exec_handle: Mutex<Option<cpu::ContextHandle>>,
+    reschedule_requested: AtomicBool,
     pub prev_thread: Option<Shared<KThread>>,
-    pub last_context_switch_instant: time::Instant
+    pub last_context_switch_instant: time::Instant,
+    start_instant: time::Instant,
+    idle_wakeup_count: AtomicU64,
+    idle_parked_time_ns: AtomicU64
 }
 
 impl KScheduler {
@@ -793,23 +1656,44 @@ impl KScheduler {
 
         Ok(Self {
             cpu_core: cpu_core,
-            needs_scheduling: Mutex::new(false),
+            needs_scheduling: AtomicBool::new(false),
             selected_thread: Mutex::new(None),
             idle_interrupt_event: AutoResetEvent::new(State::Unset),
             cur_thread: idle_thread.clone(),
             idle_thread: idle_thread,
+            cur_exec_handle: Mutex::new(None),
+            reschedule_requested: AtomicBool::new(false),
             prev_thread: None,
-            last_context_switch_instant: time::Instant::now()
+            last_context_switch_instant: time::Instant::now(),
+            start_instant: time::Instant::now(),
+            idle_wakeup_count: AtomicU64::new(0),
+            idle_parked_time_ns: AtomicU64::new(0)
         })
     }
 
+    // Blocks the (idle) host thread until the event is set, accounting the time it stays parked
+    fn park_idle_thread<E: Awaitable>(&self, event: &E) {
+        let park_start = time::Instant::now();
+        event.wait();
+
+        self.idle_parked_time_ns.fetch_add(park_start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        self.idle_wakeup_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get_idle_stats(&self) -> IdleStats {
+        IdleStats {
+            wakeup_count: self.idle_wakeup_count.load(Ordering::Relaxed),
+            parked_time: Duration::from_nanos(self.idle_parked_time_ns.load(Ordering::Relaxed)),
+            uptime: self.start_instant.elapsed()
+        }
+    }
+
     fn idle_thread_fn(cpu_core: i32) {
         log_line!("Hello World!");
     
         let scheduler = get_scheduler(cpu_core);
         loop {
-            *scheduler.needs_scheduling.lock() = false;
-            // TODO: memory barrier? (Ryujinx does so, might not be necessary at all here...)
+            scheduler.needs_scheduling.store(false, Ordering::SeqCst);
             let selected_thread = scheduler.selected_thread.lock().clone();
             let next_thread = scheduler.pick_next_thread(selected_thread);
 
@@ -817,10 +1701,11 @@ impl KScheduler {
                 get_scheduler_wait_event(&next_thread).set();
 
                 get_scheduler_wait_event(&scheduler.idle_thread).reset();
-                get_scheduler_wait_event(&scheduler.idle_thread).wait();
+                scheduler.park_idle_thread(&*get_scheduler_wait_event(&scheduler.idle_thread));
             }
 
-            scheduler.idle_interrupt_event.wait();
+            // Note: the idle thread stays parked until some scheduling change actually involves this core (see reschedule_other_cores and schedule)
+            scheduler.park_idle_thread(&scheduler.idle_interrupt_event);
         }
     }
 
@@ -838,7 +1723,7 @@ impl KScheduler {
                 let thread_ctx_lock = sel_thread_v.get().ctx.lock();
                 if thread_ctx_lock {
                     self.switch_to(Some(sel_thread_v.clone()));
-                    if !*self.needs_scheduling.lock() {
+                    if !self.needs_scheduling.load(Ordering::SeqCst) {
                         return sel_thread_v.clone();
                     }
 
@@ -853,8 +1738,7 @@ impl KScheduler {
                 return self.idle_thread.clone();
             }
 
-            *self.needs_scheduling.lock() = false;
-            // memory barrier?
+            self.needs_scheduling.store(false, Ordering::SeqCst);
             sel_thread = Some(self.selected_thread.lock().as_ref().unwrap().clone());
         }
     }
@@ -874,6 +1758,7 @@ impl KScheduler {
             }
 
             self.last_context_switch_instant = cur_instant;
+            metrics::record_context_switch();
 
             if has_current_process() {
                 let is_thread_running = !cur_thread.get().is_termination_requested();
@@ -893,11 +1778,12 @@ impl KScheduler {
             thread.get().cur_core = self.cpu_core;
         }
 
+        *self.cur_exec_handle.lock() = thread.get().cpu_exec_ctx.as_ref().map(|exec_ctx| exec_ctx.get_handle());
         self.cur_thread = thread;
     }
 
     pub fn schedule(&mut self) {
-        *self.needs_scheduling.lock() = false;
+        self.needs_scheduling.store(false, Ordering::SeqCst);
 
         let cur_thread = get_current_thread();
         let selected_thread = self.selected_thread.lock().clone();
@@ -911,15 +1797,32 @@ impl KScheduler {
         get_scheduler_wait_event(&cur_thread).reset();
         cur_thread.get().ctx.unlock();
 
+        // Only the cores which selected this thread (and couldn't lock its context since it was running here) need to retry now that it's unlocked, waking the rest would just make them spin
         for core in 0..CPU_CORE_COUNT as i32 {
-            get_scheduler(core).idle_interrupt_event.set();
+            let scheduler = get_scheduler(core);
+            let is_thread_selected = match &*scheduler.selected_thread.lock() {
+                Some(selected_thread) => selected_thread.ptr_eq(&cur_thread),
+                None => false
+            };
+            if is_thread_selected {
+                scheduler.idle_interrupt_event.set();
+            }
         }
 
-        let next_thread = self.pick_next_thread(selected_thread);
+        let next_thread = {
+            let _span = host_profiler::enter("scheduler");
+            self.pick_next_thread(selected_thread)
+        };
         get_scheduler_wait_event(&next_thread).set();
 
         if /* current thread exec ctx running? */ true {
+            // Note: time spent waiting to be scheduled again gets its own span, so that it's not mistaken for actual work of the enclosing ones
+            let _span = host_profiler::enter("scheduler_wait");
             get_scheduler_wait_event(&cur_thread).wait();
+
+            // The thread might have been resumed on a different core
+            let cur_core = cur_thread.get().cur_core;
+            pin_current_host_thread(cur_core);
         }
         else {
             cur_thread.get().is_schedulable = false;
@@ -941,7 +1844,7 @@ impl KScheduler {
             }
 
             *prev_selected_thread = next_thread;
-            *self.needs_scheduling.lock() = true;
+            self.needs_scheduling.store(true, Ordering::SeqCst);
             bit!(self.cpu_core)
         }
         else {
@@ -1008,6 +1911,138 @@ impl KScheduler {
         scheduled_cores_mask
     }
 
+    // Moves the current thread to the end of its priority queue, thus letting other threads with the same priority run
+    pub fn yield_thread() {
+        let _guard = make_critical_section_guard();
+
+        let cur_thread = get_current_thread();
+        let (state, priority, active_core) = {
+            let thread = cur_thread.get();
+            (thread.state, thread.priority, thread.active_core)
+        };
+        if state != ThreadState::Runnable {
+            return;
+        }
+
+        let next_thread = get_priority_queue().reschedule(priority, active_core, cur_thread.clone());
+        if let Some(next_thread) = next_thread {
+            if !next_thread.ptr_eq(&cur_thread) {
+                set_thread_reselection_requested(true);
+            }
+        }
+    }
+
+    // Like a regular yield, but also pulling a same-priority thread suggested for this core (from other cores), if it's not selected already in its core
+    pub fn yield_with_load_balancing() {
+        let _guard = make_critical_section_guard();
+
+        let cur_thread = get_current_thread();
+        let (state, priority, active_core) = {
+            let thread = cur_thread.get();
+            (thread.state, thread.priority, thread.active_core)
+        };
+        if state != ThreadState::Runnable {
+            return;
+        }
+
+        let next_thread = get_priority_queue().reschedule(priority, active_core, cur_thread.clone());
+
+        // TODO: also discard candidates which were scheduled after the next thread (threads don't keep their last scheduling time yet)
+        let mut dst_thread: Option<Shared<KThread>> = None;
+        for suggested_thread in get_priority_queue().get_suggested_threads_for_core(active_core) {
+            let (suggested_priority, suggested_core) = {
+                let thread = suggested_thread.get();
+                (thread.priority, thread.active_core)
+            };
+
+            if suggested_core >= 0 {
+                let is_selected_or_busy = match &*get_scheduler(suggested_core).selected_thread.lock() {
+                    Some(selected_thread) => selected_thread.ptr_eq(&suggested_thread) || (selected_thread.get().priority < 2),
+                    None => false
+                };
+                if is_selected_or_busy {
+                    continue;
+                }
+            }
+
+            if suggested_priority == priority {
+                dst_thread = Some(suggested_thread);
+                break;
+            }
+        }
+
+        let has_dst_thread = dst_thread.is_some();
+        if let Some(dst_thread) = dst_thread {
+            get_priority_queue().transfer_thread_to_core(priority, active_core, &dst_thread);
+        }
+
+        let has_next_thread = match next_thread {
+            Some(next_thread) => !next_thread.ptr_eq(&cur_thread),
+            None => false
+        };
+        if has_dst_thread || has_next_thread {
+            set_thread_reselection_requested(true);
+        }
+    }
+
+    // Gives up the current core (the thread stays as a suggestion for the cores in its affinity mask), letting threads of any priority run here
+    pub fn yield_to_any_thread() {
+        let _guard = make_critical_section_guard();
+
+        let cur_thread = get_current_thread();
+        let (state, priority, active_core) = {
+            let thread = cur_thread.get();
+            (thread.state, thread.priority, thread.active_core)
+        };
+        if state != ThreadState::Runnable {
+            return;
+        }
+
+        get_priority_queue().transfer_thread_to_core(priority, INVALID_CPU_CORE, &cur_thread);
+
+        if get_priority_queue().get_scheduled_threads_for_core(active_core).is_empty() {
+            let mut selected_thread: Option<Shared<KThread>> = None;
+            for suggested_thread in get_priority_queue().get_suggested_threads_for_core(active_core) {
+                let (suggested_priority, suggested_core) = {
+                    let thread = suggested_thread.get();
+                    (thread.priority, thread.active_core)
+                };
+                if suggested_core < 0 {
+                    continue;
+                }
+
+                let first_candidate = get_priority_queue().get_scheduled_threads_for_core(suggested_core).first().cloned();
+                if let Some(first_candidate) = first_candidate.as_ref() {
+                    if first_candidate.ptr_eq(&suggested_thread) {
+                        continue;
+                    }
+                }
+
+                let can_transfer = match first_candidate.as_ref() {
+                    Some(first_candidate) => first_candidate.get().priority >= 2,
+                    None => true
+                };
+                if can_transfer {
+                    get_priority_queue().transfer_thread_to_core(suggested_priority, active_core, &suggested_thread);
+                }
+
+                selected_thread = Some(suggested_thread);
+                break;
+            }
+
+            let is_cur_thread_selected = match selected_thread {
+                Some(selected_thread) => selected_thread.ptr_eq(&cur_thread),
+                None => false
+            };
+            if !is_cur_thread_selected {
+                set_thread_reselection_requested(true);
+            }
+        }
+        else {
+            set_thread_reselection_requested(true);
+        }
+    }
+
     pub fn enable_scheduling(scheduled_cores_mask: u64) {
         let cur_core = get_current_thread().get().cur_core;
         let cur_scheduler = get_scheduler(cur_core);
@@ -1031,7 +2066,7 @@ impl KScheduler {
             let scheduler = get_scheduler(core_to_signal);
 
             if !scheduler.cur_thread.ptr_eq(&scheduler.idle_thread) {
-                todo!("Request to reschedule");
+                scheduler.request_reschedule();
             }
 
             scheduler.idle_interrupt_event.set();
@@ -1039,8 +2074,22 @@ impl KScheduler {
         }
     }
 
+    // Interrupts the guest thread running on this core (from another one), which reschedules as soon as its execution stops (see KThread::exec_thread_fn)
+    // Note: host threads can't be interrupted, but they reschedule anyway on their next kernel call (when leaving the critical section)
+    fn request_reschedule(&self) {
+        if let Some(exec_handle) = self.cur_exec_handle.lock().as_mut() {
+            self.reschedule_requested.store(true, Ordering::SeqCst);
+            // Note: if the engine isn't running right now (the thread is inside a SVC handler, for instance) this does nothing, and the request is handled on the next stop
+            let _ = exec_handle.stop();
+        }
+    }
+
+    pub fn take_reschedule_request(&self) -> bool {
+        self.reschedule_requested.swap(false, Ordering::SeqCst)
+    }
+
     fn reschedule_current_core(&mut self) {
-        if *self.needs_scheduling.lock() {
+        if self.needs_scheduling.load(Ordering::SeqCst) {
             self.schedule();
         }
     }
@@ -1050,58 +2099,150 @@ impl KScheduler {
 
 // KConditionVariable
 
+// Wait queue owned by a kernel object: waiting threads keep track of it (see KThread::withholder), thus they can leave it on their own when timing out or being terminated
+#[derive(Clone)]
 pub struct KConditionVariable {
+    waiting_threads: Shared<VecDeque<Shared<KThread>>>,
+    // Note: the queue is only modified inside the critical section, but its size may be checked from anywhere, thus it's mirrored here (since checking the queue itself might find it locked)
+    waiting_thread_count: Arc<AtomicUsize>
 }
 
 impl KConditionVariable {
-    pub fn wait(thread_list: &mut Vec<Shared<KThread>>, timeout: Duration) {
+    pub fn new() -> Self {
+        Self {
+            waiting_threads: Shared::new(VecDeque::new()),
+            waiting_thread_count: Arc::new(AtomicUsize::new(0))
+        }
+    }
+
+    #[inline]
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        self.waiting_threads.ptr_eq(&other.waiting_threads)
+    }
+
+    pub fn get_waiting_thread_count(&self) -> usize {
+        self.waiting_thread_count.load(Ordering::SeqCst)
+    }
+
+    #[inline]
+    fn has_waiting_threads(&self) -> bool {
+        self.get_waiting_thread_count() > 0
+    }
+
+    // Note: must be called inside the critical section after modifying the queue
+    #[inline]
+    fn update_waiting_thread_count(&self, waiting_threads: &VecDeque<Shared<KThread>>) {
+        self.waiting_thread_count.store(waiting_threads.len(), Ordering::SeqCst);
+    }
+
+    fn remove(&self, thread: &Shared<KThread>) {
+        let mut waiting_threads = self.waiting_threads.get();
+        waiting_threads.retain(|waiting_thread| !waiting_thread.ptr_eq(thread));
+        self.update_waiting_thread_count(&waiting_threads);
+    }
+
+    fn resume(thread: &mut Shared<KThread>) {
+        thread.get().withholder = None;
+
+        let low_state = thread.get().state.get_low_flags();
+        if low_state == ThreadState::Waiting {
+            KThread::reschedule(thread, ThreadState::Runnable);
+        }
+    }
+
+    // Unlocks the object protecting the waited condition and waits until notified, timed out or terminated
+    // Note: the object is only unlocked once the thread is in the queue (inside the critical section), thus notifications sent right after can't get lost, even if they check the queue without entering the critical section. Since wakeups may also be caused by timeouts/termination, the caller must lock it again and re-check the condition
+    pub fn wait<T: ?Sized>(&self, obj_guard: MutexGuard<'_, T>, timeout: Option<Duration>) {
         get_critical_section().enter();
 
         let mut cur_thread = get_current_thread();
-        cur_thread.get().withholder = Some(thread_list.clone());
+        if cur_thread.get().is_termination_requested() {
+            drop(obj_guard);
+            get_critical_section().leave();
+            return;
+        }
+
+        cur_thread.get().withholder = Some(self.clone());
+        {
+            let mut waiting_threads = self.waiting_threads.get();
+            waiting_threads.push_back(cur_thread.clone());
+            self.update_waiting_thread_count(&waiting_threads);
+        }
         KThread::reschedule(&mut cur_thread, ThreadState::Waiting);
-        let withholder_idx = thread_list.len();
-        cur_thread.get().withholder_entry = Some(cur_thread.clone());
-        let cur_thread_clone = cur_thread.clone();
-        cur_thread.get().withholder.as_mut().unwrap().push(cur_thread_clone);
+        drop(obj_guard);
 
-        if cur_thread.get().is_termination_requested() {
-            thread_list.remove(withholder_idx);
+        if let Some(timeout) = timeout {
+            get_time_manager().schedule_future_invocation(cur_thread.clone(), timeout);
+        }
 
-            KThread::reschedule(&mut cur_thread, ThreadState::Runnable);
-            cur_thread.get().withholder = None;
+        get_critical_section().leave();
 
-            get_critical_section().leave();
+        if timeout.is_some() {
+            get_time_manager().unschedule_future_invocation(cur_thread.clone());
         }
-        else {
-            if !timeout.is_zero() {
-                get_time_manager().schedule_future_invocation(cur_thread.clone(), timeout);
-            }
 
-            get_critical_section().leave();
+        // Note: the thread already left the queue if it was notified or timed out, this covers any other way of getting resumed
+        let _guard = make_critical_section_guard();
+        let withholder = cur_thread.get().withholder.take();
+        if let Some(withholder) = withholder {
+            withholder.remove(&cur_thread);
+        }
+    }
 
-            if !timeout.is_zero() {
-                get_time_manager().unschedule_future_invocation(cur_thread.clone());
-            }
+    // Wakes up the longest waiting thread, returning whether there was any
+    pub fn notify_one(&self) -> bool {
+        // Nobody to wake up, which is the usual case, thus don't contend for the critical section (see wait)
+        if !self.has_waiting_threads() {
+            return false;
+        }
+
+        let _guard = make_critical_section_guard();
+
+        let thread = {
+            let mut waiting_threads = self.waiting_threads.get();
+            let thread = waiting_threads.pop_front();
+            self.update_waiting_thread_count(&waiting_threads);
+            thread
+        };
+        match thread {
+            Some(mut thread) => {
+                Self::resume(&mut thread);
+                true
+            },
+            None => false
         }
     }
 
-    pub fn notify_all(thread_list: &mut Vec<Shared<KThread>>) {
+    pub fn notify_all(&self) {
+        if !self.has_waiting_threads() {
+            return;
+        }
+
         let _guard = make_critical_section_guard();
 
-        let mut remove_withholder_entries: Vec<Shared<KThread>> = Vec::new();
-        for thread in thread_list.iter_mut() {
-            if let Some(withholder_entry) = thread.get().withholder_entry.as_ref() {
-                remove_withholder_entries.push(withholder_entry.clone());
-            }
+        let threads: Vec<Shared<KThread>> = self.waiting_threads.get().drain(..).collect();
+        self.waiting_thread_count.store(0, Ordering::SeqCst);
+        for mut thread in threads.into_iter() {
+            Self::resume(&mut thread);
+        }
+    }
 
-            thread.get().withholder_entry = None;
-            thread.get().withholder = None;
-            KThread::reschedule(thread, ThreadState::Runnable);
+    // Moves all the waiting threads to another queue (keeping their order) without waking them up
+    pub fn requeue_all(&self, other: &KConditionVariable) {
+        let _guard = make_critical_section_guard();
+
+        if self.ptr_eq(other) {
+            return;
         }
 
-        for obj in remove_withholder_entries.iter() {
-            thread_list.retain(|thread_obj| !thread_obj.ptr_eq(obj));
+        let threads: Vec<Shared<KThread>> = self.waiting_threads.get().drain(..).collect();
+        self.waiting_thread_count.store(0, Ordering::SeqCst);
+
+        let mut other_waiting_threads = other.waiting_threads.get();
+        for thread in threads.into_iter() {
+            thread.get().withholder = Some(other.clone());
+            other_waiting_threads.push_back(thread);
         }
+        other.update_waiting_thread_count(&other_waiting_threads);
     }
-}
\ No newline at end of file
+}
@@ -55,4 +55,90 @@ pub struct NsoHeader {
 
 impl NsoHeader {
     pub const MAGIC: u32 = u32::from_le_bytes(*b"NSO0");
-}
\ No newline at end of file
+}
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(C)]
+pub struct NroStart {
+    pub unused: u32,
+    pub mod_offset: u32,
+    pub padding: [u8; 0x8]
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(C)]
+pub struct NroSegmentHeader {
+    pub file_offset: u32,
+    pub size: u32
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(C)]
+pub struct NroHeader {
+    pub magic: u32,
+    pub version: u32,
+    pub size: u32,
+    pub flags: u32,
+    pub text_segment: NroSegmentHeader,
+    pub rodata_segment: NroSegmentHeader,
+    pub data_segment: NroSegmentHeader,
+    pub bss_size: u32,
+    pub reserved_1: [u8; 4],
+    pub module_id: [u8; 0x20],
+    pub dso_handle_offset: u32,
+    pub reserved_2: [u8; 4],
+    pub rodata_api_info_segment: NroSegmentHeader,
+    pub rodata_dynstr_segment: NroSegmentHeader,
+    pub rodata_dynsym_segment: NroSegmentHeader
+}
+
+impl NroHeader {
+    pub const MAGIC: u32 = u32::from_le_bytes(*b"NRO0");
+}
+
+// Offsets are relative to the MOD0 header itself, whose offset is found at .text + 0x4 (both for NSOs and NROs)
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(C)]
+pub struct Mod0Header {
+    pub magic: u32,
+    pub dynamic_offset: i32,
+    pub bss_start_offset: i32,
+    pub bss_end_offset: i32,
+    pub eh_frame_hdr_start_offset: i32,
+    pub eh_frame_hdr_end_offset: i32,
+    pub module_object_offset: i32
+}
+
+impl Mod0Header {
+    pub const MAGIC: u32 = u32::from_le_bytes(*b"MOD0");
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(C)]
+pub struct NrrCertification {
+    pub program_id_mask: u64,
+    pub program_id_pattern: u64,
+    pub reserved: [u8; 0x10],
+    pub public_key: [u8; 0x100],
+    pub signature: [u8; 0x100]
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(C)]
+pub struct NrrHeader {
+    pub magic: u32,
+    pub key_generation: u8,
+    pub reserved_1: [u8; 0xB],
+    pub certification: NrrCertification,
+    pub signature: [u8; 0x100],
+    pub program_id: u64,
+    pub size: u32,
+    pub nrr_kind: u8,
+    pub reserved_2: [u8; 3],
+    pub hashes_offset: u32,
+    pub hash_count: u32,
+    pub reserved_3: [u8; 8]
+}
+
+impl NrrHeader {
+    pub const MAGIC: u32 = u32::from_le_bytes(*b"NRR0");
+}
@@ -349,7 +349,7 @@ impl AcidFsAccessControlData {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ServiceAccessControlEntry {
     pub name: String,
     pub is_server: bool
@@ -364,7 +364,7 @@ impl ServiceAccessControlEntry {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ServiceAccessControlData {
     pub services: Vec<ServiceAccessControlEntry>
 }
@@ -0,0 +1,72 @@
+#![feature(const_btree_new)]
+#![feature(const_trait_impl)]
+#![feature(const_fn_trait_bound)]
+#![feature(thread_local)]
+#![feature(seek_stream_len)]
+#![feature(coerce_unsized)]
+#![feature(unsize)]
+#![feature(const_mut_refs)]
+#![feature(const_raw_ptr_deref)]
+#![feature(thread_id_value)]
+#![feature(derive_default_enum)]
+#![feature(specialization)]
+#![feature(adt_const_params)]
+#![feature(generic_const_exprs)]
+
+// For bit_enum enum names
+#![allow(non_snake_case)]
+
+// Library crate (pegasus_core) with the whole emulator, the pegasus binary being just a CLI frontend for it (see main.rs)
+// Other programs embed it through the embedding API (see embed)
+
+#[macro_use]
+pub mod result;
+
+#[macro_use]
+pub mod util;
+
+pub mod version;
+
+#[macro_use]
+pub mod ipc;
+
+pub mod ldr;
+
+pub mod emu;
+
+pub mod kern;
+
+pub mod os;
+
+pub mod sm;
+
+pub mod fs;
+
+pub mod set;
+
+pub mod spl;
+
+pub mod lm;
+
+pub mod fatal;
+
+pub mod ncm;
+
+pub mod account;
+
+pub mod applet;
+
+pub mod pl;
+
+pub mod ns;
+
+pub mod bsd;
+
+pub mod nifm;
+
+pub mod time;
+
+pub mod proc;
+
+pub mod embed;
+pub use embed::{ContentSource, Emulator, EmulatorBuilder, Process};
@@ -0,0 +1,119 @@
+// Note: https://switchbrew.org/wiki/Log_services
+
+bit_enum! {
+    LogPacketFlags (u8) {
+        Head = bit!(0),
+        Tail = bit!(1),
+        LittleEndian = bit!(2)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[repr(u8)]
+pub enum LogSeverity {
+    Trace = 0,
+    Info = 1,
+    Warn = 2,
+    Error = 3,
+    Fatal = 4
+}
+
+impl LogSeverity {
+    pub const fn from(raw: u8) -> Option<Self> {
+        match raw {
+            0 => Some(Self::Trace),
+            1 => Some(Self::Info),
+            2 => Some(Self::Warn),
+            3 => Some(Self::Error),
+            4 => Some(Self::Fatal),
+            _ => None
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[repr(u8)]
+pub enum LogDataChunkKey {
+    LogSessionBegin = 0,
+    LogSessionEnd = 1,
+    TextLog = 2,
+    LineNumber = 3,
+    FileName = 4,
+    FunctionName = 5,
+    ModuleName = 6,
+    ThreadName = 7,
+    LogPacketDropCount = 8,
+    UserSystemClock = 9,
+    ProcessName = 10
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[repr(C)]
+pub struct LogPacketHeader {
+    pub process_id: u64,
+    pub thread_id: u64,
+    pub flags: LogPacketFlags,
+    pub pad: u8,
+    pub severity: u8,
+    pub verbosity: u8,
+    pub payload_size: u32
+}
+
+const _: () = assert!(std::mem::size_of::<LogPacketHeader>() == 0x18);
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LogDataChunk {
+    pub key: u64,
+    pub data: Vec<u8>
+}
+
+impl LogDataChunk {
+    #[inline]
+    pub fn is(&self, key: LogDataChunkKey) -> bool {
+        self.key == key as u64
+    }
+
+    pub fn get_string(&self) -> String {
+        String::from_utf8_lossy(&self.data).trim_end_matches('\0').to_string()
+    }
+}
+
+fn read_uleb128(data: &[u8], offset: &mut usize) -> Option<u64> {
+    let mut value: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = *data.get(*offset)?;
+        *offset += 1;
+        value |= ((byte & 0x7F) as u64) << shift;
+        if (byte & 0x80) == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+// A packet is the header followed by its payload, a sequence of (uleb128 key, uleb128 size, data) chunks
+pub fn parse_log_packet(data: &[u8]) -> Option<(LogPacketHeader, Vec<LogDataChunk>)> {
+    let header_size = std::mem::size_of::<LogPacketHeader>();
+    if data.len() < header_size {
+        return None;
+    }
+
+    let header = unsafe { (data.as_ptr() as *const LogPacketHeader).read_unaligned() };
+    let payload = data.get(header_size..header_size.checked_add(header.payload_size as usize)?)?;
+
+    let mut chunks: Vec<LogDataChunk> = Vec::new();
+    let mut offset: usize = 0;
+    while offset < payload.len() {
+        let key = read_uleb128(payload, &mut offset)?;
+        let size = read_uleb128(payload, &mut offset)? as usize;
+        let chunk_data = payload.get(offset..offset.checked_add(size)?)?;
+        offset += size;
+
+        chunks.push(LogDataChunk {
+            key: key,
+            data: chunk_data.to_vec()
+        });
+    }
+
+    Some((header, chunks))
+}
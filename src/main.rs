@@ -1,171 +1,284 @@
-#![feature(const_btree_new)]
-#![feature(const_trait_impl)]
-#![feature(const_fn_trait_bound)]
-#![feature(thread_local)]
-#![feature(seek_stream_len)]
-#![feature(coerce_unsized)]
-#![feature(unsize)]
-#![feature(const_mut_refs)]
-#![feature(const_raw_ptr_deref)]
-#![feature(thread_id_value)]
-#![feature(derive_default_enum)]
-#![feature(specialization)]
-#![feature(adt_const_params)]
-#![feature(generic_const_exprs)]
-
-// For bit_enum enum names
-#![allow(non_snake_case)]
-
-use backtrace::Backtrace;
-use std::panic;
 use std::process;
+use pegasus_core::{emu, ldr, ncm, proc, result, util, log_line};
+use pegasus_core::embed::{ContentSource, EmulatorBuilder};
+use pegasus_core::result::ResultContext;
+
+// CLI frontend, everything else lives in the library crate (see lib.rs)
+
+// Interval at which the main loop checks whether the run is finished
+const MAIN_LOOP_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+const STATUS_UPDATE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+// Default instruction limit of 'core-lockstep' runs
+const DEFAULT_LOCKSTEP_INSTRUCTION_COUNT: usize = 100000;
+
+// Host setup failures are reported along with their context (see result::Error) instead of panicking
+fn exit_on_setup_error<T>(r: result::HostResult<T>) -> T {
+    match r {
+        Ok(t) => t,
+        Err(err) => {
+            emu::shutdown::flush_log_output();
+            println!("Startup failed: {}", err);
+            process::exit(1);
+        }
+    }
+}
 
-#[macro_use]
-pub mod result;
-
-#[macro_use]
-pub mod util;
-use util::make_log_guard;
-
-#[macro_use]
-pub mod ipc;
-
-pub mod ldr;
-
-pub mod emu;
-
-pub mod kern;
-use crate::fs::FileSystem;
-use crate::kern::thread::try_get_current_thread;
-use crate::util::Shared;
-
-pub mod os;
+fn load_core_dump(path: String) -> emu::coredump::CoreDump {
+    exit_on_setup_error(emu::coredump::CoreDump::load(path.clone()).with_context(|| format!("while loading core dump '{}'", path)))
+}
 
-pub mod sm;
+fn log_run_reports() {
+    match emu::service_mock::stop_recording() {
+        Ok(Some(call_count)) => log_line!("Saved service call recording ({} calls)", call_count),
+        Ok(None) => {},
+        Err(rc) => log_line!("Unable to save service call recording: {0} ({0:?})", rc)
+    };
+    emu::profiler::log_report();
+    emu::host_profiler::save_report();
+    pegasus_core::kern::proc::dump_handle_tables();
+    pegasus_core::util::dump_live_shared_objects();
+    emu::shutdown::flush_log_output();
+}
 
-pub mod fs;
+fn main() {
+    println!("Hello World!");
 
-pub mod set;
+    pegasus_core::embed::install_crash_handler();
 
-pub mod ncm;
+    // Ctrl+C requests a graceful shutdown, handled by the main loop below
+    emu::shutdown::install_signal_handler();
 
-pub mod proc;
+    // Golden trace options: record the SVC/IPC trace of this run, or compare it against a previously recorded one
+    let args: Vec<String> = std::env::args().collect();
+    let get_arg_value = |name: &str| args.iter().position(|arg| arg == name).and_then(|idx| args.get(idx + 1)).cloned();
+    let record_trace_path = get_arg_value("--record-trace");
+    let compare_trace_path = get_arg_value("--compare-trace");
+    let trace_mask = emu::trace::TraceMask::from_str(&get_arg_value("--trace-mask").unwrap_or_default());
+    if record_trace_path.is_some() || compare_trace_path.is_some() {
+        emu::trace::set_enabled(true);
+    }
 
-fn main() {
-    println!("Hello World!");
+    // Run modes for automated pipelines (see emu::run)
+    let run_options = emu::run::RunOptions::from_args(&args);
+
+    // 'svc-coverage' lists the implementation status of every SVC, optionally checking the ones enabled by a given NPDM
+    if args.get(1).map(|arg| arg.as_str()) == Some("svc-coverage") {
+        let npdm = get_arg_value("--npdm").map(|npdm_path| {
+            let npdm_data = exit_on_setup_error(util::convert_io_result(std::fs::read(&npdm_path)).with_context(|| format!("while reading NPDM '{}'", npdm_path)));
+            exit_on_setup_error(ldr::npdm::NpdmData::new(&npdm_data).with_context(|| format!("while parsing NPDM '{}'", npdm_path)))
+        });
+        let all_implemented = emu::kern::print_svc_coverage_report(npdm.as_ref());
+        process::exit(if all_implemented { 0 } else { 1 });
+    }
 
-    let orig_hook = panic::take_hook();
-    panic::set_hook(Box::new(move |panic_info| {
-        // Generate backtrace
-        // TODO: backtrace without panic calls, just everything before the panic?
-        // TODO: actual code backtrace for external programs?
-        let backtrace = Backtrace::new();
-
-        // Guard to prevent other thread logs to mix with the panic printing
-        let _guard = make_log_guard();
-
-        // Invoke the default panic handler
-        orig_hook(panic_info);
-
-        println!();
-
-        // Show information about the panicking thread/process, if possible
-        if let Some(thread) = try_get_current_thread() {
-            println!(" ---- Thread/process info ----");
-            println!();
-
-            if let Some(proc) = thread.get().owner_process.as_ref() {
-                println!("* Process name: '{}'", proc.get().npdm.meta.name.get_str().unwrap());
-                println!("* Process ID: {:#X}", proc.get().id);
-                println!("* Program ID: {}", proc.get().npdm.aci0.program_id);
-
-                if let Some(ctx) = proc.get().cpu_ctx.as_ref() {
-                    println!("* Modules:");
-                    for module in ctx.modules.iter() {
-                        let mod_name = match module.get_name() {
-                            Some(name) => name,
-                            None => String::from("<unk>")
-                        };
-
-                        println!(" -- {} (file: {})", mod_name, module.file_name);
-                    }
+    // 'core-info' loads a core dump (see KProcess::dump_core) and re-imports it into a fresh engine for post-mortem inspection
+    if args.get(1).map(|arg| arg.as_str()) == Some("core-info") {
+        let core_dump = load_core_dump(args.get(2).cloned().unwrap_or_default());
+        println!("Process '{}' (program ID {:#018X}, process ID {}, {:?})", core_dump.process_name, core_dump.program_id.0, core_dump.process_id, core_dump.arch);
+        for module in core_dump.modules.iter() {
+            println!("* Module '{}' ({} regions)", module.file_name, module.regions.len());
+        }
+        let memory_map = core_dump.get_memory_map();
+        println!("* Memory map:");
+        for entry in memory_map.iter() {
+            println!(" -- {}", entry);
+        }
+        for (i, thread) in core_dump.threads.iter().enumerate() {
+            let exec_ctx = exit_on_setup_error(core_dump.create_execution_context(i).with_context(|| format!("while importing thread {}", thread.id)));
+            let pc = exit_on_setup_error(exec_ctx.get_handle().read_register::<u64>(emu::cpu::Register::PC).with_context(|| format!("while reading the PC of thread {}", thread.id)));
+            let sp = exit_on_setup_error(exec_ctx.get_handle().read_register::<u64>(emu::cpu::Register::SP).with_context(|| format!("while reading the SP of thread {}", thread.id)));
+            println!("* Thread {}: PC {:#X} ({}), SP {:#X} ({})", thread.id, pc, emu::cpu::describe_address(&memory_map, pc), sp, emu::cpu::describe_address(&memory_map, sp));
+            if let Ok(disasm_lines) = emu::disasm::format_around_pc(&exec_ctx.get_handle(), pc, |address| emu::cpu::describe_address(&memory_map, address)) {
+                for disasm_line in disasm_lines.iter() {
+                    println!("   {}", disasm_line);
                 }
             }
-            else {
-                println!("* Not a process...");
+        }
+        process::exit(0);
+    }
+
+    // 'core-lockstep' runs a dumped thread on both unicorn and the interpreter in lockstep (see emu::cpu::lockstep), reporting the first instruction after which they diverge
+    if args.get(1).map(|arg| arg.as_str()) == Some("core-lockstep") {
+        let core_dump = load_core_dump(args.get(2).cloned().unwrap_or_default());
+        let thread_idx = get_arg_value("--thread").and_then(|thread_idx| thread_idx.parse::<usize>().ok()).unwrap_or(0);
+        let max_instruction_count = get_arg_value("--max-instructions").and_then(|count| count.parse::<usize>().ok()).unwrap_or(DEFAULT_LOCKSTEP_INSTRUCTION_COUNT);
+
+        let mut unicorn_backend = exit_on_setup_error(core_dump.create_backend(thread_idx, emu::cpu::backend::BackendKind::Unicorn).with_context(|| format!("while importing thread #{} into unicorn", thread_idx)));
+        let mut interpreter_backend = exit_on_setup_error(core_dump.create_backend(thread_idx, emu::cpu::backend::BackendKind::Interpreter).with_context(|| format!("while importing thread #{} into the interpreter", thread_idx)));
+        let compared_ranges = core_dump.get_thread_ranges(thread_idx);
+        let memory_map = core_dump.get_memory_map();
+        match exit_on_setup_error(emu::cpu::lockstep::run_lockstep(unicorn_backend.as_mut(), interpreter_backend.as_mut(), u64::MAX, max_instruction_count, &compared_ranges).context("while running both backends in lockstep")) {
+            emu::cpu::lockstep::LockstepOutcome::Finished { instruction_count } => {
+                println!("No divergence after {} instructions", instruction_count);
+                process::exit(0);
+            },
+            emu::cpu::lockstep::LockstepOutcome::Stopped { instruction_count, pc, kind, rc } => {
+                println!("No divergence after {} instructions, stopped at PC {:#X} ({}) since {} couldn't run it: {4} ({4:?})", instruction_count, pc, emu::cpu::describe_address(&memory_map, pc), kind, rc);
+                process::exit(0);
+            },
+            emu::cpu::lockstep::LockstepOutcome::Diverged(divergence) => {
+                println!("{}", divergence);
+                println!("* PC location: {}", emu::cpu::describe_address(&memory_map, divergence.pc));
+                process::exit(1);
             }
+        };
+    }
+
+    // 'core-heap' walks the guest heaps found in a core dump (see emu::heap), reporting their blocks and any corruption
+    if args.get(1).map(|arg| arg.as_str()) == Some("core-heap") {
+        let core_dump = load_core_dump(args.get(2).cloned().unwrap_or_default());
+        let read_memory = |address: u64, data: &mut [u8]| core_dump.read_memory(address, data);
+        let mut reports = emu::heap::analyze_lmem_heaps(&read_memory, &core_dump.get_memory_map());
+        // newlib arenas have no head to look for, thus their range must be given (same "<start>-<end>" format as traced ranges)
+        if let Some(range) = get_arg_value("--newlib-heap") {
+            match emu::disasm::parse_trace_range(&range) {
+                Some((start, end)) => reports.push(exit_on_setup_error(emu::heap::walk_newlib_heap(&read_memory, start, end).with_context(|| format!("while walking the newlib heap at {}", range)))),
+                None => println!("Invalid newlib heap range '{}'", range)
+            };
+        }
 
-            // TODO: thread name from TLS
-            println!("* Host thread name: '{}'", thread.get().get_host_name());
-            println!("* Is emulated thread: {}", thread.get().is_emu_thread());
-
-            // If the thread is from an actual external program, print some of its registers
-            if let Some(exec_ctx) = thread.get().cpu_exec_ctx.as_ref() {
-                let handle = exec_ctx.get_handle();
-                println!("* Registers:");
-                println!(" -- PC: {:#X}", handle.read_register::<u64>(emu::cpu::Register::PC).unwrap());
-                println!(" -- X0: {:#X}", handle.read_register::<u64>(emu::cpu::Register::X0).unwrap());
-                println!(" -- X1: {:#X}", handle.read_register::<u64>(emu::cpu::Register::X1).unwrap());
-                println!(" -- X2: {:#X}", handle.read_register::<u64>(emu::cpu::Register::X2).unwrap());
-                println!(" -- X3: {:#X}", handle.read_register::<u64>(emu::cpu::Register::X3).unwrap());
-                println!(" -- X4: {:#X}", handle.read_register::<u64>(emu::cpu::Register::X4).unwrap());
-                println!(" -- X5: {:#X}", handle.read_register::<u64>(emu::cpu::Register::X5).unwrap());
-                println!(" -- X6: {:#X}", handle.read_register::<u64>(emu::cpu::Register::X6).unwrap());
-                println!(" -- X7: {:#X}", handle.read_register::<u64>(emu::cpu::Register::X7).unwrap());
+        if reports.is_empty() {
+            println!("No heaps found");
+        }
+        for report in reports.iter() {
+            for line in emu::heap::format_report(report).iter() {
+                println!("{}", line);
             }
+        }
+        process::exit(if reports.iter().any(|report| report.is_corrupted()) { 1 } else { 0 });
+    }
 
-            println!();
+    // 'verify-contents' checks every registered NCA (hashes, headers and content metas), reporting corrupted or missing contents instead of launching anything
+    if args.get(1).map(|arg| arg.as_str()) == Some("verify-contents") {
+        exit_on_setup_error(emu::cfg::initialize());
+
+        let report = ncm::verify::verify_registered_contents();
+        for problem in report.problems.iter() {
+            println!("{}", problem);
         }
+        println!("Verified {} contents, found {} problems", report.verified_count, report.problems.len());
+        process::exit(if report.is_ok() { 0 } else { 1 });
+    }
 
-        // Print the backtrace
-        println!(" ---- Emulator backtrace ----");
-        println!();
+    let mut emu_builder = EmulatorBuilder::new();
 
-        println!("{:?}", backtrace);
+    // '--speed <unlimited|realtime|Nx>' overrides the configured speed (handy to fast-forward through boot sequences)
+    if let Some(speed) = get_arg_value("--speed") {
+        match emu::speed::parse_speed(&speed) {
+            Some((mode, fast_forward_multiplier)) => emu_builder = emu_builder.speed(mode, fast_forward_multiplier),
+            None => log_line!("Invalid speed '{}', expected 'unlimited', 'realtime' or a multiplier like '4x'", speed)
+        };
+    }
 
-        // Exit everything, panic = unrecoverable error
-        println!("Exiting...");
-        process::exit(1);
-    }));
+    // 'boot-system' launches the system modules listed in the boot manifest (which may also contain actual system titles) instead of just the emulated ones
+    if args.get(1).map(|arg| arg.as_str()) == Some("boot-system") {
+        let manifest = match get_arg_value("--boot-manifest") {
+            Some(path) => exit_on_setup_error(proc::boot2::BootManifest::load(path.clone()).with_context(|| format!("while loading boot manifest '{}'", path))),
+            None => exit_on_setup_error(proc::boot2::BootManifest::load_or_create_default().context("while loading the default boot manifest"))
+        };
+        emu_builder = emu_builder.boot_system(manifest);
+    }
 
-    emu::cfg::initialize().unwrap();
-    ncm::initialize().unwrap();
+    // Service call recording/replay (see emu::service_mock): record the replies of the given services (every one if none is given), or mock them with a previously recorded run (through the 'service_mock' boot module)
+    // Note: both must be set up before booting, since system modules already call services while starting
+    if let Some(path) = get_arg_value("--record-services") {
+        let service_names: Vec<String> = args.iter().enumerate().filter(|(_, arg)| arg.as_str() == "--record-service").filter_map(|(idx, _)| args.get(idx + 1).cloned()).collect();
+        emu::service_mock::start_recording(path, service_names);
+    }
+    else if let Some(path) = get_arg_value("--replay-services") {
+        exit_on_setup_error(emu::service_mock::load_replay_recording(&path).with_context(|| format!("while loading service call recording '{}'", path)));
+    }
 
-    kern::initialize().unwrap();
-    proc::initialize().unwrap();
+    let emulator = exit_on_setup_error(emu_builder.build());
 
-    enum TestRunKind {
-        SystemTitle(ncm::ProgramId),
-        TestNso(String)
+    // 'ipc-fuzz' sends malformed requests to the emulated services instead of running a program (see emu::fuzz)
+    if args.get(1).map(|arg| arg.as_str()) == Some("ipc-fuzz") {
+        let fuzz_options = emu::fuzz::FuzzOptions::from_args(&args);
+        let reports = exit_on_setup_error(emu::fuzz::run(&fuzz_options).context("while starting the fuzzing process"));
+        for report in reports.iter() {
+            emu::fuzz::log_report(report);
+        }
+        log_run_reports();
+        process::exit(0);
     }
 
-    // let run_kind = TestRunKind::SystemTitle(0x0100000000001000);
-    let run_kind = TestRunKind::TestNso(String::from("nso_test/build/exefs"));
+    // Run the integration test harness instead of a program
+    if args.iter().any(|arg| arg == "--run-tests") {
+        let payloads_passed = emu::harness::run_test_cases(&emu::harness::get_builtin_test_cases());
+        let host_tests_passed = emu::harness::run_host_test_cases(&emu::harness::get_builtin_host_test_cases());
+        let all_passed = payloads_passed && host_tests_passed;
+        log_run_reports();
+        process::exit(if all_passed { 0 } else { 1 });
+    }
 
     // Simplify running different kinds of programs while main is not properly finished (can't get to test IPC with system titles without implementing several SVCs)
+    // let content_source = ContentSource::Installed(ncm::StorageId::BuiltinSystem, ncm::ProgramId(0x0100000000001000));
+    let content_source = ContentSource::HostExeFs(String::from("nso_test/build/exefs"));
+
+    let process = exit_on_setup_error(emulator.launch(content_source));
+    let process_name = process.get_name();
+    let main_thread_id = process.get_main_thread_id().unwrap();
 
-    let exefs: Shared<dyn FileSystem> = match run_kind {
-        TestRunKind::SystemTitle(program_id) => {
-            let mut system_title_nca = ncm::lookup_content(ncm::StorageId::BuiltinSystem, program_id, cntx::nca::ContentType::Program).unwrap();
-            fs::PartitionFileSystem::from_nca(&mut system_title_nca, 0).unwrap()
-        },
-        TestRunKind::TestNso(exefs_path) => {
-            fs::HostFileSystem::new(exefs_path)
+    let run_start = std::time::Instant::now();
+    let mut last_status_update = run_start;
+    loop {
+        std::thread::sleep(MAIN_LOOP_INTERVAL);
+        if !run_options.headless && (last_status_update.elapsed() >= STATUS_UPDATE_INTERVAL) {
+            log_line!("Main --- loop update");
+            pegasus_core::kern::thread::log_idle_stats();
+            last_status_update = std::time::Instant::now();
         }
-    };
 
-    let mut cpu_ctx = emu::cpu::Context::new();
-    let (start_addr, npdm) = cpu_ctx.load_program(exefs, 0x6900000).unwrap();
-    let process_name = npdm.meta.name.get_string().unwrap();
-    let main_thread_host_name = format!("ext.{}.MainThread", process_name);
+        if emu::shutdown::is_shutdown_requested() {
+            log_line!("Shutdown requested, terminating guest processes...");
+            let summary = emulator.shutdown();
 
-    let mut process = kern::proc::KProcess::new(Some(cpu_ctx), npdm).unwrap();
-    let (mut main_thread, main_thread_handle) = kern::proc::KProcess::create_main_thread(&mut process, main_thread_host_name, start_addr).unwrap();
-    log_line!("Running process '{}' at {:#X}...", process_name, start_addr);
-    kern::thread::KThread::start_exec(&mut main_thread, 0u64, main_thread_handle).unwrap();
+            // Whatever was traced so far is still saved, instead of leaving nothing (or a truncated file) behind
+            if let Some(path) = record_trace_path.as_ref() {
+                let events = emu::trace::get_events();
+                emu::trace::save_trace(path, &events).unwrap();
+                log_line!("Saved trace ({} events) to '{}'", events.len(), path);
+            }
 
-    loop {
-        std::thread::sleep(std::time::Duration::from_secs(1));
-        log_line!("Main --- loop update");
+            log_line!("Shutdown summary: terminated {} threads of {} guest processes ({} didn't unwind in time) after running for {:?}", summary.terminated_thread_count, summary.terminated_process_count, summary.remaining_thread_count, run_start.elapsed());
+            log_run_reports();
+            process::exit(emu::shutdown::INTERRUPTED_EXIT_CODE);
+        }
+
+        if emu::trace::is_enabled() {
+            // When tracing, the run is considered finished once the program's main thread exits
+            let events = emu::trace::get_events();
+            let main_thread_exited = events.iter().any(|event| matches!(event, emu::trace::TraceEvent::ThreadExit { thread_id, .. } if *thread_id == main_thread_id));
+            if main_thread_exited {
+                if let Some(path) = record_trace_path.as_ref() {
+                    emu::trace::save_trace(path, &events).unwrap();
+                    log_line!("Saved trace ({} events) to '{}'", events.len(), path);
+                }
+
+                let mut trace_matches = true;
+                if let Some(path) = compare_trace_path.as_ref() {
+                    trace_matches = emu::trace::compare_with_golden_trace(path, &events, &trace_mask).unwrap();
+                }
+                log_run_reports();
+                process::exit(if trace_matches { 0 } else { 1 });
+            }
+        }
+
+        if run_options.exit_on_main_exit {
+            if let Some(exit_code) = process.get_exit_code() {
+                log_line!("Process '{}' exited with code {:#X}", process_name, exit_code);
+                log_run_reports();
+                process::exit(exit_code as i32);
+            }
+        }
+
+        if let Some(max_runtime) = run_options.max_runtime {
+            if run_start.elapsed() >= max_runtime {
+                log_line!("Maximum runtime ({:?}) reached, exiting...", max_runtime);
+                log_run_reports();
+                process::exit(emu::run::TIMED_OUT_EXIT_CODE);
+            }
+        }
     }
-}
\ No newline at end of file
+}
@@ -1,9 +1,15 @@
-use std::{collections::BTreeMap, fmt::{Debug, Display, Formatter, Result as FmtResult}, fs::{File as StdFile, read_dir}, path::PathBuf};
+use std::{collections::BTreeMap, fmt::{Debug, Display, Formatter, Result as FmtResult}, fs::File as StdFile, path::{Path, PathBuf}};
+use serde::{Serialize, Deserialize};
 use cntx::{nca::{ContentType as CntxContentType, NCA}, util::new_shared};
-use crate::{emu::cfg::{get_config, get_keyset}, fs::{DirectoryOpenMode, File, FileOpenMode, FileSystem, PartitionFileSystem, ReadOption, file_read_val}, result::*, util::{Shared, convert_io_result}};
+use crate::{emu::cfg::{get_config, get_keyset}, fs::{DirectoryOpenMode, File, FileOpenMode, FileSystem, HostFileSystem, PartitionFileSystem, ReadOnlyFileSystem, RomFsFileSystem, ReadOption, file_read_val}, result::*, util::{CString, Shared, convert_io_result}};
 pub mod result;
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub mod verify;
+
+pub mod storage;
+use storage::ContentStorageEntry;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(C)]
 pub struct ProgramId(pub u64);
 
@@ -19,7 +25,7 @@ impl Debug for ProgramId {
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
 #[repr(u8)]
 pub enum StorageId {
     None,
@@ -31,6 +37,21 @@ pub enum StorageId {
     Any
 }
 
+impl StorageId {
+    pub const fn from(raw: u8) -> Option<Self> {
+        match raw {
+            0 => Some(Self::None),
+            1 => Some(Self::Host),
+            2 => Some(Self::GameCard),
+            3 => Some(Self::BuiltinSystem),
+            4 => Some(Self::BuiltinUser),
+            5 => Some(Self::SdCard),
+            6 => Some(Self::Any),
+            _ => None
+        }
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
 #[repr(u8)]
 pub enum ContentType {
@@ -43,6 +64,21 @@ pub enum ContentType {
     DeltaFragment = 6
 }
 
+impl ContentType {
+    pub const fn from(raw: u8) -> Option<Self> {
+        match raw {
+            0 => Some(Self::Meta),
+            1 => Some(Self::Program),
+            2 => Some(Self::Data),
+            3 => Some(Self::Control),
+            4 => Some(Self::HtmlDocument),
+            5 => Some(Self::LegalInformation),
+            6 => Some(Self::DeltaFragment),
+            _ => None
+        }
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
 #[repr(u8)]
 pub enum ContentMetaType {
@@ -58,6 +94,37 @@ pub enum ContentMetaType {
     Delta = 0x83
 }
 
+impl ContentMetaType {
+    pub const fn from(raw: u8) -> Option<Self> {
+        match raw {
+            0x0 => Some(Self::Any),
+            0x1 => Some(Self::SystemProgram),
+            0x2 => Some(Self::SystemData),
+            0x3 => Some(Self::SystemUpdate),
+            0x4 => Some(Self::BootImagePackage),
+            0x5 => Some(Self::BootImagePackageSafe),
+            0x80 => Some(Self::Application),
+            0x81 => Some(Self::Patch),
+            0x82 => Some(Self::AddOnContent),
+            0x83 => Some(Self::Delta),
+            _ => None
+        }
+    }
+
+    // Types whose content metas belong to an application, which is in their extended header (unless they are the application itself)
+    pub const fn is_application_related(&self) -> bool {
+        matches!(*self, Self::Application | Self::Patch | Self::AddOnContent | Self::Delta)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[repr(u8)]
+pub enum ContentInstallType {
+    Full = 0,
+    FragmentOnly = 1,
+    Unknown = 7
+}
+
 bit_enum! {
     ContentMetaAttribute(u8) {
         None = 0,
@@ -123,6 +190,25 @@ pub struct ContentInfo {
     pub id_offset: u8
 }
 
+impl ContentInfo {
+    pub fn new(id: ContentId, size: u64, cnt_type: ContentType, id_offset: u8) -> Self {
+        let mut size_data: [u8; 0x6] = [0; 0x6];
+        size_data.copy_from_slice(&size.to_le_bytes()[..0x6]);
+        Self {
+            id: id,
+            size: size_data,
+            cnt_type: cnt_type,
+            id_offset: id_offset
+        }
+    }
+
+    pub fn get_size(&self) -> u64 {
+        let mut size_data: [u8; 0x8] = [0; 0x8];
+        size_data[..0x6].copy_from_slice(&self.size);
+        u64::from_le_bytes(size_data)
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 #[repr(C)]
 pub struct PackagedContentInfo {
@@ -140,48 +226,315 @@ pub struct ContentMetaInfo {
     pub reserved: [u8; 0x2]
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[repr(C)]
+pub struct ContentMetaKey {
+    pub program_id: ProgramId,
+    pub version: Version,
+    pub cnt_meta_type: ContentMetaType,
+    pub install_type: ContentInstallType,
+    pub padding: [u8; 0x2]
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(C)]
+pub struct ApplicationContentMetaKey {
+    pub key: ContentMetaKey,
+    pub application_id: ProgramId
+}
+
+// Header of content metas as stored in content meta databases, followed by the extended header, the content infos and the content meta infos (unlike packaged ones, without hashes)
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(C)]
+pub struct ContentMetaHeader {
+    pub extended_header_size: u16,
+    pub content_count: u16,
+    pub content_meta_count: u16,
+    pub cnt_meta_attr: ContentMetaAttribute,
+    pub reserved: u8
+}
+
+#[inline]
+fn val_as_bytes<T>(t: &T) -> &[u8] {
+    unsafe {
+        std::slice::from_raw_parts(t as *const T as *const u8, std::mem::size_of::<T>())
+    }
+}
+
+const _: () = assert!(std::mem::size_of::<ContentMetaKey>() == 0x10);
+const _: () = assert!(std::mem::size_of::<ContentMetaHeader>() == 0x8);
+
+// Content paths as returned by content storages (lr::Path)
+pub type ContentPath = CString<0x300>;
+
+// CNMT of a meta NCA
+pub struct PackagedContentMeta {
+    pub header: PackagedContentMetaHeader,
+    pub extended_header: Vec<u8>,
+    pub cnt_infos: Vec<PackagedContentInfo>,
+    pub cnt_meta_infos: Vec<ContentMetaInfo>
+}
+
+impl PackagedContentMeta {
+    pub fn read(nca: &mut NCA) -> Result<Self> {
+        let pfs0 = PartitionFileSystem::from_nca(nca, 0)?;
+        let cnmt = nca_pfs0_find_open_cnmt(&pfs0)?;
+
+        let header: PackagedContentMetaHeader = file_read_val(&cnmt, 0, ReadOption::None)?;
+        let mut offset = std::mem::size_of::<PackagedContentMetaHeader>() as u64;
+
+        let mut extended_header: Vec<u8> = vec![0; header.extended_header_size as usize];
+        let read_size = cnmt.get().read(offset, &mut extended_header, ReadOption::None)?;
+        result_return_unless!(read_size == extended_header.len(), result::ResultInvalidPackageFormat);
+        offset += extended_header.len() as u64;
+
+        let mut cnt_infos: Vec<PackagedContentInfo> = Vec::new();
+        for _ in 0..header.content_count {
+            cnt_infos.push(file_read_val(&cnmt, offset, ReadOption::None)?);
+            offset += std::mem::size_of::<PackagedContentInfo>() as u64;
+        }
+
+        let mut cnt_meta_infos: Vec<ContentMetaInfo> = Vec::new();
+        for _ in 0..header.content_meta_count {
+            cnt_meta_infos.push(file_read_val(&cnmt, offset, ReadOption::None)?);
+            offset += std::mem::size_of::<ContentMetaInfo>() as u64;
+        }
+
+        Ok(Self {
+            header: header,
+            extended_header: extended_header,
+            cnt_infos: cnt_infos,
+            cnt_meta_infos: cnt_meta_infos
+        })
+    }
+}
+
+// Installed content meta, as the content meta databases see it
+#[derive(Clone, Debug)]
+pub struct ContentMetaEntry {
+    pub key: ContentMetaKey,
+    pub attributes: ContentMetaAttribute,
+    pub extended_header: Vec<u8>,
+    pub cnt_infos: Vec<ContentInfo>,
+    pub cnt_meta_infos: Vec<ContentMetaInfo>
+}
+
+impl ContentMetaEntry {
+    pub fn from_packaged(packaged: &PackagedContentMeta, meta_cnt_info: ContentInfo) -> Self {
+        // Installed patch/delta extended headers lack the extended data size (its place is just padding)
+        let mut extended_header = packaged.extended_header.clone();
+        if matches!(packaged.header.cnt_meta_type, ContentMetaType::Patch | ContentMetaType::Delta) && (extended_header.len() >= 0x10) {
+            extended_header.truncate(0x10);
+            let padding_offset = match packaged.header.cnt_meta_type {
+                ContentMetaType::Patch => 0xC,
+                _ => 0x8
+            };
+            extended_header[padding_offset..].iter_mut().for_each(|byte| *byte = 0);
+        }
+
+        // Note: installing a content meta also registers the meta content itself
+        let mut cnt_infos = vec![meta_cnt_info];
+        cnt_infos.extend(packaged.cnt_infos.iter().map(|cnt_info| cnt_info.info));
+
+        Self {
+            key: ContentMetaKey {
+                program_id: packaged.header.program_id,
+                version: packaged.header.version,
+                cnt_meta_type: packaged.header.cnt_meta_type,
+                install_type: ContentInstallType::Full,
+                padding: [0; 0x2]
+            },
+            attributes: packaged.header.cnt_meta_attr,
+            extended_header: extended_header,
+            cnt_infos: cnt_infos,
+            cnt_meta_infos: packaged.cnt_meta_infos.clone()
+        }
+    }
+
+    fn read_extended_header_val<T: Copy>(&self, offset: usize) -> Option<T> {
+        match offset.checked_add(std::mem::size_of::<T>()) {
+            Some(end) if end <= self.extended_header.len() => Some(unsafe { (self.extended_header.as_ptr().add(offset) as *const T).read_unaligned() }),
+            _ => None
+        }
+    }
+
+    pub fn get_application_id(&self) -> Option<ProgramId> {
+        match self.key.cnt_meta_type {
+            ContentMetaType::Application => Some(self.key.program_id),
+            ContentMetaType::Patch | ContentMetaType::AddOnContent | ContentMetaType::Delta => self.read_extended_header_val::<u64>(0).map(ProgramId),
+            _ => None
+        }
+    }
+
+    pub fn get_patch_id(&self) -> Option<ProgramId> {
+        match self.key.cnt_meta_type {
+            ContentMetaType::Application => self.read_extended_header_val::<u64>(0).map(ProgramId),
+            _ => None
+        }
+    }
+
+    pub fn get_required_system_version(&self) -> Option<u32> {
+        match self.key.cnt_meta_type {
+            ContentMetaType::Application | ContentMetaType::Patch => self.read_extended_header_val::<u32>(0x8),
+            _ => None
+        }
+    }
+
+    pub fn get_required_application_version(&self) -> Option<u32> {
+        match self.key.cnt_meta_type {
+            ContentMetaType::Application => self.read_extended_header_val::<u32>(0xC),
+            ContentMetaType::AddOnContent => self.read_extended_header_val::<u32>(0x8),
+            _ => None
+        }
+    }
+
+    pub fn find_content_info(&self, cnt_type: ContentType, id_offset: u8) -> Option<&ContentInfo> {
+        self.cnt_infos.iter().find(|cnt_info| (cnt_info.cnt_type == cnt_type) && (cnt_info.id_offset == id_offset))
+    }
+
+    // The format content meta databases store (and return through Get)
+    pub fn make_data(&self) -> Vec<u8> {
+        let header = ContentMetaHeader {
+            extended_header_size: self.extended_header.len() as u16,
+            content_count: self.cnt_infos.len() as u16,
+            content_meta_count: self.cnt_meta_infos.len() as u16,
+            cnt_meta_attr: self.attributes,
+            reserved: 0
+        };
+
+        let mut data: Vec<u8> = Vec::new();
+        data.extend_from_slice(val_as_bytes(&header));
+        data.extend_from_slice(&self.extended_header);
+        for cnt_info in self.cnt_infos.iter() {
+            data.extend_from_slice(val_as_bytes(cnt_info));
+        }
+        for cnt_meta_info in self.cnt_meta_infos.iter() {
+            data.extend_from_slice(val_as_bytes(cnt_meta_info));
+        }
+        data
+    }
+}
+
 pub struct ContentEntry {
     path: String,
     program_id: ProgramId,
-    cnt_type: CntxContentType
+    cnt_type: CntxContentType,
+    // Known as long as the file is named after it
+    content_id: Option<ContentId>,
+    size: u64
 }
 
-#[inline]
-fn make_registered_path(nand_path: PathBuf) -> PathBuf {
-    nand_path.join("Contents").join("registered")
+static mut G_CONTENT_TABLE: BTreeMap<StorageId, Vec<ContentEntry>> = BTreeMap::new();
+static mut G_CONTENT_META_TABLE: BTreeMap<StorageId, Vec<ContentMetaEntry>> = BTreeMap::new();
+
+pub fn parse_content_id(path: &Path) -> Option<ContentId> {
+    // Registered contents are named '<content-id>.nca' (or '<content-id>.cnmt.nca' for meta ones)
+    let file_name = path.file_name()?.to_str()?;
+    let id_str = file_name.split('.').next()?;
+    if id_str.len() != 2 * std::mem::size_of::<ContentId>() {
+        return None;
+    }
+
+    let mut content_id: ContentId = [0; 0x10];
+    for (i, byte) in content_id.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(id_str.get(2 * i..2 * i + 2)?, 16).ok()?;
+    }
+    Some(content_id)
 }
 
-static mut G_CONTENT_TABLE: BTreeMap<StorageId, Vec<ContentEntry>> = BTreeMap::new();
+fn open_scanned_nca(path: &Path) -> HostResult<(NCA, u64)> {
+    let nca_file = StdFile::open(path)?;
+    let size = nca_file.metadata()?.len();
+    let nca_reader = new_shared(nca_file);
+    let nca = NCA::new(nca_reader, get_keyset(), None)?;
+    Ok((nca, size))
+}
 
-fn scan_registered_storage_contents(storage_id: StorageId, registered_path: PathBuf) -> Result<()> {
+// Contents are added to the ones other backends of the same storage already scanned, the first scanned one winning when the same content is found again
+fn scan_storage_contents(storage: &ContentStorageEntry) -> HostResult<()> {
+    let storage_id = storage.storage_id;
     let mut cnts: Vec<ContentEntry> = Vec::new();
+    let mut cnt_metas: Vec<ContentMetaEntry> = Vec::new();
+
+    for path in storage.backend.list_content_paths()? {
+        let content_id = parse_content_id(&path);
+        if let Some(content_id) = content_id {
+            if find_content(storage_id, content_id).is_some() || cnts.iter().any(|cnt| cnt.content_id == Some(content_id)) {
+                log_line!("[{:?}] Skipping already scanned content at '{}'", storage_id, path.display());
+                continue;
+            }
+        }
+
+        let (mut nca, size) = open_scanned_nca(&path).with_context(|| format!("while opening NCA '{}'", path.display()))?;
 
-    for entry in convert_io_result(read_dir(registered_path))? {
-        if let Ok(dir_entry) = entry {
+        let cnt_entry = ContentEntry {
+            path: path.display().to_string(),
+            program_id: ProgramId(nca.header.program_id),
+            cnt_type: nca.header.cnt_type,
+            content_id: content_id,
+            size: size
+        };
 
-            let nca_reader = new_shared(convert_io_result(StdFile::open(dir_entry.path()))?);
-            let nca = convert_io_result(NCA::new(nca_reader, get_keyset(), None))?;
+        log_line!("[{:?}] Scanned content archive (NCA) {} of type {:?}", storage_id, cnt_entry.program_id, cnt_entry.cnt_type);
 
-            let cnt_entry = ContentEntry {
-                path: dir_entry.path().as_path().display().to_string(),
-                program_id: ProgramId(nca.header.program_id),
-                cnt_type: nca.header.cnt_type
+        // Meta contents make up the content meta database, those without a known content ID can't be referenced by it
+        if let (CntxContentType::Meta, Some(content_id)) = (cnt_entry.cnt_type, cnt_entry.content_id) {
+            match PackagedContentMeta::read(&mut nca) {
+                Ok(packaged_cnt_meta) => cnt_metas.push(ContentMetaEntry::from_packaged(&packaged_cnt_meta, ContentInfo::new(content_id, size, ContentType::Meta, 0))),
+                Err(rc) => log_line!("[{0:?}] Unable to read the content meta of {1}: {2} ({2:?})", storage_id, cnt_entry.program_id, rc)
             };
+        }
+
+        cnts.push(cnt_entry);
+    }
+
+    unsafe {
+        G_CONTENT_TABLE.entry(storage_id).or_default().extend(cnts);
+        G_CONTENT_META_TABLE.entry(storage_id).or_default().extend(cnt_metas);
+    }
+
+    Ok(())
+}
 
-            log_line!("[{:?}] Scanned content archive (NCA) {} of type {:?}", storage_id, cnt_entry.program_id, cnt_entry.cnt_type);
+#[inline]
+pub fn is_storage_scanned(storage_id: StorageId) -> bool {
+    unsafe {
+        G_CONTENT_TABLE.contains_key(&storage_id)
+    }
+}
 
-            cnts.push(cnt_entry);
+pub fn list_content_ids(storage_id: StorageId) -> Vec<ContentId> {
+    unsafe {
+        match G_CONTENT_TABLE.get(&storage_id) {
+            Some(storage_cnts) => storage_cnts.iter().filter_map(|cnt| cnt.content_id).collect(),
+            None => Vec::new()
         }
     }
+}
 
+// Host path and size of a content
+pub fn find_content(storage_id: StorageId, content_id: ContentId) -> Option<(String, u64)> {
     unsafe {
-        G_CONTENT_TABLE.insert(storage_id, cnts);
+        G_CONTENT_TABLE.get(&storage_id)?.iter().find(|cnt| cnt.content_id == Some(content_id)).map(|cnt| (cnt.path.clone(), cnt.size))
     }
+}
 
-    Ok(())
+pub fn list_content_metas(storage_id: StorageId) -> Vec<ContentMetaEntry> {
+    unsafe {
+        match G_CONTENT_META_TABLE.get(&storage_id) {
+            Some(storage_cnt_metas) => storage_cnt_metas.clone(),
+            None => Vec::new()
+        }
+    }
 }
 
-pub fn lookup_content(storage_id: StorageId, program_id: ProgramId, cnt_type: CntxContentType) -> Result<NCA> {
+pub fn find_content_meta(storage_id: StorageId, key: &ContentMetaKey) -> Option<ContentMetaEntry> {
+    unsafe {
+        G_CONTENT_META_TABLE.get(&storage_id)?.iter().find(|cnt_meta| cnt_meta.key == *key).cloned()
+    }
+}
+
+fn lookup_nca(storage_id: StorageId, program_id: ProgramId, cnt_type: CntxContentType) -> Result<NCA> {
     unsafe {
         if let Some(storage_cnts) = G_CONTENT_TABLE.get(&storage_id) {
             if let Some(cnt) = storage_cnts.iter().find(|f_cnt| (f_cnt.program_id == program_id) && (f_cnt.cnt_type == cnt_type)) {
@@ -196,6 +549,78 @@ pub fn lookup_content(storage_id: StorageId, program_id: ProgramId, cnt_type: Cn
     result::ResultContentNotFound::make_err()
 }
 
+// Virtual NCAs: host directories (with exefs/ and romfs/ subdirectories) registered in the config as the program content of a program ID, so that titles can be iterated on without packing any NCAs
+// Note: they take precedence over the actual program content in every storage, and since they are not actual NCAs (they have no content ID) content storages don't list them
+
+pub const VIRTUAL_CONTENT_EXEFS_DIR: &str = "exefs";
+pub const VIRTUAL_CONTENT_ROMFS_DIR: &str = "romfs";
+
+pub fn find_virtual_content_path(program_id: ProgramId) -> Option<PathBuf> {
+    get_config().virtual_contents.iter().find_map(|(program_id_str, path)| {
+        match u64::from_str_radix(program_id_str.trim_start_matches("0x"), 16) {
+            Ok(virtual_program_id) if virtual_program_id == program_id.0 => Some(PathBuf::from(path)),
+            _ => None
+        }
+    })
+}
+
+#[inline]
+pub fn is_virtual_content(program_id: ProgramId, cnt_type: CntxContentType) -> bool {
+    (cnt_type == CntxContentType::Program) && find_virtual_content_path(program_id).is_some()
+}
+
+pub struct VirtualContent {
+    pub program_id: ProgramId,
+    pub path: PathBuf
+}
+
+impl VirtualContent {
+    fn open_host_filesystem(&self, dir_name: &str) -> Result<Shared<dyn FileSystem>> {
+        let path = self.path.join(dir_name);
+        result_return_unless!(path.is_dir(), result::ResultContentNotFound);
+
+        // Note: like actual NCA sections, these are never writable (despite being plain host directories)
+        let host_fs: Shared<dyn FileSystem> = HostFileSystem::new(path.display().to_string());
+        Ok(ReadOnlyFileSystem::new(host_fs))
+    }
+}
+
+pub enum Content {
+    Nca(NCA),
+    Virtual(VirtualContent)
+}
+
+impl Content {
+    pub fn open_partition_filesystem(&mut self, fs_idx: usize) -> Result<Shared<dyn FileSystem>> {
+        match self {
+            Self::Nca(nca) => Ok(PartitionFileSystem::from_nca(nca, fs_idx)?),
+            // Note: program NCAs only have one partition filesystem, the ExeFS
+            Self::Virtual(virtual_cnt) => virtual_cnt.open_host_filesystem(VIRTUAL_CONTENT_EXEFS_DIR)
+        }
+    }
+
+    pub fn open_romfs_filesystem(&mut self, fs_idx: usize) -> Result<Shared<dyn FileSystem>> {
+        match self {
+            Self::Nca(nca) => Ok(RomFsFileSystem::from_nca(nca, fs_idx)?),
+            Self::Virtual(virtual_cnt) => virtual_cnt.open_host_filesystem(VIRTUAL_CONTENT_ROMFS_DIR)
+        }
+    }
+}
+
+pub fn lookup_content(storage_id: StorageId, program_id: ProgramId, cnt_type: CntxContentType) -> Result<Content> {
+    if cnt_type == CntxContentType::Program {
+        if let Some(path) = find_virtual_content_path(program_id) {
+            log_line!("[{:?}] Serving program {} from virtual content at '{}'", storage_id, program_id, path.display());
+            return Ok(Content::Virtual(VirtualContent {
+                program_id: program_id,
+                path: path
+            }));
+        }
+    }
+
+    Ok(Content::Nca(lookup_nca(storage_id, program_id, cnt_type)?))
+}
+
 #[inline]
 pub fn nca_pfs0_find_open_cnmt(pfs0: &Shared<PartitionFileSystem>) -> Result<Shared<dyn File>> {
     let root_dir = pfs0.get().open_directory(PathBuf::from(""), DirectoryOpenMode::ReadFiles())?;
@@ -211,7 +636,7 @@ pub fn nca_pfs0_find_open_cnmt(pfs0: &Shared<PartitionFileSystem>) -> Result<Sha
 
 pub fn verify_system_contents() -> Result<()> {
     const SYSTEM_UPDATE_ID: ProgramId = ProgramId(0x0100000000000816);
-    let mut system_update_nca = lookup_content(StorageId::BuiltinSystem, SYSTEM_UPDATE_ID, CntxContentType::Meta)?;
+    let mut system_update_nca = lookup_nca(StorageId::BuiltinSystem, SYSTEM_UPDATE_ID, CntxContentType::Meta)?;
     let system_update_nca_pfs0 = PartitionFileSystem::from_nca(&mut system_update_nca, 0)?;
     let system_update_cnmt = nca_pfs0_find_open_cnmt(&system_update_nca_pfs0)?;
 
@@ -227,7 +652,7 @@ pub fn verify_system_contents() -> Result<()> {
         let cnt_meta_info: ContentMetaInfo = file_read_val(&system_update_cnmt, cnt_meta_info_offset, ReadOption::None)?;
 
         // Verify the content -> find it (ensure it's present), open it's CNMT and check that the program ID and content type match
-        let mut cnt_cnmt_nca = lookup_content(StorageId::BuiltinSystem, cnt_meta_info.program_id, CntxContentType::Meta)?;
+        let mut cnt_cnmt_nca = lookup_nca(StorageId::BuiltinSystem, cnt_meta_info.program_id, CntxContentType::Meta)?;
         let cnt_cnmt_nca_pfs0 = PartitionFileSystem::from_nca(&mut cnt_cnmt_nca, 0)?;
         let cnt_cnmt = nca_pfs0_find_open_cnmt(&cnt_cnmt_nca_pfs0)?;
 
@@ -241,11 +666,24 @@ pub fn verify_system_contents() -> Result<()> {
     Ok(())
 }
 
-pub fn initialize() -> Result<()> {
-    let nand_system_path = PathBuf::from(get_config().nand_system_path.clone());
-    let nand_system_registered_path = make_registered_path(nand_system_path);
-    scan_registered_storage_contents(StorageId::BuiltinSystem, nand_system_registered_path)?;
-    verify_system_contents()?;
+fn scan_storage(storage: &ContentStorageEntry) -> HostResult<()> {
+    log_line!("[{:?}] Scanning {}", storage.storage_id, storage.backend.get_description());
+    scan_storage_contents(storage).with_context(|| format!("while scanning {:?} contents ({})", storage.storage_id, storage.backend.get_description()))
+}
+
+pub fn initialize() -> HostResult<()> {
+    // Content storages are set up from the config (see ncm::storage), plus any backend registered beforehand
+    let mut storages = storage::make_configured_storages();
+    storages.extend(storage::take_registered_storages());
+    unsafe {
+        G_CONTENT_TABLE.clear();
+        G_CONTENT_META_TABLE.clear();
+    }
+    for storage in storages.iter() {
+        scan_storage(storage)?;
+    }
 
+    // Note: the system storage must always be present, thus verifying it also fails if it has no backends at all
+    verify_system_contents().context("while verifying the system contents (is the system update meta present?)")?;
     Ok(())
 }
\ No newline at end of file
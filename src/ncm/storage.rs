@@ -0,0 +1,198 @@
+use std::fs::read_dir;
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+use crate::emu::cfg::{ContentStorageConfig, get_config};
+use crate::result::*;
+use super::StorageId;
+
+// Content storage backends: where the contents of each storage are scanned from at startup (see ncm::initialize)
+// Several backends may be registered for the same storage (like a system dump plus some loose NCAs), their contents being merged in registration order
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum ContentStorageKind {
+    // A dump in the console's layout, with the contents at 'Contents/registered' (within the NAND partition, or the SD card's 'Nintendo' directory)
+    Registered,
+    // Every NCA within a host directory (and its subdirectories)
+    // Note: NCAs not named after their content ID can still be launched, but aren't listed by content storages or content meta databases
+    LooseNcas
+}
+
+pub trait ContentStorageBackend {
+    fn get_description(&self) -> String;
+
+    // Host paths of every content in the storage
+    fn list_content_paths(&self) -> HostResult<Vec<PathBuf>>;
+}
+
+pub struct RegisteredContentStorage {
+    registered_path: PathBuf
+}
+
+impl RegisteredContentStorage {
+    pub fn new(storage_id: StorageId, path: PathBuf) -> Self {
+        let base_path = match storage_id {
+            StorageId::SdCard => path.join("Nintendo"),
+            _ => path
+        };
+
+        Self {
+            registered_path: base_path.join("Contents").join("registered")
+        }
+    }
+
+    pub fn exists(&self) -> bool {
+        self.registered_path.is_dir()
+    }
+}
+
+impl ContentStorageBackend for RegisteredContentStorage {
+    fn get_description(&self) -> String {
+        format!("registered contents at '{}'", self.registered_path.display())
+    }
+
+    fn list_content_paths(&self) -> HostResult<Vec<PathBuf>> {
+        let mut paths: Vec<PathBuf> = Vec::new();
+        for entry in read_dir(&self.registered_path)? {
+            paths.push(entry?.path());
+        }
+
+        Ok(paths)
+    }
+}
+
+pub struct LooseNcaContentStorage {
+    path: PathBuf
+}
+
+impl LooseNcaContentStorage {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path: path
+        }
+    }
+}
+
+fn find_nca_paths(dir_path: &Path, paths: &mut Vec<PathBuf>) -> HostResult<()> {
+    for entry in read_dir(dir_path)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_nca_paths(&path, paths)?;
+        }
+        else if path.extension().map(|ext| ext.eq_ignore_ascii_case("nca")).unwrap_or(false) {
+            paths.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+impl ContentStorageBackend for LooseNcaContentStorage {
+    fn get_description(&self) -> String {
+        format!("loose NCAs at '{}'", self.path.display())
+    }
+
+    fn list_content_paths(&self) -> HostResult<Vec<PathBuf>> {
+        let mut paths: Vec<PathBuf> = Vec::new();
+        find_nca_paths(&self.path, &mut paths)?;
+
+        // Directory order isn't guaranteed, while duplicated contents are resolved by order
+        paths.sort();
+        Ok(paths)
+    }
+}
+
+pub struct ContentStorageEntry {
+    pub storage_id: StorageId,
+    pub backend: Box<dyn ContentStorageBackend>
+}
+
+pub fn make_backend(storage_config: &ContentStorageConfig) -> Box<dyn ContentStorageBackend> {
+    let path = PathBuf::from(storage_config.path.clone());
+    match storage_config.kind {
+        ContentStorageKind::Registered => Box::new(RegisteredContentStorage::new(storage_config.storage_id, path)),
+        ContentStorageKind::LooseNcas => Box::new(LooseNcaContentStorage::new(path))
+    }
+}
+
+// The backends listed in the config, plus the dumps at the NAND/SD card paths for the storages without any listed backend
+// Note: the system storage must always be present, while the user/SD card ones are skipped if there isn't anything there
+pub fn make_configured_storages() -> Vec<ContentStorageEntry> {
+    let config = get_config();
+    let mut storages: Vec<ContentStorageEntry> = config.content_storages.iter().map(|storage_config| ContentStorageEntry {
+        storage_id: storage_config.storage_id,
+        backend: make_backend(storage_config)
+    }).collect();
+
+    let default_storages = [
+        (StorageId::BuiltinSystem, &config.nand_system_path, true),
+        (StorageId::BuiltinUser, &config.nand_user_path, false),
+        (StorageId::SdCard, &config.sd_card_path, false)
+    ];
+    for (storage_id, path, is_required) in default_storages.iter() {
+        if storages.iter().any(|storage| storage.storage_id == *storage_id) {
+            continue;
+        }
+
+        let backend = RegisteredContentStorage::new(*storage_id, PathBuf::from((*path).clone()));
+        if *is_required || backend.exists() {
+            storages.push(ContentStorageEntry {
+                storage_id: *storage_id,
+                backend: Box::new(backend)
+            });
+        }
+    }
+
+    storages
+}
+
+static mut G_EXTRA_STORAGES: Vec<ContentStorageEntry> = Vec::new();
+
+// Backends registered this way (before ncm gets initialized, like from embedders) are scanned after the configured ones
+pub fn register_backend(storage_id: StorageId, backend: Box<dyn ContentStorageBackend>) {
+    unsafe {
+        G_EXTRA_STORAGES.push(ContentStorageEntry {
+            storage_id: storage_id,
+            backend: backend
+        });
+    }
+}
+
+pub fn take_registered_storages() -> Vec<ContentStorageEntry> {
+    unsafe {
+        std::mem::take(&mut G_EXTRA_STORAGES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::TestTempDir;
+    use super::*;
+
+    #[test]
+    fn list_content_paths() {
+        let temp_dir = TestTempDir::new("content_storages");
+        let base_path = temp_dir.path();
+
+        // Loose NCAs are found in subdirectories too, anything else being ignored
+        let loose_path = base_path.join("loose");
+        let sd_registered_path = base_path.join("sd").join("Nintendo").join("Contents").join("registered");
+        let files = [
+            loose_path.join("b.nca"),
+            loose_path.join("title").join("a.NCA"),
+            loose_path.join("readme.txt"),
+            sd_registered_path.join("0123456789abcdef0123456789abcdef.nca")
+        ];
+        for file_path in files.iter() {
+            std::fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+            std::fs::write(file_path, b"").unwrap();
+        }
+
+        let loose_paths = LooseNcaContentStorage::new(loose_path.clone()).list_content_paths().unwrap();
+        assert_eq!(loose_paths, vec![files[0].clone(), files[1].clone()]);
+
+        // SD card dumps have their contents within the 'Nintendo' directory
+        let sd_paths = RegisteredContentStorage::new(StorageId::SdCard, base_path.join("sd")).list_content_paths().unwrap();
+        assert_eq!(sd_paths, vec![files[3].clone()]);
+        assert!(!RegisteredContentStorage::new(StorageId::BuiltinUser, base_path.join("sd")).exists(), "the user storage was found within the SD card's directory");
+    }
+}
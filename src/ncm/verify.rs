@@ -0,0 +1,221 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::fs::{File as StdFile, read_dir};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use cntx::{nca::{ContentType as CntxContentType, NCA}, util::new_shared};
+use sha2::{Sha256, Digest};
+use crate::emu::cfg::get_keyset;
+use crate::util::convert_io_result;
+use crate::result::*;
+use super::*;
+
+// Content verification: corrupted dumps otherwise show up as obscure failures (or panics) deep inside cntx, so every registered NCA is checked before anything tries to parse it
+// - the SHA-256 hash of the whole NCA must match its content ID (the first half of the hash, as in its file name)
+// - only NCAs passing that check get their header parsed (which needs the header key, thus fails with missing keys as well)
+// - every content listed by the CNMT of each meta NCA must be present, with the hash the CNMT expects
+// Note: NCA header signatures are not checked, since the hash checks above already catch any modification of the dumped files
+
+pub type Sha256Hash = [u8; 0x20];
+
+const HASH_BUFFER_SIZE: usize = 0x100000;
+
+#[derive(Clone, Debug)]
+pub enum ContentProblem {
+    // The file (or any of its parts) couldn't be read
+    Unreadable(ResultCode),
+    // The file name is not a content ID, thus there is nothing to check the hash against
+    UnknownContentId,
+    HashMismatch { actual_hash: Sha256Hash },
+    // The header couldn't be decrypted/parsed (missing keys or a corrupted header)
+    InvalidHeader(ResultCode),
+    // The CNMT of a meta NCA couldn't be read
+    InvalidContentMeta(ResultCode),
+    // A content listed by a CNMT is not present in the storage
+    MissingContent { meta_program_id: ProgramId, content_id: ContentId, cnt_type: ContentType },
+    // A content listed by a CNMT is present, but its hash isn't the one the CNMT expects
+    ContentMetaHashMismatch { meta_program_id: ProgramId, content_id: ContentId, cnt_type: ContentType }
+}
+
+#[derive(Clone, Debug)]
+pub struct ContentReport {
+    pub storage_id: StorageId,
+    pub path: String,
+    pub problem: ContentProblem
+}
+
+impl Display for ContentReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "[{:?}] '{}': ", self.storage_id, self.path)?;
+        match self.problem {
+            ContentProblem::Unreadable(rc) => write!(f, "unable to read it: {0} ({0:?})", rc),
+            ContentProblem::UnknownContentId => write!(f, "the file name is not a content ID, unable to check its hash"),
+            ContentProblem::HashMismatch { actual_hash } => write!(f, "corrupted, its hash ({}) doesn't match its content ID", format_hex(&actual_hash)),
+            ContentProblem::InvalidHeader(rc) => write!(f, "invalid header (missing keys?): {0} ({0:?})", rc),
+            ContentProblem::InvalidContentMeta(rc) => write!(f, "unable to read its content meta: {0} ({0:?})", rc),
+            ContentProblem::MissingContent { meta_program_id, content_id, cnt_type } => write!(f, "content {} ({:?}) of {} is missing", format_hex(&content_id), cnt_type, meta_program_id),
+            ContentProblem::ContentMetaHashMismatch { meta_program_id, content_id, cnt_type } => write!(f, "content {} ({:?}) of {} doesn't have the hash its content meta expects", format_hex(&content_id), cnt_type, meta_program_id)
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct VerifyReport {
+    pub verified_count: usize,
+    pub problems: Vec<ContentReport>
+}
+
+impl VerifyReport {
+    #[inline]
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+fn format_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Contents on an actual NAND may also be split into directories of 4GB (at most) parts, named '00', '01' and so on
+fn get_content_part_paths(path: &Path) -> Result<Vec<PathBuf>> {
+    if path.is_dir() {
+        let mut part_paths: Vec<PathBuf> = Vec::new();
+        for entry in convert_io_result(read_dir(path))? {
+            let entry = convert_io_result(entry)?;
+            part_paths.push(entry.path());
+        }
+        part_paths.sort();
+        Ok(part_paths)
+    }
+    else {
+        Ok(vec![path.to_path_buf()])
+    }
+}
+
+fn compute_content_hash(path: &Path) -> Result<Sha256Hash> {
+    let mut hasher = Sha256::new();
+    let mut buf: Vec<u8> = vec![0; HASH_BUFFER_SIZE];
+    for part_path in get_content_part_paths(path)? {
+        let mut part_file = convert_io_result(StdFile::open(part_path))?;
+        loop {
+            let read_size = convert_io_result(part_file.read(&mut buf))?;
+            if read_size == 0 {
+                break;
+            }
+            hasher.update(&buf[..read_size]);
+        }
+    }
+
+    let mut hash: Sha256Hash = [0; 0x20];
+    hash.copy_from_slice(&hasher.finalize());
+    Ok(hash)
+}
+
+struct VerifiedContent {
+    path: PathBuf,
+    hash: Sha256Hash
+}
+
+pub fn verify_storage_contents(storage_id: StorageId, content_paths: Vec<PathBuf>, report: &mut VerifyReport) -> Result<()> {
+    let add_problem = |report: &mut VerifyReport, path: &Path, problem: ContentProblem| {
+        report.problems.push(ContentReport {
+            storage_id: storage_id,
+            path: path.display().to_string(),
+            problem: problem
+        });
+    };
+
+    // First check the hashes of every content, so that nothing corrupted is parsed afterwards
+    let mut verified_cnts: BTreeMap<ContentId, VerifiedContent> = BTreeMap::new();
+    for path in content_paths {
+        let hash = match compute_content_hash(&path) {
+            Ok(hash) => hash,
+            Err(rc) => {
+                add_problem(report, &path, ContentProblem::Unreadable(rc));
+                continue;
+            }
+        };
+
+        match parse_content_id(&path) {
+            Some(content_id) => {
+                if hash[..content_id.len()] != content_id {
+                    add_problem(report, &path, ContentProblem::HashMismatch { actual_hash: hash });
+                    continue;
+                }
+
+                log_line!("[{:?}] Verified content {}", storage_id, format_hex(&content_id));
+                report.verified_count += 1;
+                verified_cnts.insert(content_id, VerifiedContent {
+                    path: path,
+                    hash: hash
+                });
+            },
+            None => add_problem(report, &path, ContentProblem::UnknownContentId)
+        };
+    }
+
+    // Then parse them, checking that every meta NCA has all its contents
+    for verified_cnt in verified_cnts.values() {
+        // TODO: split contents are not supported by the NCA reader yet, thus only their hashes are checked
+        if verified_cnt.path.is_dir() {
+            continue;
+        }
+
+        let nca_reader = new_shared(convert_io_result(StdFile::open(&verified_cnt.path))?);
+        let mut nca = match convert_io_result(NCA::new(nca_reader, get_keyset(), None)) {
+            Ok(nca) => nca,
+            Err(rc) => {
+                add_problem(report, &verified_cnt.path, ContentProblem::InvalidHeader(rc));
+                continue;
+            }
+        };
+
+        if nca.header.cnt_type != CntxContentType::Meta {
+            continue;
+        }
+
+        let (meta_program_id, cnt_infos) = match PackagedContentMeta::read(&mut nca) {
+            Ok(packaged_cnt_meta) => (packaged_cnt_meta.header.program_id, packaged_cnt_meta.cnt_infos),
+            Err(rc) => {
+                add_problem(report, &verified_cnt.path, ContentProblem::InvalidContentMeta(rc));
+                continue;
+            }
+        };
+
+        for cnt_info in cnt_infos.iter() {
+            // Delta fragments are only needed while installing updates
+            if cnt_info.info.cnt_type == ContentType::DeltaFragment {
+                continue;
+            }
+
+            match verified_cnts.get(&cnt_info.info.id) {
+                Some(cnt) => if cnt.hash != cnt_info.sha256_hash {
+                    add_problem(report, &verified_cnt.path, ContentProblem::ContentMetaHashMismatch { meta_program_id: meta_program_id, content_id: cnt_info.info.id, cnt_type: cnt_info.info.cnt_type });
+                },
+                None => add_problem(report, &verified_cnt.path, ContentProblem::MissingContent { meta_program_id: meta_program_id, content_id: cnt_info.info.id, cnt_type: cnt_info.info.cnt_type })
+            };
+        }
+    }
+
+    Ok(())
+}
+
+// Every content storage backend (see ncm::storage) is verified on its own, thus contents listed by a CNMT must be within the same backend
+pub fn verify_registered_contents() -> VerifyReport {
+    let mut report = VerifyReport::default();
+    for storage in storage::make_configured_storages().iter() {
+        let r = match storage.backend.list_content_paths() {
+            Ok(content_paths) => verify_storage_contents(storage.storage_id, content_paths, &mut report),
+            Err(err) => Err(err.get_result())
+        };
+        if let Err(rc) = r {
+            report.problems.push(ContentReport {
+                storage_id: storage.storage_id,
+                path: storage.backend.get_description(),
+                problem: ContentProblem::Unreadable(rc)
+            });
+        }
+    }
+
+    report
+}
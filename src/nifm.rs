@@ -0,0 +1,85 @@
+use serde::{Serialize, Deserialize};
+use crate::emu::cfg;
+use crate::result::*;
+
+pub mod result;
+
+// Note: https://switchbrew.org/wiki/Network_Interface_services
+
+// What nifm tells guests about the network (see cfg::Config::nifm_network_status)
+// Note: this is independent from the actual network access of guest sockets (see bsd::is_network_offline)
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum NetworkStatus {
+    // Connected with internet access: requests get accepted
+    Connected,
+    // No network at all
+    Disconnected,
+    // Connected to a network whose connection test fails (like a LAN without internet access): the connection is reported, but requests get rejected
+    TestMode
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[repr(C)]
+pub struct ClientId {
+    pub id: u32
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum InternetConnectionType {
+    WiFi = 1,
+    Ethernet = 2
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum InternetConnectionStatusKind {
+    ConnectingUnknown1 = 0,
+    ConnectingUnknown2 = 1,
+    ConnectingUnknown3 = 2,
+    ConnectingUnknown4 = 3,
+    Connected = 4
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(C)]
+pub struct InternetConnectionStatus {
+    pub connection_type: InternetConnectionType,
+    // 0-3 bars
+    pub wifi_strength: u8,
+    pub status: InternetConnectionStatusKind
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum RequestState {
+    Invalid = 0,
+    Free = 1,
+    OnHold = 2,
+    Accepted = 3,
+    Blocking = 4
+}
+
+pub fn get_network_status() -> NetworkStatus {
+    cfg::get_config().nifm_network_status
+}
+
+pub fn get_internet_connection_status() -> Result<InternetConnectionStatus> {
+    match get_network_status() {
+        NetworkStatus::Connected | NetworkStatus::TestMode => Ok(InternetConnectionStatus {
+            connection_type: InternetConnectionType::Ethernet,
+            wifi_strength: 3,
+            status: InternetConnectionStatusKind::Connected
+        }),
+        NetworkStatus::Disconnected => result::ResultNoInternetConnection::make_err()
+    }
+}
+
+// Submitted requests are resolved right away with this result
+pub fn get_request_result() -> ResultCode {
+    match get_network_status() {
+        NetworkStatus::Connected => ResultSuccess::make(),
+        NetworkStatus::Disconnected => result::ResultNoInternetConnection::make(),
+        NetworkStatus::TestMode => result::ResultConnectionTestFailed::make()
+    }
+}
@@ -0,0 +1,6 @@
+pub const RESULT_MODULE: u32 = 110;
+
+result_define_group!(RESULT_MODULE => {
+    NoInternetConnection: 300,
+    ConnectionTestFailed: 3127
+});
@@ -0,0 +1,146 @@
+use std::path::PathBuf;
+use cntx::nca::ContentType;
+use crate::fs::{cache, FileOpenMode, FileSystem, ReadOption};
+use crate::ncm::{self, ContentMetaEntry, ContentMetaType, ProgramId, StorageId};
+use crate::set::Language;
+use crate::util::Shared;
+use crate::result::*;
+
+// Note: https://switchbrew.org/wiki/NS_services
+
+// Applications are only looked for in the user storages (the game card isn't emulated)
+pub const APPLICATION_STORAGE_IDS: [StorageId; 2] = [StorageId::BuiltinUser, StorageId::SdCard];
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum ApplicationEvent {
+    Installed = 3
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(C)]
+pub struct ApplicationRecord {
+    pub application_id: ProgramId,
+    pub last_event: ApplicationEvent,
+    pub attributes: u8,
+    pub reserved: [u8; 0x6],
+    pub last_updated: u64
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum ApplicationControlSource {
+    CacheOnly = 0,
+    Storage = 1,
+    StorageOnly = 2
+}
+
+impl ApplicationControlSource {
+    pub const fn from(raw: u8) -> Option<Self> {
+        match raw {
+            0 => Some(Self::CacheOnly),
+            1 => Some(Self::Storage),
+            2 => Some(Self::StorageOnly),
+            _ => None
+        }
+    }
+}
+
+// Control data is the NACP followed by the icon (a JPEG) for the system language
+pub const APPLICATION_CONTROL_PROPERTY_SIZE: usize = 0x4000;
+pub const APPLICATION_ICON_SIZE_MAX: usize = 0x20000;
+pub const APPLICATION_CONTROL_DATA_SIZE: usize = APPLICATION_CONTROL_PROPERTY_SIZE + APPLICATION_ICON_SIZE_MAX;
+
+const CONTROL_PROPERTY_FILE_NAME: &str = "control.nacp";
+
+// NACP languages (which icons are named after), in the order of their NACP entries
+const CONTROL_LANGUAGE_NAMES: [&str; 16] = ["AmericanEnglish", "BritishEnglish", "Japanese", "French", "German", "LatinAmericanSpanish", "Spanish", "Italian", "Dutch", "CanadianFrench", "Portuguese", "Russian", "Korean", "TraditionalChinese", "SimplifiedChinese", "BrazilianPortuguese"];
+
+const fn get_control_language_name(language: Language) -> &'static str {
+    match language {
+        Language::Japanese => "Japanese",
+        Language::AmericanEnglish => "AmericanEnglish",
+        Language::French => "French",
+        Language::German => "German",
+        Language::Italian => "Italian",
+        Language::Spanish => "Spanish",
+        Language::Chinese | Language::SimplifiedChinese => "SimplifiedChinese",
+        Language::Korean => "Korean",
+        Language::Dutch => "Dutch",
+        Language::Portuguese => "Portuguese",
+        Language::Russian => "Russian",
+        Language::Taiwanese | Language::TraditionalChinese => "TraditionalChinese",
+        Language::BritishEnglish => "BritishEnglish",
+        Language::CanadianFrench => "CanadianFrench",
+        Language::LatinAmericanSpanish => "LatinAmericanSpanish",
+        Language::BrazilianPortuguese => "BrazilianPortuguese"
+    }
+}
+
+fn list_application_metas() -> Vec<(StorageId, ContentMetaEntry)> {
+    APPLICATION_STORAGE_IDS.iter().flat_map(|storage_id| ncm::list_content_metas(*storage_id).into_iter().map(move |cnt_meta| (*storage_id, cnt_meta))).collect()
+}
+
+// Every installed application has a record, regardless of its patches/add-on contents
+// Note: records are sorted by application ID, since there's no actual event history to sort them by
+pub fn list_application_records() -> Vec<ApplicationRecord> {
+    let mut application_ids: Vec<ProgramId> = list_application_metas().into_iter().filter(|(_, cnt_meta)| cnt_meta.key.cnt_meta_type == ContentMetaType::Application).map(|(_, cnt_meta)| cnt_meta.key.program_id).collect();
+    application_ids.sort();
+    application_ids.dedup();
+
+    application_ids.into_iter().map(|application_id| ApplicationRecord {
+        application_id: application_id,
+        last_event: ApplicationEvent::Installed,
+        attributes: 0,
+        reserved: [0; 0x6],
+        last_updated: 0
+    }).collect()
+}
+
+fn read_file(fs: &Shared<dyn FileSystem>, path: &str) -> Result<Vec<u8>> {
+    let file = fs.get().open_file(PathBuf::from(path), FileOpenMode::Read())?;
+
+    let size = file.get().get_size()?;
+    let mut data = vec![0u8; size];
+    file.get().read(0, &mut data, ReadOption::None)?;
+    Ok(data)
+}
+
+// The control content of the latest installed patch takes precedence over the application's own one
+fn open_control_filesystem(application_id: ProgramId) -> Result<Shared<dyn FileSystem>> {
+    let metas = list_application_metas();
+    result_return_unless!(metas.iter().any(|(_, cnt_meta)| (cnt_meta.key.cnt_meta_type == ContentMetaType::Application) && (cnt_meta.key.program_id == application_id)), ncm::result::ResultContentMetaNotFound);
+
+    let mut control_candidates: Vec<&(StorageId, ContentMetaEntry)> = metas.iter().filter(|(_, cnt_meta)| (cnt_meta.key.cnt_meta_type == ContentMetaType::Patch) && (cnt_meta.get_application_id() == Some(application_id))).collect();
+    control_candidates.sort_by_key(|(_, cnt_meta)| std::cmp::Reverse(cnt_meta.key.version));
+    control_candidates.extend(metas.iter().filter(|(_, cnt_meta)| (cnt_meta.key.cnt_meta_type == ContentMetaType::Application) && (cnt_meta.key.program_id == application_id)));
+
+    let mut last_rc = ncm::result::ResultContentNotFound::make();
+    for (storage_id, cnt_meta) in control_candidates {
+        match cache::open_nca_filesystem(*storage_id, cnt_meta.key.program_id, ContentType::Control, 0, cache::NcaFileSystemKind::RomFs) {
+            Ok(control_fs) => return Ok(control_fs),
+            Err(rc) => last_rc = rc
+        };
+    }
+    Err(last_rc)
+}
+
+// The icon for the given language is preferred, falling back to the first one present
+pub fn load_application_control_data(application_id: ProgramId, language: Language) -> Result<Vec<u8>> {
+    let control_fs = open_control_filesystem(application_id)?;
+
+    let mut control_data = read_file(&control_fs, CONTROL_PROPERTY_FILE_NAME)?;
+    result_return_unless!(control_data.len() == APPLICATION_CONTROL_PROPERTY_SIZE, ResultInvalidControlData);
+
+    let preferred_language_name = get_control_language_name(language);
+    let language_names = std::iter::once(preferred_language_name).chain(CONTROL_LANGUAGE_NAMES.iter().copied().filter(|language_name| *language_name != preferred_language_name));
+    for language_name in language_names {
+        if let Ok(icon) = read_file(&control_fs, &format!("icon_{}.dat", language_name)) {
+            result_return_unless!(icon.len() <= APPLICATION_ICON_SIZE_MAX, ResultInvalidControlData);
+            control_data.extend_from_slice(&icon);
+            break;
+        }
+    }
+
+    Ok(control_data)
+}
@@ -1,13 +1,100 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
 use crate::util::CString;
 
+// Note: all these are userland (nn::os/libnx) structures living in guest memory, thus pointer fields hold guest addresses (and are stored as plain u64s)
+
+// Note: https://switchbrew.org/wiki/Thread_Local_Region
+// This is the 64-bit nn::os::ThreadType layout, pointed to by the TLR (see ThreadLocalRegion::thread_ref)
+
+#[derive(Copy, Clone)]
 #[repr(C)]
 pub struct ThreadType {
-    pub other_fields: [u8; 0x180],
-    pub thread_name: CString<0x20>,
-    pub thread_name_pointer: *mut u8
+    pub all_threads_node: [u64; 2],
+    pub multi_wait_object_list: [u64; 2],
+    pub reserved_1: [u64; 4],
+    pub state: u8,
+    pub stack_is_aliased: u8,
+    pub auto_registered: u8,
+    pub suspend_count: u8,
+    pub base_priority: i16,
+    pub version: u16,
+    pub original_stack: u64,
+    pub stack: u64,
+    pub stack_size: u64,
+    pub argument: u64,
+    pub function: u64,
+    pub current_fiber: u64,
+    pub initial_fiber: u64,
+    pub tls_value_array: [u64; 0x20],
+    pub name_buffer: CString<0x20>,
+    pub name_pointer: u64,
+    pub cs_thread: u32,
+    pub cv_thread: u32,
+    pub handle: u32,
+    pub reserved_2: u32,
+    pub thread_id: u64
 }
 
-// Note: https://switchbrew.org/wiki/Thread_Local_Region
+impl ThreadType {
+    pub const SIZE: usize = 0x1C0;
+
+    pub const STATE_OFFSET: usize = 0x40;
+    pub const STACK_OFFSET: usize = 0x50;
+    pub const TLS_VALUE_ARRAY_OFFSET: usize = 0x80;
+    pub const NAME_BUFFER_OFFSET: usize = 0x180;
+    pub const NAME_POINTER_OFFSET: usize = 0x1A0;
+    pub const HANDLE_OFFSET: usize = 0x1B0;
+    pub const THREAD_ID_OFFSET: usize = 0x1B8;
+
+    // Guest memory is not guaranteed to be suitably aligned for us
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        match data.len() >= Self::SIZE {
+            true => Some(unsafe { (data.as_ptr() as *const Self).read_unaligned() }),
+            false => None
+        }
+    }
+
+    // The name is only known if it points to the internal buffer (the usual case), otherwise it's somewhere else in guest memory
+    pub fn get_name(&self, address: u64) -> Option<String> {
+        match self.name_pointer == address + Self::NAME_BUFFER_OFFSET as u64 {
+            true => self.name_buffer.get_string().ok(),
+            false => None
+        }
+    }
+}
+
+const _: () = assert!(std::mem::size_of::<ThreadType>() == ThreadType::SIZE);
+
+// Exception frame passed to the userland exception handler
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[repr(C)]
+pub struct ExceptionInfo {
+    pub x: [u64; 9],
+    pub lr: u64,
+    pub sp: u64,
+    pub pc: u64,
+    pub pstate: u32,
+    pub afsr0: u32,
+    pub afsr1: u32,
+    pub esr: u32,
+    pub far: u64
+}
+
+impl Display for ExceptionInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        writeln!(f, " -- PC: {:#018X}, LR: {:#018X}, SP: {:#018X}", self.pc, self.lr, self.sp)?;
+        writeln!(f, " -- PSTATE: {:#010X}, ESR: {:#010X}, FAR: {:#018X}", self.pstate, self.esr, self.far)?;
+        for (i, x) in self.x.iter().enumerate() {
+            write!(f, " -- X{}: {:#018X}", i, x)?;
+            if (i % 3 == 2) || (i == self.x.len() - 1) {
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// This is the 64-bit layout: 32-bit processes have the same message buffer/disable counter/exception info offsets (all the emulator touches), but the pointer fields after the TLS are 32-bit there
 
 #[derive(Copy, Clone)]
 #[repr(C)]
@@ -15,13 +102,149 @@ pub struct ThreadLocalRegion {
     pub msg_buffer: [u8; 0x100],
     pub disable_counter: u16,
     pub interrupt_flag: u16,
-    pub reserved_1: [u8; 0x4],
-    pub reserved_2: [u8; 0x78],
+    pub cache_maintenance_flag: u8,
+    pub reserved_1: [u8; 0x3],
+    // Note: unused by the kernel, we use it for the userland exception frame (see ExceptionInfo)
+    pub user_exception_info: [u8; 0x78],
     pub tls: [u8; 0x50],
-    pub locale_ptr: *mut u8,
+    pub locale_ptr: u64,
     pub errno_val: i64,
     pub thread_data: [u8; 0x8],
     pub eh_globals: [u8; 0x8],
-    pub thread_ptr: *mut u8,
-    pub thread_ref: *mut ThreadType,
-}
\ No newline at end of file
+    pub thread_ptr: u64,
+    // Guest address of the thread's ThreadType
+    pub thread_ref: u64
+}
+
+impl ThreadLocalRegion {
+    pub const SIZE: usize = 0x200;
+
+    pub const MESSAGE_BUFFER_OFFSET: usize = 0;
+    pub const MESSAGE_BUFFER_SIZE: usize = 0x100;
+    pub const DISABLE_COUNTER_OFFSET: usize = 0x100;
+    pub const USER_EXCEPTION_INFO_OFFSET: usize = 0x108;
+    pub const USER_EXCEPTION_INFO_SIZE: usize = 0x78;
+    pub const TLS_OFFSET: usize = 0x180;
+    pub const TLS_SIZE: usize = 0x50;
+    pub const LIBNX_THREAD_VARS_OFFSET: usize = 0x1E0;
+    pub const THREAD_PTR_OFFSET: usize = 0x1F0;
+    pub const THREAD_REF_OFFSET: usize = 0x1F8;
+
+    // Note: libnx keeps its own thread variables (starting with this magic) at the end of the TLR instead, which have no ThreadType at all
+    pub const LIBNX_THREAD_VARS_MAGIC: u32 = 0x21545624;
+
+    #[inline]
+    pub fn get_message_buffer(&mut self) -> &mut [u8] {
+        &mut self.msg_buffer
+    }
+
+    #[inline]
+    pub fn get_user_exception_info(&mut self) -> &mut [u8] {
+        &mut self.user_exception_info
+    }
+
+    // The TLR might be misaligned (emulated threads keep it as a plain byte array), hence the unaligned accesses here
+    #[inline]
+    pub fn read_exception_info(&self) -> ExceptionInfo {
+        unsafe {
+            (self.user_exception_info.as_ptr() as *const ExceptionInfo).read_unaligned()
+        }
+    }
+
+    #[inline]
+    pub fn write_exception_info(&mut self, info: ExceptionInfo) {
+        unsafe {
+            (self.user_exception_info.as_mut_ptr() as *mut ExceptionInfo).write_unaligned(info);
+        }
+    }
+
+    #[inline]
+    pub fn has_libnx_thread_vars(&self) -> bool {
+        u32::from_le_bytes([self.thread_data[0], self.thread_data[1], self.thread_data[2], self.thread_data[3]]) == Self::LIBNX_THREAD_VARS_MAGIC
+    }
+
+    pub fn get_thread_type_address(&self) -> Option<u64> {
+        match self.has_libnx_thread_vars() || (self.thread_ref == 0) {
+            true => None,
+            false => Some(self.thread_ref)
+        }
+    }
+
+    // First field of the CMIF/HIPC header
+    #[inline]
+    pub fn get_ipc_command_type(&self) -> u16 {
+        u16::from_le_bytes([self.msg_buffer[0], self.msg_buffer[1]])
+    }
+
+    // The message buffer is all a (TLR-based) IPC message may touch, anything beyond it is thread state
+    #[inline]
+    pub const fn is_in_message_buffer(offset: usize, size: usize) -> bool {
+        is_range_within(offset, size, Self::MESSAGE_BUFFER_SIZE)
+    }
+}
+
+// Guest code relies on this exact layout, so make sure no field messes it up
+const _: () = assert!(std::mem::size_of::<ThreadLocalRegion>() == ThreadLocalRegion::SIZE);
+const _: () = assert!(ThreadLocalRegion::MESSAGE_BUFFER_OFFSET + ThreadLocalRegion::MESSAGE_BUFFER_SIZE == ThreadLocalRegion::DISABLE_COUNTER_OFFSET);
+const _: () = assert!(ThreadLocalRegion::USER_EXCEPTION_INFO_OFFSET + ThreadLocalRegion::USER_EXCEPTION_INFO_SIZE == ThreadLocalRegion::TLS_OFFSET);
+const _: () = assert!(ThreadLocalRegion::THREAD_REF_OFFSET + std::mem::size_of::<u64>() == ThreadLocalRegion::SIZE);
+const _: () = assert!(ThreadLocalRegion::LIBNX_THREAD_VARS_OFFSET + 0x20 == ThreadLocalRegion::SIZE);
+// Note: the exception frame is placed in the (otherwise unused) reserved TLR area right after the IPC message buffer stuff, which has the exact same size
+const _: () = assert!(std::mem::size_of::<ExceptionInfo>() == ThreadLocalRegion::USER_EXCEPTION_INFO_SIZE);
+
+// Snapshot of a guest thread's context, as shown in crash reports
+#[derive(Clone, Debug, Default)]
+pub struct ThreadContextDump {
+    pub thread_id: u64,
+    pub name: Option<String>,
+    // X0-X30
+    pub x: [u64; 31],
+    pub sp: u64,
+    pub pc: u64,
+    pub pstate: u32,
+    pub tls_address: u64,
+    pub thread_type_address: u64,
+    // Last frame saved in the TLR, if the thread ever got an exception dispatched
+    pub exception_info: Option<ExceptionInfo>
+}
+
+impl Display for ThreadContextDump {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        writeln!(f, " -- Thread ID: {:#X}", self.thread_id)?;
+        if let Some(name) = self.name.as_ref() {
+            writeln!(f, " -- Thread name: '{}'", name)?;
+        }
+        writeln!(f, " -- TLS address: {:#X}", self.tls_address)?;
+        writeln!(f, " -- ThreadType address: {:#X}", self.thread_type_address)?;
+        writeln!(f, " -- PC: {:#018X}, SP: {:#018X}, PSTATE: {:#010X}", self.pc, self.sp, self.pstate)?;
+        for (i, x) in self.x.iter().enumerate() {
+            match i {
+                29 => write!(f, " -- FP: {:#018X}", x)?,
+                30 => write!(f, " -- LR: {:#018X}", x)?,
+                _ => write!(f, " -- X{}: {:#018X}", i, x)?
+            };
+            if (i % 4 == 3) || (i == self.x.len() - 1) {
+                writeln!(f)?;
+            }
+        }
+
+        if let Some(exception_info) = self.exception_info.as_ref() {
+            writeln!(f, " -- Last exception frame:")?;
+            write!(f, "{}", exception_info)?;
+        }
+        Ok(())
+    }
+}
+
+#[inline]
+pub const fn is_range_within(offset: usize, size: usize, buf_size: usize) -> bool {
+    match offset.checked_add(size) {
+        Some(end) => end <= buf_size,
+        None => false
+    }
+}
+
+#[inline]
+pub const fn ranges_overlap(a_start: usize, a_size: usize, b_start: usize, b_size: usize) -> bool {
+    (a_size > 0) && (b_size > 0) && (a_start < b_start + b_size) && (b_start < a_start + a_size)
+}
@@ -0,0 +1,209 @@
+use std::fs::read;
+use std::mem;
+use std::path::{Path, PathBuf};
+use cntx::nca::ContentType;
+use crate::emu::cfg;
+use crate::fs::{cache, FileOpenMode, ReadOption};
+use crate::ncm::{ProgramId, StorageId};
+use crate::set::{Language, LanguageCode};
+use crate::util::convert_io_result;
+use crate::result::*;
+
+// Note: https://switchbrew.org/wiki/Shared_Database_services
+
+pub const SHARED_FONT_MEMORY_SIZE: usize = 0x1100000;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum SharedFontType {
+    Standard = 0,
+    ChineseSimplified = 1,
+    ExtendedChineseSimplified = 2,
+    ChineseTraditional = 3,
+    Korean = 4,
+    NintendoExtended = 5
+}
+
+pub const SHARED_FONT_TYPES: [SharedFontType; 6] = [SharedFontType::Standard, SharedFontType::ChineseSimplified, SharedFontType::ExtendedChineseSimplified, SharedFontType::ChineseTraditional, SharedFontType::Korean, SharedFontType::NintendoExtended];
+
+impl SharedFontType {
+    pub const fn from(raw: u32) -> Option<Self> {
+        match raw {
+            0 => Some(Self::Standard),
+            1 => Some(Self::ChineseSimplified),
+            2 => Some(Self::ExtendedChineseSimplified),
+            3 => Some(Self::ChineseTraditional),
+            4 => Some(Self::Korean),
+            5 => Some(Self::NintendoExtended),
+            _ => None
+        }
+    }
+
+    // System data archive containing the font
+    pub const fn get_system_data_id(self) -> ProgramId {
+        match self {
+            Self::Standard => ProgramId(0x0100000000000811),
+            Self::ChineseSimplified | Self::ExtendedChineseSimplified => ProgramId(0x0100000000000814),
+            Self::ChineseTraditional => ProgramId(0x0100000000000813),
+            Self::Korean => ProgramId(0x0100000000000812),
+            Self::NintendoExtended => ProgramId(0x0100000000000810)
+        }
+    }
+
+    pub const fn get_system_data_file_name(self) -> &'static str {
+        match self {
+            Self::Standard => "nintendo_udsg-r_std_003.bfttf",
+            Self::ChineseSimplified => "nintendo_udsg-r_org_zh-cn_003.bfttf",
+            Self::ExtendedChineseSimplified => "nintendo_udsg-r_ext_zh-cn_003.bfttf",
+            Self::ChineseTraditional => "nintendo_udjxh-db_zh-tw_003.bfttf",
+            Self::Korean => "nintendo_udsg-r_ko_003.bfttf",
+            Self::NintendoExtended => "nintendo_ext_003.bfttf"
+        }
+    }
+
+    // Fallback TTF file (see cfg::Config::shared_font_path), used when the system data archive isn't available
+    pub const fn get_fallback_file_name(self) -> &'static str {
+        match self {
+            Self::Standard => "FontStandard.ttf",
+            Self::ChineseSimplified => "FontChineseSimplified.ttf",
+            Self::ExtendedChineseSimplified => "FontExtendedChineseSimplified.ttf",
+            Self::ChineseTraditional => "FontChineseTraditional.ttf",
+            Self::Korean => "FontKorean.ttf",
+            Self::NintendoExtended => "FontNintendoExtended.ttf"
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum SharedFontLoadState {
+    Loading = 0,
+    Loaded = 1
+}
+
+// Fonts are stored in the shared memory as their (plain) TTF data preceded by a header: the magic and the size, the latter XOR-ed with the key (both little-endian)
+// System data archives store them as BFTTF files instead: the same header, but with the encrypted magic and the whole data XOR-ed with the key (big-endian words)
+const BFTTF_MAGIC: u32 = 0x18029A7F;
+const BFTTF_ENCRYPTED_MAGIC: u32 = 0x36F81A1E;
+const BFTTF_KEY: u32 = 0x49621806;
+const SHARED_FONT_HEADER_SIZE: usize = 2 * mem::size_of::<u32>();
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct SharedFont {
+    pub font_type: SharedFontType,
+    // Offset of the TTF data (past the header) within the shared memory
+    pub offset: u32,
+    pub size: u32
+}
+
+fn read_u32_le(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + mem::size_of::<u32>())?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+pub fn decrypt_bfttf(bfttf: &[u8]) -> Option<Vec<u8>> {
+    if read_u32_le(bfttf, 0)? != BFTTF_ENCRYPTED_MAGIC {
+        return None;
+    }
+
+    let size = (read_u32_le(bfttf, mem::size_of::<u32>())? ^ BFTTF_KEY) as usize;
+    let encrypted_data = bfttf.get(SHARED_FONT_HEADER_SIZE..SHARED_FONT_HEADER_SIZE.checked_add(size)?)?;
+
+    let mut ttf: Vec<u8> = Vec::with_capacity(size);
+    for word in encrypted_data.chunks(mem::size_of::<u32>()) {
+        let mut word_bytes = [0u8; 4];
+        word_bytes[..word.len()].copy_from_slice(word);
+        let decrypted_word = u32::from_le_bytes(word_bytes) ^ BFTTF_KEY;
+        ttf.extend_from_slice(&decrypted_word.to_be_bytes()[..word.len()]);
+    }
+    Some(ttf)
+}
+
+fn load_system_data_font(font_type: SharedFontType) -> Result<Vec<u8>> {
+    let font_fs = cache::open_nca_filesystem(StorageId::BuiltinSystem, font_type.get_system_data_id(), ContentType::Data, 0, cache::NcaFileSystemKind::RomFs)?;
+    let font_file = font_fs.get().open_file(PathBuf::from(font_type.get_system_data_file_name()), FileOpenMode::Read())?;
+
+    let size = font_file.get().get_size()?;
+    let mut bfttf = vec![0u8; size];
+    font_file.get().read(0, &mut bfttf, ReadOption::None)?;
+
+    match decrypt_bfttf(&bfttf) {
+        Some(ttf) => Ok(ttf),
+        None => ResultInvalidFontData::make_err()
+    }
+}
+
+fn load_fallback_font(font_type: SharedFontType) -> Result<Vec<u8>> {
+    let font_path = Path::new(&cfg::get_config().shared_font_path).join(font_type.get_fallback_file_name());
+    convert_io_result(read(font_path))
+}
+
+// Every available font is laid out in the shared memory, the rest are just left out
+pub fn load_shared_fonts() -> (Vec<u8>, Vec<SharedFont>) {
+    let mut shmem_data: Vec<u8> = Vec::with_capacity(SHARED_FONT_MEMORY_SIZE);
+    let mut fonts: Vec<SharedFont> = Vec::new();
+
+    for font_type in SHARED_FONT_TYPES.iter().copied() {
+        let ttf = match load_system_data_font(font_type) {
+            Ok(ttf) => ttf,
+            Err(rc) => match load_fallback_font(font_type) {
+                Ok(ttf) => {
+                    log_line!("[pl] Using fallback font for {:?} (system data unavailable: {:?})", font_type, rc);
+                    ttf
+                },
+                Err(fallback_rc) => {
+                    log_line!("[pl] Font {:?} is not available (system data: {:?}, fallback: {:?})", font_type, rc, fallback_rc);
+                    continue;
+                }
+            }
+        };
+
+        let offset = shmem_data.len() + SHARED_FONT_HEADER_SIZE;
+        // Fonts are kept aligned to words
+        let end_offset = (offset + ttf.len() + mem::size_of::<u32>() - 1) & !(mem::size_of::<u32>() - 1);
+        if end_offset > SHARED_FONT_MEMORY_SIZE {
+            log_line!("[pl] Font {:?} (size {:#X}) doesn't fit in the shared memory", font_type, ttf.len());
+            continue;
+        }
+
+        shmem_data.extend_from_slice(&BFTTF_MAGIC.to_le_bytes());
+        shmem_data.extend_from_slice(&(ttf.len() as u32 ^ BFTTF_KEY).to_le_bytes());
+        shmem_data.extend_from_slice(&ttf);
+        shmem_data.resize(end_offset, 0);
+
+        fonts.push(SharedFont {
+            font_type: font_type,
+            offset: offset as u32,
+            size: ttf.len() as u32
+        });
+    }
+
+    shmem_data.resize(SHARED_FONT_MEMORY_SIZE, 0);
+    (shmem_data, fonts)
+}
+
+// The fonts for the language come first
+pub fn get_font_types_in_order_of_priority(language_code: LanguageCode) -> Vec<SharedFontType> {
+    let is_language = |languages: &[Language]| languages.iter().any(|language| language.get_language_code() == language_code);
+
+    let preferred_types: &[SharedFontType] = if is_language(&[Language::Chinese, Language::SimplifiedChinese]) {
+        &[SharedFontType::ChineseSimplified, SharedFontType::ExtendedChineseSimplified]
+    }
+    else if is_language(&[Language::Taiwanese, Language::TraditionalChinese]) {
+        &[SharedFontType::ChineseTraditional]
+    }
+    else if is_language(&[Language::Korean]) {
+        &[SharedFontType::Korean]
+    }
+    else {
+        &[]
+    };
+
+    let mut font_types: Vec<SharedFontType> = preferred_types.to_vec();
+    for font_type in SHARED_FONT_TYPES.iter() {
+        if !font_types.contains(font_type) {
+            font_types.push(*font_type);
+        }
+    }
+    font_types
+}
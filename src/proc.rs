@@ -6,6 +6,36 @@ pub mod sm;
 
 pub mod set;
 
+pub mod spl;
+
+pub mod ro;
+
+pub mod lm;
+
+pub mod fatal;
+
+pub mod ncm;
+
+pub mod account;
+
+pub mod am;
+
+pub mod pl;
+
+pub mod ns;
+
+pub mod bsd;
+
+pub mod nifm;
+
+pub mod fs;
+
+pub mod service_mock;
+
+pub mod boot2;
+
+pub mod result;
+
 pub struct EmulatedProcess {
 }
 
@@ -163,13 +193,6 @@ impl EmulatedProcess {
 }
 
 pub fn initialize() -> Result<()> {
-    // First initialize sm, and wait until it's ready
-    sm::start_process()?;
-    sm::wait_ready();
-
-    // Then initialize everything else
-    set::start_process()?;
-
-    // TODO: also wait for all the other processes?
-    Ok(())
+    // The built-in manifest only contains the emulated modules (sm first, then everything else)
+    boot2::boot_system(&boot2::BootManifest::default())
 }
\ No newline at end of file
@@ -0,0 +1,30 @@
+use crate::account;
+use crate::ipc::server;
+use crate::kern::{proc::KProcess, thread::KThread};
+use crate::ncm::ProgramId;
+use crate::result::*;
+use super::EmulatedProcess;
+
+// Code for the emulated 'account' process, serving the users defined in the config (see account::load_user_profiles)
+
+pub mod account_service;
+
+pub fn start_process() -> Result<()> {
+    let npdm = EmulatedProcess::make_npdm("account", 27, 0x4000, ProgramId(0x010000000000001E), vec![
+        /* ... */
+    ], 128)?;
+
+    account::load_user_profiles();
+
+    let process = KProcess::new(None, npdm)?;
+    let mut main_thread = KProcess::create_main_thread_host(&process, String::from("pg.proc.account.MainThread"))?;
+    KThread::start_host(&mut main_thread, main_thread_fn)?;
+    Ok(())
+}
+
+fn main_thread_fn() {
+    let mut manager: server::ServerManager<0x40> = server::ServerManager::new().unwrap();
+
+    manager.register_service_server::<account_service::AccountServiceForApplication>().unwrap();
+    manager.loop_process().unwrap();
+}
@@ -0,0 +1,157 @@
+use crate::account::*;
+use crate::account::result as account_result;
+use crate::ipc::sf;
+use crate::ipc::sf::account::{IAccountServiceForApplication, IProfile};
+use crate::ipc::server;
+use crate::util::Shared;
+use crate::result::*;
+
+pub struct Profile {
+    session: sf::Session,
+    user: UserProfile
+}
+
+impl Profile {
+    pub fn new(user: UserProfile) -> Self {
+        Self {
+            session: sf::Session::new(),
+            user: user
+        }
+    }
+}
+
+impl IProfile for Profile {
+    fn get(&mut self, mut out_user_data: sf::OutFixedPointerBuffer<UserData>) -> Result<ProfileBase> {
+        log_line!("[account] get: uid {}", self.user.uid);
+
+        // Note: emulated users have no icon/Mii data
+        out_user_data.set_as(UserData::default());
+        self.user.get_profile_base()
+    }
+
+    fn get_base(&mut self) -> Result<ProfileBase> {
+        log_line!("[account] get_base: uid {}", self.user.uid);
+
+        self.user.get_profile_base()
+    }
+
+    fn get_image_size(&mut self) -> Result<u32> {
+        let image_size = self.user.get_image_size()?;
+        log_line!("[account] get_image_size: uid {} -> {:#X}", self.user.uid, image_size);
+
+        Ok(image_size as u32)
+    }
+
+    fn load_image(&mut self, out_image: sf::OutMapAliasBuffer) -> Result<u32> {
+        let image = self.user.load_image()?;
+        log_line!("[account] load_image: uid {}, buffer size {:#X}, image size {:#X}", self.user.uid, out_image.size, image.len());
+
+        // Like GetImageSize, the actual image size is returned even if the buffer is too small to hold it
+        let copy_size = image.len().min(out_image.size);
+        out_image.get_mut_slice::<u8>()[..copy_size].copy_from_slice(&image[..copy_size]);
+        Ok(image.len() as u32)
+    }
+}
+
+impl sf::IObject for Profile {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
+
+pub struct AccountServiceForApplication {
+    session: sf::Session
+}
+
+fn write_uid_list(out_uids: &sf::OutPointerBuffer, uids: &[Uid]) {
+    let out_uid_list = out_uids.get_mut_slice::<Uid>();
+    for (i, out_uid) in out_uid_list.iter_mut().enumerate() {
+        // Remaining entries are set to the invalid (zero) uid
+        *out_uid = uids.get(i).copied().unwrap_or_default();
+    }
+}
+
+impl IAccountServiceForApplication for AccountServiceForApplication {
+    fn get_user_count(&mut self) -> Result<u32> {
+        let user_count = get_user_profiles().len();
+        log_line!("[account] get_user_count -> {}", user_count);
+
+        Ok(user_count as u32)
+    }
+
+    fn get_user_existence(&mut self, uid: Uid) -> Result<bool> {
+        result_return_unless!(uid.is_valid(), account_result::ResultInvalidUserId);
+
+        let exists = get_user_profiles().iter().any(|user| user.uid == uid);
+        log_line!("[account] get_user_existence: uid {} -> {}", uid, exists);
+        Ok(exists)
+    }
+
+    fn list_all_users(&mut self, out_uids: sf::OutPointerBuffer) -> Result<()> {
+        let uids: Vec<Uid> = get_user_profiles().iter().map(|user| user.uid).collect();
+        log_line!("[account] list_all_users: buffer size {:#X} -> {:?}", out_uids.size, uids);
+
+        write_uid_list(&out_uids, &uids);
+        Ok(())
+    }
+
+    fn list_open_users(&mut self, out_uids: sf::OutPointerBuffer) -> Result<()> {
+        // Note: every user is considered to be open, since there's no user selection to open them
+        let uids: Vec<Uid> = get_user_profiles().iter().map(|user| user.uid).collect();
+        log_line!("[account] list_open_users: buffer size {:#X} -> {:?}", out_uids.size, uids);
+
+        write_uid_list(&out_uids, &uids);
+        Ok(())
+    }
+
+    fn get_last_opened_user(&mut self) -> Result<Uid> {
+        let uid = get_user_profiles().first().map(|user| user.uid).unwrap_or_default();
+        log_line!("[account] get_last_opened_user -> {}", uid);
+
+        Ok(uid)
+    }
+
+    fn get_profile(&mut self, uid: Uid) -> Result<Shared<dyn sf::IObject>> {
+        log_line!("[account] get_profile: uid {}", uid);
+
+        let user = find_user_profile(uid)?;
+        Ok(Shared::new(Profile::new(user)))
+    }
+
+    fn initialize_application_info_v0(&mut self, process_id: sf::ProcessId) -> Result<()> {
+        log_line!("[account] initialize_application_info_v0: process_id {:#X}", process_id.process_id);
+        Ok(())
+    }
+}
+
+impl sf::IObject for AccountServiceForApplication {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
+
+impl server::IServerObject for AccountServiceForApplication {
+    fn new() -> Self {
+        Self {
+            session: sf::Session::new()
+        }
+    }
+}
+
+impl server::IService for AccountServiceForApplication {
+    fn get_name() -> &'static str {
+        "acc:u0"
+    }
+
+    fn get_max_sesssions() -> u32 {
+        0x40
+    }
+}
@@ -0,0 +1,31 @@
+use crate::ipc::server;
+use crate::kern::{proc::KProcess, thread::KThread};
+use crate::ncm::ProgramId;
+use crate::result::*;
+use super::EmulatedProcess;
+
+// Code for the emulated 'am' process, where library applets are host implementations (see applet::get_library_applet_fn)
+
+pub mod storage;
+
+pub mod library_applet;
+
+pub mod applet_proxy;
+
+pub fn start_process() -> Result<()> {
+    let npdm = EmulatedProcess::make_npdm("am", 27, 0x4000, ProgramId(0x0100000000000023), vec![
+        /* ... */
+    ], 256)?;
+
+    let process = KProcess::new(None, npdm)?;
+    let mut main_thread = KProcess::create_main_thread_host(&process, String::from("pg.proc.am.MainThread"))?;
+    KThread::start_host(&mut main_thread, main_thread_fn)?;
+    Ok(())
+}
+
+fn main_thread_fn() {
+    let mut manager: server::ServerManager<0x100> = server::ServerManager::new().unwrap();
+
+    manager.register_service_server::<applet_proxy::ApplicationProxyService>().unwrap();
+    manager.loop_process().unwrap();
+}
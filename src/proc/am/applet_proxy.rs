@@ -0,0 +1,82 @@
+use crate::ipc::sf;
+use crate::ipc::sf::am::{IApplicationProxy, IApplicationProxyService};
+use crate::ipc::server;
+use crate::kern::svc;
+use crate::util::Shared;
+use crate::result::*;
+use super::library_applet::LibraryAppletCreator;
+
+// Note: only the library applet creator is provided so far, everything else applications need from am isn't emulated yet
+
+pub struct ApplicationProxy {
+    session: sf::Session,
+    process_id: u64
+}
+
+impl ApplicationProxy {
+    pub fn new(process_id: u64) -> Self {
+        Self {
+            session: sf::Session::new(),
+            process_id: process_id
+        }
+    }
+}
+
+impl IApplicationProxy for ApplicationProxy {
+    fn get_library_applet_creator(&mut self) -> Result<Shared<dyn sf::IObject>> {
+        log_line!("[am] get_library_applet_creator: process_id {:#X}", self.process_id);
+        Ok(Shared::new(LibraryAppletCreator::new()))
+    }
+}
+
+impl sf::IObject for ApplicationProxy {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
+
+pub struct ApplicationProxyService {
+    session: sf::Session
+}
+
+impl IApplicationProxyService for ApplicationProxyService {
+    fn open_application_proxy(&mut self, process_id: sf::ProcessId, self_process_handle: sf::CopyHandle) -> Result<Shared<dyn sf::IObject>> {
+        log_line!("[am] open_application_proxy: process_id {:#X}", process_id.process_id);
+
+        // The process handle isn't needed (yet)
+        svc::close_handle(self_process_handle.handle)?;
+        Ok(Shared::new(ApplicationProxy::new(process_id.process_id)))
+    }
+}
+
+impl sf::IObject for ApplicationProxyService {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
+
+impl server::IServerObject for ApplicationProxyService {
+    fn new() -> Self {
+        Self {
+            session: sf::Session::new()
+        }
+    }
+}
+
+impl server::IService for ApplicationProxyService {
+    fn get_name() -> &'static str {
+        "appletOE"
+    }
+
+    fn get_max_sesssions() -> u32 {
+        0x40
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::VecDeque;
+use crate::applet::{self, AppletId};
+use crate::applet::result as applet_result;
+use crate::ipc::sf;
+use crate::ipc::sf::am::{ILibraryAppletAccessor, ILibraryAppletCreator};
+use crate::ipc::result as ipc_result;
+use crate::kern::svc;
+use crate::util::Shared;
+use crate::result::*;
+use super::storage::{Storage, find_storage_data};
+
+pub struct LibraryAppletAccessor {
+    session: sf::Session,
+    raw_applet_id: u32,
+    in_data: Vec<Vec<u8>>,
+    out_data: VecDeque<Vec<u8>>,
+    result: Option<ResultCode>,
+    state_changed_event_handle: svc::Handle,
+    state_changed_readable_event_handle: svc::Handle
+}
+
+impl LibraryAppletAccessor {
+    pub fn new(raw_applet_id: u32) -> Result<Self> {
+        let (state_changed_event_handle, state_changed_readable_event_handle) = svc::create_event()?;
+
+        Ok(Self {
+            session: sf::Session::new(),
+            raw_applet_id: raw_applet_id,
+            in_data: Vec::new(),
+            out_data: VecDeque::new(),
+            result: None,
+            state_changed_event_handle: state_changed_event_handle,
+            state_changed_readable_event_handle: state_changed_readable_event_handle
+        })
+    }
+
+    fn run(&mut self) -> ResultCode {
+        let applet_fn = match AppletId::from(self.raw_applet_id) {
+            Some(applet_id) => match applet::get_library_applet_fn(applet_id) {
+                Some(applet_fn) => applet_fn,
+                None => {
+                    log_line!("[am] Library applet {:?} is not emulated, finishing it without output", applet_id);
+                    return ResultSuccess::make();
+                }
+            },
+            None => {
+                log_line!("[am] Unknown library applet {:#X}, finishing it without output", self.raw_applet_id);
+                return ResultSuccess::make();
+            }
+        };
+
+        match applet_fn(&self.in_data) {
+            Ok(out_data) => {
+                self.out_data.extend(out_data);
+                ResultSuccess::make()
+            },
+            Err(rc) => rc
+        }
+    }
+}
+
+impl ILibraryAppletAccessor for LibraryAppletAccessor {
+    fn get_applet_state_changed_event(&mut self) -> Result<sf::CopyHandle> {
+        Ok(sf::CopyHandle::from(self.state_changed_readable_event_handle))
+    }
+
+    fn is_completed(&mut self) -> Result<bool> {
+        Ok(self.result.is_some())
+    }
+
+    fn start(&mut self) -> Result<()> {
+        result_return_if!(self.result.is_some(), applet_result::ResultLibraryAppletAlreadyStarted);
+        log_line!("[am] Starting library applet {:#X} with {} input storage(s)", self.raw_applet_id, self.in_data.len());
+
+        // Host applets finish right away, so callers waiting for the applet to exit are woken up immediately
+        let rc = self.run();
+        self.result = Some(rc);
+        svc::signal_event(self.state_changed_event_handle)
+    }
+
+    fn request_exit(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn terminate(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn get_result(&mut self) -> Result<()> {
+        match self.result {
+            Some(rc) => rc.to(()),
+            None => ipc_result::ResultUnsupportedOperation::make_err()
+        }
+    }
+
+    fn push_in_data(&mut self, storage: Shared<dyn sf::IObject>) -> Result<()> {
+        match find_storage_data(&storage) {
+            Some(data) => {
+                self.in_data.push(data.get().clone());
+                Ok(())
+            },
+            None => ipc_result::ResultUnsupportedOperation::make_err()
+        }
+    }
+
+    fn pop_out_data(&mut self) -> Result<Shared<dyn sf::IObject>> {
+        match self.out_data.pop_front() {
+            Some(data) => Ok(Storage::new(data)),
+            None => applet_result::ResultNoDataInChannel::make_err()
+        }
+    }
+}
+
+impl sf::IObject for LibraryAppletAccessor {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
+
+impl Drop for LibraryAppletAccessor {
+    fn drop(&mut self) {
+        let _ = svc::close_handle(self.state_changed_event_handle);
+        let _ = svc::close_handle(self.state_changed_readable_event_handle);
+    }
+}
+
+pub struct LibraryAppletCreator {
+    session: sf::Session
+}
+
+impl LibraryAppletCreator {
+    pub fn new() -> Self {
+        Self {
+            session: sf::Session::new()
+        }
+    }
+}
+
+impl ILibraryAppletCreator for LibraryAppletCreator {
+    fn create_library_applet(&mut self, applet_id: u32, mode: u32) -> Result<Shared<dyn sf::IObject>> {
+        log_line!("[am] create_library_applet: applet_id {:#X} ({:?}), mode {}", applet_id, AppletId::from(applet_id), mode);
+
+        Ok(Shared::new(LibraryAppletAccessor::new(applet_id)?))
+    }
+
+    fn create_storage(&mut self, size: i64) -> Result<Shared<dyn sf::IObject>> {
+        result_return_unless!(size >= 0, applet_result::ResultInvalidStorageOffset);
+
+        Ok(Storage::new(vec![0; size as usize]))
+    }
+}
+
+impl sf::IObject for LibraryAppletCreator {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
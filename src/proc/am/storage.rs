@@ -0,0 +1,110 @@
+use parking_lot::Mutex;
+use crate::applet::result as applet_result;
+use crate::ipc::sf;
+use crate::ipc::sf::am::{IStorage, IStorageAccessor};
+use crate::util::{Shared, WeakShared};
+use crate::result::*;
+
+pub struct StorageAccessor {
+    session: sf::Session,
+    data: Shared<Vec<u8>>
+}
+
+impl StorageAccessor {
+    pub fn new(data: Shared<Vec<u8>>) -> Self {
+        Self {
+            session: sf::Session::new(),
+            data: data
+        }
+    }
+
+    fn check_range(&self, offset: i64, size: usize) -> Result<usize> {
+        let data_size = self.data.get().len();
+        result_return_unless!((offset >= 0) && (offset as usize <= data_size), applet_result::ResultInvalidStorageOffset);
+        result_return_unless!(size <= data_size - offset as usize, applet_result::ResultInvalidStorageOffset);
+        Ok(offset as usize)
+    }
+}
+
+impl IStorageAccessor for StorageAccessor {
+    fn get_size(&mut self) -> Result<i64> {
+        Ok(self.data.get().len() as i64)
+    }
+
+    fn write(&mut self, offset: i64, in_buf: sf::InAutoSelectBuffer) -> Result<()> {
+        let offset = self.check_range(offset, in_buf.size)?;
+
+        self.data.get()[offset..offset + in_buf.size].copy_from_slice(in_buf.get_slice::<u8>());
+        Ok(())
+    }
+
+    fn read(&mut self, offset: i64, out_buf: sf::OutAutoSelectBuffer) -> Result<()> {
+        let offset = self.check_range(offset, out_buf.size)?;
+
+        out_buf.get_mut_slice::<u8>().copy_from_slice(&self.data.get()[offset..offset + out_buf.size]);
+        Ok(())
+    }
+}
+
+impl sf::IObject for StorageAccessor {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
+
+pub struct Storage {
+    session: sf::Session,
+    data: Shared<Vec<u8>>
+}
+
+// Storages pushed to library applets are received as type-erased objects, thus the created ones are tracked to find their data back (see find_storage_data)
+static mut G_STORAGES: Mutex<Vec<WeakShared<Storage>>> = parking_lot::const_mutex(Vec::new());
+
+impl Storage {
+    pub fn new(data: Vec<u8>) -> Shared<Self> {
+        let storage = Shared::new(Self {
+            session: sf::Session::new(),
+            data: Shared::new(data)
+        });
+
+        unsafe {
+            let mut storages = G_STORAGES.lock();
+            storages.retain(|storage| storage.upgrade().is_some());
+            storages.push(storage.downgrade());
+        }
+        storage
+    }
+}
+
+pub fn find_storage_data(object: &Shared<dyn sf::IObject>) -> Option<Shared<Vec<u8>>> {
+    let storages = unsafe {
+        G_STORAGES.lock()
+    };
+
+    for storage in storages.iter().filter_map(|storage| storage.upgrade()) {
+        if storage.addr_eq(object) {
+            return Some(storage.get().data.clone());
+        }
+    }
+    None
+}
+
+impl IStorage for Storage {
+    fn open(&mut self) -> Result<Shared<dyn sf::IObject>> {
+        Ok(Shared::new(StorageAccessor::new(self.data.clone())))
+    }
+}
+
+impl sf::IObject for Storage {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
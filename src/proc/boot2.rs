@@ -0,0 +1,232 @@
+use std::fs::File;
+use std::time::{Duration, Instant};
+use serde::{Serialize, Deserialize};
+use crate::emu::cpu;
+use crate::fs;
+use crate::kern::{proc::KProcess, thread::KThread};
+use crate::ncm::{self, ProgramId};
+use crate::sm::ServiceName;
+use crate::util::{self, convert_io_result, convert_serde_json_result, Shared};
+use crate::result::*;
+use super::result;
+
+// Boot2-like startup of system modules: the modules listed in a (configurable) manifest are launched in dependency order, waiting for each one to be ready before launching the ones depending on it
+
+pub const BOOT_MANIFEST_FILE: &str = "boot2.json";
+
+const SYSTEM_TITLE_BASE_ADDRESS: u64 = 0x6900000;
+const SERVICE_WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct BootModule {
+    pub name: String,
+    // Note: modules with a program ID are launched from the system NAND contents, otherwise they are emulated ones (see get_emulated_module_start_fn)
+    #[serde(default)]
+    pub program_id: Option<u64>,
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    // Services the module is considered ready after registering
+    #[serde(default)]
+    pub wait_services: Vec<String>,
+    // Optional modules which can't be launched (not implemented yet, not present in NAND...) are skipped instead of failing the boot
+    #[serde(default)]
+    pub optional: bool
+}
+
+impl BootModule {
+    fn emulated(name: &str, depends_on: &[&str], wait_services: &[&str], optional: bool) -> Self {
+        Self {
+            name: String::from(name),
+            program_id: None,
+            depends_on: depends_on.iter().map(|dep| String::from(*dep)).collect(),
+            wait_services: wait_services.iter().map(|service| String::from(*service)).collect(),
+            optional: optional
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct BootManifest {
+    pub modules: Vec<BootModule>
+}
+
+impl Default for BootManifest {
+    fn default() -> Self {
+        Self {
+            modules: vec![
+                BootModule::emulated("sm", &[], &[], false),
+                BootModule::emulated("lm", &["sm"], &["lm"], false),
+                BootModule::emulated("spl", &["sm"], &["spl:"], false),
+                BootModule::emulated("ncm", &["sm"], &["ncm"], false),
+                BootModule::emulated("fs", &["sm", "spl"], &["fsp-srv"], false),
+                BootModule::emulated("settings", &["sm"], &["set", "set:sys"], false),
+                BootModule::emulated("account", &["sm"], &["acc:u0"], false),
+                BootModule::emulated("am", &["sm"], &["appletOE"], false),
+                BootModule::emulated("glue", &["sm"], &["pl:u", "time:u"], false),
+                BootModule::emulated("ns", &["sm", "ncm"], &["ns:am2", "ns:su"], false),
+                BootModule::emulated("ro", &["sm", "fs"], &["ldr:ro"], false),
+                BootModule::emulated("fatal", &["sm"], &["fatal:u"], false),
+                BootModule::emulated("bsdsocket", &["sm"], &["bsd:u", "bsd:s"], false),
+                BootModule::emulated("nifm", &["sm"], &["nifm:u"], false)
+            ]
+        }
+    }
+}
+
+impl BootManifest {
+    pub fn load(path: String) -> Result<Self> {
+        let file = convert_io_result(File::open(path))?;
+        convert_serde_json_result(serde_json::from_reader(file))
+    }
+
+    pub fn save(&self, path: String) -> Result<()> {
+        let file = convert_io_result(File::create(path))?;
+        convert_serde_json_result(serde_json::to_writer_pretty(file, self))
+    }
+
+    // Loads the manifest at the current working directory, creating a default one if not present
+    pub fn load_or_create_default() -> Result<Self> {
+        let manifest_path = util::get_path_relative_to_cwd(BOOT_MANIFEST_FILE);
+        match Self::load(manifest_path.clone()) {
+            Ok(manifest) => Ok(manifest),
+            Err(_) => {
+                let default_manifest: Self = Default::default();
+                default_manifest.save(manifest_path)?;
+                Ok(default_manifest)
+            }
+        }
+    }
+
+    fn find_module_index(&self, name: &str) -> Option<usize> {
+        self.modules.iter().position(|module| module.name == name)
+    }
+
+    // Returns the module indices in launch order, keeping the manifest order among modules whose dependencies are already satisfied
+    pub fn get_launch_order(&self) -> Result<Vec<usize>> {
+        let mut dependencies: Vec<Vec<usize>> = Vec::with_capacity(self.modules.len());
+        for module in self.modules.iter() {
+            let mut module_deps: Vec<usize> = Vec::new();
+            for dep_name in module.depends_on.iter() {
+                match self.find_module_index(dep_name) {
+                    Some(dep_idx) => module_deps.push(dep_idx),
+                    None => {
+                        log_line!("[boot2] Module '{}' depends on unknown module '{}'", module.name, dep_name);
+                        return result::ResultMissingDependency::make_err();
+                    }
+                }
+            }
+            dependencies.push(module_deps);
+        }
+
+        let mut order: Vec<usize> = Vec::with_capacity(self.modules.len());
+        let mut launched: Vec<bool> = vec![false; self.modules.len()];
+        while order.len() < self.modules.len() {
+            let next_idx = (0..self.modules.len()).find(|&idx| !launched[idx] && dependencies[idx].iter().all(|&dep_idx| launched[dep_idx]));
+            match next_idx {
+                Some(idx) => {
+                    launched[idx] = true;
+                    order.push(idx);
+                },
+                None => {
+                    let pending: Vec<&str> = (0..self.modules.len()).filter(|&idx| !launched[idx]).map(|idx| self.modules[idx].name.as_str()).collect();
+                    log_line!("[boot2] Dependency cycle between modules {:?}", pending);
+                    return result::ResultDependencyCycle::make_err();
+                }
+            }
+        }
+
+        Ok(order)
+    }
+}
+
+fn start_sm() -> Result<()> {
+    // sm must be fully ready before anything else talks to it
+    super::sm::start_process()?;
+    super::sm::wait_ready();
+    Ok(())
+}
+
+fn get_emulated_module_start_fn(name: &str) -> Option<fn() -> Result<()>> {
+    match name {
+        "sm" => Some(start_sm),
+        "spl" => Some(super::spl::start_process),
+        "settings" => Some(super::set::start_process),
+        "ro" => Some(super::ro::start_process),
+        "lm" => Some(super::lm::start_process),
+        "fatal" => Some(super::fatal::start_process),
+        "ncm" => Some(super::ncm::start_process),
+        "fs" => Some(super::fs::start_process),
+        "account" => Some(super::account::start_process),
+        "am" => Some(super::am::start_process),
+        "glue" => Some(super::pl::start_process),
+        "ns" => Some(super::ns::start_process),
+        "bsdsocket" => Some(super::bsd::start_process),
+        "nifm" => Some(super::nifm::start_process),
+        "service_mock" => Some(super::service_mock::start_process),
+        _ => None
+    }
+}
+
+pub fn launch_system_title(program_id: ProgramId) -> Result<Shared<KProcess>> {
+    let exefs = fs::cache::open_nca_filesystem(ncm::StorageId::BuiltinSystem, program_id, cntx::nca::ContentType::Program, 0, fs::cache::NcaFileSystemKind::Partition)?;
+
+    let mut cpu_ctx = cpu::Context::new();
+    let (start_addr, npdm) = cpu_ctx.load_program(exefs, SYSTEM_TITLE_BASE_ADDRESS)?;
+    let process_name = npdm.meta.name.get_string()?;
+
+    let mut process = KProcess::new(Some(cpu_ctx), npdm)?;
+    let (mut main_thread, main_thread_handle) = KProcess::create_main_thread(&mut process, format!("ext.{}.MainThread", process_name), start_addr)?;
+    KThread::start_exec(&mut main_thread, 0u64, main_thread_handle)?;
+    Ok(process)
+}
+
+fn wait_for_services(module: &BootModule) -> Result<()> {
+    let start = Instant::now();
+    for service in module.wait_services.iter() {
+        let service_name = ServiceName::new(service);
+        while !super::sm::has_service_info(service_name) {
+            if start.elapsed() >= SERVICE_WAIT_TIMEOUT {
+                log_line!("[boot2] Timed out waiting for module '{}' to register '{}'", module.name, service);
+                return result::ResultStartTimedOut::make_err();
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    Ok(())
+}
+
+fn launch_module(module: &BootModule) -> Result<()> {
+    match module.program_id {
+        Some(program_id) => {
+            launch_system_title(ProgramId(program_id))?;
+        },
+        None => match get_emulated_module_start_fn(&module.name) {
+            Some(start_fn) => (start_fn)()?,
+            None => return result::ResultUnknownModule::make_err()
+        }
+    };
+
+    wait_for_services(module)
+}
+
+pub fn boot_system(manifest: &BootManifest) -> Result<()> {
+    let order = manifest.get_launch_order()?;
+    for idx in order {
+        let module = &manifest.modules[idx];
+        match launch_module(module) {
+            Ok(()) => log_line!("[boot2] Launched module '{}'", module.name),
+            Err(rc) => {
+                if module.optional {
+                    log_line!("[boot2] Skipping optional module '{}': {:?}", module.name, rc);
+                }
+                else {
+                    log_line!("[boot2] Unable to launch module '{}': {:?}", module.name, rc);
+                    return Err(rc);
+                }
+            }
+        };
+    }
+
+    Ok(())
+}
@@ -0,0 +1,85 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{Builder, Thread};
+use std::time::Duration;
+use parking_lot::Mutex;
+use crate::ipc::server;
+use crate::kern::{event::KReadableEvent, proc::KProcess, thread::KThread};
+use crate::ncm::ProgramId;
+use crate::util::Shared;
+use crate::result::*;
+use super::EmulatedProcess;
+
+// Code for the emulated 'bsdsocket' process, backing guest sockets with host ones (see bsd::is_address_allowed for what guests can reach)
+
+pub mod bsd_service;
+
+// Interval at which requests deferred by socket calls which would block are retried (see request_retry)
+const RETRY_INTERVAL: Duration = Duration::from_millis(1);
+
+// Signaled whenever requests deferred by socket calls (see bsd_service) might be able to complete, so that the server handles them again
+static mut G_WAKE_EVENT: Mutex<Option<Shared<KReadableEvent>>> = parking_lot::const_mutex(None);
+
+static G_RETRY_REQUESTED: AtomicBool = AtomicBool::new(false);
+static mut G_RETRY_THREAD: Mutex<Option<Thread>> = parking_lot::const_mutex(None);
+
+pub fn signal_wake_event() {
+    let wake_event = unsafe {
+        G_WAKE_EVENT.lock().clone()
+    };
+
+    if let Some(wake_event) = wake_event {
+        KReadableEvent::signal(&wake_event);
+    }
+}
+
+// Host sockets can't be waited for along with kernel objects, thus requests deferred because their calls would block are just retried after a while
+pub fn request_retry() {
+    G_RETRY_REQUESTED.store(true, Ordering::SeqCst);
+
+    unsafe {
+        if let Some(retry_thread) = G_RETRY_THREAD.lock().as_ref() {
+            retry_thread.unpark();
+        }
+    }
+}
+
+fn retry_thread_fn() {
+    loop {
+        // Note: retries requested right before parking aren't lost, since unparking beforehand makes the next park return right away
+        std::thread::park();
+
+        while G_RETRY_REQUESTED.swap(false, Ordering::SeqCst) {
+            std::thread::sleep(RETRY_INTERVAL);
+            signal_wake_event();
+        }
+    }
+}
+
+pub fn start_process() -> Result<()> {
+    let npdm = EmulatedProcess::make_npdm("bsdsocket", 27, 0x4000, ProgramId(0x0100000000000012), vec![
+        /* ... */
+    ], 128)?;
+
+    let process = KProcess::new(None, npdm)?;
+    let mut main_thread = KProcess::create_main_thread_host(&process, String::from("pg.proc.bsdsocket.MainThread"))?;
+    KThread::start_host(&mut main_thread, main_thread_fn)?;
+    Ok(())
+}
+
+fn main_thread_fn() {
+    let mut manager: server::ServerManager<0x40> = server::ServerManager::new().unwrap();
+
+    let wake_event = manager.register_wake_event().unwrap();
+    unsafe {
+        *G_WAKE_EVENT.lock() = Some(wake_event);
+    }
+
+    let retry_thread = Builder::new().name(String::from("pg.proc.bsdsocket.RetryThread")).spawn(retry_thread_fn).unwrap();
+    unsafe {
+        *G_RETRY_THREAD.lock() = Some(retry_thread.thread().clone());
+    }
+
+    manager.register_service_server::<bsd_service::UserBsdService>().unwrap();
+    manager.register_service_server::<bsd_service::SystemBsdService>().unwrap();
+    manager.loop_process().unwrap();
+}
@@ -0,0 +1,699 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddrV4, TcpListener, TcpStream, UdpSocket};
+use std::sync::Arc;
+use std::thread::Builder;
+use std::time::{Duration, Instant};
+use parking_lot::Mutex;
+use crate::bsd::*;
+use crate::ipc::cmif::result as cmif_result;
+use crate::ipc::sf;
+use crate::ipc::sf::bsd::IClient;
+use crate::ipc::server;
+use crate::kern::svc;
+use crate::result::*;
+
+// Note: the service is served from a single thread, thus socket calls can't block it: host sockets are always non-blocking, and calls which would block (on sockets the guest left in blocking mode) get their requests deferred and retried (see super::request_retry) until they complete
+// Connecting can't be done without blocking, thus it's done by a host thread instead
+
+// Each session has its own socket table, like each registered client on the console
+const MAX_SOCKET_COUNT: usize = 0x80;
+
+type ConnectResult = Arc<Mutex<Option<io::Result<TcpStream>>>>;
+
+enum SocketState {
+    // Host TCP sockets can't exist until they are connected/listening, thus until then only the bound address (if any) is kept
+    UnconnectedTcp(Option<SocketAddrV4>),
+    // The result is filled by the connecting thread once done
+    ConnectingTcp(ConnectResult),
+    TcpStream(TcpStream),
+    TcpListener(TcpListener, VecDeque<(TcpStream, SocketAddrV4)>),
+    // Unbound UDP sockets get implicitly bound to any address on first use
+    Udp(Option<UdpSocket>)
+}
+
+struct Socket {
+    sock_type: u32,
+    state: SocketState,
+    // As set by the guest, since host sockets are always non-blocking
+    non_blocking: bool
+}
+
+impl Socket {
+    fn new(sock_type: u32) -> Self {
+        let state = match sock_type {
+            SOCK_DGRAM => SocketState::Udp(None),
+            _ => SocketState::UnconnectedTcp(None)
+        };
+
+        Self {
+            sock_type: sock_type,
+            state: state,
+            non_blocking: false
+        }
+    }
+
+    fn get_udp(&mut self) -> BsdResult<&UdpSocket> {
+        match &mut self.state {
+            SocketState::Udp(udp) => {
+                if udp.is_none() {
+                    let new_udp = convert_io_bsd_result(UdpSocket::bind(get_any_address()))?;
+                    convert_io_bsd_result(new_udp.set_nonblocking(true))?;
+                    *udp = Some(new_udp);
+                }
+                Ok(udp.as_ref().unwrap())
+            },
+            _ => Err(Errno::OperationNotSupported)
+        }
+    }
+
+    fn send(&mut self, data: &[u8]) -> BsdResult<usize> {
+        match &mut self.state {
+            SocketState::TcpStream(stream) => convert_io_bsd_result(stream.write(data)),
+            SocketState::Udp(Some(udp)) => convert_io_bsd_result(udp.send(data)),
+            _ => Err(Errno::NotConnected)
+        }
+    }
+
+    fn recv(&mut self, out_data: &mut [u8], peek: bool) -> BsdResult<usize> {
+        match &mut self.state {
+            SocketState::TcpStream(stream) => convert_io_bsd_result(match peek {
+                true => stream.peek(out_data),
+                false => stream.read(out_data)
+            }),
+            SocketState::Udp(Some(udp)) => convert_io_bsd_result(match peek {
+                true => udp.peek(out_data),
+                false => udp.recv(out_data)
+            }),
+            _ => Err(Errno::NotConnected)
+        }
+    }
+
+    fn accept_pending(&mut self) -> BsdResult<(TcpStream, SocketAddrV4)> {
+        match &mut self.state {
+            SocketState::TcpListener(listener, pending_connections) => match pending_connections.pop_front() {
+                Some(connection) => Ok(connection),
+                None => accept_allowed(listener)
+            },
+            _ => Err(Errno::InvalidArgument)
+        }
+    }
+
+    // Returns WouldBlock until the connecting thread is done (see start_connect), then moves to the connected stream (or back to unconnected if connecting failed)
+    fn finish_connect(&mut self) -> BsdResult<()> {
+        let connect_result = match &self.state {
+            SocketState::ConnectingTcp(connect_result) => connect_result.lock().take(),
+            _ => return Ok(())
+        };
+
+        match connect_result {
+            Some(r) => match r.and_then(|stream| stream.set_nonblocking(true).map(|_| stream)) {
+                Ok(stream) => {
+                    self.state = SocketState::TcpStream(stream);
+                    Ok(())
+                },
+                Err(err) => {
+                    self.state = SocketState::UnconnectedTcp(None);
+                    Err(Errno::from_io_error(&err))
+                }
+            },
+            None => Err(Errno::WouldBlock)
+        }
+    }
+
+    // Checks readiness without consuming anything: peeked data stays there, and connections accepted meanwhile are kept until the guest accepts them
+    fn poll(&mut self, events: u16) -> u16 {
+        // Connecting sockets aren't ready for anything until connected, and failed connections keep their error for the guest to get it (see do_connect and SO_ERROR)
+        if let SocketState::ConnectingTcp(connect_result) = &self.state {
+            match connect_result.lock().as_ref() {
+                Some(Ok(_)) => {},
+                Some(Err(_)) => return POLLERR,
+                None => return 0
+            };
+            if self.finish_connect().is_err() {
+                return POLLERR;
+            }
+        }
+
+        let mut peek_buf = [0u8; 1];
+        let revents = match &mut self.state {
+            SocketState::UnconnectedTcp(_) => POLLHUP,
+            SocketState::ConnectingTcp(_) => 0,
+            SocketState::TcpStream(stream) => match stream.peek(&mut peek_buf) {
+                Ok(0) => POLLIN | POLLHUP,
+                Ok(_) => POLLIN | POLLOUT,
+                Err(err) if Errno::from_io_error(&err) == Errno::WouldBlock => POLLOUT,
+                Err(_) => POLLERR
+            },
+            SocketState::TcpListener(listener, pending_connections) => {
+                if let Ok(connection) = accept_allowed(listener) {
+                    pending_connections.push_back(connection);
+                }
+                match pending_connections.is_empty() {
+                    true => 0,
+                    false => POLLIN
+                }
+            },
+            SocketState::Udp(None) => POLLOUT,
+            SocketState::Udp(Some(udp)) => match udp.peek_from(&mut peek_buf) {
+                Ok(_) => POLLIN | POLLOUT,
+                Err(err) if Errno::from_io_error(&err) == Errno::WouldBlock => POLLOUT,
+                Err(_) => POLLERR
+            }
+        };
+
+        // Errors and hangups are always reported, even if not requested
+        revents & (events | POLLERR | POLLHUP)
+    }
+}
+
+fn check_address_allowed(addr: SocketAddrV4) -> BsdResult<()> {
+    if is_address_allowed(addr) {
+        Ok(())
+    }
+    else {
+        log_line!("[bsd] Address {} isn't in the allowed hosts", addr);
+        Err(Errno::HostUnreachable)
+    }
+}
+
+// Note: checked by anything making sockets reachable too (binding, listening, accepting), not only on socket creation
+fn check_network_online() -> BsdResult<()> {
+    match is_network_offline() {
+        true => Err(Errno::NetworkDown),
+        false => Ok(())
+    }
+}
+
+// Connections from hosts which aren't allowed are dropped right away, as if they never arrived
+fn accept_allowed(listener: &TcpListener) -> BsdResult<(TcpStream, SocketAddrV4)> {
+    loop {
+        let (stream, addr) = convert_io_bsd_result(listener.accept())?;
+        let addr = get_socket_addr_v4(addr)?;
+        match is_address_allowed(addr) {
+            true => {
+                convert_io_bsd_result(stream.set_nonblocking(true))?;
+                return Ok((stream, addr));
+            },
+            false => log_line!("[bsd] Dropping connection from {}, which isn't in the allowed hosts", addr)
+        }
+    }
+}
+
+fn start_connect(addr: SocketAddrV4) -> BsdResult<ConnectResult> {
+    let connect_result: ConnectResult = Arc::new(Mutex::new(None));
+
+    let thread_connect_result = connect_result.clone();
+    let connect_thread = Builder::new().name(format!("pg.bsd.Connect({})", addr)).spawn(move || {
+        let stream = TcpStream::connect(addr);
+        *thread_connect_result.lock() = Some(stream);
+        super::signal_wake_event();
+    });
+    convert_io_bsd_result(connect_thread)?;
+    Ok(connect_result)
+}
+
+fn make_bsd_result(r: BsdResult<i32>) -> Result<(i32, u32)> {
+    match r {
+        Ok(ret) => Ok((ret, 0)),
+        Err(errno) => Ok((-1, errno as u32))
+    }
+}
+
+fn make_bsd_result_with_len(r: BsdResult<(i32, u32)>) -> Result<(i32, u32, u32)> {
+    match r {
+        Ok((ret, len)) => Ok((ret, 0, len)),
+        Err(errno) => Ok((-1, errno as u32, 0))
+    }
+}
+
+// bsd:u and bsd:s are the same service (the latter being for system processes)
+pub struct BsdService<const IS_SYSTEM: bool> {
+    session: sf::Session,
+    sockets: BTreeMap<i32, Socket>,
+    // Start of the poll being waited for, if any: since clients wait for the reply meanwhile, the next poll request in this session is always the same one
+    poll_start: Option<Instant>
+}
+
+pub type UserBsdService = BsdService<false>;
+pub type SystemBsdService = BsdService<true>;
+
+impl<const IS_SYSTEM: bool> BsdService<IS_SYSTEM> {
+    fn get_socket(&mut self, fd: i32) -> BsdResult<&mut Socket> {
+        self.sockets.get_mut(&fd).ok_or(Errno::BadFileDescriptor)
+    }
+
+    // Like BSD, the lowest unused descriptor is allocated
+    fn allocate_fd(&mut self, socket: Socket) -> BsdResult<i32> {
+        let fd = (0..MAX_SOCKET_COUNT as i32).find(|fd| !self.sockets.contains_key(fd)).ok_or(Errno::TooManyFiles)?;
+        self.sockets.insert(fd, socket);
+        Ok(fd)
+    }
+
+    // Calls which would block on blocking sockets get their requests deferred instead, being retried until they don't
+    fn defer_if_would_block<T>(&self, fd: i32, flags: u32, r: BsdResult<T>) -> Result<BsdResult<T>> {
+        let is_blocking_call = match self.sockets.get(&fd) {
+            Some(socket) => !socket.non_blocking && ((flags & MSG_DONTWAIT) == 0),
+            None => false
+        };
+
+        if is_blocking_call && matches!(r, Err(Errno::WouldBlock)) {
+            super::request_retry();
+            return cmif_result::ResultRequestDeferredByUser::make_err();
+        }
+        Ok(r)
+    }
+
+    fn do_socket(&mut self, domain: u32, sock_type: u32, protocol: u32) -> BsdResult<i32> {
+        check_network_online()?;
+        if domain != AF_INET {
+            return Err(Errno::AddressFamilyNotSupported);
+        }
+
+        match (sock_type, protocol) {
+            (SOCK_STREAM, IPPROTO_IP | IPPROTO_TCP) | (SOCK_DGRAM, IPPROTO_IP | IPPROTO_UDP) => self.allocate_fd(Socket::new(sock_type)),
+            _ => Err(Errno::ProtocolNotSupported)
+        }
+    }
+
+    // Returns WouldBlock while waiting, the request being retried meanwhile (see poll)
+    fn do_poll(&mut self, fds: &sf::InAutoSelectBuffer, out_fds: &sf::OutAutoSelectBuffer, fd_count: u32, timeout: i32) -> BsdResult<i32> {
+        let mut poll_fds: Vec<PollFd> = fds.get_slice::<PollFd>().iter().take(fd_count as usize).copied().collect();
+        let poll_start = *self.poll_start.get_or_insert_with(Instant::now);
+
+        let mut ready_count = 0;
+        for poll_fd in poll_fds.iter_mut() {
+            poll_fd.revents = match self.sockets.get_mut(&poll_fd.fd) {
+                Some(socket) => socket.poll(poll_fd.events),
+                None => POLLNVAL
+            };
+            if poll_fd.revents != 0 {
+                ready_count += 1;
+            }
+        }
+
+        // A negative timeout waits indefinitely
+        let timed_out = (timeout >= 0) && (poll_start.elapsed() >= Duration::from_millis(timeout as u64));
+        if (ready_count == 0) && !timed_out {
+            return Err(Errno::WouldBlock);
+        }
+
+        self.poll_start = None;
+        let out_poll_fds = out_fds.get_mut_slice::<PollFd>();
+        let copy_count = out_poll_fds.len().min(poll_fds.len());
+        out_poll_fds[..copy_count].copy_from_slice(&poll_fds[..copy_count]);
+        Ok(ready_count)
+    }
+
+    fn do_recv(&mut self, fd: i32, flags: u32, out_data: &mut [u8]) -> BsdResult<i32> {
+        let socket = self.get_socket(fd)?;
+        socket.recv(out_data, (flags & MSG_PEEK) != 0).map(|size| size as i32)
+    }
+
+    fn do_recv_from(&mut self, fd: i32, flags: u32, out_data: &mut [u8], out_addr: &mut [u8]) -> BsdResult<(i32, u32)> {
+        let socket = self.get_socket(fd)?;
+        let (size, addr) = match socket.state {
+            SocketState::Udp(_) => {
+                let udp = socket.get_udp()?;
+                let (size, addr) = convert_io_bsd_result(match (flags & MSG_PEEK) != 0 {
+                    true => udp.peek_from(out_data),
+                    false => udp.recv_from(out_data)
+                })?;
+                (size, Some(get_socket_addr_v4(addr)?))
+            },
+            // Connection-based sockets don't fill the address
+            _ => (socket.recv(out_data, (flags & MSG_PEEK) != 0)?, None)
+        };
+
+        let addr_len = match addr {
+            Some(addr) => SockAddrIn::from_socket_addr(addr).write_bytes(out_addr),
+            None => 0
+        };
+        Ok((size as i32, addr_len))
+    }
+
+    fn do_send(&mut self, fd: i32, data: &[u8]) -> BsdResult<i32> {
+        let socket = self.get_socket(fd)?;
+        socket.send(data).map(|size| size as i32)
+    }
+
+    fn do_send_to(&mut self, fd: i32, data: &[u8], addr: &[u8]) -> BsdResult<i32> {
+        // Without a destination address it's just like a regular send
+        if addr.is_empty() {
+            return self.do_send(fd, data);
+        }
+
+        let addr = SockAddrIn::from_bytes(addr)?.to_socket_addr();
+        check_address_allowed(addr)?;
+
+        let udp = self.get_socket(fd)?.get_udp()?;
+        convert_io_bsd_result(udp.send_to(data, addr)).map(|size| size as i32)
+    }
+
+    fn do_accept(&mut self, fd: i32, out_addr: &mut [u8]) -> BsdResult<(i32, u32)> {
+        check_network_online()?;
+
+        // Note: accepted sockets are always blocking at first (for the guest), regardless of the listening socket
+        let (stream, addr) = self.get_socket(fd)?.accept_pending()?;
+        let new_fd = self.allocate_fd(Socket {
+            sock_type: SOCK_STREAM,
+            state: SocketState::TcpStream(stream),
+            non_blocking: false
+        })?;
+        log_line!("[bsd] Accepted connection from {} (fd {} -> fd {})", addr, fd, new_fd);
+        Ok((new_fd, SockAddrIn::from_socket_addr(addr).write_bytes(out_addr)))
+    }
+
+    // Note: local addresses must be allowed too, thus guests can't listen on host interfaces which aren't (like the unspecified address, which listens on all of them)
+    fn do_bind(&mut self, fd: i32, addr: &[u8]) -> BsdResult<i32> {
+        check_network_online()?;
+        let addr = SockAddrIn::from_bytes(addr)?.to_socket_addr();
+        check_address_allowed(addr)?;
+
+        let socket = self.get_socket(fd)?;
+        match &mut socket.state {
+            SocketState::UnconnectedTcp(bind_addr @ None) => *bind_addr = Some(addr),
+            SocketState::Udp(udp @ None) => {
+                let new_udp = convert_io_bsd_result(UdpSocket::bind(addr))?;
+                convert_io_bsd_result(new_udp.set_nonblocking(true))?;
+                *udp = Some(new_udp);
+            },
+            _ => return Err(Errno::InvalidArgument)
+        };
+
+        log_line!("[bsd] Bound fd {} to {}", fd, addr);
+        Ok(0)
+    }
+
+    // Blocking sockets get WouldBlock while connecting, the request being handled again once the connecting thread is done (see start_connect)
+    // Non-blocking ones get InProgress instead (and Already if they try again meanwhile), the guest polling them for the connection to finish
+    fn do_connect(&mut self, fd: i32, addr: &[u8]) -> BsdResult<i32> {
+        let addr = SockAddrIn::from_bytes(addr)?.to_socket_addr();
+        check_address_allowed(addr)?;
+
+        let socket = self.get_socket(fd)?;
+        match &socket.state {
+            // Note: the bound address (if any) is ignored, host TCP streams can't be bound before connecting
+            SocketState::UnconnectedTcp(_) => {
+                socket.state = SocketState::ConnectingTcp(start_connect(addr)?);
+                return Err(match socket.non_blocking {
+                    true => Errno::InProgress,
+                    false => Errno::WouldBlock
+                });
+            },
+            SocketState::ConnectingTcp(_) => match socket.finish_connect() {
+                Err(Errno::WouldBlock) if socket.non_blocking => return Err(Errno::Already),
+                r => r?
+            },
+            SocketState::Udp(_) => {
+                let udp = socket.get_udp()?;
+                convert_io_bsd_result(udp.connect(addr))?;
+            },
+            _ => return Err(Errno::IsConnected)
+        };
+
+        log_line!("[bsd] Connected fd {} to {}", fd, addr);
+        Ok(0)
+    }
+
+    fn do_get_peer_name(&mut self, fd: i32, out_addr: &mut [u8]) -> BsdResult<(i32, u32)> {
+        let socket = self.get_socket(fd)?;
+        let addr = match &socket.state {
+            SocketState::TcpStream(stream) => convert_io_bsd_result(stream.peer_addr())?,
+            SocketState::Udp(Some(udp)) => convert_io_bsd_result(udp.peer_addr())?,
+            _ => return Err(Errno::NotConnected)
+        };
+
+        Ok((0, SockAddrIn::from_socket_addr(get_socket_addr_v4(addr)?).write_bytes(out_addr)))
+    }
+
+    fn do_get_sock_name(&mut self, fd: i32, out_addr: &mut [u8]) -> BsdResult<(i32, u32)> {
+        let socket = self.get_socket(fd)?;
+        let addr = match &socket.state {
+            SocketState::UnconnectedTcp(bind_addr) => bind_addr.unwrap_or(get_any_address()),
+            SocketState::ConnectingTcp(_) => get_any_address(),
+            SocketState::TcpStream(stream) => get_socket_addr_v4(convert_io_bsd_result(stream.local_addr())?)?,
+            SocketState::TcpListener(listener, _) => get_socket_addr_v4(convert_io_bsd_result(listener.local_addr())?)?,
+            SocketState::Udp(None) => get_any_address(),
+            SocketState::Udp(Some(udp)) => get_socket_addr_v4(convert_io_bsd_result(udp.local_addr())?)?
+        };
+
+        Ok((0, SockAddrIn::from_socket_addr(addr).write_bytes(out_addr)))
+    }
+
+    fn do_get_sock_opt(&mut self, fd: i32, level: u32, opt_name: u32, out_opt_val: &mut [u8]) -> BsdResult<(i32, u32)> {
+        let socket = self.get_socket(fd)?;
+        let opt_val: u32 = match (level, opt_name) {
+            (SOL_SOCKET, SO_TYPE) => socket.sock_type,
+            (SOL_SOCKET, SO_ERROR) => {
+                // Connections which finished meanwhile are picked up here too, failed ones being reported (only once, like errors of host sockets)
+                let connect_errno = match socket.finish_connect() {
+                    Ok(()) | Err(Errno::WouldBlock) => None,
+                    Err(errno) => Some(errno)
+                };
+                let err = match &socket.state {
+                    SocketState::TcpStream(stream) => stream.take_error(),
+                    SocketState::TcpListener(listener, _) => listener.take_error(),
+                    SocketState::Udp(Some(udp)) => udp.take_error(),
+                    _ => Ok(None)
+                };
+                match (connect_errno, convert_io_bsd_result(err)?) {
+                    (Some(errno), _) => errno as u32,
+                    (None, Some(err)) => Errno::from_io_error(&err) as u32,
+                    (None, None) => 0
+                }
+            },
+            (IPPROTO_TCP, TCP_NODELAY) => match &socket.state {
+                SocketState::TcpStream(stream) => convert_io_bsd_result(stream.nodelay())? as u32,
+                _ => 0
+            },
+            _ => {
+                log_line!("[bsd] Unsupported socket option (level {:#X}, name {:#X})", level, opt_name);
+                return Err(Errno::ProtocolNotAvailable);
+            }
+        };
+
+        let opt_val_data = opt_val.to_le_bytes();
+        let copy_size = opt_val_data.len().min(out_opt_val.len());
+        out_opt_val[..copy_size].copy_from_slice(&opt_val_data[..copy_size]);
+        Ok((0, opt_val_data.len() as u32))
+    }
+
+    fn do_listen(&mut self, fd: i32) -> BsdResult<i32> {
+        check_network_online()?;
+
+        let socket = self.get_socket(fd)?;
+        let bind_addr = match &socket.state {
+            SocketState::UnconnectedTcp(bind_addr) => bind_addr.unwrap_or(get_any_address()),
+            _ => return Err(Errno::InvalidArgument)
+        };
+        // Unbound sockets listen on the unspecified address, which must be allowed too (see do_bind)
+        check_address_allowed(bind_addr)?;
+
+        // Note: the backlog is left to the host
+        let listener = convert_io_bsd_result(TcpListener::bind(bind_addr))?;
+        convert_io_bsd_result(listener.set_nonblocking(true))?;
+        socket.state = SocketState::TcpListener(listener, VecDeque::new());
+
+        log_line!("[bsd] Listening on fd {} ({})", fd, bind_addr);
+        Ok(0)
+    }
+
+    fn do_fcntl(&mut self, fd: i32, cmd: u32, flags: u32) -> BsdResult<i32> {
+        let socket = self.get_socket(fd)?;
+        match cmd {
+            F_GETFL => Ok(match socket.non_blocking {
+                true => O_NONBLOCK as i32,
+                false => 0
+            }),
+            // Note: host sockets are always non-blocking, this only changes whether calls which would block get deferred (see defer_if_would_block)
+            F_SETFL => {
+                socket.non_blocking = (flags & O_NONBLOCK) != 0;
+                Ok(0)
+            },
+            _ => Err(Errno::InvalidArgument)
+        }
+    }
+
+    fn do_set_sock_opt(&mut self, fd: i32, level: u32, opt_name: u32, opt_val: &[u8]) -> BsdResult<i32> {
+        let socket = self.get_socket(fd)?;
+        match (level, opt_name, &socket.state) {
+            (IPPROTO_TCP, TCP_NODELAY, SocketState::TcpStream(stream)) => {
+                let no_delay = opt_val.iter().any(|byte| *byte != 0);
+                convert_io_bsd_result(stream.set_nodelay(no_delay))?;
+            },
+            // Other options (buffer sizes, reusing addresses...) are left to the host
+            _ => log_line!("[bsd] Ignoring socket option (level {:#X}, name {:#X})", level, opt_name)
+        };
+
+        Ok(0)
+    }
+
+    fn do_shutdown(&mut self, fd: i32, how: u32) -> BsdResult<i32> {
+        let how = match how {
+            SHUT_RD => Shutdown::Read,
+            SHUT_WR => Shutdown::Write,
+            SHUT_RDWR => Shutdown::Both,
+            _ => return Err(Errno::InvalidArgument)
+        };
+
+        match &self.get_socket(fd)?.state {
+            SocketState::TcpStream(stream) => convert_io_bsd_result(stream.shutdown(how))?,
+            _ => return Err(Errno::NotConnected)
+        };
+        Ok(0)
+    }
+
+    fn do_close(&mut self, fd: i32) -> BsdResult<i32> {
+        // Host sockets are closed when dropped
+        self.sockets.remove(&fd).ok_or(Errno::BadFileDescriptor)?;
+        log_line!("[bsd] Closed fd {}", fd);
+        Ok(0)
+    }
+}
+
+impl<const IS_SYSTEM: bool> IClient for BsdService<IS_SYSTEM> {
+    fn register_client(&mut self, config: LibraryConfigData, process_id: sf::ProcessId, transfer_mem_size: u64, transfer_mem_handle: sf::CopyHandle) -> Result<u64> {
+        log_line!("[bsd] register_client: process_id {:#X}, config {:?}, transfer_mem_size {:#X}", process_id.process_id, config, transfer_mem_size);
+
+        // The transfer memory is just meant for the console's own socket buffers
+        svc::close_handle(transfer_mem_handle.handle)?;
+        Ok(0)
+    }
+
+    fn start_monitoring(&mut self, process_id: sf::ProcessId) -> Result<()> {
+        log_line!("[bsd] start_monitoring: process_id {:#X}", process_id.process_id);
+        Ok(())
+    }
+
+    fn socket(&mut self, domain: u32, sock_type: u32, protocol: u32) -> Result<(i32, u32)> {
+        let r = self.do_socket(domain, sock_type, protocol);
+        log_line!("[bsd] socket: domain {}, type {}, protocol {} -> {:?}", domain, sock_type, protocol, r);
+        make_bsd_result(r)
+    }
+
+    fn poll(&mut self, fds: sf::InAutoSelectBuffer, out_fds: sf::OutAutoSelectBuffer, fd_count: u32, timeout: i32) -> Result<(i32, u32)> {
+        let r = self.do_poll(&fds, &out_fds, fd_count, timeout);
+        if let Err(Errno::WouldBlock) = r {
+            super::request_retry();
+            return cmif_result::ResultRequestDeferredByUser::make_err();
+        }
+        make_bsd_result(r)
+    }
+
+    fn recv(&mut self, fd: i32, flags: u32, out_buf: sf::OutAutoSelectBuffer) -> Result<(i32, u32)> {
+        let r = self.do_recv(fd, flags, out_buf.get_mut_slice::<u8>());
+        make_bsd_result(self.defer_if_would_block(fd, flags, r)?)
+    }
+
+    fn recv_from(&mut self, fd: i32, flags: u32, out_buf: sf::OutAutoSelectBuffer, out_addr: sf::OutAutoSelectBuffer) -> Result<(i32, u32, u32)> {
+        let r = self.do_recv_from(fd, flags, out_buf.get_mut_slice::<u8>(), out_addr.get_mut_slice::<u8>());
+        make_bsd_result_with_len(self.defer_if_would_block(fd, flags, r)?)
+    }
+
+    fn send(&mut self, fd: i32, flags: u32, buf: sf::InAutoSelectBuffer) -> Result<(i32, u32)> {
+        let r = self.do_send(fd, buf.get_slice::<u8>());
+        make_bsd_result(self.defer_if_would_block(fd, flags, r)?)
+    }
+
+    fn send_to(&mut self, fd: i32, flags: u32, buf: sf::InAutoSelectBuffer, addr: sf::InAutoSelectBuffer) -> Result<(i32, u32)> {
+        let r = self.do_send_to(fd, buf.get_slice::<u8>(), addr.get_slice::<u8>());
+        make_bsd_result(self.defer_if_would_block(fd, flags, r)?)
+    }
+
+    fn accept(&mut self, fd: i32, out_addr: sf::OutAutoSelectBuffer) -> Result<(i32, u32, u32)> {
+        let r = self.do_accept(fd, out_addr.get_mut_slice::<u8>());
+        make_bsd_result_with_len(self.defer_if_would_block(fd, 0, r)?)
+    }
+
+    fn bind(&mut self, fd: i32, addr: sf::InAutoSelectBuffer) -> Result<(i32, u32)> {
+        make_bsd_result(self.do_bind(fd, addr.get_slice::<u8>()))
+    }
+
+    fn connect(&mut self, fd: i32, addr: sf::InAutoSelectBuffer) -> Result<(i32, u32)> {
+        let r = self.do_connect(fd, addr.get_slice::<u8>());
+        // Note: blocking sockets wait for the connection without retries, since the connecting thread wakes the server up itself
+        if let Err(Errno::WouldBlock) = r {
+            return cmif_result::ResultRequestDeferredByUser::make_err();
+        }
+        if let Err(errno) = r {
+            log_line!("[bsd] connect: fd {} -> {:?}", fd, errno);
+        }
+        make_bsd_result(r)
+    }
+
+    fn get_peer_name(&mut self, fd: i32, out_addr: sf::OutAutoSelectBuffer) -> Result<(i32, u32, u32)> {
+        make_bsd_result_with_len(self.do_get_peer_name(fd, out_addr.get_mut_slice::<u8>()))
+    }
+
+    fn get_sock_name(&mut self, fd: i32, out_addr: sf::OutAutoSelectBuffer) -> Result<(i32, u32, u32)> {
+        make_bsd_result_with_len(self.do_get_sock_name(fd, out_addr.get_mut_slice::<u8>()))
+    }
+
+    fn get_sock_opt(&mut self, fd: i32, level: u32, opt_name: u32, out_opt_val: sf::OutAutoSelectBuffer) -> Result<(i32, u32, u32)> {
+        make_bsd_result_with_len(self.do_get_sock_opt(fd, level, opt_name, out_opt_val.get_mut_slice::<u8>()))
+    }
+
+    fn listen(&mut self, fd: i32, _backlog: i32) -> Result<(i32, u32)> {
+        make_bsd_result(self.do_listen(fd))
+    }
+
+    fn fcntl(&mut self, fd: i32, cmd: u32, flags: u32) -> Result<(i32, u32)> {
+        make_bsd_result(self.do_fcntl(fd, cmd, flags))
+    }
+
+    fn set_sock_opt(&mut self, fd: i32, level: u32, opt_name: u32, opt_val: sf::InAutoSelectBuffer) -> Result<(i32, u32)> {
+        make_bsd_result(self.do_set_sock_opt(fd, level, opt_name, opt_val.get_slice::<u8>()))
+    }
+
+    fn shutdown(&mut self, fd: i32, how: u32) -> Result<(i32, u32)> {
+        make_bsd_result(self.do_shutdown(fd, how))
+    }
+
+    fn write(&mut self, fd: i32, buf: sf::InAutoSelectBuffer) -> Result<(i32, u32)> {
+        let r = self.do_send(fd, buf.get_slice::<u8>());
+        make_bsd_result(self.defer_if_would_block(fd, 0, r)?)
+    }
+
+    fn read(&mut self, fd: i32, out_buf: sf::OutAutoSelectBuffer) -> Result<(i32, u32)> {
+        let r = self.do_recv(fd, 0, out_buf.get_mut_slice::<u8>());
+        make_bsd_result(self.defer_if_would_block(fd, 0, r)?)
+    }
+
+    fn close(&mut self, fd: i32) -> Result<(i32, u32)> {
+        make_bsd_result(self.do_close(fd))
+    }
+}
+
+impl<const IS_SYSTEM: bool> sf::IObject for BsdService<IS_SYSTEM> {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
+
+impl<const IS_SYSTEM: bool> server::IServerObject for BsdService<IS_SYSTEM> {
+    fn new() -> Self {
+        Self {
+            session: sf::Session::new(),
+            sockets: BTreeMap::new(),
+            poll_start: None
+        }
+    }
+}
+
+impl<const IS_SYSTEM: bool> server::IService for BsdService<IS_SYSTEM> {
+    fn get_name() -> &'static str {
+        match IS_SYSTEM {
+            true => "bsd:s",
+            false => "bsd:u"
+        }
+    }
+
+    fn get_max_sesssions() -> u32 {
+        0x20
+    }
+}
@@ -0,0 +1,80 @@
+use crate::emu::output::{self, OutputChannel};
+use crate::fatal::*;
+use crate::ipc::sf;
+use crate::ipc::sf::fatal::IService;
+use crate::ipc::server;
+use crate::kern::proc::find_process_by_id;
+use crate::ncm::ProgramId;
+use crate::result::*;
+
+pub struct Service {
+    session: sf::Session
+}
+
+impl Service {
+    fn throw_fatal_impl(&mut self, rc: ResultCode, policy: u32, process_id: u64, cpu_ctx: Option<Aarch64CpuContext>) -> Result<()> {
+        let program_id = match find_process_by_id(process_id) {
+            Ok(process) => process.get().npdm.aci0.program_id,
+            Err(_) => ProgramId(0)
+        };
+
+        let mut msg = format!("Fatal error {} thrown by {} (policy: {:?})", rc, program_id, FatalPolicy::from(policy));
+        if let Some(cpu_ctx) = cpu_ctx {
+            msg.push_str(&format!("\nPC: {:#018X}, LR: {:#018X}, SP: {:#018X}, FP: {:#018X}", cpu_ctx.pc, cpu_ctx.x[30], cpu_ctx.x[31], cpu_ctx.x[29]));
+            msg.push_str(&format!("\nESR: {:#X}, FAR: {:#018X}, start address: {:#018X}", cpu_ctx.esr, cpu_ctx.far, cpu_ctx.start_address));
+            for (i, address) in cpu_ctx.get_stack_trace().iter().enumerate() {
+                msg.push_str(&format!("\nStack trace [{}]: {:#018X}", i, address));
+            }
+        }
+
+        log_line!("[fatal:u] (process {:#X}) {}", process_id, msg);
+        output::write_output(process_id, program_id, OutputChannel::Fatal, &msg);
+
+        // TODO: actually terminate the process when the policy involves the error screen, like the real fatal does
+        Ok(())
+    }
+}
+
+impl IService for Service {
+    fn throw_fatal(&mut self, rc: ResultCode, process_id: sf::ProcessId) -> Result<()> {
+        self.throw_fatal_impl(rc, FatalPolicy::ErrorScreen as u32, process_id.process_id, None)
+    }
+
+    fn throw_fatal_with_policy(&mut self, rc: ResultCode, policy: u32, process_id: sf::ProcessId) -> Result<()> {
+        self.throw_fatal_impl(rc, policy, process_id.process_id, None)
+    }
+
+    fn throw_fatal_with_cpu_context(&mut self, rc: ResultCode, policy: u32, process_id: sf::ProcessId, ctx_buf: sf::InAutoSelectBuffer) -> Result<()> {
+        // Note: 32-bit contexts are not supported, thus those errors are reported without any context
+        let cpu_ctx = Aarch64CpuContext::from_bytes(ctx_buf.get_slice::<u8>());
+        self.throw_fatal_impl(rc, policy, process_id.process_id, cpu_ctx)
+    }
+}
+
+impl sf::IObject for Service {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
+
+impl server::IServerObject for Service {
+    fn new() -> Self {
+        Self {
+            session: sf::Session::new()
+        }
+    }
+}
+
+impl server::IService for Service {
+    fn get_name() -> &'static str {
+        "fatal:u"
+    }
+
+    fn get_max_sesssions() -> u32 {
+        0x40
+    }
+}
@@ -0,0 +1,48 @@
+use parking_lot::Mutex;
+use crate::ipc::server;
+use crate::kern::{event::KReadableEvent, proc::KProcess, thread::KThread};
+use crate::fs::result;
+use crate::ncm::ProgramId;
+use crate::util::Shared;
+use crate::result::*;
+use super::EmulatedProcess;
+
+// Code for the emulated 'fs' process, serving the host-backed filesystems (see fs module)
+
+pub mod file_system;
+
+pub mod storage;
+
+pub mod file_system_proxy;
+
+// Signaled by async reads (see fs::aio) once they complete, so that the server handles the requests deferred on them again
+static mut G_ASYNC_WAKE_EVENT: Mutex<Option<Shared<KReadableEvent>>> = parking_lot::const_mutex(None);
+
+pub fn get_async_wake_event() -> Result<Shared<KReadableEvent>> {
+    unsafe {
+        G_ASYNC_WAKE_EVENT.lock().clone().ok_or(result::ResultNotInitialized::make())
+    }
+}
+
+pub fn start_process() -> Result<()> {
+    let npdm = EmulatedProcess::make_npdm("fs", 27, 0x4000, ProgramId(0x0100000000000000), vec![
+        /* ... */
+    ], 256)?;
+
+    let process = KProcess::new(None, npdm)?;
+    let mut main_thread = KProcess::create_main_thread_host(&process, String::from("pg.proc.fs.MainThread"))?;
+    KThread::start_host(&mut main_thread, main_thread_fn)?;
+    Ok(())
+}
+
+fn main_thread_fn() {
+    let mut manager: server::ServerManager<0x100> = server::ServerManager::new().unwrap();
+
+    let async_wake_event = manager.register_wake_event().unwrap();
+    unsafe {
+        *G_ASYNC_WAKE_EVENT.lock() = Some(async_wake_event);
+    }
+
+    manager.register_service_server::<file_system_proxy::FileSystemProxy>().unwrap();
+    manager.loop_process().unwrap();
+}
@@ -0,0 +1,256 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use crate::fs::aio::{self, AsyncReadOperation};
+use crate::fs::{CreateOption, Directory, DirectoryEntry, DirectoryOpenMode, File, FileOpenMode, FileSystem, ReadOption, TimeStampRaw, WriteOption};
+use crate::fs::result;
+use crate::ipc::cmif::result as cmif_result;
+use crate::ipc::sf;
+use crate::ipc::sf::fs::{IDirectory, IFile, IFileSystem, Path};
+use crate::util::Shared;
+use crate::result::*;
+
+// Adapters exposing the fs module filesystems/files/directories to guests, like the actual fs service does with its own ones
+
+// Reads at least this big are performed asynchronously (see fs::aio), so that they don't block the whole server meanwhile
+const ASYNC_READ_MIN_SIZE: usize = 0x10000;
+
+fn get_path(path: &sf::InFixedPointerBuffer<Path>) -> Result<PathBuf> {
+    Ok(PathBuf::from(path.get_as::<Path>().get_string()?))
+}
+
+fn get_offset_and_size(offset: i64, size: i64) -> Result<(u64, usize)> {
+    result_return_if!(offset < 0, result::ResultInvalidOffset);
+    result_return_if!(size < 0, result::ResultInvalidSize);
+    Ok((offset as u64, size as usize))
+}
+
+// Note: files are accessed with Shared::get_blocking, since async reads might be accessing them from their worker threads meanwhile
+pub struct FileInterfaceAdapter {
+    session: sf::Session,
+    file: Shared<dyn File>,
+    // Async reads which (deferred) read requests are waiting for, along with their (offset, size)
+    // Note: cloned/domain sessions share this adapter, thus several requests might be waiting at once
+    pending_reads: Vec<(u64, usize, Arc<AsyncReadOperation>)>
+}
+
+impl FileInterfaceAdapter {
+    pub fn new(file: Shared<dyn File>) -> Self {
+        Self {
+            session: sf::Session::new(),
+            file: file,
+            pending_reads: Vec::new()
+        }
+    }
+}
+
+impl IFile for FileInterfaceAdapter {
+    fn read(&mut self, out_buf: sf::OutNonSecureMapAliasBuffer, _option: u32, offset: i64, size: i64) -> Result<i64> {
+        let (offset, size) = get_offset_and_size(offset, size)?;
+        let out_data = out_buf.get_mut_slice::<u8>();
+        let read_size = size.min(out_data.len());
+
+        if read_size < ASYNC_READ_MIN_SIZE {
+            let read_size = self.file.get_blocking().read(offset, &mut out_data[..read_size], ReadOption::None)?;
+            return Ok(read_size as i64);
+        }
+
+        // Note: the request is deferred until the read completes, being handled again meanwhile, thus it's matched with its async read by offset and size
+        let pending_read = match self.pending_reads.iter().position(|(pending_offset, pending_size, _)| (*pending_offset == offset) && (*pending_size == read_size)) {
+            Some(pending_idx) => self.pending_reads.remove(pending_idx).2,
+            None => aio::read_async(self.file.clone(), offset, read_size, ReadOption::None, super::get_async_wake_event()?)?
+        };
+
+        match pending_read.take_result() {
+            Some(result) => {
+                let data = result?;
+                out_data[..data.len()].copy_from_slice(&data);
+                Ok(data.len() as i64)
+            },
+            None => {
+                self.pending_reads.push((offset, read_size, pending_read));
+                cmif_result::ResultRequestDeferredByUser::make_err()
+            }
+        }
+    }
+
+    fn write(&mut self, buf: sf::InNonSecureMapAliasBuffer, option: u32, offset: i64, size: i64) -> Result<()> {
+        let (offset, size) = get_offset_and_size(offset, size)?;
+        let data = buf.get_slice::<u8>();
+        result_return_unless!(size <= data.len(), result::ResultInvalidSize);
+
+        let write_option = match option {
+            1 => WriteOption::Flush,
+            _ => WriteOption::None
+        };
+        self.file.get_blocking().write(offset, &data[..size], write_option)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.file.get_blocking().flush()
+    }
+
+    fn set_size(&mut self, size: i64) -> Result<()> {
+        result_return_if!(size < 0, result::ResultInvalidSize);
+        self.file.get_blocking().set_size(size as usize)
+    }
+
+    fn get_size(&mut self) -> Result<i64> {
+        let size = self.file.get_blocking().get_size()?;
+        Ok(size as i64)
+    }
+}
+
+impl sf::IObject for FileInterfaceAdapter {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
+
+pub struct DirectoryInterfaceAdapter {
+    session: sf::Session,
+    dir: Shared<dyn Directory>
+}
+
+impl DirectoryInterfaceAdapter {
+    pub fn new(dir: Shared<dyn Directory>) -> Self {
+        Self {
+            session: sf::Session::new(),
+            dir: dir
+        }
+    }
+}
+
+impl IDirectory for DirectoryInterfaceAdapter {
+    fn read(&mut self, out_entries: sf::OutMapAliasBuffer) -> Result<i64> {
+        let out_entries_slice = out_entries.get_mut_slice::<DirectoryEntry>();
+        let entries = self.dir.get().read(out_entries_slice.len())?;
+
+        for (out_entry, entry) in out_entries_slice.iter_mut().zip(entries.iter()) {
+            *out_entry = *entry;
+        }
+        Ok(entries.len().min(out_entries_slice.len()) as i64)
+    }
+
+    fn get_entry_count(&mut self) -> Result<i64> {
+        let count = self.dir.get().get_entry_count()?;
+        Ok(count as i64)
+    }
+}
+
+impl sf::IObject for DirectoryInterfaceAdapter {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
+
+pub struct FileSystemInterfaceAdapter {
+    session: sf::Session,
+    fs: Shared<dyn FileSystem>
+}
+
+impl FileSystemInterfaceAdapter {
+    pub fn new(fs: Shared<dyn FileSystem>) -> Self {
+        Self {
+            session: sf::Session::new(),
+            fs: fs
+        }
+    }
+}
+
+impl IFileSystem for FileSystemInterfaceAdapter {
+    fn create_file(&mut self, path: sf::InFixedPointerBuffer<Path>, option: u32, size: i64) -> Result<()> {
+        result_return_if!(size < 0, result::ResultInvalidSize);
+        let path = get_path(&path)?;
+        log_line!("[fs] create_file: '{}' (size {:#X})", path.display(), size);
+        self.fs.get().create_file(path, size as usize, CreateOption::from(option))
+    }
+
+    fn delete_file(&mut self, path: sf::InFixedPointerBuffer<Path>) -> Result<()> {
+        self.fs.get().delete_file(get_path(&path)?)
+    }
+
+    fn create_directory(&mut self, path: sf::InFixedPointerBuffer<Path>) -> Result<()> {
+        self.fs.get().create_directory(get_path(&path)?)
+    }
+
+    fn delete_directory(&mut self, path: sf::InFixedPointerBuffer<Path>) -> Result<()> {
+        self.fs.get().delete_directory(get_path(&path)?)
+    }
+
+    fn delete_directory_recursively(&mut self, path: sf::InFixedPointerBuffer<Path>) -> Result<()> {
+        self.fs.get().delete_directory_recursively(get_path(&path)?)
+    }
+
+    fn rename_file(&mut self, old_path: sf::InFixedPointerBuffer<Path>, new_path: sf::InFixedPointerBuffer<Path>) -> Result<()> {
+        self.fs.get().rename_file(get_path(&old_path)?, get_path(&new_path)?)
+    }
+
+    fn rename_directory(&mut self, old_path: sf::InFixedPointerBuffer<Path>, new_path: sf::InFixedPointerBuffer<Path>) -> Result<()> {
+        self.fs.get().rename_directory(get_path(&old_path)?, get_path(&new_path)?)
+    }
+
+    fn get_entry_type(&mut self, path: sf::InFixedPointerBuffer<Path>) -> Result<u32> {
+        let entry_type = self.fs.get().get_entry_type(get_path(&path)?)?;
+        Ok(entry_type as u32)
+    }
+
+    fn open_file(&mut self, path: sf::InFixedPointerBuffer<Path>, open_mode: u32) -> Result<Shared<dyn sf::IObject>> {
+        let open_mode = FileOpenMode::from(open_mode);
+        result_return_unless!((open_mode & !(FileOpenMode::Read() | FileOpenMode::Write() | FileOpenMode::Append())).get() == 0, result::ResultInvalidOpenMode);
+        // Note: appending is only allowed along with writing
+        result_return_if!(open_mode.contains(FileOpenMode::Append()) && !open_mode.contains(FileOpenMode::Write()), result::ResultInvalidOpenMode);
+
+        let path = get_path(&path)?;
+        log_line!("[fs] open_file: '{}' (mode {:#X})", path.display(), open_mode.get());
+        let file = self.fs.get().open_file(path, open_mode)?;
+        Ok(Shared::new(FileInterfaceAdapter::new(file)))
+    }
+
+    fn open_directory(&mut self, path: sf::InFixedPointerBuffer<Path>, open_mode: u32) -> Result<Shared<dyn sf::IObject>> {
+        let path = get_path(&path)?;
+        log_line!("[fs] open_directory: '{}' (mode {:#X})", path.display(), open_mode);
+        let dir = self.fs.get().open_directory(path, DirectoryOpenMode::from(open_mode))?;
+        Ok(Shared::new(DirectoryInterfaceAdapter::new(dir)))
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        self.fs.get().commit()
+    }
+
+    fn get_free_space_size(&mut self, path: sf::InFixedPointerBuffer<Path>) -> Result<i64> {
+        let size = self.fs.get().get_free_space_size(get_path(&path)?)?;
+        Ok(size as i64)
+    }
+
+    fn get_total_space_size(&mut self, path: sf::InFixedPointerBuffer<Path>) -> Result<i64> {
+        let size = self.fs.get().get_total_space_size(get_path(&path)?)?;
+        Ok(size as i64)
+    }
+
+    fn clean_directory_recursively(&mut self, path: sf::InFixedPointerBuffer<Path>) -> Result<()> {
+        self.fs.get().clean_directory_recursively(get_path(&path)?)
+    }
+
+    fn get_file_time_stamp_raw(&mut self, path: sf::InFixedPointerBuffer<Path>) -> Result<TimeStampRaw> {
+        self.fs.get().get_file_time_stamp_raw(get_path(&path)?)
+    }
+}
+
+impl sf::IObject for FileSystemInterfaceAdapter {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
@@ -0,0 +1,84 @@
+use std::path::PathBuf;
+use crate::fs;
+use crate::fs::bis::{self, BisPartitionId};
+use crate::fs::result;
+use crate::ipc::sf;
+use crate::ipc::sf::fs::{IFileSystemProxy, Path};
+use crate::ipc::server;
+use crate::util::Shared;
+use crate::result::*;
+use super::file_system::FileSystemInterfaceAdapter;
+use super::storage::StorageInterfaceAdapter;
+
+fn get_bis_partition_id(raw_partition_id: u32) -> Result<BisPartitionId> {
+    BisPartitionId::from(raw_partition_id).ok_or(result::ResultPartitionNotFound::make())
+}
+
+pub struct FileSystemProxy {
+    session: sf::Session,
+    process_id: u64
+}
+
+impl IFileSystemProxy for FileSystemProxy {
+    fn set_current_process(&mut self, process_id: sf::ProcessId) -> Result<()> {
+        log_line!("[fs] set_current_process: process_id {:#X}", process_id.process_id);
+        self.process_id = process_id.process_id;
+        Ok(())
+    }
+
+    fn open_bis_file_system(&mut self, root_path: sf::InFixedPointerBuffer<Path>, partition_id: u32) -> Result<Shared<dyn sf::IObject>> {
+        let partition_id = get_bis_partition_id(partition_id)?;
+        let root_path = PathBuf::from(root_path.get_as::<Path>().get_string()?);
+        log_line!("[fs] open_bis_file_system: {:?} at '{}'", partition_id, root_path.display());
+
+        let bis_fs = bis::open_bis_filesystem(partition_id, root_path)?;
+        Ok(Shared::new(FileSystemInterfaceAdapter::new(bis_fs)))
+    }
+
+    fn open_bis_storage(&mut self, partition_id: u32) -> Result<Shared<dyn sf::IObject>> {
+        let partition_id = get_bis_partition_id(partition_id)?;
+        log_line!("[fs] open_bis_storage: {:?}", partition_id);
+
+        let bis_storage = bis::open_bis_storage(partition_id)?;
+        Ok(Shared::new(StorageInterfaceAdapter::new(bis_storage)))
+    }
+
+    fn open_sd_card_file_system(&mut self) -> Result<Shared<dyn sf::IObject>> {
+        log_line!("[fs] open_sd_card_file_system: process_id {:#X}", self.process_id);
+        Ok(Shared::new(FileSystemInterfaceAdapter::new(fs::open_sd_card_filesystem())))
+    }
+
+    fn get_global_access_log_mode(&mut self) -> Result<u32> {
+        // Note: access logs are never requested, since they would just go to the SD card
+        Ok(0)
+    }
+}
+
+impl sf::IObject for FileSystemProxy {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
+
+impl server::IServerObject for FileSystemProxy {
+    fn new() -> Self {
+        Self {
+            session: sf::Session::new(),
+            process_id: 0
+        }
+    }
+}
+
+impl server::IService for FileSystemProxy {
+    fn get_name() -> &'static str {
+        "fsp-srv"
+    }
+
+    fn get_max_sesssions() -> u32 {
+        0x40
+    }
+}
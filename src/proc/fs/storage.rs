@@ -0,0 +1,64 @@
+use crate::fs::Storage;
+use crate::fs::result;
+use crate::ipc::sf;
+use crate::ipc::sf::fs::IStorage;
+use crate::util::Shared;
+use crate::result::*;
+
+pub struct StorageInterfaceAdapter {
+    session: sf::Session,
+    storage: Shared<dyn Storage>
+}
+
+impl StorageInterfaceAdapter {
+    pub fn new(storage: Shared<dyn Storage>) -> Self {
+        Self {
+            session: sf::Session::new(),
+            storage: storage
+        }
+    }
+}
+
+impl IStorage for StorageInterfaceAdapter {
+    fn read(&mut self, out_buf: sf::OutNonSecureMapAliasBuffer, offset: i64, size: i64) -> Result<()> {
+        result_return_if!(offset < 0, result::ResultInvalidOffset);
+        result_return_if!(size < 0, result::ResultInvalidSize);
+        let out_data = out_buf.get_mut_slice::<u8>();
+        result_return_unless!(size as usize <= out_data.len(), result::ResultInvalidSize);
+
+        self.storage.get().read(offset as u64, &mut out_data[..size as usize])
+    }
+
+    fn write(&mut self, buf: sf::InNonSecureMapAliasBuffer, offset: i64, size: i64) -> Result<()> {
+        result_return_if!(offset < 0, result::ResultInvalidOffset);
+        result_return_if!(size < 0, result::ResultInvalidSize);
+        let data = buf.get_slice::<u8>();
+        result_return_unless!(size as usize <= data.len(), result::ResultInvalidSize);
+
+        self.storage.get().write(offset as u64, &data[..size as usize])
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.storage.get().flush()
+    }
+
+    fn set_size(&mut self, size: i64) -> Result<()> {
+        result_return_if!(size < 0, result::ResultInvalidSize);
+        self.storage.get().set_size(size as usize)
+    }
+
+    fn get_size(&mut self) -> Result<i64> {
+        let size = self.storage.get().get_size()?;
+        Ok(size as i64)
+    }
+}
+
+impl sf::IObject for StorageInterfaceAdapter {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
@@ -0,0 +1,27 @@
+use crate::ipc::server;
+use crate::kern::{proc::KProcess, thread::KThread};
+use crate::ncm::ProgramId;
+use crate::result::*;
+use super::EmulatedProcess;
+
+// Code for the emulated 'lm' process
+
+pub mod log_service;
+
+pub fn start_process() -> Result<()> {
+    let npdm = EmulatedProcess::make_npdm("lm", 27, 0x2000, ProgramId(0x0100000000000015), vec![
+        /* ... */
+    ], 128)?;
+
+    let process = KProcess::new(None, npdm)?;
+    let mut main_thread = KProcess::create_main_thread_host(&process, String::from("pg.proc.lm.MainThread"))?;
+    KThread::start_host(&mut main_thread, main_thread_fn)?;
+    Ok(())
+}
+
+fn main_thread_fn() {
+    let mut manager: server::ServerManager<0x40> = server::ServerManager::new().unwrap();
+
+    manager.register_service_server::<log_service::LogService>().unwrap();
+    manager.loop_process().unwrap();
+}
@@ -0,0 +1,139 @@
+use crate::emu::output::{self, OutputChannel};
+use crate::ipc::sf;
+use crate::ipc::sf::lm::{ILogService, ILogger};
+use crate::ipc::server;
+use crate::kern::proc::find_process_by_id;
+use crate::lm::*;
+use crate::ncm::ProgramId;
+use crate::util::Shared;
+use crate::result::*;
+
+pub struct Logger {
+    session: sf::Session,
+    process_id: u64,
+    program_id: ProgramId,
+    // A log may be split into several packets (from the head one to the tail one), whose text is accumulated here
+    pending_text: String,
+    pending_severity: Option<LogSeverity>
+}
+
+impl Logger {
+    pub fn new(process_id: u64) -> Self {
+        // Note: logs from unknown processes are still collected, just not attributed to any program
+        let program_id = match find_process_by_id(process_id) {
+            Ok(process) => process.get().npdm.aci0.program_id,
+            Err(_) => ProgramId(0)
+        };
+
+        Self {
+            session: sf::Session::new(),
+            process_id: process_id,
+            program_id: program_id,
+            pending_text: String::new(),
+            pending_severity: None
+        }
+    }
+
+    fn flush(&mut self) {
+        let text = std::mem::take(&mut self.pending_text);
+        let severity = self.pending_severity.take();
+
+        let msg = match severity {
+            Some(severity) => format!("[{:?}] {}", severity, text.trim_end()),
+            None => String::from(text.trim_end())
+        };
+        log_line!("[lm] (process {:#X}) {}", self.process_id, msg);
+        output::write_output(self.process_id, self.program_id, OutputChannel::Log, &msg);
+    }
+}
+
+impl ILogger for Logger {
+    fn log(&mut self, log_buf: sf::InAutoSelectBuffer) -> Result<()> {
+        // Note: like the real lm, malformed packets are just dropped instead of failing
+        let (header, chunks) = match parse_log_packet(log_buf.get_slice::<u8>()) {
+            Some(packet) => packet,
+            None => {
+                log_line!("[lm] Dropping malformed log packet (size {:#X})", log_buf.size);
+                return Ok(());
+            }
+        };
+
+        if header.flags.contains(LogPacketFlags::Head()) {
+            self.pending_text.clear();
+            self.pending_severity = LogSeverity::from(header.severity);
+        }
+
+        for chunk in chunks.iter().filter(|chunk| chunk.is(LogDataChunkKey::TextLog)) {
+            self.pending_text.push_str(&chunk.get_string());
+        }
+
+        if header.flags.contains(LogPacketFlags::Tail()) {
+            self.flush();
+        }
+        Ok(())
+    }
+
+    fn set_destination(&mut self, destination: u32) -> Result<()> {
+        // Note: every log ends up in the emulator output regardless of the destination (TMA, UART, ...)
+        log_line!("[lm] set_destination: destination {:#X}", destination);
+        Ok(())
+    }
+}
+
+impl sf::IObject for Logger {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
+
+impl Drop for Logger {
+    fn drop(&mut self) {
+        // Don't lose a log whose tail packet never arrived
+        if !self.pending_text.is_empty() {
+            self.flush();
+        }
+    }
+}
+
+pub struct LogService {
+    session: sf::Session
+}
+
+impl ILogService for LogService {
+    fn open_logger(&mut self, process_id: sf::ProcessId) -> Result<Shared<dyn sf::IObject>> {
+        log_line!("[lm] open_logger: process_id {:#X}", process_id.process_id);
+        Ok(Shared::new(Logger::new(process_id.process_id)))
+    }
+}
+
+impl sf::IObject for LogService {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
+
+impl server::IServerObject for LogService {
+    fn new() -> Self {
+        Self {
+            session: sf::Session::new()
+        }
+    }
+}
+
+impl server::IService for LogService {
+    fn get_name() -> &'static str {
+        "lm"
+    }
+
+    fn get_max_sesssions() -> u32 {
+        0x40
+    }
+}
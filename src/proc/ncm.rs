@@ -0,0 +1,31 @@
+use crate::ipc::server;
+use crate::kern::{proc::KProcess, thread::KThread};
+use crate::ncm::ProgramId;
+use crate::result::*;
+use super::EmulatedProcess;
+
+// Code for the emulated 'ncm' process, backed by the contents scanned at startup (see ncm::initialize)
+
+pub mod content_storage;
+
+pub mod content_meta_database;
+
+pub mod content_manager;
+
+pub fn start_process() -> Result<()> {
+    let npdm = EmulatedProcess::make_npdm("ncm", 27, 0x4000, ProgramId(0x0100000000000002), vec![
+        /* ... */
+    ], 128)?;
+
+    let process = KProcess::new(None, npdm)?;
+    let mut main_thread = KProcess::create_main_thread_host(&process, String::from("pg.proc.ncm.MainThread"))?;
+    KThread::start_host(&mut main_thread, main_thread_fn)?;
+    Ok(())
+}
+
+fn main_thread_fn() {
+    let mut manager: server::ServerManager<0x100> = server::ServerManager::new().unwrap();
+
+    manager.register_service_server::<content_manager::ContentManager>().unwrap();
+    manager.loop_process().unwrap();
+}
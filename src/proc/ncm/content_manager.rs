@@ -0,0 +1,131 @@
+use crate::ipc::sf;
+use crate::ipc::sf::ncm::IContentManager;
+use crate::ipc::server;
+use crate::ncm::*;
+use crate::ncm::result;
+use crate::util::Shared;
+use crate::result::*;
+use super::content_storage::ContentStorage;
+use super::content_meta_database::ContentMetaDatabase;
+
+pub struct ContentManager {
+    session: sf::Session
+}
+
+// Storages are active as long as their contents were scanned at startup, and can't be (in)activated afterwards
+fn get_active_storage_id(raw_storage_id: u8, for_storage: bool) -> Result<StorageId> {
+    let storage_id = StorageId::from(raw_storage_id).ok_or(result::ResultUnknownStorage::make())?;
+    if is_storage_scanned(storage_id) {
+        return Ok(storage_id);
+    }
+
+    match (storage_id, for_storage) {
+        (StorageId::GameCard, true) => result::ResultGameCardContentStorageNotActive::make_err(),
+        (StorageId::BuiltinSystem, true) => result::ResultBuiltInSystemContentStorageNotActive::make_err(),
+        (StorageId::BuiltinUser, true) => result::ResultBuiltInUserContentStorageNotActive::make_err(),
+        (StorageId::SdCard, true) => result::ResultSdCardContentStorageNotActive::make_err(),
+        (_, true) => result::ResultUnknownContentStorageNotActive::make_err(),
+        (StorageId::GameCard, false) => result::ResultGameCardContentMetaDatabaseNotActive::make_err(),
+        (StorageId::BuiltinSystem, false) => result::ResultBuiltInSystemContentMetaDatabaseNotActive::make_err(),
+        (StorageId::BuiltinUser, false) => result::ResultBuiltInUserContentMetaDatabaseNotActive::make_err(),
+        (StorageId::SdCard, false) => result::ResultSdCardContentMetaDatabaseNotActive::make_err(),
+        (_, false) => result::ResultUnknownContentMetaDatabaseNotActive::make_err()
+    }
+}
+
+impl IContentManager for ContentManager {
+    fn create_content_storage(&mut self, storage_id: u8) -> Result<()> {
+        log_line!("[ncm] create_content_storage: storage_id {}", storage_id);
+        get_active_storage_id(storage_id, true)?;
+        Ok(())
+    }
+
+    fn create_content_meta_database(&mut self, storage_id: u8) -> Result<()> {
+        log_line!("[ncm] create_content_meta_database: storage_id {}", storage_id);
+        get_active_storage_id(storage_id, false)?;
+        Ok(())
+    }
+
+    fn verify_content_storage(&mut self, storage_id: u8) -> Result<()> {
+        get_active_storage_id(storage_id, true)?;
+        Ok(())
+    }
+
+    fn verify_content_meta_database(&mut self, storage_id: u8) -> Result<()> {
+        get_active_storage_id(storage_id, false)?;
+        Ok(())
+    }
+
+    fn open_content_storage(&mut self, storage_id: u8) -> Result<Shared<dyn sf::IObject>> {
+        let storage_id = get_active_storage_id(storage_id, true)?;
+        log_line!("[ncm] open_content_storage: {:?}", storage_id);
+        Ok(Shared::new(ContentStorage::new(storage_id)))
+    }
+
+    fn open_content_meta_database(&mut self, storage_id: u8) -> Result<Shared<dyn sf::IObject>> {
+        let storage_id = get_active_storage_id(storage_id, false)?;
+        log_line!("[ncm] open_content_meta_database: {:?}", storage_id);
+        Ok(Shared::new(ContentMetaDatabase::new(storage_id)))
+    }
+
+    fn cleanup_content_meta_database(&mut self, storage_id: u8) -> Result<()> {
+        get_active_storage_id(storage_id, false)?;
+        Ok(())
+    }
+
+    fn activate_content_storage(&mut self, storage_id: u8) -> Result<()> {
+        log_line!("[ncm] activate_content_storage: storage_id {}", storage_id);
+        get_active_storage_id(storage_id, true)?;
+        Ok(())
+    }
+
+    fn inactivate_content_storage(&mut self, storage_id: u8) -> Result<()> {
+        log_line!("[ncm] inactivate_content_storage: storage_id {}", storage_id);
+        get_active_storage_id(storage_id, true)?;
+        Ok(())
+    }
+
+    fn activate_content_meta_database(&mut self, storage_id: u8) -> Result<()> {
+        log_line!("[ncm] activate_content_meta_database: storage_id {}", storage_id);
+        get_active_storage_id(storage_id, false)?;
+        Ok(())
+    }
+
+    fn inactivate_content_meta_database(&mut self, storage_id: u8) -> Result<()> {
+        log_line!("[ncm] inactivate_content_meta_database: storage_id {}", storage_id);
+        get_active_storage_id(storage_id, false)?;
+        Ok(())
+    }
+
+    fn invalidate_rights_id_cache(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl sf::IObject for ContentManager {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
+
+impl server::IServerObject for ContentManager {
+    fn new() -> Self {
+        Self {
+            session: sf::Session::new()
+        }
+    }
+}
+
+impl server::IService for ContentManager {
+    fn get_name() -> &'static str {
+        "ncm"
+    }
+
+    fn get_max_sesssions() -> u32 {
+        0x10
+    }
+}
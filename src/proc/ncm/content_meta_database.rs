@@ -0,0 +1,179 @@
+use crate::ipc::sf;
+use crate::ipc::sf::ncm::IContentMetaDatabase;
+use crate::ncm::*;
+use crate::ncm::result;
+use crate::result::*;
+
+// Read-only view of the content metas of a registered storage
+
+pub struct ContentMetaDatabase {
+    session: sf::Session,
+    storage_id: StorageId,
+    disabled: bool
+}
+
+impl ContentMetaDatabase {
+    pub fn new(storage_id: StorageId) -> Self {
+        Self {
+            session: sf::Session::new(),
+            storage_id: storage_id,
+            disabled: false
+        }
+    }
+
+    fn ensure_enabled(&self) -> Result<()> {
+        result_return_if!(self.disabled, result::ResultInvalidContentMetaDatabase);
+        Ok(())
+    }
+
+    fn find_content_meta(&self, key: &ContentMetaKey) -> Result<ContentMetaEntry> {
+        self.ensure_enabled()?;
+        find_content_meta(self.storage_id, key).ok_or(result::ResultContentMetaNotFound::make())
+    }
+}
+
+fn write_out_entries<T: Copy>(out_buf: &sf::OutMapAliasBuffer, entries: &[T]) -> usize {
+    let out_entries = out_buf.get_mut_slice::<T>();
+    let count = out_entries.len().min(entries.len());
+    out_entries[..count].copy_from_slice(&entries[..count]);
+    count
+}
+
+impl IContentMetaDatabase for ContentMetaDatabase {
+    fn get(&mut self, out_buf: sf::OutMapAliasBuffer, key: ContentMetaKey) -> Result<u64> {
+        let data = self.find_content_meta(&key)?.make_data();
+        result_return_unless!(out_buf.size >= data.len(), result::ResultBufferInsufficient);
+
+        out_buf.get_mut_slice::<u8>()[..data.len()].copy_from_slice(&data);
+        Ok(data.len() as u64)
+    }
+
+    fn get_content_id_by_type(&mut self, cnt_type: u8, key: ContentMetaKey) -> Result<ContentId> {
+        self.get_content_id_by_type_and_id_offset(cnt_type, 0, key)
+    }
+
+    fn list_content_info(&mut self, out_infos: sf::OutMapAliasBuffer, offset: i32, key: ContentMetaKey) -> Result<i32> {
+        result_return_if!(offset < 0, result::ResultInvalidOffset);
+        let cnt_meta = self.find_content_meta(&key)?;
+
+        let cnt_infos = cnt_meta.cnt_infos.get(offset as usize..).unwrap_or_default();
+        Ok(write_out_entries(&out_infos, cnt_infos) as i32)
+    }
+
+    fn list(&mut self, out_keys: sf::OutMapAliasBuffer, cnt_meta_type: u8, install_type: u8, application_id: ProgramId, min_id: ProgramId, max_id: ProgramId) -> Result<(i32, i32)> {
+        self.ensure_enabled()?;
+        log_line!("[ncm] list: type {:#X}, install type {}, application {}, IDs {} - {}", cnt_meta_type, install_type, application_id, min_id, max_id);
+
+        let keys: Vec<ContentMetaKey> = list_content_metas(self.storage_id).into_iter().filter(|cnt_meta| {
+            let type_matches = (cnt_meta_type == ContentMetaType::Any as u8) || (cnt_meta.key.cnt_meta_type as u8 == cnt_meta_type);
+            let install_type_matches = (install_type == ContentInstallType::Unknown as u8) || (cnt_meta.key.install_type as u8 == install_type);
+            let id_in_range = (min_id <= cnt_meta.key.program_id) && (cnt_meta.key.program_id <= max_id);
+            let application_matches = (application_id == ProgramId(0)) || (cnt_meta.get_application_id() == Some(application_id));
+            type_matches && install_type_matches && id_in_range && application_matches
+        }).map(|cnt_meta| cnt_meta.key).collect();
+
+        let count = write_out_entries(&out_keys, &keys);
+        Ok((keys.len() as i32, count as i32))
+    }
+
+    fn get_latest_content_meta_key(&mut self, program_id: ProgramId) -> Result<ContentMetaKey> {
+        self.ensure_enabled()?;
+
+        let latest_key = list_content_metas(self.storage_id).into_iter()
+            .map(|cnt_meta| cnt_meta.key)
+            .filter(|key| (key.program_id == program_id) && (key.install_type == ContentInstallType::Full))
+            .max_by_key(|key| key.version);
+        latest_key.ok_or(result::ResultContentMetaNotFound::make())
+    }
+
+    fn list_application(&mut self, out_keys: sf::OutMapAliasBuffer, cnt_meta_type: u8) -> Result<(i32, i32)> {
+        self.ensure_enabled()?;
+
+        let keys: Vec<ApplicationContentMetaKey> = list_content_metas(self.storage_id).into_iter().filter_map(|cnt_meta| {
+            let type_matches = (cnt_meta_type == ContentMetaType::Any as u8) || (cnt_meta.key.cnt_meta_type as u8 == cnt_meta_type);
+            match (type_matches, cnt_meta.get_application_id()) {
+                (true, Some(application_id)) => Some(ApplicationContentMetaKey {
+                    key: cnt_meta.key,
+                    application_id: application_id
+                }),
+                _ => None
+            }
+        }).collect();
+
+        let count = write_out_entries(&out_keys, &keys);
+        Ok((keys.len() as i32, count as i32))
+    }
+
+    fn has(&mut self, key: ContentMetaKey) -> Result<bool> {
+        self.ensure_enabled()?;
+        Ok(find_content_meta(self.storage_id, &key).is_some())
+    }
+
+    fn has_all(&mut self, keys: sf::InMapAliasBuffer) -> Result<bool> {
+        self.ensure_enabled()?;
+        Ok(keys.get_slice::<ContentMetaKey>().iter().all(|key| find_content_meta(self.storage_id, key).is_some()))
+    }
+
+    fn get_size(&mut self, key: ContentMetaKey) -> Result<u64> {
+        Ok(self.find_content_meta(&key)?.make_data().len() as u64)
+    }
+
+    fn get_required_system_version(&mut self, key: ContentMetaKey) -> Result<u32> {
+        self.find_content_meta(&key)?.get_required_system_version().ok_or(result::ResultInvalidContentMetaKey::make())
+    }
+
+    fn get_patch_id(&mut self, key: ContentMetaKey) -> Result<ProgramId> {
+        self.find_content_meta(&key)?.get_patch_id().ok_or(result::ResultInvalidContentMetaKey::make())
+    }
+
+    fn disable_forcibly(&mut self) -> Result<()> {
+        self.disabled = true;
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        // Note: nothing can be modified, thus there is never anything to commit
+        self.ensure_enabled()
+    }
+
+    fn has_content(&mut self, content_id: ContentId, key: ContentMetaKey) -> Result<bool> {
+        let cnt_meta = self.find_content_meta(&key)?;
+        Ok(cnt_meta.cnt_infos.iter().any(|cnt_info| cnt_info.id == content_id))
+    }
+
+    fn list_content_meta_info(&mut self, out_infos: sf::OutMapAliasBuffer, offset: i32, key: ContentMetaKey) -> Result<i32> {
+        result_return_if!(offset < 0, result::ResultInvalidOffset);
+        let cnt_meta = self.find_content_meta(&key)?;
+
+        let cnt_meta_infos = cnt_meta.cnt_meta_infos.get(offset as usize..).unwrap_or_default();
+        Ok(write_out_entries(&out_infos, cnt_meta_infos) as i32)
+    }
+
+    fn get_attributes(&mut self, key: ContentMetaKey) -> Result<ContentMetaAttribute> {
+        Ok(self.find_content_meta(&key)?.attributes)
+    }
+
+    fn get_required_application_version(&mut self, key: ContentMetaKey) -> Result<u32> {
+        self.find_content_meta(&key)?.get_required_application_version().ok_or(result::ResultInvalidContentMetaKey::make())
+    }
+
+    fn get_content_id_by_type_and_id_offset(&mut self, cnt_type: u8, id_offset: u8, key: ContentMetaKey) -> Result<ContentId> {
+        let cnt_meta = self.find_content_meta(&key)?;
+        let cnt_type = ContentType::from(cnt_type).ok_or(result::ResultContentNotFound::make())?;
+
+        match cnt_meta.find_content_info(cnt_type, id_offset) {
+            Some(cnt_info) => Ok(cnt_info.id),
+            None => result::ResultContentNotFound::make_err()
+        }
+    }
+}
+
+impl sf::IObject for ContentMetaDatabase {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
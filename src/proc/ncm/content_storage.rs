@@ -0,0 +1,136 @@
+use std::fs::File as StdFile;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use crate::ipc::sf;
+use crate::ipc::sf::ncm::IContentStorage;
+use crate::ncm::*;
+use crate::ncm::result;
+use crate::util::{CString, convert_io_result};
+use crate::result::*;
+
+// Read-only view of a registered storage: placeholders (thus installing anything) are not supported
+
+pub struct ContentStorage {
+    session: sf::Session,
+    storage_id: StorageId,
+    disabled: bool
+}
+
+fn get_storage_mount_name(storage_id: StorageId) -> &'static str {
+    match storage_id {
+        StorageId::BuiltinSystem => "@SystemContent",
+        StorageId::BuiltinUser => "@UserContent",
+        StorageId::SdCard => "@SdCardContent",
+        _ => "@Unknown"
+    }
+}
+
+impl ContentStorage {
+    pub fn new(storage_id: StorageId) -> Self {
+        Self {
+            session: sf::Session::new(),
+            storage_id: storage_id,
+            disabled: false
+        }
+    }
+
+    fn ensure_enabled(&self) -> Result<()> {
+        result_return_if!(self.disabled, result::ResultInvalidContentStorage);
+        Ok(())
+    }
+
+    fn find_content(&self, content_id: ContentId) -> Result<(String, u64)> {
+        find_content(self.storage_id, content_id).ok_or(result::ResultContentNotFound::make())
+    }
+}
+
+impl IContentStorage for ContentStorage {
+    fn has(&mut self, content_id: ContentId) -> Result<bool> {
+        self.ensure_enabled()?;
+        Ok(find_content(self.storage_id, content_id).is_some())
+    }
+
+    fn get_path(&mut self, mut out_path: sf::OutFixedPointerBuffer<ContentPath>, content_id: ContentId) -> Result<()> {
+        self.ensure_enabled()?;
+        let (path, _) = self.find_content(content_id)?;
+
+        // Note: contents are laid out flat on the host, unlike the hashed directories of the actual storages
+        let file_name = Path::new(&path).file_name().and_then(|name| name.to_str()).unwrap_or_default().to_string();
+        let content_path = format!("{}://registered/{}", get_storage_mount_name(self.storage_id), file_name);
+        log_line!("[ncm] get_path: {:?} -> '{}'", self.storage_id, content_path);
+
+        out_path.set_as(CString::<0x300>::from_str(&content_path)?);
+        Ok(())
+    }
+
+    fn get_content_count(&mut self) -> Result<i32> {
+        self.ensure_enabled()?;
+        Ok(list_content_ids(self.storage_id).len() as i32)
+    }
+
+    fn list_content_id(&mut self, out_ids: sf::OutMapAliasBuffer, offset: i32) -> Result<i32> {
+        self.ensure_enabled()?;
+        result_return_if!(offset < 0, result::ResultInvalidOffset);
+
+        let out_ids_slice = out_ids.get_mut_slice::<ContentId>();
+        let content_ids = list_content_ids(self.storage_id);
+        let mut count: usize = 0;
+        for (out_id, content_id) in out_ids_slice.iter_mut().zip(content_ids.iter().skip(offset as usize)) {
+            *out_id = *content_id;
+            count += 1;
+        }
+        Ok(count as i32)
+    }
+
+    fn get_size_from_content_id(&mut self, content_id: ContentId) -> Result<i64> {
+        self.ensure_enabled()?;
+        let (_, size) = self.find_content(content_id)?;
+        Ok(size as i64)
+    }
+
+    fn disable_forcibly(&mut self) -> Result<()> {
+        self.disabled = true;
+        Ok(())
+    }
+
+    fn read_content_id_file(&mut self, out_buf: sf::OutMapAliasBuffer, content_id: ContentId, offset: i64) -> Result<()> {
+        self.ensure_enabled()?;
+        result_return_if!(offset < 0, result::ResultInvalidOffset);
+        let (path, size) = self.find_content(content_id)?;
+        result_return_unless!((offset as u64) <= size, result::ResultInvalidOffset);
+
+        let mut file = convert_io_result(StdFile::open(path))?;
+        convert_io_result(file.seek(SeekFrom::Start(offset as u64)))?;
+
+        let out_data = out_buf.get_mut_slice::<u8>();
+        let read_size = (out_data.len() as u64).min(size - offset as u64) as usize;
+        convert_io_result(file.read_exact(&mut out_data[..read_size]))?;
+        Ok(())
+    }
+
+    fn get_free_space_size(&mut self) -> Result<i64> {
+        // Note: nothing can be installed anyway
+        self.ensure_enabled()?;
+        Ok(0)
+    }
+
+    fn get_total_space_size(&mut self) -> Result<i64> {
+        self.ensure_enabled()?;
+        let total_size: u64 = list_content_ids(self.storage_id).iter().filter_map(|content_id| find_content(self.storage_id, *content_id)).map(|(_, size)| size).sum();
+        Ok(total_size as i64)
+    }
+
+    fn flush_place_holder(&mut self) -> Result<()> {
+        self.ensure_enabled()
+    }
+}
+
+impl sf::IObject for ContentStorage {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
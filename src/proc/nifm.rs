@@ -0,0 +1,31 @@
+use crate::ipc::server;
+use crate::kern::{proc::KProcess, thread::KThread};
+use crate::ncm::ProgramId;
+use crate::result::*;
+use super::EmulatedProcess;
+
+// Code for the emulated 'nifm' process, reporting the configured network status (actual sockets are served by bsd)
+
+pub mod static_service;
+
+pub mod general_service;
+
+pub mod request;
+
+pub fn start_process() -> Result<()> {
+    let npdm = EmulatedProcess::make_npdm("nifm", 27, 0x4000, ProgramId(0x010000000000000F), vec![
+        /* ... */
+    ], 128)?;
+
+    let process = KProcess::new(None, npdm)?;
+    let mut main_thread = KProcess::create_main_thread_host(&process, String::from("pg.proc.nifm.MainThread"))?;
+    KThread::start_host(&mut main_thread, main_thread_fn)?;
+    Ok(())
+}
+
+fn main_thread_fn() {
+    let mut manager: server::ServerManager<0x40> = server::ServerManager::new().unwrap();
+
+    manager.register_service_server::<static_service::StaticService>().unwrap();
+    manager.loop_process().unwrap();
+}
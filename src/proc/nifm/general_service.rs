@@ -0,0 +1,63 @@
+use std::net::Ipv4Addr;
+use crate::ipc::sf;
+use crate::ipc::sf::nifm::IGeneralService;
+use crate::nifm::*;
+use crate::util::Shared;
+use crate::result::*;
+use super::request::Request;
+
+// Note: the connection is reported as an ethernet one, the status being the configured one (see nifm::NetworkStatus)
+
+pub struct GeneralService {
+    session: sf::Session,
+    client_id: ClientId
+}
+
+impl GeneralService {
+    pub fn new(client_id: ClientId) -> Self {
+        Self {
+            session: sf::Session::new(),
+            client_id: client_id
+        }
+    }
+}
+
+impl IGeneralService for GeneralService {
+    fn get_client_id(&mut self, mut out_client_id: sf::OutFixedPointerBuffer<ClientId>) -> Result<()> {
+        out_client_id.set_as(self.client_id);
+        Ok(())
+    }
+
+    fn create_request(&mut self, requirement_preset: u32) -> Result<Shared<dyn sf::IObject>> {
+        log_line!("[nifm] create_request: client_id {}, requirement_preset {}", self.client_id.id, requirement_preset);
+        Ok(Shared::new(Request::new(requirement_preset)?))
+    }
+
+    fn get_current_ip_address(&mut self) -> Result<u32> {
+        crate::nifm::get_internet_connection_status()?;
+
+        // Note: the loopback address is reported since the host's actual address is meaningless to guests, in network byte order like every other address
+        Ok(u32::from_ne_bytes(Ipv4Addr::LOCALHOST.octets()))
+    }
+
+    fn get_internet_connection_status(&mut self) -> Result<InternetConnectionStatus> {
+        crate::nifm::get_internet_connection_status()
+    }
+
+    fn is_any_internet_request_accepted(&mut self, client_id: sf::InFixedPointerBuffer<ClientId>) -> Result<bool> {
+        // Since requests are resolved right away, they're always accepted if internet is available
+        let accepted = get_network_status() == NetworkStatus::Connected;
+        log_line!("[nifm] is_any_internet_request_accepted: client_id {} -> {}", client_id.get_as::<ClientId>().id, accepted);
+        Ok(accepted)
+    }
+}
+
+impl sf::IObject for GeneralService {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
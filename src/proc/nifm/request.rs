@@ -0,0 +1,101 @@
+use crate::ipc::sf;
+use crate::ipc::sf::nifm::IRequest;
+use crate::kern::svc;
+use crate::nifm::*;
+use crate::result::*;
+
+// Requests are resolved as soon as they're submitted, depending on the configured network status (see nifm::get_request_result)
+pub struct Request {
+    session: sf::Session,
+    requirement_preset: u32,
+    state: RequestState,
+    result: ResultCode,
+    state_event_handle: svc::Handle,
+    state_readable_event_handle: svc::Handle,
+    event_handle: svc::Handle,
+    readable_event_handle: svc::Handle
+}
+
+impl Request {
+    pub fn new(requirement_preset: u32) -> Result<Self> {
+        let (state_event_handle, state_readable_event_handle) = svc::create_event()?;
+        let (event_handle, readable_event_handle) = svc::create_event()?;
+
+        Ok(Self {
+            session: sf::Session::new(),
+            requirement_preset: requirement_preset,
+            state: RequestState::Free,
+            result: ResultSuccess::make(),
+            state_event_handle: state_event_handle,
+            state_readable_event_handle: state_readable_event_handle,
+            event_handle: event_handle,
+            readable_event_handle: readable_event_handle
+        })
+    }
+
+    fn set_state(&mut self, state: RequestState, result: ResultCode) -> Result<()> {
+        self.state = state;
+        self.result = result;
+        svc::signal_event(self.state_event_handle)?;
+        svc::signal_event(self.event_handle)
+    }
+}
+
+impl IRequest for Request {
+    fn get_request_state(&mut self) -> Result<RequestState> {
+        Ok(self.state)
+    }
+
+    fn get_result(&mut self) -> Result<()> {
+        // The command result is the request's own one
+        self.result.to(())
+    }
+
+    fn get_system_event_readable_handles(&mut self) -> Result<(sf::CopyHandle, sf::CopyHandle)> {
+        Ok((sf::CopyHandle::from(self.state_readable_event_handle), sf::CopyHandle::from(self.readable_event_handle)))
+    }
+
+    fn cancel(&mut self) -> Result<()> {
+        self.set_state(RequestState::Free, ResultSuccess::make())
+    }
+
+    fn submit(&mut self) -> Result<()> {
+        let result = get_request_result();
+        log_line!("[nifm] Submitted request (requirement preset {}) -> {1} ({1:?})", self.requirement_preset, result);
+
+        let state = match result.is_success() {
+            true => RequestState::Accepted,
+            false => RequestState::Free
+        };
+        self.set_state(state, result)
+    }
+
+    fn set_requirement_preset(&mut self, preset: u32) -> Result<()> {
+        self.requirement_preset = preset;
+        Ok(())
+    }
+
+    fn set_connection_confirmation_option(&mut self, _option: i8) -> Result<()> {
+        // There's nothing to confirm, requests are resolved right away anyway
+        Ok(())
+    }
+}
+
+impl sf::IObject for Request {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
+
+impl Drop for Request {
+    fn drop(&mut self) {
+        let _ = svc::close_handle(self.state_event_handle);
+        let _ = svc::close_handle(self.state_readable_event_handle);
+        let _ = svc::close_handle(self.event_handle);
+        let _ = svc::close_handle(self.readable_event_handle);
+    }
+}
@@ -0,0 +1,62 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use crate::ipc::sf;
+use crate::ipc::sf::nifm::IStaticService;
+use crate::ipc::server;
+use crate::nifm::ClientId;
+use crate::util::Shared;
+use crate::result::*;
+use super::general_service::GeneralService;
+
+// Every general service gets its own client ID
+static G_NEXT_CLIENT_ID: AtomicU32 = AtomicU32::new(1);
+
+fn create_general_service() -> Shared<dyn sf::IObject> {
+    let client_id = ClientId {
+        id: G_NEXT_CLIENT_ID.fetch_add(1, Ordering::SeqCst)
+    };
+    Shared::new(GeneralService::new(client_id))
+}
+
+pub struct StaticService {
+    session: sf::Session
+}
+
+impl IStaticService for StaticService {
+    fn create_general_service_old(&mut self) -> Result<Shared<dyn sf::IObject>> {
+        log_line!("[nifm] create_general_service_old");
+        Ok(create_general_service())
+    }
+
+    fn create_general_service(&mut self, process_id: sf::ProcessId) -> Result<Shared<dyn sf::IObject>> {
+        log_line!("[nifm] create_general_service: process_id {:#X}", process_id.process_id);
+        Ok(create_general_service())
+    }
+}
+
+impl sf::IObject for StaticService {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
+
+impl server::IServerObject for StaticService {
+    fn new() -> Self {
+        Self {
+            session: sf::Session::new()
+        }
+    }
+}
+
+impl server::IService for StaticService {
+    fn get_name() -> &'static str {
+        "nifm:u"
+    }
+
+    fn get_max_sesssions() -> u32 {
+        0x40
+    }
+}
@@ -0,0 +1,30 @@
+use crate::ipc::server;
+use crate::kern::{proc::KProcess, thread::KThread};
+use crate::ncm::ProgramId;
+use crate::result::*;
+use super::EmulatedProcess;
+
+// Code for the emulated 'ns' process, only serving application records and control data (from the installed contents) for now
+
+pub mod application_manager;
+
+pub mod system_update;
+
+pub fn start_process() -> Result<()> {
+    let npdm = EmulatedProcess::make_npdm("ns", 27, 0x4000, ProgramId(0x010000000000001F), vec![
+        /* ... */
+    ], 128)?;
+
+    let process = KProcess::new(None, npdm)?;
+    let mut main_thread = KProcess::create_main_thread_host(&process, String::from("pg.proc.ns.MainThread"))?;
+    KThread::start_host(&mut main_thread, main_thread_fn)?;
+    Ok(())
+}
+
+fn main_thread_fn() {
+    let mut manager: server::ServerManager<0x40> = server::ServerManager::new().unwrap();
+
+    manager.register_service_server::<application_manager::ServiceGetterInterface>().unwrap();
+    manager.register_service_server::<system_update::SystemUpdateInterface>().unwrap();
+    manager.loop_process().unwrap();
+}
@@ -0,0 +1,133 @@
+use crate::emu::cfg;
+use crate::ipc::sf;
+use crate::ipc::sf::ns::{IApplicationManagerInterface, IReadOnlyApplicationControlDataInterface, IServiceGetterInterface};
+use crate::ipc::server;
+use crate::ncm::ProgramId;
+use crate::ns::*;
+use crate::util::Shared;
+use crate::result::*;
+
+fn list_application_record_impl(out_records: &sf::OutMapAliasBuffer, entry_offset: i32) -> Result<i32> {
+    let records = list_application_records();
+    let offset = (entry_offset.max(0) as usize).min(records.len());
+
+    let out_record_list = out_records.get_mut_slice::<ApplicationRecord>();
+    let count = out_record_list.len().min(records.len() - offset);
+    out_record_list[..count].copy_from_slice(&records[offset..offset + count]);
+    log_line!("[ns] list_application_record: offset {}, buffer size {:#X} -> {:?}", entry_offset, out_records.size, &records[offset..offset + count]);
+
+    Ok(count as i32)
+}
+
+fn get_application_control_data_impl(out_data: &sf::OutMapAliasBuffer, source: u8, application_id: ProgramId) -> Result<u32> {
+    // Note: there's no control data cache, everything is read from the contents themselves whatever the source
+    let source = ApplicationControlSource::from(source);
+    let control_data = load_application_control_data(application_id, cfg::get_config().system_language)?;
+    log_line!("[ns] get_application_control_data: source {:?}, application_id {}, buffer size {:#X} -> size {:#X}", source, application_id, out_data.size, control_data.len());
+
+    let copy_size = control_data.len().min(out_data.size);
+    out_data.get_mut_slice::<u8>()[..copy_size].copy_from_slice(&control_data[..copy_size]);
+    Ok(copy_size as u32)
+}
+
+pub struct ApplicationManagerInterface {
+    session: sf::Session
+}
+
+impl ApplicationManagerInterface {
+    pub fn new() -> Self {
+        Self {
+            session: sf::Session::new()
+        }
+    }
+}
+
+impl IApplicationManagerInterface for ApplicationManagerInterface {
+    fn list_application_record(&mut self, out_records: sf::OutMapAliasBuffer, entry_offset: i32) -> Result<i32> {
+        list_application_record_impl(&out_records, entry_offset)
+    }
+
+    fn get_application_control_data(&mut self, out_data: sf::OutMapAliasBuffer, source: u8, application_id: ProgramId) -> Result<u32> {
+        get_application_control_data_impl(&out_data, source, application_id)
+    }
+}
+
+impl sf::IObject for ApplicationManagerInterface {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
+
+pub struct ReadOnlyApplicationControlDataInterface {
+    session: sf::Session
+}
+
+impl ReadOnlyApplicationControlDataInterface {
+    pub fn new() -> Self {
+        Self {
+            session: sf::Session::new()
+        }
+    }
+}
+
+impl IReadOnlyApplicationControlDataInterface for ReadOnlyApplicationControlDataInterface {
+    fn get_application_control_data(&mut self, out_data: sf::OutMapAliasBuffer, source: u8, application_id: ProgramId) -> Result<u32> {
+        get_application_control_data_impl(&out_data, source, application_id)
+    }
+}
+
+impl sf::IObject for ReadOnlyApplicationControlDataInterface {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
+
+pub struct ServiceGetterInterface {
+    session: sf::Session
+}
+
+impl IServiceGetterInterface for ServiceGetterInterface {
+    fn get_read_only_application_control_data_interface(&mut self) -> Result<Shared<dyn sf::IObject>> {
+        Ok(Shared::new(ReadOnlyApplicationControlDataInterface::new()))
+    }
+
+    fn get_application_manager_interface(&mut self) -> Result<Shared<dyn sf::IObject>> {
+        Ok(Shared::new(ApplicationManagerInterface::new()))
+    }
+}
+
+impl sf::IObject for ServiceGetterInterface {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
+
+impl server::IServerObject for ServiceGetterInterface {
+    fn new() -> Self {
+        Self {
+            session: sf::Session::new()
+        }
+    }
+}
+
+impl server::IService for ServiceGetterInterface {
+    fn get_name() -> &'static str {
+        "ns:am2"
+    }
+
+    fn get_max_sesssions() -> u32 {
+        0x20
+    }
+}
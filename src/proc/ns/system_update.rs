@@ -0,0 +1,46 @@
+use crate::ipc::sf;
+use crate::ipc::sf::ns::ISystemUpdateInterface;
+use crate::ipc::server;
+use crate::result::*;
+
+// There are never system updates to download or install
+const BACKGROUND_NETWORK_UPDATE_STATE_NONE: u8 = 0;
+
+pub struct SystemUpdateInterface {
+    session: sf::Session
+}
+
+impl ISystemUpdateInterface for SystemUpdateInterface {
+    fn get_background_network_update_state(&mut self) -> Result<u8> {
+        log_line!("[ns] get_background_network_update_state -> {}", BACKGROUND_NETWORK_UPDATE_STATE_NONE);
+        Ok(BACKGROUND_NETWORK_UPDATE_STATE_NONE)
+    }
+}
+
+impl sf::IObject for SystemUpdateInterface {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
+
+impl server::IServerObject for SystemUpdateInterface {
+    fn new() -> Self {
+        Self {
+            session: sf::Session::new()
+        }
+    }
+}
+
+impl server::IService for SystemUpdateInterface {
+    fn get_name() -> &'static str {
+        "ns:su"
+    }
+
+    fn get_max_sesssions() -> u32 {
+        0x20
+    }
+}
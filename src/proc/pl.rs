@@ -0,0 +1,38 @@
+use crate::ipc::server;
+use crate::kern::{proc::KProcess, thread::KThread};
+use crate::ncm::ProgramId;
+use crate::result::*;
+use super::EmulatedProcess;
+
+// Code for the emulated 'glue' process, serving the shared fonts (pl:u) and the time services (time:u, time:a and time:s)
+
+pub mod platform_service;
+
+pub mod time_service;
+
+pub mod time_zone_service;
+
+pub fn start_process() -> Result<()> {
+    let npdm = EmulatedProcess::make_npdm("glue", 27, 0x4000, ProgramId(0x0100000000000031), vec![
+        /* ... */
+    ], 128)?;
+
+    let process = KProcess::new(None, npdm)?;
+    let mut main_thread = KProcess::create_main_thread_host(&process, String::from("pg.proc.glue.MainThread"))?;
+    KThread::start_host(&mut main_thread, main_thread_fn)?;
+    Ok(())
+}
+
+fn main_thread_fn() {
+    // Fonts are loaded (and the shared memory created) before serving anything, since clients map the shared memory right away
+    platform_service::initialize().unwrap();
+    time_zone_service::initialize();
+
+    let mut manager: server::ServerManager<0x40> = server::ServerManager::new().unwrap();
+
+    manager.register_service_server::<platform_service::PlatformServiceManager>().unwrap();
+    manager.register_service_server::<time_service::UserStaticService>().unwrap();
+    manager.register_service_server::<time_service::AdminStaticService>().unwrap();
+    manager.register_service_server::<time_service::SystemStaticService>().unwrap();
+    manager.loop_process().unwrap();
+}
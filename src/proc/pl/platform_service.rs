@@ -0,0 +1,134 @@
+use std::mem;
+use parking_lot::Mutex;
+use crate::ipc::sf;
+use crate::ipc::sf::pl::IPlatformServiceManager;
+use crate::ipc::server;
+use crate::kern::proc::get_current_process;
+use crate::kern::shmem::KSharedMemory;
+use crate::kern::svc::{Handle, MemoryPermission};
+use crate::pl::*;
+use crate::set::LanguageCode;
+use crate::result::*;
+
+struct SharedFontState {
+    shmem_handle: Handle,
+    fonts: Vec<SharedFont>
+}
+
+static mut G_SHARED_FONT_STATE: Mutex<Option<SharedFontState>> = parking_lot::const_mutex(None);
+
+pub fn initialize() -> Result<()> {
+    let (shmem_data, fonts) = load_shared_fonts();
+    log_line!("[pl] Loaded shared fonts: {:?}", fonts);
+
+    let shmem = KSharedMemory::new(shmem_data, MemoryPermission::Read() | MemoryPermission::Write(), MemoryPermission::Read())?;
+    let shmem_handle = get_current_process().get().handle_table.allocate_handle_set(shmem)?;
+
+    unsafe {
+        *G_SHARED_FONT_STATE.lock() = Some(SharedFontState {
+            shmem_handle: shmem_handle,
+            fonts: fonts
+        });
+    }
+    Ok(())
+}
+
+fn get_shared_fonts() -> Vec<SharedFont> {
+    unsafe {
+        G_SHARED_FONT_STATE.lock().as_ref().map(|state| state.fonts.clone()).unwrap_or_default()
+    }
+}
+
+// Fonts which aren't available have no size/offset
+fn find_shared_font(raw_font_type: u32) -> Result<Option<SharedFont>> {
+    let font_type = match SharedFontType::from(raw_font_type) {
+        Some(font_type) => font_type,
+        None => return ResultNotSupported::make_err()
+    };
+
+    Ok(get_shared_fonts().into_iter().find(|font| font.font_type == font_type))
+}
+
+fn write_array<T: Copy>(out_buf: &sf::OutMapAliasBuffer, values: &[T]) {
+    let out_values = out_buf.get_mut_slice::<T>();
+    let count = out_values.len().min(values.len());
+    out_values[..count].copy_from_slice(&values[..count]);
+}
+
+pub struct PlatformServiceManager {
+    session: sf::Session
+}
+
+impl IPlatformServiceManager for PlatformServiceManager {
+    fn request_load(&mut self, font_type: u32) -> Result<()> {
+        // Every font is already loaded at startup
+        find_shared_font(font_type)?;
+        Ok(())
+    }
+
+    fn get_load_state(&mut self, font_type: u32) -> Result<u32> {
+        find_shared_font(font_type)?;
+        Ok(SharedFontLoadState::Loaded as u32)
+    }
+
+    fn get_size(&mut self, font_type: u32) -> Result<u32> {
+        Ok(find_shared_font(font_type)?.map(|font| font.size).unwrap_or(0))
+    }
+
+    fn get_shared_memory_address_offset(&mut self, font_type: u32) -> Result<u32> {
+        Ok(find_shared_font(font_type)?.map(|font| font.offset).unwrap_or(0))
+    }
+
+    fn get_shared_memory_native_handle(&mut self) -> Result<sf::CopyHandle> {
+        let shmem_handle = unsafe {
+            match G_SHARED_FONT_STATE.lock().as_ref() {
+                Some(state) => state.shmem_handle,
+                None => return ResultNotSupported::make_err()
+            }
+        };
+
+        Ok(sf::CopyHandle::from(shmem_handle))
+    }
+
+    fn get_shared_font_in_order_of_priority(&mut self, out_types: sf::OutMapAliasBuffer, out_offsets: sf::OutMapAliasBuffer, out_sizes: sf::OutMapAliasBuffer, language_code: LanguageCode) -> Result<(bool, u32)> {
+        let fonts = get_shared_fonts();
+        let ordered_fonts: Vec<SharedFont> = get_font_types_in_order_of_priority(language_code).into_iter().filter_map(|font_type| fonts.iter().find(|font| font.font_type == font_type).copied()).collect();
+
+        let max_count = [out_types.size, out_offsets.size, out_sizes.size].iter().map(|size| size / mem::size_of::<u32>()).min().unwrap_or(0);
+        let count = ordered_fonts.len().min(max_count);
+        log_line!("[pl] get_shared_font_in_order_of_priority: language_code {:#X} -> {:?}", language_code.0, &ordered_fonts[..count]);
+
+        write_array(&out_types, &ordered_fonts[..count].iter().map(|font| font.font_type as u32).collect::<Vec<u32>>());
+        write_array(&out_offsets, &ordered_fonts[..count].iter().map(|font| font.offset).collect::<Vec<u32>>());
+        write_array(&out_sizes, &ordered_fonts[..count].iter().map(|font| font.size).collect::<Vec<u32>>());
+        Ok((true, count as u32))
+    }
+}
+
+impl sf::IObject for PlatformServiceManager {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
+
+impl server::IServerObject for PlatformServiceManager {
+    fn new() -> Self {
+        Self {
+            session: sf::Session::new()
+        }
+    }
+}
+
+impl server::IService for PlatformServiceManager {
+    fn get_name() -> &'static str {
+        "pl:u"
+    }
+
+    fn get_max_sesssions() -> u32 {
+        0x40
+    }
+}
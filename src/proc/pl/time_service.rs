@@ -0,0 +1,127 @@
+use crate::ipc::sf;
+use crate::ipc::sf::time::{IStaticService, ISteadyClock, ISystemClock};
+use crate::ipc::server;
+use crate::time::*;
+use crate::util::Shared;
+use crate::result::*;
+use super::time_zone_service::TimeZoneService;
+
+// Every system clock (user, network and local ones) is the host's clock, thus they're all the same
+pub struct SystemClock {
+    session: sf::Session
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self {
+            session: sf::Session::new()
+        }
+    }
+}
+
+impl ISystemClock for SystemClock {
+    fn get_current_time(&mut self) -> Result<i64> {
+        Ok(get_current_posix_time())
+    }
+}
+
+impl sf::IObject for SystemClock {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
+
+pub struct SteadyClock {
+    session: sf::Session
+}
+
+impl SteadyClock {
+    pub fn new() -> Self {
+        Self {
+            session: sf::Session::new()
+        }
+    }
+}
+
+impl ISteadyClock for SteadyClock {
+    fn get_current_time_point(&mut self) -> Result<SteadyClockTimePoint> {
+        Ok(get_steady_clock_time_point())
+    }
+}
+
+impl sf::IObject for SteadyClock {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
+
+// time:u, time:a and time:s are the same service (with different permissions on the console, for setting clocks and such)
+pub struct StaticService<const K: StaticServiceKind> {
+    session: sf::Session
+}
+
+pub type UserStaticService = StaticService<{StaticServiceKind::User}>;
+pub type AdminStaticService = StaticService<{StaticServiceKind::Admin}>;
+pub type SystemStaticService = StaticService<{StaticServiceKind::System}>;
+
+impl<const K: StaticServiceKind> IStaticService for StaticService<K> {
+    fn get_standard_user_system_clock(&mut self) -> Result<Shared<dyn sf::IObject>> {
+        Ok(Shared::new(SystemClock::new()))
+    }
+
+    fn get_standard_network_system_clock(&mut self) -> Result<Shared<dyn sf::IObject>> {
+        Ok(Shared::new(SystemClock::new()))
+    }
+
+    fn get_standard_steady_clock(&mut self) -> Result<Shared<dyn sf::IObject>> {
+        Ok(Shared::new(SteadyClock::new()))
+    }
+
+    fn get_time_zone_service(&mut self) -> Result<Shared<dyn sf::IObject>> {
+        Ok(Shared::new(TimeZoneService::new()))
+    }
+
+    fn get_standard_local_system_clock(&mut self) -> Result<Shared<dyn sf::IObject>> {
+        Ok(Shared::new(SystemClock::new()))
+    }
+}
+
+impl<const K: StaticServiceKind> sf::IObject for StaticService<K> {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
+
+impl<const K: StaticServiceKind> server::IServerObject for StaticService<K> {
+    fn new() -> Self {
+        Self {
+            session: sf::Session::new()
+        }
+    }
+}
+
+impl<const K: StaticServiceKind> server::IService for StaticService<K> {
+    fn get_name() -> &'static str {
+        match K {
+            StaticServiceKind::User => "time:u",
+            StaticServiceKind::Admin => "time:a",
+            StaticServiceKind::System => "time:s"
+        }
+    }
+
+    fn get_max_sesssions() -> u32 {
+        0x40
+    }
+}
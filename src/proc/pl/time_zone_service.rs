@@ -0,0 +1,103 @@
+use parking_lot::Mutex;
+use crate::emu::cfg;
+use crate::ipc::sf;
+use crate::ipc::sf::time::ITimeZoneService;
+use crate::time::*;
+use crate::time::result as time_result;
+use crate::result::*;
+
+struct DeviceTimeZone {
+    location_name: LocationName,
+    rule: TimeZoneRule
+}
+
+static mut G_DEVICE_TIME_ZONE: Mutex<Option<DeviceTimeZone>> = parking_lot::const_mutex(None);
+
+// The device's time zone ("my rule") is the configured one, or UTC if it isn't available
+pub fn initialize() {
+    let location_name = cfg::get_config().time_zone_location_name.clone();
+    let device_time_zone = match load_device_time_zone(&location_name) {
+        Ok(device_time_zone) => device_time_zone,
+        Err(rc) => {
+            log_line!("[time] Unable to load the configured time zone '{}', using UTC instead: {1} ({1:?})", location_name, rc);
+            DeviceTimeZone {
+                location_name: LocationName::from_str("UTC").unwrap(),
+                rule: TimeZoneRule::utc()
+            }
+        }
+    };
+    log_line!("[time] Device time zone: '{}' ({} transitions)", device_time_zone.location_name, device_time_zone.rule.transitions.len());
+
+    unsafe {
+        *G_DEVICE_TIME_ZONE.lock() = Some(device_time_zone);
+    }
+}
+
+fn load_device_time_zone(location_name: &str) -> Result<DeviceTimeZone> {
+    Ok(DeviceTimeZone {
+        location_name: LocationName::from_str(location_name)?,
+        rule: load_time_zone_rule(location_name)?
+    })
+}
+
+pub struct TimeZoneService {
+    session: sf::Session
+}
+
+impl TimeZoneService {
+    pub fn new() -> Self {
+        Self {
+            session: sf::Session::new()
+        }
+    }
+}
+
+impl ITimeZoneService for TimeZoneService {
+    fn get_device_location_name(&mut self) -> Result<LocationName> {
+        unsafe {
+            Ok(G_DEVICE_TIME_ZONE.lock().as_ref().map(|device_time_zone| device_time_zone.location_name).unwrap_or_default())
+        }
+    }
+
+    fn set_device_location_name(&mut self, location_name: LocationName) -> Result<()> {
+        log_line!("[time] set_device_location_name: '{}'", location_name);
+
+        let device_time_zone = load_device_time_zone(location_name.get_str()?)?;
+        unsafe {
+            *G_DEVICE_TIME_ZONE.lock() = Some(device_time_zone);
+        }
+        Ok(())
+    }
+
+    fn load_time_zone_rule(&mut self, location_name: LocationName, out_rule: sf::OutMapAliasBuffer) -> Result<()> {
+        log_line!("[time] load_time_zone_rule: '{}'", location_name);
+        result_return_unless!(out_rule.size >= TIME_ZONE_RULE_SIZE, time_result::ResultOutOfRange);
+
+        let rule = load_time_zone_rule(location_name.get_str()?)?;
+        out_rule.get_mut_slice::<u8>()[..TIME_ZONE_RULE_SIZE].copy_from_slice(&rule.to_bytes());
+        Ok(())
+    }
+
+    fn to_calendar_time(&mut self, posix_time: i64, rule: sf::InMapAliasBuffer) -> Result<(CalendarTime, CalendarAdditionalInfo)> {
+        TimeZoneRule::from_bytes(rule.get_slice::<u8>())?.to_calendar_time(posix_time)
+    }
+
+    fn to_calendar_time_with_my_rule(&mut self, posix_time: i64) -> Result<(CalendarTime, CalendarAdditionalInfo)> {
+        unsafe {
+            match G_DEVICE_TIME_ZONE.lock().as_ref() {
+                Some(device_time_zone) => device_time_zone.rule.to_calendar_time(posix_time),
+                None => TimeZoneRule::utc().to_calendar_time(posix_time)
+            }
+        }
+    }
+}
+
+impl sf::IObject for TimeZoneService {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
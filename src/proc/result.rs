@@ -0,0 +1,8 @@
+pub const RESULT_MODULE: u32 = 506;
+
+result_define_group!(RESULT_MODULE => {
+    UnknownModule: 1,
+    MissingDependency: 2,
+    DependencyCycle: 3,
+    StartTimedOut: 4
+});
@@ -0,0 +1,238 @@
+use sha2::{Sha256, Digest};
+use crate::emu::symbols;
+use crate::ipc::sf;
+use crate::ipc::sf::ro::IRoInterface;
+use crate::ipc::server;
+use crate::kern::mem::{PAGE_SIZE, is_page_aligned};
+use crate::kern::proc::find_process_by_id;
+use crate::kern::svc;
+use crate::ldr::{NroHeader, NroStart, NrrHeader};
+use crate::ldr::result as ldr_result;
+use crate::util;
+use crate::result::*;
+
+const MAX_NRR_COUNT: usize = 0x40;
+const MAX_NRO_COUNT: usize = 0x40;
+
+pub type Sha256Hash = [u8; 0x20];
+
+struct NrrInfo {
+    address: u64,
+    hashes: Vec<Sha256Hash>
+}
+
+struct NroInfo {
+    base_address: u64,
+    nro_address: u64,
+    module_id: [u8; 0x20]
+}
+
+pub struct RoInterface {
+    session: sf::Session,
+    process_id: Option<u64>,
+    nrr_infos: Vec<NrrInfo>,
+    nro_infos: Vec<NroInfo>
+}
+
+fn read_process_memory(process_id: u64, address: u64, size: usize) -> Result<Vec<u8>> {
+    let process = find_process_by_id(process_id)?;
+    let mut data: Vec<u8> = vec![0; size];
+    match process.get().cpu_ctx.as_ref() {
+        Some(cpu_ctx) => cpu_ctx.read_memory(address, &mut data)?,
+        None => return ldr_result::ResultInvalidProcess::make_err()
+    };
+
+    Ok(data)
+}
+
+impl RoInterface {
+    fn ensure_process(&self, process_id: sf::ProcessId) -> Result<()> {
+        match self.process_id {
+            Some(registered_process_id) => {
+                result_return_unless!(registered_process_id == process_id.process_id, ldr_result::ResultInvalidProcess);
+                Ok(())
+            },
+            None => ldr_result::ResultInvalidSession::make_err()
+        }
+    }
+
+    fn validate_nro(&self, nro_data: &[u8], nro_size: u64, bss_size: u64) -> Result<NroHeader> {
+        let nro_header: NroHeader = util::slice_read_val(nro_data, Some(std::mem::size_of::<NroStart>()))?;
+        result_return_unless!(nro_header.magic == NroHeader::MAGIC, ldr_result::ResultInvalidNro);
+        result_return_unless!(nro_header.size as u64 == nro_size, ldr_result::ResultInvalidNro);
+        result_return_unless!(util::align_up(nro_header.bss_size as usize, PAGE_SIZE) as u64 == bss_size, ldr_result::ResultInvalidNro);
+
+        // Segments must be page-aligned and laid out one after another (.text, .rodata, .data)
+        let text = nro_header.text_segment;
+        let rodata = nro_header.rodata_segment;
+        let data = nro_header.data_segment;
+        // Note: these come from guest memory, thus they may overflow
+        result_return_unless!(text.file_offset == 0, ldr_result::ResultInvalidNro);
+        result_return_unless!(text.file_offset.checked_add(text.size) == Some(rodata.file_offset), ldr_result::ResultInvalidNro);
+        result_return_unless!(rodata.file_offset.checked_add(rodata.size) == Some(data.file_offset), ldr_result::ResultInvalidNro);
+        result_return_unless!(data.file_offset.checked_add(data.size).map(|end_offset| end_offset as u64) == Some(nro_size), ldr_result::ResultInvalidNro);
+        for segment in [text, rodata, data].iter() {
+            result_return_unless!(is_page_aligned(segment.file_offset as u64) && is_page_aligned(segment.size as u64), ldr_result::ResultInvalidNro);
+        }
+
+        // The NRO hash must be present in any of the registered NRRs
+        let mut nro_hash: Sha256Hash = [0; 0x20];
+        nro_hash.copy_from_slice(&Sha256::digest(nro_data));
+        let is_registered = self.nrr_infos.iter().any(|nrr_info| nrr_info.hashes.contains(&nro_hash));
+        result_return_unless!(is_registered, ldr_result::ResultNotRegistered);
+
+        Ok(nro_header)
+    }
+}
+
+impl IRoInterface for RoInterface {
+    fn map_manual_load_module_memory(&mut self, process_id: sf::ProcessId, nro_address: u64, nro_size: u64, bss_address: u64, bss_size: u64) -> Result<u64> {
+        log_line!("map_manual_load_module_memory: nro_address {:#X}, nro_size {:#X}, bss_address {:#X}, bss_size {:#X}", nro_address, nro_size, bss_address, bss_size);
+        self.ensure_process(process_id)?;
+
+        result_return_unless!(self.nro_infos.len() < MAX_NRO_COUNT, ldr_result::ResultInsufficientNroRegistrations);
+        result_return_unless!(is_page_aligned(nro_address) && is_page_aligned(bss_address), ldr_result::ResultInvalidAddress);
+        result_return_unless!((nro_size > 0) && is_page_aligned(nro_size) && is_page_aligned(bss_size), ldr_result::ResultInvalidSize);
+        result_return_if!(nro_address.checked_add(nro_size).is_none() || bss_address.checked_add(bss_size).is_none(), ldr_result::ResultInvalidSize);
+
+        let nro_data = read_process_memory(self.process_id.unwrap(), nro_address, nro_size as usize)?;
+        let nro_header = self.validate_nro(&nro_data, nro_size, bss_size)?;
+        result_return_if!(self.nro_infos.iter().any(|nro_info| nro_info.module_id == nro_header.module_id), ldr_result::ResultNroAlreadyLoaded);
+
+        // Note: unlike the real ro, the NRO memory is copied instead of being aliased (the original memory stays accessible), and .bss is placed right after .data instead of using the given buffer
+        let process = find_process_by_id(self.process_id.unwrap())?;
+        let base_address = match process.get().cpu_ctx.as_mut() {
+            Some(cpu_ctx) => {
+                let base_address = cpu_ctx.load_nro(format!("nro_{:#X}", nro_address), &nro_data, bss_size as usize)?;
+                symbols::register_module(self.process_id.unwrap(), cpu_ctx.modules.last().unwrap());
+                base_address
+            },
+            None => return ldr_result::ResultInvalidProcess::make_err()
+        };
+
+        self.nro_infos.push(NroInfo {
+            base_address: base_address,
+            nro_address: nro_address,
+            module_id: nro_header.module_id
+        });
+        log_line!("Loaded NRO at {:#X}!", base_address);
+        Ok(base_address)
+    }
+
+    fn unmap_manual_load_module_memory(&mut self, process_id: sf::ProcessId, nro_address: u64) -> Result<()> {
+        log_line!("unmap_manual_load_module_memory: nro_address {:#X}", nro_address);
+        self.ensure_process(process_id)?;
+
+        // Note: the address is the one returned by MapManualLoadModuleMemory
+        let nro_info_idx = match self.nro_infos.iter().position(|nro_info| nro_info.base_address == nro_address) {
+            Some(idx) => idx,
+            None => return ldr_result::ResultNotLoaded::make_err()
+        };
+
+        let process = find_process_by_id(self.process_id.unwrap())?;
+        match process.get().cpu_ctx.as_mut() {
+            Some(cpu_ctx) => cpu_ctx.unload_nro(self.nro_infos[nro_info_idx].base_address)?,
+            None => return ldr_result::ResultInvalidProcess::make_err()
+        };
+
+        let nro_info = self.nro_infos.remove(nro_info_idx);
+        symbols::unregister_module(self.process_id.unwrap(), nro_info.base_address);
+        log_line!("Unloaded NRO at {:#X} (originally at {:#X})!", nro_info.base_address, nro_info.nro_address);
+        Ok(())
+    }
+
+    fn register_module_info(&mut self, process_id: sf::ProcessId, nrr_address: u64, nrr_size: u64) -> Result<()> {
+        log_line!("register_module_info: nrr_address {:#X}, nrr_size {:#X}", nrr_address, nrr_size);
+        self.ensure_process(process_id)?;
+
+        result_return_unless!(self.nrr_infos.len() < MAX_NRR_COUNT, ldr_result::ResultInsufficientNrrRegistrations);
+        result_return_unless!(is_page_aligned(nrr_address), ldr_result::ResultInvalidAddress);
+        result_return_unless!((nrr_size > 0) && is_page_aligned(nrr_size), ldr_result::ResultInvalidSize);
+        result_return_if!(self.nrr_infos.iter().any(|nrr_info| nrr_info.address == nrr_address), ldr_result::ResultInvalidNrr);
+
+        let nrr_data = read_process_memory(self.process_id.unwrap(), nrr_address, nrr_size as usize)?;
+        let nrr_header: NrrHeader = util::slice_read_val(&nrr_data, None)?;
+        result_return_unless!(nrr_header.magic == NrrHeader::MAGIC, ldr_result::ResultInvalidNrr);
+        result_return_unless!(nrr_header.size as u64 == nrr_size, ldr_result::ResultInvalidSize);
+        // TODO: validate the NRR certification/signature and program ID (the actual keys would be needed)
+
+        let hashes_offset = nrr_header.hashes_offset as usize;
+        let hash_count = nrr_header.hash_count as usize;
+        result_return_unless!(hashes_offset + hash_count * std::mem::size_of::<Sha256Hash>() <= nrr_data.len(), ldr_result::ResultInvalidNrr);
+
+        let mut hashes: Vec<Sha256Hash> = Vec::with_capacity(hash_count);
+        for i in 0..hash_count {
+            let hash: Sha256Hash = util::slice_read_val(&nrr_data, Some(hashes_offset + i * std::mem::size_of::<Sha256Hash>()))?;
+            hashes.push(hash);
+        }
+
+        self.nrr_infos.push(NrrInfo {
+            address: nrr_address,
+            hashes: hashes
+        });
+        log_line!("Registered NRR with {} hashes!", hash_count);
+        Ok(())
+    }
+
+    fn unregister_module_info(&mut self, process_id: sf::ProcessId, nrr_address: u64) -> Result<()> {
+        log_line!("unregister_module_info: nrr_address {:#X}", nrr_address);
+        self.ensure_process(process_id)?;
+
+        match self.nrr_infos.iter().position(|nrr_info| nrr_info.address == nrr_address) {
+            Some(idx) => {
+                self.nrr_infos.remove(idx);
+                Ok(())
+            },
+            None => ldr_result::ResultNotRegistered::make_err()
+        }
+    }
+
+    fn register_process_handle(&mut self, process_id: sf::ProcessId, process_handle: sf::CopyHandle) -> Result<()> {
+        log_line!("register_process_handle: process_id {:#X}", process_id.process_id);
+
+        // Note: the process is looked up by its ID, thus the handle itself isn't needed
+        svc::close_handle(process_handle.handle)?;
+        result_return_if!(self.process_id.is_some(), ldr_result::ResultInvalidSession);
+
+        find_process_by_id(process_id.process_id).map_err(|_| ldr_result::ResultInvalidProcess::make())?;
+        self.process_id = Some(process_id.process_id);
+        Ok(())
+    }
+}
+
+impl sf::IObject for RoInterface {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        vec! [
+            ipc_cmif_interface_make_command_meta!(map_manual_load_module_memory: 0),
+            ipc_cmif_interface_make_command_meta!(unmap_manual_load_module_memory: 1),
+            ipc_cmif_interface_make_command_meta!(register_module_info: 2),
+            ipc_cmif_interface_make_command_meta!(unregister_module_info: 3),
+            ipc_cmif_interface_make_command_meta!(register_process_handle: 4)
+        ]
+    }
+}
+
+impl server::IServerObject for RoInterface {
+    fn new() -> Self {
+        Self {
+            session: sf::Session::new(),
+            process_id: None,
+            nrr_infos: Vec::new(),
+            nro_infos: Vec::new()
+        }
+    }
+}
+
+impl server::IService for RoInterface {
+    fn get_name() -> &'static str {
+        "ldr:ro"
+    }
+
+    fn get_max_sesssions() -> u32 {
+        0x20
+    }
+}
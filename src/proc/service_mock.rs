@@ -0,0 +1,216 @@
+use core::ptr;
+use crate::emu::service_mock::{self, ServiceMock, ServiceRecording, ServiceRequest};
+use crate::ipc::{CommandContext, ObjectInfo};
+use crate::ipc::cmif;
+use crate::ipc::tipc;
+use crate::ipc::sf;
+use crate::ipc::sf::client;
+use crate::ipc::sf::client::sm::{self as sm_client, IUserInterface};
+use crate::kern::{ipc as kern_ipc, proc::KProcess, thread::KThread, svc};
+use crate::kern::result as kern_result;
+use crate::ncm::ProgramId;
+use crate::sm::ServiceName;
+use crate::result::*;
+use crate::result as lib_result;
+use super::EmulatedProcess;
+
+// Code for the emulated 'service_mock' process, which serves the services in a recording (see emu::service_mock) by replying their recorded raw replies
+// Note: it's meant to be listed in the boot manifest in place of the modules providing the mocked services, with the recording given through '--replay-services'
+
+const MAX_SESSIONS: u32 = 0x40;
+
+struct MockService {
+    name: String,
+    mock: ServiceMock
+}
+
+struct MockPort {
+    handle: svc::Handle,
+    service_idx: usize
+}
+
+struct MockSession {
+    handle: svc::Handle,
+    service_idx: usize,
+    is_domain: bool
+}
+
+struct MockServer {
+    services: Vec<MockService>,
+    ports: Vec<MockPort>,
+    sessions: Vec<MockSession>
+}
+
+fn reply_to_session(handle: svc::Handle) -> Result<()> {
+    match svc::reply_and_receive(&[], handle, 0) {
+        Err(rc) => {
+            if kern_result::ResultTimedOut::matches(rc) || kern_result::ResultSessionClosed::matches(rc) {
+                Ok(())
+            }
+            else {
+                Err(rc)
+            }
+        },
+        _ => Ok(())
+    }
+}
+
+fn is_convert_to_domain_request(request: &ServiceRequest) -> bool {
+    let is_control = (request.command_type == cmif::CommandType::Control as u32) || (request.command_type == cmif::CommandType::ControlWithContext as u32);
+    is_control && (request.command_id == Some(cmif::ControlRequestId::ConvertCurrentObjectToDomain as u32))
+}
+
+fn write_failure_reply(session: &MockSession, request: &ServiceRequest, rc: ResultCode) {
+    // TIPC command types are the command ID plus 16
+    if request.command_type >= 16 {
+        let mut ctx = CommandContext::new_server(ObjectInfo::from_handle(session.handle), ptr::null_mut());
+        tipc::server::write_request_command_response_on_msg_buffer(&mut ctx, rc, request.command_type);
+        return;
+    }
+
+    let command_type = cmif::convert_command_type(request.command_type);
+    let mut object_info = ObjectInfo::from_handle(session.handle);
+    // Only requests are wrapped in domain messages, thus the reply needs the domain header as well (the object ID comes from the request's one)
+    if session.is_domain && ((command_type == cmif::CommandType::Request) || (command_type == cmif::CommandType::RequestWithContext)) {
+        object_info.domain_object_id = request.data.get(1).copied().unwrap_or(0);
+    }
+
+    let mut ctx = CommandContext::new_server(object_info, ptr::null_mut());
+    cmif::server::write_request_command_response_on_msg_buffer(&mut ctx, rc, command_type);
+}
+
+impl MockServer {
+    fn new(recording: &ServiceRecording) -> Result<Self> {
+        let mut server = Self {
+            services: Vec::new(),
+            ports: Vec::new(),
+            sessions: Vec::new()
+        };
+
+        let sm = client::new_named_port_object::<sm_client::UserInterface>()?;
+        for service_name in recording.get_service_names() {
+            // Services already provided by another module can't be mocked
+            match sm.get().register_service(ServiceName::new(&service_name), false, MAX_SESSIONS) {
+                Ok(port_handle) => {
+                    let mock = ServiceMock::new(recording, &service_name);
+                    log_line!("Mocking '{}' with {} recorded calls", service_name, mock.get_call_count());
+                    server.ports.push(MockPort {
+                        handle: port_handle.handle,
+                        service_idx: server.services.len()
+                    });
+                    server.services.push(MockService {
+                        name: service_name,
+                        mock: mock
+                    });
+                },
+                Err(rc) => log_line!("Unable to mock '{}': {1} ({1:?})", service_name, rc)
+            };
+        }
+        sm.get().detach_client(sf::ProcessId::new())?;
+
+        Ok(server)
+    }
+
+    fn close_session(&mut self, session_idx: usize) -> Result<()> {
+        let session = self.sessions.remove(session_idx);
+        svc::close_handle(session.handle)
+    }
+
+    fn process_session(&mut self, session_idx: usize) -> Result<()> {
+        let handle = self.sessions[session_idx].handle;
+        if let Err(rc) = svc::reply_and_receive(&[handle], 0, -1) {
+            if kern_result::ResultSessionClosed::matches(rc) {
+                return self.close_session(session_idx);
+            }
+            return Err(rc);
+        }
+
+        // Note: received requests were already validated by the kernel
+        let request = match kern_ipc::get_current_service_request() {
+            Some(request) => request,
+            None => return self.close_session(session_idx)
+        };
+
+        if request.command_type == cmif::CommandType::Close as u32 {
+            let mut ctx = CommandContext::new_server(ObjectInfo::from_handle(handle), ptr::null_mut());
+            cmif::server::write_close_command_response_on_msg_buffer(&mut ctx);
+            reply_to_session(handle)?;
+            return self.close_session(session_idx);
+        }
+
+        let session = &mut self.sessions[session_idx];
+        let service = &mut self.services[session.service_idx];
+        match service.mock.find_reply(&request) {
+            Some(reply) => {
+                kern_ipc::write_current_message(&reply)?;
+                // Domain requests (and replies) are only recorded/replied as they are, but failures must be written accordingly
+                if is_convert_to_domain_request(&request) {
+                    session.is_domain = true;
+                }
+            },
+            None => {
+                log_line!("No recorded call of '{}' for command {:?} (command type {})", service.name, request.command_id, request.command_type);
+                write_failure_reply(session, &request, lib_result::ResultNoRecordedServiceCall::make());
+            }
+        };
+
+        reply_to_session(handle)
+    }
+
+    fn process(&mut self) -> Result<()> {
+        let handles: Vec<svc::Handle> = self.ports.iter().map(|port| port.handle).chain(self.sessions.iter().map(|session| session.handle)).collect();
+        let idx = svc::wait_synchronization(&handles, -1)?;
+
+        match self.ports.get(idx) {
+            Some(port) => {
+                let session_handle = svc::accept_session(port.handle)?;
+                self.sessions.push(MockSession {
+                    handle: session_handle,
+                    service_idx: port.service_idx,
+                    is_domain: false
+                });
+                Ok(())
+            },
+            None => self.process_session(idx - self.ports.len())
+        }
+    }
+
+    fn loop_process(&mut self) -> Result<()> {
+        loop {
+            match self.process() {
+                Err(rc) => {
+                    if kern_result::ResultCancelled::matches(rc) || kern_result::ResultTimedOut::matches(rc) {
+                        continue;
+                    }
+                    return Err(rc);
+                },
+                _ => {}
+            }
+        }
+    }
+}
+
+pub fn start_process() -> Result<()> {
+    let recording = match service_mock::get_replay_recording() {
+        Some(recording) => recording,
+        None => {
+            log_line!("[service_mock] No recording to replay was given");
+            return lib_result::ResultNoRecordedServiceCall::make_err();
+        }
+    };
+
+    // Note: not an actual system title
+    let npdm = EmulatedProcess::make_npdm("service_mock", 27, 0x2000, ProgramId(0x010000000000FFFE), vec![
+        /* ... */
+    ], 512)?;
+
+    let process = KProcess::new(None, npdm)?;
+    let mut main_thread = KProcess::create_main_thread_host(&process, String::from("pg.proc.service_mock.MainThread"))?;
+    KThread::start_host(&mut main_thread, move || main_thread_fn(recording))?;
+    Ok(())
+}
+
+fn main_thread_fn(recording: ServiceRecording) {
+    let mut server = MockServer::new(&recording).unwrap();
+    server.loop_process().unwrap();
+}
@@ -6,6 +6,8 @@ use super::EmulatedProcess;
 
 // Code for the emulated 'settings' process
 
+pub mod settings;
+
 pub mod sys;
 
 pub fn start_process() -> Result<()> {
@@ -24,6 +26,7 @@ fn main_thread_fn() {
 
     let mut manager: server::ServerManager<0x100> = server::ServerManager::new().unwrap();
 
+    manager.register_service_server::<settings::SettingsServer>().unwrap();
     manager.register_service_server::<sys::SystemSettingsServer>().unwrap();
     manager.loop_process().unwrap();
 }
\ No newline at end of file
@@ -0,0 +1,54 @@
+use crate::emu::cfg;
+use crate::ipc::sf;
+use crate::ipc::sf::set::ISettingsServer;
+use crate::ipc::server;
+use crate::set::*;
+use crate::result::*;
+
+pub struct SettingsServer {
+    session: sf::Session
+}
+
+impl ISettingsServer for SettingsServer {
+    fn get_language_code(&mut self) -> Result<LanguageCode> {
+        let language = cfg::get_config().system_language;
+        log_line!("get_language_code -> {:?} ({})", language, language.get_name());
+
+        Ok(language.get_language_code())
+    }
+
+    fn get_region_code(&mut self) -> Result<RegionCode> {
+        let region = cfg::get_config().system_region;
+        log_line!("get_region_code -> {:?}", region);
+
+        Ok(region)
+    }
+}
+
+impl sf::IObject for SettingsServer {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
+
+impl server::IServerObject for SettingsServer {
+    fn new() -> Self {
+        Self {
+            session: sf::Session::new()
+        }
+    }
+}
+
+impl server::IService for SettingsServer {
+    fn get_name() -> &'static str {
+        "set"
+    }
+
+    fn get_max_sesssions() -> u32 {
+        0x40
+    }
+}
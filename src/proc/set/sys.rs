@@ -1,15 +1,19 @@
+use std::mem;
 use std::path::PathBuf;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use cntx::nca::ContentType;
 use crate::fs::file_read_val;
-use crate::fs::{RomFsFileSystem, FileSystem, FileOpenMode, ReadOption};
+use crate::fs::{cache, FileOpenMode, ReadOption};
 use crate::ipc::sf;
+use crate::ipc::result as ipc_result;
 use crate::ipc::sf::set::ISystemSettingsServer;
 use crate::ipc::server;
-use crate::ncm::{ProgramId, StorageId, lookup_content};
+use crate::ncm::{ProgramId, StorageId};
 use crate::set::*;
 use crate::result::*;
+use crate::version::{self, Version};
+use crate::emu::cfg;
 
 pub struct SystemSettingsServer {
     session: sf::Session
@@ -34,8 +38,7 @@ fn load_firmware_version(fw_ver: FirmwareVersion) {
 pub fn get_firmware_version(with_revision: bool) -> Result<FirmwareVersion> {
     if !is_firmware_version_loaded() {
         const SYSTEM_VERSION_ID: ProgramId = ProgramId(0x0100000000000809);
-        let mut system_version_nca = lookup_content(StorageId::BuiltinSystem, SYSTEM_VERSION_ID, ContentType::Data)?;
-        let system_version_fs = RomFsFileSystem::from_nca(&mut system_version_nca, 0)?;
+        let system_version_fs = cache::open_nca_filesystem(StorageId::BuiltinSystem, SYSTEM_VERSION_ID, ContentType::Data, 0, cache::NcaFileSystemKind::RomFs)?;
 
         let system_version_file = system_version_fs.get().open_file(PathBuf::from("file"), FileOpenMode::Read())?;
         let fw_ver: FirmwareVersion = file_read_val(&system_version_file, 0, ReadOption::None)?;
@@ -43,6 +46,7 @@ pub fn get_firmware_version(with_revision: bool) -> Result<FirmwareVersion> {
         log_line!("Loaded firmware version: {:#?}", fw_ver);
 
         load_firmware_version(fw_ver);
+        version::set_version(Version::new(fw_ver.major, fw_ver.minor, fw_ver.micro));
     }
 
     let mut fw_ver = unsafe {
@@ -71,6 +75,15 @@ impl ISystemSettingsServer for SystemSettingsServer {
         out_version.set_as(get_firmware_version(true)?);
         Ok(())
     }
+
+    fn get_device_nick_name(&mut self, mut out_name: sf::OutAutoSelectBuffer) -> Result<()> {
+        let nickname = cfg::get_config().device_nickname.clone();
+        log_line!("get_device_nick_name -> {}", nickname);
+
+        result_return_unless!(out_name.size >= mem::size_of::<DeviceNickName>(), ipc_result::ResultPointerBufferTooSmall);
+        out_name.set_as(DeviceNickName::from_string(nickname)?);
+        Ok(())
+    }
 }
 
 impl sf::IObject for SystemSettingsServer {
@@ -79,10 +92,7 @@ impl sf::IObject for SystemSettingsServer {
     }
 
     fn get_command_table(&self) -> sf::CommandMetadataTable {
-        vec! [
-            ipc_cmif_interface_make_command_meta!(get_firmware_version: 3),
-            ipc_cmif_interface_make_command_meta!(get_firmware_version_2: 4)
-        ]
+        self.get_interface_command_table()
     }
 }
 
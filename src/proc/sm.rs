@@ -3,9 +3,13 @@ use rsevents::{Awaitable, ManualResetEvent, State};
 use crate::ipc::sf;
 use crate::ipc::sf::sm::IUserInterface;
 use crate::ipc::server;
+use crate::ipc::cmif::result as cmif_result;
 use crate::kern::svc::Handle;
-use crate::kern::{proc::KProcess, thread::KThread, svc};
+use crate::kern::result as kern_result;
+use crate::kern::{proc::KProcess, proc::find_process_by_id, proc::get_current_process, thread::KThread, ipc::KClientPort, svc};
+use crate::ldr::npdm::ServiceAccessControlData;
 use crate::ncm::ProgramId;
+use crate::emu::cfg;
 use crate::sm::*;
 use crate::result::*;
 use super::EmulatedProcess;
@@ -34,9 +38,17 @@ struct ServiceInfo {
     port_handle: Handle
 }
 
+const MAX_SERVICE_COUNT: usize = 0x100;
+
 static mut G_SERVICES: Mutex<Vec<ServiceInfo>> = parking_lot::const_mutex(Vec::new());
 
-fn has_service_info(name: ServiceName) -> bool {
+fn get_service_count() -> usize {
+    unsafe {
+        G_SERVICES.lock().len()
+    }
+}
+
+pub fn has_service_info(name: ServiceName) -> bool {
     unsafe {
         let services = G_SERVICES.lock();
 
@@ -93,8 +105,12 @@ fn find_service_info(name: ServiceName) -> Result<ServiceInfo> {
 
 fn register_service(name: ServiceName, process_id: u64, max_sessions: u32, is_light: bool) -> Result<Handle> {
     result_return_if!(has_service_info(name), result::ResultAlreadyRegistered);
+    result_return_unless!(get_service_count() < MAX_SERVICE_COUNT, result::ResultOutOfServices);
     
     let (server_handle, client_handle) = svc::create_port(max_sessions, is_light, 0)?;
+    // Name the port after the service, so that kernel diagnostics (like the IPC watchdog) can tell which service a session belongs to
+    let client_port = get_current_process().get().handle_table.get_handle_obj::<KClientPort>(client_handle)?;
+    client_port.get().parent.get().set_name(String::from(name.to_str()));
     let service_info = ServiceInfo {
         name: name,
         owner_process_id: process_id,
@@ -114,7 +130,31 @@ fn unregister_service(name: ServiceName, process_id: u64) -> Result<()> {
 fn get_service_handle(name: ServiceName) -> Result<Handle> {
     let service_info = find_service_info(name)?;
 
-    svc::connect_to_port(service_info.port_handle)
+    svc::connect_to_port(service_info.port_handle).map_err(|rc| match kern_result::ResultOutOfSessions::matches(rc) {
+        true => result::ResultOutOfSessions::make(),
+        false => rc
+    })
+}
+
+fn can_access_service(access_control: &ServiceAccessControlData, name: ServiceName, is_server: bool) -> bool {
+    let name_str = name.to_str().trim_end_matches('\0');
+
+    for entry in access_control.services.iter() {
+        if entry.is_server != is_server {
+            continue;
+        }
+
+        // A trailing '*' acts as a wildcard for any service name with that prefix
+        let has_access = match entry.name.strip_suffix('*') {
+            Some(prefix) => name_str.starts_with(prefix),
+            None => entry.name == name_str
+        };
+        if has_access {
+            return true;
+        }
+    }
+
+    false
 }
 
 static mut G_READY: Option<ManualResetEvent> = None;
@@ -142,13 +182,34 @@ pub fn wait_ready() {
 pub struct UserInterface {
     session: sf::Session,
     process_id: u64,
+    access_control: Option<ServiceAccessControlData>,
     initialized: bool
 }
 
+impl UserInterface {
+    fn check_access(&self, name: ServiceName, is_server: bool) -> Result<()> {
+        if let Some(access_control) = self.access_control.as_ref() {
+            result_return_unless!(can_access_service(access_control, name, is_server), result::ResultNotAllowed);
+        }
+
+        Ok(())
+    }
+}
+
 impl IUserInterface for UserInterface {
     fn register_client(&mut self, process_id: sf::ProcessId) -> Result<()> {
         log_line!("register_client - process_id: {:#X}", process_id.process_id);
 
+        // No access control means no enforcement at all (disabled through the config, mostly for debugging)
+        self.access_control = match cfg::get_config().enforce_service_access_control {
+            true => {
+                let process = find_process_by_id(process_id.process_id).map_err(|_| result::ResultInvalidClient::make())?;
+                let access_control = process.get().npdm.aci0_service_access_control.clone();
+                Some(access_control)
+            },
+            false => None
+        };
+
         self.process_id = process_id.process_id;
         self.initialized = true;
         Ok(())
@@ -159,6 +220,10 @@ impl IUserInterface for UserInterface {
         
         result_return_unless!(self.initialized, result::ResultInvalidClient);
         result_return_if!(name.is_empty(), result::ResultInvalidServiceName);
+        self.check_access(name, false)?;
+
+        // Like the real sm, requests for services not registered yet are answered once they get registered
+        result_return_unless!(has_service_info(name), cmif_result::ResultRequestDeferredByUser);
 
         let handle = get_service_handle(name)?;
         Ok(sf::MoveHandle::from(handle))
@@ -169,6 +234,7 @@ impl IUserInterface for UserInterface {
         
         result_return_unless!(self.initialized, result::ResultInvalidClient);
         result_return_if!(name.is_empty(), result::ResultInvalidServiceName);
+        self.check_access(name, true)?;
 
         let handle = register_service(name, self.process_id, max_sessions, is_light)?;
         Ok(sf::MoveHandle::from(handle))
@@ -187,6 +253,7 @@ impl IUserInterface for UserInterface {
     fn detach_client(&mut self, process_id: sf::ProcessId) -> Result<()> {
         log_line!("detach_client - process_id: {:#X}", process_id.process_id);
 
+        self.access_control = None;
         self.initialized = false;
         Ok(())
     }
@@ -213,6 +280,7 @@ impl server::IServerObject for UserInterface {
         Self {
             session: sf::Session::new(),
             process_id: 0,
+            access_control: None,
             initialized: false
         }
     }
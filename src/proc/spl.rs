@@ -0,0 +1,37 @@
+use crate::ipc::server;
+use crate::kern::{proc::KProcess, thread::KThread};
+use crate::ncm::ProgramId;
+use crate::result::*;
+use super::EmulatedProcess;
+
+// Code for the emulated 'spl' process
+
+pub mod smc;
+
+pub mod general;
+
+pub mod fs;
+
+pub fn start_process() -> Result<()> {
+    let npdm = EmulatedProcess::make_npdm("spl", 27, 0x4000, ProgramId(0x0100000000000028), vec![
+        /* ... */
+    ], 128)?;
+
+    let process = KProcess::new(None, npdm)?;
+    let mut main_thread = KProcess::create_main_thread_host(&process, String::from("pg.proc.spl.MainThread"))?;
+    KThread::start_host(&mut main_thread, main_thread_fn)?;
+    Ok(())
+}
+
+fn main_thread_fn() {
+    log_line!("Hello World!");
+
+    smc::initialize();
+
+    let mut manager: server::ServerManager<0x40> = server::ServerManager::new().unwrap();
+
+    manager.register_service_server::<general::GeneralInterface>().unwrap();
+    // Note: registered regardless of the firmware version (which may not be known yet), even if it only exists since 4.0.0
+    manager.register_service_server::<fs::FsInterface>().unwrap();
+    manager.loop_process().unwrap();
+}
@@ -0,0 +1,108 @@
+use crate::ipc::sf;
+use crate::ipc::sf::spl::IGeneralInterface;
+use crate::ipc::server;
+use crate::spl::*;
+use crate::result::*;
+use super::smc;
+
+pub struct GeneralInterface {
+    session: sf::Session
+}
+
+impl IGeneralInterface for GeneralInterface {
+    fn get_config(&mut self, item: u32) -> Result<u64> {
+        let value = smc::get_config(item)?;
+        log_line!("[spl:] get_config: item {:?} ({}) -> {:#X}", ConfigItem::from(item), item, value);
+        Ok(value)
+    }
+
+    fn generate_aes_kek(&mut self, kek_source: KeySource, generation: u32, option: u32) -> Result<AccessKey> {
+        log_line!("[spl:] generate_aes_kek: generation {}, option {:#X}", generation, option);
+        smc::generate_aes_kek(kek_source, generation, option)
+    }
+
+    fn load_aes_key(&mut self, keyslot: u32, access_key: AccessKey, key_source: KeySource) -> Result<()> {
+        log_line!("[spl:] load_aes_key: keyslot {}", keyslot);
+        smc::load_aes_key(keyslot, access_key, key_source)
+    }
+
+    fn generate_aes_key(&mut self, access_key: AccessKey, key_source: KeySource) -> Result<AesKey> {
+        log_line!("[spl:] generate_aes_key...");
+        smc::generate_aes_key(access_key, key_source)
+    }
+
+    fn set_config(&mut self, item: u32, value: u64) -> Result<()> {
+        log_line!("[spl:] set_config: item {:?} ({}), value {:#X}", ConfigItem::from(item), item, value);
+        smc::set_config(item, value)
+    }
+
+    fn generate_random_bytes(&mut self, out_buf: sf::OutPointerBuffer) -> Result<()> {
+        log_line!("[spl:] generate_random_bytes: size {:#X}", out_buf.size);
+        smc::generate_random_bytes(out_buf.get_mut_slice())
+    }
+
+    fn is_development(&mut self) -> Result<bool> {
+        let is_development = smc::is_development()?;
+        log_line!("[spl:] is_development -> {}", is_development);
+        Ok(is_development)
+    }
+
+    fn decrypt_aes_key(&mut self, key_source: KeySource, generation: u32, option: u32) -> Result<AesKey> {
+        log_line!("[spl:] decrypt_aes_key: generation {}, option {:#X}", generation, option);
+        smc::decrypt_aes_key(key_source, generation, option)
+    }
+
+    fn compute_ctr(&mut self, out_buf: sf::OutNonSecureMapAliasBuffer, keyslot: u32, in_buf: sf::InNonSecureMapAliasBuffer, iv_ctr: IvCtr) -> Result<()> {
+        log_line!("[spl:] compute_ctr: keyslot {}, size {:#X}", keyslot, in_buf.size);
+        smc::compute_ctr(keyslot, iv_ctr, in_buf.get_slice(), out_buf.get_mut_slice())
+    }
+
+    fn allocate_aes_keyslot(&mut self) -> Result<u32> {
+        let keyslot = smc::allocate_aes_keyslot()?;
+        log_line!("[spl:] allocate_aes_keyslot -> {}", keyslot);
+        Ok(keyslot)
+    }
+
+    fn deallocate_aes_keyslot(&mut self, keyslot: u32) -> Result<()> {
+        log_line!("[spl:] deallocate_aes_keyslot: keyslot {}", keyslot);
+        smc::deallocate_aes_keyslot(keyslot)
+    }
+
+    fn set_boot_reason(&mut self, boot_reason: BootReasonValue) -> Result<()> {
+        log_line!("[spl:] set_boot_reason: {:?}", boot_reason);
+        smc::set_boot_reason(boot_reason)
+    }
+
+    fn get_boot_reason(&mut self) -> Result<BootReasonValue> {
+        log_line!("[spl:] get_boot_reason...");
+        smc::get_boot_reason()
+    }
+}
+
+impl sf::IObject for GeneralInterface {
+    fn get_session(&mut self) -> &mut sf::Session {
+        &mut self.session
+    }
+
+    fn get_command_table(&self) -> sf::CommandMetadataTable {
+        self.get_interface_command_table()
+    }
+}
+
+impl server::IServerObject for GeneralInterface {
+    fn new() -> Self {
+        Self {
+            session: sf::Session::new()
+        }
+    }
+}
+
+impl server::IService for GeneralInterface {
+    fn get_name() -> &'static str {
+        "spl:"
+    }
+
+    fn get_max_sesssions() -> u32 {
+        0x10
+    }
+}
@@ -0,0 +1,300 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use aes::{Aes128, BlockCipher, NewBlockCipher};
+use aes::cipher::generic_array::GenericArray;
+use parking_lot::Mutex;
+use sha2::{Sha256, Digest};
+use crate::emu::cfg;
+use crate::result::*;
+use crate::spl::*;
+use crate::spl::result as spl_result;
+
+// Secure monitor emulation backing every spl interface
+// Note: key derivation follows the console (master key -> KEK -> key), but device-unique keys and access key sealing are not modelled, thus access keys are just unsealed KEKs
+
+pub type Key = [u8; AES_BLOCK_SIZE];
+
+pub const MASTER_KEY_COUNT: usize = 0x20;
+pub const AES_KEYSLOT_COUNT: usize = 6;
+
+const FAKE_KEY_SEED: &str = "pegasus.spl";
+const RANDOM_SEED: u64 = 0x7065676173757321;
+
+struct SecureMonitor {
+    keyset_keys: BTreeMap<String, Key>,
+    keyslots: [Option<Key>; AES_KEYSLOT_COUNT],
+    allocated_keyslots: [bool; AES_KEYSLOT_COUNT],
+    boot_reason: Option<BootReasonValue>,
+    is_charger_hiz_mode_enabled: bool,
+    random_state: u64
+}
+
+static mut G_SECURE_MONITOR: Mutex<Option<SecureMonitor>> = parking_lot::const_mutex(None);
+
+// Only 0x10-byte entries are relevant here (master keys and key sources)
+fn load_keyset_keys() -> BTreeMap<String, Key> {
+    let mut keys: BTreeMap<String, Key> = BTreeMap::new();
+    let keyset_file = match File::open(cfg::get_keyset_path()) {
+        Ok(file) => file,
+        Err(_) => return keys
+    };
+
+    for line in BufReader::new(keyset_file).lines().flatten() {
+        let mut parts = line.splitn(2, '=');
+        let (name, value) = match (parts.next(), parts.next()) {
+            (Some(name), Some(value)) => (name.trim().to_lowercase(), value.trim()),
+            _ => continue
+        };
+
+        if value.len() != AES_BLOCK_SIZE * 2 {
+            continue;
+        }
+
+        let mut key: Key = [0; AES_BLOCK_SIZE];
+        let is_valid = (0..AES_BLOCK_SIZE).all(|i| match u8::from_str_radix(&value[i * 2..i * 2 + 2], 16) {
+            Ok(byte) => {
+                key[i] = byte;
+                true
+            },
+            Err(_) => false
+        });
+        if is_valid {
+            keys.insert(name, key);
+        }
+    }
+
+    keys
+}
+
+pub fn initialize() {
+    let keyset_keys = match cfg::get_config().spl_use_keyset_keys {
+        true => load_keyset_keys(),
+        false => BTreeMap::new()
+    };
+    log_line!("Loaded {} keys from the keyset", keyset_keys.len());
+
+    unsafe {
+        *G_SECURE_MONITOR.lock() = Some(SecureMonitor {
+            keyset_keys: keyset_keys,
+            keyslots: [None; AES_KEYSLOT_COUNT],
+            allocated_keyslots: [false; AES_KEYSLOT_COUNT],
+            boot_reason: None,
+            is_charger_hiz_mode_enabled: false,
+            random_state: RANDOM_SEED
+        });
+    }
+}
+
+fn with_secure_monitor<T>(f: impl FnOnce(&mut SecureMonitor) -> Result<T>) -> Result<T> {
+    unsafe {
+        let mut secmon = G_SECURE_MONITOR.lock();
+        match secmon.as_mut() {
+            Some(secmon) => f(secmon),
+            None => spl_result::ResultSecureMonitorNotInitialized::make_err()
+        }
+    }
+}
+
+fn make_fake_key(name: &str) -> Key {
+    let mut hasher = Sha256::new();
+    hasher.update(FAKE_KEY_SEED.as_bytes());
+    hasher.update(name.as_bytes());
+    let hash = hasher.finalize();
+
+    let mut key: Key = [0; AES_BLOCK_SIZE];
+    key.copy_from_slice(&hash[..AES_BLOCK_SIZE]);
+    key
+}
+
+impl SecureMonitor {
+    // Keyset keys when available, otherwise deterministic fake ones (same name, same key)
+    fn get_key(&self, name: &str) -> Key {
+        match self.keyset_keys.get(name) {
+            Some(key) => *key,
+            None => make_fake_key(name)
+        }
+    }
+
+    fn get_master_key(&self, generation: u32) -> Result<Key> {
+        // Note: generations 0 and 1 both refer to the first master key
+        let master_key_idx = generation.max(1) as usize - 1;
+        result_return_unless!(master_key_idx < MASTER_KEY_COUNT, spl_result::ResultSecureMonitorInvalidArgument);
+
+        Ok(self.get_key(&format!("master_key_{:02x}", master_key_idx)))
+    }
+
+    fn check_keyslot(&self, keyslot: u32) -> Result<usize> {
+        result_return_unless!((keyslot as usize) < AES_KEYSLOT_COUNT, spl_result::ResultInvalidKeySlot);
+        Ok(keyslot as usize)
+    }
+}
+
+fn aes_decrypt_block(key: &Key, data: &Key) -> Key {
+    let cipher = Aes128::new(GenericArray::from_slice(key));
+    let mut block = GenericArray::clone_from_slice(data);
+    cipher.decrypt_block(&mut block);
+
+    let mut out: Key = [0; AES_BLOCK_SIZE];
+    out.copy_from_slice(&block);
+    out
+}
+
+fn aes_ctr_crypt(key: &Key, iv_ctr: &Key, in_data: &[u8], out_data: &mut [u8]) {
+    let cipher = Aes128::new(GenericArray::from_slice(key));
+    let mut ctr = u128::from_be_bytes(*iv_ctr);
+    for (in_block, out_block) in in_data.chunks(AES_BLOCK_SIZE).zip(out_data.chunks_mut(AES_BLOCK_SIZE)) {
+        let mut key_stream = GenericArray::clone_from_slice(&ctr.to_be_bytes());
+        cipher.encrypt_block(&mut key_stream);
+        for i in 0..in_block.len() {
+            out_block[i] = in_block[i] ^ key_stream[i];
+        }
+        ctr = ctr.wrapping_add(1);
+    }
+}
+
+fn generate_aes_kek_impl(secmon: &SecureMonitor, kek_source: &KeySource, generation: u32, option: u32) -> Result<AccessKey> {
+    // TODO: device-unique (option bit 0) and seal key type options
+    let _ = option;
+    let master_key = secmon.get_master_key(generation)?;
+    Ok(AccessKey { data: aes_decrypt_block(&master_key, &kek_source.data) })
+}
+
+fn generate_aes_key_impl(secmon: &SecureMonitor, access_key: &AccessKey, key_source: &KeySource) -> AesKey {
+    let generation_key = aes_decrypt_block(&access_key.data, &secmon.get_key("aes_key_generation_source"));
+    AesKey { data: aes_decrypt_block(&generation_key, &key_source.data) }
+}
+
+fn get_default_config(item: ConfigItem) -> u64 {
+    match item {
+        ConfigItem::SecurityEngineInterruptNumber => 0x2C,
+        ConfigItem::HardwareType => HardwareType::Icosa as u64,
+        ConfigItem::HardwareState => HardwareState::Production as u64,
+        // Note: fake device ID, constant so that anything derived from it (save data paths, etc.) stays the same across runs
+        ConfigItem::DeviceId => 0x0000_0000_5045_4741,
+        _ => 0
+    }
+}
+
+pub fn get_config(item: u32) -> Result<u64> {
+    let config_item = match ConfigItem::from(item) {
+        Some(config_item) => config_item,
+        None => return spl_result::ResultSecureMonitorInvalidArgument::make_err()
+    };
+    // Note: the package2 hash is only retrieved via GetPackage2Hash
+    result_return_if!(config_item == ConfigItem::Package2Hash, spl_result::ResultSecureMonitorInvalidArgument);
+
+    if let Some(value) = cfg::get_config().spl_config_overrides.get(&format!("{:?}", config_item)) {
+        return Ok(*value);
+    }
+
+    match config_item {
+        ConfigItem::IsChargerHiZModeEnabled => with_secure_monitor(|secmon| Ok(secmon.is_charger_hiz_mode_enabled as u64)),
+        _ => Ok(get_default_config(config_item))
+    }
+}
+
+pub fn set_config(item: u32, value: u64) -> Result<()> {
+    // Note: the secure monitor only allows setting the charger HiZ mode
+    result_return_unless!(ConfigItem::from(item) == Some(ConfigItem::IsChargerHiZModeEnabled), spl_result::ResultSecureMonitorInvalidArgument);
+
+    with_secure_monitor(|secmon| {
+        secmon.is_charger_hiz_mode_enabled = value != 0;
+        Ok(())
+    })
+}
+
+pub fn is_development() -> Result<bool> {
+    Ok(get_config(ConfigItem::HardwareState as u32)? == HardwareState::Development as u64)
+}
+
+// xorshift64*, deterministic so that emulation runs are reproducible
+pub fn generate_random_bytes(out_data: &mut [u8]) -> Result<()> {
+    with_secure_monitor(|secmon| {
+        for chunk in out_data.chunks_mut(8) {
+            secmon.random_state ^= secmon.random_state >> 12;
+            secmon.random_state ^= secmon.random_state << 25;
+            secmon.random_state ^= secmon.random_state >> 27;
+            let value = secmon.random_state.wrapping_mul(0x2545F4914F6CDD1D);
+            chunk.copy_from_slice(&value.to_le_bytes()[..chunk.len()]);
+        }
+        Ok(())
+    })
+}
+
+pub fn generate_aes_kek(kek_source: KeySource, generation: u32, option: u32) -> Result<AccessKey> {
+    with_secure_monitor(|secmon| generate_aes_kek_impl(secmon, &kek_source, generation, option))
+}
+
+pub fn load_aes_key(keyslot: u32, access_key: AccessKey, key_source: KeySource) -> Result<()> {
+    with_secure_monitor(|secmon| {
+        let keyslot_idx = secmon.check_keyslot(keyslot)?;
+        secmon.keyslots[keyslot_idx] = Some(aes_decrypt_block(&access_key.data, &key_source.data));
+        Ok(())
+    })
+}
+
+pub fn generate_aes_key(access_key: AccessKey, key_source: KeySource) -> Result<AesKey> {
+    with_secure_monitor(|secmon| Ok(generate_aes_key_impl(secmon, &access_key, &key_source)))
+}
+
+pub fn decrypt_aes_key(key_source: KeySource, generation: u32, option: u32) -> Result<AesKey> {
+    with_secure_monitor(|secmon| {
+        let kek_source = KeySource { data: secmon.get_key("aes_kek_generation_source") };
+        let access_key = generate_aes_kek_impl(secmon, &kek_source, generation, option)?;
+        Ok(generate_aes_key_impl(secmon, &access_key, &key_source))
+    })
+}
+
+pub fn compute_ctr(keyslot: u32, iv_ctr: IvCtr, in_data: &[u8], out_data: &mut [u8]) -> Result<()> {
+    result_return_unless!(in_data.len() == out_data.len(), spl_result::ResultInvalidSize);
+
+    with_secure_monitor(|secmon| {
+        let keyslot_idx = secmon.check_keyslot(keyslot)?;
+        // Note: unloaded keyslots behave like zeroed ones
+        let key = secmon.keyslots[keyslot_idx].unwrap_or([0; AES_BLOCK_SIZE]);
+        aes_ctr_crypt(&key, &iv_ctr.data, in_data, out_data);
+        Ok(())
+    })
+}
+
+pub fn allocate_aes_keyslot() -> Result<u32> {
+    with_secure_monitor(|secmon| {
+        match secmon.allocated_keyslots.iter().position(|allocated| !allocated) {
+            Some(keyslot_idx) => {
+                secmon.allocated_keyslots[keyslot_idx] = true;
+                Ok(keyslot_idx as u32)
+            },
+            None => spl_result::ResultOutOfKeySlots::make_err()
+        }
+    })
+}
+
+pub fn deallocate_aes_keyslot(keyslot: u32) -> Result<()> {
+    with_secure_monitor(|secmon| {
+        let keyslot_idx = secmon.check_keyslot(keyslot)?;
+        result_return_unless!(secmon.allocated_keyslots[keyslot_idx], spl_result::ResultInvalidKeySlot);
+
+        secmon.allocated_keyslots[keyslot_idx] = false;
+        secmon.keyslots[keyslot_idx] = None;
+        Ok(())
+    })
+}
+
+pub fn set_boot_reason(boot_reason: BootReasonValue) -> Result<()> {
+    with_secure_monitor(|secmon| {
+        result_return_if!(secmon.boot_reason.is_some(), spl_result::ResultBootReasonAlreadySet);
+
+        secmon.boot_reason = Some(boot_reason);
+        Ok(())
+    })
+}
+
+pub fn get_boot_reason() -> Result<BootReasonValue> {
+    with_secure_monitor(|secmon| {
+        match secmon.boot_reason {
+            Some(boot_reason) => Ok(boot_reason),
+            None => spl_result::ResultBootReasonNotSet::make_err()
+        }
+    })
+}
@@ -87,11 +87,31 @@ impl ResultCode {
     pub const fn get_description(&self) -> u32 {
         unpack_description(self.value)
     }
+
+    // Human-readable description, like "fs: path not found", see the result registry below
+    pub fn describe(&self) -> String {
+        if self.is_success() {
+            return String::from("success");
+        }
+
+        let module = self.get_module();
+        let description = self.get_description();
+        match find_result_module(module) {
+            Some(module_info) => match (module_info.get_result_name)(description) {
+                Some(result_name) => format!("{}: {}", module_info.name, humanize_result_name(result_name)),
+                None => format!("{}: unknown result", module_info.name)
+            },
+            None => match find_official_module_name(module) {
+                Some(module_name) => format!("{}: unknown result", module_name),
+                None => format!("unknown module {}", module)
+            }
+        }
+    }
 }
 
 impl fmt::Debug for ResultCode {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
-        write!(fmt, "{:#X}", self.value)
+        write!(fmt, "{:#X}, {}", self.value, self.describe())
     }
 }
 
@@ -119,9 +139,33 @@ macro_rules! result_define {
     };
 }
 
+// Every group also defines the name lookup used by the result registry (see result_register_modules)
 macro_rules! result_define_group {
     ($module:expr => { $( $name:ident: $description:expr ),* }) => {
         $( result_define!($name: $module, $description); )*
+
+        pub fn get_result_name(description: u32) -> Option<&'static str> {
+            $(
+                if description == $description {
+                    return Some(stringify!($name));
+                }
+            )*
+            None
+        }
+    };
+}
+
+macro_rules! result_register_modules {
+    ($( $result_mod:path => $name:literal ),*) => {
+        const RESULT_MODULES: &[ResultModuleInfo] = &[
+            $(
+                ResultModuleInfo {
+                    module: { use $result_mod as result_mod; result_mod::RESULT_MODULE },
+                    name: $name,
+                    get_result_name: { use $result_mod as result_mod; result_mod::get_result_name }
+                }
+            ),*
+        ];
     };
 }
 
@@ -172,6 +216,157 @@ impl<T: Copy> ResultExt<T> for Result<T> {
     }
 }
 
+// Host-side errors: a result code plus the context it happened in (outermost first), so that setup failures (config, keys, contents...) can be properly reported
+// Note: only the result code itself ever reaches guests, through the From conversion below (thus '?' works from host results inside regular ones)
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Error {
+    rc: ResultCode,
+    contexts: Vec<String>
+}
+
+pub type HostResult<T> = result::Result<T, Error>;
+
+impl Error {
+    pub const fn new(rc: ResultCode) -> Self {
+        Self {
+            rc: rc,
+            contexts: Vec::new()
+        }
+    }
+
+    pub fn with_message(rc: ResultCode, msg: String) -> Self {
+        Self {
+            rc: rc,
+            contexts: vec![msg]
+        }
+    }
+
+    #[inline]
+    pub const fn get_result(&self) -> ResultCode {
+        self.rc
+    }
+
+    pub fn get_contexts(&self) -> &[String] {
+        &self.contexts
+    }
+
+    pub fn add_context(mut self, context: String) -> Self {
+        self.contexts.insert(0, context);
+        self
+    }
+}
+
+impl From<ResultCode> for Error {
+    fn from(rc: ResultCode) -> Self {
+        Self::new(rc)
+    }
+}
+
+impl From<Error> for ResultCode {
+    fn from(err: Error) -> Self {
+        err.rc
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
+        for context in self.contexts.iter() {
+            write!(fmt, "{}: ", context)?;
+        }
+        write!(fmt, "{0} ({0:?})", self.rc)
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> result::Result<(), fmt::Error> {
+        fmt::Display::fmt(self, fmt)
+    }
+}
+
+pub trait ResultContext<T> {
+    fn with_context<S: Into<String>, F: FnOnce() -> S>(self, f: F) -> HostResult<T>;
+
+    #[inline]
+    fn context<S: Into<String>>(self, context: S) -> HostResult<T> where Self: Sized {
+        self.with_context(|| context)
+    }
+}
+
+impl<T> ResultContext<T> for Result<T> {
+    fn with_context<S: Into<String>, F: FnOnce() -> S>(self, f: F) -> HostResult<T> {
+        self.map_err(|rc| Error::new(rc).add_context(f().into()))
+    }
+}
+
+impl<T> ResultContext<T> for HostResult<T> {
+    fn with_context<S: Into<String>, F: FnOnce() -> S>(self, f: F) -> HostResult<T> {
+        self.map_err(|err| err.add_context(f().into()))
+    }
+}
+
+// Result registry
+
+pub struct ResultModuleInfo {
+    pub module: u32,
+    pub name: &'static str,
+    pub get_result_name: fn(u32) -> Option<&'static str>
+}
+
+result_register_modules! {
+    crate::kern::result => "kern",
+    crate::fs::result => "fs",
+    crate::ncm::result => "ncm",
+    crate::ldr::result => "ldr",
+    crate::ipc::cmif::result => "sf",
+    crate::ipc::result => "hipc",
+    crate::sm::result => "sm",
+    crate::spl::result => "spl",
+    crate::account::result => "account",
+    crate::applet::result => "am",
+    crate::nifm::result => "nifm",
+    crate::time::result => "time",
+    crate::result => "pegasus",
+    crate::emu::cpu::result => "pegasus::cpu",
+    crate::proc::result => "pegasus::proc"
+}
+
+// Official modules without any results defined here, so that their results are at least told apart
+// Note: https://switchbrew.org/wiki/Error_codes
+const OFFICIAL_MODULE_NAMES: &[(u32, &str)] = &[
+    (3, "os"),
+    (8, "lr"),
+    (15, "pm"),
+    (16, "ns"),
+    (22, "ro"),
+    (105, "settings"),
+    (138, "pctl"),
+    (147, "audio"),
+    (153, "hid")
+];
+
+pub fn find_result_module(module: u32) -> Option<&'static ResultModuleInfo> {
+    RESULT_MODULES.iter().find(|module_info| module_info.module == module)
+}
+
+fn find_official_module_name(module: u32) -> Option<&'static str> {
+    OFFICIAL_MODULE_NAMES.iter().find(|(official_module, _)| *official_module == module).map(|(_, name)| *name)
+}
+
+// "PathNotFound" -> "path not found"
+fn humanize_result_name(name: &str) -> String {
+    let mut human_name = String::with_capacity(name.len() + 8);
+    let mut prev_ch: Option<char> = None;
+    for ch in name.chars() {
+        if ch.is_ascii_uppercase() && prev_ch.map(|prev_ch| prev_ch.is_ascii_lowercase() || prev_ch.is_ascii_digit()).unwrap_or(false) {
+            human_name.push(' ');
+        }
+        human_name.push(ch.to_ascii_lowercase());
+        prev_ch = Some(ch);
+    }
+    human_name
+}
+
 // Results
 
 pub const RESULT_MODULE: u32 = 503;
@@ -181,5 +376,12 @@ result_define_group!(RESULT_MODULE => {
     InvalidCast: 2,
     ReadOutOfBounds: 3,
     InvalidUtf8String: 4,
-    InvalidJson: 5
+    InvalidJson: 5,
+    WriteOutOfBounds: 6,
+    InvalidKeyset: 7,
+    InvalidFontData: 8,
+    InvalidControlData: 9,
+    EmulatorAlreadyCreated: 10,
+    InvalidInputRecording: 11,
+    NoRecordedServiceCall: 12
 });
\ No newline at end of file
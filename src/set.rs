@@ -1,3 +1,4 @@
+use serde::{Serialize, Deserialize};
 use crate::util::CString;
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
@@ -15,4 +16,82 @@ pub struct FirmwareVersion {
     pub version_hash: CString<0x40>,
     pub display_version: CString<0x18>,
     pub display_title: CString<0x80>
-}
\ No newline at end of file
+}
+
+// Language codes are the language's name (like "en-US") packed into a u64, nul-padded
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[repr(C)]
+pub struct LanguageCode(pub u64);
+
+impl LanguageCode {
+    pub fn from_str(name: &str) -> Self {
+        let mut raw_code = [0u8; 8];
+        let name_len = name.len().min(raw_code.len());
+        raw_code[..name_len].copy_from_slice(&name.as_bytes()[..name_len]);
+        Self(u64::from_le_bytes(raw_code))
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum Language {
+    Japanese,
+    AmericanEnglish,
+    French,
+    German,
+    Italian,
+    Spanish,
+    Chinese,
+    Korean,
+    Dutch,
+    Portuguese,
+    Russian,
+    Taiwanese,
+    BritishEnglish,
+    CanadianFrench,
+    LatinAmericanSpanish,
+    SimplifiedChinese,
+    TraditionalChinese,
+    BrazilianPortuguese
+}
+
+impl Language {
+    pub const fn get_name(self) -> &'static str {
+        match self {
+            Self::Japanese => "ja",
+            Self::AmericanEnglish => "en-US",
+            Self::French => "fr",
+            Self::German => "de",
+            Self::Italian => "it",
+            Self::Spanish => "es",
+            Self::Chinese => "zh-CN",
+            Self::Korean => "ko",
+            Self::Dutch => "nl",
+            Self::Portuguese => "pt",
+            Self::Russian => "ru",
+            Self::Taiwanese => "zh-TW",
+            Self::BritishEnglish => "en-GB",
+            Self::CanadianFrench => "fr-CA",
+            Self::LatinAmericanSpanish => "es-419",
+            Self::SimplifiedChinese => "zh-Hans",
+            Self::TraditionalChinese => "zh-Hant",
+            Self::BrazilianPortuguese => "pt-BR"
+        }
+    }
+
+    pub fn get_language_code(self) -> LanguageCode {
+        LanguageCode::from_str(self.get_name())
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[repr(i32)]
+pub enum RegionCode {
+    Japan = 0,
+    Usa = 1,
+    Europe = 2,
+    Australia = 3,
+    HongKongTaiwanKorea = 4,
+    China = 5
+}
+
+pub type DeviceNickName = CString<0x80>;
@@ -0,0 +1,102 @@
+pub mod result;
+
+pub const AES_BLOCK_SIZE: usize = 0x10;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[repr(u32)]
+pub enum ConfigItem {
+    DisableProgramVerification = 1,
+    DramId = 2,
+    SecurityEngineInterruptNumber = 3,
+    FuseVersion = 4,
+    HardwareType = 5,
+    HardwareState = 6,
+    IsRecoveryBoot = 7,
+    DeviceId = 8,
+    BootReason = 9,
+    MemoryMode = 10,
+    IsDevelopmentFunctionEnabled = 11,
+    KernelConfiguration = 12,
+    IsChargerHiZModeEnabled = 13,
+    QuestState = 14,
+    RegulatorType = 15,
+    DeviceUniqueKeyGeneration = 16,
+    Package2Hash = 17
+}
+
+impl ConfigItem {
+    pub const fn from(raw: u32) -> Option<Self> {
+        match raw {
+            1 => Some(Self::DisableProgramVerification),
+            2 => Some(Self::DramId),
+            3 => Some(Self::SecurityEngineInterruptNumber),
+            4 => Some(Self::FuseVersion),
+            5 => Some(Self::HardwareType),
+            6 => Some(Self::HardwareState),
+            7 => Some(Self::IsRecoveryBoot),
+            8 => Some(Self::DeviceId),
+            9 => Some(Self::BootReason),
+            10 => Some(Self::MemoryMode),
+            11 => Some(Self::IsDevelopmentFunctionEnabled),
+            12 => Some(Self::KernelConfiguration),
+            13 => Some(Self::IsChargerHiZModeEnabled),
+            14 => Some(Self::QuestState),
+            15 => Some(Self::RegulatorType),
+            16 => Some(Self::DeviceUniqueKeyGeneration),
+            17 => Some(Self::Package2Hash),
+            _ => None
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u64)]
+pub enum HardwareType {
+    Icosa = 0,
+    Copper = 1,
+    Hoag = 2,
+    Iowa = 3,
+    Calcio = 4,
+    Aula = 5
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u64)]
+pub enum HardwareState {
+    Development = 0,
+    Production = 1
+}
+
+// Sealed/unsealed KEKs (the secure monitor hands these out instead of raw keys)
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[repr(C)]
+pub struct AccessKey {
+    pub data: [u8; AES_BLOCK_SIZE]
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[repr(C)]
+pub struct KeySource {
+    pub data: [u8; AES_BLOCK_SIZE]
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[repr(C)]
+pub struct AesKey {
+    pub data: [u8; AES_BLOCK_SIZE]
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[repr(C)]
+pub struct IvCtr {
+    pub data: [u8; AES_BLOCK_SIZE]
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[repr(C)]
+pub struct BootReasonValue {
+    pub power_intr: u8,
+    pub rtc_intr: u8,
+    pub nv_erc: u8,
+    pub boot_reason: u8
+}
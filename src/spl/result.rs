@@ -0,0 +1,19 @@
+pub const RESULT_MODULE: u32 = 26;
+
+result_define_group!(RESULT_MODULE => {
+    SecureMonitorNotImplemented: 1,
+    SecureMonitorInvalidArgument: 2,
+    SecureMonitorBusy: 3,
+    SecureMonitorNoAsyncOperation: 4,
+    SecureMonitorInvalidAsyncOperation: 5,
+    SecureMonitorNotPermitted: 6,
+    SecureMonitorNotInitialized: 7,
+    InvalidSize: 100,
+    UnknownSecureMonitorError: 101,
+    DecryptionFailed: 102,
+    OutOfKeySlots: 104,
+    InvalidKeySlot: 105,
+    BootReasonAlreadySet: 106,
+    BootReasonNotSet: 107,
+    InvalidArgument: 108
+});
@@ -0,0 +1,663 @@
+use std::fs::read;
+use std::mem;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use cntx::nca::ContentType;
+use crate::emu::cfg;
+use crate::fs::{cache, FileOpenMode, ReadOption};
+use crate::kern::{get_system_tick, SYSTEM_TICK_FREQUENCY};
+use crate::ncm::{ProgramId, StorageId};
+use crate::util::{CString, convert_io_result};
+use crate::result::*;
+
+pub mod result;
+
+// Note: https://switchbrew.org/wiki/Glue_services#Time_services
+
+// Which of the time:* services, all of them being the same interface with different permissions
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum StaticServiceKind {
+    User,
+    Admin,
+    System
+}
+
+// Like "Europe/Madrid"
+pub type LocationName = CString<0x24>;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[repr(C)]
+pub struct CalendarTime {
+    pub year: i16,
+    pub month: i8,
+    pub day: i8,
+    pub hour: i8,
+    pub minute: i8,
+    pub second: i8,
+    pub pad: i8
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[repr(C)]
+pub struct CalendarAdditionalInfo {
+    // 0 being Sunday
+    pub day_of_week: u32,
+    // 0 being January 1st
+    pub day_of_year: u32,
+    pub time_zone_name: CString<0x8>,
+    pub is_dst: u32,
+    pub utc_offset: i32
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+#[repr(C)]
+pub struct SteadyClockTimePoint {
+    pub time_point: i64,
+    pub source_id: [u8; 0x10]
+}
+
+// Steady clock time points are seconds since boot, and there's a single (fixed) clock source
+const STEADY_CLOCK_SOURCE_ID: [u8; 0x10] = *b"pegasus-steady-0";
+
+pub fn get_steady_clock_time_point() -> SteadyClockTimePoint {
+    SteadyClockTimePoint {
+        time_point: (get_system_tick() / SYSTEM_TICK_FREQUENCY) as i64,
+        source_id: STEADY_CLOCK_SOURCE_ID
+    }
+}
+
+// System clocks (user, local and network ones) just follow the host's clock
+pub fn get_current_posix_time() -> i64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as i64,
+        Err(err) => -(err.duration().as_secs() as i64)
+    }
+}
+
+// Time zone rules are opaque to guests, which just pass them back to ToCalendarTime and similar commands
+// Thus they're stored in the guest's buffer in our own format rather than in the console's one
+pub const TIME_ZONE_RULE_SIZE: usize = 0x4000;
+const TIME_ZONE_RULE_MAGIC: u32 = u32::from_le_bytes(*b"PGTZ");
+const MAX_TRANSITION_COUNT: usize = 1000;
+const MAX_TIME_TYPE_COUNT: usize = 128;
+const TIME_TYPE_ABBREVIATION_SIZE: usize = 8;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TimeType {
+    pub utc_offset: i32,
+    pub is_dst: bool,
+    pub abbreviation: String
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TimeZoneRule {
+    // Transition times along with the time type they switch to, sorted by time
+    pub transitions: Vec<(i64, usize)>,
+    pub types: Vec<TimeType>
+}
+
+// TZif (tzdata binary) format, as found in both the system archive and host zoneinfo directories
+const TZIF_MAGIC: &[u8] = b"TZif";
+const TZIF_HEADER_SIZE: usize = 0x2C;
+
+struct TzifHeader {
+    version: u8,
+    is_ut_count: usize,
+    is_std_count: usize,
+    leap_count: usize,
+    time_count: usize,
+    type_count: usize,
+    char_count: usize
+}
+
+impl TzifHeader {
+    fn parse(data: &[u8]) -> Option<Self> {
+        if data.get(..TZIF_MAGIC.len())? != TZIF_MAGIC {
+            return None;
+        }
+
+        let read_count = |idx: usize| read_u32_be(data, 0x14 + idx * mem::size_of::<u32>()).map(|count| count as usize);
+        Some(Self {
+            version: *data.get(TZIF_MAGIC.len())?,
+            is_ut_count: read_count(0)?,
+            is_std_count: read_count(1)?,
+            leap_count: read_count(2)?,
+            time_count: read_count(3)?,
+            type_count: read_count(4)?,
+            char_count: read_count(5)?
+        })
+    }
+
+    fn get_data_size(&self, time_size: usize) -> usize {
+        self.time_count * time_size + self.time_count + self.type_count * 6 + self.char_count + self.leap_count * (time_size + 4) + self.is_std_count + self.is_ut_count
+    }
+}
+
+fn read_u32_be(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + mem::size_of::<u32>())?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_i64_be(data: &[u8], offset: usize) -> Option<i64> {
+    let bytes = data.get(offset..offset + mem::size_of::<i64>())?;
+    let mut raw = [0u8; 8];
+    raw.copy_from_slice(bytes);
+    Some(i64::from_be_bytes(raw))
+}
+
+fn parse_tzif(data: &[u8]) -> Option<TimeZoneRule> {
+    let v1_header = TzifHeader::parse(data)?;
+
+    // Version 2+ files have a second header and data block with 64-bit times, past the (legacy) 32-bit one
+    let (header, data_offset, time_size) = match v1_header.version {
+        0 => (v1_header, TZIF_HEADER_SIZE, mem::size_of::<u32>()),
+        _ => {
+            let v2_header_offset = TZIF_HEADER_SIZE + v1_header.get_data_size(mem::size_of::<u32>());
+            (TzifHeader::parse(data.get(v2_header_offset..)?)?, v2_header_offset + TZIF_HEADER_SIZE, mem::size_of::<i64>())
+        }
+    };
+    if (header.type_count == 0) || (header.type_count > MAX_TIME_TYPE_COUNT) {
+        return None;
+    }
+
+    let type_idxs_offset = data_offset + header.time_count * time_size;
+    let types_offset = type_idxs_offset + header.time_count;
+    let chars_offset = types_offset + header.type_count * 6;
+    let chars = data.get(chars_offset..chars_offset + header.char_count)?;
+
+    let mut types: Vec<TimeType> = Vec::with_capacity(header.type_count);
+    for i in 0..header.type_count {
+        let type_offset = types_offset + i * 6;
+        let abbreviation_idx = *data.get(type_offset + 5)? as usize;
+        let abbreviation = chars.get(abbreviation_idx..)?.split(|c| *c == 0).next()?;
+        types.push(TimeType {
+            utc_offset: read_u32_be(data, type_offset)? as i32,
+            is_dst: *data.get(type_offset + 4)? != 0,
+            abbreviation: String::from_utf8_lossy(abbreviation).into_owned()
+        });
+    }
+
+    let mut transitions: Vec<(i64, usize)> = Vec::with_capacity(header.time_count);
+    for i in 0..header.time_count {
+        let time = match time_size {
+            4 => read_u32_be(data, data_offset + i * time_size)? as i32 as i64,
+            _ => read_i64_be(data, data_offset + i * time_size)?
+        };
+        let type_idx = *data.get(type_idxs_offset + i)? as usize;
+        if type_idx >= types.len() {
+            return None;
+        }
+        transitions.push((time, type_idx));
+    }
+
+    // Version 2+ files end with a POSIX TZ string describing times past the last transition (which is all there is for recent years in "slim" tzdata)
+    if header.version != 0 {
+        let footer_offset = data_offset + header.get_data_size(time_size);
+        let footer = data.get(footer_offset..)?;
+        let tz_string = String::from_utf8_lossy(footer);
+        if let Some(posix_tz) = parse_posix_tz(tz_string.trim_matches('\n')) {
+            expand_posix_tz(&posix_tz, &mut transitions, &mut types);
+        }
+    }
+
+    // Only the most recent transitions are kept if there are too many (hardly the case with actual tzdata)
+    if transitions.len() > MAX_TRANSITION_COUNT {
+        transitions = transitions.split_off(transitions.len() - MAX_TRANSITION_COUNT);
+    }
+
+    Some(TimeZoneRule {
+        transitions: transitions,
+        types: types
+    })
+}
+
+// POSIX TZ strings, like "CET-1CEST,M3.5.0,M10.5.0/3"
+// Note: https://pubs.opengroup.org/onlinepubs/9699919799/basedefs/V1_chap08.html
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum PosixTzDate {
+    // Jn: day 1-365, February 29th never being counted
+    JulianNoLeap(i64),
+    // n: day 0-365, counting February 29th
+    Julian(i64),
+    // Mm.w.d: day d (0 being Sunday) of week w (5 being the last one) of month m
+    MonthWeekDay(i64, i64, i64)
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+struct PosixTzRule {
+    date: PosixTzDate,
+    // Seconds since the local midnight
+    time: i64
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct PosixTz {
+    std_type: TimeType,
+    // The DST type along with the rules for when it starts and ends
+    dst: Option<(TimeType, PosixTzRule, PosixTzRule)>
+}
+
+// Transitions are generated from the footer up to this year, like tzdata's "fat" binaries (which stop at 2037) but somewhat further
+const POSIX_TZ_EXPANSION_END_YEAR: i64 = 2100;
+
+struct PosixTzParser<'a> {
+    tz: &'a [u8],
+    pos: usize
+}
+
+impl<'a> PosixTzParser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.tz.get(self.pos).copied()
+    }
+
+    fn consume(&mut self, c: u8) -> bool {
+        let is_next = self.peek() == Some(c);
+        if is_next {
+            self.pos += 1;
+        }
+        is_next
+    }
+
+    fn parse_number(&mut self) -> Option<i64> {
+        let start = self.pos;
+        while self.peek().map(|c| c.is_ascii_digit()).unwrap_or(false) {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.tz[start..self.pos]).ok()?.parse::<i64>().ok()
+    }
+
+    // Either alphabetic or quoted ("<+03>")
+    fn parse_name(&mut self) -> Option<String> {
+        let start = self.pos;
+        let name = match self.consume(b'<') {
+            true => {
+                while self.peek()? != b'>' {
+                    self.pos += 1;
+                }
+                self.pos += 1;
+                &self.tz[start + 1..self.pos - 1]
+            },
+            false => {
+                while self.peek().map(|c| c.is_ascii_alphabetic()).unwrap_or(false) {
+                    self.pos += 1;
+                }
+                &self.tz[start..self.pos]
+            }
+        };
+
+        match name.is_empty() {
+            true => None,
+            false => Some(String::from_utf8_lossy(name).into_owned())
+        }
+    }
+
+    // [+-]hh[:mm[:ss]], as seconds
+    fn parse_time(&mut self) -> Option<i64> {
+        let sign = match self.peek() {
+            Some(b'-') => {
+                self.pos += 1;
+                -1
+            },
+            Some(b'+') => {
+                self.pos += 1;
+                1
+            },
+            _ => 1
+        };
+
+        let mut seconds = self.parse_number()? * 3600;
+        if self.consume(b':') {
+            seconds += self.parse_number()? * 60;
+            if self.consume(b':') {
+                seconds += self.parse_number()?;
+            }
+        }
+        Some(sign * seconds)
+    }
+
+    fn parse_rule(&mut self) -> Option<PosixTzRule> {
+        let date = match self.peek()? {
+            b'J' => {
+                self.pos += 1;
+                PosixTzDate::JulianNoLeap(self.parse_number()?)
+            },
+            b'M' => {
+                self.pos += 1;
+                let month = self.parse_number()?;
+                if !self.consume(b'.') {
+                    return None;
+                }
+                let week = self.parse_number()?;
+                if !self.consume(b'.') {
+                    return None;
+                }
+                PosixTzDate::MonthWeekDay(month, week, self.parse_number()?)
+            },
+            _ => PosixTzDate::Julian(self.parse_number()?)
+        };
+
+        // Transitions happen at 02:00:00 unless specified
+        let time = match self.consume(b'/') {
+            true => self.parse_time()?,
+            false => 2 * 3600
+        };
+        Some(PosixTzRule {
+            date: date,
+            time: time
+        })
+    }
+}
+
+fn parse_posix_tz(tz_string: &str) -> Option<PosixTz> {
+    let mut parser = PosixTzParser {
+        tz: tz_string.as_bytes(),
+        pos: 0
+    };
+
+    // POSIX offsets are the ones to add to local time to get UTC, thus the opposite of UTC offsets
+    let std_name = parser.parse_name()?;
+    let std_type = TimeType {
+        utc_offset: -parser.parse_time()? as i32,
+        is_dst: false,
+        abbreviation: std_name
+    };
+    if parser.peek().is_none() {
+        return Some(PosixTz {
+            std_type: std_type,
+            dst: None
+        });
+    }
+
+    // DST is an hour ahead unless specified, and follows the US rules unless specified
+    let dst_name = parser.parse_name()?;
+    let dst_utc_offset = match parser.peek() {
+        Some(b',') | None => std_type.utc_offset + 3600,
+        _ => -parser.parse_time()? as i32
+    };
+    let dst_type = TimeType {
+        utc_offset: dst_utc_offset,
+        is_dst: true,
+        abbreviation: dst_name
+    };
+    let (start_rule, end_rule) = match parser.consume(b',') {
+        true => {
+            let start_rule = parser.parse_rule()?;
+            if !parser.consume(b',') {
+                return None;
+            }
+            (start_rule, parser.parse_rule()?)
+        },
+        false => (PosixTzRule { date: PosixTzDate::MonthWeekDay(3, 2, 0), time: 2 * 3600 }, PosixTzRule { date: PosixTzDate::MonthWeekDay(11, 1, 0), time: 2 * 3600 })
+    };
+
+    Some(PosixTz {
+        std_type: std_type,
+        dst: Some((dst_type, start_rule, end_rule))
+    })
+}
+
+// Days since 1970-01-01 (in local time) of the day the rule applies on the given year
+fn get_posix_tz_rule_day(rule: &PosixTzRule, year: i64) -> i64 {
+    let year_start_day = days_from_civil(year, 1, 1);
+    match rule.date {
+        PosixTzDate::JulianNoLeap(day) => year_start_day + day - 1 + if is_leap_year(year) && (day >= 60) { 1 } else { 0 },
+        PosixTzDate::Julian(day) => year_start_day + day,
+        PosixTzDate::MonthWeekDay(month, week, week_day) => {
+            let month_start_day = days_from_civil(year, month, 1);
+            // 1970-01-01 was a Thursday
+            let month_start_week_day = (month_start_day + 4).rem_euclid(7);
+            let mut month_day = (week_day - month_start_week_day).rem_euclid(7) + (week - 1) * 7;
+            // The fifth week means the last one, which might actually be the fourth
+            let month_day_count = days_from_civil(year + month / 12, month % 12 + 1, 1) - month_start_day;
+            while month_day >= month_day_count {
+                month_day -= 7;
+            }
+            month_start_day + month_day
+        }
+    }
+}
+
+fn find_or_add_time_type(types: &mut Vec<TimeType>, time_type: &TimeType) -> usize {
+    match types.iter().position(|cur_type| cur_type == time_type) {
+        Some(type_idx) => type_idx,
+        None => {
+            types.push(time_type.clone());
+            types.len() - 1
+        }
+    }
+}
+
+// Turns the footer rules into actual transitions past the last one
+fn expand_posix_tz(posix_tz: &PosixTz, transitions: &mut Vec<(i64, usize)>, types: &mut Vec<TimeType>) {
+    let (dst_type, start_rule, end_rule) = match posix_tz.dst.as_ref() {
+        Some(dst) => dst,
+        // Without DST, the last transition's type (or the only type) already applies forever
+        None => return
+    };
+    if types.len() + 2 > MAX_TIME_TYPE_COUNT {
+        return;
+    }
+
+    let std_type_idx = find_or_add_time_type(types, &posix_tz.std_type);
+    let dst_type_idx = find_or_add_time_type(types, dst_type);
+    let last_time = transitions.last().map(|(time, _)| *time).unwrap_or(i64::MIN);
+    let first_year = match transitions.last() {
+        Some((time, _)) => civil_from_days(time.div_euclid(SECONDS_PER_DAY)).0,
+        None => 1970
+    };
+
+    for year in first_year..=POSIX_TZ_EXPANSION_END_YEAR {
+        // Rule times are in the local time in effect before each transition
+        let start_time = get_posix_tz_rule_day(start_rule, year) * SECONDS_PER_DAY + start_rule.time - posix_tz.std_type.utc_offset as i64;
+        let end_time = get_posix_tz_rule_day(end_rule, year) * SECONDS_PER_DAY + end_rule.time - dst_type.utc_offset as i64;
+
+        // In the southern hemisphere DST ends before it starts within a year
+        let mut year_transitions = [(start_time, dst_type_idx), (end_time, std_type_idx)];
+        year_transitions.sort_by_key(|(time, _)| *time);
+        for transition in year_transitions.iter() {
+            if transition.0 > last_time {
+                transitions.push(*transition);
+            }
+        }
+    }
+}
+
+// (year, month, day) in the proleptic Gregorian calendar to days since 1970-01-01
+// Note: http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// Days since 1970-01-01 to (year, month, day) in the proleptic Gregorian calendar (the inverse of the above)
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn is_leap_year(year: i64) -> bool {
+    ((year % 4) == 0) && (((year % 100) != 0) || ((year % 400) == 0))
+}
+
+const DAYS_BEFORE_MONTH: [i64; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+impl TimeZoneRule {
+    pub fn utc() -> Self {
+        Self {
+            transitions: Vec::new(),
+            types: vec![
+                TimeType {
+                    utc_offset: 0,
+                    is_dst: false,
+                    abbreviation: String::from("UTC")
+                }
+            ]
+        }
+    }
+
+    pub fn from_tzif(data: &[u8]) -> Result<Self> {
+        parse_tzif(data).ok_or(result::ResultTimeZoneConversionFailed::make())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::with_capacity(TIME_ZONE_RULE_SIZE);
+        data.extend_from_slice(&TIME_ZONE_RULE_MAGIC.to_le_bytes());
+        data.extend_from_slice(&(self.transitions.len() as u32).to_le_bytes());
+        data.extend_from_slice(&(self.types.len() as u32).to_le_bytes());
+        for (time, type_idx) in self.transitions.iter() {
+            data.extend_from_slice(&time.to_le_bytes());
+            data.push(*type_idx as u8);
+        }
+        for time_type in self.types.iter() {
+            data.extend_from_slice(&time_type.utc_offset.to_le_bytes());
+            data.push(time_type.is_dst as u8);
+            let mut abbreviation = [0u8; TIME_TYPE_ABBREVIATION_SIZE];
+            let abbreviation_len = time_type.abbreviation.len().min(TIME_TYPE_ABBREVIATION_SIZE - 1);
+            abbreviation[..abbreviation_len].copy_from_slice(&time_type.abbreviation.as_bytes()[..abbreviation_len]);
+            data.extend_from_slice(&abbreviation);
+        }
+
+        data.resize(TIME_ZONE_RULE_SIZE, 0);
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let read_u32_le = |offset: usize| data.get(offset..offset + mem::size_of::<u32>()).map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+        let invalid_rule = result::ResultTimeZoneConversionFailed::make();
+
+        result_return_unless!(read_u32_le(0) == Some(TIME_ZONE_RULE_MAGIC), result::ResultTimeZoneConversionFailed);
+        let transition_count = read_u32_le(4).ok_or(invalid_rule)? as usize;
+        let type_count = read_u32_le(8).ok_or(invalid_rule)? as usize;
+        result_return_unless!((transition_count <= MAX_TRANSITION_COUNT) && (type_count > 0) && (type_count <= MAX_TIME_TYPE_COUNT), result::ResultTimeZoneConversionFailed);
+
+        let mut offset = 3 * mem::size_of::<u32>();
+        let mut transitions: Vec<(i64, usize)> = Vec::with_capacity(transition_count);
+        for _ in 0..transition_count {
+            let time_bytes = data.get(offset..offset + mem::size_of::<i64>()).ok_or(invalid_rule)?;
+            let mut raw_time = [0u8; 8];
+            raw_time.copy_from_slice(time_bytes);
+            let type_idx = *data.get(offset + mem::size_of::<i64>()).ok_or(invalid_rule)? as usize;
+            result_return_unless!(type_idx < type_count, result::ResultTimeZoneConversionFailed);
+            transitions.push((i64::from_le_bytes(raw_time), type_idx));
+            offset += mem::size_of::<i64>() + 1;
+        }
+
+        let mut types: Vec<TimeType> = Vec::with_capacity(type_count);
+        for _ in 0..type_count {
+            let utc_offset = read_u32_le(offset).ok_or(invalid_rule)? as i32;
+            let is_dst = *data.get(offset + mem::size_of::<u32>()).ok_or(invalid_rule)? != 0;
+            let abbreviation_offset = offset + mem::size_of::<u32>() + 1;
+            let abbreviation = data.get(abbreviation_offset..abbreviation_offset + TIME_TYPE_ABBREVIATION_SIZE).ok_or(invalid_rule)?;
+            types.push(TimeType {
+                utc_offset: utc_offset,
+                is_dst: is_dst,
+                abbreviation: String::from_utf8_lossy(abbreviation.split(|c| *c == 0).next().unwrap_or_default()).into_owned()
+            });
+            offset = abbreviation_offset + TIME_TYPE_ABBREVIATION_SIZE;
+        }
+
+        Ok(Self {
+            transitions: transitions,
+            types: types
+        })
+    }
+
+    // Before the first transition, the first standard (non-DST) type applies, like tzcode does
+    pub fn get_time_type(&self, posix_time: i64) -> &TimeType {
+        let transition_count = self.transitions.partition_point(|(time, _)| *time <= posix_time);
+        match transition_count {
+            0 => self.types.iter().find(|time_type| !time_type.is_dst).unwrap_or(&self.types[0]),
+            _ => &self.types[self.transitions[transition_count - 1].1]
+        }
+    }
+
+    pub fn to_calendar_time(&self, posix_time: i64) -> Result<(CalendarTime, CalendarAdditionalInfo)> {
+        let time_type = self.get_time_type(posix_time);
+        let local_time = posix_time.checked_add(time_type.utc_offset as i64).ok_or(result::ResultOutOfRange::make())?;
+
+        let days = local_time.div_euclid(SECONDS_PER_DAY);
+        let day_seconds = local_time.rem_euclid(SECONDS_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+        result_return_unless!((year >= i16::MIN as i64) && (year <= i16::MAX as i64), result::ResultOutOfRange);
+
+        let leap_day = if (month > 2) && is_leap_year(year) { 1 } else { 0 };
+        let calendar_time = CalendarTime {
+            year: year as i16,
+            month: month as i8,
+            day: day as i8,
+            hour: (day_seconds / 3600) as i8,
+            minute: ((day_seconds / 60) % 60) as i8,
+            second: (day_seconds % 60) as i8,
+            pad: 0
+        };
+        let additional_info = CalendarAdditionalInfo {
+            // 1970-01-01 was a Thursday
+            day_of_week: (days + 4).rem_euclid(7) as u32,
+            day_of_year: (DAYS_BEFORE_MONTH[(month - 1) as usize] + leap_day + day - 1) as u32,
+            time_zone_name: CString::from_str(&time_type.abbreviation)?,
+            is_dst: time_type.is_dst as u32,
+            utc_offset: time_type.utc_offset
+        };
+        Ok((calendar_time, additional_info))
+    }
+}
+
+// Time zone binaries (TZif files) are stored in the TimeZoneBinary system data archive as "zoneinfo/<location-name>"
+const TIME_ZONE_BINARY_ID: ProgramId = ProgramId(0x010000000000080E);
+
+// Location names are paths within zoneinfo directories, thus anything else (like "..") is rejected before looking for them
+fn is_valid_location_name(location_name: &str) -> bool {
+    !location_name.is_empty() && Path::new(location_name).components().all(|component| matches!(component, Component::Normal(_)))
+}
+
+fn load_system_time_zone_binary(location_name: &str) -> Result<Vec<u8>> {
+    let tz_fs = cache::open_nca_filesystem(StorageId::BuiltinSystem, TIME_ZONE_BINARY_ID, ContentType::Data, 0, cache::NcaFileSystemKind::RomFs)?;
+    let tz_file = tz_fs.get().open_file(PathBuf::from(format!("zoneinfo/{}", location_name)), FileOpenMode::Read())?;
+
+    let size = tz_file.get().get_size()?;
+    let mut tzif = vec![0u8; size];
+    tz_file.get().read(0, &mut tzif, ReadOption::None)?;
+    Ok(tzif)
+}
+
+fn load_host_time_zone_binary(location_name: &str) -> Result<Vec<u8>> {
+    let tzif_path = Path::new(&cfg::get_config().time_zone_info_path).join(location_name);
+    convert_io_result(read(tzif_path))
+}
+
+// The system data archive is preferred, falling back to the host's tzdata (see cfg::Config::time_zone_info_path)
+// Note: "UTC" is always available, even without any tzdata
+pub fn load_time_zone_rule(location_name: &str) -> Result<TimeZoneRule> {
+    result_return_unless!(is_valid_location_name(location_name), result::ResultTimeZoneNotFound);
+
+    let tzif = match load_system_time_zone_binary(location_name) {
+        Ok(tzif) => tzif,
+        Err(rc) => match load_host_time_zone_binary(location_name) {
+            Ok(tzif) => tzif,
+            Err(fallback_rc) => {
+                if location_name == "UTC" {
+                    return Ok(TimeZoneRule::utc());
+                }
+
+                log_line!("[time] Time zone '{}' is not available (system data: {:?}, fallback: {:?})", location_name, rc, fallback_rc);
+                return result::ResultTimeZoneNotFound::make_err();
+            }
+        }
+    };
+
+    TimeZoneRule::from_tzif(&tzif)
+}
@@ -0,0 +1,7 @@
+pub const RESULT_MODULE: u32 = 116;
+
+result_define_group!(RESULT_MODULE => {
+    OutOfRange: 902,
+    TimeZoneConversionFailed: 903,
+    TimeZoneNotFound: 989
+});
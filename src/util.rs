@@ -5,14 +5,14 @@ use std::num::NonZeroUsize;
 use std::ops::CoerceUnsized;
 use std::ptr;
 use std::any::Any;
-use std::sync::Arc;
-use std::io::{ErrorKind, Result as IoResult};
-use serde_json::Result as SerdeJsonResult;
+use std::sync::{mpsc, Arc, Weak};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::io::{Error as IoError, ErrorKind, Result as IoResult, Write};
+use std::time::Duration;
+use serde_json::{Error as SerdeJsonError, Result as SerdeJsonResult};
 use std::thread;
 use parking_lot::lock_api::{GetThreadId, RawReentrantMutex, RawMutex as RawMutexTrait};
 use parking_lot::{RawMutex, Mutex, MutexGuard};
-use crate::kern::proc::{get_current_process, has_current_process};
-use crate::kern::thread::has_current_thread;
 use crate::fs::result as fs_result;
 use crate::result;
 use crate::result::*;
@@ -203,6 +203,7 @@ pub const fn new_recursive_lock() -> RecursiveLock {
     RecursiveLock::INIT
 }
 
+// Held by the logger thread while printing, and by whoever else needs to print something without log lines getting mixed in (like crash reports)
 static mut G_LOG_LOCK: RecursiveLock = new_recursive_lock();
 
 pub fn make_log_guard<'a>() -> RecursiveLockGuard<'a> {
@@ -211,21 +212,204 @@ pub fn make_log_guard<'a>() -> RecursiveLockGuard<'a> {
     }
 }
 
-pub fn log_line_msg(msg: String) {
-    let _guard = make_log_guard();
+#[derive(Clone, Debug)]
+pub struct LogLine {
+    pub process_name: String,
+    pub thread_name: String,
+    pub msg: String
+}
+
+// Note: callbacks are invoked from the logger thread, not from the thread which logged the line
+pub type LogCallback = Arc<dyn Fn(&LogLine) + Send + Sync>;
+
+// Embedders get every log line through the callback (see embed::EmulatorBuilder::on_log), printing them being optional then
+static mut G_LOG_CALLBACK: Mutex<Option<LogCallback>> = parking_lot::const_mutex(None);
+static mut G_LOG_STDOUT_ENABLED: AtomicBool = AtomicBool::new(true);
+
+pub fn set_log_callback(callback: Option<LogCallback>) {
+    unsafe {
+        *G_LOG_CALLBACK.lock() = callback;
+    }
+}
+
+pub fn set_log_stdout_enabled(enabled: bool) {
+    unsafe {
+        G_LOG_STDOUT_ENABLED.store(enabled, Ordering::SeqCst);
+    }
+}
+
+// Logging happens from everywhere (unicorn hooks, SVCs holding process/thread locks...), thus logging threads never lock anything nor wait for anyone:
+// each thread just sends its lines through its own (thread-local) sender to the logger thread, which is the one printing them and invoking the callback
+enum LogMessage {
+    Line(LogLine),
+    Flush(mpsc::Sender<()>)
+}
+
+const LOGGER_THREAD_NAME: &str = "pegasus.Logger";
+
+// Flushing gives up after this, since the logger thread might be the one stuck (like within a callback)
+const LOG_FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+static mut G_LOGGER_SENDER: Mutex<Option<mpsc::Sender<LogMessage>>> = parking_lot::const_mutex(None);
+
+#[thread_local]
+static mut G_LOG_THREAD_SENDER: Option<mpsc::Sender<LogMessage>> = None;
+
+struct LogNames {
+    process_name: String,
+    guest_thread_name: Option<String>
+}
+
+// Names shown in the log lines of the current thread, only present for kernel threads
+// Note: the current thread (or its process) might be locked by whoever is logging, even by other threads, thus logging never accesses them: kernel threads set their names themselves instead (see kern::thread::set_current_thread)
+#[thread_local]
+static mut G_LOG_NAMES: Option<LogNames> = None;
+
+fn write_log_line(line: &LogLine) {
+    let stdout_enabled = unsafe { G_LOG_STDOUT_ENABLED.load(Ordering::SeqCst) };
+    if stdout_enabled {
+        println!("[{} -> {}] {}", line.process_name, line.thread_name, line.msg);
+    }
+
+    // Note: the callback is cloned out first, since it may log itself (which just queues more lines)
+    let callback = unsafe { G_LOG_CALLBACK.lock().clone() };
+    if let Some(callback) = callback {
+        (callback)(line);
+    }
+}
+
+fn run_logger(receiver: mpsc::Receiver<LogMessage>) {
+    for msg in receiver.iter() {
+        match msg {
+            LogMessage::Line(line) => {
+                let _guard = make_log_guard();
+                write_log_line(&line);
+            },
+            LogMessage::Flush(done_sender) => {
+                let _ = std::io::stdout().flush();
+                let _ = done_sender.send(());
+            }
+        }
+    }
+}
+
+// The logger thread is started by the first thread which logs something
+fn get_logger_sender() -> Option<mpsc::Sender<LogMessage>> {
+    unsafe {
+        if G_LOG_THREAD_SENDER.is_none() {
+            let mut logger_sender = G_LOGGER_SENDER.lock();
+            if logger_sender.is_none() {
+                let (sender, receiver) = mpsc::channel();
+                if thread::Builder::new().name(String::from(LOGGER_THREAD_NAME)).spawn(move || run_logger(receiver)).is_ok() {
+                    *logger_sender = Some(sender);
+                }
+            }
+            G_LOG_THREAD_SENDER = logger_sender.clone();
+        }
+
+        G_LOG_THREAD_SENDER.clone()
+    }
+}
+
+fn is_logger_thread() -> bool {
+    thread::current().name() == Some(LOGGER_THREAD_NAME)
+}
 
-    let process_name = match has_current_process() {
-        true => String::from(get_current_process().get().npdm.meta.name.get_str().unwrap()),
-        false => String::from("Host~pegasus")
+// Waits until every line logged so far was printed (like before exiting, or before printing something else straight to stdout)
+pub fn flush_log() {
+    // The logger thread can't wait for itself (callbacks might flush, or it might be the one panicking)
+    if is_logger_thread() {
+        return;
+    }
+
+    if let Some(sender) = get_logger_sender() {
+        let (done_sender, done_receiver) = mpsc::channel();
+        if sender.send(LogMessage::Flush(done_sender)).is_ok() {
+            let _ = done_receiver.recv_timeout(LOG_FLUSH_TIMEOUT);
+        }
+    }
+}
+
+pub fn set_log_names(process_name: String, guest_thread_name: Option<String>) {
+    unsafe {
+        G_LOG_NAMES = Some(LogNames {
+            process_name: process_name,
+            guest_thread_name: guest_thread_name
+        });
+    }
+}
+
+pub fn set_log_guest_thread_name(guest_thread_name: Option<String>) {
+    unsafe {
+        if let Some(log_names) = G_LOG_NAMES.as_mut() {
+            log_names.guest_thread_name = guest_thread_name;
+        }
+    }
+}
+
+pub fn reset_log_names() {
+    unsafe {
+        G_LOG_NAMES = None;
+    }
+}
+
+fn get_log_names() -> (String, String) {
+    let host_thread_name = String::from(thread::current().name().unwrap_or("<unnamed>"));
+    let log_names = unsafe {
+        match G_LOG_NAMES.as_ref() {
+            Some(log_names) => log_names,
+            None => return (String::from("Host~pegasus"), format!("Host~{}", host_thread_name))
+        }
     };
-    let thread_name = match has_current_thread() {
-        true => String::from(std::thread::current().name().unwrap()),
-        false => format!("Host~{}", std::thread::current().name().unwrap())
+
+    let thread_name = match log_names.guest_thread_name.as_ref() {
+        Some(guest_name) => format!("{} ('{}')", host_thread_name, guest_name),
+        None => host_thread_name
+    };
+    (log_names.process_name.clone(), thread_name)
+}
+
+pub fn log_line_msg(msg: String) {
+    let (process_name, thread_name) = get_log_names();
+    let line = LogLine {
+        process_name: process_name,
+        thread_name: thread_name,
+        msg: msg
     };
 
-    println!("[{} -> {}] {}", process_name, thread_name, msg);
+    // Lines are written right away if the logger thread isn't available (it couldn't be started, or this is it), thus nothing is ever lost
+    let unsent_line = match is_logger_thread() {
+        true => Some(line),
+        false => match get_logger_sender() {
+            Some(sender) => match sender.send(LogMessage::Line(line)) {
+                Ok(()) => None,
+                Err(mpsc::SendError(LogMessage::Line(line))) => Some(line),
+                Err(_) => None
+            },
+            None => Some(line)
+        }
+    };
+
+    if let Some(line) = unsent_line {
+        let _guard = make_log_guard();
+        write_log_line(&line);
+    }
+}
+
+// Unlike the name given to std::thread::Builder (which can't be changed afterwards), this is the one shown by host debuggers/tools
+#[cfg(target_os = "linux")]
+pub fn set_current_host_thread_name(name: &str) {
+    // Note: Linux limits thread names to 15 characters (plus the NUL terminator)
+    let name_c: Vec<u8> = name.bytes().filter(|ch| *ch != 0).take(15).chain(std::iter::once(0)).collect();
+    unsafe {
+        libc::pthread_setname_np(libc::pthread_self(), name_c.as_ptr() as *const libc::c_char);
+    }
 }
 
+#[cfg(not(target_os = "linux"))]
+pub fn set_current_host_thread_name(_name: &str) {}
+
+#[macro_export]
 macro_rules! log_line {
     ($($arg:tt)*) => {{
         let log_msg = format!($($arg)*);
@@ -488,6 +672,18 @@ pub fn slice_read_val_advance<T: Copy>(slice: &[u8], offset: &mut usize) -> Resu
     Ok(t)
 }
 
+pub fn slice_write_val<T: Copy>(slice: &mut [u8], offset: Option<usize>, t: T) -> Result<()> {
+    let offset_val = offset.unwrap_or(0);
+
+    result_return_unless!((offset_val + core::mem::size_of::<T>()) <= slice.len(), result::ResultWriteOutOfBounds);
+    
+    unsafe {
+        let ptr = slice.as_mut_ptr().offset(offset_val as isize) as *mut T;
+        ptr.write_unaligned(t);
+    }
+    Ok(())
+}
+
 pub fn slice_read_data_advance(slice: &[u8], offset: &mut usize, len: usize) -> Result<Vec<u8>> {
     let data = slice_read_data(slice, Some(*offset), len)?;
     *offset += len;
@@ -499,29 +695,56 @@ pub fn get_path_relative_to_cwd(name: &str) -> String {
     current_dir().unwrap().join(name).as_path().display().to_string()
 }
 
-pub fn convert_io_result<T>(r: IoResult<T>) -> Result<T> {
-    r.map_err(|err| match err.kind() {
+fn convert_io_error_kind(kind: ErrorKind) -> ResultCode {
+    match kind {
         // TODO: finish
         ErrorKind::NotFound => fs_result::ResultPathNotFound::make(),
         ErrorKind::PermissionDenied => fs_result::ResultTargetLocked::make(),
         ErrorKind::WouldBlock => fs_result::ResultTargetLocked::make(),
         ErrorKind::UnexpectedEof => fs_result::ResultOutOfRange::make(),
         _ => result::ResultNotSupported::make()
-    })
+    }
+}
+
+pub fn convert_io_result<T>(r: IoResult<T>) -> Result<T> {
+    r.map_err(|err| convert_io_error_kind(err.kind()))
+}
+
+// Unlike the above, the error message is kept (it's often the most useful part, like a parse error from cntx)
+impl From<IoError> for result::Error {
+    fn from(err: IoError) -> Self {
+        Self::with_message(convert_io_error_kind(err.kind()), err.to_string())
+    }
+}
+
+impl From<SerdeJsonError> for result::Error {
+    fn from(err: SerdeJsonError) -> Self {
+        Self::with_message(result::ResultInvalidJson::make(), err.to_string())
+    }
 }
 
 pub fn convert_serde_json_result<T>(r: SerdeJsonResult<T>) -> Result<T> {
     r.map_err(|_| result::ResultInvalidJson::make())
 }
 
+pub fn convert_utf8_result<T>(r: std::result::Result<T, std::string::FromUtf8Error>) -> Result<T> {
+    r.map_err(|_| result::ResultInvalidUtf8String::make())
+}
+
 pub struct Shared<T: ?Sized>(pub Arc<Mutex<T>>);
-pub struct SharedAny(pub Arc<dyn Any + Send + Sync>);
+// Note: the type name is kept since it can't be obtained back from the type-erased object (useful for debugging)
+pub struct SharedAny(pub Arc<dyn Any + Send + Sync>, pub &'static str);
 
 impl<T: ?Sized> Shared<T> {
     pub fn ptr_eq(&self, other: &Shared<T>) -> bool {
         Arc::ptr_eq(&self.0, &other.0)
     }
 
+    // Same as above, but also for differently typed Shareds (like a Shared<dyn sf::IObject> and the concrete object it was created from)
+    pub fn addr_eq<U: ?Sized>(&self, other: &Shared<U>) -> bool {
+        (Arc::as_ptr(&self.0) as *const u8) == (Arc::as_ptr(&other.0) as *const u8)
+    }
+
     pub fn get(&self) -> MutexGuard<'_, T> {
         if self.0.is_locked() {
             panic!("Attempted to access an already locked Shared<{}>", std::any::type_name::<T>());
@@ -530,6 +753,11 @@ impl<T: ?Sized> Shared<T> {
         self.0.lock()
     }
 
+    // Threads outside the emulator (like an embedder's UI thread, see embed::Process) wait for emulator threads to release the object instead
+    pub fn get_blocking(&self) -> MutexGuard<'_, T> {
+        self.0.lock()
+    }
+
     pub fn is_locked(&self) -> bool {
         self.0.is_locked()
     }
@@ -537,11 +765,16 @@ impl<T: ?Sized> Shared<T> {
 
 impl<T: Any + Send + Sync + Sized> Shared<T> {
     pub fn new(t: T) -> Self {
-        Shared(Arc::new(Mutex::new(t)))
+        let shared = Shared(Arc::new(Mutex::new(t)));
+
+        #[cfg(debug_assertions)]
+        register_live_shared(&shared);
+
+        shared
     }
 
     pub fn as_any(&self) -> SharedAny {
-        SharedAny(self.0.clone())
+        SharedAny(self.0.clone(), std::any::type_name::<T>())
     }
 
     pub fn ptr_eq_any(&self, other: &SharedAny) -> bool {
@@ -555,9 +788,103 @@ impl<T: ?Sized> Clone for Shared<T> {
     }
 }
 
+impl<T: ?Sized> Shared<T> {
+    pub fn downgrade(&self) -> WeakShared<T> {
+        WeakShared(Arc::downgrade(&self.0))
+    }
+}
+
+// Non-owning reference to a Shared, for backlinks which would otherwise keep both objects alive forever
+pub struct WeakShared<T: ?Sized>(pub Weak<Mutex<T>>);
+
+impl<T> WeakShared<T> {
+    pub fn new() -> Self {
+        WeakShared(Weak::new())
+    }
+}
+
+impl<T> Default for WeakShared<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: ?Sized> WeakShared<T> {
+    pub fn upgrade(&self) -> Option<Shared<T>> {
+        self.0.upgrade().map(Shared)
+    }
+}
+
+impl<T: ?Sized> Clone for WeakShared<T> {
+    fn clone(&self) -> Self {
+        WeakShared(self.0.clone())
+    }
+}
+
 impl<T: ?Sized + Unsize<U>, U: ?Sized> CoerceUnsized<Shared<U>> for Shared<T> {}
 
+// Leak detection (debug builds only): all Shareds are tracked (weakly) so that the ones still alive can be listed, for instance at shutdown
+
+#[cfg(debug_assertions)]
+static mut G_LIVE_SHARED_OBJECTS: Mutex<Vec<(&'static str, Weak<dyn Any + Send + Sync>)>> = parking_lot::const_mutex(Vec::new());
+
+#[cfg(debug_assertions)]
+fn register_live_shared<T: Any + Send + Sync>(shared: &Shared<T>) {
+    let weak_any: Weak<dyn Any + Send + Sync> = Arc::downgrade(&(shared.0.clone() as Arc<dyn Any + Send + Sync>));
+
+    unsafe {
+        let mut live_shared_objects = G_LIVE_SHARED_OBJECTS.lock();
+
+        // Forget about already dropped ones every now and then, so that this doesn't grow forever
+        if live_shared_objects.len() == live_shared_objects.capacity() {
+            live_shared_objects.retain(|(_, weak)| weak.strong_count() > 0);
+        }
+        live_shared_objects.push((std::any::type_name::<T>(), weak_any));
+    }
+}
+
+// Returns the count of Shareds still alive by type name (always empty on release builds)
+pub fn get_live_shared_object_counts() -> Vec<(&'static str, usize)> {
+    #[cfg(debug_assertions)]
+    unsafe {
+        let live_shared_objects = G_LIVE_SHARED_OBJECTS.lock();
+
+        let mut counts: Vec<(&'static str, usize)> = Vec::new();
+        for (type_name, weak) in live_shared_objects.iter() {
+            if weak.strong_count() > 0 {
+                match counts.iter_mut().find(|(count_type_name, _)| count_type_name == type_name) {
+                    Some((_, count)) => *count += 1,
+                    None => counts.push((type_name, 1))
+                }
+            }
+        }
+
+        counts.sort_by(|(_, a), (_, b)| b.cmp(a));
+        counts
+    }
+
+    #[cfg(not(debug_assertions))]
+    Vec::new()
+}
+
+pub fn dump_live_shared_objects() {
+    let counts = get_live_shared_object_counts();
+    if counts.is_empty() {
+        return;
+    }
+
+    log_line!("Shared objects still alive:");
+    for (type_name, count) in counts {
+        log_line!("  {} x{}", type_name, count);
+    }
+}
+
 impl SharedAny {
+    // Without the module path (like "KServerSession")
+    pub fn get_type_name(&self) -> &'static str {
+        self.1.rsplit("::").next().unwrap_or(self.1)
+    }
+
     pub fn cast<U: Any + Send + Sync>(&self) -> Result<Shared<U>> {
         match self.0.clone().downcast::<Mutex<U>>() {
             Ok(arc) => Ok(Shared(arc)),
@@ -568,6 +895,38 @@ impl SharedAny {
 
 impl Clone for SharedAny {
     fn clone(&self) -> Self {
-        SharedAny(self.0.clone())
+        SharedAny(self.0.clone(), self.1)
+    }
+}
+
+// Scratch directory for unit tests, unique per test process and call (since tests run in parallel), removed once dropped
+#[cfg(test)]
+pub struct TestTempDir(std::path::PathBuf);
+
+#[cfg(test)]
+impl TestTempDir {
+    pub fn new(name: &str) -> Self {
+        static G_NEXT_TEST_TEMP_DIR_ID: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let id = G_NEXT_TEST_TEMP_DIR_ID.fetch_add(1, Ordering::SeqCst);
+        let path = std::env::temp_dir().join(format!("pegasus_test_{}_{}_{}", name, std::process::id(), id));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.0
+    }
+
+    // As a string, like most paths taken by the emulator
+    pub fn join_str(&self, name: &str) -> String {
+        self.0.join(name).to_string_lossy().into_owned()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+impl Drop for TestTempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
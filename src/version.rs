@@ -0,0 +1,40 @@
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use parking_lot::Mutex;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Version {
+    pub major: u8,
+    pub minor: u8,
+    pub micro: u8
+}
+
+impl Version {
+    pub const fn new(major: u8, minor: u8, micro: u8) -> Self {
+        Self {
+            major: major,
+            minor: minor,
+            micro: micro
+        }
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.micro)
+    }
+}
+
+// Emulated firmware version (see set:sys), unknown until the system version title is loaded
+static mut G_VERSION: Mutex<Option<Version>> = parking_lot::const_mutex(None);
+
+pub fn set_version(version: Version) {
+    unsafe {
+        *G_VERSION.lock() = Some(version);
+    }
+}
+
+pub fn try_get_version() -> Option<Version> {
+    unsafe {
+        *G_VERSION.lock()
+    }
+}
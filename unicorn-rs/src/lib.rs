@@ -16,6 +16,9 @@ pub use crate::{arm::*, arm64::*, m68k::*, mips::*, ppc::*, sparc::*, x86::*};
 use ffi::uc_engine;
 use ffi::uc_hook;
 use libc::c_void;
+use std::any::Any;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use unicorn_const::*;
 
 #[derive(Debug)]
@@ -38,6 +41,24 @@ impl Drop for Context {
     }
 }
 
+/// An invalid memory access (unmapped, protected...), as reported by the invalid memory access hook.
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryFault {
+    pub mem_type: MemType,
+    pub address: u64,
+    pub size: usize,
+    pub value: u64
+}
+
+/// A memory map backed by host memory, see `Handle::mem_map_ptr_all`.
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryMap {
+    pub address: u64,
+    pub size: usize,
+    pub perms: Permission,
+    pub ptr: *mut c_void
+}
+
 #[derive(Clone, Copy)]
 pub struct Handle {
     pub inner_handle: uc_engine
@@ -142,6 +163,21 @@ impl Handle {
         }
     }
 
+    /// Map several memory regions at once, either all of them or none: if any mapping fails, the already
+    /// mapped ones are unmapped back, and the index of the failing one is returned along with the error.
+    pub fn mem_map_ptr_all(&mut self, maps: &[MemoryMap]) -> Result<(), (usize, uc_error)> {
+        for (i, map) in maps.iter().enumerate() {
+            if let Err(err) = self.mem_map_ptr(map.address, map.size, map.perms, map.ptr) {
+                for mapped in maps[..i].iter() {
+                    let _ = self.mem_unmap(mapped.address, mapped.size);
+                }
+                return Err((i, err));
+            }
+        }
+
+        Ok(())
+    }
+
     /// Map a memory region in the emulator at the specified address.
     ///
     /// `address` must be aligned to 4kb or this will return `Error::ARG`.
@@ -214,6 +250,20 @@ impl Handle {
         }
     }
 
+    /// Read the program counter, whichever the architecture is.
+    ///
+    /// supported: ARM, ARM64
+    pub fn pc_read(&self) -> Result<u64, uc_error> {
+        let arch = self.query(Query::ARCH)?;
+        if arch == Arch::ARM64 as usize {
+            self.reg_read::<u64>(RegisterARM64::PC as i32)
+        } else if arch == Arch::ARM as usize {
+            Ok(self.reg_read::<u32>(RegisterARM::PC as i32)? as u64)
+        } else {
+            Err(uc_error::ARCH)
+        }
+    }
+
     /// Allocate and return an empty Unicorn context.
     ///
     /// To be populated via context_save.
@@ -323,36 +373,82 @@ impl Handle {
     }
 }
 
+/// Whether a registered hook's callback gets called, see `Engine::get_hook_switch`.
+///
+/// Disabled hooks stay registered in unicorn (thus translated code still calls into them), but their
+/// callback returns right away, which is way cheaper than removing and re-adding them.
+#[derive(Debug, Clone)]
+pub struct HookSwitch(Arc<AtomicBool>);
+
+impl HookSwitch {
+    fn new() -> Self {
+        Self(Arc::new(AtomicBool::new(true)))
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.0.store(enabled, Ordering::SeqCst);
+    }
+
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// What unicorn gets as the user data of each hook: it's boxed on its own, so that its address stays the same while the hook is registered
+struct HookData<F> {
+    switch: HookSwitch,
+    callback: F
+}
+
+struct RegisteredHook {
+    hook: uc_hook,
+    switch: HookSwitch,
+    // Only kept alive here, it's accessed through unicorn
+    _data: Box<dyn Any + Send + Sync>
+}
+
 pub struct Engine {
     pub handle: Handle,
-    pub code_hooks: Vec<(Box<dyn Fn(Handle, u64, usize) + Send + Sync>, uc_hook)>,
-    pub invalid_memory_access_hooks: Vec<(Box<dyn Fn(Handle, MemType, u64, usize, u64) + Send + Sync>, uc_hook)>,
-    pub invalid_insn_hooks: Vec<(Box<dyn Fn(Handle) + Send + Sync>, uc_hook)>,
-    pub intr_hooks: Vec<(Box<dyn Fn(Handle, u32) + Send + Sync>, uc_hook)>
+    hooks: Vec<RegisteredHook>,
+    last_memory_fault: Arc<Mutex<Option<MemoryFault>>>
 }
 
-unsafe extern "C" fn code_hook_impl(engine: uc_engine, address: u64, size: u32, user_data: *mut u8) {
-    let handle = Handle::new(engine);
-    let callback = &*(user_data as *mut Box<dyn Fn(Handle, u64, usize) + Send + Sync>);
-    callback(handle, address, size as usize);
+unsafe extern "C" fn code_hook_impl<F: Fn(Handle, u64, usize)>(engine: uc_engine, address: u64, size: u32, user_data: *mut u8) {
+    let data = &*(user_data as *const HookData<F>);
+    if data.switch.is_enabled() {
+        (data.callback)(Handle::new(engine), address, size as usize);
+    }
+}
+
+// Note: unicorn expects a bool here (whether the access was handled), returning false keeps the emulation failing with the corresponding error
+unsafe extern "C" fn invalid_memory_access_hook_impl<F: Fn(Handle, MemType, u64, usize, u64)>(engine: uc_engine, mem_type: MemType, address: u64, size: u32, value: u64, user_data: *mut u8) -> bool {
+    let data = &*(user_data as *const HookData<F>);
+    if data.switch.is_enabled() {
+        (data.callback)(Handle::new(engine), mem_type, address, size as usize, value);
+    }
+    false
 }
 
-unsafe extern "C" fn invalid_memory_access_hook_impl(engine: uc_engine, mem_type: MemType, address: u64, size: u32, value: u64, user_data: *mut u8) {
-    let handle = Handle::new(engine);
-    let callback = &*(user_data as *mut Box<dyn Fn(Handle, MemType, u64, usize, u64) + Send + Sync>);
-    callback(handle, mem_type, address, size as usize, value);
+unsafe extern "C" fn invalid_insn_hook_impl<F: Fn(Handle)>(engine: uc_engine, user_data: *mut u8) {
+    let data = &*(user_data as *const HookData<F>);
+    if data.switch.is_enabled() {
+        (data.callback)(Handle::new(engine));
+    }
 }
 
-unsafe extern "C" fn invalid_insn_hook_impl(engine: uc_engine, user_data: *mut u8) {
-    let handle = Handle::new(engine);
-    let callback = &*(user_data as *mut Box<dyn Fn(Handle) + Send + Sync>);
-    callback(handle);
+unsafe extern "C" fn intr_hook_impl<F: Fn(Handle, u32)>(engine: uc_engine, intr_no: u32, user_data: *mut u8) {
+    let data = &*(user_data as *const HookData<F>);
+    if data.switch.is_enabled() {
+        (data.callback)(Handle::new(engine), intr_no);
+    }
 }
 
-unsafe extern "C" fn intr_hook_impl(engine: uc_engine, intr_no: u32, user_data: *mut u8) {
-    let handle = Handle::new(engine);
-    let callback = &*(user_data as *mut Box<dyn Fn(Handle, u32) + Send + Sync>);
-    callback(handle, intr_no);
+unsafe extern "C" fn mem_write_hook_impl<F: Fn(Handle, u64, usize, u64)>(engine: uc_engine, _mem_type: MemType, address: u64, size: i32, value: i64, user_data: *mut u8) {
+    let data = &*(user_data as *const HookData<F>);
+    if data.switch.is_enabled() {
+        (data.callback)(Handle::new(engine), address, size as usize, value as u64);
+    }
 }
 
 impl Engine {
@@ -364,132 +460,116 @@ impl Engine {
         if err == uc_error::OK {
             Ok(Self {
                 handle: Handle::new(handle),
-                code_hooks: Vec::new(),
-                invalid_memory_access_hooks: Vec::new(),
-                invalid_insn_hooks: Vec::new(),
-                intr_hooks: Vec::new()
+                hooks: Vec::new(),
+                last_memory_fault: Arc::new(Mutex::new(None))
             })
         } else {
             Err(err)
         }
     }
 
-    pub fn add_code_hook<F: Fn(Handle, u64, usize) + Send + Sync + 'static>(&mut self, f: F, begin: u64, end: u64) -> Result<uc_hook, uc_error> {
-        unsafe {
-            let mut hook: uc_hook = core::ptr::null_mut();
-            let index = self.code_hooks.len();
-            self.code_hooks.push((Box::new(f), hook));
-            let (callback_ref, _) = &mut self.code_hooks[index];
-            let err = ffi::uc_hook_add(self.handle.inner_handle, &mut hook as *mut _, HookType::CODE, code_hook_impl as *mut c_void, callback_ref as *mut _ as *mut c_void, begin, end);
-            if err == uc_error::OK {
-                Ok(hook)
-            }
-            else {
-                let _ = self.code_hooks.remove(index);
-                Err(err)
-            }
+    fn add_hook<F: Send + Sync + 'static>(&mut self, hook_type: HookType, callback_impl: *mut c_void, f: F, begin: u64, end: u64) -> Result<uc_hook, uc_error> {
+        let switch = HookSwitch::new();
+        let data = Box::new(HookData {
+            switch: switch.clone(),
+            callback: f
+        });
+
+        let mut hook: uc_hook = core::ptr::null_mut();
+        let err = unsafe { ffi::uc_hook_add(self.handle.inner_handle, &mut hook as *mut _, hook_type, callback_impl, &*data as *const HookData<F> as *mut c_void, begin, end) };
+        if err == uc_error::OK {
+            self.hooks.push(RegisteredHook {
+                hook: hook,
+                switch: switch,
+                _data: data
+            });
+            Ok(hook)
+        }
+        else {
+            Err(err)
         }
     }
 
+    pub fn add_code_hook<F: Fn(Handle, u64, usize) + Send + Sync + 'static>(&mut self, f: F, begin: u64, end: u64) -> Result<uc_hook, uc_error> {
+        self.add_hook(HookType::CODE, code_hook_impl::<F> as *mut c_void, f, begin, end)
+    }
+
+    /// Called before every basic block within the given range gets executed, with its address and size.
+    pub fn add_block_hook<F: Fn(Handle, u64, usize) + Send + Sync + 'static>(&mut self, f: F, begin: u64, end: u64) -> Result<uc_hook, uc_error> {
+        // Note: block hooks take the same arguments as code hooks
+        self.add_hook(HookType::BLOCK, code_hook_impl::<F> as *mut c_void, f, begin, end)
+    }
+
     pub fn add_invalid_memory_access_hook<F: Fn(Handle, MemType, u64, usize, u64) + Send + Sync + 'static>(&mut self, f: F, begin: u64, end: u64) -> Result<uc_hook, uc_error> {
-        unsafe {
-            let mut hook: uc_hook = core::ptr::null_mut();
-            let index = self.invalid_memory_access_hooks.len();
-            self.invalid_memory_access_hooks.push((Box::new(f), hook));
-            let (callback_ref, _) = &mut self.invalid_memory_access_hooks[index];
-            let err = ffi::uc_hook_add(self.handle.inner_handle, &mut hook as *mut _, HookType::MEM_INVALID, invalid_memory_access_hook_impl as *mut c_void, callback_ref as *mut _ as *mut c_void, begin, end);
-            if err == uc_error::OK {
-                Ok(hook)
-            }
-            else {
-                let _ = self.invalid_memory_access_hooks.remove(index);
-                Err(err)
-            }
-        }
+        self.add_hook(HookType::MEM_INVALID, invalid_memory_access_hook_impl::<F> as *mut c_void, f, begin, end)
     }
 
     pub fn add_invalid_insn_hook<F: Fn(Handle) + Send + Sync + 'static>(&mut self, f: F, begin: u64, end: u64) -> Result<uc_hook, uc_error> {
-        unsafe {
-            let mut hook: uc_hook = core::ptr::null_mut();
-            let index = self.invalid_insn_hooks.len();
-            self.invalid_insn_hooks.push((Box::new(f), hook));
-            let (callback_ref, _) = &mut self.invalid_insn_hooks[index];
-            let err = ffi::uc_hook_add(self.handle.inner_handle, &mut hook as *mut _, HookType::INSN_INVALID, invalid_insn_hook_impl as *mut c_void, callback_ref as *mut _ as *mut c_void, begin, end);
-            if err == uc_error::OK {
-                Ok(hook)
-            }
-            else {
-                let _ = self.invalid_insn_hooks.remove(index);
-                Err(err)
-            }
-        }
+        self.add_hook(HookType::INSN_INVALID, invalid_insn_hook_impl::<F> as *mut c_void, f, begin, end)
     }
 
     pub fn add_intr_hook<F: Fn(Handle, u32) + Send + Sync + 'static>(&mut self, f: F, begin: u64, end: u64) -> Result<uc_hook, uc_error> {
-        unsafe {
-            let mut hook: uc_hook = core::ptr::null_mut();
-            let index = self.intr_hooks.len();
-            self.intr_hooks.push((Box::new(f), hook));
-            let (callback_ref, _) = &mut self.intr_hooks[index];
-            let err = ffi::uc_hook_add(self.handle.inner_handle, &mut hook as *mut _, HookType::INTR, intr_hook_impl as *mut c_void, callback_ref as *mut _ as *mut c_void, begin, end);
-            if err == uc_error::OK {
-                Ok(hook)
-            }
-            else {
-                let _ = self.intr_hooks.remove(index);
-                Err(err)
-            }
+        self.add_hook(HookType::INTR, intr_hook_impl::<F> as *mut c_void, f, begin, end)
+    }
+
+    /// Called before every (valid) memory write within the given range, with the address, size and value written.
+    pub fn add_mem_write_hook<F: Fn(Handle, u64, usize, u64) + Send + Sync + 'static>(&mut self, f: F, begin: u64, end: u64) -> Result<uc_hook, uc_error> {
+        self.add_hook(HookType::MEM_WRITE, mem_write_hook_impl::<F> as *mut c_void, f, begin, end)
+    }
+
+    /// Keep track of invalid memory accesses, so that the one making `emu_start` fail can be retrieved
+    /// afterwards with `take_last_memory_fault`.
+    pub fn enable_memory_fault_tracking(&mut self) -> Result<uc_hook, uc_error> {
+        let last_memory_fault = self.last_memory_fault.clone();
+        self.add_invalid_memory_access_hook(move |_, mem_type, address, size, value| {
+            *last_memory_fault.lock().unwrap() = Some(MemoryFault {
+                mem_type: mem_type,
+                address: address,
+                size: size,
+                value: value
+            });
+        }, 1, 0)
+    }
+
+    /// Returns (and clears) the last invalid memory access, if memory fault tracking is enabled.
+    pub fn take_last_memory_fault(&self) -> Option<MemoryFault> {
+        self.last_memory_fault.lock().unwrap().take()
+    }
+
+    /// Returns the switch of a hook, which enables/disables it without removing it (even from other threads,
+    /// like while the engine is running).
+    ///
+    /// `hook` is the value returned by `add_*_hook` functions.
+    pub fn get_hook_switch(&self, hook: uc_hook) -> Result<HookSwitch, uc_error> {
+        match self.hooks.iter().find(|registered_hook| registered_hook.hook == hook) {
+            Some(registered_hook) => Ok(registered_hook.switch.clone()),
+            None => Err(uc_error::HOOK)
         }
     }
 
+    /// Enable or disable a hook without removing it, see `HookSwitch`.
+    pub fn set_hook_enabled(&self, hook: uc_hook, enabled: bool) -> Result<(), uc_error> {
+        self.get_hook_switch(hook)?.set_enabled(enabled);
+        Ok(())
+    }
+
+    pub fn is_hook_enabled(&self, hook: uc_hook) -> Result<bool, uc_error> {
+        Ok(self.get_hook_switch(hook)?.is_enabled())
+    }
+
     /// Remove a hook.
     ///
     /// `hook` is the value returned by `add_*_hook` functions.
     pub fn remove_hook(&mut self, hook: uc_hook) -> Result<(), uc_error> {
-        let err: uc_error;
-        let mut found = false;
-
-        for i in 0..self.code_hooks.len() {
-            let (_, c_hook) = self.code_hooks[i];
-            if hook == c_hook {
-                found = true;
-                let _ = self.code_hooks.remove(i);
-                break;
-            }
-        }
-        for i in 0..self.invalid_memory_access_hooks.len() {
-            let (_, c_hook) = self.invalid_memory_access_hooks[i];
-            if hook == c_hook {
-                found = true;
-                let _ = self.invalid_memory_access_hooks.remove(i);
-                break;
-            }
-        }
-        for i in 0..self.invalid_insn_hooks.len() {
-            let (_, c_hook) = self.invalid_insn_hooks[i];
-            if hook == c_hook {
-                found = true;
-                let _ = self.invalid_insn_hooks.remove(i);
-                break;
-            }
-        }
-        for i in 0..self.intr_hooks.len() {
-            let (_, c_hook) = self.intr_hooks[i];
-            if hook == c_hook {
-                found = true;
-                let _ = self.intr_hooks.remove(i);
-                break;
-            }
-        }
-
-        if found {
-            err = unsafe { ffi::uc_hook_del(self.handle.inner_handle, hook) };
-        }
-        else {
-            err = uc_error::HOOK;
-        }
+        let index = match self.hooks.iter().position(|registered_hook| registered_hook.hook == hook) {
+            Some(index) => index,
+            None => return Err(uc_error::HOOK)
+        };
 
+        let err = unsafe { ffi::uc_hook_del(self.handle.inner_handle, hook) };
         if err == uc_error::OK {
+            // Note: the callback can only be dropped once unicorn no longer uses it
+            let _ = self.hooks.remove(index);
             Ok(())
         }
         else {
@@ -545,6 +625,11 @@ impl Engine {
         self.handle.mem_map_ptr(address, size, perms, ptr)
     }
 
+    /// Map several memory regions at once, either all of them or none, see `Handle::mem_map_ptr_all`.
+    pub fn mem_map_ptr_all(&mut self, maps: &[MemoryMap]) -> Result<(), (usize, uc_error)> {
+        self.handle.mem_map_ptr_all(maps)
+    }
+
     /// Map a memory region in the emulator at the specified address.
     ///
     /// `address` must be aligned to 4kb or this will return `Error::ARG`.
@@ -589,6 +674,11 @@ impl Engine {
         self.handle.reg_read(regid)
     }
 
+    /// Read the program counter, whichever the architecture is.
+    pub fn pc_read(&self) -> Result<u64, uc_error> {
+        self.handle.pc_read()
+    }
+
     /// Allocate and return an empty Unicorn context.
     ///
     /// To be populated via context_save.
@@ -655,4 +745,161 @@ impl Drop for Engine {
     fn drop(&mut self) {
         unsafe { ffi::uc_close(self.handle.inner_handle) };
     }
-}
\ No newline at end of file
+}
+enum BuilderHook {
+    Code(Box<dyn Fn(Handle, u64, usize) + Send + Sync>, u64, u64),
+    Block(Box<dyn Fn(Handle, u64, usize) + Send + Sync>, u64, u64),
+    InvalidMemoryAccess(Box<dyn Fn(Handle, MemType, u64, usize, u64) + Send + Sync>, u64, u64),
+    InvalidInsn(Box<dyn Fn(Handle) + Send + Sync>, u64, u64),
+    Intr(Box<dyn Fn(Handle, u32) + Send + Sync>, u64, u64),
+    MemWrite(Box<dyn Fn(Handle, u64, usize, u64) + Send + Sync>, u64, u64)
+}
+
+struct BuilderMapping {
+    address: u64,
+    size: usize,
+    perms: Permission,
+    ptr: Option<*mut c_void>
+}
+
+/// Declarative configuration of an `Engine`: everything is applied (in order: memory maps, registers, hooks)
+/// right after opening it, failing as a whole if any step fails.
+///
+/// TODO: CPU model and page size selection, which need uc_ctl (only available since unicorn 2)
+pub struct EngineBuilder {
+    arch: Arch,
+    mode: Mode,
+    page_size: Option<usize>,
+    mappings: Vec<BuilderMapping>,
+    registers: Vec<(i32, u64)>,
+    hooks: Vec<BuilderHook>,
+    memory_fault_tracking: bool
+}
+
+impl EngineBuilder {
+    pub fn new(arch: Arch, mode: Mode) -> Self {
+        Self {
+            arch: arch,
+            mode: mode,
+            page_size: None,
+            mappings: Vec::new(),
+            registers: Vec::new(),
+            hooks: Vec::new(),
+            memory_fault_tracking: false
+        }
+    }
+
+    /// Granularity of the memory maps: building fails with `Error::ARG` if it isn't a multiple of the
+    /// actual engine page size or if any memory map isn't aligned to it.
+    pub fn page_size(mut self, page_size: usize) -> Self {
+        self.page_size = Some(page_size);
+        self
+    }
+
+    /// Memory allocated by unicorn, see `Handle::mem_map`.
+    pub fn map(mut self, address: u64, size: usize, perms: Permission) -> Self {
+        self.mappings.push(BuilderMapping { address: address, size: size, perms: perms, ptr: None });
+        self
+    }
+
+    /// Memory backed by the given host memory, see `Handle::mem_map_ptr`.
+    pub fn map_ptr(mut self, address: u64, size: usize, perms: Permission, ptr: *mut c_void) -> Self {
+        self.mappings.push(BuilderMapping { address: address, size: size, perms: perms, ptr: Some(ptr) });
+        self
+    }
+
+    /// Initial register value (registers narrower than 64 bits take the lower bits).
+    pub fn reg_write(mut self, regid: i32, value: u64) -> Self {
+        self.registers.push((regid, value));
+        self
+    }
+
+    pub fn code_hook<F: Fn(Handle, u64, usize) + Send + Sync + 'static>(mut self, f: F, begin: u64, end: u64) -> Self {
+        self.hooks.push(BuilderHook::Code(Box::new(f), begin, end));
+        self
+    }
+
+    pub fn block_hook<F: Fn(Handle, u64, usize) + Send + Sync + 'static>(mut self, f: F, begin: u64, end: u64) -> Self {
+        self.hooks.push(BuilderHook::Block(Box::new(f), begin, end));
+        self
+    }
+
+    pub fn invalid_memory_access_hook<F: Fn(Handle, MemType, u64, usize, u64) + Send + Sync + 'static>(mut self, f: F, begin: u64, end: u64) -> Self {
+        self.hooks.push(BuilderHook::InvalidMemoryAccess(Box::new(f), begin, end));
+        self
+    }
+
+    pub fn invalid_insn_hook<F: Fn(Handle) + Send + Sync + 'static>(mut self, f: F, begin: u64, end: u64) -> Self {
+        self.hooks.push(BuilderHook::InvalidInsn(Box::new(f), begin, end));
+        self
+    }
+
+    pub fn intr_hook<F: Fn(Handle, u32) + Send + Sync + 'static>(mut self, f: F, begin: u64, end: u64) -> Self {
+        self.hooks.push(BuilderHook::Intr(Box::new(f), begin, end));
+        self
+    }
+
+    pub fn mem_write_hook<F: Fn(Handle, u64, usize, u64) + Send + Sync + 'static>(mut self, f: F, begin: u64, end: u64) -> Self {
+        self.hooks.push(BuilderHook::MemWrite(Box::new(f), begin, end));
+        self
+    }
+
+    /// See `Engine::enable_memory_fault_tracking`.
+    pub fn memory_fault_tracking(mut self) -> Self {
+        self.memory_fault_tracking = true;
+        self
+    }
+
+    /// Applies a set of settings at once (like the hooks every engine of some kind needs).
+    pub fn preset<F: FnOnce(Self) -> Self>(self, f: F) -> Self {
+        f(self)
+    }
+
+    pub fn build(self) -> Result<Engine, uc_error> {
+        let mut engine = Engine::new(self.arch, self.mode)?;
+
+        if let Some(page_size) = self.page_size {
+            let engine_page_size = engine.query(Query::PAGE_SIZE)?;
+            if (page_size == 0) || (engine_page_size == 0) || ((page_size % engine_page_size) != 0) {
+                return Err(uc_error::ARG);
+            }
+
+            let page_mask = page_size as u64 - 1;
+            if self.mappings.iter().any(|mapping| ((mapping.address & page_mask) != 0) || (((mapping.size as u64) & page_mask) != 0)) {
+                return Err(uc_error::ARG);
+            }
+        }
+
+        for mapping in self.mappings.iter() {
+            match mapping.ptr {
+                Some(ptr) => engine.mem_map_ptr(mapping.address, mapping.size, mapping.perms, ptr)?,
+                None => engine.mem_map(mapping.address, mapping.size, mapping.perms)?
+            };
+        }
+
+        let is_32bit = self.arch == Arch::ARM;
+        for (regid, value) in self.registers.into_iter() {
+            match is_32bit {
+                true => engine.reg_write(regid, value as u32)?,
+                false => engine.reg_write(regid, value)?
+            };
+        }
+
+        for hook in self.hooks.into_iter() {
+            match hook {
+                BuilderHook::Code(f, begin, end) => engine.add_code_hook(f, begin, end)?,
+                BuilderHook::Block(f, begin, end) => engine.add_block_hook(f, begin, end)?,
+                BuilderHook::InvalidMemoryAccess(f, begin, end) => engine.add_invalid_memory_access_hook(f, begin, end)?,
+                BuilderHook::InvalidInsn(f, begin, end) => engine.add_invalid_insn_hook(f, begin, end)?,
+                BuilderHook::Intr(f, begin, end) => engine.add_intr_hook(f, begin, end)?,
+                BuilderHook::MemWrite(f, begin, end) => engine.add_mem_write_hook(f, begin, end)?
+            };
+        }
+
+        if self.memory_fault_tracking {
+            engine.enable_memory_fault_tracking()?;
+        }
+
+        Ok(engine)
+    }
+}
@@ -1,5 +1,7 @@
 #![allow(non_camel_case_types)]
 use bitflags::bitflags;
+use std::error;
+use std::fmt;
 
 pub const API_MAJOR: u64 = 1;
 pub const API_MINOR: u64 = 0;
@@ -36,6 +38,45 @@ pub enum uc_error {
     EXCEPTION = 21,
 }
 
+impl uc_error {
+    /// Same messages as `uc_strerror`, without going through the C library.
+    pub fn message(&self) -> &'static str {
+        match self {
+            uc_error::OK => "OK (UC_ERR_OK)",
+            uc_error::NOMEM => "No memory available or memory not present (UC_ERR_NOMEM)",
+            uc_error::ARCH => "Invalid/unsupported architecture (UC_ERR_ARCH)",
+            uc_error::HANDLE => "Invalid handle (UC_ERR_HANDLE)",
+            uc_error::MODE => "Invalid mode (UC_ERR_MODE)",
+            uc_error::VERSION => "Different API version between core & binding (UC_ERR_VERSION)",
+            uc_error::READ_UNMAPPED => "Invalid memory read (UC_ERR_READ_UNMAPPED)",
+            uc_error::WRITE_UNMAPPED => "Invalid memory write (UC_ERR_WRITE_UNMAPPED)",
+            uc_error::FETCH_UNMAPPED => "Invalid memory fetch (UC_ERR_FETCH_UNMAPPED)",
+            uc_error::HOOK => "Invalid hook type (UC_ERR_HOOK)",
+            uc_error::INSN_INVALID => "Invalid instruction (UC_ERR_INSN_INVALID)",
+            uc_error::MAP => "Invalid memory mapping (UC_ERR_MAP)",
+            uc_error::WRITE_PROT => "Write to write-protected memory (UC_ERR_WRITE_PROT)",
+            uc_error::READ_PROT => "Read from non-readable memory (UC_ERR_READ_PROT)",
+            uc_error::FETCH_PROT => "Fetch from non-executable memory (UC_ERR_FETCH_PROT)",
+            uc_error::ARG => "Invalid argument (UC_ERR_ARG)",
+            uc_error::READ_UNALIGNED => "Read from unaligned memory (UC_ERR_READ_UNALIGNED)",
+            uc_error::WRITE_UNALIGNED => "Write to unaligned memory (UC_ERR_WRITE_UNALIGNED)",
+            uc_error::FETCH_UNALIGNED => "Fetch from unaligned memory (UC_ERR_FETCH_UNALIGNED)",
+            uc_error::HOOK_EXIST => "Hook for this event already existed (UC_ERR_HOOK_EXIST)",
+            uc_error::RESOURCE => "Insufficient resource (UC_ERR_RESOURCE)",
+            uc_error::EXCEPTION => "Unhandled CPU exception (UC_ERR_EXCEPTION)",
+        }
+    }
+}
+
+impl fmt::Display for uc_error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+/// Allows composing unicorn errors with other error types (`Box<dyn Error>`, `#[from]`/`#[source]` fields of thiserror enums and so on).
+impl error::Error for uc_error {}
+
 #[repr(C)]
 #[derive(PartialEq, Debug, Clone, Copy)]
 pub enum MemType {
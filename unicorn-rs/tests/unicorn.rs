@@ -681,3 +681,47 @@ fn x86_block_callback() {
     assert_eq!(expects, *blocks_cell.borrow());
     assert_eq!(emu.remove_hook(hook), Ok(()));
 }
+
+#[test]
+fn uc_error_display() {
+    assert_eq!(uc_error::READ_UNMAPPED.to_string(), "Invalid memory read (UC_ERR_READ_UNMAPPED)");
+
+    let err: Box<dyn std::error::Error> = Box::new(uc_error::EXCEPTION);
+    assert_eq!(err.to_string(), "Unhandled CPU exception (UC_ERR_EXCEPTION)");
+    assert!(err.source().is_none());
+}
+
+#[test]
+fn x86_code_hook_switch() {
+    let x86_code32: Vec<u8> = vec![0x41, 0x4a]; // INC ecx; DEC edx
+
+    let mut emu = unicorn::Engine::new(Arch::X86, Mode::MODE_32).expect("failed to initialize unicorn instance");
+    assert_eq!(emu.mem_map(0x1000, 0x4000, Permission::ALL), Ok(()));
+    assert_eq!(emu.mem_write(0x1000, &x86_code32), Ok(()));
+
+    let executed_count = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let callback_executed_count = executed_count.clone();
+    let hook = emu
+        .add_code_hook(move |_, _, _| {
+            callback_executed_count.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        }, 1, 0)
+        .expect("failed to add code hook");
+    let hook_switch = emu.get_hook_switch(hook).expect("failed to get hook switch");
+
+    let run_count = |emu: &mut unicorn::Engine| {
+        let prev_count = executed_count.load(std::sync::atomic::Ordering::SeqCst);
+        assert_eq!(emu.emu_start(0x1000, 0x1002, 10 * SECOND_SCALE, 1000), Ok(()));
+        executed_count.load(std::sync::atomic::Ordering::SeqCst) - prev_count
+    };
+
+    assert_eq!(run_count(&mut emu), 2);
+    assert_eq!(emu.set_hook_enabled(hook, false), Ok(()));
+    assert_eq!(emu.is_hook_enabled(hook), Ok(false));
+    assert_eq!(run_count(&mut emu), 0);
+    hook_switch.set_enabled(true);
+    assert_eq!(emu.is_hook_enabled(hook), Ok(true));
+    assert_eq!(run_count(&mut emu), 2);
+
+    assert_eq!(emu.remove_hook(hook), Ok(()));
+    assert_eq!(emu.set_hook_enabled(hook, true), Err(uc_error::HOOK));
+}